@@ -0,0 +1,163 @@
+// Times the hot attempt queries on a generated database, with and without the indexes from migration 5.
+// Run with `cargo bench --bench attempt_queries`
+use std::time::{Duration, Instant};
+
+use rusqlite::Connection;
+
+const NUM_PUZZLES: u32 = 500;
+const NUM_USERS: u32 = 2000;
+const ATTEMPTS_PER_USER: u32 = 100;
+const ITERATIONS: u32 = 200;
+
+// Copied from `migrations.rs`, since the server isn't a library
+const INDEXES: &str = "CREATE INDEX IF NOT EXISTS puzzle_attempts_by_puzzle
+        ON puzzle_attempts (puzzle_id, attempt_number);
+    CREATE INDEX IF NOT EXISTS puzzle_attempts_by_user_and_time
+        ON puzzle_attempts (username, timestamp_seconds);";
+
+// The rated attempts at a puzzle, as read when computing its rating
+const RATED_ATTEMPTS_FOR_PUZZLE: &str = "SELECT rated_attempts.solved, users.rating
+    FROM rated_attempts JOIN users ON rated_attempts.username = users.username
+    WHERE puzzle_id = ?1";
+
+// A user's latest attempt in a puzzle set, as read for their progress.
+// Already fast without the new indexes, thanks to `puzzle_attempts_by_user`
+const LAST_ACTIVITY_IN_SET: &str =
+    "SELECT MAX(puzzle_attempts.timestamp_seconds) FROM puzzle_attempts
+    JOIN puzzle_set_entries ON puzzle_set_entries.puzzle_id = puzzle_attempts.puzzle_id
+    WHERE puzzle_set_entries.set_id = ?1 AND puzzle_attempts.username = ?2";
+
+// The first page of a user's attempt history
+const ATTEMPT_HISTORY: &str =
+    "SELECT puzzle_id, solved, timestamp_seconds, rowid FROM puzzle_attempts
+    WHERE username = ?1 ORDER BY timestamp_seconds DESC, rowid DESC LIMIT 50";
+
+fn main() {
+    let db_conn = generate_database();
+    let without_indexes = run_queries(&db_conn);
+    db_conn.execute_batch(INDEXES).unwrap();
+    db_conn.execute_batch("ANALYZE").unwrap();
+    let with_indexes = run_queries(&db_conn);
+
+    for ((name, before), (_, after)) in without_indexes.iter().zip(&with_indexes) {
+        println!(
+            "{name}: {:?} per query without indexes, {:?} with, {:.1}x faster",
+            *before / ITERATIONS,
+            *after / ITERATIONS,
+            before.as_secs_f64() / after.as_secs_f64()
+        );
+    }
+}
+
+fn generate_database() -> Connection {
+    let db_conn = Connection::open_in_memory().unwrap();
+    db_conn
+        .execute_batch(
+            "CREATE TABLE puzzle_attempts (
+                puzzle_id INTEGER NOT NULL,
+                username TEXT NOT NULL,
+                solved INTEGER NOT NULL,
+                solve_time_seconds INTEGER NOT NULL,
+                solution TEXT NOT NULL,
+                timestamp_seconds INTEGER NOT NULL,
+                attempt_number INTEGER NOT NULL DEFAULT 1,
+                practice INTEGER NOT NULL DEFAULT 0
+            );
+            CREATE UNIQUE INDEX puzzle_attempts_by_user
+                ON puzzle_attempts (username, puzzle_id, attempt_number);
+            CREATE VIEW rated_attempts AS SELECT * FROM puzzle_attempts
+                WHERE attempt_number = 1 AND practice = 0;
+            CREATE TABLE users (username TEXT PRIMARY KEY, rating REAL NOT NULL);
+            CREATE TABLE puzzle_set_entries (
+                set_id INTEGER NOT NULL,
+                puzzle_id INTEGER NOT NULL,
+                PRIMARY KEY (set_id, puzzle_id)
+            );",
+        )
+        .unwrap();
+
+    let transaction = db_conn.unchecked_transaction().unwrap();
+    {
+        let mut insert_attempt = transaction
+            .prepare(
+                "INSERT INTO puzzle_attempts
+                (puzzle_id, username, solved, solve_time_seconds, solution, timestamp_seconds, attempt_number)
+                VALUES (?1, ?2, ?3, 30, '', ?4, ?5)",
+            )
+            .unwrap();
+        for user in 0..NUM_USERS {
+            let username = format!("user{user}");
+            transaction
+                .execute(
+                    "INSERT INTO users (username, rating) VALUES (?1, 1500)",
+                    [&username],
+                )
+                .unwrap();
+            for attempt in 0..ATTEMPTS_PER_USER {
+                // Every user sees the puzzles in a different order, and tries each of them twice
+                let puzzle_id = (user * 7 + (attempt / 2) * 13) % NUM_PUZZLES;
+                insert_attempt
+                    .execute(rusqlite::params![
+                        puzzle_id,
+                        username,
+                        (user + attempt) % 3 != 0,
+                        1_700_000_000 + user * 1000 + attempt,
+                        attempt % 2 + 1,
+                    ])
+                    .unwrap();
+            }
+        }
+        for puzzle_id in 0..NUM_PUZZLES {
+            transaction
+                .execute(
+                    "INSERT INTO puzzle_set_entries (set_id, puzzle_id) VALUES (?1, ?2)",
+                    [puzzle_id % 10, puzzle_id],
+                )
+                .unwrap();
+        }
+    }
+    transaction.commit().unwrap();
+    db_conn
+}
+
+fn run_queries(db_conn: &Connection) -> Vec<(&'static str, Duration)> {
+    let mut rated_attempts = db_conn.prepare(RATED_ATTEMPTS_FOR_PUZZLE).unwrap();
+    let start = Instant::now();
+    for i in 0..ITERATIONS {
+        let rows = rated_attempts
+            .query_map([i % NUM_PUZZLES], |row| row.get::<_, bool>(0))
+            .unwrap()
+            .count();
+        std::hint::black_box(rows);
+    }
+    let rated_attempts_time = start.elapsed();
+
+    let mut last_activity = db_conn.prepare(LAST_ACTIVITY_IN_SET).unwrap();
+    let start = Instant::now();
+    for i in 0..ITERATIONS {
+        let timestamp: Option<i64> = last_activity
+            .query_row(rusqlite::params![i % 10, format!("user{i}")], |row| {
+                row.get(0)
+            })
+            .unwrap();
+        std::hint::black_box(timestamp);
+    }
+    let last_activity_time = start.elapsed();
+
+    let mut attempt_history = db_conn.prepare(ATTEMPT_HISTORY).unwrap();
+    let start = Instant::now();
+    for i in 0..ITERATIONS {
+        let rows = attempt_history
+            .query_map([format!("user{i}")], |row| row.get::<_, u32>(0))
+            .unwrap()
+            .count();
+        std::hint::black_box(rows);
+    }
+    let attempt_history_time = start.elapsed();
+
+    vec![
+        ("rated attempts for a puzzle", rated_attempts_time),
+        ("last activity in a puzzle set", last_activity_time),
+        ("attempt history page", attempt_history_time),
+    ]
+}
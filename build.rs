@@ -0,0 +1,8 @@
+// Generates the gRPC server and client from `proto/`, see `src/grpc.rs`.
+// The proto files are compiled with `protox`, so that building doesn't need `protoc` installed
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    println!("cargo:rerun-if-changed=proto");
+    let file_descriptors = protox::compile(["proto/tak_tactics.proto"], ["proto"])?;
+    tonic_build::configure().compile_fds(file_descriptors)?;
+    Ok(())
+}
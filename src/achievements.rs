@@ -0,0 +1,212 @@
+use axum::{Json, extract::Path, http::StatusCode};
+use rusqlite::Connection;
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use crate::{db, validation};
+
+pub fn init_db_tables(db_conn: &Connection) -> anyhow::Result<()> {
+    // `rule` is one of the strings in `AchievementRule::from_db`, and `threshold` is its parameter
+    db_conn.execute(
+        "CREATE TABLE IF NOT EXISTS achievements (
+            id TEXT NOT NULL PRIMARY KEY,
+            name TEXT NOT NULL,
+            description TEXT NOT NULL,
+            rule TEXT NOT NULL,
+            threshold INTEGER NOT NULL
+        )",
+        [],
+    )?;
+
+    db_conn.execute(
+        "CREATE TABLE IF NOT EXISTS user_achievements (
+            username TEXT NOT NULL,
+            achievement_id TEXT NOT NULL,
+            earned_seconds INTEGER NOT NULL DEFAULT (strftime('%s', 'now')),
+            PRIMARY KEY (username, achievement_id),
+            FOREIGN KEY (achievement_id) REFERENCES achievements(id)
+        )",
+        [],
+    )?;
+
+    // Default badges. Edits made directly in the database are kept
+    db_conn.execute(
+        "INSERT OR IGNORE INTO achievements (id, name, description, rule, threshold) VALUES
+            ('first-solve', 'First blood', 'Solve your first puzzle', 'solved_count', 1),
+            ('tinue-50', 'Tinue hunter', 'Solve 50 puzzles', 'solved_count', 50),
+            ('streak-10', 'Dedicated', 'Solve a puzzle on 10 consecutive days', 'streak_days', 10),
+            ('fast-10', 'Lightning', 'Solve a puzzle in less than 10 seconds', 'fast_solve', 10)",
+        [],
+    )?;
+
+    Ok(())
+}
+
+#[derive(Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct UserAchievement {
+    id: String,
+    name: String,
+    description: String,
+    earned_seconds: Option<u64>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum AchievementRule {
+    // Solved at least `threshold` distinct puzzles
+    SolvedCount,
+    // Solved at least one puzzle on `threshold` consecutive days (UTC)
+    StreakDays,
+    // Solved a puzzle on the first attempt in less than `threshold` seconds
+    FastSolve,
+}
+
+impl AchievementRule {
+    fn from_db(rule: &str) -> Option<Self> {
+        match rule {
+            "solved_count" => Some(Self::SolvedCount),
+            "streak_days" => Some(Self::StreakDays),
+            "fast_solve" => Some(Self::FastSolve),
+            _ => None,
+        }
+    }
+}
+
+struct AchievementRow {
+    id: String,
+    rule: String,
+    threshold: u32,
+}
+
+// Get all achievements, and when the user earned them
+#[utoipa::path(
+    get,
+    path = "/users/{username}/achievements",
+    tag = "achievements",
+    params(("username" = String, Path)),
+    responses((status = 200, body = Vec<UserAchievement>)),
+)]
+pub async fn get_user_achievements(
+    Path(username): Path<String>,
+) -> Result<Json<Vec<UserAchievement>>, StatusCode> {
+    let username = validation::canonical_username(&username);
+    let db_conn = db::open().map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let achievements = read_user_achievements(&db_conn, &username)
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    Ok(Json(achievements))
+}
+
+// Award any achievements the user has newly qualified for. Called after every attempt
+pub fn evaluate(db_conn: &Connection, username: &str) -> anyhow::Result<()> {
+    let mut stmt = db_conn.prepare(
+        "SELECT id, rule, threshold FROM achievements WHERE id NOT IN (
+            SELECT achievement_id FROM user_achievements WHERE username = ?1
+        )",
+    )?;
+    let unearned = stmt
+        .query_map([username], |row| {
+            Ok(AchievementRow {
+                id: row.get(0)?,
+                rule: row.get(1)?,
+                threshold: row.get(2)?,
+            })
+        })?
+        .collect::<Result<Vec<_>, _>>()?;
+
+    for achievement in unearned {
+        let Some(rule) = AchievementRule::from_db(&achievement.rule) else {
+            tracing::error!(
+                "Unknown rule {} for achievement {}",
+                achievement.rule,
+                achievement.id
+            );
+            continue;
+        };
+        let earned = match rule {
+            AchievementRule::SolvedCount => {
+                num_puzzles_solved(db_conn, username)? >= achievement.threshold
+            }
+            AchievementRule::StreakDays => {
+                longest_solve_streak_days(db_conn, username)? >= achievement.threshold
+            }
+            AchievementRule::FastSolve => {
+                has_fast_first_solve(db_conn, username, achievement.threshold)?
+            }
+        };
+        if earned {
+            db_conn.execute(
+                "INSERT OR IGNORE INTO user_achievements (username, achievement_id) VALUES (?1, ?2)",
+                rusqlite::params![username, achievement.id],
+            )?;
+        }
+    }
+    Ok(())
+}
+
+fn num_puzzles_solved(db_conn: &Connection, username: &str) -> anyhow::Result<u32> {
+    Ok(db_conn.query_row(
+        "SELECT COUNT(DISTINCT puzzle_id) FROM puzzle_attempts
+        WHERE user_id = (SELECT id FROM users WHERE username = ?1) AND solved = 1",
+        [username],
+        |row| row.get(0),
+    )?)
+}
+
+fn longest_solve_streak_days(db_conn: &Connection, username: &str) -> anyhow::Result<u32> {
+    let mut stmt = db_conn.prepare(
+        "SELECT DISTINCT timestamp_seconds / 86400 AS day FROM puzzle_attempts
+        WHERE user_id = (SELECT id FROM users WHERE username = ?1) AND solved = 1 ORDER BY day",
+    )?;
+    let days = stmt
+        .query_map([username], |row| row.get::<_, i64>(0))?
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let mut longest = 0;
+    let mut current = 0;
+    let mut previous_day = None;
+    for day in days {
+        if previous_day == Some(day - 1) {
+            current += 1;
+        } else {
+            current = 1;
+        }
+        longest = longest.max(current);
+        previous_day = Some(day);
+    }
+    Ok(longest)
+}
+
+fn has_fast_first_solve(
+    db_conn: &Connection,
+    username: &str,
+    max_seconds: u32,
+) -> anyhow::Result<bool> {
+    let mut stmt = db_conn.prepare(
+        "SELECT 1 FROM rated_attempts
+        WHERE username = ?1 AND solved = 1 AND solve_time_seconds < ?2",
+    )?;
+    Ok(stmt.exists(rusqlite::params![username, max_seconds])?)
+}
+
+fn read_user_achievements(
+    db_conn: &Connection,
+    username: &str,
+) -> anyhow::Result<Vec<UserAchievement>> {
+    let mut stmt = db_conn.prepare(
+        "SELECT achievements.id, achievements.name, achievements.description,
+            user_achievements.earned_seconds
+        FROM achievements
+        LEFT JOIN user_achievements ON user_achievements.achievement_id = achievements.id
+            AND user_achievements.username = ?1
+        ORDER BY achievements.id",
+    )?;
+    let rows = stmt.query_map([username], |row| {
+        Ok(UserAchievement {
+            id: row.get(0)?,
+            name: row.get(1)?,
+            description: row.get(2)?,
+            earned_seconds: row.get(3)?,
+        })
+    })?;
+    Ok(rows.collect::<Result<Vec<_>, _>>()?)
+}
@@ -0,0 +1,174 @@
+use axum::{
+    Json,
+    extract::{FromRequestParts, Path, State},
+    http::{StatusCode, header::AUTHORIZATION, request::Parts},
+};
+use rand::{Rng, distr::Alphanumeric};
+use rusqlite::{Connection, OptionalExtension};
+use serde::{Deserialize, Serialize};
+use skillratings::glicko2::Glicko2Rating;
+use utoipa::ToSchema;
+
+use crate::{
+    AppState, audit, db,
+    validation::{self, ApiError},
+};
+
+pub fn init_db_tables(db_conn: &Connection) -> anyhow::Result<()> {
+    // Tokens are created with `tak-tactics-backend create-token`. Only their SHA-256 hashes are stored
+    db_conn.execute(
+        "CREATE TABLE IF NOT EXISTS admin_tokens (
+            token_hash TEXT PRIMARY KEY,
+            name TEXT NOT NULL,
+            created_seconds INTEGER NOT NULL DEFAULT (strftime('%s', 'now'))
+        )",
+        [],
+    )?;
+
+    Ok(())
+}
+
+// Extractor for endpoints that should only be available to admins.
+// Requests must send `Authorization: Bearer <token>`, with either the `ADMIN_TOKEN` environment variable
+// or a token from `create-token`. If neither exists, all admin endpoints are disabled
+pub struct AdminAuth {
+    // Who is making the request, for `audit.rs`
+    pub actor: String,
+}
+
+impl<S: Send + Sync> FromRequestParts<S> for AdminAuth {
+    type Rejection = StatusCode;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        let token = parts
+            .headers
+            .get(AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "))
+            .ok_or(StatusCode::UNAUTHORIZED)?;
+        if let Ok(admin_token) = std::env::var("ADMIN_TOKEN")
+            && !admin_token.is_empty()
+            && token == admin_token
+        {
+            return Ok(AdminAuth {
+                actor: "ADMIN_TOKEN".to_string(),
+            });
+        }
+        let db_conn = db::open().map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+        match token_name(&db_conn, token) {
+            Ok(Some(name)) => Ok(AdminAuth { actor: name }),
+            Ok(None) => Err(StatusCode::FORBIDDEN),
+            Err(e) => {
+                tracing::error!("Error reading admin tokens from database: {:?}", e);
+                Err(StatusCode::INTERNAL_SERVER_ERROR)
+            }
+        }
+    }
+}
+
+// Create a new admin token. It's only shown once, since the database only keeps its hash
+pub fn create_token(db_conn: &Connection, name: &str) -> anyhow::Result<String> {
+    let token: String = rand::rng()
+        .sample_iter(&Alphanumeric)
+        .take(40)
+        .map(char::from)
+        .collect();
+    db_conn.execute(
+        "INSERT INTO admin_tokens (token_hash, name) VALUES (?1, ?2)",
+        rusqlite::params![hash_token(&token), name],
+    )?;
+    Ok(token)
+}
+
+fn token_name(db_conn: &Connection, token: &str) -> anyhow::Result<Option<String>> {
+    Ok(db_conn
+        .query_row(
+            "SELECT name FROM admin_tokens WHERE token_hash = ?1",
+            [hash_token(token)],
+            |row| row.get(0),
+        )
+        .optional()?)
+}
+
+// SHA-256, in hex. Tokens are random enough that they don't need a salt
+pub fn hash_token(token: &str) -> String {
+    ring::digest::digest(&ring::digest::SHA256, token.as_bytes())
+        .as_ref()
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect()
+}
+
+#[derive(Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct RatingExclusion {
+    excluded_from_ratings: bool,
+}
+
+// Set whether the user's attempts count towards puzzle ratings, for accounts like the puzzle authors' own.
+// Users who haven't made an attempt yet are added with the default rating
+#[utoipa::path(
+    post,
+    path = "/admin/users/{username}/rating-exclusion",
+    tag = "ratings",
+    params(("username" = String, Path)),
+    request_body = RatingExclusion,
+    security(("admin_token" = [])),
+    responses(
+        (status = 200, body = RatingExclusion),
+        (status = 400, body = validation::ValidationError),
+        (status = 401),
+        (status = 403),
+    ),
+)]
+pub async fn set_rating_exclusion(
+    admin: AdminAuth,
+    State(state): State<AppState>,
+    Path(username): Path<String>,
+    Json(payload): Json<RatingExclusion>,
+) -> Result<Json<RatingExclusion>, ApiError> {
+    let username = validation::canonical_username(&username);
+    validation::validate_username(&username)?;
+    let db_conn = db::open().map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let default_rating = Glicko2Rating::default();
+    db_conn
+        .execute(
+            "INSERT INTO users (username, rating, deviation, volatility, excluded_from_ratings)
+            VALUES (?1, ?2, ?3, ?4, ?5)
+            ON CONFLICT (username) DO UPDATE SET excluded_from_ratings = excluded.excluded_from_ratings",
+            rusqlite::params![
+                username,
+                default_rating.rating,
+                default_rating.deviation,
+                default_rating.volatility,
+                payload.excluded_from_ratings
+            ],
+        )
+        .map_err(|e| {
+            tracing::error!("Error updating rating exclusion: {:?}", e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+    audit::record_for(
+        &db_conn,
+        &admin,
+        "set_rating_exclusion",
+        serde_json::json!({
+            "username": username,
+            "excludedFromRatings": payload.excluded_from_ratings,
+        }),
+    )?;
+    // The ratings of the puzzles the user attempted change now, not when they drop out of the cache
+    let puzzle_ids = rated_puzzle_ids(&db_conn, &username).map_err(|e| {
+        tracing::error!("Error reading rated attempts: {:?}", e);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+    state.store.invalidate_puzzle_ratings(&puzzle_ids);
+    Ok(Json(payload))
+}
+
+fn rated_puzzle_ids(db_conn: &Connection, username: &str) -> anyhow::Result<Vec<u32>> {
+    let mut stmt =
+        db_conn.prepare("SELECT DISTINCT puzzle_id FROM rated_attempts WHERE username = ?1")?;
+    let rows = stmt.query_map([username], |row| row.get(0))?;
+    Ok(rows.collect::<Result<_, _>>()?)
+}
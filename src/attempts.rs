@@ -0,0 +1,640 @@
+use axum::{
+    Json,
+    extract::{Path, Query},
+    http::StatusCode,
+};
+use rusqlite::{Connection, OptionalExtension};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    PuzzleRow,
+    board::{Board, Move},
+    db, devices,
+    pagination::{Page, PageQuery},
+    storage, users,
+    validation::{self, ApiError},
+};
+use utoipa::{IntoParams, ToSchema};
+
+// Attempts are numbered per user and puzzle, starting at 1.
+// Only a user's first attempt at a puzzle is rated. Later attempts are stored like any other,
+// but don't affect ratings, and don't count in competitions that compare first tries.
+// Practice attempts are never rated. They still take up an attempt number,
+// so practicing a puzzle that hadn't been seen before uses up its rated attempt.
+// Anonymous first attempts from a device another user already made a rated attempt from aren't rated either, see `devices.rs`.
+// A failed rated attempt can be retried once, which can make it count as half a solve, see `retries.rs`.
+// Queries should read rated attempts from the `rated_attempts` view, instead of repeating the rule.
+// The view is created in `migrations.rs`, and has to be kept in sync with `is_rated`
+
+pub fn is_rated(attempt_number: u32, practice: bool, shared_device: bool) -> bool {
+    attempt_number == 1 && !practice && !shared_device
+}
+
+pub struct NewAttempt {
+    pub puzzle_id: u32,
+    pub username: String,
+    pub solved: bool,
+    pub solve_time_seconds: u32,
+    pub solution: Vec<String>,
+    // Milliseconds since the start of the attempt, for each move in `solution`
+    pub move_times_ms: Option<Vec<u32>>,
+    pub practice: bool,
+    // Where an anonymous attempt was made from. Empty for logged in users and attempts the server made
+    pub device: devices::Device,
+    // A retry of the user's failed rated attempt at the puzzle, see `retries.rs`
+    pub retry: bool,
+    // What the rules say about `solution`, from `judge`. Stored as it is
+    pub judgement: Option<Judgement>,
+}
+
+pub struct InsertedAttempt {
+    pub attempt_number: u32,
+    // Another user already made a rated attempt at the puzzle from the same device, see `devices.rs`
+    pub shared_device: bool,
+}
+
+// Store an attempt. `retry_of` is the number of the failed attempt it retries, if it's a retry
+pub fn insert_attempt(
+    db_conn: &Connection,
+    attempt: &NewAttempt,
+    retry_of: Option<u32>,
+) -> anyhow::Result<InsertedAttempt> {
+    let user_id = users::get_or_create_id(db_conn, &attempt.username)?;
+    let judgement = attempt.judgement.as_ref();
+    let inserted = db_conn.query_row(
+        "INSERT INTO puzzle_attempts (puzzle_id, user_id, solved, solve_time_seconds, solution, practice, attempt_number,
+            device_hash, ip_hash, shared_device, retry_of, verdict, illegal_move_index)
+        VALUES (?1, ?2, ?3, ?4, ?5, ?6, (
+            SELECT COALESCE(MAX(attempt_number), 0) + 1 FROM puzzle_attempts
+            WHERE puzzle_id = ?1 AND user_id = ?2
+        ), ?7, ?8, EXISTS (
+            SELECT 1 FROM rated_attempts
+            WHERE puzzle_id = ?1 AND user_id != ?2 AND (device_hash = ?7 OR ip_hash = ?8)
+        ), ?9, ?10, ?11)
+        RETURNING attempt_number, shared_device",
+        rusqlite::params![
+            attempt.puzzle_id,
+            user_id,
+            attempt.solved,
+            attempt.solve_time_seconds,
+            attempt.solution.join(" "),
+            attempt.practice,
+            attempt.device.id_hash,
+            attempt.device.ip_hash,
+            retry_of,
+            judgement.map(|judgement| judgement.verdict.name()),
+            judgement.and_then(|judgement| judgement.illegal_move_index)
+        ],
+        |row| {
+            Ok(InsertedAttempt {
+                attempt_number: row.get(0)?,
+                shared_device: row.get(1)?,
+            })
+        },
+    )?;
+    let attempt_number = inserted.attempt_number;
+    if let Some(move_times_ms) = &attempt.move_times_ms {
+        let mut stmt = db_conn.prepare_cached(
+            "INSERT INTO attempt_moves (username, puzzle_id, attempt_number, move_index, ptn_move, elapsed_ms)
+            VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+        )?;
+        for (i, (ptn_move, elapsed_ms)) in attempt.solution.iter().zip(move_times_ms).enumerate() {
+            stmt.execute(rusqlite::params![
+                attempt.username,
+                attempt.puzzle_id,
+                attempt_number,
+                i,
+                ptn_move,
+                elapsed_ms
+            ])?;
+        }
+    }
+    Ok(inserted)
+}
+
+// The server's view of a submitted attempt, which may not agree with what the client reported
+#[derive(Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct Verification {
+    // Whether the submitted line matches the puzzle's solution
+    pub correct: bool,
+    pub solution: Vec<String>,
+    pub target_time_seconds: u32,
+    // Whether the puzzle was solved correctly within the target time
+    pub target_time_met: bool,
+    // Not set if the puzzle's own position couldn't be set up
+    #[serde(flatten)]
+    pub judgement: Option<Judgement>,
+}
+
+// Compare a submitted line with the puzzle's solution, ignoring annotations like `'` and `!`
+pub fn verify(puzzle: &PuzzleRow, submitted: &[String], solve_time_seconds: u32) -> Verification {
+    let solution: Vec<String> = puzzle
+        .solution
+        .split_whitespace()
+        .map(String::from)
+        .collect();
+    let correct = matches_solution(puzzle, submitted);
+    let target_time_seconds = puzzle.target_time_seconds;
+    Verification {
+        correct,
+        solution,
+        target_time_seconds,
+        target_time_met: correct && solve_time_seconds <= target_time_seconds,
+        judgement: judge(puzzle, submitted),
+    }
+}
+
+fn matches_solution(puzzle: &PuzzleRow, submitted: &[String]) -> bool {
+    submitted
+        .iter()
+        .map(|ptn_move| validation::normalize_move(ptn_move))
+        .filter(|ptn_move| !ptn_move.is_empty())
+        .eq(puzzle
+            .solution
+            .split_whitespace()
+            .map(validation::normalize_move)
+            .filter(|ptn_move| !ptn_move.is_empty()))
+}
+
+// What the rules say about a submitted line
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub enum Verdict {
+    // The line is the solution
+    Correct,
+    // Every move is legal, but the line isn't the solution
+    Wrong,
+    // A move can't be played in the position it was played in
+    Illegal,
+}
+
+impl Verdict {
+    pub fn name(self) -> &'static str {
+        match self {
+            Verdict::Correct => "correct",
+            Verdict::Wrong => "wrong",
+            Verdict::Illegal => "illegal",
+        }
+    }
+
+    fn from_name(name: &str) -> Option<Self> {
+        [Verdict::Correct, Verdict::Wrong, Verdict::Illegal]
+            .into_iter()
+            .find(|verdict| verdict.name() == name)
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct Judgement {
+    pub verdict: Verdict,
+    // For illegal lines, the index in the submitted line of the first illegal move
+    pub illegal_move_index: Option<u32>,
+}
+
+// Play a submitted line out from the puzzle's position, after the defender's move.
+// A line that matches the solution is correct without being played, so that puzzles whose stored
+// solution breaks the rules don't fail everyone.
+// None if the puzzle's own position or the defender's move can't be played
+pub fn judge(puzzle: &PuzzleRow, submitted: &[String]) -> Option<Judgement> {
+    if matches_solution(puzzle, submitted) {
+        return Some(Judgement {
+            verdict: Verdict::Correct,
+            illegal_move_index: None,
+        });
+    }
+    let mut board = Board::from_tps(&puzzle.root_tps).ok()?;
+    if !validation::normalize_move(&puzzle.defender_start_move).is_empty() {
+        board
+            .play(&Move::parse(&puzzle.defender_start_move).ok()?)
+            .ok()?;
+    }
+    let illegal_move_index = submitted.iter().position(|ptn_move| {
+        if validation::normalize_move(ptn_move).is_empty() {
+            return false;
+        }
+        Move::parse(ptn_move)
+            .and_then(|mv| board.play(&mv))
+            .is_err()
+    });
+    Some(match illegal_move_index {
+        Some(index) => Judgement {
+            verdict: Verdict::Illegal,
+            illegal_move_index: Some(index as u32),
+        },
+        None => Judgement {
+            verdict: Verdict::Wrong,
+            illegal_move_index: None,
+        },
+    })
+}
+
+#[derive(Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct AttemptHistoryEntry {
+    // For `GET /attempts/{id}/replay`
+    id: i64,
+    puzzle_id: u64,
+    attempt_number: u32,
+    solved: bool,
+    solve_time_seconds: u32,
+    practice: bool,
+    timestamp_seconds: u64,
+    // The number of the failed attempt this one retried, see `retries.rs`
+    retry_of: Option<u32>,
+    // This attempt failed, but its retry solved the puzzle
+    solved_on_retry: bool,
+    // Not set for attempts from before lines were judged
+    verdict: Option<Verdict>,
+    illegal_move_index: Option<u32>,
+}
+
+// Get the user's attempts, newest first
+#[utoipa::path(
+    get,
+    path = "/users/{username}/attempts",
+    tag = "attempts",
+    params(("username" = String, Path), PageQuery),
+    responses(
+        (status = 200, body = Page<AttemptHistoryEntry>),
+        (status = 400, body = validation::ValidationError),
+    ),
+)]
+pub async fn get_attempt_history(
+    Path(username): Path<String>,
+    Query(page): Query<PageQuery>,
+) -> Result<Json<Page<AttemptHistoryEntry>>, ApiError> {
+    let username = validation::canonical_username(&username);
+    validation::validate_username(&username)?;
+    let db_conn = db::open().map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let page = page.fetch(
+        |after, limit| read_attempt_history(&db_conn, &username, after, limit),
+        |entry| (entry.timestamp_seconds, entry.id),
+    )?;
+    Ok(Json(page))
+}
+
+fn read_attempt_history(
+    db_conn: &Connection,
+    username: &str,
+    after: Option<(u64, i64)>,
+    limit: u32,
+) -> anyhow::Result<Vec<AttemptHistoryEntry>> {
+    let (after_timestamp, after_id) = after.unzip();
+    let mut stmt = db_conn.prepare(
+        "SELECT id, puzzle_id, attempt_number, solved, solve_time_seconds, practice, timestamp_seconds, retry_of,
+            solved_on_retry, verdict, illegal_move_index
+        FROM puzzle_attempts
+        WHERE user_id = (SELECT id FROM users WHERE username = ?1)
+            AND (?2 IS NULL OR (timestamp_seconds, id) < (?2, ?3))
+        ORDER BY timestamp_seconds DESC, id DESC
+        LIMIT ?4",
+    )?;
+    let rows = stmt.query_map(
+        rusqlite::params![username, after_timestamp, after_id, limit],
+        |row| {
+            Ok(AttemptHistoryEntry {
+                id: row.get(0)?,
+                puzzle_id: row.get(1)?,
+                attempt_number: row.get(2)?,
+                solved: row.get(3)?,
+                solve_time_seconds: row.get(4)?,
+                practice: row.get(5)?,
+                timestamp_seconds: row.get(6)?,
+                retry_of: row.get(7)?,
+                solved_on_retry: row.get(8)?,
+                verdict: row
+                    .get::<_, Option<String>>(9)?
+                    .and_then(|name| Verdict::from_name(&name)),
+                illegal_move_index: row.get(10)?,
+            })
+        },
+    )?;
+    Ok(rows.collect::<Result<Vec<_>, _>>()?)
+}
+
+// How long solvers think about each move of a puzzle's solution.
+// Only solved attempts with move times are counted, so that every one played the same moves
+#[derive(Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct MoveTimeStats {
+    move_index: u32,
+    ptn_move: String,
+    num_attempts: u32,
+    // Time since the previous move, or since the start for the first one
+    average_think_ms: f64,
+    max_think_ms: u32,
+}
+
+#[utoipa::path(
+    get,
+    path = "/puzzles/{id}/move-times",
+    tag = "attempts",
+    params(("id" = u32, Path)),
+    responses((status = 200, body = Vec<MoveTimeStats>), (status = 404)),
+)]
+pub async fn get_move_times(Path(id): Path<u32>) -> Result<Json<Vec<MoveTimeStats>>, StatusCode> {
+    let db_conn = db::open().map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let puzzle = storage::read_puzzle_by_id(&db_conn, id)
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+        .filter(|puzzle| puzzle.published)
+        .ok_or(StatusCode::NOT_FOUND)?;
+    read_move_times(&db_conn, &puzzle).map(Json).map_err(|e| {
+        tracing::error!("Error reading move times: {:?}", e);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })
+}
+
+fn read_move_times(db_conn: &Connection, puzzle: &PuzzleRow) -> anyhow::Result<Vec<MoveTimeStats>> {
+    let solution: Vec<&str> = puzzle.solution.split_whitespace().collect();
+    let mut stmt = db_conn.prepare(
+        "SELECT move_index, COUNT(*), AVG(think_ms), MAX(think_ms) FROM (
+            SELECT attempt_moves.move_index,
+                attempt_moves.elapsed_ms - COALESCE(LAG(attempt_moves.elapsed_ms) OVER (
+                    PARTITION BY attempt_moves.username, attempt_moves.attempt_number
+                    ORDER BY attempt_moves.move_index
+                ), 0) AS think_ms
+            FROM attempt_moves
+            JOIN users ON users.username = attempt_moves.username
+            JOIN puzzle_attempts ON puzzle_attempts.user_id = users.id
+                AND puzzle_attempts.puzzle_id = attempt_moves.puzzle_id
+                AND puzzle_attempts.attempt_number = attempt_moves.attempt_number
+            WHERE attempt_moves.puzzle_id = ?1 AND puzzle_attempts.solved = 1
+        )
+        GROUP BY move_index
+        ORDER BY move_index",
+    )?;
+    let rows = stmt.query_map([puzzle.id], |row| {
+        let move_index: u32 = row.get(0)?;
+        Ok(MoveTimeStats {
+            move_index,
+            ptn_move: solution
+                .get(move_index as usize)
+                .copied()
+                .unwrap_or_default()
+                .to_string(),
+            num_attempts: row.get(1)?,
+            average_think_ms: row.get(2)?,
+            max_think_ms: row.get(3)?,
+        })
+    })?;
+    Ok(rows.collect::<Result<Vec<_>, _>>()?)
+}
+
+// Upper bounds of the solve time histogram's buckets, in seconds. The last bucket has no upper bound
+const SOLVE_TIME_BUCKETS: [u32; 8] = [5, 10, 20, 30, 60, 120, 300, 600];
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct SolveTimesQuery {
+    // The user to compare with everyone else, if any
+    username: Option<String>,
+}
+
+// How long solvers took on their rated attempt at a puzzle. Failed attempts aren't counted
+#[derive(Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct SolveTimes {
+    num_solves: u32,
+    buckets: Vec<SolveTimeBucket>,
+    // Only set if the user in the query solved the puzzle in their rated attempt
+    user: Option<UserSolveTime>,
+}
+
+#[derive(Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct SolveTimeBucket {
+    min_seconds: u32,
+    // Exclusive. Not set for the last bucket
+    max_seconds: Option<u32>,
+    num_solves: u32,
+}
+
+#[derive(Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct UserSolveTime {
+    solve_time_seconds: u32,
+    // The percentage of solvers who took longer, as in "you solved this faster than 72% of players"
+    faster_than_percent: f64,
+}
+
+#[utoipa::path(
+    get,
+    path = "/puzzles/{id}/solve-times",
+    tag = "attempts",
+    params(("id" = u32, Path), SolveTimesQuery),
+    responses(
+        (status = 200, body = SolveTimes),
+        (status = 400, body = validation::ValidationError),
+        (status = 404),
+    ),
+)]
+pub async fn get_solve_times(
+    Path(id): Path<u32>,
+    Query(query): Query<SolveTimesQuery>,
+) -> Result<Json<SolveTimes>, ApiError> {
+    let username = match &query.username {
+        Some(username) => {
+            validation::validate_username(username)?;
+            Some(validation::canonical_username(username))
+        }
+        None => None,
+    };
+    let db_conn = db::open().map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    storage::read_puzzle_by_id(&db_conn, id)
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+        .filter(|puzzle| puzzle.published)
+        .ok_or(StatusCode::NOT_FOUND)?;
+    let solve_times = read_solve_times(&db_conn, id, username.as_deref()).map_err(|e| {
+        tracing::error!("Error reading solve times: {:?}", e);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+    Ok(Json(solve_times))
+}
+
+fn read_solve_times(
+    db_conn: &Connection,
+    puzzle_id: u32,
+    username: Option<&str>,
+) -> anyhow::Result<SolveTimes> {
+    let mut stmt = db_conn.prepare(
+        "SELECT username, solve_time_seconds FROM rated_attempts WHERE puzzle_id = ?1 AND solved = 1",
+    )?;
+    let solves: Vec<(String, u32)> = stmt
+        .query_map([puzzle_id], |row| Ok((row.get(0)?, row.get(1)?)))?
+        .collect::<Result<_, _>>()?;
+
+    let mut buckets: Vec<SolveTimeBucket> = std::iter::once(0)
+        .chain(SOLVE_TIME_BUCKETS)
+        .zip(SOLVE_TIME_BUCKETS.map(Some).into_iter().chain([None]))
+        .map(|(min_seconds, max_seconds)| SolveTimeBucket {
+            min_seconds,
+            max_seconds,
+            num_solves: 0,
+        })
+        .collect();
+    for (_, solve_time_seconds) in &solves {
+        let i = SOLVE_TIME_BUCKETS
+            .iter()
+            .position(|max_seconds| solve_time_seconds < max_seconds)
+            .unwrap_or(SOLVE_TIME_BUCKETS.len());
+        buckets[i].num_solves += 1;
+    }
+
+    let user = username
+        .and_then(|username| solves.iter().find(|(solver, _)| solver == username))
+        .map(|(_, solve_time_seconds)| {
+            let num_slower = solves
+                .iter()
+                .filter(|(_, other)| other > solve_time_seconds)
+                .count();
+            UserSolveTime {
+                solve_time_seconds: *solve_time_seconds,
+                faster_than_percent: 100.0 * num_slower as f64 / solves.len() as f64,
+            }
+        });
+    Ok(SolveTimes {
+        num_solves: solves.len() as u32,
+        buckets,
+        user,
+    })
+}
+
+// A past attempt, with everything needed to play it back: the position, the moves the user played,
+// and when they played them. Move times are only set for attempts that sent them, see `attempt_moves`
+#[derive(Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct AttemptReplay {
+    id: i64,
+    puzzle_id: u64,
+    username: String,
+    attempt_number: u32,
+    solved: bool,
+    practice: bool,
+    solve_time_seconds: u32,
+    timestamp_seconds: u64,
+    size: usize,
+    komi: String,
+    #[serde(rename = "rootTPS")]
+    root_tps: String,
+    // Played before the position is shown, like in `GET /puzzles`
+    defender_start_move: String,
+    moves: Vec<ReplayMove>,
+    // The puzzle's solution, to compare the moves against
+    solution: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct ReplayMove {
+    ptn_move: String,
+    // Whether the move matches the solution at this point
+    correct: bool,
+    // Milliseconds since the start of the attempt
+    elapsed_ms: Option<u32>,
+}
+
+#[utoipa::path(
+    get,
+    path = "/attempts/{id}/replay",
+    tag = "attempts",
+    params(("id" = i64, Path)),
+    responses((status = 200, body = AttemptReplay), (status = 404)),
+)]
+pub async fn get_attempt_replay(Path(id): Path<i64>) -> Result<Json<AttemptReplay>, StatusCode> {
+    let db_conn = db::open().map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    read_replay(&db_conn, id)
+        .map_err(|e| {
+            tracing::error!("Error reading attempt replay: {:?}", e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?
+        .map(Json)
+        .ok_or(StatusCode::NOT_FOUND)
+}
+
+fn read_replay(db_conn: &Connection, id: i64) -> anyhow::Result<Option<AttemptReplay>> {
+    let attempt = db_conn
+        .query_row(
+            "SELECT puzzle_attempts.puzzle_id, users.username, puzzle_attempts.attempt_number, puzzle_attempts.solved,
+                puzzle_attempts.practice, puzzle_attempts.solve_time_seconds, puzzle_attempts.timestamp_seconds,
+                puzzle_attempts.solution
+            FROM puzzle_attempts JOIN users ON users.id = puzzle_attempts.user_id
+            WHERE puzzle_attempts.id = ?1",
+            [id],
+            |row| {
+                Ok((
+                    row.get::<_, u32>(0)?,
+                    row.get::<_, String>(1)?,
+                    row.get::<_, u32>(2)?,
+                    row.get::<_, bool>(3)?,
+                    row.get::<_, bool>(4)?,
+                    row.get::<_, u32>(5)?,
+                    row.get::<_, u64>(6)?,
+                    row.get::<_, String>(7)?,
+                ))
+            },
+        )
+        .optional()?;
+    let Some((
+        puzzle_id,
+        username,
+        attempt_number,
+        solved,
+        practice,
+        solve_time_seconds,
+        timestamp_seconds,
+        played,
+    )) = attempt
+    else {
+        return Ok(None);
+    };
+    let Some(puzzle) = storage::read_puzzle_by_id(db_conn, puzzle_id)? else {
+        return Ok(None);
+    };
+
+    let mut stmt = db_conn.prepare(
+        "SELECT elapsed_ms FROM attempt_moves
+        WHERE username = ?1 AND puzzle_id = ?2 AND attempt_number = ?3
+        ORDER BY move_index",
+    )?;
+    let move_times_ms: Vec<u32> = stmt
+        .query_map(
+            rusqlite::params![username, puzzle_id, attempt_number],
+            |row| row.get(0),
+        )?
+        .collect::<Result<_, _>>()?;
+
+    let solution: Vec<String> = puzzle
+        .solution
+        .split_whitespace()
+        .map(String::from)
+        .collect();
+    let moves = played
+        .split_whitespace()
+        .enumerate()
+        .map(|(i, ptn_move)| ReplayMove {
+            ptn_move: ptn_move.to_string(),
+            correct: solution.get(i).is_some_and(|expected| {
+                validation::normalize_move(expected) == validation::normalize_move(ptn_move)
+            }),
+            elapsed_ms: move_times_ms.get(i).copied(),
+        })
+        .collect();
+    Ok(Some(AttemptReplay {
+        id,
+        puzzle_id: puzzle.id,
+        username,
+        attempt_number,
+        solved,
+        practice,
+        solve_time_seconds,
+        timestamp_seconds,
+        size: puzzle.size,
+        komi: puzzle.komi,
+        root_tps: puzzle.root_tps,
+        defender_start_move: puzzle.defender_start_move,
+        moves,
+        solution,
+    }))
+}
@@ -0,0 +1,120 @@
+use axum::{Json, extract::Query, http::StatusCode};
+use rusqlite::Connection;
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use crate::{
+    admin::AdminAuth,
+    db,
+    pagination::{Page, PageQuery},
+    validation::{self, ApiError},
+};
+
+// Who did what with admin access: every admin endpoint that changes data, user exports,
+// and the commands that change data directly
+
+// The actor for commands run on the server, see `main.rs`
+pub const COMMAND_LINE: &str = "command line";
+
+pub fn init_db_tables(db_conn: &Connection) -> anyhow::Result<()> {
+    // `payload` is a JSON object with what the action was given, like the request body
+    db_conn.execute(
+        "CREATE TABLE IF NOT EXISTS audit_log (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            actor TEXT NOT NULL,
+            action TEXT NOT NULL,
+            payload TEXT NOT NULL,
+            timestamp_seconds INTEGER NOT NULL DEFAULT (strftime('%s', 'now'))
+        )",
+        [],
+    )?;
+
+    Ok(())
+}
+
+#[derive(Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct AuditEntry {
+    id: i64,
+    // The name of the admin token that was used, or `ADMIN_TOKEN` or "command line"
+    actor: String,
+    action: String,
+    #[schema(value_type = Object)]
+    payload: serde_json::Value,
+    timestamp_seconds: u64,
+}
+
+pub fn record(
+    db_conn: &Connection,
+    actor: &str,
+    action: &str,
+    payload: serde_json::Value,
+) -> anyhow::Result<()> {
+    db_conn.execute(
+        "INSERT INTO audit_log (actor, action, payload) VALUES (?1, ?2, ?3)",
+        rusqlite::params![actor, action, payload.to_string()],
+    )?;
+    Ok(())
+}
+
+// For handlers, which fail if the action couldn't be recorded
+pub fn record_for(
+    db_conn: &Connection,
+    admin: &AdminAuth,
+    action: &str,
+    payload: serde_json::Value,
+) -> Result<(), StatusCode> {
+    record(db_conn, &admin.actor, action, payload).map_err(|e| {
+        tracing::error!("Error writing to audit log: {:?}", e);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })
+}
+
+// List admin actions, newest first
+#[utoipa::path(
+    get,
+    path = "/admin/audit-log",
+    tag = "admin",
+    params(PageQuery),
+    security(("admin_token" = [])),
+    responses(
+        (status = 200, body = Page<AuditEntry>),
+        (status = 400, body = validation::ValidationError),
+        (status = 401),
+        (status = 403),
+    ),
+)]
+pub async fn get_audit_log(
+    _: AdminAuth,
+    Query(page): Query<PageQuery>,
+) -> Result<Json<Page<AuditEntry>>, ApiError> {
+    let db_conn = db::open().map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let page = page.fetch(
+        |after, limit| read_entries(&db_conn, after, limit),
+        |entry| entry.id,
+    )?;
+    Ok(Json(page))
+}
+
+fn read_entries(
+    db_conn: &Connection,
+    after: Option<i64>,
+    limit: u32,
+) -> anyhow::Result<Vec<AuditEntry>> {
+    let mut stmt = db_conn.prepare(
+        "SELECT id, actor, action, payload, timestamp_seconds FROM audit_log
+        WHERE ?1 IS NULL OR id < ?1
+        ORDER BY id DESC
+        LIMIT ?2",
+    )?;
+    let rows = stmt.query_and_then(rusqlite::params![after, limit], |row| {
+        Ok(AuditEntry {
+            id: row.get(0)?,
+            actor: row.get(1)?,
+            action: row.get(2)?,
+            payload: serde_json::from_str(&row.get::<_, String>(3)?)?,
+            timestamp_seconds: row.get(4)?,
+        })
+    })?;
+    rows.collect()
+}
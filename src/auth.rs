@@ -0,0 +1,340 @@
+use axum::{Json, extract::State, http::StatusCode};
+use rand::{Rng, distr::Alphanumeric};
+use rusqlite::{Connection, OptionalExtension};
+use serde::{Deserialize, Serialize, de::DeserializeOwned};
+use utoipa::ToSchema;
+
+use crate::{
+    AppState,
+    config::AuthConfig,
+    db, mail, now_seconds,
+    sessions::{self, Tokens},
+    validation::{self, ApiError, ValidationError},
+};
+
+// Passwordless login for users without a playtak account. `POST /auth/magic-link` emails a link with a signed token,
+// and the frontend posts the token to `POST /auth/magic-link/verify` to start a session, see `sessions.rs`.
+// The first link sent to an email links it to a username, and later links log in as that username.
+// Only usernames nobody has played as can be linked, so that an email can't take over a playtak player's history.
+// Login tokens are HMAC-signed rather than stored, so only ones that have been used are kept, to stop them being used twice.
+// Off unless `auth.secret` is set in the config
+
+const MAX_EMAIL_LENGTH: usize = 254;
+const MAGIC_LINK_PURPOSE: &str = "magic-link";
+
+pub fn init_db_tables(db_conn: &Connection) -> anyhow::Result<()> {
+    db_conn.execute(
+        "CREATE TABLE IF NOT EXISTS user_emails (
+            username TEXT PRIMARY KEY,
+            email TEXT NOT NULL UNIQUE,
+            created_seconds INTEGER NOT NULL DEFAULT (strftime('%s', 'now'))
+        )",
+        [],
+    )?;
+    // Kept until the link would have expired anyway
+    db_conn.execute(
+        "CREATE TABLE IF NOT EXISTS used_magic_links (
+            nonce TEXT PRIMARY KEY,
+            expires_seconds INTEGER NOT NULL
+        )",
+        [],
+    )?;
+
+    Ok(())
+}
+
+// What a login token vouches for
+#[derive(Serialize, Deserialize)]
+struct MagicLink {
+    email: String,
+    username: String,
+    expires_seconds: u64,
+    // Identifies the link once it's been used
+    nonce: String,
+}
+
+pub fn random_token(length: usize) -> String {
+    rand::rng()
+        .sample_iter(&Alphanumeric)
+        .take(length)
+        .map(char::from)
+        .collect()
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{byte:02x}")).collect()
+}
+
+fn unhex(text: &str) -> Option<Vec<u8>> {
+    if !text.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..text.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(text.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+// The value's JSON in hex, a dot, and an HMAC-SHA256 in hex of the purpose and the JSON.
+// Signing the purpose too means a token made for one thing can't be passed off as another
+pub fn sign(secret: &str, purpose: &str, value: &impl Serialize) -> String {
+    let payload = hex(&serde_json::to_vec(value).unwrap_or_default());
+    let key = ring::hmac::Key::new(ring::hmac::HMAC_SHA256, secret.as_bytes());
+    let signature = ring::hmac::sign(&key, format!("{purpose}:{payload}").as_bytes());
+    format!("{payload}.{}", hex(signature.as_ref()))
+}
+
+// The value a token was signed for, if the signature matches. Doesn't check whether it has expired
+pub fn verify<T: DeserializeOwned>(secret: &str, purpose: &str, token: &str) -> Option<T> {
+    let (payload, signature) = token.split_once('.')?;
+    let key = ring::hmac::Key::new(ring::hmac::HMAC_SHA256, secret.as_bytes());
+    ring::hmac::verify(
+        &key,
+        format!("{purpose}:{payload}").as_bytes(),
+        &unhex(signature)?,
+    )
+    .ok()?;
+    serde_json::from_slice(&unhex(payload)?).ok()
+}
+
+// Only checks for the obvious mistakes. Whether it's real is up to the email arriving
+fn validate_email(email: &str) -> Result<(), ValidationError> {
+    let valid = email.len() <= MAX_EMAIL_LENGTH
+        && !email.chars().any(char::is_whitespace)
+        && email
+            .split_once('@')
+            .is_some_and(|(local, domain)| !local.is_empty() && domain.contains('.'));
+    if !valid {
+        return Err(ValidationError::new("email", "Invalid email address"));
+    }
+    Ok(())
+}
+
+// Emails are case-insensitive in practice, even though the local part technically isn't
+fn canonical_email(email: &str) -> String {
+    email.trim().to_lowercase()
+}
+
+fn linked_username(db_conn: &Connection, email: &str) -> anyhow::Result<Option<String>> {
+    Ok(db_conn
+        .query_row(
+            "SELECT username FROM user_emails WHERE email = ?1",
+            [email],
+            |row| row.get(0),
+        )
+        .optional()?)
+}
+
+// Whether anyone has played as the username. Every user who has made an attempt has a row in `users`
+fn has_played(db_conn: &Connection, username: &str) -> anyhow::Result<bool> {
+    Ok(db_conn
+        .prepare_cached("SELECT 1 FROM users WHERE username = ?1")?
+        .exists([username])?)
+}
+
+fn linked_email(db_conn: &Connection, username: &str) -> anyhow::Result<Option<String>> {
+    Ok(db_conn
+        .query_row(
+            "SELECT email FROM user_emails WHERE username = ?1",
+            [username],
+            |row| row.get(0),
+        )
+        .optional()?)
+}
+
+#[derive(Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct MagicLinkRequest {
+    email: String,
+    // The username to log in as. Only needed the first time, since the email is linked to it after that
+    username: Option<String>,
+}
+
+// Email a login link. The link expires after `auth.magic_link_ttl_seconds`, and only works once
+#[utoipa::path(
+    post,
+    path = "/auth/magic-link",
+    tag = "auth",
+    request_body = MagicLinkRequest,
+    responses(
+        (status = 202, description = "The link was sent"),
+        (status = 400, body = ValidationError),
+        (status = 404, description = "Login by email is off"),
+        (status = 409, description = "The username is linked to another email, or has been played as without one"),
+        (status = 502, description = "The email couldn't be sent"),
+    ),
+)]
+pub async fn send_magic_link(
+    State(state): State<AppState>,
+    Json(payload): Json<MagicLinkRequest>,
+) -> Result<StatusCode, ApiError> {
+    let secret = state
+        .config
+        .auth
+        .secret
+        .as_deref()
+        .ok_or(StatusCode::NOT_FOUND)?;
+    let email = canonical_email(&payload.email);
+    validate_email(&email)?;
+    let db_conn = db::open().map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let username = match linked_username(&db_conn, &email).map_err(|e| {
+        tracing::error!("Error reading user emails: {:?}", e);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })? {
+        Some(username) => username,
+        None => {
+            let username = payload.username.ok_or_else(|| {
+                ValidationError::new("username", "A username is needed for a new email")
+            })?;
+            validation::validate_username(&username)?;
+            let username = validation::canonical_username(&username);
+            let taken = linked_email(&db_conn, &username)
+                .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+                .is_some()
+                || has_played(&db_conn, &username)
+                    .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+            if taken {
+                return Err(StatusCode::CONFLICT.into());
+            }
+            username
+        }
+    };
+
+    let token = sign(
+        secret,
+        MAGIC_LINK_PURPOSE,
+        &MagicLink {
+            email: email.clone(),
+            username: username.clone(),
+            expires_seconds: now_seconds() + state.config.auth.magic_link_ttl_seconds,
+            nonce: random_token(24),
+        },
+    );
+    let url = state.config.auth.magic_link_url.replace("{token}", &token);
+    let mail = mail::Mail {
+        to: email,
+        subject: "Log in to Tak tactics".to_string(),
+        text: format!(
+            "Open this link to log in as {username}:\n\n{url}\n\nIt expires in {} minutes. If you didn't ask for it, you can ignore this email.",
+            state.config.auth.magic_link_ttl_seconds / 60
+        ),
+    };
+    state.mail.send(mail).await.map_err(|e| {
+        tracing::warn!("Failed to send login email: {:?}", e);
+        StatusCode::BAD_GATEWAY
+    })?;
+    Ok(StatusCode::ACCEPTED)
+}
+
+#[derive(Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct VerifyMagicLink {
+    // From the link
+    token: String,
+}
+
+// Log in with the token from a login link
+#[utoipa::path(
+    post,
+    path = "/auth/magic-link/verify",
+    tag = "auth",
+    request_body = VerifyMagicLink,
+    responses(
+        (status = 200, body = Tokens),
+        (status = 401, description = "The token is invalid, has expired or has been used"),
+        (status = 404, description = "Login by email is off"),
+        (status = 409, description = "The username was linked to another email or played as after the link was sent"),
+    ),
+)]
+pub async fn verify_magic_link(
+    State(state): State<AppState>,
+    Json(payload): Json<VerifyMagicLink>,
+) -> Result<Json<Tokens>, ApiError> {
+    let secret = state
+        .config
+        .auth
+        .secret
+        .as_deref()
+        .ok_or(StatusCode::NOT_FOUND)?;
+    let link: MagicLink =
+        verify(secret, MAGIC_LINK_PURPOSE, &payload.token).ok_or(StatusCode::UNAUTHORIZED)?;
+    if link.expires_seconds <= now_seconds() {
+        return Err(StatusCode::UNAUTHORIZED.into());
+    }
+
+    let mut db_conn = db::open().map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let transaction = db_conn
+        .transaction()
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let tokens = match use_link(&transaction, &link, secret, &state.config.auth) {
+        Ok(LoginOutcome::LoggedIn(tokens)) => tokens,
+        Ok(LoginOutcome::AlreadyUsed) => return Err(StatusCode::UNAUTHORIZED.into()),
+        Ok(LoginOutcome::LinkedElsewhere) => return Err(StatusCode::CONFLICT.into()),
+        Err(e) => {
+            tracing::error!("Error logging in with a login link: {:?}", e);
+            return Err(StatusCode::INTERNAL_SERVER_ERROR.into());
+        }
+    };
+    transaction
+        .commit()
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    Ok(Json(tokens))
+}
+
+enum LoginOutcome {
+    LoggedIn(Tokens),
+    AlreadyUsed,
+    // The email or the username has been linked to another, or the username played as, since the link was sent
+    LinkedElsewhere,
+}
+
+// Mark the link as used, link its email to its username, and start a session
+fn use_link(
+    db_conn: &Connection,
+    link: &MagicLink,
+    secret: &str,
+    config: &AuthConfig,
+) -> anyhow::Result<LoginOutcome> {
+    db_conn.execute(
+        "DELETE FROM used_magic_links WHERE expires_seconds <= ?1",
+        [now_seconds()],
+    )?;
+    let first_use = db_conn.execute(
+        "INSERT INTO used_magic_links (nonce, expires_seconds) VALUES (?1, ?2) ON CONFLICT DO NOTHING",
+        rusqlite::params![link.nonce, link.expires_seconds],
+    )? == 1;
+    if !first_use {
+        return Ok(LoginOutcome::AlreadyUsed);
+    }
+
+    match linked_email(db_conn, &link.username)? {
+        Some(email) if email != link.email => return Ok(LoginOutcome::LinkedElsewhere),
+        Some(_) => {}
+        None => {
+            if has_played(db_conn, &link.username)? {
+                return Ok(LoginOutcome::LinkedElsewhere);
+            }
+            let linked = db_conn.execute(
+                "INSERT INTO user_emails (username, email) VALUES (?1, ?2) ON CONFLICT DO NOTHING",
+                rusqlite::params![link.username, link.email],
+            )? == 1;
+            if !linked {
+                return Ok(LoginOutcome::LinkedElsewhere);
+            }
+        }
+    }
+
+    Ok(LoginOutcome::LoggedIn(sessions::start_session(
+        db_conn,
+        secret,
+        config,
+        &link.username,
+    )?))
+}
+
+// Whether the username has been claimed by logging in with an email, so that only its owner may use it
+pub fn is_claimed(db_conn: &Connection, username: &str) -> anyhow::Result<bool> {
+    Ok(db_conn
+        .prepare_cached("SELECT 1 FROM user_emails WHERE username = ?1")?
+        .exists([username])?)
+}
@@ -0,0 +1,109 @@
+use std::path::Path;
+
+use axum::{Json, extract::State, http::StatusCode};
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use crate::{AppState, admin::AdminAuth, audit, config::BackupsConfig, db, scheduler};
+
+// Backups are taken with `VACUUM INTO`, which copies the database from a single read transaction,
+// so the server keeps serving while it runs and the copy is consistent. They're plain SQLite files:
+// to restore one, stop the server and put it in place of `database.path`
+
+// Backup file names sort in the order they were taken
+const PREFIX: &str = "puzzles-";
+const EXTENSION: &str = ".db";
+
+#[derive(Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct Backup {
+    // In `backups.directory`
+    file_name: String,
+    size_bytes: u64,
+    // Older backups that were deleted to keep `backups.keep` of them
+    num_deleted: usize,
+}
+
+pub fn take_backup(config: &BackupsConfig) -> anyhow::Result<Backup> {
+    std::fs::create_dir_all(&config.directory)?;
+    let db_conn = db::open()?;
+    let timestamp: String =
+        db_conn.query_row("SELECT strftime('%Y-%m-%dT%H-%M-%fZ', 'now')", [], |row| {
+            row.get(0)
+        })?;
+    let file_name = format!("{PREFIX}{timestamp}{EXTENSION}");
+    let path = config.directory.join(&file_name);
+    let path_str = path
+        .to_str()
+        .ok_or_else(|| anyhow::anyhow!("Backup path {} isn't UTF-8", path.display()))?;
+    db_conn.execute("VACUUM INTO ?1", [path_str])?;
+    let size_bytes = std::fs::metadata(&path)?.len();
+    let num_deleted = rotate(&config.directory, config.keep.max(1))?;
+    Ok(Backup {
+        file_name,
+        size_bytes,
+        num_deleted,
+    })
+}
+
+// Delete all but the newest `keep` backups. Other files in the directory are left alone
+fn rotate(directory: &Path, keep: usize) -> anyhow::Result<usize> {
+    let mut backups = Vec::new();
+    for entry in std::fs::read_dir(directory)? {
+        let name = entry?.file_name().to_string_lossy().into_owned();
+        if name.starts_with(PREFIX) && name.ends_with(EXTENSION) {
+            backups.push(name);
+        }
+    }
+    backups.sort();
+    let num_deleted = backups.len().saturating_sub(keep);
+    for name in &backups[..num_deleted] {
+        std::fs::remove_file(directory.join(name))?;
+    }
+    Ok(num_deleted)
+}
+
+// The `backups` job, see `scheduler.rs`
+pub async fn run_backup(config: BackupsConfig) -> anyhow::Result<()> {
+    let backup = take_backup(&config)?;
+    tracing::info!(
+        "Backed up the database to {} ({} bytes)",
+        backup.file_name,
+        backup.size_bytes
+    );
+    Ok(())
+}
+
+// Take a backup now, as a run of the `backups` job
+#[utoipa::path(
+    post,
+    path = "/admin/backups",
+    tag = "admin",
+    security(("admin_token" = [])),
+    responses(
+        (status = 200, body = Backup),
+        (status = 401),
+        (status = 403),
+        (status = 409, description = "A backup is already being taken"),
+    ),
+)]
+pub async fn create_backup(
+    admin: AdminAuth,
+    State(state): State<AppState>,
+) -> Result<Json<Backup>, StatusCode> {
+    let config = &state.config.backups;
+    let backup = scheduler::run_as("backups", async { take_backup(config) })
+        .await
+        .map_err(|e| {
+            tracing::error!("Error taking backup: {:?}", e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?
+        .ok_or(StatusCode::CONFLICT)?;
+    audit::record_for(
+        &db::open().map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?,
+        &admin,
+        "create_backup",
+        serde_json::json!({"fileName": backup.file_name}),
+    )?;
+    Ok(Json(backup))
+}
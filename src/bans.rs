@@ -0,0 +1,206 @@
+use axum::{
+    Json,
+    extract::{Path, Query},
+    http::StatusCode,
+};
+use rusqlite::Connection;
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use crate::{
+    admin::AdminAuth,
+    audit, db,
+    pagination::{Page, PageQuery},
+    validation::{self, ApiError},
+};
+
+// Usernames that are not served puzzles and can't submit attempts, for clients that spam
+// or deliberately fail puzzles to poison their ratings. Their earlier attempts are kept,
+// use `rating-exclusion` as well to take them out of puzzle ratings
+pub fn init_db_tables(db_conn: &Connection) -> anyhow::Result<()> {
+    db_conn.execute(
+        "CREATE TABLE IF NOT EXISTS banned_users (
+            username TEXT PRIMARY KEY,
+            reason TEXT NOT NULL,
+            banned_seconds INTEGER NOT NULL DEFAULT (strftime('%s', 'now'))
+        )",
+        [],
+    )?;
+
+    Ok(())
+}
+
+pub fn is_banned(db_conn: &Connection, username: &str) -> anyhow::Result<bool> {
+    Ok(db_conn
+        .prepare_cached("SELECT 1 FROM banned_users WHERE username = ?1")?
+        .exists([username])?)
+}
+
+// For handlers that serve puzzles or record attempts, which return 403 for banned users
+pub fn check_not_banned(db_conn: &Connection, username: &str) -> Result<(), StatusCode> {
+    match is_banned(db_conn, username) {
+        Ok(false) => Ok(()),
+        Ok(true) => Err(StatusCode::FORBIDDEN),
+        Err(e) => {
+            tracing::error!("Error reading ban list from database: {:?}", e);
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct NewBan {
+    // What the user did, for other admins
+    reason: String,
+}
+
+#[derive(Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct Ban {
+    username: String,
+    reason: String,
+    banned_seconds: u64,
+}
+
+// Ban a user. Their attempt in progress is dropped rather than recorded as failed.
+// Banning a user who is already banned only updates the reason
+#[utoipa::path(
+    post,
+    path = "/admin/users/{username}/ban",
+    tag = "admin",
+    params(("username" = String, Path)),
+    request_body = NewBan,
+    security(("admin_token" = [])),
+    responses(
+        (status = 200, body = Ban),
+        (status = 400, body = validation::ValidationError),
+        (status = 401),
+        (status = 403),
+    ),
+)]
+pub async fn ban_user(
+    admin: AdminAuth,
+    Path(username): Path<String>,
+    Json(payload): Json<NewBan>,
+) -> Result<Json<Ban>, ApiError> {
+    let username = validation::canonical_username(&username);
+    validation::validate_username(&username)?;
+    let mut db_conn = db::open().map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let ban = write_ban(&mut db_conn, &username, &payload.reason).map_err(|e| {
+        tracing::error!("Error banning user: {:?}", e);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+    audit::record_for(
+        &db_conn,
+        &admin,
+        "ban_user",
+        serde_json::json!({"username": username, "reason": payload.reason}),
+    )?;
+    Ok(Json(ban))
+}
+
+// Lift a user's ban
+#[utoipa::path(
+    delete,
+    path = "/admin/users/{username}/ban",
+    tag = "admin",
+    params(("username" = String, Path)),
+    security(("admin_token" = [])),
+    responses(
+        (status = 204),
+        (status = 401),
+        (status = 403),
+        (status = 404, description = "The user isn't banned"),
+    ),
+)]
+pub async fn unban_user(
+    admin: AdminAuth,
+    Path(username): Path<String>,
+) -> Result<StatusCode, StatusCode> {
+    let username = validation::canonical_username(&username);
+    let db_conn = db::open().map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let num_deleted = db_conn
+        .execute("DELETE FROM banned_users WHERE username = ?1", [&username])
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    if num_deleted == 0 {
+        return Err(StatusCode::NOT_FOUND);
+    }
+    audit::record_for(
+        &db_conn,
+        &admin,
+        "unban_user",
+        serde_json::json!({"username": username}),
+    )?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+// List banned users, newest ban first
+#[utoipa::path(
+    get,
+    path = "/admin/bans",
+    tag = "admin",
+    params(PageQuery),
+    security(("admin_token" = [])),
+    responses(
+        (status = 200, body = Page<Ban>),
+        (status = 400, body = validation::ValidationError),
+        (status = 401),
+        (status = 403),
+    ),
+)]
+pub async fn get_bans(
+    _: AdminAuth,
+    Query(page): Query<PageQuery>,
+) -> Result<Json<Page<Ban>>, ApiError> {
+    let db_conn = db::open().map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let page = page.fetch(
+        |after, limit| read_bans(&db_conn, after, limit),
+        |ban| (ban.banned_seconds, ban.username.clone()),
+    )?;
+    Ok(Json(page))
+}
+
+fn write_ban(db_conn: &mut Connection, username: &str, reason: &str) -> anyhow::Result<Ban> {
+    let transaction = db_conn.transaction()?;
+    let ban = transaction.query_row(
+        "INSERT INTO banned_users (username, reason) VALUES (?1, ?2)
+        ON CONFLICT (username) DO UPDATE SET reason = excluded.reason
+        RETURNING username, reason, banned_seconds",
+        rusqlite::params![username, reason],
+        read_ban,
+    )?;
+    transaction.execute(
+        "DELETE FROM attempts_in_progress WHERE username = ?1",
+        [username],
+    )?;
+    transaction.commit()?;
+    Ok(ban)
+}
+
+fn read_ban(row: &rusqlite::Row) -> rusqlite::Result<Ban> {
+    Ok(Ban {
+        username: row.get(0)?,
+        reason: row.get(1)?,
+        banned_seconds: row.get(2)?,
+    })
+}
+
+fn read_bans(
+    db_conn: &Connection,
+    after: Option<(u64, String)>,
+    limit: u32,
+) -> anyhow::Result<Vec<Ban>> {
+    let (after_seconds, after_username) = after.unzip();
+    let mut stmt = db_conn.prepare(
+        "SELECT username, reason, banned_seconds FROM banned_users
+        WHERE ?1 IS NULL OR (banned_seconds, username) < (?1, ?2)
+        ORDER BY banned_seconds DESC, username DESC
+        LIMIT ?3",
+    )?;
+    let rows = stmt.query_map(
+        rusqlite::params![after_seconds, after_username, limit],
+        read_ban,
+    )?;
+    Ok(rows.collect::<Result<Vec<_>, _>>()?)
+}
@@ -0,0 +1,536 @@
+use anyhow::{anyhow, bail, ensure};
+
+use crate::validation;
+
+// Tak positions, read from TPS and changed by playing PTN moves, for rendering puzzles and judging submitted lines.
+// Moves are checked against the rules of movement and against the players' piece reserves
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Color {
+    White,
+    Black,
+}
+
+impl Color {
+    pub fn other(self) -> Self {
+        match self {
+            Color::White => Color::Black,
+            Color::Black => Color::White,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Role {
+    #[default]
+    Flat,
+    Wall,
+    Cap,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Stack {
+    // Bottom to top
+    pub colors: Vec<Color>,
+    // The role of the top piece. Every piece under it is flat
+    pub top_role: Role,
+}
+
+impl Stack {
+    pub fn top(&self) -> Option<(Color, Role)> {
+        self.colors.last().map(|&color| (color, self.top_role))
+    }
+}
+
+// Zero-based, so that a1 is file 0 and rank 0
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Square {
+    pub file: usize,
+    pub rank: usize,
+}
+
+impl Square {
+    fn parse(file: u8, rank: u8) -> anyhow::Result<Self> {
+        ensure!(
+            (b'a'..=b'h').contains(&file) && (b'1'..=b'8').contains(&rank),
+            "{}{} isn't a square, which is a file from `a` to `h` and a rank from 1 to 8",
+            file as char,
+            rank as char
+        );
+        Ok(Self {
+            file: (file - b'a') as usize,
+            rank: (rank - b'1') as usize,
+        })
+    }
+
+    fn step(self, direction: Direction) -> Option<Self> {
+        let (file, rank) = match direction {
+            Direction::Left => (self.file.checked_sub(1)?, self.rank),
+            Direction::Right => (self.file + 1, self.rank),
+            Direction::Up => (self.file, self.rank + 1),
+            Direction::Down => (self.file, self.rank.checked_sub(1)?),
+        };
+        Some(Self { file, rank })
+    }
+}
+
+impl std::fmt::Display for Square {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}{}", (b'a' + self.file as u8) as char, self.rank + 1)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Direction {
+    Left,
+    Right,
+    Up,
+    Down,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Move {
+    Place {
+        square: Square,
+        role: Role,
+    },
+    // Pick up `drops.iter().sum()` pieces and drop them one square further each time
+    Spread {
+        square: Square,
+        direction: Direction,
+        drops: Vec<usize>,
+    },
+}
+
+impl Move {
+    // Annotations like `'` and `!` are ignored. Errors say what's wrong with the move, for showing to users
+    pub fn parse(ptn_move: &str) -> anyhow::Result<Self> {
+        Self::parse_normalized(validation::normalize_move(ptn_move).as_bytes())
+            .map_err(|reason| anyhow!("Invalid move {ptn_move:?}: {reason}"))
+    }
+
+    fn parse_normalized(bytes: &[u8]) -> anyhow::Result<Self> {
+        match *bytes {
+            [] => bail!("it's empty"),
+            [file, rank] => Ok(Move::Place {
+                square: Square::parse(file, rank)?,
+                role: Role::Flat,
+            }),
+            [piece @ (b'F' | b'S' | b'C'), file, rank] => Ok(Move::Place {
+                square: Square::parse(file, rank)?,
+                role: match piece {
+                    b'S' => Role::Wall,
+                    b'C' => Role::Cap,
+                    _ => Role::Flat,
+                },
+            }),
+            _ => {
+                let (count, rest) = match bytes.first() {
+                    Some(digit @ b'1'..=b'8') => ((digit - b'0') as usize, &bytes[1..]),
+                    _ => (1, bytes),
+                };
+                let &[file, rank, direction, ref drops @ ..] = rest else {
+                    bail!("it's neither a placement like `Sc3` nor a spread like `3c3>12`");
+                };
+                let square = Square::parse(file, rank)?;
+                let direction = match direction {
+                    b'<' => Direction::Left,
+                    b'>' => Direction::Right,
+                    b'+' => Direction::Up,
+                    b'-' => Direction::Down,
+                    other => bail!(
+                        "{:?} isn't a direction, which is one of `<`, `>`, `+` and `-`",
+                        other as char
+                    ),
+                };
+                let drops: Vec<usize> = if drops.is_empty() {
+                    vec![count]
+                } else {
+                    drops
+                        .iter()
+                        .map(|&b| match b {
+                            b'1'..=b'8' => Ok((b - b'0') as usize),
+                            _ => Err(anyhow!("{:?} isn't a number of pieces to drop", b as char)),
+                        })
+                        .collect::<anyhow::Result<_>>()?
+                };
+                let total: usize = drops.iter().sum();
+                ensure!(
+                    total == count,
+                    "the drops add up to {total} pieces, but {count} are picked up"
+                );
+                Ok(Move::Spread {
+                    square,
+                    direction,
+                    drops,
+                })
+            }
+        }
+    }
+
+    // Check that the move doesn't leave a board of this size, or carry more than the carry limit.
+    // Doesn't need the position, unlike `Board::play`
+    pub fn check_fits(&self, size: usize) -> anyhow::Result<()> {
+        let squares = self.squares();
+        let (first, last) = (squares[0], squares[squares.len() - 1]);
+        ensure!(
+            first.file < size && first.rank < size,
+            "{} is off the {size}x{size} board",
+            first
+        );
+        if let Move::Spread { drops, .. } = self {
+            let count: usize = drops.iter().sum();
+            ensure!(
+                count <= size,
+                "it carries {count} pieces, but at most {size} can be carried on a {size}x{size} board"
+            );
+            ensure!(
+                squares.len() == drops.len() + 1 && last.file < size && last.rank < size,
+                "it spreads off the {size}x{size} board"
+            );
+        }
+        Ok(())
+    }
+
+    // Every square the move changes, starting with the one it was played from
+    pub fn squares(&self) -> Vec<Square> {
+        match self {
+            Move::Place { square, .. } => vec![*square],
+            Move::Spread {
+                square,
+                direction,
+                drops,
+            } => {
+                let mut squares = vec![*square];
+                let mut current = *square;
+                for _ in drops {
+                    match current.step(*direction) {
+                        Some(next) => current = next,
+                        None => break,
+                    }
+                    squares.push(current);
+                }
+                squares
+            }
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Board {
+    pub size: usize,
+    // By rank, from a1
+    stacks: Vec<Stack>,
+    pub to_move: Color,
+    // As in TPS and PTN, where both players' first moves are move 1
+    pub move_number: u32,
+}
+
+impl Board {
+    // A position like `x3,12,2S/x,22S,22C,11,21/121,212,12,1121C,1212S/21S,1,21,211S,12S/x,21S,2,x2 1 26`.
+    // Ranks are listed from the top, and stacks from the bottom
+    pub fn from_tps(tps: &str) -> anyhow::Result<Self> {
+        let invalid = || anyhow!("Invalid TPS {tps:?}");
+        let [rows, to_move, move_number] = tps
+            .split_whitespace()
+            .collect::<Vec<_>>()
+            .try_into()
+            .map_err(|_| invalid())?;
+        let rows: Vec<&str> = rows.split('/').collect();
+        let size = rows.len();
+        ensure!((3..=8).contains(&size), "Invalid board size {size} in TPS");
+
+        let mut stacks = vec![Stack::default(); size * size];
+        for (i, row) in rows.iter().enumerate() {
+            let rank = size - 1 - i;
+            let mut file = 0;
+            for item in row.split(',') {
+                if let Some(num_empty) = item.strip_prefix('x') {
+                    file += if num_empty.is_empty() {
+                        1
+                    } else {
+                        num_empty.parse::<usize>().map_err(|_| invalid())?
+                    };
+                    continue;
+                }
+                ensure!(file < size, "Rank {} in TPS is too long", rank + 1);
+                let (pieces, top_role) = match item.as_bytes().last() {
+                    Some(b'S') => (&item[..item.len() - 1], Role::Wall),
+                    Some(b'C') => (&item[..item.len() - 1], Role::Cap),
+                    _ => (item, Role::Flat),
+                };
+                let colors = pieces
+                    .bytes()
+                    .map(|b| match b {
+                        b'1' => Ok(Color::White),
+                        b'2' => Ok(Color::Black),
+                        _ => Err(invalid()),
+                    })
+                    .collect::<anyhow::Result<Vec<_>>>()?;
+                ensure!(!colors.is_empty(), "Invalid TPS {tps:?}");
+                stacks[rank * size + file] = Stack { colors, top_role };
+                file += 1;
+            }
+            ensure!(
+                file == size,
+                "Rank {} in TPS has {file} squares, not {size}",
+                rank + 1
+            );
+        }
+
+        let to_move = match to_move {
+            "1" => Color::White,
+            "2" => Color::Black,
+            _ => return Err(invalid()),
+        };
+        let move_number = move_number.parse().map_err(|_| invalid())?;
+        ensure!(move_number >= 1, "Invalid TPS {tps:?}");
+        Ok(Self {
+            size,
+            stacks,
+            to_move,
+            move_number,
+        })
+    }
+
+    pub fn stack(&self, square: Square) -> &Stack {
+        &self.stacks[square.rank * self.size + square.file]
+    }
+
+    // The stones and capstones each player starts with on a board of this size
+    pub fn reserves(size: usize) -> (usize, usize) {
+        match size {
+            3 => (10, 0),
+            4 => (15, 0),
+            5 => (21, 1),
+            6 => (30, 1),
+            7 => (40, 2),
+            _ => (50, 2),
+        }
+    }
+
+    // The stones and capstones of this colour on the board
+    fn pieces_used(&self, color: Color) -> (usize, usize) {
+        let mut stones = 0;
+        let mut caps = 0;
+        for stack in &self.stacks {
+            stones += stack.colors.iter().filter(|&&c| c == color).count();
+            if stack.top() == Some((color, Role::Cap)) {
+                stones -= 1;
+                caps += 1;
+            }
+        }
+        (stones, caps)
+    }
+
+    fn stack_mut(&mut self, square: Square) -> anyhow::Result<&mut Stack> {
+        ensure!(
+            square.file < self.size && square.rank < self.size,
+            "Square {square} is off the board"
+        );
+        Ok(&mut self.stacks[square.rank * self.size + square.file])
+    }
+
+    // Play a move for the player to move. The board is left as it was if the move is illegal
+    pub fn play(&mut self, mv: &Move) -> anyhow::Result<()> {
+        let mut board = self.clone();
+        board.apply(mv)?;
+        if board.to_move == Color::Black {
+            board.move_number += 1;
+        }
+        board.to_move = board.to_move.other();
+        *self = board;
+        Ok(())
+    }
+
+    fn apply(&mut self, mv: &Move) -> anyhow::Result<()> {
+        // On their first move, each player places one of the other player's flats
+        let first_move = self.move_number == 1;
+        match mv {
+            Move::Place { square, role } => {
+                let color = if first_move {
+                    ensure!(*role == Role::Flat, "The first moves must place flats");
+                    self.to_move.other()
+                } else {
+                    self.to_move
+                };
+                let (stones, caps) = Self::reserves(self.size);
+                let (stones_used, caps_used) = self.pieces_used(color);
+                if *role == Role::Cap {
+                    ensure!(caps_used < caps, "No capstones left to place");
+                } else {
+                    ensure!(stones_used < stones, "No stones left to place");
+                }
+                let stack = self.stack_mut(*square)?;
+                ensure!(stack.colors.is_empty(), "Can't place on a stack");
+                *stack = Stack {
+                    colors: vec![color],
+                    top_role: *role,
+                };
+            }
+            Move::Spread {
+                square,
+                direction,
+                drops,
+            } => {
+                ensure!(!first_move, "The first moves must be placements");
+                let count: usize = drops.iter().sum();
+                ensure!(
+                    count <= self.size,
+                    "Can't carry more than {} pieces",
+                    self.size
+                );
+                let to_move = self.to_move;
+                let stack = self.stack_mut(*square)?;
+                ensure!(
+                    stack.top().is_some_and(|(color, _)| color == to_move),
+                    "Can only move stacks the player controls"
+                );
+                ensure!(
+                    count <= stack.colors.len(),
+                    "Can't carry {count} pieces from a stack of {}",
+                    stack.colors.len()
+                );
+                let mut carried = stack.colors.split_off(stack.colors.len() - count);
+                let carried_role = std::mem::take(&mut stack.top_role);
+
+                let mut current = *square;
+                for (i, &drop) in drops.iter().enumerate() {
+                    ensure!(drop > 0, "Every drop must leave at least one piece");
+                    current = current
+                        .step(*direction)
+                        .ok_or_else(|| anyhow!("The move goes off the board"))?;
+                    let last = i == drops.len() - 1;
+                    let stack = self.stack_mut(current)?;
+                    match stack.top() {
+                        Some((_, Role::Cap)) => bail!("Can't move onto a capstone"),
+                        Some((_, Role::Wall)) => ensure!(
+                            last && drop == 1 && carried_role == Role::Cap,
+                            "Only a capstone on its own can flatten a wall"
+                        ),
+                        _ => (),
+                    }
+                    stack.colors.extend(carried.drain(..drop));
+                    stack.top_role = if last { carried_role } else { Role::Flat };
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn play(board: &mut Board, moves: &[&str]) -> anyhow::Result<()> {
+        for mv in moves {
+            board.play(&Move::parse(mv)?)?;
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn spreads_off_the_board_dont_fit() {
+        assert!(Move::parse("3a1>12").unwrap().check_fits(3).is_ok());
+        assert!(Move::parse("3a1>111").unwrap().check_fits(3).is_err());
+        assert!(Move::parse("a1<").unwrap().check_fits(3).is_err());
+        assert!(Move::parse("2b1-11").unwrap().check_fits(5).is_err());
+        assert!(Move::parse("d4").unwrap().check_fits(3).is_err());
+
+        let mut board = Board::from_tps("x3/x3/21,x2 1 3").unwrap();
+        let before = board.clone();
+        assert!(play(&mut board, &["a1<"]).is_err());
+        assert_eq!(board, before);
+    }
+
+    #[test]
+    fn carries_are_limited_by_the_board_size() {
+        assert!(Move::parse("4a1>22").unwrap().check_fits(3).is_err());
+        assert!(Move::parse("4a1>22").unwrap().check_fits(4).is_ok());
+
+        let mut board = Board::from_tps("x3/x3/2121,x2 1 10").unwrap();
+        assert!(play(&mut board, &["4a1>22"]).is_err());
+        assert!(play(&mut board, &["3a1>12"]).is_ok());
+        assert_eq!(board.stack(Square { file: 1, rank: 0 }).colors.len(), 1);
+        assert_eq!(board.stack(Square { file: 2, rank: 0 }).colors.len(), 2);
+    }
+
+    #[test]
+    fn only_a_lone_capstone_flattens_walls() {
+        let mut board = Board::from_tps("x5/x5/x5/x5/1C,12S,x3 1 10").unwrap();
+        play(&mut board, &["a1>"]).unwrap();
+        let b1 = board.stack(Square { file: 1, rank: 0 });
+        assert_eq!(b1.colors, [Color::White, Color::Black, Color::White]);
+        assert_eq!(b1.top_role, Role::Cap);
+
+        let mut board = Board::from_tps("x5/x5/x5/x5/1,2S,x3 1 10").unwrap();
+        assert!(play(&mut board, &["a1>"]).is_err());
+        let mut board = Board::from_tps("x5/x5/x5/x5/11C,2S,x3 1 10").unwrap();
+        assert!(play(&mut board, &["2a1>"]).is_err());
+        let mut board = Board::from_tps("x5/x5/x5/x5/1C,2C,x3 1 10").unwrap();
+        assert!(play(&mut board, &["a1>"]).is_err());
+    }
+
+    #[test]
+    fn first_moves_place_the_other_players_flats() {
+        let mut board = Board::from_tps("x3/x3/x3 1 1").unwrap();
+        assert!(play(&mut board, &["Sa1"]).is_err());
+        assert!(play(&mut board, &["Ca1"]).is_err());
+        play(&mut board, &["a1", "c3"]).unwrap();
+        assert_eq!(
+            board.stack(Square { file: 0, rank: 0 }).top(),
+            Some((Color::Black, Role::Flat))
+        );
+        assert_eq!(
+            board.stack(Square { file: 2, rank: 2 }).top(),
+            Some((Color::White, Role::Flat))
+        );
+        assert_eq!((board.to_move, board.move_number), (Color::White, 2));
+
+        let mut board = Board::from_tps("x3/x3/2,x2 2 1").unwrap();
+        assert!(play(&mut board, &["a1>"]).is_err());
+    }
+
+    #[test]
+    fn placements_need_pieces_in_reserve() {
+        // Every white stone on a 3x3 board, so white has none left
+        let mut board = Board::from_tps("1111,x2/1111,x2/11,x,2 1 12").unwrap();
+        assert!(play(&mut board, &["b2"]).is_err());
+        assert!(play(&mut board, &["Sb2"]).is_err());
+        board.to_move = Color::Black;
+        assert!(play(&mut board, &["b2"]).is_ok());
+
+        // 3x3 boards have no capstones
+        let mut board = Board::from_tps("x3/x3/1,x,2 1 3").unwrap();
+        assert!(play(&mut board, &["Cb2"]).is_err());
+
+        let mut board = Board::from_tps("x5/x5/x5/x5/1C,x4 2 3").unwrap();
+        assert!(play(&mut board, &["Cb1"]).is_ok());
+        assert!(play(&mut board, &["Cc1"]).is_err());
+        assert!(play(&mut board, &["c1"]).is_ok());
+    }
+
+    #[test]
+    fn malformed_tps_is_rejected() {
+        for tps in [
+            "",
+            "x3/x3/x3 1",
+            "x3/x3/x3 1 1 1",
+            "x3/x3 1 1",
+            "x9/x9/x9/x9/x9/x9/x9/x9/x9 1 1",
+            "x3/x3/x4 1 1",
+            "x3/x3/x2 1 1",
+            "x3/x3/1,1,1,1 1 1",
+            "x3/x3/13,x2 1 1",
+            "x3/x3/S,x2 1 1",
+            "x3/x3/xy,x2 1 1",
+            "x3/x3/x3 3 1",
+            "x3/x3/x3 1 0",
+            "x3/x3/x3 1 one",
+        ] {
+            assert!(Board::from_tps(tps).is_err(), "{tps:?} was accepted");
+        }
+        assert!(Board::from_tps("x3/x,12S,x/2,x2 2 5").is_ok());
+    }
+}
@@ -0,0 +1,237 @@
+use axum::{
+    Json,
+    extract::{Path, Query},
+    http::StatusCode,
+};
+use rusqlite::Connection;
+use serde::{Deserialize, Serialize};
+
+use crate::{Puzzle, PuzzleRequest, bans, db, puzzle_sets, validation};
+use utoipa::ToSchema;
+
+pub fn init_db_tables(db_conn: &Connection) -> anyhow::Result<()> {
+    // Each chapter is backed by a puzzle set. If `required_solved` is null,
+    // every puzzle in the chapter must be attempted to unlock the next one
+    // Chapters have to be inserted manually for now
+    db_conn.execute(
+        "CREATE TABLE IF NOT EXISTS campaign_chapters (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            set_id INTEGER NOT NULL UNIQUE,
+            position INTEGER NOT NULL UNIQUE,
+            required_solved INTEGER,
+            FOREIGN KEY (set_id) REFERENCES puzzle_sets(id)
+        )",
+        [],
+    )?;
+
+    // Unlocks are never revoked, even if the requirements are changed later
+    db_conn.execute(
+        "CREATE TABLE IF NOT EXISTS campaign_unlocks (
+            username TEXT NOT NULL,
+            chapter_id INTEGER NOT NULL,
+            unlocked_seconds INTEGER NOT NULL DEFAULT (strftime('%s', 'now')),
+            PRIMARY KEY (username, chapter_id),
+            FOREIGN KEY (chapter_id) REFERENCES campaign_chapters(id)
+        )",
+        [],
+    )?;
+
+    Ok(())
+}
+
+#[derive(Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct CampaignChapter {
+    id: u64,
+    set_id: u64,
+    name: String,
+    description: String,
+    position: u32,
+    required_solved: Option<u32>,
+    num_puzzles: u32,
+    num_attempted: u32,
+    num_solved: u32,
+    unlocked: bool,
+    passed: bool,
+}
+
+pub struct ChapterUnlock {
+    pub chapter_id: u64,
+    pub set_id: u64,
+    pub unlocked: bool,
+}
+
+struct ChapterRow {
+    id: u64,
+    set_id: u64,
+    name: String,
+    description: String,
+    position: u32,
+    required_solved: Option<u32>,
+}
+
+// Get every chapter of the campaign, with the user's progress and unlock state
+#[utoipa::path(
+    get,
+    path = "/campaign",
+    tag = "campaign",
+    params(PuzzleRequest),
+    responses((status = 200, body = Vec<CampaignChapter>)),
+)]
+pub async fn get_campaign(
+    Query(request): Query<PuzzleRequest>,
+) -> Result<Json<Vec<CampaignChapter>>, StatusCode> {
+    let username = validation::canonical_username(&request.username);
+    if username.is_empty() {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+    let db_conn = db::open().map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    update_unlocks(&db_conn, &username).map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let chapters = read_campaign_for_user(&db_conn, &username)
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    Ok(Json(chapters))
+}
+
+// Get the next unattempted puzzle in a chapter. The chapter must be unlocked
+#[utoipa::path(
+    get,
+    path = "/campaign/chapters/{id}/next",
+    tag = "campaign",
+    params(("id" = u64, Path), PuzzleRequest),
+    responses(
+        (status = 200, body = Puzzle),
+        (status = 403, description = "The chapter is locked, or the user is banned"),
+        (status = 404),
+    ),
+)]
+pub async fn get_next_puzzle_in_chapter(
+    Path(id): Path<u64>,
+    Query(request): Query<PuzzleRequest>,
+) -> Result<Json<Puzzle>, StatusCode> {
+    let username = validation::canonical_username(&request.username);
+    if username.is_empty() {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+    let db_conn = db::open().map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    bans::check_not_banned(&db_conn, &username)?;
+    update_unlocks(&db_conn, &username).map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let chapter = read_campaign_for_user(&db_conn, &username)
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+        .into_iter()
+        .find(|chapter| chapter.id == id)
+        .ok_or(StatusCode::NOT_FOUND)?;
+    if !chapter.unlocked {
+        return Err(StatusCode::FORBIDDEN);
+    }
+    match puzzle_sets::read_next_unattempted_puzzle_in_set(&db_conn, chapter.set_id, &username) {
+        Ok(Some(puzzle)) => Ok(Json(puzzle.into())),
+        Ok(None) => Err(StatusCode::NOT_FOUND),
+        Err(e) => {
+            tracing::error!("Error reading campaign chapter from database: {:?}", e);
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
+// Unlock every chapter whose preceding chapter the user has passed.
+// The first chapter is always unlocked
+pub fn update_unlocks(db_conn: &Connection, username: &str) -> anyhow::Result<()> {
+    let chapters = read_chapters(db_conn)?;
+    let mut previous_passed = true;
+    for chapter in chapters {
+        if previous_passed {
+            db_conn.execute(
+                "INSERT OR IGNORE INTO campaign_unlocks (username, chapter_id) VALUES (?1, ?2)",
+                rusqlite::params![username, chapter.id],
+            )?;
+        }
+        let Some(progress) =
+            puzzle_sets::read_puzzle_set_progress(db_conn, chapter.set_id, username)?
+        else {
+            break;
+        };
+        previous_passed = chapter_passed(chapter.required_solved, &progress);
+    }
+    Ok(())
+}
+
+pub fn read_chapter_unlocks(
+    db_conn: &Connection,
+    username: &str,
+) -> anyhow::Result<Vec<ChapterUnlock>> {
+    let mut stmt = db_conn.prepare(
+        "SELECT campaign_chapters.id, campaign_chapters.set_id,
+            campaign_unlocks.chapter_id IS NOT NULL
+        FROM campaign_chapters
+        LEFT JOIN campaign_unlocks ON campaign_unlocks.chapter_id = campaign_chapters.id
+            AND campaign_unlocks.username = ?1
+        ORDER BY campaign_chapters.position",
+    )?;
+    let rows = stmt.query_map([username], |row| {
+        Ok(ChapterUnlock {
+            chapter_id: row.get(0)?,
+            set_id: row.get(1)?,
+            unlocked: row.get(2)?,
+        })
+    })?;
+    Ok(rows.collect::<Result<Vec<_>, _>>()?)
+}
+
+fn chapter_passed(required_solved: Option<u32>, progress: &puzzle_sets::PuzzleSetProgress) -> bool {
+    match required_solved {
+        Some(required_solved) => progress.num_solved >= required_solved,
+        None => progress.completed,
+    }
+}
+
+fn read_chapters(db_conn: &Connection) -> anyhow::Result<Vec<ChapterRow>> {
+    let mut stmt = db_conn.prepare(
+        "SELECT campaign_chapters.id, campaign_chapters.set_id, puzzle_sets.name,
+            puzzle_sets.description, campaign_chapters.position, campaign_chapters.required_solved
+        FROM campaign_chapters JOIN puzzle_sets ON puzzle_sets.id = campaign_chapters.set_id
+        ORDER BY campaign_chapters.position",
+    )?;
+    let rows = stmt.query_map([], |row| {
+        Ok(ChapterRow {
+            id: row.get(0)?,
+            set_id: row.get(1)?,
+            name: row.get(2)?,
+            description: row.get(3)?,
+            position: row.get(4)?,
+            required_solved: row.get(5)?,
+        })
+    })?;
+    Ok(rows.collect::<Result<Vec<_>, _>>()?)
+}
+
+fn read_campaign_for_user(
+    db_conn: &Connection,
+    username: &str,
+) -> anyhow::Result<Vec<CampaignChapter>> {
+    let mut unlocked_stmt = db_conn
+        .prepare("SELECT 1 FROM campaign_unlocks WHERE username = ?1 AND chapter_id = ?2")?;
+
+    let mut chapters = vec![];
+    for chapter in read_chapters(db_conn)? {
+        let Some(progress) =
+            puzzle_sets::read_puzzle_set_progress(db_conn, chapter.set_id, username)?
+        else {
+            continue;
+        };
+        let unlocked = unlocked_stmt.exists(rusqlite::params![username, chapter.id])?;
+        chapters.push(CampaignChapter {
+            id: chapter.id,
+            set_id: chapter.set_id,
+            name: chapter.name,
+            description: chapter.description,
+            position: chapter.position,
+            required_solved: chapter.required_solved,
+            num_puzzles: progress.num_puzzles,
+            num_attempted: progress.num_attempted,
+            num_solved: progress.num_solved,
+            unlocked,
+            passed: chapter_passed(chapter.required_solved, &progress),
+        });
+    }
+    Ok(chapters)
+}
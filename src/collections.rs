@@ -0,0 +1,346 @@
+use axum::{
+    Json,
+    extract::{Path, Query, State},
+    http::StatusCode,
+};
+use rand::{Rng, distr::Alphanumeric};
+use rusqlite::{Connection, OptionalExtension};
+use serde::{Deserialize, Serialize};
+use serde_rusqlite::from_row;
+
+use crate::{
+    AppState, Puzzle, PuzzleRequest, PuzzleRow, bans, db,
+    validation::{self, ApiError},
+};
+use utoipa::ToSchema;
+
+pub fn init_db_tables(db_conn: &Connection) -> anyhow::Result<()> {
+    db_conn.execute(
+        "CREATE TABLE IF NOT EXISTS collections (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            slug TEXT NOT NULL UNIQUE,
+            owner TEXT NOT NULL,
+            name TEXT NOT NULL,
+            created_seconds INTEGER NOT NULL DEFAULT (strftime('%s', 'now'))
+        )",
+        [],
+    )?;
+
+    db_conn.execute(
+        "CREATE TABLE IF NOT EXISTS collection_entries (
+            collection_id INTEGER NOT NULL,
+            puzzle_id INTEGER NOT NULL,
+            position INTEGER NOT NULL,
+            PRIMARY KEY (collection_id, puzzle_id),
+            FOREIGN KEY (collection_id) REFERENCES collections(id),
+            FOREIGN KEY (puzzle_id) REFERENCES puzzles(id)
+        )",
+        [],
+    )?;
+
+    Ok(())
+}
+
+#[derive(Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct Collection {
+    slug: String,
+    owner: String,
+    name: String,
+    puzzle_ids: Vec<u64>,
+}
+
+#[derive(Serialize, Deserialize, ToSchema)]
+pub struct CreateCollectionRequest {
+    username: String,
+    name: String,
+}
+
+#[derive(Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct AddPuzzleRequest {
+    username: String,
+    puzzle_id: u64,
+}
+
+struct CollectionRow {
+    id: u64,
+    slug: String,
+    owner: String,
+    name: String,
+}
+
+// Create a new, empty collection owned by the user
+#[utoipa::path(
+    post,
+    path = "/collections",
+    tag = "collections",
+    request_body = CreateCollectionRequest,
+    responses(
+        (status = 200, body = Collection),
+        (status = 400, body = validation::ValidationError),
+    ),
+)]
+pub async fn create_collection(
+    Json(payload): Json<CreateCollectionRequest>,
+) -> Result<Json<Collection>, ApiError> {
+    validation::validate_username(&payload.username)?;
+    validation::validate_name(&payload.name)?;
+    let owner = validation::canonical_username(&payload.username);
+    let db_conn = db::open().map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let slug = generate_slug();
+    db_conn
+        .execute(
+            "INSERT INTO collections (slug, owner, name) VALUES (?1, ?2, ?3)",
+            rusqlite::params![slug, owner, payload.name],
+        )
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    Ok(Json(Collection {
+        slug,
+        owner,
+        name: payload.name,
+        puzzle_ids: vec![],
+    }))
+}
+
+// Get a collection by its public slug
+#[utoipa::path(
+    get,
+    path = "/collections/{slug}",
+    tag = "collections",
+    params(("slug" = String, Path)),
+    responses((status = 200, body = Collection), (status = 404)),
+)]
+pub async fn get_collection(Path(slug): Path<String>) -> Result<Json<Collection>, StatusCode> {
+    let db_conn = db::open().map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let row = read_collection_by_slug(&db_conn, &slug)
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+        .ok_or(StatusCode::NOT_FOUND)?;
+    let collection =
+        read_collection(&db_conn, row).map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    Ok(Json(collection))
+}
+
+// List all collections created by a user
+#[utoipa::path(
+    get,
+    path = "/users/{username}/collections",
+    tag = "collections",
+    params(("username" = String, Path)),
+    responses((status = 200, body = Vec<Collection>)),
+)]
+pub async fn get_collections_for_user(
+    Path(username): Path<String>,
+) -> Result<Json<Vec<Collection>>, StatusCode> {
+    let username = validation::canonical_username(&username);
+    let db_conn = db::open().map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let collections = read_collections_for_user(&db_conn, &username)
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    Ok(Json(collections))
+}
+
+// Add a puzzle to the end of a collection. Only the owner may do this
+#[utoipa::path(
+    post,
+    path = "/collections/{slug}/puzzles",
+    tag = "collections",
+    params(("slug" = String, Path)),
+    request_body = AddPuzzleRequest,
+    responses(
+        (status = 200, body = Collection),
+        (status = 403, description = "Not the owner of the collection"),
+        (status = 404),
+    ),
+)]
+pub async fn add_puzzle_to_collection(
+    Path(slug): Path<String>,
+    State(state): State<AppState>,
+    Json(payload): Json<AddPuzzleRequest>,
+) -> Result<Json<Collection>, StatusCode> {
+    let db_conn = db::open().map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let username = validation::canonical_username(&payload.username);
+    let row = read_owned_collection(&db_conn, &slug, &username)?;
+    state
+        .store
+        .published_puzzle(payload.puzzle_id as u32)
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+        .ok_or(StatusCode::NOT_FOUND)?;
+    db_conn
+        .execute(
+            "INSERT OR IGNORE INTO collection_entries (collection_id, puzzle_id, position)
+            VALUES (?1, ?2, (SELECT COALESCE(MAX(position), -1) + 1 FROM collection_entries WHERE collection_id = ?1))",
+            rusqlite::params![row.id, payload.puzzle_id],
+        )
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let collection =
+        read_collection(&db_conn, row).map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    Ok(Json(collection))
+}
+
+// Remove a puzzle from a collection. Only the owner may do this
+#[utoipa::path(
+    delete,
+    path = "/collections/{slug}/puzzles/{puzzle_id}",
+    tag = "collections",
+    params(("slug" = String, Path), ("puzzle_id" = u64, Path), PuzzleRequest),
+    responses(
+        (status = 200, body = Collection),
+        (status = 403, description = "Not the owner of the collection"),
+        (status = 404),
+    ),
+)]
+pub async fn remove_puzzle_from_collection(
+    Path((slug, puzzle_id)): Path<(String, u64)>,
+    Query(request): Query<PuzzleRequest>,
+) -> Result<Json<Collection>, StatusCode> {
+    let username = validation::canonical_username(&request.username);
+    let db_conn = db::open().map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let row = read_owned_collection(&db_conn, &slug, &username)?;
+    db_conn
+        .execute(
+            "DELETE FROM collection_entries WHERE collection_id = ?1 AND puzzle_id = ?2",
+            rusqlite::params![row.id, puzzle_id],
+        )
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let collection =
+        read_collection(&db_conn, row).map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    Ok(Json(collection))
+}
+
+// Get the first puzzle in the collection that the user hasn't attempted yet
+#[utoipa::path(
+    get,
+    path = "/collections/{slug}/next",
+    tag = "collections",
+    params(("slug" = String, Path), PuzzleRequest),
+    responses(
+        (status = 200, body = Puzzle),
+        (status = 403, description = "The user is banned"),
+        (status = 404),
+    ),
+)]
+pub async fn get_next_puzzle_in_collection(
+    Path(slug): Path<String>,
+    Query(request): Query<PuzzleRequest>,
+) -> Result<Json<Puzzle>, StatusCode> {
+    let username = validation::canonical_username(&request.username);
+    if username.is_empty() {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+    let db_conn = db::open().map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let row = read_collection_by_slug(&db_conn, &slug)
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+        .ok_or(StatusCode::NOT_FOUND)?;
+    bans::check_not_banned(&db_conn, &username)?;
+    match read_next_unattempted_puzzle_in_collection(&db_conn, row.id, &username) {
+        Ok(Some(puzzle)) => Ok(Json(puzzle.into())),
+        Ok(None) => Err(StatusCode::NOT_FOUND),
+        Err(e) => {
+            tracing::error!("Error reading collection from database: {:?}", e);
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
+fn generate_slug() -> String {
+    rand::rng()
+        .sample_iter(&Alphanumeric)
+        .take(10)
+        .map(char::from)
+        .collect()
+}
+
+fn read_owned_collection(
+    db_conn: &Connection,
+    slug: &str,
+    username: &str,
+) -> Result<CollectionRow, StatusCode> {
+    let row = read_collection_by_slug(db_conn, slug)
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+        .ok_or(StatusCode::NOT_FOUND)?;
+    if username.is_empty() || row.owner != username {
+        return Err(StatusCode::FORBIDDEN);
+    }
+    Ok(row)
+}
+
+fn read_collection_by_slug(
+    db_conn: &Connection,
+    slug: &str,
+) -> anyhow::Result<Option<CollectionRow>> {
+    Ok(db_conn
+        .query_row(
+            "SELECT id, slug, owner, name FROM collections WHERE slug = ?1",
+            [slug],
+            |row| {
+                Ok(CollectionRow {
+                    id: row.get(0)?,
+                    slug: row.get(1)?,
+                    owner: row.get(2)?,
+                    name: row.get(3)?,
+                })
+            },
+        )
+        .optional()?)
+}
+
+fn read_collections_for_user(
+    db_conn: &Connection,
+    username: &str,
+) -> anyhow::Result<Vec<Collection>> {
+    let mut stmt =
+        db_conn.prepare("SELECT id, slug, owner, name FROM collections WHERE owner = ?1")?;
+    let rows = stmt
+        .query_map([username], |row| {
+            Ok(CollectionRow {
+                id: row.get(0)?,
+                slug: row.get(1)?,
+                owner: row.get(2)?,
+                name: row.get(3)?,
+            })
+        })?
+        .collect::<Result<Vec<_>, _>>()?;
+    rows.into_iter()
+        .map(|row| read_collection(db_conn, row))
+        .collect()
+}
+
+fn read_collection(db_conn: &Connection, row: CollectionRow) -> anyhow::Result<Collection> {
+    let mut stmt = db_conn.prepare(
+        "SELECT puzzle_id FROM collection_entries WHERE collection_id = ?1 ORDER BY position",
+    )?;
+    let puzzle_ids = stmt
+        .query_map([row.id], |row| row.get(0))?
+        .collect::<Result<Vec<u64>, _>>()?;
+    Ok(Collection {
+        slug: row.slug,
+        owner: row.owner,
+        name: row.name,
+        puzzle_ids,
+    })
+}
+
+fn read_next_unattempted_puzzle_in_collection(
+    db_conn: &Connection,
+    collection_id: u64,
+    username: &str,
+) -> anyhow::Result<Option<PuzzleRow>> {
+    let mut stmt = db_conn.prepare(
+        "SELECT puzzles.* FROM collection_entries
+        JOIN puzzles ON puzzles.id = collection_entries.puzzle_id
+        WHERE collection_entries.collection_id = ?1 AND puzzles.published = 1 AND NOT EXISTS (
+            SELECT 1 FROM puzzle_attempts
+            WHERE puzzle_attempts.puzzle_id = puzzles.id
+                AND puzzle_attempts.user_id = (SELECT id FROM users WHERE username = ?2)
+        )
+        ORDER BY collection_entries.position LIMIT 1",
+    )?;
+    Ok(stmt
+        .query_and_then(
+            rusqlite::params![collection_id, username],
+            from_row::<PuzzleRow>,
+        )?
+        .next()
+        .transpose()?)
+}
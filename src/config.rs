@@ -0,0 +1,461 @@
+use std::{
+    collections::BTreeMap,
+    net::SocketAddr,
+    path::{Path, PathBuf},
+};
+
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
+
+use crate::scheduler::Schedule;
+
+// Server configuration, read from a TOML file at startup.
+// The file is `TAK_TACTICS_CONFIG` if set, otherwise the optional `config.toml` in the working directory.
+// Every setting has a default, so the file may only contain some sections
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub server: ServerConfig,
+    pub database: DatabaseConfig,
+    pub cors: CorsConfig,
+    pub compression: CompressionConfig,
+    pub rate_limit: RateLimitConfig,
+    pub error_reporting: ErrorReportingConfig,
+    pub target_time: TargetTimeConfig,
+    pub attempts: AttemptsConfig,
+    pub seasons: SeasonsConfig,
+    pub rating_limits: RatingLimitsConfig,
+    pub retention: RetentionConfig,
+    pub scheduler: SchedulerConfig,
+    pub backups: BackupsConfig,
+    pub discord: DiscordConfig,
+    pub feed: FeedConfig,
+    pub auth: AuthConfig,
+    pub mail: MailConfig,
+    pub devices: DevicesConfig,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ServerConfig {
+    // The plaintext HTTP listener
+    pub address: SocketAddr,
+    // Set to false to not listen on `address`, for example when only serving on `unix_socket`
+    pub tcp: bool,
+    // Also serve plaintext HTTP on a Unix socket, for a reverse proxy on the same host.
+    // Requests on it have no client IP, so set `rate_limit.trust_forwarded_for` to rate limit them per IP
+    pub unix_socket: Option<PathBuf>,
+    // Also serve HTTPS, for deployments without a reverse proxy in front
+    pub tls: Option<TlsConfig>,
+    // Start without accepting attempts, see `read_only.rs`
+    pub read_only: bool,
+    // Also serve the gRPC API in `proto/`, on its own port. Off if not set
+    pub grpc_address: Option<SocketAddr>,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        Self {
+            address: SocketAddr::from(([0, 0, 0, 0], 3000)),
+            tcp: true,
+            unix_socket: None,
+            tls: None,
+            read_only: false,
+            grpc_address: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TlsConfig {
+    pub address: SocketAddr,
+    // PEM files. The certificate file may contain the whole chain
+    pub cert_path: PathBuf,
+    pub key_path: PathBuf,
+    // Check the files for changes this often, and reload them if they changed.
+    // Lets certificates be renewed without a restart. Off if not set
+    pub reload_interval_seconds: Option<u64>,
+}
+
+// SQLite settings, applied to every connection when it's opened
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DatabaseConfig {
+    // All data lives in this one SQLite file, except what's in Postgres if `postgres_url` is set.
+    // SQLite URIs work too, like `file:test?mode=memory&cache=shared` for a database that only lives in memory
+    pub path: String,
+    pub journal_mode: JournalMode,
+    pub synchronous: Synchronous,
+    // How long to wait for another connection's lock before failing with `SQLITE_BUSY`
+    pub busy_timeout_ms: u64,
+    pub foreign_keys: bool,
+    // Store puzzles, attempts and ratings in Postgres instead, see `postgres.rs`. Needs the `postgres` feature.
+    // The other modules still run their SQL on the SQLite file, so only the puzzle handlers can be
+    // spread over several instances so far
+    pub postgres_url: Option<String>,
+}
+
+// WAL lets reads run alongside the writer, instead of waiting for it
+impl Default for DatabaseConfig {
+    fn default() -> Self {
+        Self {
+            path: "puzzles.db".to_string(),
+            journal_mode: JournalMode::Wal,
+            synchronous: Synchronous::Normal,
+            busy_timeout_ms: 5000,
+            foreign_keys: true,
+            postgres_url: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum JournalMode {
+    Delete,
+    Truncate,
+    Persist,
+    Memory,
+    Wal,
+    Off,
+}
+
+// `normal` is safe in WAL mode, but may lose the last commits on power loss
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Synchronous {
+    Off,
+    Normal,
+    Full,
+    Extra,
+}
+
+// Which browser origins may call the API. Lock `allowed_origins` down to the frontend's domain in production
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CorsConfig {
+    // Full origins, like "https://example.com", or "*" for any origin
+    pub allowed_origins: Vec<String>,
+    pub allowed_methods: Vec<String>,
+    // Request headers that browsers may send, or "*" for any
+    pub allowed_headers: Vec<String>,
+}
+
+impl Default for CorsConfig {
+    fn default() -> Self {
+        Self {
+            allowed_origins: vec!["*".to_string()],
+            allowed_methods: ["GET", "POST", "PUT", "DELETE"].map(String::from).to_vec(),
+            allowed_headers: [
+                "authorization",
+                "content-type",
+                "idempotency-key",
+                "x-device-id",
+                "x-request-id",
+            ]
+            .map(String::from)
+            .to_vec(),
+        }
+    }
+}
+
+// Compress responses for clients that accept it. Gzip and Brotli are chosen by the `Accept-Encoding` header
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CompressionConfig {
+    pub enabled: bool,
+    pub gzip: bool,
+    pub br: bool,
+    // Smaller responses aren't worth compressing
+    pub min_size_bytes: u16,
+}
+
+impl Default for CompressionConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            gzip: true,
+            br: true,
+            min_size_bytes: 1024,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RateLimitConfig {
+    pub enabled: bool,
+    // Requests per minute from a single IP address, to any endpoint
+    pub per_ip_per_minute: u32,
+    // Requests per minute that include a given username, in the query string or a JSON body
+    pub per_username_per_minute: u32,
+    // Use the first address in `X-Forwarded-For` as the client IP. Only enable this behind a reverse proxy
+    pub trust_forwarded_for: bool,
+}
+
+impl Default for RateLimitConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            per_ip_per_minute: 300,
+            per_username_per_minute: 60,
+            trust_forwarded_for: false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ErrorReportingConfig {
+    // Panics and 5xx responses are posted here as JSON, see `error_reporting.rs`
+    pub webhook_url: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DiscordConfig {
+    // A channel's webhook URL, from its integration settings. New puzzle reports are posted there, see `discord.rs`
+    pub webhook_url: Option<String>,
+}
+
+// `GET /puzzles/feed.atom`, see `feed.rs`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct FeedConfig {
+    pub title: String,
+    // Where each entry links to, with `{id}` replaced by the puzzle's id. Relative URLs are relative to the feed.
+    // Defaults to the puzzle in the API, set it to the puzzle's page on the site
+    pub puzzle_url: String,
+}
+
+impl Default for FeedConfig {
+    fn default() -> Self {
+        Self {
+            title: "Tak tactics puzzles".to_string(),
+            puzzle_url: "{id}".to_string(),
+        }
+    }
+}
+
+// Passwordless login by email, for users without a playtak account, see `auth.rs`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AuthConfig {
+    // Signs login links. Login by email is off if not set. Changing it invalidates every link that has been sent
+    pub secret: Option<String>,
+    // Where login links point to, with `{token}` replaced by the link's token. Set it to the frontend's login page,
+    // which should post the token to `/auth/magic-link/verify`
+    pub magic_link_url: String,
+    pub magic_link_ttl_seconds: u64,
+    // Access tokens have to be replaced with `/auth/refresh` this often
+    pub access_token_ttl_seconds: u64,
+    // Sessions end if they aren't refreshed for this long
+    pub refresh_token_ttl_seconds: u64,
+}
+
+impl Default for AuthConfig {
+    fn default() -> Self {
+        Self {
+            secret: None,
+            magic_link_url: "/login?token={token}".to_string(),
+            magic_link_ttl_seconds: 15 * 60,
+            access_token_ttl_seconds: 15 * 60,
+            refresh_token_ttl_seconds: 30 * 24 * 60 * 60,
+        }
+    }
+}
+
+// How emails are sent, see `mail.rs`. Without an API URL, emails are only logged
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct MailConfig {
+    // Emails are posted here as JSON, with `from`, `to`, `subject` and `text`
+    pub api_url: Option<String>,
+    // Sent as `Authorization: Bearer <api_key>`
+    pub api_key: Option<String>,
+    pub from: String,
+}
+
+impl Default for MailConfig {
+    fn default() -> Self {
+        Self {
+            api_url: None,
+            api_key: None,
+            from: "Tak tactics <noreply@localhost>".to_string(),
+        }
+    }
+}
+
+// Limits on rating anonymous attempts by the device they came from, see `devices.rs`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DevicesConfig {
+    // Set to false to rate every user's first attempt, whichever device it came from
+    pub one_rated_attempt_per_device: bool,
+}
+
+impl Default for DevicesConfig {
+    fn default() -> Self {
+        Self {
+            one_rated_attempt_per_device: true,
+        }
+    }
+}
+
+// How puzzles' target times are scaled to the rating of the user solving them, see `target_time.rs`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TargetTimeConfig {
+    // Set to false to give every user the puzzle's own target time
+    pub enabled: bool,
+    // The target time doubles for every this many rating points the puzzle is above the user,
+    // and halves for every this many points it's below
+    pub doubling_rating_difference: f64,
+    // Limits on the scaling, as factors of the puzzle's own target time
+    pub min_factor: f64,
+    pub max_factor: f64,
+}
+
+impl Default for TargetTimeConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            doubling_rating_difference: 400.0,
+            min_factor: 0.5,
+            max_factor: 2.0,
+        }
+    }
+}
+
+// Attempts that have been served but not submitted yet, see `in_progress.rs`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AttemptsConfig {
+    // Attempts that still haven't been submitted this long after the puzzle was served are recorded as failed
+    pub abandon_after_seconds: u64,
+}
+
+impl Default for AttemptsConfig {
+    fn default() -> Self {
+        Self {
+            abandon_after_seconds: 60 * 60,
+        }
+    }
+}
+
+// Seasonal ratings, which start over at the default every season, see `seasons.rs`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SeasonsConfig {
+    pub enabled: bool,
+    // Seasons start on January 1st and every this many months after, in UTC.
+    // Changing it only moves the boundaries of seasons that haven't started yet
+    pub length_months: u32,
+}
+
+impl Default for SeasonsConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            length_months: 3,
+        }
+    }
+}
+
+// Floors and ceilings for ratings, applied to every rating `ratings.rs` computes. Not set means no limit.
+// Ratings that hit a limit after a rated attempt are recorded in `rating_clamps`, see `rating_history.rs`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RatingLimitsConfig {
+    pub user_floor: Option<f64>,
+    pub user_ceiling: Option<f64>,
+    pub puzzle_floor: Option<f64>,
+    pub puzzle_ceiling: Option<f64>,
+}
+
+impl Default for RatingLimitsConfig {
+    fn default() -> Self {
+        Self {
+            user_floor: Some(800.0),
+            user_ceiling: None,
+            puzzle_floor: Some(500.0),
+            puzzle_ceiling: Some(3500.0),
+        }
+    }
+}
+
+// Old data that's removed so that the database doesn't grow without bound, see `retention.rs`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RetentionConfig {
+    pub enabled: bool,
+    // Users whose usernames start with this, in any case, are guests. Playtak names its guests like "Guest123"
+    pub guest_prefix: String,
+    // Guests that haven't made an attempt for this many days are deleted, like `DELETE /users/{username}`.
+    // Their attempts that don't count toward puzzle ratings are removed, and the rest are kept anonymized
+    pub guest_inactive_days: u64,
+    // Attempts in progress this old are removed without being recorded,
+    // in case `attempts.abandon_after_seconds` is longer than this
+    pub in_progress_days: u64,
+}
+
+impl Default for RetentionConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            guest_prefix: "guest".to_string(),
+            guest_inactive_days: 90,
+            in_progress_days: 7,
+        }
+    }
+}
+
+// Copies of the database taken while the server runs, see `backups.rs`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct BackupsConfig {
+    // Whether the `backups` job runs. Backups can be taken with `POST /admin/backups` either way
+    pub enabled: bool,
+    // Created if it doesn't exist. Should be on another disk than the database, to survive losing it
+    pub directory: PathBuf,
+    // The oldest backups are deleted once there are more than this many
+    pub keep: usize,
+}
+
+impl Default for BackupsConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            directory: PathBuf::from("backups"),
+            keep: 7,
+        }
+    }
+}
+
+// When background jobs run, see `scheduler.rs`
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SchedulerConfig {
+    // Cron expressions by job name, like `purge-old-data = "0 4 * * *"`, replacing the jobs' default schedules
+    pub schedules: BTreeMap<String, Schedule>,
+}
+
+impl Config {
+    pub fn load() -> anyhow::Result<Self> {
+        match std::env::var("TAK_TACTICS_CONFIG") {
+            Ok(path) => Self::from_file(Path::new(&path)),
+            Err(_) if Path::new("config.toml").exists() => {
+                Self::from_file(Path::new("config.toml"))
+            }
+            Err(_) => Ok(Self::default()),
+        }
+    }
+
+    pub fn from_file(path: &Path) -> anyhow::Result<Self> {
+        let contents = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read config file {}", path.display()))?;
+        toml::from_str(&contents)
+            .with_context(|| format!("Failed to parse config file {}", path.display()))
+    }
+}
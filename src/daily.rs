@@ -0,0 +1,184 @@
+use axum::{
+    Json,
+    extract::Path,
+    http::{HeaderValue, StatusCode, header},
+    response::{IntoResponse, Response},
+};
+use rusqlite::{Connection, OptionalExtension};
+use serde::{Deserialize, Serialize};
+use serde_rusqlite::from_row;
+use utoipa::ToSchema;
+
+use crate::{
+    Puzzle, PuzzleRow, db, now_seconds,
+    webhooks::{self, WebhookEvent},
+};
+
+const DAY_SECONDS: u64 = 86400;
+
+pub fn init_db_tables(db_conn: &Connection) -> anyhow::Result<()> {
+    // `day` is the number of days since the unix epoch, in UTC
+    db_conn.execute(
+        "CREATE TABLE IF NOT EXISTS daily_puzzles (
+            day INTEGER PRIMARY KEY,
+            puzzle_id INTEGER NOT NULL,
+            FOREIGN KEY (puzzle_id) REFERENCES puzzles(id)
+        )",
+        [],
+    )?;
+
+    Ok(())
+}
+
+pub fn current_day() -> i64 {
+    (now_seconds() / DAY_SECONDS) as i64
+}
+
+// Get today's puzzle, which is the same for everyone
+#[utoipa::path(
+    get,
+    path = "/daily",
+    tag = "daily",
+    responses((status = 200, body = Puzzle), (status = 404)),
+)]
+pub async fn get_daily_puzzle() -> Result<Json<Puzzle>, StatusCode> {
+    let db_conn = db::open().map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    match read_daily_puzzle(&db_conn, current_day()) {
+        Ok(Some(puzzle)) => Ok(Json(puzzle.into())),
+        Ok(None) => Err(StatusCode::NOT_FOUND),
+        Err(e) => {
+            tracing::error!("Error reading daily puzzle from database: {:?}", e);
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
+// Get the puzzle for the given day, picking one the first time a day is requested.
+// Puzzles that have not been featured before are preferred
+pub fn read_daily_puzzle(db_conn: &Connection, day: i64) -> anyhow::Result<Option<PuzzleRow>> {
+    // Same pool of puzzles as `read_unsolved_puzzles_from_db`
+    let num_picked = db_conn.execute(
+        "INSERT OR IGNORE INTO daily_puzzles (day, puzzle_id)
+        SELECT ?1, id FROM puzzles WHERE published = 1
+        ORDER BY id IN (SELECT puzzle_id FROM daily_puzzles), RANDOM() LIMIT 1",
+        [day],
+    )?;
+    let mut stmt = db_conn.prepare(
+        "SELECT puzzles.* FROM daily_puzzles
+        JOIN puzzles ON puzzles.id = daily_puzzles.puzzle_id WHERE daily_puzzles.day = ?1",
+    )?;
+    let puzzle = stmt
+        .query_and_then([day], from_row::<PuzzleRow>)?
+        .next()
+        .transpose()?;
+    if num_picked > 0
+        && let Some(puzzle) = &puzzle
+    {
+        webhooks::enqueue(
+            db_conn,
+            WebhookEvent::DailyPuzzle,
+            &Puzzle::from(puzzle.clone()),
+        )?;
+    }
+    Ok(puzzle)
+}
+
+// The `daily-puzzle` job, see `scheduler.rs`. Picks the puzzle when the day starts,
+// rather than on the first request, so that webhooks are sent on time
+pub async fn run_pick() -> anyhow::Result<()> {
+    read_daily_puzzle(&db::open()?, current_day())?;
+    Ok(())
+}
+
+// The daily puzzle for "puzzle of the day" widgets on other sites. Any origin may read it,
+// and it's cached by browsers and CDNs for as long as it can't change
+#[derive(Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct EmbeddedPuzzle {
+    // Days since the Unix epoch, in UTC
+    day: i64,
+    id: u64,
+    size: usize,
+    komi: String,
+    #[serde(rename = "rootTPS")]
+    root_tps: String,
+    defender_start_move: String,
+    // Relative to the API's origin
+    image_url: String,
+}
+
+// Get today's puzzle for embedding. Cached until the day ends
+#[utoipa::path(
+    get,
+    path = "/embed/daily",
+    tag = "daily",
+    responses((status = 200, body = EmbeddedPuzzle), (status = 404)),
+)]
+pub async fn get_embedded_daily_puzzle() -> Result<Response, StatusCode> {
+    let day = current_day();
+    let seconds_left = (day as u64 + 1) * DAY_SECONDS - now_seconds();
+    embedded_puzzle(day, &format!("public, max-age={seconds_left}"))
+}
+
+// Get the puzzle of a past day, or today, for embedding. Cached forever, since it never changes
+#[utoipa::path(
+    get,
+    path = "/embed/daily/{day}",
+    tag = "daily",
+    params(("day" = i64, Path, description = "Days since the Unix epoch, in UTC")),
+    responses((status = 200, body = EmbeddedPuzzle), (status = 404)),
+)]
+pub async fn get_embedded_puzzle_for_day(Path(day): Path<i64>) -> Result<Response, StatusCode> {
+    if day > current_day() {
+        return Err(StatusCode::NOT_FOUND);
+    }
+    embedded_puzzle(day, "public, max-age=31536000, immutable")
+}
+
+fn embedded_puzzle(day: i64, cache_control: &str) -> Result<Response, StatusCode> {
+    let db_conn = db::open().map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    // Past days that never had a puzzle aren't given one now
+    let puzzle = if day == current_day() {
+        read_daily_puzzle(&db_conn, day)
+    } else {
+        read_past_daily_puzzle(&db_conn, day)
+    }
+    .map_err(|e| {
+        tracing::error!("Error reading daily puzzle from database: {:?}", e);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?
+    .ok_or(StatusCode::NOT_FOUND)?;
+    let embedded = EmbeddedPuzzle {
+        day,
+        id: puzzle.id,
+        size: puzzle.size,
+        komi: puzzle.komi,
+        root_tps: puzzle.root_tps,
+        defender_start_move: puzzle.defender_start_move,
+        image_url: format!("/v1/puzzles/{}/image.svg", puzzle.id),
+    };
+    let mut response = Json(embedded).into_response();
+    let headers = response.headers_mut();
+    headers.insert(
+        header::CACHE_CONTROL,
+        HeaderValue::from_str(cache_control).map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?,
+    );
+    // Set here rather than by the CORS layer, so that widgets work whatever `cors.allowed_origins` is
+    headers.insert(
+        header::ACCESS_CONTROL_ALLOW_ORIGIN,
+        HeaderValue::from_static("*"),
+    );
+    Ok(response)
+}
+
+fn read_past_daily_puzzle(db_conn: &Connection, day: i64) -> anyhow::Result<Option<PuzzleRow>> {
+    Ok(db_conn
+        .query_row(
+            "SELECT puzzles.* FROM daily_puzzles
+            JOIN puzzles ON puzzles.id = daily_puzzles.puzzle_id WHERE daily_puzzles.day = ?1",
+            [day],
+            |row| Ok(from_row::<PuzzleRow>(row)),
+        )
+        .optional()?
+        .transpose()?)
+}
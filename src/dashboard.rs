@@ -0,0 +1,234 @@
+use axum::{Json, extract::Query, http::StatusCode};
+use rusqlite::Connection;
+use serde::{Deserialize, Serialize};
+use skillratings::glicko2::Glicko2Rating;
+use utoipa::{IntoParams, ToSchema};
+
+use crate::{
+    admin::AdminAuth,
+    db,
+    validation::{self, ApiError, ValidationError},
+};
+
+// Summaries of recent activity for an admin frontend, so that it doesn't need access to the database.
+// Days are UTC dates, and days without any activity are left out
+
+const DEFAULT_DAYS: u32 = 30;
+const MAX_DAYS: u32 = 365;
+// Puzzles with fewer rated attempts than this don't have a meaningful solve rate yet
+const MIN_ATTEMPTS_FOR_SOLVE_RATE: u32 = 5;
+const NUM_WORST_PUZZLES: u32 = 10;
+
+#[derive(Serialize, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct DashboardQuery {
+    // How many days back to summarize, including today. Defaults to 30
+    days: Option<u32>,
+}
+
+#[derive(Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct Dashboard {
+    days: u32,
+    attempts_per_day: Vec<DayAttempts>,
+    // Users are counted on the day of their first attempt, rated or not
+    new_users_per_day: Vec<DayNewUsers>,
+    // Over all rated attempts, not just the recent ones
+    worst_solve_rates: Vec<PuzzleSolveRate>,
+    num_unresolved_reports: u32,
+    // Not set if there are no unresolved reports
+    oldest_unresolved_report_seconds: Option<u64>,
+    rating_drift_per_day: Vec<DayRatingDrift>,
+}
+
+#[derive(Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct DayAttempts {
+    day: String,
+    num_attempts: u32,
+    num_rated_attempts: u32,
+    num_solved: u32,
+    num_users: u32,
+}
+
+#[derive(Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct DayNewUsers {
+    day: String,
+    num_users: u32,
+}
+
+#[derive(Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct PuzzleSolveRate {
+    puzzle_id: u32,
+    num_attempts: u32,
+    num_solved: u32,
+    solve_rate: f64,
+}
+
+// How much ratings moved on average per rated attempt. If users' ratings keep going up while puzzles' go down,
+// or the other way around, the rating pool is inflating or deflating
+#[derive(Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct DayRatingDrift {
+    day: String,
+    num_rated_attempts: u32,
+    // A user's first rated attempt is compared to the default rating
+    average_user_rating_change: Option<f64>,
+    // Not set if no puzzle had a rating before that day's attempts
+    average_puzzle_rating_change: Option<f64>,
+}
+
+// Summarize recent attempts, new users, the hardest puzzles, unresolved reports and rating drift
+#[utoipa::path(
+    get,
+    path = "/admin/dashboard",
+    tag = "admin",
+    params(DashboardQuery),
+    security(("admin_token" = [])),
+    responses(
+        (status = 200, body = Dashboard),
+        (status = 400, body = validation::ValidationError),
+        (status = 401),
+        (status = 403),
+    ),
+)]
+pub async fn get_dashboard(
+    _: AdminAuth,
+    Query(query): Query<DashboardQuery>,
+) -> Result<Json<Dashboard>, ApiError> {
+    let days = query.days.unwrap_or(DEFAULT_DAYS);
+    if !(1..=MAX_DAYS).contains(&days) {
+        return Err(
+            ValidationError::new("days", format!("Days must be between 1 and {MAX_DAYS}")).into(),
+        );
+    }
+    let db_conn = db::open().map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    match read_dashboard(&db_conn, days) {
+        Ok(dashboard) => Ok(Json(dashboard)),
+        Err(e) => {
+            tracing::error!("Error reading dashboard from database: {:?}", e);
+            Err(StatusCode::INTERNAL_SERVER_ERROR.into())
+        }
+    }
+}
+
+fn read_dashboard(db_conn: &Connection, days: u32) -> anyhow::Result<Dashboard> {
+    // The start of the first day, in seconds
+    let since: u64 = db_conn.query_row(
+        "SELECT CAST(strftime('%s', date('now', ?1)) AS INTEGER)",
+        [format!("-{} days", days - 1)],
+        |row| row.get(0),
+    )?;
+
+    let attempts_per_day = db_conn
+        .prepare(
+            "SELECT date(timestamp_seconds, 'unixepoch') AS day, COUNT(*),
+                SUM(attempt_number = 1 AND practice = 0 AND shared_device = 0), SUM(solved), COUNT(DISTINCT user_id)
+            FROM puzzle_attempts
+            WHERE timestamp_seconds >= ?1
+            GROUP BY day
+            ORDER BY day",
+        )?
+        .query_map([since], |row| {
+            Ok(DayAttempts {
+                day: row.get(0)?,
+                num_attempts: row.get(1)?,
+                num_rated_attempts: row.get(2)?,
+                num_solved: row.get(3)?,
+                num_users: row.get(4)?,
+            })
+        })?
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let new_users_per_day = db_conn
+        .prepare(
+            "SELECT date(first_seconds, 'unixepoch') AS day, COUNT(*)
+            FROM (SELECT MIN(timestamp_seconds) AS first_seconds FROM puzzle_attempts GROUP BY user_id)
+            WHERE first_seconds >= ?1
+            GROUP BY day
+            ORDER BY day",
+        )?
+        .query_map([since], |row| {
+            Ok(DayNewUsers {
+                day: row.get(0)?,
+                num_users: row.get(1)?,
+            })
+        })?
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let worst_solve_rates = db_conn
+        .prepare(
+            "SELECT rated_attempts.puzzle_id, COUNT(*) AS num_attempts, SUM(rated_attempts.solved) AS num_solved
+            FROM rated_attempts JOIN puzzles ON puzzles.id = rated_attempts.puzzle_id
+            WHERE puzzles.published = 1
+            GROUP BY rated_attempts.puzzle_id
+            HAVING num_attempts >= ?1
+            ORDER BY CAST(num_solved AS REAL) / num_attempts, num_attempts DESC, rated_attempts.puzzle_id
+            LIMIT ?2",
+        )?
+        .query_map([MIN_ATTEMPTS_FOR_SOLVE_RATE, NUM_WORST_PUZZLES], |row| {
+            let num_attempts: u32 = row.get(1)?;
+            let num_solved: u32 = row.get(2)?;
+            Ok(PuzzleSolveRate {
+                puzzle_id: row.get(0)?,
+                num_attempts,
+                num_solved,
+                solve_rate: num_solved as f64 / num_attempts as f64,
+            })
+        })?
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let (num_unresolved_reports, oldest_unresolved_report_seconds) = db_conn.query_row(
+        "SELECT COUNT(*), MIN(created_seconds) FROM puzzle_reports WHERE resolved_seconds IS NULL",
+        [],
+        |row| Ok((row.get(0)?, row.get(1)?)),
+    )?;
+
+    // The changes are computed over the whole history, so that the first change in the window
+    // is still compared to the rating before it
+    let rating_drift_per_day = db_conn
+        .prepare(
+            "WITH user_changes AS (
+                SELECT attempt_id, timestamp_seconds, rating - LAG(rating, 1, ?2) OVER (
+                    PARTITION BY username ORDER BY timestamp_seconds, attempt_id
+                ) AS change
+                FROM user_rating_history
+            ),
+            puzzle_changes AS (
+                SELECT attempt_id, rating - LAG(rating) OVER (
+                    PARTITION BY puzzle_id ORDER BY attempt_id
+                ) AS change
+                FROM puzzle_rating_history
+            )
+            SELECT date(user_changes.timestamp_seconds, 'unixepoch') AS day, COUNT(*),
+                AVG(user_changes.change), AVG(puzzle_changes.change)
+            FROM user_changes LEFT JOIN puzzle_changes ON puzzle_changes.attempt_id = user_changes.attempt_id
+            WHERE user_changes.timestamp_seconds >= ?1
+            GROUP BY day
+            ORDER BY day",
+        )?
+        .query_map(
+            rusqlite::params![since, Glicko2Rating::default().rating],
+            |row| {
+                Ok(DayRatingDrift {
+                    day: row.get(0)?,
+                    num_rated_attempts: row.get(1)?,
+                    average_user_rating_change: row.get(2)?,
+                    average_puzzle_rating_change: row.get(3)?,
+                })
+            },
+        )?
+        .collect::<Result<Vec<_>, _>>()?;
+
+    Ok(Dashboard {
+        days,
+        attempts_per_day,
+        new_users_per_day,
+        worst_solve_rates,
+        num_unresolved_reports,
+        oldest_unresolved_report_seconds,
+        rating_drift_per_day,
+    })
+}
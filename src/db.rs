@@ -0,0 +1,127 @@
+use std::{
+    panic::{self, AssertUnwindSafe},
+    sync::OnceLock,
+    time::Duration,
+};
+
+use anyhow::anyhow;
+use rusqlite::{
+    Connection,
+    trace::{TraceEvent, TraceEventCodes},
+};
+
+use crate::config::{DatabaseConfig, JournalMode, Synchronous};
+
+// Statements slower than this are logged as warnings
+const SLOW_STATEMENT: Duration = Duration::from_millis(100);
+
+static CONFIG: OnceLock<DatabaseConfig> = OnceLock::new();
+
+// Set the settings for every connection opened afterwards. Should be called once at startup,
+// before any connections are opened. Until then, connections use the default settings
+pub fn configure(config: DatabaseConfig) {
+    if CONFIG.set(config).is_err() {
+        tracing::warn!("Database settings were already set, ignoring the new ones");
+    }
+}
+
+// Open a connection to the database.
+// Every statement run on it is logged at debug level with its duration,
+// within the span of whatever is running it, like a request or a `telemetry::time_db_query`
+pub fn open() -> rusqlite::Result<Connection> {
+    let config = CONFIG.get_or_init(Default::default);
+    let db_conn = Connection::open(&config.path)?;
+    db_conn.trace_v2(TraceEventCodes::SQLITE_TRACE_PROFILE, Some(log_statement));
+    apply_settings(&db_conn, config)?;
+    Ok(db_conn)
+}
+
+fn apply_settings(db_conn: &Connection, config: &DatabaseConfig) -> rusqlite::Result<()> {
+    let journal_mode = match config.journal_mode {
+        JournalMode::Delete => "delete",
+        JournalMode::Truncate => "truncate",
+        JournalMode::Persist => "persist",
+        JournalMode::Memory => "memory",
+        JournalMode::Wal => "wal",
+        JournalMode::Off => "off",
+    };
+    let synchronous = match config.synchronous {
+        Synchronous::Off => "off",
+        Synchronous::Normal => "normal",
+        Synchronous::Full => "full",
+        Synchronous::Extra => "extra",
+    };
+    db_conn.busy_timeout(Duration::from_millis(config.busy_timeout_ms))?;
+    // Setting the journal mode returns the new mode, so it can't go through `pragma_update`
+    db_conn.pragma_update_and_check(None, "journal_mode", journal_mode, |_| Ok(()))?;
+    db_conn.pragma_update(None, "synchronous", synchronous)?;
+    db_conn.pragma_update(None, "foreign_keys", config.foreign_keys)?;
+    Ok(())
+}
+
+// SQLite only allows one writer at a time, so concurrent writes on separate connections fail with `SQLITE_BUSY`.
+// Instead, writes are sent to a single thread that owns the only write connection, and run one at a time.
+// Reads can still use their own connections
+#[derive(Clone)]
+pub struct Writer {
+    sender: std::sync::mpsc::Sender<WriteJob>,
+}
+
+type WriteJob = Box<dyn FnOnce(&mut Connection) + Send>;
+
+impl Writer {
+    pub fn start() -> anyhow::Result<Self> {
+        let mut db_conn = open()?;
+        let (sender, receiver) = std::sync::mpsc::channel::<WriteJob>();
+        std::thread::Builder::new()
+            .name("db-writer".to_string())
+            .spawn(move || {
+                for job in receiver {
+                    // A panicking write only fails that write, not every write after it
+                    let _ = panic::catch_unwind(AssertUnwindSafe(|| job(&mut db_conn)));
+                }
+            })?;
+        Ok(Self { sender })
+    }
+
+    // Run `write` on the write connection, once every write queued before it has finished.
+    // It runs within the caller's span, so its statements are logged as part of the request
+    pub async fn write<T: Send + 'static>(
+        &self,
+        write: impl FnOnce(&mut Connection) -> T + Send + 'static,
+    ) -> anyhow::Result<T> {
+        let (result_sender, result) = tokio::sync::oneshot::channel();
+        let span = tracing::Span::current();
+        self.sender
+            .send(Box::new(move |db_conn| {
+                let _ = result_sender.send(span.in_scope(|| write(db_conn)));
+            }))
+            .map_err(|_| anyhow!("The database writer has stopped"))?;
+        result
+            .await
+            .map_err(|_| anyhow!("The database write panicked"))
+    }
+}
+
+fn log_statement(event: TraceEvent) {
+    let TraceEvent::Profile(statement, duration) = event else {
+        return;
+    };
+    let sql = statement.sql();
+    let sql = sql.as_ref();
+    let duration_ms = duration.as_secs_f64() * 1000.0;
+    if duration >= SLOW_STATEMENT {
+        tracing::warn!(target: "sql", duration_ms, sql, "Slow SQL statement");
+    } else {
+        tracing::debug!(target: "sql", duration_ms, sql, "SQL statement");
+    }
+}
+
+// Run when shutting down, after every request has finished.
+// Lets SQLite update its query planner statistics, which it otherwise only does when asked
+pub fn close() -> anyhow::Result<()> {
+    let db_conn = open()?;
+    db_conn.execute_batch("PRAGMA optimize;")?;
+    db_conn.close().map_err(|(_, e)| e)?;
+    Ok(())
+}
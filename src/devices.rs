@@ -0,0 +1,76 @@
+use std::{
+    convert::Infallible,
+    net::{IpAddr, SocketAddr},
+};
+
+use axum::{
+    extract::{ConnectInfo, FromRequestParts},
+    http::request::Parts,
+};
+
+use crate::{AppState, admin::hash_token, rate_limit, sessions::AuthUser};
+
+// Guests can make up a new username for every attempt, so a puzzle's rating could be pushed around by failing it
+// under name after name. Anonymous attempts are tagged with where they came from: the `X-Device-Id` header,
+// a random id the frontend keeps in local storage, and the client's IP address. A first attempt is only rated
+// if no other user has a rated attempt at the puzzle from the same device id or IP. Clearing local storage
+// doesn't get around that, but guests sharing an IP with someone who already tried the puzzle aren't rated either.
+// Logged in users are trusted with their own attempts, see `sessions.rs`.
+// Only hashes are stored, so that the database doesn't hold IPs in the clear
+
+pub const DEVICE_ID_HEADER: &str = "x-device-id";
+const MAX_DEVICE_ID_LENGTH: usize = 64;
+
+// Extractor for the device a request came from. Empty if the user is logged in,
+// or if `devices.one_rated_attempt_per_device` is off
+#[derive(Clone, Debug, Default)]
+pub struct Device {
+    pub id_hash: Option<String>,
+    pub ip_hash: Option<String>,
+}
+
+impl FromRequestParts<AppState> for Device {
+    type Rejection = Infallible;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &AppState,
+    ) -> Result<Self, Self::Rejection> {
+        if !state.config.devices.one_rated_attempt_per_device
+            || parts.extensions.get::<AuthUser>().is_some()
+        {
+            return Ok(Device::default());
+        }
+        let id_hash = parts
+            .headers
+            .get(DEVICE_ID_HEADER)
+            .and_then(|value| value.to_str().ok())
+            .map(str::trim)
+            .filter(|id| !id.is_empty() && id.len() <= MAX_DEVICE_ID_LENGTH)
+            .map(|id| hash_token(&format!("device:{id}")));
+        let ip_hash = rate_limit::client_ip(
+            &parts.headers,
+            parts.extensions.get::<ConnectInfo<SocketAddr>>(),
+            state.config.rate_limit.trust_forwarded_for,
+        )
+        .map(hash_ip);
+        Ok(Device { id_hash, ip_hash })
+    }
+}
+
+impl Device {
+    // For requests without headers, like the gRPC API's
+    pub fn from_ip(state: &AppState, ip: Option<IpAddr>) -> Self {
+        if !state.config.devices.one_rated_attempt_per_device {
+            return Device::default();
+        }
+        Device {
+            id_hash: None,
+            ip_hash: ip.map(hash_ip),
+        }
+    }
+}
+
+fn hash_ip(ip: IpAddr) -> String {
+    hash_token(&format!("ip:{ip}"))
+}
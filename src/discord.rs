@@ -0,0 +1,59 @@
+use serde::Serialize;
+
+use crate::config::DiscordConfig;
+
+// Posts short messages to a Discord channel through the webhook in `[discord]` in the config,
+// so that moderators hear about reports without watching `/admin/reports`. Off if no webhook is set.
+// Messages are sent in the background, and ones that fail are only logged
+#[derive(Clone, Default)]
+pub struct Discord {
+    webhook_url: Option<String>,
+    client: reqwest::Client,
+}
+
+// See https://discord.com/developers/docs/resources/webhook#execute-webhook
+#[derive(Serialize)]
+struct Message {
+    content: String,
+    allowed_mentions: AllowedMentions,
+}
+
+#[derive(Serialize)]
+struct AllowedMentions {
+    parse: [&'static str; 0],
+}
+
+// Discord rejects longer messages
+const MAX_CONTENT_LENGTH: usize = 2000;
+
+impl Discord {
+    pub fn new(config: &DiscordConfig) -> Self {
+        Self {
+            webhook_url: config.webhook_url.clone(),
+            client: reqwest::Client::new(),
+        }
+    }
+
+    pub fn notify(&self, content: String) {
+        let Some(webhook_url) = self.webhook_url.clone() else {
+            return;
+        };
+        // Text from users shouldn't be able to ping `@everyone`
+        let message = Message {
+            content: content.chars().take(MAX_CONTENT_LENGTH).collect(),
+            allowed_mentions: AllowedMentions { parse: [] },
+        };
+        let client = self.client.clone();
+        tokio::spawn(async move {
+            let result = client
+                .post(&webhook_url)
+                .json(&message)
+                .send()
+                .await
+                .and_then(|response| response.error_for_status());
+            if let Err(e) = result {
+                tracing::warn!("Failed to send Discord notification: {}", e);
+            }
+        });
+    }
+}
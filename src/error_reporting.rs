@@ -0,0 +1,177 @@
+use std::{
+    cell::RefCell,
+    panic::{self, PanicHookInfo},
+};
+
+use axum::{
+    extract::{Request, State},
+    middleware::Next,
+    response::Response,
+};
+use serde::Serialize;
+use tokio::sync::{mpsc, oneshot};
+
+use crate::{AppState, config::ErrorReportingConfig, now_seconds, telemetry};
+
+// Panics and 5xx responses are posted as JSON to the webhook in `[error_reporting]` in the config,
+// so that production failures don't only show up in the logs. Reporting is off if no webhook is set
+#[derive(Clone, Default)]
+pub struct ErrorReporter {
+    sender: Option<mpsc::UnboundedSender<Message>>,
+}
+
+enum Message {
+    Report(ErrorReport),
+    // Answered once every earlier report has been sent
+    Flush(oneshot::Sender<()>),
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ErrorReport {
+    // Either "panic" or "serverError"
+    kind: &'static str,
+    message: String,
+    version: &'static str,
+    timestamp_seconds: u64,
+    // The rest is only set for errors during a request
+    status: Option<u16>,
+    method: Option<String>,
+    uri: Option<String>,
+    request_id: Option<String>,
+}
+
+impl ErrorReport {
+    fn new(kind: &'static str, message: String) -> Self {
+        Self {
+            kind,
+            message,
+            version: env!("CARGO_PKG_VERSION"),
+            timestamp_seconds: now_seconds(),
+            status: None,
+            method: None,
+            uri: None,
+            request_id: None,
+        }
+    }
+}
+
+tokio::task_local! {
+    // Set while handling a request. A panic during the request is stored here instead of being reported
+    // straight away, so that `report_errors` can report it along with the request
+    static REQUEST_PANIC: RefCell<Option<String>>;
+}
+
+impl ErrorReporter {
+    // Start posting reports to the configured webhook, if there is one
+    pub fn start(config: &ErrorReportingConfig) -> Self {
+        let Some(webhook_url) = config.webhook_url.clone() else {
+            return Self::default();
+        };
+        let (sender, mut receiver) = mpsc::unbounded_channel::<Message>();
+        tokio::spawn(async move {
+            let client = reqwest::Client::new();
+            while let Some(message) = receiver.recv().await {
+                let report = match message {
+                    Message::Report(report) => report,
+                    Message::Flush(done) => {
+                        let _ = done.send(());
+                        continue;
+                    }
+                };
+                let result = client
+                    .post(&webhook_url)
+                    .json(&report)
+                    .send()
+                    .await
+                    .and_then(|response| response.error_for_status());
+                if let Err(e) = result {
+                    tracing::warn!("Failed to send error report: {}", e);
+                }
+            }
+        });
+        Self {
+            sender: Some(sender),
+        }
+    }
+
+    fn report(&self, report: ErrorReport) {
+        if let Some(sender) = &self.sender {
+            let _ = sender.send(Message::Report(report));
+        }
+    }
+
+    // Wait for every report so far to be sent, so that none are lost when shutting down
+    pub async fn flush(&self) {
+        if let Some(sender) = &self.sender {
+            let (done, flushed) = oneshot::channel();
+            if sender.send(Message::Flush(done)).is_ok() {
+                let _ = flushed.await;
+            }
+        }
+    }
+
+    // Report every panic, in addition to the default behaviour of printing it
+    pub fn install_panic_hook(&self) {
+        let reporter = self.clone();
+        let default_hook = panic::take_hook();
+        panic::set_hook(Box::new(move |info| {
+            default_hook(info);
+            let message = panic_message(info);
+            let in_request = REQUEST_PANIC
+                .try_with(|panic| *panic.borrow_mut() = Some(message.clone()))
+                .is_ok();
+            if !in_request {
+                reporter.report(ErrorReport::new("panic", message));
+            }
+        }));
+    }
+}
+
+fn panic_message(info: &PanicHookInfo) -> String {
+    let payload = info
+        .payload()
+        .downcast_ref::<&str>()
+        .map(|s| s.to_string())
+        .or_else(|| info.payload().downcast_ref::<String>().cloned())
+        .unwrap_or_else(|| "Box<dyn Any>".to_string());
+    match info.location() {
+        Some(location) => format!("{payload} at {location}"),
+        None => payload,
+    }
+}
+
+// Report 5xx responses, including panics that were turned into a 500 by `CatchPanicLayer`
+pub async fn report_errors(
+    State(state): State<AppState>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let method = request.method().to_string();
+    let uri = request.uri().to_string();
+    let request_id = request
+        .headers()
+        .get(telemetry::REQUEST_ID_HEADER)
+        .and_then(|id| id.to_str().ok())
+        .map(String::from);
+    let (response, panic) = REQUEST_PANIC
+        .scope(RefCell::new(None), async {
+            let response = next.run(request).await;
+            (response, REQUEST_PANIC.with(|panic| panic.take()))
+        })
+        .await;
+    if response.status().is_server_error() {
+        let (kind, message) = match panic {
+            Some(message) => ("panic", message),
+            None => ("serverError", response.status().to_string()),
+        };
+        state.error_reporter.report(ErrorReport {
+            status: Some(response.status().as_u16()),
+            method: Some(method),
+            uri: Some(uri),
+            request_id,
+            ..ErrorReport::new(kind, message)
+        });
+    }
+    response
+}
@@ -0,0 +1,46 @@
+use std::hash::{DefaultHasher, Hash, Hasher};
+
+use axum::{
+    http::{HeaderMap, HeaderValue, StatusCode, header},
+    response::{IntoResponse, Response},
+};
+use serde::Serialize;
+
+// A weak ETag from a hash of `content`.
+// Weak, because it's derived from what the response is built from, rather than from the exact response bytes
+pub fn weak_etag(content: &impl Serialize) -> HeaderValue {
+    let mut hasher = DefaultHasher::new();
+    serde_json::to_vec(content).unwrap().hash(&mut hasher);
+    HeaderValue::from_str(&format!("W/\"{:016x}\"", hasher.finish())).unwrap()
+}
+
+// Respond with 304 Not Modified if the request's `If-None-Match` has the ETag, otherwise with `response`.
+// Either way the response has the ETag, and tells caches to revalidate it before every use
+pub fn with_etag(
+    request_headers: &HeaderMap,
+    etag: HeaderValue,
+    response: impl IntoResponse,
+) -> Response {
+    let mut response = if if_none_match(request_headers, &etag) {
+        StatusCode::NOT_MODIFIED.into_response()
+    } else {
+        response.into_response()
+    };
+    response.headers_mut().insert(header::ETAG, etag);
+    response
+        .headers_mut()
+        .insert(header::CACHE_CONTROL, HeaderValue::from_static("no-cache"));
+    response
+}
+
+// `If-None-Match` uses the weak comparison, so `W/` prefixes are ignored
+fn if_none_match(request_headers: &HeaderMap, etag: &HeaderValue) -> bool {
+    let opaque = |tag: &str| tag.trim().trim_start_matches("W/").to_string();
+    let etag = opaque(etag.to_str().unwrap_or_default());
+    request_headers
+        .get_all(header::IF_NONE_MATCH)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .any(|tag| tag.trim() == "*" || opaque(tag) == etag)
+}
@@ -0,0 +1,150 @@
+use std::convert::Infallible;
+
+use axum::{
+    extract::{Query, State},
+    response::sse::{self, KeepAlive, Sse},
+};
+use rusqlite::Connection;
+use serde::{Deserialize, Serialize};
+use tokio::sync::broadcast;
+use tokio_stream::{Stream, StreamExt, wrappers::BroadcastStream};
+
+use crate::{AppState, attempts::NewAttempt, daily, ratings::RatingChange, storage, validation};
+use utoipa::{IntoParams, ToSchema};
+
+pub type EventSender = broadcast::Sender<Event>;
+
+pub fn event_channel() -> EventSender {
+    broadcast::channel(256).0
+}
+
+#[derive(Clone, Serialize, Deserialize, ToSchema)]
+#[serde(tag = "type", rename_all = "camelCase")]
+pub enum Event {
+    // Someone attempted today's puzzle
+    #[serde(rename_all = "camelCase")]
+    DailyAttempt {
+        username: String,
+        puzzle_id: u64,
+        solved: bool,
+        solve_time_seconds: u32,
+    },
+    // A rated user's stats changed, and the leaderboard should be re-fetched
+    #[serde(rename_all = "camelCase")]
+    LeaderboardChanged { username: String },
+    #[serde(rename_all = "camelCase")]
+    PuzzleRating { puzzle_id: u64, rating: f64 },
+    // The user's own rating changed. Only sent to subscribers who gave their username
+    #[serde(rename_all = "camelCase")]
+    UserRating {
+        username: String,
+        puzzle_id: u64,
+        #[serde(flatten)]
+        change: RatingChange,
+    },
+}
+
+impl Event {
+    fn name(&self) -> &'static str {
+        match self {
+            Event::DailyAttempt { .. } => "dailyAttempt",
+            Event::LeaderboardChanged { .. } => "leaderboardChanged",
+            Event::PuzzleRating { .. } => "puzzleRating",
+            Event::UserRating { .. } => "userRating",
+        }
+    }
+
+    fn is_visible_to(&self, subscriber: Option<&str>) -> bool {
+        match self {
+            Event::UserRating { username, .. } => subscriber == Some(username.as_str()),
+            _ => true,
+        }
+    }
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct EventsQuery {
+    username: Option<String>,
+}
+
+// Stream live events. Pass `username` to also receive your own rating updates
+#[utoipa::path(
+    get,
+    path = "/events",
+    tag = "events",
+    params(EventsQuery),
+    responses(
+        (status = 200, description = "A stream of server-sent events", content_type = "text/event-stream", body = Event),
+    ),
+)]
+pub async fn get_events(
+    State(state): State<AppState>,
+    Query(query): Query<EventsQuery>,
+) -> Sse<impl Stream<Item = Result<sse::Event, Infallible>>> {
+    let username = query
+        .username
+        .map(|username| validation::canonical_username(&username));
+    let stream = BroadcastStream::new(state.events.subscribe()).filter_map(move |event| {
+        // Lagging receivers just skip the events they missed
+        let event = event.ok()?;
+        if !event.is_visible_to(username.as_deref()) {
+            return None;
+        }
+        let sse_event = sse::Event::default()
+            .event(event.name())
+            .json_data(&event)
+            .unwrap();
+        Some(Ok(sse_event))
+    });
+    Sse::new(stream).keep_alive(KeepAlive::default())
+}
+
+// Broadcast the events caused by a new attempt.
+// `rating_change` is only set if the attempt was rated
+pub fn publish_attempt_events(
+    db_conn: &Connection,
+    events: &EventSender,
+    attempt: &NewAttempt,
+    rating_change: Option<&RatingChange>,
+) -> anyhow::Result<()> {
+    // Nobody is listening, so don't bother computing anything
+    if events.receiver_count() == 0 {
+        return Ok(());
+    }
+    let puzzle_id = attempt.puzzle_id as u64;
+    let username = attempt.username.to_string();
+
+    let is_daily = db_conn
+        .prepare("SELECT 1 FROM daily_puzzles WHERE day = ?1 AND puzzle_id = ?2")?
+        .exists(rusqlite::params![daily::current_day(), puzzle_id])?;
+    if is_daily {
+        let _ = events.send(Event::DailyAttempt {
+            username: username.clone(),
+            puzzle_id,
+            solved: attempt.solved,
+            solve_time_seconds: attempt.solve_time_seconds,
+        });
+    }
+
+    let puzzle_rating = match rating_change {
+        Some(change) => {
+            let _ = events.send(Event::LeaderboardChanged {
+                username: username.clone(),
+            });
+            let _ = events.send(Event::UserRating {
+                username,
+                puzzle_id,
+                change: change.clone(),
+            });
+            change.puzzle_rating
+        }
+        None => storage::rating_for_puzzle(db_conn, puzzle_id as i64)?.rating,
+    };
+    let _ = events.send(Event::PuzzleRating {
+        puzzle_id,
+        rating: puzzle_rating,
+    });
+
+    Ok(())
+}
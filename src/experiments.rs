@@ -0,0 +1,310 @@
+use axum::{Json, extract::Path, http::StatusCode};
+use rusqlite::{Connection, OptionalExtension};
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use crate::{admin::AdminAuth, audit, db};
+
+// An A/B test between ways of doing the same thing, like two target time formulas.
+// The code that does the thing asks `variant` which one to use for a user.
+// Users are assigned by a hash of their username, so they always get the same variant,
+// and every variant is recorded with the attempts made while the experiment was running
+pub struct Experiment {
+    pub name: &'static str,
+    // The first variant is the control, which everyone gets while the experiment isn't running.
+    // Don't change the variants of an experiment that has run, since users would be assigned differently
+    pub variants: &'static [&'static str],
+}
+
+// Scaling target times to the user's rating, see `target_time.rs`
+pub const TARGET_TIME: Experiment = Experiment {
+    name: "target-time",
+    variants: &["scaled", "unscaled"],
+};
+
+const EXPERIMENTS: &[Experiment] = &[TARGET_TIME];
+
+pub fn init_db_tables(db_conn: &Connection) -> anyhow::Result<()> {
+    // Experiments that have been started with the admin endpoints. Stopped ones keep their row
+    db_conn.execute(
+        "CREATE TABLE IF NOT EXISTS experiments (
+            name TEXT PRIMARY KEY,
+            started_seconds INTEGER NOT NULL DEFAULT (strftime('%s', 'now')),
+            stopped_seconds INTEGER
+        )",
+        [],
+    )?;
+
+    db_conn.execute(
+        "CREATE TABLE IF NOT EXISTS attempt_variants (
+            username TEXT NOT NULL,
+            puzzle_id INTEGER NOT NULL,
+            attempt_number INTEGER NOT NULL,
+            experiment TEXT NOT NULL,
+            variant TEXT NOT NULL,
+            PRIMARY KEY (username, puzzle_id, attempt_number, experiment)
+        )",
+        [],
+    )?;
+
+    Ok(())
+}
+
+// The variant the user gets, which is the control unless the experiment is running
+pub fn variant(
+    db_conn: &Connection,
+    experiment: &Experiment,
+    username: &str,
+) -> anyhow::Result<&'static str> {
+    if is_running(db_conn, experiment.name)? {
+        Ok(assigned_variant(experiment, username))
+    } else {
+        Ok(experiment.variants[0])
+    }
+}
+
+// Record the user's variant of every running experiment with their attempt
+pub fn record_variants(
+    db_conn: &Connection,
+    username: &str,
+    puzzle_id: u32,
+    attempt_number: u32,
+) -> anyhow::Result<()> {
+    for experiment in EXPERIMENTS {
+        if is_running(db_conn, experiment.name)? {
+            db_conn.execute(
+                "INSERT INTO attempt_variants (username, puzzle_id, attempt_number, experiment, variant)
+                VALUES (?1, ?2, ?3, ?4, ?5)",
+                rusqlite::params![
+                    username,
+                    puzzle_id,
+                    attempt_number,
+                    experiment.name,
+                    assigned_variant(experiment, username),
+                ],
+            )?;
+        }
+    }
+    Ok(())
+}
+
+fn is_running(db_conn: &Connection, name: &str) -> anyhow::Result<bool> {
+    Ok(db_conn
+        .prepare_cached("SELECT 1 FROM experiments WHERE name = ?1 AND stopped_seconds IS NULL")?
+        .exists([name])?)
+}
+
+// SHA-256 rather than `DefaultHasher`, which may hash differently in another Rust version
+fn assigned_variant(experiment: &Experiment, username: &str) -> &'static str {
+    let digest = ring::digest::digest(
+        &ring::digest::SHA256,
+        format!("{}:{}", experiment.name, username).as_bytes(),
+    );
+    let hash = u64::from_be_bytes(digest.as_ref()[..8].try_into().unwrap());
+    experiment.variants[(hash % experiment.variants.len() as u64) as usize]
+}
+
+fn find_experiment(name: &str) -> Result<&'static Experiment, StatusCode> {
+    EXPERIMENTS
+        .iter()
+        .find(|experiment| experiment.name == name)
+        .ok_or(StatusCode::NOT_FOUND)
+}
+
+#[derive(Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct ExperimentResults {
+    name: String,
+    running: bool,
+    // Not set if the experiment has never been started
+    started_seconds: Option<u64>,
+    stopped_seconds: Option<u64>,
+    variants: Vec<VariantResults>,
+}
+
+// Only rated attempts are counted, since practice and repeated attempts are easier
+#[derive(Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct VariantResults {
+    variant: String,
+    num_users: u32,
+    num_attempts: u32,
+    num_solved: u32,
+    // Not set if there are no attempts
+    solve_rate: Option<f64>,
+    average_solve_time_seconds: Option<f64>,
+}
+
+// List every experiment, with its results so far
+#[utoipa::path(
+    get,
+    path = "/admin/experiments",
+    tag = "experiments",
+    security(("admin_token" = [])),
+    responses(
+        (status = 200, body = Vec<ExperimentResults>),
+        (status = 401),
+        (status = 403),
+    ),
+)]
+pub async fn get_experiments(_: AdminAuth) -> Result<Json<Vec<ExperimentResults>>, StatusCode> {
+    let db_conn = db::open().map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    EXPERIMENTS
+        .iter()
+        .map(|experiment| read_results(&db_conn, experiment))
+        .collect::<anyhow::Result<Vec<_>>>()
+        .map(Json)
+        .map_err(|e| {
+            tracing::error!("Error reading experiment results: {:?}", e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })
+}
+
+// Compare the solve rates of an experiment's variants
+#[utoipa::path(
+    get,
+    path = "/admin/experiments/{name}",
+    tag = "experiments",
+    params(("name" = String, Path)),
+    security(("admin_token" = [])),
+    responses(
+        (status = 200, body = ExperimentResults),
+        (status = 401),
+        (status = 403),
+        (status = 404),
+    ),
+)]
+pub async fn get_experiment(
+    _: AdminAuth,
+    Path(name): Path<String>,
+) -> Result<Json<ExperimentResults>, StatusCode> {
+    let experiment = find_experiment(&name)?;
+    let db_conn = db::open().map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    read_results(&db_conn, experiment).map(Json).map_err(|e| {
+        tracing::error!("Error reading experiment results: {:?}", e);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })
+}
+
+// Start assigning users to variants. Starting a stopped experiment resumes it, keeping its results
+#[utoipa::path(
+    post,
+    path = "/admin/experiments/{name}/start",
+    tag = "experiments",
+    params(("name" = String, Path)),
+    security(("admin_token" = [])),
+    responses(
+        (status = 200, body = ExperimentResults),
+        (status = 401),
+        (status = 403),
+        (status = 404),
+    ),
+)]
+pub async fn start_experiment(
+    admin: AdminAuth,
+    Path(name): Path<String>,
+) -> Result<Json<ExperimentResults>, StatusCode> {
+    let experiment = find_experiment(&name)?;
+    let db_conn = db::open().map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    db_conn
+        .execute(
+            "INSERT INTO experiments (name) VALUES (?1)
+            ON CONFLICT (name) DO UPDATE SET stopped_seconds = NULL",
+            [experiment.name],
+        )
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    audit::record_for(
+        &db_conn,
+        &admin,
+        "start_experiment",
+        serde_json::json!({"name": experiment.name}),
+    )?;
+    read_results(&db_conn, experiment)
+        .map(Json)
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)
+}
+
+// Stop the experiment, so that everyone gets the control variant again
+#[utoipa::path(
+    post,
+    path = "/admin/experiments/{name}/stop",
+    tag = "experiments",
+    params(("name" = String, Path)),
+    security(("admin_token" = [])),
+    responses(
+        (status = 200, body = ExperimentResults),
+        (status = 401),
+        (status = 403),
+        (status = 404),
+    ),
+)]
+pub async fn stop_experiment(
+    admin: AdminAuth,
+    Path(name): Path<String>,
+) -> Result<Json<ExperimentResults>, StatusCode> {
+    let experiment = find_experiment(&name)?;
+    let db_conn = db::open().map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    db_conn
+        .execute(
+            "UPDATE experiments SET stopped_seconds = strftime('%s', 'now')
+            WHERE name = ?1 AND stopped_seconds IS NULL",
+            [experiment.name],
+        )
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    audit::record_for(
+        &db_conn,
+        &admin,
+        "stop_experiment",
+        serde_json::json!({"name": experiment.name}),
+    )?;
+    read_results(&db_conn, experiment)
+        .map(Json)
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)
+}
+
+fn read_results(
+    db_conn: &Connection,
+    experiment: &Experiment,
+) -> anyhow::Result<ExperimentResults> {
+    let times: Option<(u64, Option<u64>)> = db_conn
+        .query_row(
+            "SELECT started_seconds, stopped_seconds FROM experiments WHERE name = ?1",
+            [experiment.name],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        )
+        .optional()?;
+    let mut stmt = db_conn.prepare(
+        "SELECT COUNT(DISTINCT rated_attempts.username), COUNT(*), COALESCE(SUM(rated_attempts.solved), 0),
+            AVG(rated_attempts.solve_time_seconds)
+        FROM attempt_variants
+        JOIN rated_attempts ON rated_attempts.username = attempt_variants.username
+            AND rated_attempts.puzzle_id = attempt_variants.puzzle_id
+            AND rated_attempts.attempt_number = attempt_variants.attempt_number
+        WHERE attempt_variants.experiment = ?1 AND attempt_variants.variant = ?2",
+    )?;
+    let variants = experiment
+        .variants
+        .iter()
+        .map(|variant| {
+            stmt.query_row([experiment.name, variant], |row| {
+                let num_attempts: u32 = row.get(1)?;
+                let num_solved: u32 = row.get(2)?;
+                Ok(VariantResults {
+                    variant: variant.to_string(),
+                    num_users: row.get(0)?,
+                    num_attempts,
+                    num_solved,
+                    solve_rate: (num_attempts > 0).then(|| num_solved as f64 / num_attempts as f64),
+                    average_solve_time_seconds: row.get(3)?,
+                })
+            })
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(ExperimentResults {
+        name: experiment.name.to_string(),
+        running: matches!(times, Some((_, None))),
+        started_seconds: times.map(|(started, _)| started),
+        stopped_seconds: times.and_then(|(_, stopped)| stopped),
+        variants,
+    })
+}
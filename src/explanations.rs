@@ -0,0 +1,291 @@
+use std::collections::BTreeSet;
+
+use axum::{
+    Json,
+    extract::{Path, Query, State},
+    http::StatusCode,
+};
+use rusqlite::{Connection, OptionalExtension};
+use serde::{Deserialize, Serialize};
+use utoipa::{IntoParams, ToSchema};
+
+use crate::{
+    AppState,
+    admin::AdminAuth,
+    audit, db,
+    validation::{self, ApiError, ValidationError},
+};
+
+// Explanations of puzzles written by admins: a title, the idea behind the solution, and comments on its moves.
+// They give the solution away, so users only get them once they've finished the puzzle
+
+const MAX_TITLE_LENGTH: usize = 100;
+const MAX_EXPLANATION_LENGTH: usize = 4000;
+const MAX_COMMENT_LENGTH: usize = 500;
+
+pub fn init_db_tables(db_conn: &Connection) -> anyhow::Result<()> {
+    db_conn.execute(
+        "CREATE TABLE IF NOT EXISTS puzzle_explanations (
+            puzzle_id INTEGER PRIMARY KEY,
+            title TEXT,
+            explanation TEXT,
+            FOREIGN KEY (puzzle_id) REFERENCES puzzles(id)
+        )",
+        [],
+    )?;
+    // `move_index` is the move's index in the puzzle's solution
+    db_conn.execute(
+        "CREATE TABLE IF NOT EXISTS puzzle_move_comments (
+            puzzle_id INTEGER NOT NULL,
+            move_index INTEGER NOT NULL,
+            comment TEXT NOT NULL,
+            PRIMARY KEY (puzzle_id, move_index),
+            FOREIGN KEY (puzzle_id) REFERENCES puzzles(id)
+        )",
+        [],
+    )?;
+
+    Ok(())
+}
+
+#[derive(Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct Explanation {
+    title: Option<String>,
+    // Of the winning idea
+    explanation: Option<String>,
+    // In the order of the solution
+    #[serde(default)]
+    move_comments: Vec<MoveComment>,
+}
+
+#[derive(Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct MoveComment {
+    // Index into the puzzle's solution, starting at 0
+    move_index: usize,
+    comment: String,
+}
+
+impl Explanation {
+    fn is_empty(&self) -> bool {
+        self.title.is_none() && self.explanation.is_none() && self.move_comments.is_empty()
+    }
+}
+
+fn validate_explanation(
+    explanation: &Explanation,
+    num_moves: usize,
+) -> Result<(), ValidationError> {
+    if explanation
+        .title
+        .as_ref()
+        .is_some_and(|title| title.trim().is_empty() || title.chars().count() > MAX_TITLE_LENGTH)
+    {
+        return Err(ValidationError::new(
+            "title",
+            format!("Title must be 1 to {MAX_TITLE_LENGTH} characters"),
+        ));
+    }
+    if explanation
+        .explanation
+        .as_ref()
+        .is_some_and(|text| text.trim().is_empty() || text.chars().count() > MAX_EXPLANATION_LENGTH)
+    {
+        return Err(ValidationError::new(
+            "explanation",
+            format!("Explanation must be 1 to {MAX_EXPLANATION_LENGTH} characters"),
+        ));
+    }
+    let mut move_indexes = BTreeSet::new();
+    for comment in &explanation.move_comments {
+        if comment.move_index >= num_moves {
+            return Err(ValidationError::new(
+                "moveComments",
+                format!("The solution only has {num_moves} moves"),
+            ));
+        }
+        if !move_indexes.insert(comment.move_index) {
+            return Err(ValidationError::new(
+                "moveComments",
+                "Each move can only have one comment",
+            ));
+        }
+        if comment.comment.trim().is_empty() || comment.comment.chars().count() > MAX_COMMENT_LENGTH
+        {
+            return Err(ValidationError::new(
+                "moveComments",
+                format!("Comments must be 1 to {MAX_COMMENT_LENGTH} characters"),
+            ));
+        }
+    }
+    Ok(())
+}
+
+fn read_explanation(db_conn: &Connection, puzzle_id: u32) -> anyhow::Result<Option<Explanation>> {
+    let Some((title, explanation)) = db_conn
+        .query_row(
+            "SELECT title, explanation FROM puzzle_explanations WHERE puzzle_id = ?1",
+            [puzzle_id],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        )
+        .optional()?
+    else {
+        return Ok(None);
+    };
+    let mut stmt = db_conn.prepare(
+        "SELECT move_index, comment FROM puzzle_move_comments WHERE puzzle_id = ?1 ORDER BY move_index",
+    )?;
+    let move_comments = stmt
+        .query_map([puzzle_id], |row| {
+            Ok(MoveComment {
+                move_index: row.get(0)?,
+                comment: row.get(1)?,
+            })
+        })?
+        .collect::<Result<_, _>>()?;
+    Ok(Some(Explanation {
+        title,
+        explanation,
+        move_comments,
+    }))
+}
+
+fn write_explanation(
+    db_conn: &Connection,
+    puzzle_id: u32,
+    explanation: &Explanation,
+) -> anyhow::Result<()> {
+    db_conn.execute(
+        "DELETE FROM puzzle_explanations WHERE puzzle_id = ?1",
+        [puzzle_id],
+    )?;
+    db_conn.execute(
+        "DELETE FROM puzzle_move_comments WHERE puzzle_id = ?1",
+        [puzzle_id],
+    )?;
+    if explanation.is_empty() {
+        return Ok(());
+    }
+    db_conn.execute(
+        "INSERT INTO puzzle_explanations (puzzle_id, title, explanation) VALUES (?1, ?2, ?3)",
+        rusqlite::params![puzzle_id, explanation.title, explanation.explanation],
+    )?;
+    for comment in &explanation.move_comments {
+        db_conn.execute(
+            "INSERT INTO puzzle_move_comments (puzzle_id, move_index, comment) VALUES (?1, ?2, ?3)",
+            rusqlite::params![puzzle_id, comment.move_index, comment.comment],
+        )?;
+    }
+    Ok(())
+}
+
+fn has_finished(db_conn: &Connection, username: &str, puzzle_id: u32) -> anyhow::Result<bool> {
+    Ok(db_conn
+        .prepare_cached(
+            "SELECT 1 FROM puzzle_attempts
+            WHERE user_id = (SELECT id FROM users WHERE username = ?1) AND puzzle_id = ?2",
+        )?
+        .exists(rusqlite::params![username, puzzle_id])?)
+}
+
+#[derive(Serialize, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct ExplanationQuery {
+    // Must have submitted an attempt at the puzzle
+    username: String,
+}
+
+// Get a puzzle's explanation, for a user who has finished the puzzle
+#[utoipa::path(
+    get,
+    path = "/puzzles/{id}/explanation",
+    tag = "puzzles",
+    params(("id" = u32, Path), ExplanationQuery),
+    responses(
+        (status = 200, body = Explanation),
+        (status = 400, body = ValidationError),
+        (status = 403, description = "The user hasn't finished the puzzle"),
+        (status = 404, description = "The puzzle doesn't exist or has no explanation"),
+    ),
+)]
+pub async fn get_explanation(
+    Path(id): Path<u32>,
+    State(state): State<AppState>,
+    Query(query): Query<ExplanationQuery>,
+) -> Result<Json<Explanation>, ApiError> {
+    validation::validate_username(&query.username)?;
+    let username = validation::canonical_username(&query.username);
+    state
+        .store
+        .published_puzzle(id)
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+        .ok_or(StatusCode::NOT_FOUND)?;
+    let db_conn = db::open().map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let finished = has_finished(&db_conn, &username, id).map_err(|e| {
+        tracing::error!("Error reading attempts: {:?}", e);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+    if !finished {
+        return Err(StatusCode::FORBIDDEN.into());
+    }
+    let explanation = read_explanation(&db_conn, id).map_err(|e| {
+        tracing::error!("Error reading explanation: {:?}", e);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+    Ok(Json(explanation.ok_or(StatusCode::NOT_FOUND)?))
+}
+
+// Replace a puzzle's explanation. An empty one removes it.
+// Works for unpublished puzzles too, so they can be explained before publishing
+#[utoipa::path(
+    put,
+    path = "/admin/puzzles/{id}/explanation",
+    tag = "admin",
+    params(("id" = u32, Path)),
+    request_body = Explanation,
+    security(("admin_token" = [])),
+    responses(
+        (status = 200, body = Explanation),
+        (status = 400, body = ValidationError),
+        (status = 401),
+        (status = 403),
+        (status = 404),
+    ),
+)]
+pub async fn set_explanation(
+    admin: AdminAuth,
+    Path(id): Path<u32>,
+    State(state): State<AppState>,
+    Json(mut payload): Json<Explanation>,
+) -> Result<Json<Explanation>, ApiError> {
+    let puzzle = state
+        .store
+        .puzzle(id)
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+        .ok_or(StatusCode::NOT_FOUND)?;
+    validate_explanation(&payload, puzzle.solution.split_whitespace().count())?;
+    payload
+        .move_comments
+        .sort_by_key(|comment| comment.move_index);
+    let mut db_conn = db::open().map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let transaction = db_conn
+        .transaction()
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    write_explanation(&transaction, id, &payload).map_err(|e| {
+        tracing::error!("Error writing explanation: {:?}", e);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+    audit::record_for(
+        &transaction,
+        &admin,
+        "set_puzzle_explanation",
+        serde_json::json!({"puzzleId": id, "explanation": payload}),
+    )?;
+    transaction
+        .commit()
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    Ok(Json(payload))
+}
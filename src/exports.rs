@@ -0,0 +1,297 @@
+use std::fmt::Write;
+
+use axum::{
+    body::Body,
+    extract::{Path, Query},
+    http::{StatusCode, header},
+    response::IntoResponse,
+};
+use rusqlite::Connection;
+use serde::{Deserialize, Serialize};
+use tokio::sync::mpsc;
+use tokio_stream::wrappers::ReceiverStream;
+use utoipa::IntoParams;
+
+use crate::{
+    admin::AdminAuth,
+    audit,
+    board::{Board, Color},
+    db,
+    validation::{self, ApiError, ValidationError},
+};
+
+// Attempts as CSV, for analysis in spreadsheets and notebooks. Rows are read on a blocking thread
+// and sent to the client in chunks as they're written, so that exports of every attempt don't have to fit in memory.
+// One user's attempts can also be downloaded as PTN games, to look through in the usual Tak tools
+
+// Rows per chunk of the response body
+const ROWS_PER_CHUNK: usize = 1000;
+// Chunks written ahead of a slow client, before reading from the database waits
+const MAX_BUFFERED_CHUNKS: usize = 4;
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct ExportQuery {
+    // Only attempts at or after this time, in Unix seconds
+    since: Option<u64>,
+    // Only attempts before this time, in Unix seconds
+    until: Option<u64>,
+}
+
+#[derive(Serialize)]
+struct AttemptRow {
+    id: i64,
+    puzzle_id: u32,
+    username: String,
+    attempt_number: u32,
+    solved: bool,
+    practice: bool,
+    solve_time_seconds: u32,
+    timestamp_seconds: u64,
+    // Moves separated by spaces
+    solution: String,
+}
+
+const HEADERS: [&str; 9] = [
+    "id",
+    "puzzle_id",
+    "username",
+    "attempt_number",
+    "solved",
+    "practice",
+    "solve_time_seconds",
+    "timestamp_seconds",
+    "solution",
+];
+
+// Download every attempt in the time range, rated or not, oldest first
+#[utoipa::path(
+    get,
+    path = "/admin/export/attempts.csv",
+    tag = "admin",
+    params(ExportQuery),
+    security(("admin_token" = [])),
+    responses(
+        (status = 200, description = "One attempt per row, with a header row", content_type = "text/csv", body = String),
+        (status = 400, body = ValidationError),
+        (status = 401),
+        (status = 403),
+    ),
+)]
+pub async fn export_attempts(
+    admin: AdminAuth,
+    Query(query): Query<ExportQuery>,
+) -> Result<impl IntoResponse, ApiError> {
+    if let (Some(since), Some(until)) = (query.since, query.until)
+        && since > until
+    {
+        return Err(ValidationError::new("until", "Until must not be before since").into());
+    }
+    let db_conn = db::open().map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    audit::record_for(
+        &db_conn,
+        &admin,
+        "export_attempts",
+        serde_json::json!({"since": query.since, "until": query.until}),
+    )?;
+
+    let (sender, receiver) = mpsc::channel(MAX_BUFFERED_CHUNKS);
+    tokio::task::spawn_blocking(move || {
+        if let Err(e) = write_attempts(&sender, query.since, query.until) {
+            tracing::error!("Error exporting attempts: {:?}", e);
+            // Ends the response with an error, so that the client doesn't mistake it for a complete export
+            let _ = sender.blocking_send(Err(std::io::Error::other("Export failed")));
+        }
+    });
+    Ok((
+        [
+            (header::CONTENT_TYPE, "text/csv; charset=utf-8"),
+            (
+                header::CONTENT_DISPOSITION,
+                "attachment; filename=\"attempts.csv\"",
+            ),
+        ],
+        Body::from_stream(ReceiverStream::new(receiver)),
+    ))
+}
+
+fn write_attempts(
+    sender: &mpsc::Sender<std::io::Result<Vec<u8>>>,
+    since: Option<u64>,
+    until: Option<u64>,
+) -> anyhow::Result<()> {
+    let db_conn = db::open()?;
+    let mut stmt = db_conn.prepare(
+        "SELECT puzzle_attempts.id, puzzle_attempts.puzzle_id, users.username, puzzle_attempts.attempt_number,
+            puzzle_attempts.solved, puzzle_attempts.practice, puzzle_attempts.solve_time_seconds,
+            puzzle_attempts.timestamp_seconds, puzzle_attempts.solution
+        FROM puzzle_attempts JOIN users ON users.id = puzzle_attempts.user_id
+        WHERE (?1 IS NULL OR puzzle_attempts.timestamp_seconds >= ?1)
+            AND (?2 IS NULL OR puzzle_attempts.timestamp_seconds < ?2)
+        ORDER BY puzzle_attempts.timestamp_seconds, puzzle_attempts.id",
+    )?;
+    let rows = stmt.query_map(rusqlite::params![since, until], |row| {
+        Ok(AttemptRow {
+            id: row.get(0)?,
+            puzzle_id: row.get(1)?,
+            username: row.get(2)?,
+            attempt_number: row.get(3)?,
+            solved: row.get(4)?,
+            practice: row.get(5)?,
+            solve_time_seconds: row.get(6)?,
+            timestamp_seconds: row.get(7)?,
+            solution: row.get(8)?,
+        })
+    })?;
+
+    // Each chunk is written by its own writer. The header is written by hand, so that it's there even if there are no attempts
+    let new_writer = || {
+        csv::WriterBuilder::new()
+            .has_headers(false)
+            .from_writer(vec![])
+    };
+    let mut writer = new_writer();
+    writer.write_record(HEADERS)?;
+    for (i, row) in rows.enumerate() {
+        writer.serialize(row?)?;
+        if (i + 1) % ROWS_PER_CHUNK == 0 {
+            let chunk = std::mem::replace(&mut writer, new_writer())
+                .into_inner()
+                .map_err(|e| e.into_error())?;
+            if sender.blocking_send(Ok(chunk)).is_err() {
+                // The client went away
+                return Ok(());
+            }
+        }
+    }
+    let chunk = writer.into_inner().map_err(|e| e.into_error())?;
+    let _ = sender.blocking_send(Ok(chunk));
+    Ok(())
+}
+
+// Download the user's attempts as a PTN file with one game per attempt, oldest first.
+// Each game starts from the puzzle's position, with the defender's move and then the moves the user played
+#[utoipa::path(
+    get,
+    path = "/users/{username}/attempts.ptn",
+    tag = "attempts",
+    params(("username" = String, Path)),
+    responses(
+        (status = 200, description = "PTN games separated by blank lines", content_type = "text/plain", body = String),
+        (status = 400, body = ValidationError),
+    ),
+)]
+pub async fn export_attempts_ptn(
+    Path(username): Path<String>,
+) -> Result<impl IntoResponse, ApiError> {
+    let username = validation::canonical_username(&username);
+    validation::validate_username(&username)?;
+    let db_conn = db::open().map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let ptn = write_attempts_ptn(&db_conn, &username).map_err(|e| {
+        tracing::error!("Error exporting attempts as PTN: {:?}", e);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+    let content_disposition = format!("attachment; filename=\"{username}-attempts.ptn\"");
+    Ok((
+        [
+            (
+                header::CONTENT_TYPE,
+                "text/plain; charset=utf-8".to_string(),
+            ),
+            (header::CONTENT_DISPOSITION, content_disposition),
+        ],
+        ptn,
+    ))
+}
+
+fn write_attempts_ptn(db_conn: &Connection, username: &str) -> anyhow::Result<String> {
+    let mut stmt = db_conn.prepare(
+        "SELECT puzzle_attempts.puzzle_id, puzzle_attempts.attempt_number, puzzle_attempts.solved,
+            puzzle_attempts.practice, puzzle_attempts.solve_time_seconds,
+            strftime('%Y.%m.%d', puzzle_attempts.timestamp_seconds, 'unixepoch'), puzzle_attempts.solution,
+            puzzles.size, puzzles.komi, puzzles.root_tps, puzzles.defender_start_move, puzzles.player_white,
+            puzzles.player_black
+        FROM puzzle_attempts JOIN puzzles ON puzzles.id = puzzle_attempts.puzzle_id
+        WHERE puzzle_attempts.user_id = (SELECT id FROM users WHERE username = ?1)
+        ORDER BY puzzle_attempts.timestamp_seconds, puzzle_attempts.id",
+    )?;
+    let mut rows = stmt.query([username])?;
+    let mut ptn = String::new();
+    while let Some(row) = rows.next()? {
+        let puzzle_id: u32 = row.get(0)?;
+        let attempt_number: u32 = row.get(1)?;
+        let solved: bool = row.get(2)?;
+        let practice: bool = row.get(3)?;
+        let solve_time_seconds: u32 = row.get(4)?;
+        let date: String = row.get(5)?;
+        let played: String = row.get(6)?;
+        let size: usize = row.get(7)?;
+        let komi: String = row.get(8)?;
+        let root_tps: String = row.get(9)?;
+        let defender_start_move: String = row.get(10)?;
+
+        if !ptn.is_empty() {
+            ptn.push('\n');
+        }
+        let tags = [
+            ("Site", "Tak tactics".to_string()),
+            ("Event", format!("Puzzle {puzzle_id}")),
+            ("Round", attempt_number.to_string()),
+            ("Player1", row.get(11)?),
+            ("Player2", row.get(12)?),
+            ("Date", date),
+            ("Size", size.to_string()),
+            ("Komi", komi),
+            ("TPS", root_tps.clone()),
+        ];
+        for (name, value) in tags {
+            writeln!(ptn, "[{name} \"{value}\"]")?;
+        }
+        ptn.push('\n');
+
+        let moves = std::iter::once(defender_start_move.as_str())
+            .chain(played.split_whitespace())
+            .filter(|ptn_move| *ptn_move != "*");
+        let comment = match (solved, practice) {
+            (true, false) => format!("Solved in {solve_time_seconds} seconds"),
+            (true, true) => format!("Solved in {solve_time_seconds} seconds, practicing"),
+            (false, false) => "Not solved".to_string(),
+            (false, true) => "Not solved, practicing".to_string(),
+        };
+        write_moves(&mut ptn, &root_tps, moves, &comment)?;
+    }
+    Ok(ptn)
+}
+
+// Numbered from the position's move number, with `--` in place of white's move if black moves first.
+// Ends with the comment on a line of its own
+fn write_moves<'a>(
+    ptn: &mut String,
+    root_tps: &str,
+    moves: impl Iterator<Item = &'a str>,
+    comment: &str,
+) -> anyhow::Result<()> {
+    let board = Board::from_tps(root_tps)?;
+    let mut move_number = board.move_number;
+    let mut line = match board.to_move {
+        Color::White => String::new(),
+        Color::Black => format!("{move_number}. --"),
+    };
+    let mut to_move = board.to_move;
+    for ptn_move in moves {
+        match to_move {
+            Color::White => line = format!("{move_number}. {ptn_move}"),
+            Color::Black => {
+                writeln!(ptn, "{line} {ptn_move}")?;
+                line.clear();
+                move_number += 1;
+            }
+        }
+        to_move = to_move.other();
+    }
+    if !line.is_empty() {
+        writeln!(ptn, "{line}")?;
+    }
+    writeln!(ptn, "{{{comment}}}")?;
+    Ok(())
+}
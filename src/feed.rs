@@ -0,0 +1,121 @@
+use axum::{
+    extract::State,
+    http::{HeaderMap, HeaderValue, StatusCode, header},
+    response::{IntoResponse, Response},
+};
+use rusqlite::Connection;
+
+use crate::{AppState, config::FeedConfig, db, etag};
+
+// An Atom feed of the newest published puzzles, for feed readers and bots
+const NUM_ENTRIES: u32 = 50;
+
+struct Entry {
+    id: u64,
+    size: usize,
+    rating: Option<i32>,
+    player_white: String,
+    player_black: String,
+    // RFC 3339, as Atom wants
+    published: String,
+}
+
+#[utoipa::path(
+    get,
+    path = "/puzzles/feed.atom",
+    tag = "puzzles",
+    responses((status = 200, description = "An Atom feed", content_type = "application/atom+xml", body = String)),
+)]
+pub async fn get_feed(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+) -> Result<Response, StatusCode> {
+    let db_conn = db::open().map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let entries = read_entries(&db_conn).map_err(|e| {
+        tracing::error!("Error reading puzzles for the feed: {:?}", e);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+    let feed = render(&state.config.feed, &entries);
+    let etag = etag::weak_etag(&feed);
+    Ok(etag::with_etag(
+        &headers,
+        etag,
+        (
+            [(
+                header::CONTENT_TYPE,
+                HeaderValue::from_static("application/atom+xml; charset=utf-8"),
+            )],
+            feed,
+        )
+            .into_response(),
+    ))
+}
+
+fn read_entries(db_conn: &Connection) -> anyhow::Result<Vec<Entry>> {
+    let mut stmt = db_conn.prepare(
+        "SELECT id, size, COALESCE(rating, initial_rating), player_white, player_black,
+            strftime('%Y-%m-%dT%H:%M:%SZ', COALESCE(published_seconds, 0), 'unixepoch')
+        FROM puzzles WHERE published = 1
+        ORDER BY published_seconds DESC, id DESC LIMIT ?1",
+    )?;
+    let rows = stmt.query_map([NUM_ENTRIES], |row| {
+        Ok(Entry {
+            id: row.get(0)?,
+            size: row.get(1)?,
+            rating: row.get(2)?,
+            player_white: row.get(3)?,
+            player_black: row.get(4)?,
+            published: row.get(5)?,
+        })
+    })?;
+    Ok(rows.collect::<Result<_, _>>()?)
+}
+
+fn render(config: &FeedConfig, entries: &[Entry]) -> String {
+    // The newest entry, or the epoch for an empty feed, so that the feed only changes with its entries
+    let updated = entries
+        .first()
+        .map_or("1970-01-01T00:00:00Z", |entry| &entry.published);
+    let mut feed = format!(
+        "<?xml version=\"1.0\" encoding=\"utf-8\"?>\n\
+        <feed xmlns=\"http://www.w3.org/2005/Atom\">\n\
+        <id>urn:tak-tactics:puzzles</id>\n\
+        <title>{title}</title>\n\
+        <author><name>{title}</name></author>\n\
+        <updated>{updated}</updated>\n",
+        title = escape(&config.title)
+    );
+    for entry in entries {
+        let difficulty = match entry.rating {
+            Some(rating) => format!("rated {rating}"),
+            None => "not rated yet".to_string(),
+        };
+        let link = config.puzzle_url.replace("{id}", &entry.id.to_string());
+        feed.push_str(&format!(
+            "<entry>\n\
+            <id>urn:tak-tactics:puzzle:{id}</id>\n\
+            <title>Puzzle {id}: {size}x{size}, {difficulty}</title>\n\
+            <link rel=\"alternate\" href=\"{link}\"/>\n\
+            <link rel=\"enclosure\" type=\"image/svg+xml\" href=\"{id}/image.svg\"/>\n\
+            <published>{published}</published>\n\
+            <updated>{published}</updated>\n\
+            <summary>A {size}x{size} puzzle from {white} vs {black}, {difficulty}</summary>\n\
+            </entry>\n",
+            id = entry.id,
+            size = entry.size,
+            link = escape(&link),
+            published = entry.published,
+            white = escape(&entry.player_white),
+            black = escape(&entry.player_black),
+        ));
+    }
+    feed.push_str("</feed>\n");
+    feed
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
@@ -0,0 +1,206 @@
+use std::path::Path;
+
+use anyhow::{Context, anyhow};
+use rusqlite::{Connection, Transaction};
+use serde::{Deserialize, Serialize};
+use skillratings::glicko2::Glicko2Rating;
+
+use serde_rusqlite::from_row;
+
+use crate::{
+    Puzzle, PuzzleRow, default_target_time_seconds, notifications, storage, validation,
+    webhooks::{self, WebhookEvent},
+};
+
+// Puzzles and users as JSON, with puzzles in the same shape as the API serves them. See `fixtures/sample.json`.
+// Used for sample data in local development and tests, and by the `import` and `export` commands
+#[derive(Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Fixture {
+    pub puzzles: Vec<FixturePuzzle>,
+    pub users: Vec<FixtureUser>,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub struct FixturePuzzle {
+    // Assigned by the database if not set
+    pub id: Option<u64>,
+    pub size: usize,
+    pub komi: String,
+    #[serde(rename = "rootTPS")]
+    pub root_tps: String,
+    pub defender_start_move: String,
+    pub solution: Vec<String>,
+    // Computed from the position and solution if not set, see `default_target_time_seconds`
+    pub target_time_seconds: Option<u32>,
+    pub player_white: String,
+    pub player_black: String,
+    pub playtak_game_id: usize,
+    #[serde(default = "default_published")]
+    pub published: bool,
+    pub initial_rating: Option<i32>,
+}
+
+// Ratings that aren't set get the same defaults as new users
+#[derive(Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct FixtureUser {
+    // As the user typed it. Usernames that only differ in case are the same user
+    pub username: String,
+    pub rating: Option<f64>,
+    pub deviation: Option<f64>,
+    pub volatility: Option<f64>,
+    // Whether the user's attempts are left out of puzzle ratings
+    #[serde(default)]
+    pub excluded_from_ratings: bool,
+}
+
+fn default_published() -> bool {
+    true
+}
+
+pub fn load(path: &Path) -> anyhow::Result<Fixture> {
+    let contents = std::fs::read_to_string(path)
+        .with_context(|| format!("Failed to read fixture file {}", path.display()))?;
+    serde_json::from_str(&contents)
+        .with_context(|| format!("Failed to parse fixture file {}", path.display()))
+}
+
+// Load a fixture into an empty database, in a single transaction. If there are any puzzles or users already,
+// nothing is changed and this returns false
+pub fn seed(db_conn: &mut Connection, fixture: &Fixture) -> anyhow::Result<bool> {
+    let transaction = db_conn.transaction()?;
+    let has_data: bool = transaction.query_row(
+        "SELECT EXISTS (SELECT 1 FROM puzzles) OR EXISTS (SELECT 1 FROM users)",
+        [],
+        |row| row.get(0),
+    )?;
+    if has_data {
+        return Ok(false);
+    }
+    insert(&transaction, fixture)?;
+    transaction.commit()?;
+    Ok(true)
+}
+
+// Add a fixture to a database that may already have data.
+// Fails without changing anything if a puzzle id or username is already taken
+pub fn import(db_conn: &mut Connection, fixture: &Fixture) -> anyhow::Result<()> {
+    let transaction = db_conn.transaction()?;
+    insert(&transaction, fixture)?;
+    transaction.commit()?;
+    Ok(())
+}
+
+// Every puzzle and user, in a format that `import` can read back.
+// Attempts aren't included, so importing an export gives users their ratings but no history.
+// Deleted users aren't included either, see `users::delete_user`
+pub fn export(db_conn: &Connection) -> anyhow::Result<Fixture> {
+    let mut stmt = db_conn.prepare("SELECT * FROM puzzles ORDER BY id")?;
+    let puzzles = stmt
+        .query_and_then([], from_row::<PuzzleRow>)?
+        .map(|row| {
+            let row = row?;
+            Ok(FixturePuzzle {
+                id: Some(row.id),
+                size: row.size,
+                komi: row.komi,
+                root_tps: row.root_tps,
+                defender_start_move: row.defender_start_move,
+                solution: row.solution.split_whitespace().map(String::from).collect(),
+                target_time_seconds: Some(row.target_time_seconds),
+                player_white: row.player_white,
+                player_black: row.player_black,
+                playtak_game_id: row.playtak_game_id,
+                published: row.published,
+                initial_rating: row.initial_rating,
+            })
+        })
+        .collect::<anyhow::Result<Vec<_>>>()?;
+
+    let mut stmt = db_conn
+        .prepare("SELECT COALESCE(display_name, username), rating, deviation, volatility, excluded_from_ratings
+            FROM users WHERE deleted_seconds IS NULL ORDER BY username")?;
+    let users = stmt
+        .query_map([], |row| {
+            Ok(FixtureUser {
+                username: row.get(0)?,
+                rating: row.get(1)?,
+                deviation: row.get(2)?,
+                volatility: row.get(3)?,
+                excluded_from_ratings: row.get(4)?,
+            })
+        })?
+        .collect::<Result<Vec<_>, _>>()?;
+
+    Ok(Fixture { puzzles, users })
+}
+
+fn insert(transaction: &Transaction, fixture: &Fixture) -> anyhow::Result<()> {
+    for puzzle in &fixture.puzzles {
+        let solution = puzzle.solution.join(" ");
+        let target_time_seconds = puzzle
+            .target_time_seconds
+            .unwrap_or_else(|| default_target_time_seconds(&puzzle.root_tps, &solution));
+        transaction.execute(
+            "INSERT INTO puzzles (id, size, komi, root_tps, defender_start_move, solution, target_time_seconds,
+                player_white, player_black, playtak_game_id, published, initial_rating, published_seconds)
+            VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12,
+                CASE WHEN ?11 THEN strftime('%s', 'now') END)",
+            rusqlite::params![
+                puzzle.id,
+                puzzle.size,
+                puzzle.komi,
+                puzzle.root_tps,
+                puzzle.defender_start_move,
+                solution,
+                target_time_seconds,
+                puzzle.player_white,
+                puzzle.player_black,
+                puzzle.playtak_game_id,
+                puzzle.published,
+                puzzle.initial_rating,
+            ],
+        )
+        .with_context(|| match puzzle.id {
+            Some(id) => format!("Failed to insert puzzle {id}"),
+            None => format!("Failed to insert puzzle from playtak game {}", puzzle.playtak_game_id),
+        })?;
+        if puzzle.published {
+            let id = transaction.last_insert_rowid() as u32;
+            if let Some(row) = storage::read_puzzle_by_id(transaction, id)? {
+                let size = row.size;
+                let puzzle = Puzzle::from(row);
+                webhooks::enqueue(transaction, WebhookEvent::PuzzlePublished, &puzzle)?;
+                notifications::notify_new_puzzle(transaction, size, &puzzle)?;
+            }
+        }
+    }
+
+    let default_rating = Glicko2Rating::default();
+    for user in &fixture.users {
+        validation::validate_username(&user.username).map_err(|e| {
+            anyhow!(
+                "Invalid username {:?} in fixture: {}",
+                user.username,
+                e.message
+            )
+        })?;
+        transaction
+            .execute(
+                "INSERT INTO users (username, display_name, rating, deviation, volatility, excluded_from_ratings)
+                VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+                rusqlite::params![
+                    validation::canonical_username(&user.username),
+                    user.username,
+                    user.rating.unwrap_or(default_rating.rating),
+                    user.deviation.unwrap_or(default_rating.deviation),
+                    user.volatility.unwrap_or(default_rating.volatility),
+                    user.excluded_from_ratings,
+                ],
+            )
+            .with_context(|| format!("Failed to insert user {}", user.username))?;
+    }
+    Ok(())
+}
@@ -0,0 +1,212 @@
+use axum::{
+    Json,
+    extract::{Path, Query},
+    http::StatusCode,
+};
+use rusqlite::Connection;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    PuzzleRequest, daily, db, leaderboard,
+    validation::{self, ApiError, ValidationError},
+};
+use utoipa::ToSchema;
+
+pub fn init_db_tables(db_conn: &Connection) -> anyhow::Result<()> {
+    db_conn.execute(
+        "CREATE TABLE IF NOT EXISTS follows (
+            follower TEXT NOT NULL,
+            followee TEXT NOT NULL,
+            created_seconds INTEGER NOT NULL DEFAULT (strftime('%s', 'now')),
+            PRIMARY KEY (follower, followee)
+        )",
+        [],
+    )?;
+
+    Ok(())
+}
+
+#[derive(Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct DailyComparison {
+    day: i64,
+    puzzle_id: u64,
+    results: Vec<DailyResult>,
+}
+
+#[derive(Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct DailyResult {
+    username: String,
+    attempted: bool,
+    solved: bool,
+    solve_time_seconds: Option<u32>,
+}
+
+// Follow a user. The request body contains the follower's username
+#[utoipa::path(
+    post,
+    path = "/users/{username}/follow",
+    tag = "friends",
+    params(("username" = String, Path, description = "The user to follow")),
+    request_body(content = PuzzleRequest, description = "The follower"),
+    responses((status = 200), (status = 400, body = validation::ValidationError)),
+)]
+pub async fn follow_user(
+    Path(followee): Path<String>,
+    Json(payload): Json<PuzzleRequest>,
+) -> Result<(), ApiError> {
+    validation::validate_username(&payload.username)?;
+    validation::validate_username(&followee)?;
+    let follower = validation::canonical_username(&payload.username);
+    let followee = validation::canonical_username(&followee);
+    if follower == followee {
+        return Err(ValidationError::new("username", "Users cannot follow themselves").into());
+    }
+    let db_conn = db::open().map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    db_conn
+        .execute(
+            "INSERT OR IGNORE INTO follows (follower, followee) VALUES (?1, ?2)",
+            rusqlite::params![follower, followee],
+        )
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    Ok(())
+}
+
+// Unfollow a user
+#[utoipa::path(
+    delete,
+    path = "/users/{username}/follow",
+    tag = "friends",
+    params(
+        ("username" = String, Path, description = "The user to unfollow"),
+        PuzzleRequest,
+    ),
+    responses((status = 200)),
+)]
+pub async fn unfollow_user(
+    Path(followee): Path<String>,
+    Query(request): Query<PuzzleRequest>,
+) -> Result<(), StatusCode> {
+    let follower = validation::canonical_username(&request.username);
+    let followee = validation::canonical_username(&followee);
+    let db_conn = db::open().map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    db_conn
+        .execute(
+            "DELETE FROM follows WHERE follower = ?1 AND followee = ?2",
+            rusqlite::params![follower, followee],
+        )
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    Ok(())
+}
+
+// List everyone the user follows
+#[utoipa::path(
+    get,
+    path = "/users/{username}/following",
+    tag = "friends",
+    params(("username" = String, Path)),
+    responses((status = 200, body = Vec<String>)),
+)]
+pub async fn get_following(Path(username): Path<String>) -> Result<Json<Vec<String>>, StatusCode> {
+    let username = validation::canonical_username(&username);
+    let db_conn = db::open().map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let following =
+        read_following(&db_conn, &username).map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    Ok(Json(following))
+}
+
+// Leaderboard of the user and everyone they follow
+#[utoipa::path(
+    get,
+    path = "/users/{username}/following/leaderboard",
+    tag = "friends",
+    params(("username" = String, Path)),
+    responses((status = 200, body = Vec<leaderboard::LeaderboardEntry>)),
+)]
+pub async fn get_following_leaderboard(
+    Path(username): Path<String>,
+) -> Result<Json<Vec<leaderboard::LeaderboardEntry>>, StatusCode> {
+    let username = validation::canonical_username(&username);
+    let db_conn = db::open().map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let leaderboard = leaderboard::read_following_leaderboard(&db_conn, &username)
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    Ok(Json(leaderboard))
+}
+
+// How the user and everyone they follow did on today's puzzle
+#[utoipa::path(
+    get,
+    path = "/users/{username}/following/daily",
+    tag = "friends",
+    params(("username" = String, Path)),
+    responses((status = 200, body = DailyComparison), (status = 404)),
+)]
+pub async fn get_following_daily(
+    Path(username): Path<String>,
+) -> Result<Json<DailyComparison>, StatusCode> {
+    let username = validation::canonical_username(&username);
+    let db_conn = db::open().map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let day = daily::current_day();
+    let puzzle = daily::read_daily_puzzle(&db_conn, day)
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+        .ok_or(StatusCode::NOT_FOUND)?;
+
+    let mut usernames = vec![username.clone()];
+    usernames.extend(
+        read_following(&db_conn, &username).map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?,
+    );
+    let results = usernames
+        .into_iter()
+        .map(|username| read_daily_result(&db_conn, day, puzzle.id, username))
+        .collect::<anyhow::Result<Vec<_>>>()
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+    Ok(Json(DailyComparison {
+        day,
+        puzzle_id: puzzle.id,
+        results,
+    }))
+}
+
+fn read_following(db_conn: &Connection, username: &str) -> anyhow::Result<Vec<String>> {
+    let mut stmt =
+        db_conn.prepare("SELECT followee FROM follows WHERE follower = ?1 ORDER BY followee")?;
+    let rows = stmt.query_map([username], |row| row.get(0))?;
+    Ok(rows.collect::<Result<Vec<_>, _>>()?)
+}
+
+// Only the user's first attempt made on the day itself counts
+fn read_daily_result(
+    db_conn: &Connection,
+    day: i64,
+    puzzle_id: u64,
+    username: String,
+) -> anyhow::Result<DailyResult> {
+    let mut stmt = db_conn.prepare(
+        "SELECT solved, solve_time_seconds FROM puzzle_attempts
+        WHERE puzzle_id = ?1 AND user_id = (SELECT id FROM users WHERE username = ?2)
+            AND timestamp_seconds / 86400 = ?3
+        ORDER BY timestamp_seconds ASC LIMIT 1",
+    )?;
+    let first_attempt = stmt
+        .query_map(rusqlite::params![puzzle_id, username, day], |row| {
+            Ok((row.get::<_, bool>(0)?, row.get::<_, u32>(1)?))
+        })?
+        .next()
+        .transpose()?;
+    Ok(match first_attempt {
+        Some((solved, solve_time_seconds)) => DailyResult {
+            username,
+            attempted: true,
+            solved,
+            solve_time_seconds: Some(solve_time_seconds),
+        },
+        None => DailyResult {
+            username,
+            attempted: false,
+            solved: false,
+            solve_time_seconds: None,
+        },
+    })
+}
@@ -0,0 +1,369 @@
+use std::sync::OnceLock;
+
+use async_graphql::{
+    ComplexObject, Context, EmptyMutation, EmptySubscription, Object, Schema, SimpleObject,
+    http::GraphiQLSource,
+};
+use async_graphql_axum::{GraphQLRequest, GraphQLResponse};
+use axum::{extract::State, response::Html};
+use rusqlite::Connection;
+
+use crate::{AppState, PuzzleRow, db, validation};
+
+// A read-only GraphQL view of puzzles, users and their attempts, for clients that want nested data
+// in one request. Everything here is also in the REST API, which is still the one to write through.
+// Queries that nest too deep or ask for too much are rejected before they run
+
+const MAX_DEPTH: usize = 8;
+const MAX_COMPLEXITY: usize = 2000;
+// For every list, when `first` isn't given
+const DEFAULT_PAGE_SIZE: u32 = 20;
+const MAX_PAGE_SIZE: u32 = 100;
+
+type ApiSchema = Schema<Query, EmptyMutation, EmptySubscription>;
+
+fn schema() -> &'static ApiSchema {
+    static SCHEMA: OnceLock<ApiSchema> = OnceLock::new();
+    SCHEMA.get_or_init(|| {
+        Schema::build(Query, EmptyMutation, EmptySubscription)
+            .limit_depth(MAX_DEPTH)
+            .limit_complexity(MAX_COMPLEXITY)
+            .finish()
+    })
+}
+
+// Run a GraphQL query. See `GET /graphql` for the schema and an editor to try queries in
+#[utoipa::path(
+    post,
+    path = "/graphql",
+    tag = "graphql",
+    request_body(content = Object, description = "A GraphQL request, with `query` and optionally `variables`"),
+    responses((status = 200, description = "A GraphQL response, with `data` and any `errors`", body = Object)),
+)]
+pub async fn graphql(State(state): State<AppState>, request: GraphQLRequest) -> GraphQLResponse {
+    schema()
+        .execute(request.into_inner().data(state))
+        .await
+        .into()
+}
+
+// GraphiQL, for exploring the schema
+#[utoipa::path(
+    get,
+    path = "/graphql",
+    tag = "graphql",
+    responses((status = 200, description = "An HTML page", content_type = "text/html")),
+)]
+pub async fn graphiql() -> Html<String> {
+    Html(GraphiQLSource::build().endpoint("graphql").finish())
+}
+
+// Errors are logged instead of being shown to clients
+fn internal(e: impl Into<anyhow::Error>) -> async_graphql::Error {
+    tracing::error!("Error in GraphQL query: {:?}", e.into());
+    async_graphql::Error::new("Internal server error")
+}
+
+fn page_size(first: Option<u32>) -> u32 {
+    first.unwrap_or(DEFAULT_PAGE_SIZE).min(MAX_PAGE_SIZE)
+}
+
+pub struct Query;
+
+#[Object]
+impl Query {
+    // A published puzzle
+    async fn puzzle(&self, ctx: &Context<'_>, id: u32) -> async_graphql::Result<Option<Puzzle>> {
+        let state = ctx.data::<AppState>()?;
+        let puzzle = state.store.published_puzzle(id).await.map_err(internal)?;
+        Ok(puzzle.map(Puzzle::from))
+    }
+
+    // Published puzzles by id, optionally of one size. Pass the last id as `after` for the next page
+    async fn puzzles(
+        &self,
+        size: Option<u32>,
+        after: Option<u32>,
+        first: Option<u32>,
+    ) -> async_graphql::Result<Vec<Puzzle>> {
+        let db_conn = db::open().map_err(internal)?;
+        let mut stmt = db_conn
+            .prepare(
+                "SELECT * FROM puzzles WHERE published = 1 AND (?1 IS NULL OR size = ?1) AND id > ?2
+                ORDER BY id LIMIT ?3",
+            )
+            .map_err(internal)?;
+        let puzzles = stmt
+            .query_and_then(
+                rusqlite::params![size, after.unwrap_or(0), page_size(first)],
+                serde_rusqlite::from_row::<PuzzleRow>,
+            )
+            .map_err(internal)?
+            .map(|row| row.map(Puzzle::from))
+            .collect::<Result<_, _>>()
+            .map_err(internal)?;
+        Ok(puzzles)
+    }
+
+    async fn user(&self, username: String) -> async_graphql::Result<Option<User>> {
+        let db_conn = db::open().map_err(internal)?;
+        read_user(&db_conn, &validation::canonical_username(&username)).map_err(internal)
+    }
+
+    // The highest rated users
+    async fn leaderboard(&self, first: Option<u32>) -> async_graphql::Result<Vec<User>> {
+        let db_conn = db::open().map_err(internal)?;
+        read_users(
+            &db_conn,
+            "deleted_seconds IS NULL ORDER BY rating DESC LIMIT ?1",
+            rusqlite::params![page_size(first)],
+        )
+        .map_err(internal)
+    }
+
+    async fn stats(&self) -> async_graphql::Result<Stats> {
+        let db_conn = db::open().map_err(internal)?;
+        db_conn
+            .query_row(
+                "SELECT (SELECT COUNT(*) FROM puzzles WHERE published = 1),
+                    (SELECT COUNT(*) FROM users WHERE deleted_seconds IS NULL),
+                    (SELECT COUNT(*) FROM puzzle_attempts),
+                    (SELECT COUNT(*) FROM puzzle_attempts WHERE solved = 1)",
+                [],
+                |row| {
+                    Ok(Stats {
+                        num_puzzles: row.get(0)?,
+                        num_users: row.get(1)?,
+                        num_attempts: row.get(2)?,
+                        num_solved: row.get(3)?,
+                    })
+                },
+            )
+            .map_err(internal)
+    }
+}
+
+#[derive(SimpleObject)]
+struct Stats {
+    // Published puzzles
+    num_puzzles: u32,
+    num_users: u32,
+    // Rated and unrated
+    num_attempts: u32,
+    num_solved: u32,
+}
+
+#[derive(SimpleObject)]
+#[graphql(complex)]
+struct Puzzle {
+    id: u64,
+    size: usize,
+    komi: String,
+    #[graphql(name = "rootTPS")]
+    root_tps: String,
+    defender_start_move: String,
+    solution: Vec<String>,
+    // Without the random variation that `GET /puzzles/{id}` adds
+    target_time_seconds: u32,
+    player_white: String,
+    player_black: String,
+    playtak_game_id: usize,
+}
+
+impl From<PuzzleRow> for Puzzle {
+    fn from(row: PuzzleRow) -> Self {
+        Self {
+            id: row.id,
+            size: row.size,
+            komi: row.komi,
+            root_tps: row.root_tps,
+            defender_start_move: row.defender_start_move,
+            solution: row.solution.split_whitespace().map(String::from).collect(),
+            target_time_seconds: row.target_time_seconds,
+            player_white: row.player_white,
+            player_black: row.player_black,
+            playtak_game_id: row.playtak_game_id,
+        }
+    }
+}
+
+#[ComplexObject]
+impl Puzzle {
+    async fn rating(&self, ctx: &Context<'_>) -> async_graphql::Result<f64> {
+        let state = ctx.data::<AppState>()?;
+        let rating = state
+            .store
+            .puzzle_rating(self.id as u32)
+            .await
+            .map_err(internal)?;
+        Ok(rating.rating)
+    }
+
+    // Rated attempts
+    async fn num_attempts(&self) -> async_graphql::Result<u32> {
+        count(
+            "SELECT COUNT(*) FROM rated_attempts WHERE puzzle_id = ?1",
+            self.id,
+        )
+    }
+
+    async fn num_solved(&self) -> async_graphql::Result<u32> {
+        count(
+            "SELECT COUNT(*) FROM rated_attempts WHERE puzzle_id = ?1 AND solved = 1",
+            self.id,
+        )
+    }
+
+    // Rated attempts, newest first. Pass the last id as `before` for the next page
+    async fn attempts(
+        &self,
+        before: Option<i64>,
+        first: Option<u32>,
+    ) -> async_graphql::Result<Vec<Attempt>> {
+        let db_conn = db::open().map_err(internal)?;
+        read_attempts(
+            &db_conn,
+            "puzzle_id = ?1 AND attempt_number = 1 AND practice = 0 AND shared_device = 0",
+            self.id as i64,
+            before,
+            first,
+        )
+        .map_err(internal)
+    }
+}
+
+#[derive(SimpleObject)]
+#[graphql(complex)]
+struct User {
+    #[graphql(skip)]
+    id: i64,
+    // The display name, see `storage::set_display_name`
+    username: String,
+    rating: f64,
+    deviation: f64,
+}
+
+fn read_user(db_conn: &Connection, username: &str) -> anyhow::Result<Option<User>> {
+    Ok(read_users(
+        db_conn,
+        "deleted_seconds IS NULL AND username = ?1",
+        rusqlite::params![username],
+    )?
+    .pop())
+}
+
+fn read_users(
+    db_conn: &Connection,
+    filter: &str,
+    params: &[&dyn rusqlite::ToSql],
+) -> anyhow::Result<Vec<User>> {
+    let mut stmt = db_conn.prepare(&format!(
+        "SELECT id, COALESCE(display_name, username), rating, deviation FROM users WHERE {filter}"
+    ))?;
+    let rows = stmt.query_map(params, |row| {
+        Ok(User {
+            id: row.get(0)?,
+            username: row.get(1)?,
+            rating: row.get(2)?,
+            deviation: row.get(3)?,
+        })
+    })?;
+    Ok(rows.collect::<Result<_, _>>()?)
+}
+
+#[ComplexObject]
+impl User {
+    // Distinct puzzles
+    async fn num_solved(&self) -> async_graphql::Result<u32> {
+        count(
+            "SELECT COUNT(DISTINCT puzzle_id) FROM puzzle_attempts WHERE user_id = ?1 AND solved = 1",
+            self.id,
+        )
+    }
+
+    // Rated and unrated, newest first. Pass the last id as `before` for the next page
+    async fn attempts(
+        &self,
+        before: Option<i64>,
+        first: Option<u32>,
+    ) -> async_graphql::Result<Vec<Attempt>> {
+        let db_conn = db::open().map_err(internal)?;
+        read_attempts(&db_conn, "user_id = ?1", self.id, before, first).map_err(internal)
+    }
+}
+
+#[derive(SimpleObject)]
+#[graphql(complex)]
+struct Attempt {
+    id: i64,
+    #[graphql(skip)]
+    puzzle_id: u32,
+    #[graphql(skip)]
+    user_id: i64,
+    solved: bool,
+    solve_time_seconds: u32,
+    timestamp_seconds: u64,
+    // Only the first attempt at a puzzle is rated
+    attempt_number: u32,
+    practice: bool,
+}
+
+fn read_attempts(
+    db_conn: &Connection,
+    filter: &str,
+    id: i64,
+    before: Option<i64>,
+    first: Option<u32>,
+) -> anyhow::Result<Vec<Attempt>> {
+    let mut stmt = db_conn.prepare(&format!(
+        "SELECT id, puzzle_id, user_id, solved, solve_time_seconds, timestamp_seconds, attempt_number, practice
+        FROM puzzle_attempts WHERE {filter} AND (?2 IS NULL OR id < ?2)
+        ORDER BY id DESC LIMIT ?3"
+    ))?;
+    let rows = stmt.query_map(rusqlite::params![id, before, page_size(first)], |row| {
+        Ok(Attempt {
+            id: row.get(0)?,
+            puzzle_id: row.get(1)?,
+            user_id: row.get(2)?,
+            solved: row.get(3)?,
+            solve_time_seconds: row.get(4)?,
+            timestamp_seconds: row.get(5)?,
+            attempt_number: row.get(6)?,
+            practice: row.get(7)?,
+        })
+    })?;
+    Ok(rows.collect::<Result<_, _>>()?)
+}
+
+#[ComplexObject]
+impl Attempt {
+    // Unless it has been unpublished since
+    async fn puzzle(&self, ctx: &Context<'_>) -> async_graphql::Result<Option<Puzzle>> {
+        let state = ctx.data::<AppState>()?;
+        let puzzle = state
+            .store
+            .published_puzzle(self.puzzle_id)
+            .await
+            .map_err(internal)?;
+        Ok(puzzle.map(Puzzle::from))
+    }
+
+    // Unless their data has been deleted
+    async fn user(&self) -> async_graphql::Result<Option<User>> {
+        let db_conn = db::open().map_err(internal)?;
+        Ok(read_users(
+            &db_conn,
+            "deleted_seconds IS NULL AND id = ?1",
+            rusqlite::params![self.user_id],
+        )
+        .map_err(internal)?
+        .pop())
+    }
+}
+
+fn count(sql: &str, id: impl rusqlite::ToSql) -> async_graphql::Result<u32> {
+    let db_conn = db::open().map_err(internal)?;
+    db_conn
+        .query_row(sql, [id], |row| row.get(0))
+        .map_err(internal)
+}
@@ -0,0 +1,302 @@
+// `tonic::Status` is large, but it is the error type every gRPC call has to return
+#![allow(clippy::result_large_err)]
+
+use std::{net::SocketAddr, pin::Pin};
+
+use axum::{
+    Json,
+    extract::{Path, Query, State},
+    http::{HeaderMap, HeaderValue, StatusCode},
+};
+use tokio::sync::watch;
+use tokio_stream::{Stream, StreamExt, wrappers::BroadcastStream};
+use tonic::{Request, Response, Status};
+
+use crate::{
+    AppState, PuzzleQuery, PuzzleResponse, db, devices,
+    events::Event,
+    idempotency,
+    sessions::{self, AuthUser},
+    shutdown,
+    validation::ApiError,
+};
+
+// The gRPC API in `proto/tak_tactics.proto`, for bots and analysis tools.
+// Served on its own listener at `server.grpc_address`, outside of the HTTP middleware,
+// so read-only mode, rate limits and claimed usernames are checked here instead. Logged in users send their access token
+// as `authorization: Bearer <token>` metadata. Calls go through the same handlers as the REST API
+
+tonic::include_proto!("tak_tactics.v1");
+
+use puzzles_server::{Puzzles, PuzzlesServer};
+
+pub use puzzles_client::PuzzlesClient;
+
+pub struct PuzzlesService {
+    state: AppState,
+}
+
+pub fn service(state: AppState) -> PuzzlesServer<PuzzlesService> {
+    PuzzlesServer::new(PuzzlesService { state })
+}
+
+// Serve until a shutdown is requested and every call has finished
+pub async fn serve(
+    state: AppState,
+    listener: tokio::net::TcpListener,
+    shutdown: watch::Receiver<bool>,
+) -> anyhow::Result<()> {
+    tracing::info!("Listening for gRPC on {}", listener.local_addr()?);
+    let incoming = tokio_stream::wrappers::TcpListenerStream::new(listener);
+    tonic::transport::Server::builder()
+        .add_service(service(state))
+        .serve_with_incoming_shutdown(incoming, shutdown::requested(shutdown))
+        .await?;
+    Ok(())
+}
+
+fn to_status(error: ApiError) -> Status {
+    match error {
+        ApiError::Invalid(error) => {
+            Status::invalid_argument(format!("{}: {}", error.field, error.message))
+        }
+        ApiError::Status(StatusCode::NOT_FOUND) => Status::not_found("Not found"),
+        ApiError::Status(StatusCode::FORBIDDEN) => Status::permission_denied("The user is banned"),
+        ApiError::Status(StatusCode::CONFLICT) => {
+            Status::failed_precondition("The idempotency key was used for a different puzzle")
+        }
+        ApiError::Status(status) => Status::internal(status.to_string()),
+    }
+}
+
+fn internal(e: anyhow::Error) -> Status {
+    tracing::error!("Error in gRPC call: {:?}", e);
+    Status::internal("Internal server error")
+}
+
+impl PuzzlesService {
+    // Like the HTTP rate limiter, per client IP and per username
+    fn check_rate_limit<T>(
+        &self,
+        request: &Request<T>,
+        username: Option<&str>,
+    ) -> Result<(), Status> {
+        if !self.state.config.rate_limit.enabled {
+            return Ok(());
+        }
+        let ip = request.remote_addr().map(|addr: SocketAddr| addr.ip());
+        let username = username.map(crate::validation::canonical_username);
+        self.state
+            .rate_limiter
+            .check(ip, username.as_deref())
+            .map_err(|retry_after_seconds| {
+                Status::resource_exhausted(format!(
+                    "Rate limit exceeded, retry in {retry_after_seconds} seconds"
+                ))
+            })
+    }
+
+    // The user behind the call's access token, if the call may act as the username, like `sessions::authenticate`
+    fn check_acting_as<T>(
+        &self,
+        request: &Request<T>,
+        username: &str,
+    ) -> Result<Option<AuthUser>, Status> {
+        let Some(secret) = self.state.config.auth.secret.as_deref() else {
+            return Ok(None);
+        };
+        let db_conn = db::open().map_err(|e| internal(e.into()))?;
+        let user = match request
+            .metadata()
+            .get("authorization")
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "))
+        {
+            Some(token) => sessions::user_for_token(&db_conn, secret, token).map_err(internal)?,
+            None => None,
+        };
+        let username = crate::validation::canonical_username(username);
+        sessions::check_acting_as(&db_conn, user.as_ref(), &username).map_err(
+            |status| match status {
+                StatusCode::UNAUTHORIZED => Status::unauthenticated("Log in to act as this user"),
+                StatusCode::FORBIDDEN => Status::permission_denied("Logged in as another user"),
+                _ => Status::internal("Internal server error"),
+            },
+        )?;
+        Ok(user)
+    }
+}
+
+impl From<crate::Puzzle> for Puzzle {
+    fn from(puzzle: crate::Puzzle) -> Self {
+        Self {
+            id: puzzle.id,
+            size: puzzle.size as u32,
+            komi: puzzle.komi,
+            root_tps: puzzle.root_tps,
+            defender_start_move: puzzle.defender_start_move,
+            solution: puzzle.solution,
+            target_time_seconds: puzzle.target_time_seconds,
+            player_white: puzzle.player_white,
+            player_black: puzzle.player_black,
+            playtak_game_id: puzzle.playtak_game_id as u64,
+        }
+    }
+}
+
+type PuzzleRatingStream = Pin<Box<dyn Stream<Item = Result<PuzzleRating, Status>> + Send>>;
+
+#[tonic::async_trait]
+impl Puzzles for PuzzlesService {
+    async fn get_puzzle(
+        &self,
+        request: Request<GetPuzzleRequest>,
+    ) -> Result<Response<Puzzle>, Status> {
+        self.check_rate_limit(&request, None)?;
+        let puzzle = self
+            .state
+            .store
+            .published_puzzle(request.get_ref().id)
+            .await
+            .map_err(internal)?
+            .ok_or_else(|| Status::not_found("Not found"))?;
+        Ok(Response::new(crate::Puzzle::from(puzzle).into()))
+    }
+
+    async fn next_puzzle(
+        &self,
+        request: Request<NextPuzzleRequest>,
+    ) -> Result<Response<Puzzle>, Status> {
+        self.check_rate_limit(&request, Some(&request.get_ref().username))?;
+        self.check_acting_as(&request, &request.get_ref().username)?;
+        let request = request.into_inner();
+        let query = PuzzleQuery {
+            username: request.username,
+            rated: Some(!request.practice),
+            player: request.player,
+            size: None,
+            strategy: Default::default(),
+        };
+        let Json(puzzle) = crate::get_puzzle(State(self.state.clone()), Query(query))
+            .await
+            .map_err(to_status)?;
+        Ok(Response::new(puzzle.into()))
+    }
+
+    async fn submit_attempt(
+        &self,
+        request: Request<SubmitAttemptRequest>,
+    ) -> Result<Response<AttemptResult>, Status> {
+        if let Some(message) = self.state.read_only.message() {
+            return Err(Status::unavailable(message));
+        }
+        self.check_rate_limit(&request, Some(&request.get_ref().username))?;
+        // Like over HTTP, logged in users are trusted with their own attempts
+        let device = match self.check_acting_as(&request, &request.get_ref().username)? {
+            Some(_) => devices::Device::default(),
+            None => devices::Device::from_ip(
+                &self.state,
+                request.remote_addr().map(|address| address.ip()),
+            ),
+        };
+        let request = request.into_inner();
+        let mut headers = HeaderMap::new();
+        if let Some(key) = request.idempotency_key {
+            let value = HeaderValue::from_str(&key).map_err(|_| {
+                Status::invalid_argument("Idempotency key must be visible ASCII characters")
+            })?;
+            headers.insert(idempotency::IDEMPOTENCY_KEY_HEADER, value);
+        }
+        let payload = PuzzleResponse {
+            id: request.puzzle_id as usize,
+            username: request.username,
+            solved: request.solved,
+            solution: request.solution,
+            solve_time_seconds: request.solve_time_seconds,
+            move_times_ms: (!request.move_times_ms.is_empty()).then_some(request.move_times_ms),
+            rated: !request.practice,
+            retry: false,
+        };
+        let Json(result) = crate::solve_puzzle(
+            Path(request.puzzle_id),
+            State(self.state.clone()),
+            headers,
+            device,
+            Json(payload),
+        )
+        .await
+        .map_err(to_status)?;
+        Ok(Response::new(AttemptResult {
+            attempt_number: result.attempt_number,
+            rated: result.rated,
+            rating_change: result.rating_change.map(|change| RatingChange {
+                old_rating: change.old_rating,
+                new_rating: change.new_rating,
+                puzzle_rating: change.puzzle_rating,
+            }),
+            correct: result.verification.correct,
+            solution: result.verification.solution,
+            target_time_seconds: result.verification.target_time_seconds,
+            target_time_met: result.verification.target_time_met,
+        }))
+    }
+
+    type GetPuzzleRatingsStream = PuzzleRatingStream;
+
+    async fn get_puzzle_ratings(
+        &self,
+        request: Request<GetPuzzleRatingsRequest>,
+    ) -> Result<Response<Self::GetPuzzleRatingsStream>, Status> {
+        self.check_rate_limit(&request, None)?;
+        let ratings = self
+            .state
+            .store
+            .published_puzzle_ratings()
+            .await
+            .map_err(internal)?;
+        let ratings = ratings.into_iter().map(|(puzzle_id, rating)| {
+            Ok(PuzzleRating {
+                puzzle_id: puzzle_id as u64,
+                rating,
+            })
+        });
+        Ok(Response::new(Box::pin(tokio_stream::iter(ratings))))
+    }
+
+    async fn get_user_rating(
+        &self,
+        request: Request<GetUserRatingRequest>,
+    ) -> Result<Response<UserRating>, Status> {
+        self.check_rate_limit(&request, None)?;
+        let username = &request.get_ref().username;
+        crate::validation::validate_username(username).map_err(|e| to_status(e.into()))?;
+        let rating = self
+            .state
+            .store
+            .user_rating(&crate::validation::canonical_username(username))
+            .await
+            .map_err(internal)?;
+        Ok(Response::new(UserRating {
+            rating: rating.rating,
+            deviation: rating.deviation,
+        }))
+    }
+
+    type WatchPuzzleRatingsStream = PuzzleRatingStream;
+
+    // Subscribers that fall too far behind skip the ratings they missed, like `GET /events`
+    async fn watch_puzzle_ratings(
+        &self,
+        request: Request<WatchPuzzleRatingsRequest>,
+    ) -> Result<Response<Self::WatchPuzzleRatingsStream>, Status> {
+        self.check_rate_limit(&request, None)?;
+        let stream =
+            BroadcastStream::new(self.state.events.subscribe()).filter_map(|event| match event {
+                Ok(Event::PuzzleRating { puzzle_id, rating }) => {
+                    Some(Ok(PuzzleRating { puzzle_id, rating }))
+                }
+                _ => None,
+            });
+        Ok(Response::new(Box::pin(stream)))
+    }
+}
@@ -0,0 +1,112 @@
+use std::sync::atomic::Ordering;
+
+use anyhow::bail;
+use axum::{Json, extract::State, http::StatusCode};
+use serde::{Deserialize, Serialize};
+
+use crate::{AppState, db, migrations};
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Health {
+    status: String,
+    version: String,
+    uptime_seconds: u64,
+    database: String,
+}
+
+// Every check is either "ok" or a description of what went wrong
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Readiness {
+    status: String,
+    migrations: String,
+    database: String,
+    background_jobs: String,
+}
+
+// Report whether the server can reach its database, for load balancers and uptime monitors.
+// Responds with 503 if the database check fails
+pub async fn get_health(State(state): State<AppState>) -> (StatusCode, Json<Health>) {
+    let database = check_result(check_database());
+    let status = status_code([&database]);
+    let health = Health {
+        status: status_text(status),
+        version: env!("CARGO_PKG_VERSION").to_string(),
+        uptime_seconds: state.started_at.elapsed().as_secs(),
+        database,
+    };
+    (status, Json(health))
+}
+
+// Liveness probe. Always succeeds while the process is able to serve requests at all,
+// so that orchestrators only restart the server if it's stuck
+pub async fn get_liveness() -> Json<serde_json::Value> {
+    Json(serde_json::json!({ "status": "ok" }))
+}
+
+// Readiness probe. Fails with 503 until the server has finished starting up,
+// or if it loses write access to the database, so that orchestrators don't route traffic to it
+pub async fn get_readiness(State(state): State<AppState>) -> (StatusCode, Json<Readiness>) {
+    let migrations = check_result(check_migrations());
+    let database = check_result(check_database_writable());
+    let background_jobs = if state.background_jobs_started.load(Ordering::Acquire) {
+        "ok".to_string()
+    } else {
+        "not started".to_string()
+    };
+    let status = status_code([&migrations, &database, &background_jobs]);
+    let readiness = Readiness {
+        status: status_text(status),
+        migrations,
+        database,
+        background_jobs,
+    };
+    (status, Json(readiness))
+}
+
+fn check_result(result: anyhow::Result<()>) -> String {
+    match result {
+        Ok(()) => "ok".to_string(),
+        Err(e) => {
+            tracing::error!("Health check failed: {:?}", e);
+            format!("error: {}", e)
+        }
+    }
+}
+
+fn status_code<const N: usize>(checks: [&String; N]) -> StatusCode {
+    if checks.iter().all(|check| *check == "ok") {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    }
+}
+
+fn status_text(status: StatusCode) -> String {
+    if status.is_success() { "ok" } else { "error" }.to_string()
+}
+
+fn check_database() -> anyhow::Result<()> {
+    let db_conn = db::open()?;
+    db_conn.query_row("SELECT COUNT(*) FROM puzzles", [], |row| {
+        row.get::<_, i64>(0)
+    })?;
+    Ok(())
+}
+
+fn check_migrations() -> anyhow::Result<()> {
+    let db_conn = db::open()?;
+    let pending = migrations::pending(&db_conn)?;
+    if pending > 0 {
+        bail!("{pending} migrations not applied");
+    }
+    Ok(())
+}
+
+// Taking the write lock is enough to know that a write would succeed, without having to write anything
+fn check_database_writable() -> anyhow::Result<()> {
+    let db_conn = db::open()?;
+    db_conn.execute_batch("BEGIN IMMEDIATE; ROLLBACK;")?;
+    Ok(())
+}
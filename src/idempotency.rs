@@ -0,0 +1,105 @@
+use axum::http::HeaderMap;
+use rusqlite::{Connection, OptionalExtension};
+
+use crate::{
+    now_seconds,
+    validation::{ApiError, ValidationError},
+};
+
+pub const IDEMPOTENCY_KEY_HEADER: &str = "idempotency-key";
+
+const MAX_KEY_LENGTH: usize = 128;
+
+// Keys are forgotten after a day. Clients only retry for a few minutes at most
+pub const KEY_LIFETIME_SECONDS: u64 = 24 * 60 * 60;
+
+pub fn init_db_tables(db_conn: &Connection) -> anyhow::Result<()> {
+    // Keys are chosen by the client, so they are only unique per user
+    db_conn.execute(
+        "CREATE TABLE IF NOT EXISTS idempotency_keys (
+            username TEXT NOT NULL,
+            key TEXT NOT NULL,
+            puzzle_id INTEGER NOT NULL,
+            created_seconds INTEGER NOT NULL,
+            PRIMARY KEY (username, key)
+        )",
+        [],
+    )?;
+
+    Ok(())
+}
+
+// Read the optional `Idempotency-Key` header
+pub fn key_from_headers(headers: &HeaderMap) -> Result<Option<String>, ApiError> {
+    let Some(value) = headers.get(IDEMPOTENCY_KEY_HEADER) else {
+        return Ok(None);
+    };
+    let key = value
+        .to_str()
+        .ok()
+        .filter(|key| !key.is_empty() && key.len() <= MAX_KEY_LENGTH)
+        .ok_or_else(|| {
+            ValidationError::new(
+                "Idempotency-Key",
+                format!("Idempotency key must be 1 to {MAX_KEY_LENGTH} visible ASCII characters"),
+            )
+        })?;
+    Ok(Some(key.to_string()))
+}
+
+// The attempt a key was first used for
+pub struct KeyedAttempt {
+    pub puzzle_id: u32,
+    pub attempt_number: u32,
+    // The JSON response sent for the attempt. Not stored for keys from before responses were saved
+    pub response: Option<String>,
+}
+
+// Look up the attempt recorded with a key, if any. Also forgets expired keys
+pub fn read_keyed_attempt(
+    db_conn: &Connection,
+    username: &str,
+    key: &str,
+) -> anyhow::Result<Option<KeyedAttempt>> {
+    db_conn.execute(
+        "DELETE FROM idempotency_keys WHERE created_seconds < ?1",
+        [now_seconds().saturating_sub(KEY_LIFETIME_SECONDS)],
+    )?;
+    Ok(db_conn
+        .query_row(
+            "SELECT puzzle_id, attempt_number, response FROM idempotency_keys
+            WHERE username = ?1 AND key = ?2",
+            [username, key],
+            |row| {
+                Ok(KeyedAttempt {
+                    puzzle_id: row.get(0)?,
+                    attempt_number: row.get(1)?,
+                    response: row.get(2)?,
+                })
+            },
+        )
+        .optional()?)
+}
+
+// Remember the attempt recorded with a key.
+// Should run in the same transaction as recording the attempt, so that a key is only stored if its attempt is too
+pub fn store_key(
+    db_conn: &Connection,
+    username: &str,
+    key: &str,
+    attempt: &KeyedAttempt,
+) -> anyhow::Result<()> {
+    db_conn.execute(
+        "INSERT INTO idempotency_keys (username, key, puzzle_id, attempt_number, response, created_seconds)
+        VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+        rusqlite::params![
+            username,
+            key,
+            attempt.puzzle_id,
+            attempt.attempt_number,
+            attempt.response,
+            now_seconds()
+        ],
+    )?;
+    Ok(())
+}
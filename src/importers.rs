@@ -0,0 +1,245 @@
+use std::{collections::HashSet, path::Path};
+
+use anyhow::{Context, anyhow, ensure};
+use rusqlite::Connection;
+use serde::Deserialize;
+
+use crate::{
+    board::{Board, Move},
+    fixtures::{self, Fixture, FixturePuzzle},
+    validation,
+};
+
+// Puzzles from other collections, for the `import --format` command. Each format is mapped onto
+// a `FixturePuzzle`, and checked the same way: the position must parse, the defender's move must be legal in it,
+// and the solution must be valid PTN. Puzzles we already have, with the same position and defender's move, are skipped
+
+#[derive(Clone, Copy, Debug, clap::ValueEnum)]
+pub enum Format {
+    // A JSON list of puzzles, like PuzzleTak exports, either on its own or as `{"puzzles": [...]}`.
+    // Each has `tps`, `setupMove`, `solution` as a list or a string of moves,
+    // and optionally `white`, `black`, `gameId` and `komi`
+    PuzzletakJson,
+    // A spreadsheet saved as CSV, with a header row naming the same columns as `puzzletak-json`.
+    // Headers are matched ignoring case, spaces and underscores, so `Setup move` and `setup_move` both work
+    Csv,
+}
+
+// Fields every format maps onto
+struct Record {
+    tps: String,
+    // The move the defender plays into the puzzle position
+    setup_move: String,
+    solution: Vec<String>,
+    white: String,
+    black: String,
+    game_id: usize,
+    komi: f64,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum PuzzleTakFile {
+    List(Vec<PuzzleTakPuzzle>),
+    Wrapped { puzzles: Vec<PuzzleTakPuzzle> },
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct PuzzleTakPuzzle {
+    tps: String,
+    #[serde(alias = "lastMove")]
+    setup_move: String,
+    solution: Moves,
+    #[serde(default)]
+    white: String,
+    #[serde(default)]
+    black: String,
+    #[serde(alias = "playtakId")]
+    game_id: Option<usize>,
+    komi: Option<f64>,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum Moves {
+    List(Vec<String>),
+    Line(String),
+}
+
+#[derive(Deserialize)]
+struct CsvRow {
+    tps: String,
+    setupmove: String,
+    // Moves separated by spaces
+    solution: String,
+    #[serde(default)]
+    white: String,
+    #[serde(default)]
+    black: String,
+    #[serde(default)]
+    gameid: Option<usize>,
+    #[serde(default)]
+    komi: Option<f64>,
+}
+
+// What an import added, and what it left out
+pub struct ImportSummary {
+    pub num_imported: usize,
+    pub num_duplicates: usize,
+}
+
+// Read and check every puzzle in a file. Fails on the first invalid puzzle, naming its position in the file
+pub fn load(path: &Path, format: Format, published: bool) -> anyhow::Result<Vec<FixturePuzzle>> {
+    let contents = std::fs::read_to_string(path)
+        .with_context(|| format!("Failed to read {}", path.display()))?;
+    parse(&contents, format, published)
+        .with_context(|| format!("Failed to import {}", path.display()))
+}
+
+pub fn parse(
+    contents: &str,
+    format: Format,
+    published: bool,
+) -> anyhow::Result<Vec<FixturePuzzle>> {
+    let records = match format {
+        Format::PuzzletakJson => parse_puzzletak_json(contents)?,
+        Format::Csv => parse_csv(contents)?,
+    };
+    records
+        .into_iter()
+        .enumerate()
+        .map(|(i, record)| {
+            to_fixture_puzzle(record, published).with_context(|| format!("Puzzle {}", i + 1))
+        })
+        .collect()
+}
+
+fn parse_puzzletak_json(contents: &str) -> anyhow::Result<Vec<Record>> {
+    let puzzles = match serde_json::from_str(contents).context("Invalid PuzzleTak JSON")? {
+        PuzzleTakFile::List(puzzles) | PuzzleTakFile::Wrapped { puzzles } => puzzles,
+    };
+    Ok(puzzles
+        .into_iter()
+        .map(|puzzle| Record {
+            tps: puzzle.tps,
+            setup_move: puzzle.setup_move,
+            solution: match puzzle.solution {
+                Moves::List(moves) => moves,
+                Moves::Line(line) => line.split_whitespace().map(String::from).collect(),
+            },
+            white: puzzle.white,
+            black: puzzle.black,
+            game_id: puzzle.game_id.unwrap_or_default(),
+            komi: puzzle.komi.unwrap_or_default(),
+        })
+        .collect())
+}
+
+fn parse_csv(contents: &str) -> anyhow::Result<Vec<Record>> {
+    let mut reader = csv::ReaderBuilder::new()
+        .trim(csv::Trim::All)
+        .from_reader(contents.as_bytes());
+    let headers: csv::StringRecord = reader
+        .headers()?
+        .iter()
+        .map(|header| {
+            header
+                .chars()
+                .filter(|c| c.is_ascii_alphanumeric())
+                .collect::<String>()
+                .to_ascii_lowercase()
+        })
+        .collect();
+    reader.set_headers(headers);
+    reader
+        .deserialize::<CsvRow>()
+        .enumerate()
+        .map(|(i, row)| {
+            // After the header row, and counting from 1 like spreadsheets do
+            let row = row.with_context(|| format!("Invalid row {}", i + 2))?;
+            Ok(Record {
+                tps: row.tps,
+                setup_move: row.setupmove,
+                solution: row.solution.split_whitespace().map(String::from).collect(),
+                white: row.white,
+                black: row.black,
+                game_id: row.gameid.unwrap_or_default(),
+                komi: row.komi.unwrap_or_default(),
+            })
+        })
+        .collect()
+}
+
+fn to_fixture_puzzle(record: Record, published: bool) -> anyhow::Result<FixturePuzzle> {
+    let mut board = Board::from_tps(&record.tps)?;
+    let setup_move = Move::parse(&record.setup_move)?;
+    board
+        .play(&setup_move)
+        .with_context(|| format!("Setup move {:?} is illegal", record.setup_move))?;
+
+    let mut solution = record.solution;
+    ensure!(
+        solution
+            .iter()
+            .any(|ptn_move| !validation::normalize_move(ptn_move).is_empty()),
+        "Solution is empty"
+    );
+    validation::validate_solution(&solution).map_err(|e| anyhow!(e.message))?;
+    // Stored solutions end with `*`
+    if solution.last().is_none_or(|last| !last.ends_with('*')) {
+        solution.push("*".to_string());
+    }
+
+    Ok(FixturePuzzle {
+        id: None,
+        size: board.size,
+        komi: record.komi.to_string(),
+        root_tps: record.tps,
+        defender_start_move: record.setup_move,
+        solution,
+        target_time_seconds: None,
+        player_white: record.white,
+        player_black: record.black,
+        playtak_game_id: record.game_id,
+        published,
+        initial_rating: None,
+    })
+}
+
+// Add the puzzles that aren't in the database yet, in a single transaction.
+// Puzzles are the same if they have the same position and defender's move, whichever collection they came from
+pub fn import(
+    db_conn: &mut Connection,
+    puzzles: Vec<FixturePuzzle>,
+) -> anyhow::Result<ImportSummary> {
+    let key = |root_tps: &str, defender_start_move: &str| {
+        (
+            root_tps.split_whitespace().collect::<Vec<_>>().join(" "),
+            validation::normalize_move(defender_start_move).to_string(),
+        )
+    };
+    let mut seen: HashSet<(String, String)> = db_conn
+        .prepare("SELECT root_tps, defender_start_move FROM puzzles")?
+        .query_map([], |row| {
+            Ok(key(&row.get::<_, String>(0)?, &row.get::<_, String>(1)?))
+        })?
+        .collect::<Result<_, _>>()?;
+    let num_puzzles = puzzles.len();
+    let puzzles: Vec<FixturePuzzle> = puzzles
+        .into_iter()
+        .filter(|puzzle| seen.insert(key(&puzzle.root_tps, &puzzle.defender_start_move)))
+        .collect();
+    let summary = ImportSummary {
+        num_imported: puzzles.len(),
+        num_duplicates: num_puzzles - puzzles.len(),
+    };
+    fixtures::import(
+        db_conn,
+        &Fixture {
+            puzzles,
+            users: vec![],
+        },
+    )?;
+    Ok(summary)
+}
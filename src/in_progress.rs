@@ -0,0 +1,323 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use axum::{
+    Json,
+    extract::{Query, State},
+    http::StatusCode,
+};
+use rusqlite::{Connection, OptionalExtension};
+use serde::{Deserialize, Serialize};
+use utoipa::{IntoParams, ToSchema};
+
+use crate::{
+    AppState, Puzzle, attempts, bans, db, storage,
+    validation::{self, ApiError},
+};
+
+// Every puzzle served by `GET /puzzles` is an attempt in progress until it's submitted,
+// so that a user who reloads the page gets the same puzzle and clock back from `GET /puzzles/current`.
+// Each user has at most one. Serving another puzzle replaces it without recording anything,
+// like before attempts were tracked. Ones that are never submitted are recorded as failed
+// once they're older than `attempts.abandon_after_seconds`
+
+pub fn init_db_tables(db_conn: &Connection) -> anyhow::Result<()> {
+    // `moves` is a JSON array of `Move`s
+    db_conn.execute(
+        "CREATE TABLE IF NOT EXISTS attempts_in_progress (
+            username TEXT PRIMARY KEY,
+            puzzle_id INTEGER NOT NULL,
+            rated INTEGER NOT NULL,
+            target_time_seconds INTEGER NOT NULL,
+            started_ms INTEGER NOT NULL,
+            moves TEXT NOT NULL DEFAULT '[]'
+        )",
+        [],
+    )?;
+
+    Ok(())
+}
+
+#[derive(Clone, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct Move {
+    ptn_move: String,
+    // Milliseconds since the puzzle was served
+    elapsed_ms: u32,
+}
+
+#[derive(Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct CurrentAttempt {
+    // With the same target time as when it was first served
+    puzzle: Puzzle,
+    rated: bool,
+    started_seconds: u64,
+    elapsed_ms: u64,
+    // Moves played so far, from `POST /puzzles/current/moves`
+    moves: Vec<Move>,
+}
+
+struct InProgressRow {
+    puzzle_id: u32,
+    rated: bool,
+    target_time_seconds: u32,
+    started_ms: u64,
+    moves: Vec<Move>,
+}
+
+#[derive(Serialize, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct CurrentQuery {
+    username: String,
+}
+
+#[derive(Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct NewMove {
+    username: String,
+    ptn_move: String,
+}
+
+fn now_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_millis() as u64
+}
+
+// Start tracking a puzzle that was just served, replacing the user's earlier one
+pub fn start(
+    db_conn: &Connection,
+    username: &str,
+    puzzle: &Puzzle,
+    rated: bool,
+) -> anyhow::Result<()> {
+    db_conn.execute(
+        "INSERT OR REPLACE INTO attempts_in_progress (username, puzzle_id, rated, target_time_seconds, started_ms)
+        VALUES (?1, ?2, ?3, ?4, ?5)",
+        rusqlite::params![
+            username,
+            puzzle.id,
+            rated,
+            puzzle.target_time_seconds,
+            now_ms()
+        ],
+    )?;
+    Ok(())
+}
+
+// Stop tracking the user's attempt at this puzzle, once it has been submitted
+pub fn finish(db_conn: &Connection, username: &str, puzzle_id: u32) -> anyhow::Result<()> {
+    db_conn.execute(
+        "DELETE FROM attempts_in_progress WHERE username = ?1 AND puzzle_id = ?2",
+        rusqlite::params![username, puzzle_id],
+    )?;
+    Ok(())
+}
+
+fn read_in_progress(db_conn: &Connection, username: &str) -> anyhow::Result<Option<InProgressRow>> {
+    let row = db_conn
+        .query_row(
+            "SELECT puzzle_id, rated, target_time_seconds, started_ms, moves FROM attempts_in_progress
+            WHERE username = ?1",
+            [username],
+            |row| {
+                Ok((
+                    row.get(0)?,
+                    row.get(1)?,
+                    row.get(2)?,
+                    row.get(3)?,
+                    row.get::<_, String>(4)?,
+                ))
+            },
+        )
+        .optional()?;
+    row.map(
+        |(puzzle_id, rated, target_time_seconds, started_ms, moves)| {
+            Ok(InProgressRow {
+                puzzle_id,
+                rated,
+                target_time_seconds,
+                started_ms,
+                moves: serde_json::from_str(&moves)?,
+            })
+        },
+    )
+    .transpose()
+}
+
+// Record every abandoned attempt as failed, or only the user's if `username` is set.
+// Each one is removed before it's recorded, so that it's only ever recorded once
+pub async fn expire_abandoned(state: &AppState, username: Option<&str>) -> anyhow::Result<usize> {
+    let abandon_after_seconds = state.config.attempts.abandon_after_seconds;
+    let cutoff_ms = now_ms().saturating_sub(abandon_after_seconds * 1000);
+    let expired = {
+        let db_conn = db::open()?;
+        let mut stmt = db_conn.prepare(
+            "DELETE FROM attempts_in_progress WHERE started_ms <= ?1 AND (?2 IS NULL OR username = ?2)
+            RETURNING username, puzzle_id, rated, moves",
+        )?;
+        stmt.query_map(rusqlite::params![cutoff_ms, username], |row| {
+            Ok((
+                row.get::<_, String>(0)?,
+                row.get::<_, u32>(1)?,
+                row.get::<_, bool>(2)?,
+                row.get::<_, String>(3)?,
+            ))
+        })?
+        .collect::<Result<Vec<_>, _>>()?
+    };
+
+    let num_expired = expired.len();
+    for (username, puzzle_id, rated, moves) in expired {
+        let moves: Vec<Move> = serde_json::from_str(&moves)?;
+        let solution: Vec<String> = moves.iter().map(|m| m.ptn_move.clone()).collect();
+        let judgement = state
+            .store
+            .puzzle(puzzle_id)
+            .await?
+            .and_then(|puzzle| attempts::judge(&puzzle, &solution));
+        let attempt = attempts::NewAttempt {
+            puzzle_id,
+            username,
+            solved: false,
+            solve_time_seconds: abandon_after_seconds.min(validation::MAX_SOLVE_TIME_SECONDS as u64)
+                as u32,
+            move_times_ms: Some(moves.iter().map(|m| m.elapsed_ms).collect()),
+            solution,
+            practice: !rated,
+            device: Default::default(),
+            retry: false,
+            judgement,
+        };
+        state.store.record_attempt(attempt, None).await?;
+    }
+    Ok(num_expired)
+}
+
+// The `expire-abandoned-attempts` job, see `scheduler.rs`
+pub async fn run_expiry(state: AppState) -> anyhow::Result<()> {
+    // They're expired once the server isn't read-only anymore instead
+    if state.read_only.message().is_some() {
+        return Ok(());
+    }
+    let num_expired = expire_abandoned(&state, None).await?;
+    if num_expired > 0 {
+        tracing::info!("Recorded {} abandoned attempts", num_expired);
+    }
+    Ok(())
+}
+
+async fn current_attempt(
+    state: &AppState,
+    username: &str,
+) -> Result<Option<CurrentAttempt>, StatusCode> {
+    bans::check_not_banned(
+        &db::open().map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?,
+        username,
+    )?;
+    expire_abandoned(state, Some(username)).await.map_err(|e| {
+        tracing::error!("Error expiring abandoned attempts: {:?}", e);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+    let db_conn = db::open().map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let Some(row) =
+        read_in_progress(&db_conn, username).map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+    else {
+        return Ok(None);
+    };
+    let Some(puzzle) = storage::read_puzzle_by_id(&db_conn, row.puzzle_id)
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+    else {
+        return Ok(None);
+    };
+    Ok(Some(CurrentAttempt {
+        puzzle: Puzzle {
+            target_time_seconds: row.target_time_seconds,
+            ..puzzle.into()
+        },
+        rated: row.rated,
+        started_seconds: row.started_ms / 1000,
+        elapsed_ms: now_ms().saturating_sub(row.started_ms),
+        moves: row.moves,
+    }))
+}
+
+// Get the puzzle the user was last served, if it hasn't been submitted or abandoned
+#[utoipa::path(
+    get,
+    path = "/puzzles/current",
+    tag = "puzzles",
+    params(CurrentQuery),
+    responses(
+        (status = 200, body = CurrentAttempt),
+        (status = 400, body = validation::ValidationError),
+        (status = 403, description = "The user is banned"),
+        (status = 404, description = "No attempt in progress"),
+    ),
+)]
+pub async fn get_current_attempt(
+    State(state): State<AppState>,
+    Query(query): Query<CurrentQuery>,
+) -> Result<Json<CurrentAttempt>, ApiError> {
+    validation::validate_username(&query.username)?;
+    let username = validation::canonical_username(&query.username);
+    Ok(current_attempt(&state, &username)
+        .await?
+        .map(Json)
+        .ok_or(StatusCode::NOT_FOUND)?)
+}
+
+// Save a move of the attempt in progress, so it's restored along with the puzzle.
+// Moves aren't checked against the solution here, that happens when the attempt is submitted
+#[utoipa::path(
+    post,
+    path = "/puzzles/current/moves",
+    tag = "puzzles",
+    request_body = NewMove,
+    responses(
+        (status = 200, body = CurrentAttempt),
+        (status = 400, body = validation::ValidationError),
+        (status = 403, description = "The user is banned"),
+        (status = 404, description = "No attempt in progress"),
+    ),
+)]
+pub async fn add_move(
+    State(state): State<AppState>,
+    Json(payload): Json<NewMove>,
+) -> Result<Json<CurrentAttempt>, ApiError> {
+    validation::validate_username(&payload.username)?;
+    validation::validate_solution(std::slice::from_ref(&payload.ptn_move))?;
+    let username = validation::canonical_username(&payload.username);
+    let Some(mut current) = current_attempt(&state, &username).await? else {
+        return Err(StatusCode::NOT_FOUND.into());
+    };
+    validation::validate_solution_for_size(
+        std::slice::from_ref(&payload.ptn_move),
+        current.puzzle.size,
+    )?;
+    if current.moves.len() >= validation::MAX_SOLUTION_MOVES {
+        return Err(validation::ValidationError::new(
+            "ptnMove",
+            format!(
+                "Attempts can't have more than {} moves",
+                validation::MAX_SOLUTION_MOVES
+            ),
+        )
+        .into());
+    }
+    current.moves.push(Move {
+        ptn_move: payload.ptn_move,
+        elapsed_ms: current.elapsed_ms as u32,
+    });
+    let db_conn = db::open().map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let moves =
+        serde_json::to_string(&current.moves).map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    db_conn
+        .execute(
+            "UPDATE attempts_in_progress SET moves = ?1 WHERE username = ?2 AND puzzle_id = ?3",
+            rusqlite::params![moves, username, current.puzzle.id],
+        )
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    Ok(Json(current))
+}
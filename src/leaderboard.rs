@@ -0,0 +1,322 @@
+use axum::{
+    Json,
+    extract::{Path, Query},
+    http::StatusCode,
+};
+use rusqlite::{Connection, OptionalExtension};
+use serde::{Deserialize, Serialize};
+use utoipa::{IntoParams, ToSchema};
+
+use crate::{db, telemetry};
+
+const LEADERBOARD_SIZE: u32 = 100;
+
+pub fn init_db_tables(db_conn: &Connection) -> anyhow::Result<()> {
+    // The leaderboard as it was when a week or month ended, see `take_snapshots`.
+    // Periods are in UTC, and identified by the date they start on, like `2025-06-02`. Weeks start on Mondays
+    db_conn.execute(
+        "CREATE TABLE IF NOT EXISTS leaderboard_snapshots (
+            period TEXT NOT NULL,
+            period_start TEXT NOT NULL,
+            period_end TEXT NOT NULL,
+            taken_seconds INTEGER NOT NULL DEFAULT (strftime('%s', 'now')),
+            PRIMARY KEY (period, period_start)
+        )",
+        [],
+    )?;
+
+    db_conn.execute(
+        "CREATE TABLE IF NOT EXISTS leaderboard_snapshot_entries (
+            period TEXT NOT NULL,
+            period_start TEXT NOT NULL,
+            rank INTEGER NOT NULL,
+            username TEXT NOT NULL,
+            rating REAL NOT NULL,
+            num_solved INTEGER NOT NULL,
+            PRIMARY KEY (period, period_start, rank)
+        )",
+        [],
+    )?;
+
+    Ok(())
+}
+
+#[derive(Clone, Copy, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum Period {
+    Week,
+    Month,
+}
+
+impl Period {
+    fn name(self) -> &'static str {
+        match self {
+            Period::Week => "week",
+            Period::Month => "month",
+        }
+    }
+
+    // SQLite date modifiers from today to the start of the last period that has ended
+    fn last_start_modifiers(self) -> (&'static str, &'static str) {
+        match self {
+            Period::Week => ("-13 days", "weekday 1"),
+            Period::Month => ("start of month", "-1 month"),
+        }
+    }
+
+    // SQLite date modifier from the start of a period to its end
+    fn length_modifier(self) -> &'static str {
+        match self {
+            Period::Week => "+7 days",
+            Period::Month => "+1 month",
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct LeaderboardEntry {
+    rank: u32,
+    // The user's display name, see `storage::set_display_name`
+    username: String,
+    rating: f64,
+    num_solved: u32,
+}
+
+// Get the highest rated users
+#[utoipa::path(
+    get,
+    path = "/leaderboard",
+    tag = "leaderboard",
+    responses((status = 200, body = Vec<LeaderboardEntry>)),
+)]
+pub async fn get_leaderboard() -> Result<Json<Vec<LeaderboardEntry>>, StatusCode> {
+    let db_conn = db::open().map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let leaderboard = telemetry::time_db_query("read_leaderboard", || read_leaderboard(&db_conn))
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    Ok(Json(leaderboard))
+}
+
+pub fn read_leaderboard(db_conn: &Connection) -> anyhow::Result<Vec<LeaderboardEntry>> {
+    read_leaderboard_entries(db_conn, "1 = 1", rusqlite::params![LEADERBOARD_SIZE])
+}
+
+// The leaderboard restricted to the user and everyone they follow
+pub fn read_following_leaderboard(
+    db_conn: &Connection,
+    username: &str,
+) -> anyhow::Result<Vec<LeaderboardEntry>> {
+    read_leaderboard_entries(
+        db_conn,
+        "(users.username = ?2 OR users.username IN (SELECT followee FROM follows WHERE follower = ?2))",
+        rusqlite::params![LEADERBOARD_SIZE, username],
+    )
+}
+
+fn read_leaderboard_entries(
+    db_conn: &Connection,
+    filter: &str,
+    params: &[&dyn rusqlite::ToSql],
+) -> anyhow::Result<Vec<LeaderboardEntry>> {
+    let mut stmt = db_conn.prepare(&format!(
+        "SELECT COALESCE(users.display_name, users.username), users.rating,
+            (SELECT COUNT(DISTINCT puzzle_id) FROM puzzle_attempts
+                WHERE puzzle_attempts.user_id = users.id AND solved = 1)
+        FROM users WHERE users.deleted_seconds IS NULL AND {filter}
+        ORDER BY users.rating DESC LIMIT ?1"
+    ))?;
+    let rows = stmt.query_map(params, |row| {
+        Ok(LeaderboardEntry {
+            rank: 0,
+            username: row.get(0)?,
+            rating: row.get(1)?,
+            num_solved: row.get(2)?,
+        })
+    })?;
+    let mut entries = rows.collect::<Result<Vec<_>, _>>()?;
+    for (i, entry) in entries.iter_mut().enumerate() {
+        entry.rank = i as u32 + 1;
+    }
+    Ok(entries)
+}
+
+#[derive(Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct Snapshot {
+    period: Period,
+    period_start: String,
+    // The first day after the period
+    period_end: String,
+    taken_seconds: u64,
+}
+
+#[derive(Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct SnapshotWithEntries {
+    #[serde(flatten)]
+    snapshot: Snapshot,
+    entries: Vec<LeaderboardEntry>,
+}
+
+#[derive(Serialize, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct SnapshotsQuery {
+    period: Period,
+}
+
+// Freeze the leaderboard for the last week and month that have ended, if they haven't been already.
+// Snapshots are taken by a background job soon after each period ends, or by the first request for them.
+// Ratings aren't kept per period, so a snapshot has the ratings from when it was taken.
+// Returns the number of snapshots taken
+pub fn take_snapshots(db_conn: &mut Connection) -> anyhow::Result<usize> {
+    let transaction = db_conn.transaction()?;
+    let mut num_taken = 0;
+    for period in [Period::Week, Period::Month] {
+        let (to_start, to_period_start) = period.last_start_modifiers();
+        let period_start: Option<String> = transaction
+            .query_row(
+                "INSERT OR IGNORE INTO leaderboard_snapshots (period, period_start, period_end)
+                SELECT ?1, start, date(start, ?4) FROM (SELECT date('now', ?2, ?3) AS start)
+                RETURNING period_start",
+                rusqlite::params![
+                    period.name(),
+                    to_start,
+                    to_period_start,
+                    period.length_modifier()
+                ],
+                |row| row.get(0),
+            )
+            .optional()?;
+        let Some(period_start) = period_start else {
+            continue;
+        };
+        let mut stmt = transaction.prepare(
+            "INSERT INTO leaderboard_snapshot_entries (period, period_start, rank, username, rating, num_solved)
+            VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+        )?;
+        for entry in read_leaderboard(&transaction)? {
+            stmt.execute(rusqlite::params![
+                period.name(),
+                period_start,
+                entry.rank,
+                entry.username,
+                entry.rating,
+                entry.num_solved
+            ])?;
+        }
+        num_taken += 1;
+    }
+    transaction.commit()?;
+    Ok(num_taken)
+}
+
+// The `leaderboard-snapshots` job, see `scheduler.rs`
+pub async fn run_snapshots() -> anyhow::Result<()> {
+    let num_taken = take_snapshots(&mut db::open()?)?;
+    if num_taken > 0 {
+        tracing::info!("Took {} leaderboard snapshots", num_taken);
+    }
+    Ok(())
+}
+
+// List the past weeks or months with a leaderboard snapshot, newest first
+#[utoipa::path(
+    get,
+    path = "/leaderboard/snapshots",
+    tag = "leaderboard",
+    params(SnapshotsQuery),
+    responses((status = 200, body = Vec<Snapshot>), (status = 400)),
+)]
+pub async fn get_snapshots(
+    Query(query): Query<SnapshotsQuery>,
+) -> Result<Json<Vec<Snapshot>>, StatusCode> {
+    let mut db_conn = db::open().map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    take_snapshots(&mut db_conn)
+        .and_then(|_| read_snapshots(&db_conn, query.period))
+        .map(Json)
+        .map_err(|e| {
+            tracing::error!("Error reading leaderboard snapshots: {:?}", e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })
+}
+
+// Get the leaderboard as it was at the end of the week or month starting on `start`
+#[utoipa::path(
+    get,
+    path = "/leaderboard/snapshots/{period}/{start}",
+    tag = "leaderboard",
+    params(
+        ("period" = Period, Path),
+        ("start" = String, Path, description = "The first day of the period, like `2025-06-02`"),
+    ),
+    responses((status = 200, body = SnapshotWithEntries), (status = 400), (status = 404)),
+)]
+pub async fn get_snapshot(
+    Path((period, start)): Path<(Period, String)>,
+) -> Result<Json<SnapshotWithEntries>, StatusCode> {
+    let mut db_conn = db::open().map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    take_snapshots(&mut db_conn)
+        .and_then(|_| read_snapshot(&db_conn, period, &start))
+        .map_err(|e| {
+            tracing::error!("Error reading leaderboard snapshot: {:?}", e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?
+        .map(Json)
+        .ok_or(StatusCode::NOT_FOUND)
+}
+
+fn read_snapshots(db_conn: &Connection, period: Period) -> anyhow::Result<Vec<Snapshot>> {
+    let mut stmt = db_conn.prepare(
+        "SELECT period_start, period_end, taken_seconds FROM leaderboard_snapshots
+        WHERE period = ?1 ORDER BY period_start DESC",
+    )?;
+    let rows = stmt.query_map([period.name()], |row| {
+        Ok(Snapshot {
+            period,
+            period_start: row.get(0)?,
+            period_end: row.get(1)?,
+            taken_seconds: row.get(2)?,
+        })
+    })?;
+    Ok(rows.collect::<Result<Vec<_>, _>>()?)
+}
+
+fn read_snapshot(
+    db_conn: &Connection,
+    period: Period,
+    start: &str,
+) -> anyhow::Result<Option<SnapshotWithEntries>> {
+    let snapshot = db_conn
+        .query_row(
+            "SELECT period_end, taken_seconds FROM leaderboard_snapshots
+            WHERE period = ?1 AND period_start = ?2",
+            [period.name(), start],
+            |row| {
+                Ok(Snapshot {
+                    period,
+                    period_start: start.to_string(),
+                    period_end: row.get(0)?,
+                    taken_seconds: row.get(1)?,
+                })
+            },
+        )
+        .optional()?;
+    let Some(snapshot) = snapshot else {
+        return Ok(None);
+    };
+    let mut stmt = db_conn.prepare(
+        "SELECT rank, username, rating, num_solved FROM leaderboard_snapshot_entries
+        WHERE period = ?1 AND period_start = ?2 ORDER BY rank",
+    )?;
+    let entries = stmt
+        .query_map([period.name(), start], |row| {
+            Ok(LeaderboardEntry {
+                rank: row.get(0)?,
+                username: row.get(1)?,
+                rating: row.get(2)?,
+                num_solved: row.get(3)?,
+            })
+        })?
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(Some(SnapshotWithEntries { snapshot, entries }))
+}
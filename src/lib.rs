@@ -0,0 +1,643 @@
+use std::{
+    sync::{Arc, atomic::AtomicBool},
+    time::{Instant, SystemTime, UNIX_EPOCH},
+};
+
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+
+use axum::{
+    Json,
+    extract::{DefaultBodyLimit, Path, Query, State},
+    http::{HeaderMap, StatusCode},
+    response::Response,
+};
+use tower_http::trace::{DefaultOnRequest, DefaultOnResponse};
+use tower_http::{catch_panic::CatchPanicLayer, trace::TraceLayer};
+use tracing::Level;
+use utoipa::{IntoParams, ToSchema};
+use validation::ApiError;
+
+mod achievements;
+pub mod admin;
+mod attempts;
+pub mod audit;
+mod auth;
+mod backups;
+mod bans;
+mod board;
+mod campaign;
+mod collections;
+pub mod config;
+mod daily;
+mod dashboard;
+pub mod db;
+mod devices;
+mod discord;
+pub mod error_reporting;
+mod etag;
+mod events;
+mod experiments;
+mod explanations;
+mod exports;
+mod feed;
+pub mod fixtures;
+mod friends;
+mod graphql;
+pub mod grpc;
+mod health;
+mod idempotency;
+pub mod importers;
+mod in_progress;
+mod leaderboard;
+mod live;
+pub mod mail;
+mod maintenance;
+pub mod migrations;
+mod notifications;
+mod openapi;
+mod pagination;
+#[cfg(feature = "postgres")]
+pub mod postgres;
+mod progress;
+mod puzzle_packs;
+mod puzzle_sets;
+mod races;
+mod rate_limit;
+mod rating_history;
+pub mod ratings;
+mod read_only;
+mod recommendations;
+mod reports;
+pub mod retention;
+mod retries;
+mod routes;
+pub mod scheduler;
+mod search;
+mod seasons;
+pub mod server;
+mod sessions;
+mod settings;
+pub mod shutdown;
+mod stats;
+pub mod storage;
+mod svg;
+mod target_time;
+mod teams;
+pub mod telemetry;
+mod themes;
+mod tournaments;
+mod users;
+mod validation;
+mod webhooks;
+
+#[derive(Clone)]
+pub struct AppState {
+    pub races: races::RaceRooms,
+    pub events: events::EventSender,
+    pub config: Arc<config::Config>,
+    pub rate_limiter: Arc<rate_limit::RateLimiter>,
+    pub started_at: Instant,
+    // Set once startup is done and every background task has been spawned, see `health.rs`
+    pub background_jobs_started: Arc<AtomicBool>,
+    pub metrics: metrics_exporter_prometheus::PrometheusHandle,
+    pub error_reporter: error_reporting::ErrorReporter,
+    pub discord: discord::Discord,
+    pub mail: Arc<dyn mail::MailSender>,
+    pub store: Arc<dyn storage::PuzzleStore>,
+    pub read_only: Arc<read_only::ReadOnly>,
+}
+
+impl AppState {
+    // Should be created once the database has been set up, see `storage::init_db_tables`
+    pub fn new(
+        config: config::Config,
+        metrics: metrics_exporter_prometheus::PrometheusHandle,
+    ) -> anyhow::Result<Self> {
+        let events = events::event_channel();
+        Ok(Self {
+            races: Default::default(),
+            events: events.clone(),
+            rate_limiter: Arc::new(rate_limit::RateLimiter::new(config.rate_limit.clone())),
+            error_reporter: error_reporting::ErrorReporter::start(&config.error_reporting),
+            discord: discord::Discord::new(&config.discord),
+            mail: mail::sender(&config.mail),
+            store: Arc::new(storage::SqliteStore::new(events, config.seasons.clone())?),
+            read_only: Arc::new(read_only::ReadOnly::new(config.server.read_only)),
+            config: Arc::new(config),
+            started_at: Instant::now(),
+            background_jobs_started: Default::default(),
+            metrics,
+        })
+    }
+}
+
+// Jobs that run for as long as the server does. Not started by `app`, so that tests don't run them
+pub fn spawn_background_jobs(state: AppState) -> anyhow::Result<()> {
+    scheduler::spawn(state)
+}
+
+// The whole API with every middleware, ready to be served
+pub fn app(state: AppState) -> anyhow::Result<axum::Router> {
+    let compression_config = state.config.compression.clone();
+    Ok(routes::router()
+        .layer(DefaultBodyLimit::max(validation::MAX_BODY_BYTES))
+        .layer(axum::middleware::from_fn_with_state(
+            state.clone(),
+            read_only::reject_writes,
+        ))
+        .layer(axum::middleware::from_fn_with_state(
+            state.clone(),
+            sessions::authenticate,
+        ))
+        .layer(axum::middleware::from_fn_with_state(
+            state.clone(),
+            rate_limit::rate_limit,
+        ))
+        .layer(server::cors_layer(&state.config.cors)?)
+        .layer(axum::middleware::from_fn(telemetry::track_requests))
+        .layer(CatchPanicLayer::new())
+        .layer(axum::middleware::from_fn_with_state(
+            state.clone(),
+            error_reporting::report_errors,
+        ))
+        .with_state(state)
+        .layer(
+            tower::ServiceBuilder::new()
+                .layer(server::compression_layer(&compression_config))
+                .layer(axum::middleware::from_fn(telemetry::request_id))
+                .layer(
+                    TraceLayer::new_for_http()
+                        .make_span_with(telemetry::request_span)
+                        .on_request(DefaultOnRequest::new().level(Level::INFO))
+                        .on_response(DefaultOnResponse::new().level(Level::INFO)),
+                ),
+        ))
+}
+
+#[derive(Clone, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+struct Puzzle {
+    id: u64,
+    size: usize,
+    komi: String,
+    #[serde(rename = "rootTPS")]
+    root_tps: String,
+    defender_start_move: String,
+    solution: Vec<String>,
+    target_time_seconds: u32,
+    player_white: String,
+    player_black: String,
+    playtak_game_id: usize,
+    // Served again because the user has attempted every puzzle. Reviews are never rated
+    #[serde(default)]
+    review: bool,
+}
+
+impl From<PuzzleRow> for Puzzle {
+    fn from(row: PuzzleRow) -> Self {
+        let low_target_time = row.target_time_seconds as f32;
+        let target_time = rand::rng().random_range(low_target_time..(low_target_time * 1.2)) as u32;
+        Self {
+            id: row.id,
+            size: row.size,
+            komi: row.komi,
+            root_tps: row.root_tps,
+            defender_start_move: row.defender_start_move,
+            solution: row.solution.split_whitespace().map(String::from).collect(),
+            target_time_seconds: target_time,
+            player_white: row.player_white,
+            player_black: row.player_black,
+            playtak_game_id: row.playtak_game_id,
+            review: false,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, ToSchema, IntoParams)]
+#[into_params(parameter_in = Query)]
+struct PuzzleRequest {
+    username: String,
+}
+
+#[derive(Serialize, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+struct PuzzleQuery {
+    username: String,
+    // Set to false to practice, which serves puzzles the user has seen before.
+    // Defaults to the user's `ratedByDefault` setting
+    rated: Option<bool>,
+    // Only serve puzzles from games this playtak player played in
+    player: Option<String>,
+    // Only serve puzzles on this board size. Defaults to the user's `preferredSizes` setting
+    size: Option<usize>,
+    // How to pick among unattempted puzzles. Ignored when practicing, or once they've all been attempted
+    #[serde(default)]
+    strategy: recommendations::Strategy,
+}
+
+fn default_rated() -> bool {
+    true
+}
+
+#[derive(Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+struct PuzzleResponse {
+    id: usize,
+    username: String,
+    solved: bool,
+    solution: Vec<String>,
+    solve_time_seconds: u32,
+    // Milliseconds from when the puzzle was shown until each move in `solution` was played.
+    // Optional, but lets the move time stats show which moves people think longest about
+    #[serde(default)]
+    move_times_ms: Option<Vec<u32>>,
+    // Practice attempts are stored, but never affect ratings
+    #[serde(default = "default_rated")]
+    rated: bool,
+    // A second try right after failing the puzzle's rated attempt, see `retries.rs`
+    #[serde(default)]
+    retry: bool,
+}
+
+pub fn now_seconds() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs()
+}
+
+// Get a random puzzle
+#[utoipa::path(
+    get,
+    path = "/puzzles",
+    tag = "puzzles",
+    params(PuzzleQuery),
+    responses(
+        (status = 200, body = Puzzle),
+        (status = 400, body = validation::ValidationError),
+        (status = 403, description = "The user is banned"),
+        (status = 404, description = "No puzzles for the user, not even to review"),
+    ),
+)]
+#[axum::debug_handler]
+async fn get_puzzle(
+    State(state): State<AppState>,
+    query: Query<PuzzleQuery>,
+) -> Result<Json<Puzzle>, ApiError> {
+    validation::validate_username(&query.username)?;
+    if let Some(player) = &query.player {
+        validation::validate_username(player)
+            .map_err(|e| validation::ValidationError::new("player", e.message))?;
+    }
+    if let Some(size) = query.size
+        && !(3..=8).contains(&size)
+    {
+        return Err(
+            validation::ValidationError::new("size", "Size must be between 3 and 8").into(),
+        );
+    }
+    let username = validation::canonical_username(&query.username);
+    let db_conn = db::open().map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    bans::check_not_banned(&db_conn, &username)?;
+    // The query overrides the user's settings
+    let user_settings = settings::read_settings(&db_conn, &username).map_err(|e| {
+        tracing::error!("Error reading settings: {:?}", e);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+    let rated = query.rated.unwrap_or(user_settings.rated_by_default);
+    let sizes: Vec<usize> = match query.size {
+        Some(size) => vec![size],
+        None => user_settings.preferred_sizes.into_iter().collect(),
+    };
+    let filter = storage::PuzzleFilter {
+        player: query.player.as_deref(),
+        sizes: &sizes,
+    };
+    let puzzle = match (rated, query.strategy) {
+        (false, _) => state.store.practice_puzzle(&username, &filter).await,
+        (true, recommendations::Strategy::Random) => {
+            select_puzzle_for_user(state.store.as_ref(), &username, &filter).await
+        }
+        (true, recommendations::Strategy::Weaknesses) => {
+            telemetry::time_db_query("select_recommended_puzzle", || {
+                recommendations::select_puzzle(
+                    &db_conn,
+                    &recommendations::Weaknesses,
+                    &username,
+                    &filter,
+                )
+            })
+        }
+    };
+    // Once the user has attempted every puzzle, they review the ones they've seen rather than running out
+    let mut review = false;
+    let puzzle = match puzzle {
+        Ok(None) if rated => {
+            review = true;
+            state.store.review_puzzle(&username, &filter).await
+        }
+        puzzle => puzzle,
+    };
+    let mut puzzle = match puzzle {
+        Ok(Some(puzzle)) => puzzle,
+        Ok(None) => return Err(StatusCode::NOT_FOUND.into()),
+        Err(e) => {
+            tracing::error!("Error reading puzzles from database: {:?}", e);
+            return Err(StatusCode::INTERNAL_SERVER_ERROR.into());
+        }
+    };
+    puzzle.target_time_seconds = target_time_for_user(&state, &puzzle, &username)
+        .await
+        .map_err(|e| {
+            tracing::error!("Error reading ratings from database: {:?}", e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+    let puzzle = Puzzle {
+        review,
+        ..Puzzle::from(puzzle)
+    };
+    in_progress::start(&db_conn, &username, &puzzle, rated && !review).map_err(|e| {
+        tracing::error!("Error storing attempt in progress: {:?}", e);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+    Ok(Json(puzzle))
+}
+
+async fn target_time_for_user(
+    state: &AppState,
+    puzzle: &PuzzleRow,
+    username: &str,
+) -> anyhow::Result<u32> {
+    let variant = experiments::variant(&db::open()?, &experiments::TARGET_TIME, username)?;
+    if variant == "unscaled" {
+        return Ok(puzzle.target_time_seconds);
+    }
+    let puzzle_rating = state.store.puzzle_rating(puzzle.id as u32).await?;
+    let user_rating = state.store.user_rating(username).await?;
+    Ok(target_time::for_user(
+        &state.config.target_time,
+        puzzle.target_time_seconds,
+        puzzle_rating.rating,
+        user_rating.rating,
+    ))
+}
+
+// Puzzles 3 and 15 introduce new users to the site, so they aren't forced on users who ask for a player's puzzles,
+// or on users who only want puzzles on other sizes
+async fn select_puzzle_for_user(
+    store: &dyn storage::PuzzleStore,
+    username: &str,
+    filter: &storage::PuzzleFilter<'_>,
+) -> anyhow::Result<Option<PuzzleRow>> {
+    if filter.player.is_some() {
+        return store.unattempted_puzzle(username, filter).await;
+    }
+    let puzzles_solved = store.attempts_for_user(username).await?;
+    let is_allowed =
+        |puzzle: &PuzzleRow| filter.sizes.is_empty() || filter.sizes.contains(&puzzle.size);
+
+    // Always show puzzle 3 first
+    if !puzzles_solved.iter().any(|attempt| attempt.puzzle_id == 3)
+        && let Some(puzzle_3) = store.puzzle(3).await?
+        && is_allowed(&puzzle_3)
+    {
+        return Ok(Some(puzzle_3));
+    }
+
+    // Always show puzzle 15 second
+    if !puzzles_solved.iter().any(|attempt| attempt.puzzle_id == 15)
+        && let Some(puzzle_15) = store.puzzle(15).await?
+        && is_allowed(&puzzle_15)
+    {
+        return Ok(Some(puzzle_15));
+    }
+
+    // Then show any published puzzle
+    store.unattempted_puzzle(username, filter).await
+}
+
+// Get a single published puzzle.
+// The ETag only changes when the puzzle does, although the target time is picked again for every response.
+// There's no user here, so the target time isn't scaled to a rating like in `GET /puzzles`
+#[utoipa::path(
+    get,
+    path = "/puzzles/{id}",
+    tag = "puzzles",
+    params(("id" = u32, Path)),
+    responses(
+        (status = 200, body = Puzzle),
+        (status = 304, description = "The puzzle matches the `If-None-Match` ETag"),
+        (status = 404),
+    ),
+)]
+async fn get_puzzle_by_id(
+    Path(id): Path<u32>,
+    State(state): State<AppState>,
+    headers: HeaderMap,
+) -> Result<Response, StatusCode> {
+    let puzzle = state
+        .store
+        .published_puzzle(id)
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+        .ok_or(StatusCode::NOT_FOUND)?;
+    let etag = etag::weak_etag(&puzzle);
+    Ok(etag::with_etag(&headers, etag, Json(Puzzle::from(puzzle))))
+}
+
+// Get the ratings of every published puzzle, by id
+#[utoipa::path(
+    get,
+    path = "/puzzles/ratings",
+    tag = "puzzles",
+    responses(
+        (status = 200, body = std::collections::BTreeMap<u32, f64>),
+        (status = 304, description = "The ratings match the `If-None-Match` ETag"),
+    ),
+)]
+async fn get_puzzle_ratings(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+) -> Result<Response, StatusCode> {
+    let ratings = state.store.published_puzzle_ratings().await.map_err(|e| {
+        tracing::error!("Error reading puzzle ratings from database: {:?}", e);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+    let etag = etag::weak_etag(&ratings);
+    Ok(etag::with_etag(&headers, etag, Json(ratings)))
+}
+
+// Get elo rating of a single puzzle
+#[utoipa::path(
+    get,
+    path = "/puzzles/{id}/rating",
+    tag = "puzzles",
+    params(("id" = u32, Path)),
+    responses(
+        (status = 200, body = f64),
+        (status = 304, description = "The rating matches the `If-None-Match` ETag"),
+    ),
+)]
+async fn get_puzzle_rating(
+    Path(id): Path<u32>,
+    State(state): State<AppState>,
+    headers: HeaderMap,
+) -> Result<Response, StatusCode> {
+    let rating = state
+        .store
+        .puzzle_rating(id)
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let etag = etag::weak_etag(&rating.rating);
+    Ok(etag::with_etag(&headers, etag, Json(rating.rating)))
+}
+
+// Solve puzzle
+// Retries should send the same `Idempotency-Key` header, so the attempt is only recorded once
+#[utoipa::path(
+    post,
+    path = "/puzzles/{id}",
+    tag = "puzzles",
+    params(
+        ("id" = u32, Path),
+        ("Idempotency-Key" = Option<String>, Header, description = "Retries with the same key only record the attempt once"),
+    ),
+    request_body = PuzzleResponse,
+    responses(
+        (status = 200, body = AttemptResult),
+        (status = 400, body = validation::ValidationError),
+        (status = 403, description = "The user is banned"),
+        (status = 404),
+        (status = 409, description = "The idempotency key was used for a different puzzle"),
+    ),
+)]
+#[axum::debug_handler]
+async fn solve_puzzle(
+    Path(id): Path<u32>,
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    device: devices::Device,
+    Json(payload): Json<PuzzleResponse>,
+) -> Result<Json<AttemptResult>, ApiError> {
+    validation::validate_username(&payload.username)?;
+    validation::validate_solution(&payload.solution)?;
+    validation::validate_solve_time(payload.solve_time_seconds)?;
+    if let Some(move_times_ms) = &payload.move_times_ms {
+        validation::validate_move_times(move_times_ms, &payload.solution)?;
+    }
+    let idempotency_key = idempotency::key_from_headers(&headers)?;
+    let username = validation::canonical_username(&payload.username);
+    bans::check_not_banned(
+        &db::open().map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?,
+        &username,
+    )?;
+    let mut puzzle = state
+        .store
+        .published_puzzle(id)
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+        .ok_or(StatusCode::NOT_FOUND)?;
+    validation::validate_solution_for_size(&payload.solution, puzzle.size)?;
+    puzzle.target_time_seconds = target_time_for_user(&state, &puzzle, &username)
+        .await
+        .map_err(|e| {
+            tracing::error!("Error reading ratings from database: {:?}", e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+    let verification = attempts::verify(&puzzle, &payload.solution, payload.solve_time_seconds);
+    if payload.solved != verification.correct {
+        let message = if verification.correct {
+            "Solution is the puzzle's solution, but wasn't marked as solved"
+        } else {
+            "Solution doesn't match the puzzle's solution"
+        };
+        return Err(validation::ValidationError::new("solution", message).into());
+    }
+    let attempt = attempts::NewAttempt {
+        puzzle_id: id,
+        username: username.clone(),
+        solved: payload.solved,
+        solve_time_seconds: payload.solve_time_seconds,
+        solution: payload.solution,
+        move_times_ms: payload.move_times_ms,
+        practice: !payload.rated,
+        device,
+        retry: payload.retry,
+        judgement: verification.judgement.clone(),
+    };
+    let recorded = match state.store.record_attempt(attempt, idempotency_key).await {
+        Ok(storage::AttemptOutcome::Recorded(recorded)) => recorded,
+        Ok(storage::AttemptOutcome::Repeated { puzzle_id, attempt }) if puzzle_id == id => attempt,
+        Ok(storage::AttemptOutcome::Repeated { .. }) => return Err(StatusCode::CONFLICT.into()),
+        Ok(storage::AttemptOutcome::NotRetryable) => {
+            return Err(validation::ValidationError::new(
+                "retry",
+                "There is no failed attempt at this puzzle to retry",
+            )
+            .into());
+        }
+        Err(e) => {
+            tracing::error!("Error recording attempt: {:?}", e);
+            return Err(StatusCode::INTERNAL_SERVER_ERROR.into());
+        }
+    };
+    // The attempt is already recorded, so these are only logged
+    let finished = db::open().map_err(anyhow::Error::from).and_then(|db_conn| {
+        in_progress::finish(&db_conn, &username, id)?;
+        storage::set_display_name(&db_conn, &username, &payload.username)
+    });
+    if let Err(e) = finished {
+        tracing::error!("Error finishing attempt in progress: {:?}", e);
+    }
+    Ok(Json(AttemptResult {
+        attempt_number: recorded.attempt_number,
+        rated: recorded.rated,
+        rating_change: recorded.rating_change,
+        retry: recorded.retry,
+        verification,
+    }))
+}
+
+#[derive(Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+struct AttemptResult {
+    attempt_number: u32,
+    // Whether the attempt counts for the user's and the puzzle's ratings
+    rated: bool,
+    // Only set for rated attempts
+    rating_change: Option<ratings::RatingChange>,
+    // Only set for retries, with the result of both attempts together
+    retry: Option<retries::RetryResult>,
+    #[serde(flatten)]
+    verification: attempts::Verification,
+}
+
+// INSERT INTO puzzles (size, komi, root_tps, defender_start_move, solution, target_time_seconds, player_white, player_black, playtak_game_id)
+// VALUES (6, "2", "2,x,x,2,1,1/2,x,2,2,1,2S/2222221S,x,x,121C,1,x/x,112,11112C,2,21211112S,2/2,22221S,2,1,x,1/2,x,1,1,1,1 2 47", "5e3< d4- 3e3+12 *", 120, "x57696c6c", "EVRNjayhawker", 491458)
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct PuzzleRow {
+    pub id: u64,
+    pub root_tps: String,
+    pub defender_start_move: String,
+    pub size: usize,
+    pub komi: String,
+    pub player_white: String,
+    pub player_black: String,
+    pub solution: String,
+    pub initial_rating: Option<i32>,
+    pub rating: Option<i32>,
+    pub target_time_seconds: u32,
+    pub playtak_game_id: usize,
+    pub published: bool,
+}
+
+// The target time for a puzzle, from the number of pieces on the board and the length of the solution.
+// Puzzles are served with a target time up to 20% above their `target_time_seconds`, see `From<PuzzleRow> for Puzzle`.
+// Used for imported puzzles without a target time, and by `backfill-target-times` for existing ones
+pub fn default_target_time_seconds(root_tps: &str, solution: &str) -> u32 {
+    let num_pieces = root_tps.chars().filter(|c| *c == '1' || *c == '2').count() / 2;
+    let length = solution.split_whitespace().count().div_ceil(2);
+    ((20 + num_pieces) * length) as u32
+}
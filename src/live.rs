@@ -0,0 +1,309 @@
+use std::time::{Duration, Instant};
+
+use axum::{
+    extract::{
+        State,
+        ws::{Message, WebSocket, WebSocketUpgrade},
+    },
+    http::StatusCode,
+    response::Response,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    AppState, Puzzle, PuzzleRow, attempts, bans, db, races,
+    ratings::RatingChange,
+    sessions::{self, AuthUser},
+    storage::{self, PuzzleFilter, PuzzleStore},
+    validation,
+};
+
+// Messages sent from the client to the server.
+// The first message on a connection must be either `start` or `joinRace`
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "camelCase")]
+enum ClientMessage {
+    // Start solving a puzzle. If `puzzle_id` is not set, a puzzle is picked like `GET /puzzles`
+    #[serde(rename_all = "camelCase")]
+    Start {
+        username: String,
+        puzzle_id: Option<u32>,
+        #[serde(default = "crate::default_rated")]
+        rated: bool,
+    },
+    // Hand the connection over to a race room, see `races.rs`
+    #[serde(rename_all = "camelCase")]
+    JoinRace { room: String, username: String },
+    #[serde(rename_all = "camelCase")]
+    Move {
+        #[serde(rename = "move")]
+        ptn_move: String,
+    },
+}
+
+// Messages sent from the server to the client while solving
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "camelCase")]
+enum ServerMessage {
+    // The puzzle to solve. The solution is left out until the attempt is finished
+    #[serde(rename_all = "camelCase")]
+    Puzzle { puzzle: Puzzle },
+    #[serde(rename_all = "camelCase")]
+    Clock { elapsed_seconds: f64 },
+    // A correct move that doesn't finish the puzzle, along with the defender's reply
+    #[serde(rename_all = "camelCase")]
+    MoveAccepted { reply: String, elapsed_seconds: f64 },
+    #[serde(rename_all = "camelCase")]
+    Finished {
+        solved: bool,
+        solution: Vec<String>,
+        solve_time_seconds: u32,
+        target_time_seconds: u32,
+        // Not set if the attempt could not be recorded
+        attempt_number: Option<u32>,
+        // Only set for rated attempts
+        rating_change: Option<RatingChange>,
+    },
+    #[serde(rename_all = "camelCase")]
+    Error { message: String },
+}
+
+#[utoipa::path(
+    get,
+    path = "/ws",
+    tag = "live",
+    description = "WebSocket for solving a puzzle live, with moves checked by the server. To play as a claimed username, send its access token as `accessToken` in the query string",
+    responses((status = 101, description = "Switching to the WebSocket protocol")),
+)]
+pub async fn live_socket(
+    ws: WebSocketUpgrade,
+    user: Option<AuthUser>,
+    State(state): State<AppState>,
+) -> Response {
+    ws.on_upgrade(move |socket| handle_socket(socket, state, user))
+}
+
+async fn handle_socket(mut socket: WebSocket, state: AppState, user: Option<AuthUser>) {
+    let Some(first_message) = receive(&mut socket).await else {
+        return;
+    };
+    if let Ok(ClientMessage::Start { username, .. } | ClientMessage::JoinRace { username, .. }) =
+        &first_message
+        && let Err(message) = check_can_play(&state, user.as_ref(), username)
+    {
+        let _ = send(&mut socket, &ServerMessage::Error { message }).await;
+        return;
+    }
+    match first_message {
+        Ok(ClientMessage::Start {
+            username,
+            puzzle_id,
+            rated,
+        }) => {
+            let username = validation::canonical_username(&username);
+            solve_session(socket, state, username, puzzle_id, rated).await
+        }
+        Ok(ClientMessage::JoinRace { room, username }) => {
+            let username = validation::canonical_username(&username);
+            races::handle_socket(socket, state.races, room, username).await
+        }
+        _ => {
+            let message = "Expected a start or joinRace message".to_string();
+            let _ = send(&mut socket, &ServerMessage::Error { message }).await;
+        }
+    }
+}
+
+// Like the HTTP endpoints, banned users can't start puzzles or join races, nobody can while the server is
+// read-only, and claimed usernames can only be played as with an access token for them
+fn check_can_play(state: &AppState, user: Option<&AuthUser>, username: &str) -> Result<(), String> {
+    if let Some(message) = state.read_only.message() {
+        return Err(message);
+    }
+    validation::validate_username(username).map_err(|e| e.message)?;
+    let username = validation::canonical_username(username);
+    let db_conn = db::open().map_err(|_| "Internal error".to_string())?;
+    match bans::is_banned(&db_conn, &username) {
+        Ok(false) => {}
+        Ok(true) => return Err("Banned users can't solve puzzles".to_string()),
+        Err(e) => {
+            tracing::error!("Error reading ban list from database: {:?}", e);
+            return Err("Internal error".to_string());
+        }
+    }
+    if state.config.auth.secret.is_none() {
+        return Ok(());
+    }
+    sessions::check_acting_as(&db_conn, user, &username).map_err(|status| match status {
+        StatusCode::UNAUTHORIZED => "Log in to play as this user".to_string(),
+        StatusCode::FORBIDDEN => "Logged in as another user".to_string(),
+        _ => "Internal error".to_string(),
+    })
+}
+
+// Serve a puzzle and check each move against the solution as it is played.
+// The server's clock is authoritative, and the attempt is recorded when it ends.
+// Disconnecting before the puzzle is finished counts as a failed attempt
+async fn solve_session(
+    mut socket: WebSocket,
+    state: AppState,
+    username: String,
+    puzzle_id: Option<u32>,
+    rated: bool,
+) {
+    let row = match read_puzzle(state.store.as_ref(), &username, puzzle_id, rated).await {
+        Ok(Some(row)) => row,
+        Ok(None) => {
+            let message = "No puzzle found".to_string();
+            let _ = send(&mut socket, &ServerMessage::Error { message }).await;
+            return;
+        }
+        Err(e) => {
+            tracing::error!("Error reading puzzle for live session: {:?}", e);
+            return;
+        }
+    };
+
+    let puzzle = Puzzle::from(row.clone());
+    let hidden_puzzle = Puzzle {
+        solution: vec![],
+        ..puzzle.clone()
+    };
+    if send(
+        &mut socket,
+        &ServerMessage::Puzzle {
+            puzzle: hidden_puzzle,
+        },
+    )
+    .await
+    .is_err()
+    {
+        return;
+    }
+
+    let start_time = Instant::now();
+    let mut clock = tokio::time::interval(Duration::from_secs(1));
+    // Moves played so far, by both players, and when they were played
+    let mut played: Vec<String> = vec![];
+    let mut move_times_ms: Vec<u32> = vec![];
+
+    let solved = loop {
+        tokio::select! {
+            _ = clock.tick() => {
+                let elapsed_seconds = start_time.elapsed().as_secs_f64();
+                if send(&mut socket, &ServerMessage::Clock { elapsed_seconds }).await.is_err() {
+                    break false;
+                }
+            }
+            message = receive(&mut socket) => match message {
+                Some(Ok(ClientMessage::Move { ptn_move })) => {
+                    let Some(expected) = puzzle.solution.get(played.len()) else {
+                        break true;
+                    };
+                    let correct = validation::normalize_move(&ptn_move) == validation::normalize_move(expected);
+                    played.push(ptn_move);
+                    move_times_ms.push(start_time.elapsed().as_millis() as u32);
+                    if !correct {
+                        break false;
+                    }
+                    let Some(reply) = puzzle.solution.get(played.len()) else {
+                        break true;
+                    };
+                    played.push(reply.clone());
+                    move_times_ms.push(start_time.elapsed().as_millis() as u32);
+                    if played.len() == puzzle.solution.len() {
+                        break true;
+                    }
+                    let elapsed_seconds = start_time.elapsed().as_secs_f64();
+                    let accepted = ServerMessage::MoveAccepted { reply: reply.clone(), elapsed_seconds };
+                    if send(&mut socket, &accepted).await.is_err() {
+                        break false;
+                    }
+                }
+                Some(Ok(_)) => {
+                    let message = "Expected a move message".to_string();
+                    let _ = send(&mut socket, &ServerMessage::Error { message }).await;
+                }
+                Some(Err(e)) => {
+                    let message = format!("Invalid message: {}", e);
+                    let _ = send(&mut socket, &ServerMessage::Error { message }).await;
+                }
+                None => break false,
+            },
+        }
+    };
+
+    let solve_time_seconds = start_time.elapsed().as_secs() as u32;
+    let judgement = attempts::judge(&row, &played);
+    let attempt = attempts::NewAttempt {
+        puzzle_id: puzzle.id as u32,
+        username,
+        solved,
+        solve_time_seconds,
+        solution: played,
+        move_times_ms: Some(move_times_ms),
+        practice: !rated,
+        device: Default::default(),
+        retry: false,
+        judgement,
+    };
+    let recorded = match state.store.record_attempt(attempt, None).await {
+        Ok(storage::AttemptOutcome::Recorded(recorded)) => Some(recorded),
+        Ok(storage::AttemptOutcome::Repeated { .. } | storage::AttemptOutcome::NotRetryable) => {
+            None
+        }
+        Err(e) => {
+            tracing::error!("Error recording live attempt: {:?}", e);
+            None
+        }
+    };
+
+    let _ = send(
+        &mut socket,
+        &ServerMessage::Finished {
+            solved,
+            solution: puzzle.solution,
+            solve_time_seconds,
+            target_time_seconds: puzzle.target_time_seconds,
+            attempt_number: recorded.as_ref().map(|recorded| recorded.attempt_number),
+            rating_change: recorded.and_then(|recorded| recorded.rating_change),
+        },
+    )
+    .await;
+    let _ = socket.send(Message::Close(None)).await;
+}
+
+async fn read_puzzle(
+    store: &dyn PuzzleStore,
+    username: &str,
+    puzzle_id: Option<u32>,
+    rated: bool,
+) -> anyhow::Result<Option<PuzzleRow>> {
+    Ok(match puzzle_id {
+        Some(id) => store.published_puzzle(id).await?,
+        None if rated => {
+            crate::select_puzzle_for_user(store, username, &PuzzleFilter::default()).await?
+        }
+        None => {
+            store
+                .practice_puzzle(username, &PuzzleFilter::default())
+                .await?
+        }
+    })
+}
+
+// Wait for the next text message. Returns `None` once the connection is closed
+async fn receive(socket: &mut WebSocket) -> Option<Result<ClientMessage, serde_json::Error>> {
+    loop {
+        match socket.recv().await? {
+            Ok(Message::Text(text)) => return Some(serde_json::from_str(&text)),
+            Ok(Message::Close(_)) | Err(_) => return None,
+            Ok(_) => {}
+        }
+    }
+}
+
+async fn send(socket: &mut WebSocket, message: &ServerMessage) -> Result<(), axum::Error> {
+    let text = serde_json::to_string(message).unwrap();
+    socket.send(Message::Text(text.into())).await
+}
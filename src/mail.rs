@@ -0,0 +1,100 @@
+use std::sync::{Arc, Mutex};
+
+use async_trait::async_trait;
+use serde::Serialize;
+
+use crate::config::MailConfig;
+
+// Sending emails, currently only login links from `auth.rs`. Handlers only see `MailSender`,
+// so that the provider can be swapped, and tests can read what would have been sent from an `Outbox`
+
+#[derive(Clone, Debug, Serialize)]
+pub struct Mail {
+    pub to: String,
+    pub subject: String,
+    pub text: String,
+}
+
+#[async_trait]
+pub trait MailSender: Send + Sync {
+    async fn send(&self, mail: Mail) -> anyhow::Result<()>;
+}
+
+// The sender for `[mail]` in the config
+pub fn sender(config: &MailConfig) -> Arc<dyn MailSender> {
+    match &config.api_url {
+        Some(api_url) => Arc::new(HttpSender {
+            api_url: api_url.clone(),
+            api_key: config.api_key.clone(),
+            from: config.from.clone(),
+            client: reqwest::Client::new(),
+        }),
+        None => Arc::new(LogSender),
+    }
+}
+
+// For development, where there's nothing to send emails with. Logs them instead, links and all
+pub struct LogSender;
+
+#[async_trait]
+impl MailSender for LogSender {
+    async fn send(&self, mail: Mail) -> anyhow::Result<()> {
+        tracing::info!(
+            "Not sending email to {} without `mail.api_url`: {}\n{}",
+            mail.to,
+            mail.subject,
+            mail.text
+        );
+        Ok(())
+    }
+}
+
+// Posts each email as JSON to a mail provider's HTTP API, or a small relay in front of one
+pub struct HttpSender {
+    api_url: String,
+    api_key: Option<String>,
+    from: String,
+    client: reqwest::Client,
+}
+
+#[derive(Serialize)]
+struct HttpMail<'a> {
+    from: &'a str,
+    #[serde(flatten)]
+    mail: &'a Mail,
+}
+
+#[async_trait]
+impl MailSender for HttpSender {
+    async fn send(&self, mail: Mail) -> anyhow::Result<()> {
+        let mut request = self.client.post(&self.api_url).json(&HttpMail {
+            from: &self.from,
+            mail: &mail,
+        });
+        if let Some(api_key) = &self.api_key {
+            request = request.bearer_auth(api_key);
+        }
+        request.send().await?.error_for_status()?;
+        Ok(())
+    }
+}
+
+// Keeps emails instead of sending them
+#[derive(Default)]
+pub struct Outbox {
+    sent: Mutex<Vec<Mail>>,
+}
+
+impl Outbox {
+    pub fn sent(&self) -> Vec<Mail> {
+        self.sent.lock().unwrap().clone()
+    }
+}
+
+#[async_trait]
+impl MailSender for Outbox {
+    async fn send(&self, mail: Mail) -> anyhow::Result<()> {
+        self.sent.lock().unwrap().push(mail);
+        Ok(())
+    }
+}
@@ -1,304 +1,290 @@
-use anyhow::Context;
-use rand::Rng;
-use rusqlite::Connection;
-use serde::{Deserialize, Serialize};
-
-use axum::{
-    Json, Router,
-    extract::{Path, Query},
-    http::{Method, StatusCode},
-    routing::{get, post},
+use std::{
+    path::{Path, PathBuf},
+    sync::atomic::Ordering,
 };
-use serde_rusqlite::from_row;
-use tower_http::trace::TraceLayer;
-use tower_http::{
-    cors::{Any, CorsLayer},
-    trace::DefaultOnRequest,
-};
-use tracing::Level;
-
-mod ratings;
 
-#[derive(Serialize, Deserialize)]
-#[serde(rename_all = "camelCase")]
-struct Puzzle {
-    id: u64,
-    size: usize,
-    komi: String,
-    #[serde(rename = "rootTPS")]
-    root_tps: String,
-    defender_start_move: String,
-    solution: Vec<String>,
-    target_time_seconds: u32,
-    player_white: String,
-    player_black: String,
-    playtak_game_id: usize,
-}
-
-impl From<PuzzleRow> for Puzzle {
-    fn from(row: PuzzleRow) -> Self {
-        // TODO: We manually set the target time here, but it should be set in the database
-        let num_pieces = row
-            .root_tps
-            .chars()
-            .filter(|c| *c == '1' || *c == '2')
-            .count()
-            / 2;
-        let length = row.solution.split_whitespace().count().div_ceil(2);
-        let low_target_time = (20.0 + num_pieces as f32) * length as f32;
-        let target_time = rand::rng().random_range(low_target_time..(low_target_time * 1.2)) as u32;
-        Self {
-            id: row.id,
-            size: row.size,
-            komi: row.komi,
-            root_tps: row.root_tps,
-            defender_start_move: row.defender_start_move,
-            solution: row.solution.split_whitespace().map(String::from).collect(),
-            target_time_seconds: target_time,
-            player_white: row.player_white,
-            player_black: row.player_black,
-            playtak_game_id: row.playtak_game_id,
-        }
-    }
-}
+use anyhow::Context;
+use clap::{Parser, Subcommand};
+use tak_tactics_backend::{
+    AppState, admin, audit, config, db, fixtures, grpc, importers, migrations, ratings, server,
+    shutdown, storage, telemetry,
+};
 
-#[derive(Serialize, Deserialize)]
-struct PuzzleRequest {
-    username: String,
+// Every command reads the same config file, and creates and migrates the database before running
+#[derive(Parser)]
+#[command(
+    version,
+    about = "Server and maintenance commands for Tak tactics puzzles"
+)]
+struct Cli {
+    // Serves if not set, like before there were commands
+    #[command(subcommand)]
+    command: Option<Command>,
 }
 
-#[derive(Serialize, Deserialize)]
-#[serde(rename_all = "camelCase")]
-struct PuzzleResponse {
-    id: usize,
-    username: String,
-    solved: bool,
-    solution: Vec<String>,
-    solve_time_seconds: u32,
+#[derive(Subcommand)]
+enum Command {
+    #[command(about = "Serve the API")]
+    Serve {
+        #[arg(
+            long,
+            value_name = "FILE",
+            help = "Load puzzles and users from a JSON fixture first, if the database is empty"
+        )]
+        seed: Option<PathBuf>,
+    },
+    #[command(about = "Create the database and apply any pending migrations, then exit")]
+    Migrate,
+    #[command(about = "Add the puzzles and users in a JSON file, in the format `export` writes")]
+    Import {
+        #[arg(value_name = "FILE")]
+        file: PathBuf,
+        #[arg(
+            long,
+            value_enum,
+            help = "Import puzzles from another collection's format instead. Puzzles we already have are skipped"
+        )]
+        format: Option<importers::Format>,
+        #[arg(
+            long,
+            requires = "format",
+            help = "Publish puzzles imported with --format straight away, instead of leaving them for review"
+        )]
+        publish: bool,
+    },
+    #[command(about = "Write every puzzle and user as JSON")]
+    Export {
+        #[arg(
+            long,
+            short,
+            value_name = "FILE",
+            help = "Write to this file instead of stdout"
+        )]
+        output: Option<PathBuf>,
+    },
+    #[command(
+        about = "Recompute every user's rating by replaying all rated attempts. Running servers may serve cached puzzle ratings for up to 10 minutes afterwards"
+    )]
+    RecomputeRatings,
+    #[command(
+        about = "Set every puzzle's target time from its position and solution, replacing target times set by hand"
+    )]
+    BackfillTargetTimes,
+    #[command(about = "Create a token for the admin endpoints, and print it")]
+    CreateToken {
+        #[arg(long, help = "What the token is for, like who it was given to")]
+        name: String,
+    },
 }
 
 #[tokio::main]
-async fn main() {
-    // initialize tracing
-    tracing_subscriber::fmt::init();
-
-    init_db_tables().unwrap();
-
-    // build our application with a route
-    let app = Router::new()
-        // `GET /` goes to `root`
-        .route("/puzzles/{id}/rating", get(get_puzzle_rating))
-        .route("/puzzles", get(get_puzzle))
-        .route("/puzzles/{id}", post(solve_puzzle))
-        .layer(
-            CorsLayer::new()
-                .allow_methods([Method::GET, Method::POST])
-                .allow_headers(Any)
-                .allow_origin(Any),
-        )
-        .layer(tower::ServiceBuilder::new().layer(
-            TraceLayer::new_for_http().on_request(DefaultOnRequest::new().level(Level::INFO)),
-        ));
-
-    // run our app with hyper, listening globally on port 3000
-    let listener = tokio::net::TcpListener::bind("0.0.0.0:3000").await.unwrap();
-    println!("Listening on http://{}", listener.local_addr().unwrap());
-    axum::serve(listener, app).await.unwrap();
-}
-
-pub fn init_db_tables() -> anyhow::Result<()> {
-    let db_conn = Connection::open("puzzles.db").context("Failed to open database connection")?;
-
-    db_conn.execute(
-        "CREATE TABLE IF NOT EXISTS puzzles (
-            id INTEGER PRIMARY KEY AUTOINCREMENT,
-            root_tps TEXT NOT NULL,
-            defender_start_move TEXT NOT NULL,
-            size INTEGER NOT NULL,
-            komi TEXT NOT NULL,
-            player_white TEXT NOT NULL,
-            player_black TEXT NOT NULL,
-            solution TEXT NOT NULL,
-            initial_rating INTEGER,
-            rating INTEGER,
-            target_time_seconds INTEGER NOT NULL DEFAULT 60,
-            playtak_game_id INTEGER NOT NULL
-        )",
-        [],
-    )?;
-
-    db_conn.execute(
-        "CREATE TABLE IF NOT EXISTS puzzle_attempts (
-            puzzle_id INTEGER NOT NULL,
-            username TEXT NOT NULL,
-            solved INTEGER NOT NULL,
-            solve_time_seconds INTEGER NOT NULL,
-            solution TEXT NOT NULL,
-            timestamp_seconds INTEGER NOT NULL DEFAULT (strftime('%s', 'now')),
-            FOREIGN KEY (puzzle_id) REFERENCES puzzles(id)
-        )",
-        [],
-    )?;
-
-    // Ratings have to be inserted manually for now
-    db_conn.execute(
-        "CREATE TABLE IF NOT EXISTS \"users\" (
-	    \"username\" TEXT NOT NULL,
-	    \"rating\" REAL NOT NULL,
-	    PRIMARY KEY(\"username\")
-    )",
-        [],
-    )?;
-
-    Ok(())
-}
-
-// Get a random puzzle
-#[axum::debug_handler]
-async fn get_puzzle(username: Query<PuzzleRequest>) -> Result<Json<Puzzle>, StatusCode> {
-    if username.username.is_empty() {
-        return Err(StatusCode::BAD_REQUEST);
-    }
-    let db_conn = Connection::open("puzzles.db")
-        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)
-        .unwrap();
-    let puzzles_solved = read_puzzle_attempts_for_user(&db_conn, &username.username)
-        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
-
-    // Always show puzzle 3 first
-    if !puzzles_solved.iter().any(|attempt| attempt.puzzle_id == 3) {
-        let puzzle_3 = read_puzzle_by_id(&db_conn, 3)
-            .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
-            .unwrap();
-        return Ok(Json(Puzzle::from(puzzle_3)));
+async fn main() -> anyhow::Result<()> {
+    let cli = Cli::parse();
+    let command = cli.command.unwrap_or(Command::Serve { seed: None });
+    if let Command::Serve { .. } = command {
+        telemetry::init_logging();
+    } else {
+        telemetry::init_cli_logging();
     }
 
-    // Always show puzzle 15 second
-    if !puzzles_solved.iter().any(|attempt| attempt.puzzle_id == 15) {
-        let puzzle_15 = read_puzzle_by_id(&db_conn, 15)
-            .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
-            .unwrap();
-        return Ok(Json(Puzzle::from(puzzle_15)));
-    }
+    let config = config::Config::load()?;
+    db::configure(config.database.clone());
+    ratings::configure(config.rating_limits.clone());
 
-    // Then show any puzzle up to id 20
-    match read_unsolved_puzzles_from_db(&db_conn, &username.username) {
-        Ok(Some(puzzle)) => Ok(Json(puzzle.into())),
-        Ok(None) => Err(StatusCode::NOT_FOUND),
-        Err(e) => {
-            eprintln!("Error reading puzzles from database: {:?}", e);
-            Err(StatusCode::INTERNAL_SERVER_ERROR)
+    match command {
+        Command::Serve { seed } => serve(config, seed.as_deref()).await,
+        Command::Migrate => {
+            let pending = migrations::pending(&db::open()?)?;
+            storage::init_db_tables()?;
+            tracing::info!("Applied {} migrations", pending);
+            Ok(())
+        }
+        Command::Import {
+            file,
+            format: Some(format),
+            publish,
+        } => {
+            storage::init_db_tables()?;
+            let puzzles = importers::load(&file, format, publish)?;
+            let mut db_conn = db::open()?;
+            let summary = importers::import(&mut db_conn, puzzles)?;
+            audit::record(
+                &db_conn,
+                audit::COMMAND_LINE,
+                "import",
+                serde_json::json!({
+                    "file": file.display().to_string(),
+                    "format": format!("{format:?}"),
+                    "numPuzzles": summary.num_imported,
+                    "numDuplicates": summary.num_duplicates,
+                }),
+            )?;
+            tracing::info!(
+                "Imported {} puzzles from {}, skipping {} that were already imported",
+                summary.num_imported,
+                file.display(),
+                summary.num_duplicates
+            );
+            Ok(())
+        }
+        Command::Import { file, .. } => {
+            storage::init_db_tables()?;
+            let fixture = fixtures::load(&file)?;
+            let mut db_conn = db::open()?;
+            fixtures::import(&mut db_conn, &fixture)?;
+            audit::record(
+                &db_conn,
+                audit::COMMAND_LINE,
+                "import",
+                serde_json::json!({
+                    "file": file.display().to_string(),
+                    "numPuzzles": fixture.puzzles.len(),
+                    "numUsers": fixture.users.len(),
+                }),
+            )?;
+            tracing::info!(
+                "Imported {} puzzles and {} users from {}",
+                fixture.puzzles.len(),
+                fixture.users.len(),
+                file.display()
+            );
+            Ok(())
+        }
+        Command::Export { output } => {
+            storage::init_db_tables()?;
+            let fixture = fixtures::export(&db::open()?)?;
+            let json = serde_json::to_string_pretty(&fixture)?;
+            match &output {
+                Some(path) => std::fs::write(path, json + "\n")
+                    .with_context(|| format!("Failed to write {}", path.display()))?,
+                None => println!("{json}"),
+            }
+            tracing::info!(
+                "Exported {} puzzles and {} users",
+                fixture.puzzles.len(),
+                fixture.users.len()
+            );
+            Ok(())
+        }
+        Command::RecomputeRatings => {
+            storage::init_db_tables()?;
+            let mut db_conn = db::open()?;
+            let num_changed = storage::recompute_user_ratings(&mut db_conn)?;
+            audit::record(
+                &db_conn,
+                audit::COMMAND_LINE,
+                "recompute_ratings",
+                serde_json::json!({"numChanged": num_changed}),
+            )?;
+            tracing::info!("Recomputed ratings, {} users' ratings changed", num_changed);
+            Ok(())
+        }
+        Command::BackfillTargetTimes => {
+            storage::init_db_tables()?;
+            let mut db_conn = db::open()?;
+            let num_changed = storage::backfill_target_times(&mut db_conn)?;
+            audit::record(
+                &db_conn,
+                audit::COMMAND_LINE,
+                "backfill_target_times",
+                serde_json::json!({"numChanged": num_changed}),
+            )?;
+            tracing::info!("Backfilled target times, {} puzzles changed", num_changed);
+            Ok(())
+        }
+        Command::CreateToken { name } => {
+            storage::init_db_tables()?;
+            let db_conn = db::open()?;
+            let token = admin::create_token(&db_conn, &name)?;
+            audit::record(
+                &db_conn,
+                audit::COMMAND_LINE,
+                "create_token",
+                serde_json::json!({"name": name}),
+            )?;
+            println!("{token}");
+            Ok(())
         }
     }
 }
 
-// Get elo rating of a single puzzle
-// Depends on player ratings being manually added to the `users` table
-async fn get_puzzle_rating(Path(id): Path<u32>) -> Result<Json<f64>, StatusCode> {
-    let db_conn = Connection::open("puzzles.db")
-        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)
+async fn serve(config: config::Config, seed: Option<&Path>) -> anyhow::Result<()> {
+    rustls::crypto::ring::default_provider()
+        .install_default()
         .unwrap();
-    let rating = ratings::rating_for_puzzles(&db_conn, id as i64)
-        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
-    Ok(Json(rating.rating))
-}
 
-// Solve puzzle
-#[axum::debug_handler]
-async fn solve_puzzle(
-    Path(id): Path<u32>,
-    Json(payload): Json<PuzzleResponse>,
-) -> Result<(), StatusCode> {
-    if payload.username.is_empty() {
-        return Err(StatusCode::BAD_REQUEST);
+    let server_config = config.server.clone();
+    let metrics = telemetry::install_recorder()?;
+
+    storage::init_db_tables()?;
+    if let Some(seed) = seed {
+        seed_database(seed)?;
     }
-    let db_conn = Connection::open("puzzles.db").map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
-    db_conn
-        .execute(
-            "INSERT INTO puzzle_attempts (puzzle_id, username, solved, solve_time_seconds, solution)
-             VALUES (?1, ?2, ?3, ?4, ?5)",
-            rusqlite::params![
-                id,
-                payload.username,
-                payload.solved,
-                payload.solve_time_seconds,
-                payload.solution.join(" ")
-            ],
-        )
-        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
-    Ok(())
-}
 
-// INSERT INTO puzzles (size, komi, root_tps, defender_start_move, solution, target_time_seconds, player_white, player_black, playtak_game_id)
-// VALUES (6, "2", "2,x,x,2,1,1/2,x,2,2,1,2S/2222221S,x,x,121C,1,x/x,112,11112C,2,21211112S,2/2,22221S,2,1,x,1/2,x,1,1,1,1 2 47", "5e3< d4- 3e3+12 *", 120, "x57696c6c", "EVRNjayhawker", 491458)
+    let mut state = AppState::new(config, metrics.clone())?;
+    if let Some(url) = &state.config.database.postgres_url {
+        state.store = storage::open_postgres(url).await?;
+    }
+    state.error_reporter.install_panic_hook();
+    let background_jobs_started = state.background_jobs_started.clone();
+    let error_reporter = state.error_reporter.clone();
+    let background_state = state.clone();
+    let grpc_state = state.clone();
 
-#[derive(Serialize, Deserialize)]
-struct PuzzleRow {
-    id: u64,
-    root_tps: String,
-    defender_start_move: String,
-    size: usize,
-    komi: String,
-    player_white: String,
-    player_black: String,
-    solution: String,
-    initial_rating: Option<i32>,
-    rating: Option<i32>,
-    target_time_seconds: u32,
-    playtak_game_id: usize,
-}
+    let app = tak_tactics_backend::app(state)?;
 
-#[derive(Serialize, Deserialize)]
-struct PuzzleAttemptRow {
-    puzzle_id: u64,
-    username: String,
-    solved: bool,
-    solve_time_seconds: u32,
-    solution: String,
-    timestamp_seconds: u64,
-}
+    // On shutdown, stop accepting connections and let in-flight requests finish.
+    // `/readyz` starts failing straight away, so that load balancers stop sending traffic here
+    let (shutdown_sender, shutdown_receiver) = tokio::sync::watch::channel(false);
+    tokio::spawn({
+        let background_jobs_started = background_jobs_started.clone();
+        async move {
+            shutdown::signal().await;
+            tracing::info!("Shutting down");
+            background_jobs_started.store(false, Ordering::Release);
+            let _ = shutdown_sender.send(true);
+        }
+    });
 
-fn read_unsolved_puzzles_from_db(
-    db_conn: &Connection,
-    username: &str,
-) -> anyhow::Result<Option<PuzzleRow>> {
-    let mut stmt = db_conn.prepare(
-        "SELECT puzzles.* FROM puzzles
-        LEFT JOIN puzzle_attempts ON puzzles.id = puzzle_attempts.puzzle_id AND puzzle_attempts.username = ?1
-        WHERE puzzles.id < 20 AND puzzle_attempts.puzzle_id IS NULL ORDER BY RANDOM() LIMIT 1",
+    telemetry::spawn_upkeep(metrics);
+    tak_tactics_backend::spawn_background_jobs(background_state)?;
+    background_jobs_started.store(true, Ordering::Release);
+    let grpc = async {
+        match server_config.grpc_address {
+            Some(address) => {
+                let listener = tokio::net::TcpListener::bind(address).await?;
+                grpc::serve(grpc_state, listener, shutdown_receiver.clone()).await
+            }
+            None => Ok(()),
+        }
+    };
+    tokio::try_join!(
+        server::serve(app, &server_config, shutdown_receiver.clone()),
+        grpc
     )?;
-    Ok(stmt
-        .query_and_then([username], from_row::<PuzzleRow>)?
-        .next()
-        .transpose()?)
-}
 
-fn read_puzzle_attempts_for_user(
-    db_conn: &Connection,
-    username: &str,
-) -> anyhow::Result<Vec<PuzzleAttemptRow>> {
-    let mut stmt = db_conn.prepare(
-        "WITH ranked_attempts AS (
-            SELECT *,
-                ROW_NUMBER() OVER (
-                    PARTITION BY username, puzzle_id
-                    ORDER BY timestamp_seconds ASC
-                ) AS rn
-            FROM puzzle_attempts WHERE username = ?1
-        )
-        SELECT *
-        FROM ranked_attempts
-        WHERE rn = 1;
-        ",
-    )?;
-    let rows = stmt.query_and_then([username], from_row::<PuzzleAttemptRow>)?;
-    Ok(rows.collect::<Result<Vec<_>, _>>()?)
+    error_reporter.flush().await;
+    if let Err(e) = db::close() {
+        tracing::error!("Error closing database: {:?}", e);
+    }
+    tracing::info!("Shut down");
+    Ok(())
 }
 
-fn read_puzzle_by_id(db_conn: &Connection, id: u32) -> anyhow::Result<Option<PuzzleRow>> {
-    let mut stmt = db_conn.prepare("SELECT * FROM puzzles WHERE id = ?1")?;
-    Ok(stmt
-        .query_and_then([id], from_row::<PuzzleRow>)?
-        .next()
-        .transpose()?)
+fn seed_database(path: &Path) -> anyhow::Result<()> {
+    let fixture = fixtures::load(path)?;
+    if fixtures::seed(&mut db::open()?, &fixture)? {
+        tracing::info!(
+            "Loaded {} puzzles and {} users from {}",
+            fixture.puzzles.len(),
+            fixture.users.len(),
+            path.display()
+        );
+    } else {
+        tracing::warn!(
+            "Not loading {}, since the database already has puzzles or users",
+            path.display()
+        );
+    }
+    Ok(())
 }
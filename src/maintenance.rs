@@ -0,0 +1,36 @@
+use rusqlite::Connection;
+
+use crate::{db, telemetry};
+
+// Keeps SQLite fast as tables like `puzzle_attempts` grow. Both are jobs in `scheduler.rs`.
+// Each step is timed in `db_maintenance_duration_seconds`
+
+// The `database-maintenance` job. `ANALYZE` updates the statistics the query planner picks indexes with,
+// and `PRAGMA optimize` does whatever else SQLite thinks is worth doing
+pub async fn run_maintenance() -> anyhow::Result<()> {
+    let db_conn = db::open()?;
+    telemetry::time_maintenance_step("analyze", || db_conn.execute_batch("ANALYZE"))?;
+    telemetry::time_maintenance_step("optimize", || db_conn.execute_batch("PRAGMA optimize"))?;
+    Ok(())
+}
+
+// The `wal-checkpoint` job. SQLite checkpoints on its own once the WAL reaches 1000 pages,
+// but only when a write happens to cross that, so the WAL can stay big for a long time between bursts.
+// A passive checkpoint copies what it can without waiting for readers or blocking writers
+pub async fn run_checkpoint() -> anyhow::Result<()> {
+    let db_conn = db::open()?;
+    let (wal_pages, checkpointed_pages) =
+        telemetry::time_maintenance_step("wal_checkpoint", || checkpoint(&db_conn))?;
+    // Both are -1 if the database isn't in WAL mode
+    if wal_pages >= 0 {
+        metrics::gauge!("db_wal_pages").set(wal_pages as f64);
+        metrics::gauge!("db_wal_checkpointed_pages").set(checkpointed_pages as f64);
+    }
+    Ok(())
+}
+
+fn checkpoint(db_conn: &Connection) -> rusqlite::Result<(i64, i64)> {
+    db_conn.query_row("PRAGMA wal_checkpoint(PASSIVE)", [], |row| {
+        Ok((row.get(1)?, row.get(2)?))
+    })
+}
@@ -0,0 +1,239 @@
+use rusqlite::Connection;
+
+// Changes to tables that already exist in deployed databases, in the order they were made.
+// `PRAGMA user_version` is the number of migrations that have been applied.
+// Never edit or reorder a migration once it has been released, only append new ones
+const MIGRATIONS: &[&str] = &[
+    // Number each user's attempts at a puzzle, see `attempts.rs`
+    "ALTER TABLE puzzle_attempts ADD COLUMN attempt_number INTEGER NOT NULL DEFAULT 1;
+    UPDATE puzzle_attempts SET attempt_number = (
+        SELECT COUNT(*) FROM puzzle_attempts AS earlier
+        WHERE earlier.username = puzzle_attempts.username
+            AND earlier.puzzle_id = puzzle_attempts.puzzle_id
+            AND (earlier.timestamp_seconds < puzzle_attempts.timestamp_seconds
+                OR (earlier.timestamp_seconds = puzzle_attempts.timestamp_seconds
+                    AND earlier.rowid <= puzzle_attempts.rowid))
+    );
+    CREATE UNIQUE INDEX IF NOT EXISTS puzzle_attempts_by_user
+        ON puzzle_attempts (username, puzzle_id, attempt_number);
+    CREATE VIEW rated_attempts AS SELECT * FROM puzzle_attempts WHERE attempt_number = 1;
+    ALTER TABLE idempotency_keys ADD COLUMN attempt_number INTEGER NOT NULL DEFAULT 1;",
+    // Unrated practice attempts
+    "ALTER TABLE puzzle_attempts ADD COLUMN practice INTEGER NOT NULL DEFAULT 0;
+    DROP VIEW rated_attempts;
+    CREATE VIEW rated_attempts AS SELECT * FROM puzzle_attempts
+        WHERE attempt_number = 1 AND practice = 0;",
+    // Full Glicko-2 ratings for users, which are now updated after every rated attempt.
+    // Also keep the whole response to a keyed attempt, so retries get the same rating change
+    "ALTER TABLE users ADD COLUMN deviation REAL NOT NULL DEFAULT 350;
+    ALTER TABLE users ADD COLUMN volatility REAL NOT NULL DEFAULT 0.06;
+    ALTER TABLE idempotency_keys ADD COLUMN response TEXT;",
+    // Only published puzzles are served. This used to be hardcoded as the puzzles with id below 20
+    "ALTER TABLE puzzles ADD COLUMN published INTEGER NOT NULL DEFAULT 0;
+    UPDATE puzzles SET published = 1 WHERE id < 20;",
+    // Indexes for reading a puzzle's rated attempts, and a user's attempts by time.
+    // A user's attempts at a single puzzle are already covered by `puzzle_attempts_by_user`.
+    // See `benches/attempt_queries.rs`
+    "CREATE INDEX IF NOT EXISTS puzzle_attempts_by_puzzle
+        ON puzzle_attempts (puzzle_id, attempt_number);
+    CREATE INDEX IF NOT EXISTS puzzle_attempts_by_user_and_time
+        ON puzzle_attempts (username, timestamp_seconds);",
+    // Give attempts a stable id, for `GET /attempts/{id}/replay`. Rowids may change on `VACUUM` without one.
+    // SQLite can't add a primary key to a table, so the table is rebuilt, keeping the old rowids as ids
+    "CREATE TABLE puzzle_attempts_with_ids (
+        id INTEGER PRIMARY KEY AUTOINCREMENT,
+        puzzle_id INTEGER NOT NULL,
+        username TEXT NOT NULL,
+        solved INTEGER NOT NULL,
+        solve_time_seconds INTEGER NOT NULL,
+        solution TEXT NOT NULL,
+        timestamp_seconds INTEGER NOT NULL DEFAULT (strftime('%s', 'now')),
+        attempt_number INTEGER NOT NULL DEFAULT 1,
+        practice INTEGER NOT NULL DEFAULT 0,
+        FOREIGN KEY (puzzle_id) REFERENCES puzzles(id)
+    );
+    INSERT INTO puzzle_attempts_with_ids (id, puzzle_id, username, solved, solve_time_seconds, solution,
+        timestamp_seconds, attempt_number, practice)
+    SELECT rowid, puzzle_id, username, solved, solve_time_seconds, solution,
+        timestamp_seconds, attempt_number, practice
+    FROM puzzle_attempts;
+    DROP VIEW rated_attempts;
+    DROP TABLE puzzle_attempts;
+    ALTER TABLE puzzle_attempts_with_ids RENAME TO puzzle_attempts;
+    CREATE UNIQUE INDEX puzzle_attempts_by_user
+        ON puzzle_attempts (username, puzzle_id, attempt_number);
+    CREATE INDEX puzzle_attempts_by_puzzle
+        ON puzzle_attempts (puzzle_id, attempt_number);
+    CREATE INDEX puzzle_attempts_by_user_and_time
+        ON puzzle_attempts (username, timestamp_seconds);
+    CREATE VIEW rated_attempts AS SELECT * FROM puzzle_attempts
+        WHERE attempt_number = 1 AND practice = 0;",
+    // Replace the hardcoded list of users left out of puzzle ratings with a flag
+    "ALTER TABLE users ADD COLUMN excluded_from_ratings INTEGER NOT NULL DEFAULT 0;
+    UPDATE users SET excluded_from_ratings = 1 WHERE username IN ('Morten', 'Mort2');",
+    // Usernames are case-insensitive, see `validation::canonical_username`. Every username is lowercased,
+    // and the attempts of users who only differ in case are merged, numbered again by time.
+    // Attempt numbers are negated first, so that they don't collide with the ones that don't change.
+    // Where both users had a row for the same thing, like an achievement, one of them is kept.
+    // Ratings are left as they were, run `recompute-ratings` afterwards if any users were merged
+    "ALTER TABLE users ADD COLUMN display_name TEXT;
+    UPDATE users SET display_name = username;
+    UPDATE users SET excluded_from_ratings = 1 WHERE lower(username) IN (
+        SELECT lower(username) FROM users WHERE excluded_from_ratings = 1
+    );
+    CREATE TEMP TABLE renumbered_attempts AS
+    SELECT old_username, puzzle_id, old_number, new_username, new_number FROM (
+        SELECT username AS old_username, puzzle_id, attempt_number AS old_number, lower(username) AS new_username,
+            ROW_NUMBER() OVER (PARTITION BY lower(username), puzzle_id ORDER BY timestamp_seconds, id) AS new_number
+        FROM puzzle_attempts
+    )
+    WHERE old_username != new_username OR old_number != new_number;
+    UPDATE puzzle_attempts SET username = renumbered.new_username, attempt_number = -renumbered.new_number
+    FROM renumbered_attempts AS renumbered
+    WHERE puzzle_attempts.username = renumbered.old_username AND puzzle_attempts.puzzle_id = renumbered.puzzle_id
+        AND puzzle_attempts.attempt_number = renumbered.old_number;
+    UPDATE attempt_moves SET username = renumbered.new_username, attempt_number = -renumbered.new_number
+    FROM renumbered_attempts AS renumbered
+    WHERE attempt_moves.username = renumbered.old_username AND attempt_moves.puzzle_id = renumbered.puzzle_id
+        AND attempt_moves.attempt_number = renumbered.old_number;
+    UPDATE attempt_variants SET username = renumbered.new_username, attempt_number = -renumbered.new_number
+    FROM renumbered_attempts AS renumbered
+    WHERE attempt_variants.username = renumbered.old_username AND attempt_variants.puzzle_id = renumbered.puzzle_id
+        AND attempt_variants.attempt_number = renumbered.old_number;
+    UPDATE OR IGNORE idempotency_keys SET username = renumbered.new_username, attempt_number = -renumbered.new_number
+    FROM renumbered_attempts AS renumbered
+    WHERE idempotency_keys.username = renumbered.old_username AND idempotency_keys.puzzle_id = renumbered.puzzle_id
+        AND idempotency_keys.attempt_number = renumbered.old_number;
+    UPDATE puzzle_attempts SET attempt_number = -attempt_number WHERE attempt_number < 0;
+    UPDATE attempt_moves SET attempt_number = -attempt_number WHERE attempt_number < 0;
+    UPDATE attempt_variants SET attempt_number = -attempt_number WHERE attempt_number < 0;
+    UPDATE idempotency_keys SET attempt_number = -attempt_number WHERE attempt_number < 0;
+    DROP TABLE renumbered_attempts;
+    UPDATE OR IGNORE users SET username = lower(username) WHERE username != lower(username);
+    DELETE FROM users WHERE username != lower(username);
+    UPDATE OR IGNORE user_achievements SET username = lower(username) WHERE username != lower(username);
+    DELETE FROM user_achievements WHERE username != lower(username);
+    UPDATE OR IGNORE banned_users SET username = lower(username) WHERE username != lower(username);
+    DELETE FROM banned_users WHERE username != lower(username);
+    UPDATE OR IGNORE campaign_unlocks SET username = lower(username) WHERE username != lower(username);
+    DELETE FROM campaign_unlocks WHERE username != lower(username);
+    UPDATE collections SET owner = lower(owner) WHERE owner != lower(owner);
+    UPDATE OR IGNORE follows SET follower = lower(follower) WHERE follower != lower(follower);
+    DELETE FROM follows WHERE follower != lower(follower);
+    UPDATE OR IGNORE follows SET followee = lower(followee) WHERE followee != lower(followee);
+    DELETE FROM follows WHERE followee != lower(followee);
+    UPDATE OR IGNORE attempts_in_progress SET username = lower(username) WHERE username != lower(username);
+    DELETE FROM attempts_in_progress WHERE username != lower(username);
+    UPDATE leaderboard_snapshot_entries SET username = lower(username) WHERE username != lower(username);
+    UPDATE OR IGNORE race_players SET username = lower(username) WHERE username != lower(username);
+    DELETE FROM race_players WHERE username != lower(username);
+    UPDATE races SET winner = lower(winner) WHERE winner != lower(winner);
+    UPDATE user_rating_history SET username = lower(username) WHERE username != lower(username);
+    UPDATE rating_clamps SET username = lower(username) WHERE username != lower(username);
+    UPDATE puzzle_reports SET username = lower(username) WHERE username != lower(username);
+    UPDATE OR IGNORE season_ratings SET username = lower(username) WHERE username != lower(username);
+    DELETE FROM season_ratings WHERE username != lower(username);
+    UPDATE OR IGNORE team_members SET username = lower(username) WHERE username != lower(username);
+    DELETE FROM team_members WHERE username != lower(username);
+    UPDATE OR IGNORE tournament_participants SET username = lower(username) WHERE username != lower(username);
+    DELETE FROM tournament_participants WHERE username != lower(username);
+    UPDATE tournament_tokens SET username = lower(username) WHERE username != lower(username);
+    UPDATE OR IGNORE tournament_results SET username = lower(username) WHERE username != lower(username);
+    DELETE FROM tournament_results WHERE username != lower(username);
+    UPDATE OR IGNORE tournament_standings SET username = lower(username) WHERE username != lower(username);
+    DELETE FROM tournament_standings WHERE username != lower(username);
+    UPDATE OR IGNORE idempotency_keys SET username = lower(username) WHERE username != lower(username);
+    DELETE FROM idempotency_keys WHERE username != lower(username);
+    DELETE FROM follows WHERE follower = followee;",
+    // Give users a numeric id, and have attempts refer to it instead of the username, see `users.rs`.
+    // Every attempt now needs a user, so users who only had unrated attempts are added with the default rating.
+    // SQLite can't change a table's primary key, so both tables are rebuilt, keeping the attempts' ids
+    "CREATE TABLE users_with_ids (
+        id INTEGER PRIMARY KEY AUTOINCREMENT,
+        username TEXT NOT NULL UNIQUE,
+        display_name TEXT,
+        rating REAL NOT NULL,
+        deviation REAL NOT NULL DEFAULT 350,
+        volatility REAL NOT NULL DEFAULT 0.06,
+        excluded_from_ratings INTEGER NOT NULL DEFAULT 0
+    );
+    INSERT INTO users_with_ids (username, display_name, rating, deviation, volatility, excluded_from_ratings)
+    SELECT username, display_name, rating, deviation, volatility, excluded_from_ratings FROM users
+    ORDER BY username;
+    INSERT INTO users_with_ids (username, display_name, rating)
+    SELECT DISTINCT username, username, 1500 FROM puzzle_attempts
+    WHERE username NOT IN (SELECT username FROM users_with_ids)
+    ORDER BY username;
+    DROP TABLE users;
+    ALTER TABLE users_with_ids RENAME TO users;
+    CREATE TABLE puzzle_attempts_with_user_ids (
+        id INTEGER PRIMARY KEY AUTOINCREMENT,
+        puzzle_id INTEGER NOT NULL,
+        user_id INTEGER NOT NULL,
+        solved INTEGER NOT NULL,
+        solve_time_seconds INTEGER NOT NULL,
+        solution TEXT NOT NULL,
+        timestamp_seconds INTEGER NOT NULL DEFAULT (strftime('%s', 'now')),
+        attempt_number INTEGER NOT NULL DEFAULT 1,
+        practice INTEGER NOT NULL DEFAULT 0,
+        FOREIGN KEY (puzzle_id) REFERENCES puzzles(id),
+        FOREIGN KEY (user_id) REFERENCES users(id)
+    );
+    INSERT INTO puzzle_attempts_with_user_ids (id, puzzle_id, user_id, solved, solve_time_seconds, solution,
+        timestamp_seconds, attempt_number, practice)
+    SELECT puzzle_attempts.id, puzzle_id, users.id, solved, solve_time_seconds, solution,
+        timestamp_seconds, attempt_number, practice
+    FROM puzzle_attempts JOIN users ON users.username = puzzle_attempts.username;
+    DROP VIEW rated_attempts;
+    DROP TABLE puzzle_attempts;
+    ALTER TABLE puzzle_attempts_with_user_ids RENAME TO puzzle_attempts;
+    CREATE UNIQUE INDEX puzzle_attempts_by_user
+        ON puzzle_attempts (user_id, puzzle_id, attempt_number);
+    CREATE INDEX puzzle_attempts_by_puzzle
+        ON puzzle_attempts (puzzle_id, attempt_number);
+    CREATE INDEX puzzle_attempts_by_user_and_time
+        ON puzzle_attempts (user_id, timestamp_seconds);
+    CREATE VIEW rated_attempts AS SELECT puzzle_attempts.*, users.username FROM puzzle_attempts
+        JOIN users ON users.id = puzzle_attempts.user_id
+        WHERE attempt_number = 1 AND practice = 0;",
+    // When a user's personal data was deleted, see `users::delete_user`
+    "ALTER TABLE users ADD COLUMN deleted_seconds INTEGER;",
+    // When puzzles were published, for `GET /puzzles/feed.atom`. Unknown for puzzles published before this,
+    // so they're dated to when the migration ran
+    "ALTER TABLE puzzles ADD COLUMN published_seconds INTEGER;
+    UPDATE puzzles SET published_seconds = strftime('%s', 'now') WHERE published = 1;",
+    // Index the puzzles that were added before `GET /puzzles/search`, see `search.rs`
+    "INSERT INTO puzzle_search (puzzle_search) VALUES ('rebuild');",
+    // Where anonymous attempts were made from, and first attempts that aren't rated because of it, see `devices.rs`
+    "ALTER TABLE puzzle_attempts ADD COLUMN device_hash TEXT;
+    ALTER TABLE puzzle_attempts ADD COLUMN ip_hash TEXT;
+    ALTER TABLE puzzle_attempts ADD COLUMN shared_device INTEGER NOT NULL DEFAULT 0;
+    DROP VIEW rated_attempts;
+    CREATE VIEW rated_attempts AS SELECT puzzle_attempts.*, users.username FROM puzzle_attempts
+        JOIN users ON users.id = puzzle_attempts.user_id
+        WHERE attempt_number = 1 AND practice = 0 AND shared_device = 0;",
+    // Retries of failed rated attempts, see `retries.rs`
+    "ALTER TABLE puzzle_attempts ADD COLUMN retry_of INTEGER;
+    ALTER TABLE puzzle_attempts ADD COLUMN solved_on_retry INTEGER NOT NULL DEFAULT 0;",
+    // What the rules say about each attempt's line, see `attempts::judge`
+    "ALTER TABLE puzzle_attempts ADD COLUMN verdict TEXT;
+    ALTER TABLE puzzle_attempts ADD COLUMN illegal_move_index INTEGER;",
+];
+
+// Apply all migrations that haven't run yet. Each one runs in its own transaction
+pub fn run(db_conn: &mut Connection) -> anyhow::Result<()> {
+    let version: usize = db_conn.query_row("PRAGMA user_version", [], |row| row.get(0))?;
+    for (i, migration) in MIGRATIONS.iter().enumerate().skip(version) {
+        let transaction = db_conn.transaction()?;
+        transaction.execute_batch(migration)?;
+        transaction.pragma_update(None, "user_version", i + 1)?;
+        transaction.commit()?;
+    }
+    Ok(())
+}
+
+// The number of migrations that haven't run yet
+pub fn pending(db_conn: &Connection) -> anyhow::Result<usize> {
+    let version: usize = db_conn.query_row("PRAGMA user_version", [], |row| row.get(0))?;
+    Ok(MIGRATIONS.len().saturating_sub(version))
+}
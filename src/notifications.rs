@@ -0,0 +1,200 @@
+use axum::{
+    Json,
+    extract::{Path, Query},
+    http::StatusCode,
+};
+use rusqlite::Connection;
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use crate::{
+    db,
+    pagination::{Page, PageQuery},
+    validation::{self, ApiError},
+};
+
+// Each user's inbox of things that happened while they were away. Notifications are added
+// by the code that makes them happen, in the same transaction, and kept until the user is deleted
+
+pub fn init_db_tables(db_conn: &Connection) -> anyhow::Result<()> {
+    // `data` is a JSON object, depending on `kind`
+    db_conn.execute(
+        "CREATE TABLE IF NOT EXISTS notifications (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            username TEXT NOT NULL,
+            kind TEXT NOT NULL,
+            data TEXT NOT NULL,
+            created_seconds INTEGER NOT NULL DEFAULT (strftime('%s', 'now')),
+            read_seconds INTEGER
+        )",
+        [],
+    )?;
+    db_conn.execute(
+        "CREATE INDEX IF NOT EXISTS notifications_by_user ON notifications (username, id)",
+        [],
+    )?;
+
+    Ok(())
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub enum NotificationKind {
+    // An admin resolved a report the user filed. `data` is the report
+    ReportResolved,
+    // A puzzle was published on one of the user's preferred sizes. `data` is the puzzle.
+    // Users without preferred sizes aren't notified, since that would be everyone
+    NewPuzzle,
+}
+
+impl NotificationKind {
+    fn name(self) -> String {
+        serde_json::to_value(self)
+            .ok()
+            .and_then(|value| value.as_str().map(String::from))
+            .unwrap_or_default()
+    }
+}
+
+#[derive(Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct Notification {
+    id: i64,
+    kind: NotificationKind,
+    #[schema(value_type = Object)]
+    data: serde_json::Value,
+    created_seconds: u64,
+    // Not set until the user has read it
+    read_seconds: Option<u64>,
+}
+
+pub fn notify(
+    db_conn: &Connection,
+    username: &str,
+    kind: NotificationKind,
+    data: &impl Serialize,
+) -> anyhow::Result<()> {
+    db_conn.execute(
+        "INSERT INTO notifications (username, kind, data) VALUES (?1, ?2, ?3)",
+        rusqlite::params![username, kind.name(), serde_json::to_string(data)?],
+    )?;
+    Ok(())
+}
+
+// Notify every user who prefers puzzles on this size
+pub fn notify_new_puzzle(
+    db_conn: &Connection,
+    size: usize,
+    puzzle: &impl Serialize,
+) -> anyhow::Result<()> {
+    db_conn.execute(
+        "INSERT INTO notifications (username, kind, data)
+        SELECT username, ?1, ?2 FROM user_settings
+        WHERE ' ' || preferred_sizes || ' ' LIKE '% ' || ?3 || ' %'",
+        rusqlite::params![
+            NotificationKind::NewPuzzle.name(),
+            serde_json::to_string(puzzle)?,
+            size
+        ],
+    )?;
+    Ok(())
+}
+
+// Get the user's notifications, newest first
+#[utoipa::path(
+    get,
+    path = "/users/{username}/notifications",
+    tag = "notifications",
+    params(("username" = String, Path), PageQuery),
+    responses(
+        (status = 200, body = Page<Notification>),
+        (status = 400, body = validation::ValidationError),
+    ),
+)]
+pub async fn get_notifications(
+    Path(username): Path<String>,
+    Query(page): Query<PageQuery>,
+) -> Result<Json<Page<Notification>>, ApiError> {
+    validation::validate_username(&username)?;
+    let username = validation::canonical_username(&username);
+    let db_conn = db::open().map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let page = page.fetch(
+        |after, limit| read_notifications(&db_conn, &username, after, limit),
+        |notification| notification.id,
+    )?;
+    Ok(Json(page))
+}
+
+fn read_notifications(
+    db_conn: &Connection,
+    username: &str,
+    after: Option<i64>,
+    limit: u32,
+) -> anyhow::Result<Vec<Notification>> {
+    let mut stmt = db_conn.prepare(
+        "SELECT id, kind, data, created_seconds, read_seconds FROM notifications
+        WHERE username = ?1 AND (?2 IS NULL OR id < ?2)
+        ORDER BY id DESC
+        LIMIT ?3",
+    )?;
+    let rows = stmt.query_and_then(rusqlite::params![username, after, limit], |row| {
+        Ok(Notification {
+            id: row.get(0)?,
+            kind: serde_json::from_value(serde_json::Value::String(row.get(1)?))?,
+            data: serde_json::from_str(&row.get::<_, String>(2)?)?,
+            created_seconds: row.get(3)?,
+            read_seconds: row.get(4)?,
+        })
+    })?;
+    rows.collect()
+}
+
+#[derive(Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct MarkRead {
+    // Every unread notification if not set
+    ids: Option<Vec<i64>>,
+}
+
+#[derive(Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct MarkedRead {
+    // Notifications that were unread until now
+    num_marked: usize,
+}
+
+// Mark some or all of the user's notifications as read. Ids of other users' notifications are ignored
+#[utoipa::path(
+    post,
+    path = "/users/{username}/notifications/read",
+    tag = "notifications",
+    params(("username" = String, Path)),
+    request_body = MarkRead,
+    responses(
+        (status = 200, body = MarkedRead),
+        (status = 400, body = validation::ValidationError),
+    ),
+)]
+pub async fn mark_read(
+    Path(username): Path<String>,
+    Json(payload): Json<MarkRead>,
+) -> Result<Json<MarkedRead>, ApiError> {
+    validation::validate_username(&username)?;
+    let username = validation::canonical_username(&username);
+    let ids = payload
+        .ids
+        .map(|ids| serde_json::to_string(&ids).unwrap_or_default());
+    let db_conn = db::open().map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let num_marked = db_conn
+        .execute(
+            "UPDATE notifications SET read_seconds = strftime('%s', 'now')
+            WHERE username = ?1 AND read_seconds IS NULL
+                AND (?2 IS NULL OR id IN (SELECT value FROM json_each(?2)))",
+            rusqlite::params![username, ids],
+        )
+        .map_err(|e| {
+            tracing::error!("Error marking notifications as read: {:?}", e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+    Ok(Json(MarkedRead { num_marked }))
+}
@@ -0,0 +1,188 @@
+use axum::{Json, response::Html};
+use utoipa::{
+    Modify, OpenApi,
+    openapi::security::{HttpAuthScheme, HttpBuilder, SecurityScheme},
+};
+
+use crate::{
+    achievements, admin, attempts, audit, auth, backups, bans, campaign, collections, daily,
+    dashboard, events, experiments, explanations, exports, feed, friends, graphql, in_progress,
+    leaderboard, live, notifications, progress, puzzle_packs, puzzle_sets, races, rating_history,
+    read_only, reports, scheduler, search, seasons, sessions, settings, stats, svg, teams, themes,
+    tournaments, users, webhooks,
+};
+
+// The spec is generated from the `#[utoipa::path]` annotations on each handler.
+// New handlers have to be added to `paths` here as well as to `routes.rs`
+#[derive(OpenApi)]
+#[openapi(
+    info(title = "Tak Tactics API"),
+    servers((url = "/v1")),
+    modifiers(&AdminToken, &SessionToken),
+    paths(
+        crate::get_puzzle,
+        crate::get_puzzle_by_id,
+        feed::get_feed,
+        puzzle_packs::export_puzzles,
+        search::search_puzzles,
+        svg::get_puzzle_image,
+        svg::get_solution_animation,
+        themes::get_themes,
+        themes::get_similar_puzzles,
+        explanations::get_explanation,
+        crate::get_puzzle_rating,
+        crate::get_puzzle_ratings,
+        crate::solve_puzzle,
+        in_progress::get_current_attempt,
+        in_progress::add_move,
+        puzzle_sets::get_puzzle_sets,
+        puzzle_sets::get_next_puzzle_in_set,
+        puzzle_sets::get_puzzle_set_progress,
+        collections::create_collection,
+        collections::get_collection,
+        collections::get_collections_for_user,
+        collections::add_puzzle_to_collection,
+        collections::remove_puzzle_from_collection,
+        collections::get_next_puzzle_in_collection,
+        campaign::get_campaign,
+        campaign::get_next_puzzle_in_chapter,
+        achievements::get_user_achievements,
+        progress::get_user_progress,
+        progress::get_puzzle_progress,
+        attempts::get_attempt_history,
+        attempts::get_move_times,
+        attempts::get_solve_times,
+        attempts::get_attempt_replay,
+        rating_history::get_user_rating_history,
+        rating_history::get_puzzle_rating_history,
+        daily::get_daily_puzzle,
+        daily::get_embedded_daily_puzzle,
+        daily::get_embedded_puzzle_for_day,
+        graphql::graphql,
+        graphql::graphiql,
+        leaderboard::get_leaderboard,
+        stats::get_stats,
+        settings::get_settings,
+        settings::set_settings,
+        notifications::get_notifications,
+        notifications::mark_read,
+        auth::send_magic_link,
+        auth::verify_magic_link,
+        sessions::refresh,
+        sessions::logout,
+        sessions::get_current_user,
+        sessions::get_sessions,
+        sessions::delete_session,
+        sessions::revoke_user_sessions,
+        leaderboard::get_snapshots,
+        leaderboard::get_snapshot,
+        seasons::get_seasons,
+        seasons::get_standings,
+        seasons::get_user_ratings,
+        friends::follow_user,
+        friends::unfollow_user,
+        friends::get_following,
+        friends::get_following_leaderboard,
+        friends::get_following_daily,
+        races::race_socket,
+        live::live_socket,
+        events::get_events,
+        tournaments::create_tournament,
+        tournaments::get_tournaments,
+        tournaments::get_tournament,
+        tournaments::register_for_tournament,
+        tournaments::get_next_tournament_puzzle,
+        tournaments::submit_tournament_attempt,
+        tournaments::get_tournament_standings,
+        teams::get_team_leaderboard,
+        teams::create_team,
+        teams::get_weekly_competition,
+        teams::get_team,
+        teams::join_team,
+        teams::leave_team,
+        admin::set_rating_exclusion,
+        bans::ban_user,
+        bans::unban_user,
+        bans::get_bans,
+        users::rename_user,
+        users::merge_users,
+        users::delete_user,
+        users::export_user,
+        rating_history::get_rating_clamps,
+        dashboard::get_dashboard,
+        exports::export_attempts,
+        exports::export_attempts_ptn,
+        reports::create_report,
+        reports::get_reports,
+        reports::resolve_report,
+        themes::set_themes,
+        explanations::set_explanation,
+        experiments::get_experiments,
+        experiments::get_experiment,
+        experiments::start_experiment,
+        experiments::stop_experiment,
+        audit::get_audit_log,
+        scheduler::get_jobs,
+        scheduler::run_job,
+        backups::create_backup,
+        read_only::get_read_only,
+        read_only::set_read_only,
+        webhooks::create_webhook,
+        webhooks::get_webhooks,
+        webhooks::delete_webhook,
+    )
+)]
+pub struct ApiDoc;
+
+// Admin endpoints take the `ADMIN_TOKEN` environment variable or a token from `create-token`, see `admin.rs`
+struct AdminToken;
+
+impl Modify for AdminToken {
+    fn modify(&self, openapi: &mut utoipa::openapi::OpenApi) {
+        let components = openapi.components.get_or_insert_with(Default::default);
+        components.add_security_scheme(
+            "admin_token",
+            SecurityScheme::Http(HttpBuilder::new().scheme(HttpAuthScheme::Bearer).build()),
+        );
+    }
+}
+
+// Endpoints for logged in users take an access token from `/auth/magic-link/verify` or `/auth/refresh`, see `sessions.rs`
+struct SessionToken;
+
+impl Modify for SessionToken {
+    fn modify(&self, openapi: &mut utoipa::openapi::OpenApi) {
+        let components = openapi.components.get_or_insert_with(Default::default);
+        components.add_security_scheme(
+            "session_token",
+            SecurityScheme::Http(HttpBuilder::new().scheme(HttpAuthScheme::Bearer).build()),
+        );
+    }
+}
+
+pub async fn get_openapi_spec() -> Json<utoipa::openapi::OpenApi> {
+    Json(ApiDoc::openapi())
+}
+
+// Swagger UI, loaded from a CDN so that it doesn't have to be bundled with the server
+pub async fn get_swagger_ui() -> Html<&'static str> {
+    Html(
+        r##"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta charset="utf-8">
+    <title>Tak Tactics API</title>
+    <link rel="stylesheet" href="https://unpkg.com/swagger-ui-dist@5/swagger-ui.css">
+</head>
+<body>
+    <div id="swagger-ui"></div>
+    <script src="https://unpkg.com/swagger-ui-dist@5/swagger-ui-bundle.js"></script>
+    <script>
+        window.onload = () => {
+            window.ui = SwaggerUIBundle({ url: "/openapi.json", dom_id: "#swagger-ui" });
+        };
+    </script>
+</body>
+</html>"##,
+    )
+}
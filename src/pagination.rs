@@ -0,0 +1,89 @@
+use axum::http::StatusCode;
+use serde::{Deserialize, Serialize, de::DeserializeOwned};
+
+use crate::validation::{ApiError, ValidationError};
+use utoipa::{IntoParams, ToSchema};
+
+pub const DEFAULT_LIMIT: u32 = 50;
+pub const MAX_LIMIT: u32 = 200;
+
+// Query parameters for paginated list endpoints.
+// `cursor` is the `nextCursor` of the previous page, and should be treated as opaque by clients
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct PageQuery {
+    pub cursor: Option<String>,
+    pub limit: Option<u32>,
+}
+
+#[derive(Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct Page<T> {
+    pub items: Vec<T>,
+    // Not set on the last page
+    pub next_cursor: Option<String>,
+}
+
+// Keyset pagination: the cursor holds the sort key of the last item on the previous page,
+// and the next page is every item after it. Unlike offsets, this stays stable when items are inserted.
+// `K` must be the full sort key of the query, including a unique tiebreaker, so that no two items share one
+impl PageQuery {
+    pub fn limit(&self) -> Result<u32, ValidationError> {
+        match self.limit {
+            None => Ok(DEFAULT_LIMIT),
+            Some(limit) if (1..=MAX_LIMIT).contains(&limit) => Ok(limit),
+            Some(_) => Err(ValidationError::new(
+                "limit",
+                format!("Limit must be between 1 and {MAX_LIMIT}"),
+            )),
+        }
+    }
+
+    pub fn after<K: DeserializeOwned>(&self) -> Result<Option<K>, ValidationError> {
+        let Some(cursor) = &self.cursor else {
+            return Ok(None);
+        };
+        decode_hex(cursor)
+            .and_then(|bytes| serde_json::from_slice(&bytes).ok())
+            .map(Some)
+            .ok_or_else(|| ValidationError::new("cursor", "Invalid cursor"))
+    }
+
+    // Run a query for one page. `fetch` is given the sort key to start after and the number of rows to read,
+    // which is one more than the limit so that we know whether there is another page
+    pub fn fetch<T, K: Serialize + DeserializeOwned>(
+        &self,
+        fetch: impl FnOnce(Option<K>, u32) -> anyhow::Result<Vec<T>>,
+        sort_key: impl Fn(&T) -> K,
+    ) -> Result<Page<T>, ApiError> {
+        let limit = self.limit()?;
+        let after = self.after()?;
+        let mut items = fetch(after, limit + 1).map_err(|e| {
+            tracing::error!("Error reading page from database: {:?}", e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+        let next_cursor = if items.len() > limit as usize {
+            items.truncate(limit as usize);
+            items
+                .last()
+                .map(|item| encode_hex(&serde_json::to_vec(&sort_key(item)).unwrap()))
+        } else {
+            None
+        };
+        Ok(Page { items, next_cursor })
+    }
+}
+
+fn encode_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+fn decode_hex(s: &str) -> Option<Vec<u8>> {
+    if !s.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..s.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(s.get(i..i + 2)?, 16).ok())
+        .collect()
+}
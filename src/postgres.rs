@@ -0,0 +1,700 @@
+use std::collections::BTreeMap;
+
+use anyhow::Context;
+use async_trait::async_trait;
+use skillratings::{Outcomes, glicko2::Glicko2Rating};
+use tokio::sync::Mutex;
+use tokio_postgres::{Client, GenericClient, NoTls, Row};
+
+use crate::{
+    PuzzleRow, attempts, idempotency, now_seconds,
+    ratings::{self, RatingChange, RatingRow},
+    retries::{RetryResult, Retryable},
+    storage::{AttemptOutcome, PuzzleAttemptRow, PuzzleFilter, PuzzleStore, RecordedAttempt},
+    telemetry,
+};
+
+// A `PuzzleStore` on Postgres, for running several instances of the server against one database.
+// Only the puzzle, attempt and rating tables live here. Everything else, like achievements, unlocks, seasons,
+// rating history and live feeds, is still worked out from the SQLite tables in the other modules,
+// so it isn't updated for attempts stored here. Used instead of `SqliteStore` when `database.postgres_url` is set
+
+// The tables match the SQLite ones after every migration in `migrations.rs`, with Postgres types.
+// A migration that changes one of these tables has to change it here too
+const SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS puzzles (
+        id BIGSERIAL PRIMARY KEY,
+        root_tps TEXT NOT NULL,
+        defender_start_move TEXT NOT NULL,
+        size BIGINT NOT NULL,
+        komi TEXT NOT NULL,
+        player_white TEXT NOT NULL,
+        player_black TEXT NOT NULL,
+        solution TEXT NOT NULL,
+        initial_rating INTEGER,
+        rating INTEGER,
+        target_time_seconds BIGINT NOT NULL DEFAULT 60,
+        playtak_game_id BIGINT NOT NULL,
+        published BOOLEAN NOT NULL DEFAULT FALSE,
+        published_seconds BIGINT
+    );
+    CREATE TABLE IF NOT EXISTS users (
+        id BIGSERIAL PRIMARY KEY,
+        username TEXT NOT NULL UNIQUE,
+        display_name TEXT,
+        rating DOUBLE PRECISION NOT NULL,
+        deviation DOUBLE PRECISION NOT NULL DEFAULT 350,
+        volatility DOUBLE PRECISION NOT NULL DEFAULT 0.06,
+        excluded_from_ratings BOOLEAN NOT NULL DEFAULT FALSE,
+        deleted_seconds BIGINT
+    );
+    CREATE TABLE IF NOT EXISTS puzzle_attempts (
+        id BIGSERIAL PRIMARY KEY,
+        puzzle_id BIGINT NOT NULL REFERENCES puzzles (id),
+        user_id BIGINT NOT NULL REFERENCES users (id),
+        solved BOOLEAN NOT NULL,
+        solve_time_seconds BIGINT NOT NULL,
+        solution TEXT NOT NULL,
+        timestamp_seconds BIGINT NOT NULL DEFAULT EXTRACT(EPOCH FROM NOW())::BIGINT,
+        attempt_number BIGINT NOT NULL DEFAULT 1,
+        practice BOOLEAN NOT NULL DEFAULT FALSE,
+        device_hash TEXT,
+        ip_hash TEXT,
+        shared_device BOOLEAN NOT NULL DEFAULT FALSE,
+        retry_of BIGINT,
+        solved_on_retry BOOLEAN NOT NULL DEFAULT FALSE,
+        verdict TEXT,
+        illegal_move_index BIGINT
+    );
+    CREATE UNIQUE INDEX IF NOT EXISTS puzzle_attempts_by_user
+        ON puzzle_attempts (user_id, puzzle_id, attempt_number);
+    CREATE INDEX IF NOT EXISTS puzzle_attempts_by_puzzle
+        ON puzzle_attempts (puzzle_id, attempt_number);
+    CREATE INDEX IF NOT EXISTS puzzle_attempts_by_user_and_time
+        ON puzzle_attempts (user_id, timestamp_seconds);
+    CREATE OR REPLACE VIEW rated_attempts AS SELECT puzzle_attempts.*, users.username FROM puzzle_attempts
+        JOIN users ON users.id = puzzle_attempts.user_id
+        WHERE attempt_number = 1 AND NOT practice AND NOT shared_device;
+    CREATE TABLE IF NOT EXISTS idempotency_keys (
+        username TEXT NOT NULL,
+        key TEXT NOT NULL,
+        puzzle_id BIGINT NOT NULL,
+        created_seconds BIGINT NOT NULL,
+        attempt_number BIGINT NOT NULL DEFAULT 1,
+        response TEXT,
+        PRIMARY KEY (username, key)
+    );
+    CREATE TABLE IF NOT EXISTS retryable_attempts (
+        username TEXT PRIMARY KEY,
+        puzzle_id BIGINT NOT NULL,
+        attempt_number BIGINT NOT NULL,
+        rating DOUBLE PRECISION NOT NULL,
+        deviation DOUBLE PRECISION NOT NULL,
+        volatility DOUBLE PRECISION NOT NULL,
+        puzzle_rating DOUBLE PRECISION NOT NULL,
+        puzzle_deviation DOUBLE PRECISION NOT NULL,
+        puzzle_volatility DOUBLE PRECISION NOT NULL
+    );";
+
+// Like `storage::PUZZLE_FILTER`, with the player as `$2` and the sizes as a `BIGINT[]` in `$3`
+const PUZZLE_FILTER: &str = "($2::TEXT IS NULL OR lower(puzzles.player_white) = lower($2) OR lower(puzzles.player_black) = lower($2))
+    AND (cardinality($3::BIGINT[]) = 0 OR puzzles.size = ANY($3))";
+
+// Reads share one connection, which pipelines concurrent queries.
+// Writes run one at a time on a second connection, like `db::Writer`. Other instances write at the same time,
+// so each attempt locks its user's row first, and attempts by the same user never interleave
+pub struct PostgresStore {
+    reader: Client,
+    writer: Mutex<Client>,
+}
+
+impl PostgresStore {
+    // `url` is a libpq connection string, like `host=localhost user=tactics dbname=tactics`
+    // or `postgres://tactics@localhost/tactics`. Creates any missing tables
+    pub async fn connect(url: &str) -> anyhow::Result<Self> {
+        let reader = open(url).await?;
+        let writer = open(url).await?;
+        writer
+            .batch_execute(SCHEMA)
+            .await
+            .context("Failed to create the Postgres tables")?;
+        Ok(Self {
+            reader,
+            writer: Mutex::new(writer),
+        })
+    }
+}
+
+async fn open(url: &str) -> anyhow::Result<Client> {
+    let (client, connection) = tokio_postgres::connect(url, NoTls)
+        .await
+        .context("Failed to connect to Postgres")?;
+    tokio::spawn(async move {
+        if let Err(e) = connection.await {
+            tracing::error!("Postgres connection failed: {:?}", e);
+        }
+    });
+    Ok(client)
+}
+
+#[async_trait]
+impl PuzzleStore for PostgresStore {
+    async fn puzzle(&self, id: u32) -> anyhow::Result<Option<PuzzleRow>> {
+        let row = self
+            .reader
+            .query_opt("SELECT * FROM puzzles WHERE id = $1", &[&(id as i64)])
+            .await?;
+        row.as_ref().map(puzzle_row).transpose()
+    }
+
+    async fn published_puzzle(&self, id: u32) -> anyhow::Result<Option<PuzzleRow>> {
+        let row = self
+            .reader
+            .query_opt(
+                "SELECT * FROM puzzles WHERE id = $1 AND published",
+                &[&(id as i64)],
+            )
+            .await?;
+        row.as_ref().map(puzzle_row).transpose()
+    }
+
+    async fn unattempted_puzzle(
+        &self,
+        username: &str,
+        filter: &PuzzleFilter,
+    ) -> anyhow::Result<Option<PuzzleRow>> {
+        let query = format!(
+            "SELECT puzzles.* FROM puzzles
+            LEFT JOIN puzzle_attempts ON puzzles.id = puzzle_attempts.puzzle_id
+                AND puzzle_attempts.user_id = (SELECT id FROM users WHERE username = $1)
+            WHERE puzzles.published AND puzzle_attempts.puzzle_id IS NULL AND {PUZZLE_FILTER}
+            ORDER BY RANDOM() LIMIT 1"
+        );
+        self.filtered_puzzle(&query, username, filter).await
+    }
+
+    async fn practice_puzzle(
+        &self,
+        username: &str,
+        filter: &PuzzleFilter,
+    ) -> anyhow::Result<Option<PuzzleRow>> {
+        let query = format!(
+            "SELECT puzzles.* FROM puzzles WHERE puzzles.published AND {PUZZLE_FILTER}
+            ORDER BY puzzles.id IN (
+                SELECT puzzle_id FROM puzzle_attempts WHERE user_id = (SELECT id FROM users WHERE username = $1)
+            ) DESC, RANDOM()
+            LIMIT 1"
+        );
+        self.filtered_puzzle(&query, username, filter).await
+    }
+
+    async fn review_puzzle(
+        &self,
+        username: &str,
+        filter: &PuzzleFilter,
+    ) -> anyhow::Result<Option<PuzzleRow>> {
+        let query = format!(
+            "SELECT puzzles.* FROM puzzles
+            JOIN puzzle_attempts ON puzzles.id = puzzle_attempts.puzzle_id
+                AND puzzle_attempts.user_id = (SELECT id FROM users WHERE username = $1)
+            WHERE puzzles.published AND {PUZZLE_FILTER}
+            GROUP BY puzzles.id
+            ORDER BY bool_or(puzzle_attempts.solved) ASC,
+                MAX(CASE WHEN puzzle_attempts.solved THEN puzzle_attempts.timestamp_seconds END) ASC,
+                RANDOM()
+            LIMIT 1"
+        );
+        self.filtered_puzzle(&query, username, filter).await
+    }
+
+    async fn attempts_for_user(&self, username: &str) -> anyhow::Result<Vec<PuzzleAttemptRow>> {
+        let rows = self
+            .reader
+            .query(
+                "SELECT puzzle_id, username, solved, solve_time_seconds, solution, timestamp_seconds, attempt_number
+                FROM rated_attempts WHERE username = $1",
+                &[&username],
+            )
+            .await?;
+        rows.iter()
+            .map(|row| {
+                Ok(PuzzleAttemptRow {
+                    puzzle_id: row.try_get::<_, i64>(0)? as u64,
+                    username: row.try_get(1)?,
+                    solved: row.try_get(2)?,
+                    solve_time_seconds: row.try_get::<_, i64>(3)? as u32,
+                    solution: row.try_get(4)?,
+                    timestamp_seconds: row.try_get::<_, i64>(5)? as u64,
+                    attempt_number: row.try_get::<_, i64>(6)? as u32,
+                })
+            })
+            .collect()
+    }
+
+    async fn record_attempt(
+        &self,
+        attempt: attempts::NewAttempt,
+        idempotency_key: Option<String>,
+    ) -> anyhow::Result<AttemptOutcome> {
+        let mut writer = self.writer.lock().await;
+        let transaction = writer.transaction().await?;
+        let user = lock_user(&transaction, &attempt.username).await?;
+        if let Some(key) = &idempotency_key
+            && let Some(repeated) = read_keyed_attempt(&transaction, &attempt, key).await?
+        {
+            return Ok(repeated);
+        }
+        let retried = if attempt.retry {
+            match take_retryable(&transaction, &attempt).await? {
+                Some(retried) => Some(retried),
+                None => return Ok(AttemptOutcome::NotRetryable),
+            }
+        } else {
+            None
+        };
+        let recorded = write_attempt(&transaction, &attempt, &user, retried).await?;
+        if let Some(key) = &idempotency_key {
+            transaction
+                .execute(
+                    "INSERT INTO idempotency_keys (username, key, puzzle_id, created_seconds, attempt_number, response)
+                    VALUES ($1, $2, $3, $4, $5, $6)",
+                    &[
+                        &attempt.username,
+                        key,
+                        &(attempt.puzzle_id as i64),
+                        &(now_seconds() as i64),
+                        &(recorded.attempt_number as i64),
+                        &serde_json::to_string(&recorded)?,
+                    ],
+                )
+                .await?;
+        }
+        transaction.commit().await?;
+        Ok(AttemptOutcome::Recorded(recorded))
+    }
+
+    // Not cached, since other instances may have recorded attempts at the puzzle since
+    async fn puzzle_rating(&self, id: u32) -> anyhow::Result<Glicko2Rating> {
+        Ok(rating_for_puzzle(&self.reader, id).await?.rating)
+    }
+
+    async fn user_rating(&self, username: &str) -> anyhow::Result<Glicko2Rating> {
+        let row = self
+            .reader
+            .query_opt(
+                "SELECT rating, deviation, volatility FROM users WHERE username = $1",
+                &[&username],
+            )
+            .await?;
+        Ok(row
+            .as_ref()
+            .map(glicko2_rating)
+            .transpose()?
+            .unwrap_or_default())
+    }
+
+    async fn published_puzzle_ratings(&self) -> anyhow::Result<BTreeMap<u32, f64>> {
+        let rows = self
+            .reader
+            .query(
+                "WITH rated_counts AS (SELECT user_id, COUNT(*) AS num_rated FROM rated_attempts GROUP BY user_id)
+                SELECT puzzles.id, puzzles.solution, rated_attempts.solved, rated_attempts.username,
+                    users.rating, users.deviation, users.volatility, rated_counts.num_rated < $1,
+                    rated_attempts.solved_on_retry
+                FROM puzzles
+                LEFT JOIN rated_attempts ON rated_attempts.puzzle_id = puzzles.id
+                    AND rated_attempts.user_id NOT IN (SELECT id FROM users WHERE excluded_from_ratings)
+                LEFT JOIN users ON users.id = rated_attempts.user_id
+                LEFT JOIN rated_counts ON rated_counts.user_id = rated_attempts.user_id
+                WHERE puzzles.published",
+                &[&(ratings::PROVISIONAL_ATTEMPTS as i64)],
+            )
+            .await?;
+        let mut puzzles: BTreeMap<u32, (String, Vec<RatingRow>)> = BTreeMap::new();
+        for row in &rows {
+            let (_, ratings) = puzzles
+                .entry(row.try_get::<_, i64>(0)? as u32)
+                .or_insert_with(|| (row.get(1), vec![]));
+            // Puzzles without attempts still get one row, with nulls for the attempt
+            if let Some(username) = row.try_get::<_, Option<String>>(3)? {
+                ratings.push(RatingRow {
+                    solved: row.try_get(2)?,
+                    solved_on_retry: row.try_get(8)?,
+                    username,
+                    rating: row.try_get(4)?,
+                    deviation: row.try_get(5)?,
+                    volatility: row.try_get(6)?,
+                    provisional: row.try_get(7)?,
+                });
+            }
+        }
+        Ok(puzzles
+            .into_iter()
+            .map(|(id, (solution, ratings))| {
+                let rating = telemetry::time_rating_computation("puzzle", || {
+                    ratings::rate_puzzle(ratings::default_rating_for_solution(&solution), ratings)
+                });
+                (id, rating.rating.rating)
+            })
+            .collect())
+    }
+
+    // Nothing is cached
+    fn invalidate_puzzle_ratings(&self, _puzzle_ids: &[u32]) {}
+}
+
+impl PostgresStore {
+    // A puzzle from one of the queries above, which take the username and the filter as `$1` to `$3`
+    async fn filtered_puzzle(
+        &self,
+        query: &str,
+        username: &str,
+        filter: &PuzzleFilter<'_>,
+    ) -> anyhow::Result<Option<PuzzleRow>> {
+        let sizes: Vec<i64> = filter.sizes.iter().map(|&size| size as i64).collect();
+        let row = self
+            .reader
+            .query_opt(query, &[&username, &filter.player, &sizes])
+            .await?;
+        row.as_ref().map(puzzle_row).transpose()
+    }
+}
+
+fn puzzle_row(row: &Row) -> anyhow::Result<PuzzleRow> {
+    Ok(PuzzleRow {
+        id: row.try_get::<_, i64>("id")? as u64,
+        root_tps: row.try_get("root_tps")?,
+        defender_start_move: row.try_get("defender_start_move")?,
+        size: row.try_get::<_, i64>("size")? as usize,
+        komi: row.try_get("komi")?,
+        player_white: row.try_get("player_white")?,
+        player_black: row.try_get("player_black")?,
+        solution: row.try_get("solution")?,
+        initial_rating: row.try_get("initial_rating")?,
+        rating: row.try_get("rating")?,
+        target_time_seconds: row.try_get::<_, i64>("target_time_seconds")? as u32,
+        playtak_game_id: row.try_get::<_, i64>("playtak_game_id")? as usize,
+        published: row.try_get("published")?,
+    })
+}
+
+fn glicko2_rating(row: &Row) -> Result<Glicko2Rating, tokio_postgres::Error> {
+    Ok(Glicko2Rating {
+        rating: row.try_get(0)?,
+        deviation: row.try_get(1)?,
+        volatility: row.try_get(2)?,
+    })
+}
+
+// The user an attempt is by, locked until the end of the transaction
+struct LockedUser {
+    id: i64,
+    rating: Glicko2Rating,
+}
+
+// Users are added with the default rating on their first attempt, like in `users::get_or_create_id`
+async fn lock_user(db_conn: &impl GenericClient, username: &str) -> anyhow::Result<LockedUser> {
+    let default_rating = Glicko2Rating::default();
+    db_conn
+        .execute(
+            "INSERT INTO users (username, display_name, rating, deviation, volatility) VALUES ($1, $1, $2, $3, $4)
+            ON CONFLICT (username) DO NOTHING",
+            &[
+                &username,
+                &default_rating.rating,
+                &default_rating.deviation,
+                &default_rating.volatility,
+            ],
+        )
+        .await?;
+    let row = db_conn
+        .query_one(
+            "SELECT rating, deviation, volatility, id FROM users
+            WHERE username = $1 FOR UPDATE",
+            &[&username],
+        )
+        .await?;
+    Ok(LockedUser {
+        id: row.try_get(3)?,
+        rating: glicko2_rating(&row)?,
+    })
+}
+
+// Like `idempotency::read_keyed_attempt`, with the earlier attempt as the outcome. Also forgets expired keys
+async fn read_keyed_attempt(
+    db_conn: &impl GenericClient,
+    attempt: &attempts::NewAttempt,
+    key: &str,
+) -> anyhow::Result<Option<AttemptOutcome>> {
+    db_conn
+        .execute(
+            "DELETE FROM idempotency_keys WHERE created_seconds < $1",
+            &[&(now_seconds().saturating_sub(idempotency::KEY_LIFETIME_SECONDS) as i64)],
+        )
+        .await?;
+    let Some(row) = db_conn
+        .query_opt(
+            "SELECT puzzle_id, attempt_number, response FROM idempotency_keys
+            WHERE username = $1 AND key = $2",
+            &[&attempt.username, &key],
+        )
+        .await?
+    else {
+        return Ok(None);
+    };
+    let attempt_number = row.try_get::<_, i64>(1)? as u32;
+    let recorded = row
+        .try_get::<_, Option<String>>(2)?
+        .and_then(|response| serde_json::from_str(&response).ok())
+        .unwrap_or_else(|| RecordedAttempt {
+            attempt_number,
+            rated: attempts::is_rated(attempt_number, attempt.practice, false),
+            rating_change: None,
+            retry: None,
+        });
+    Ok(Some(AttemptOutcome::Repeated {
+        puzzle_id: row.try_get::<_, i64>(0)? as u32,
+        attempt: recorded,
+    }))
+}
+
+// Like `retries::take`. Seasons aren't stored in Postgres, so there's never a season rating to rate again
+async fn take_retryable(
+    db_conn: &impl GenericClient,
+    attempt: &attempts::NewAttempt,
+) -> anyhow::Result<Option<Retryable>> {
+    let row = db_conn
+        .query_opt(
+            "DELETE FROM retryable_attempts WHERE username = $1 AND puzzle_id = $2
+            RETURNING rating, deviation, volatility, attempt_number, puzzle_rating, puzzle_deviation, puzzle_volatility",
+            &[&attempt.username, &(attempt.puzzle_id as i64)],
+        )
+        .await?;
+    let Some(row) = row else {
+        return Ok(None);
+    };
+    Ok(Some(Retryable {
+        attempt_number: row.try_get::<_, i64>(3)? as u32,
+        rating: glicko2_rating(&row)?,
+        puzzle_rating: Glicko2Rating {
+            rating: row.try_get(4)?,
+            deviation: row.try_get(5)?,
+            volatility: row.try_get(6)?,
+        },
+        season_rating: None,
+    }))
+}
+
+// Store an attempt and update the ratings that depend on it, like `storage::write_attempt`
+async fn write_attempt(
+    db_conn: &impl GenericClient,
+    attempt: &attempts::NewAttempt,
+    user: &LockedUser,
+    retried: Option<Retryable>,
+) -> anyhow::Result<RecordedAttempt> {
+    let username = &attempt.username;
+    let puzzle_id = attempt.puzzle_id as i64;
+    let puzzle_rating_before = if attempt.practice {
+        None
+    } else {
+        Some(rating_for_puzzle(db_conn, attempt.puzzle_id).await?.rating)
+    };
+
+    let judgement = attempt.judgement.as_ref();
+    let inserted = db_conn
+        .query_one(
+            "INSERT INTO puzzle_attempts (puzzle_id, user_id, solved, solve_time_seconds, solution, practice, attempt_number,
+                device_hash, ip_hash, shared_device, retry_of, verdict, illegal_move_index)
+            VALUES ($1, $2, $3, $4, $5, $6, (
+                SELECT COALESCE(MAX(attempt_number), 0) + 1 FROM puzzle_attempts
+                WHERE puzzle_id = $1 AND user_id = $2
+            ), $7, $8, EXISTS (
+                SELECT 1 FROM rated_attempts
+                WHERE puzzle_id = $1 AND user_id != $2 AND (device_hash = $7 OR ip_hash = $8)
+            ), $9, $10, $11)
+            RETURNING attempt_number, shared_device",
+            &[
+                &puzzle_id,
+                &user.id,
+                &attempt.solved,
+                &(attempt.solve_time_seconds as i64),
+                &attempt.solution.join(" "),
+                &attempt.practice,
+                &attempt.device.id_hash,
+                &attempt.device.ip_hash,
+                &retried.as_ref().map(|retried| retried.attempt_number as i64),
+                &judgement.map(|judgement| judgement.verdict.name()),
+                &judgement.and_then(|judgement| judgement.illegal_move_index.map(i64::from)),
+            ],
+        )
+        .await?;
+    let attempt_number = inserted.try_get::<_, i64>(0)? as u32;
+    let rated = attempts::is_rated(attempt_number, attempt.practice, inserted.try_get(1)?);
+    telemetry::record_attempt_submitted(rated, attempt.solved);
+    if retried.is_none() {
+        // Like `retries::attempted`
+        db_conn
+            .execute(
+                "DELETE FROM retryable_attempts WHERE username = $1 AND ($3 OR puzzle_id = $2)",
+                &[username, &puzzle_id, &rated],
+            )
+            .await?;
+    }
+
+    let rating_change = match puzzle_rating_before {
+        Some(puzzle_rating) if rated => {
+            let new_rating = ratings::rate_user(
+                &user.rating,
+                &puzzle_rating,
+                ratings::outcome(attempt.solved, false),
+            );
+            write_user_rating(db_conn, user.id, &new_rating.rating).await?;
+            if !attempt.solved {
+                db_conn
+                    .execute(
+                        "INSERT INTO retryable_attempts (username, puzzle_id, attempt_number, rating, deviation, volatility,
+                            puzzle_rating, puzzle_deviation, puzzle_volatility)
+                        VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9)
+                        ON CONFLICT (username) DO UPDATE SET puzzle_id = excluded.puzzle_id,
+                            attempt_number = excluded.attempt_number, rating = excluded.rating,
+                            deviation = excluded.deviation, volatility = excluded.volatility,
+                            puzzle_rating = excluded.puzzle_rating, puzzle_deviation = excluded.puzzle_deviation,
+                            puzzle_volatility = excluded.puzzle_volatility",
+                        &[
+                            username,
+                            &puzzle_id,
+                            &(attempt_number as i64),
+                            &user.rating.rating,
+                            &user.rating.deviation,
+                            &user.rating.volatility,
+                            &puzzle_rating.rating,
+                            &puzzle_rating.deviation,
+                            &puzzle_rating.volatility,
+                        ],
+                    )
+                    .await?;
+            }
+            Some(RatingChange {
+                old_rating: user.rating.rating,
+                new_rating: new_rating.rating.rating,
+                puzzle_rating: rating_for_puzzle(db_conn, attempt.puzzle_id)
+                    .await?
+                    .rating
+                    .rating,
+            })
+        }
+        _ => None,
+    };
+    let retry = match retried {
+        Some(retried) if attempt.solved => {
+            Some(rate_retried_attempt(db_conn, attempt, user, &retried).await?)
+        }
+        Some(retried) => Some(RetryResult {
+            retry_of: retried.attempt_number,
+            score: 0.0,
+            rating_change: None,
+        }),
+        None => None,
+    };
+
+    Ok(RecordedAttempt {
+        attempt_number,
+        rated: rating_change.is_some(),
+        rating_change,
+        retry,
+    })
+}
+
+// After a retry that solved the puzzle, rate the failed attempt again as a draw, like `storage::rate_retried_attempt`
+async fn rate_retried_attempt(
+    db_conn: &impl GenericClient,
+    retry: &attempts::NewAttempt,
+    user: &LockedUser,
+    retried: &Retryable,
+) -> anyhow::Result<RetryResult> {
+    db_conn
+        .execute(
+            "UPDATE puzzle_attempts SET solved_on_retry = TRUE
+            WHERE user_id = $1 AND puzzle_id = $2 AND attempt_number = $3",
+            &[
+                &user.id,
+                &(retry.puzzle_id as i64),
+                &(retried.attempt_number as i64),
+            ],
+        )
+        .await?;
+    let new_rating = ratings::rate_user(&retried.rating, &retried.puzzle_rating, Outcomes::DRAW);
+    write_user_rating(db_conn, user.id, &new_rating.rating).await?;
+    Ok(RetryResult {
+        retry_of: retried.attempt_number,
+        score: 0.5,
+        rating_change: Some(RatingChange {
+            old_rating: user.rating.rating,
+            new_rating: new_rating.rating.rating,
+            puzzle_rating: rating_for_puzzle(db_conn, retry.puzzle_id)
+                .await?
+                .rating
+                .rating,
+        }),
+    })
+}
+
+async fn write_user_rating(
+    db_conn: &impl GenericClient,
+    user_id: i64,
+    rating: &Glicko2Rating,
+) -> anyhow::Result<()> {
+    db_conn
+        .execute(
+            "UPDATE users SET rating = $2, deviation = $3, volatility = $4 WHERE id = $1",
+            &[
+                &user_id,
+                &rating.rating,
+                &rating.deviation,
+                &rating.volatility,
+            ],
+        )
+        .await?;
+    Ok(())
+}
+
+// Compute a puzzle's rating from its rated attempts, like `storage::rating_for_puzzle`
+async fn rating_for_puzzle(
+    db_conn: &impl GenericClient,
+    puzzle_id: u32,
+) -> anyhow::Result<ratings::Limited> {
+    let puzzle_id = puzzle_id as i64;
+    let rows = db_conn
+        .query(
+            "SELECT rated_attempts.solved, rated_attempts.solved_on_retry, rated_attempts.username,
+                users.rating, users.deviation, users.volatility,
+                (SELECT COUNT(*) FROM rated_attempts AS others
+                    WHERE others.user_id = rated_attempts.user_id) < $2 AS provisional
+            FROM rated_attempts JOIN users ON users.id = rated_attempts.user_id
+            WHERE puzzle_id = $1 AND NOT users.excluded_from_ratings",
+            &[&puzzle_id, &(ratings::PROVISIONAL_ATTEMPTS as i64)],
+        )
+        .await?;
+    let ratings = rows
+        .iter()
+        .map(|row| {
+            Ok(RatingRow {
+                solved: row.try_get(0)?,
+                solved_on_retry: row.try_get(1)?,
+                username: row.try_get(2)?,
+                rating: row.try_get(3)?,
+                deviation: row.try_get(4)?,
+                volatility: row.try_get(5)?,
+                provisional: row.try_get(6)?,
+            })
+        })
+        .collect::<Result<Vec<_>, tokio_postgres::Error>>()?;
+    let solution: String = db_conn
+        .query_opt("SELECT solution FROM puzzles WHERE id = $1", &[&puzzle_id])
+        .await?
+        .map(|row| row.get(0))
+        .unwrap_or_default();
+    Ok(telemetry::time_rating_computation("puzzle", || {
+        ratings::rate_puzzle(ratings::default_rating_for_solution(&solution), ratings)
+    }))
+}
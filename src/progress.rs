@@ -0,0 +1,244 @@
+use axum::{
+    Json,
+    extract::{Path, Query, State},
+    http::StatusCode,
+};
+use rusqlite::Connection;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    AppState, campaign, db, puzzle_sets,
+    validation::{self, ApiError, ValidationError},
+};
+use utoipa::{IntoParams, ToSchema};
+
+#[derive(Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct SetProgress {
+    set_id: u64,
+    name: String,
+    // Set if the puzzle set is used as a campaign chapter
+    chapter_id: Option<u64>,
+    unlocked: bool,
+    num_puzzles: u32,
+    num_attempted: u32,
+    num_solved: u32,
+    completed: bool,
+    last_activity_seconds: Option<u64>,
+    next_puzzle_id: Option<u64>,
+}
+
+// Get the user's progress through every puzzle set and campaign chapter
+#[utoipa::path(
+    get,
+    path = "/users/{username}/progress",
+    tag = "progress",
+    params(("username" = String, Path)),
+    responses((status = 200, body = Vec<SetProgress>)),
+)]
+pub async fn get_user_progress(
+    Path(username): Path<String>,
+) -> Result<Json<Vec<SetProgress>>, StatusCode> {
+    let username = validation::canonical_username(&username);
+    if username.is_empty() {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+    let db_conn = db::open().map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    match read_user_progress(&db_conn, &username) {
+        Ok(progress) => Ok(Json(progress)),
+        Err(e) => {
+            tracing::error!("Error reading user progress from database: {:?}", e);
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
+fn read_user_progress(db_conn: &Connection, username: &str) -> anyhow::Result<Vec<SetProgress>> {
+    campaign::update_unlocks(db_conn, username)?;
+    let chapter_unlocks = campaign::read_chapter_unlocks(db_conn, username)?;
+
+    let mut last_activity_stmt = db_conn.prepare(
+        "SELECT MAX(puzzle_attempts.timestamp_seconds) FROM puzzle_attempts
+        JOIN puzzle_set_entries ON puzzle_set_entries.puzzle_id = puzzle_attempts.puzzle_id
+        WHERE puzzle_set_entries.set_id = ?1
+            AND puzzle_attempts.user_id = (SELECT id FROM users WHERE username = ?2)",
+    )?;
+
+    let mut all_progress = vec![];
+    for set in puzzle_sets::read_puzzle_sets(db_conn)? {
+        let Some(progress) = puzzle_sets::read_puzzle_set_progress(db_conn, set.id, username)?
+        else {
+            continue;
+        };
+        let chapter = chapter_unlocks
+            .iter()
+            .find(|chapter| chapter.set_id == set.id);
+        let unlocked = chapter.is_none_or(|chapter| chapter.unlocked);
+        let last_activity_seconds =
+            last_activity_stmt.query_row(rusqlite::params![set.id, username], |row| row.get(0))?;
+        // Don't recommend puzzles from chapters the user can't play yet
+        let next_puzzle_id = if unlocked {
+            puzzle_sets::read_next_unattempted_puzzle_in_set(db_conn, set.id, username)?
+                .map(|puzzle| puzzle.id)
+        } else {
+            None
+        };
+
+        all_progress.push(SetProgress {
+            set_id: set.id,
+            name: set.name,
+            chapter_id: chapter.map(|chapter| chapter.chapter_id),
+            unlocked,
+            num_puzzles: progress.num_puzzles,
+            num_attempted: progress.num_attempted,
+            num_solved: progress.num_solved,
+            completed: progress.completed,
+            last_activity_seconds,
+            next_puzzle_id,
+        });
+    }
+    Ok(all_progress)
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+#[serde(rename_all = "camelCase")]
+pub struct PuzzleProgressQuery {
+    size: Option<usize>,
+    // Only count puzzles whose current rating is in this band, for progress by difficulty
+    min_rating: Option<f64>,
+    max_rating: Option<f64>,
+}
+
+#[derive(Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct PuzzleProgress {
+    username: String,
+    #[serde(flatten)]
+    counts: PuzzleCounts,
+    by_size: Vec<SizeProgress>,
+}
+
+#[derive(Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct SizeProgress {
+    size: usize,
+    #[serde(flatten)]
+    counts: PuzzleCounts,
+}
+
+#[derive(Default, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct PuzzleCounts {
+    num_published: u32,
+    num_attempted: u32,
+    num_solved: u32,
+    // Published puzzles the user hasn't attempted, which are the ones `GET /puzzles` can still serve
+    num_remaining: u32,
+}
+
+// Get how many of the published puzzles the user has solved, in total and for each board size.
+// Pass `size` to only count puzzles of that size, and `minRating` and `maxRating` to only count puzzles of that difficulty
+#[utoipa::path(
+    get,
+    path = "/users/{username}/puzzle-progress",
+    tag = "progress",
+    params(("username" = String, Path), PuzzleProgressQuery),
+    responses(
+        (status = 200, body = PuzzleProgress),
+        (status = 400, body = validation::ValidationError),
+    ),
+)]
+pub async fn get_puzzle_progress(
+    Path(username): Path<String>,
+    State(state): State<AppState>,
+    Query(query): Query<PuzzleProgressQuery>,
+) -> Result<Json<PuzzleProgress>, ApiError> {
+    let username = validation::canonical_username(&username);
+    validation::validate_username(&username)?;
+    if let (Some(min_rating), Some(max_rating)) = (query.min_rating, query.max_rating)
+        && min_rating > max_rating
+    {
+        return Err(ValidationError::new("minRating", "minRating is more than maxRating").into());
+    }
+    // Puzzle ratings are computed from the attempts, so the band is applied to them rather than in SQL
+    let puzzle_ids = if query.min_rating.is_some() || query.max_rating.is_some() {
+        let ratings = state.store.published_puzzle_ratings().await.map_err(|e| {
+            tracing::error!("Error reading puzzle ratings from database: {:?}", e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+        let in_band: Vec<u32> = ratings
+            .into_iter()
+            .filter(|&(_, rating)| {
+                query
+                    .min_rating
+                    .is_none_or(|min_rating| rating >= min_rating)
+                    && query
+                        .max_rating
+                        .is_none_or(|max_rating| rating <= max_rating)
+            })
+            .map(|(id, _)| id)
+            .collect();
+        Some(in_band)
+    } else {
+        None
+    };
+    let db_conn = db::open().map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    match read_puzzle_progress(&db_conn, &username, query.size, puzzle_ids.as_deref()) {
+        Ok(by_size) => {
+            let mut counts = PuzzleCounts::default();
+            for size_progress in &by_size {
+                counts.num_published += size_progress.counts.num_published;
+                counts.num_attempted += size_progress.counts.num_attempted;
+                counts.num_solved += size_progress.counts.num_solved;
+                counts.num_remaining += size_progress.counts.num_remaining;
+            }
+            Ok(Json(PuzzleProgress {
+                username,
+                counts,
+                by_size,
+            }))
+        }
+        Err(e) => {
+            tracing::error!("Error reading puzzle progress from database: {:?}", e);
+            Err(StatusCode::INTERNAL_SERVER_ERROR.into())
+        }
+    }
+}
+
+// Only counts the puzzles in `puzzle_ids`, if it's given
+fn read_puzzle_progress(
+    db_conn: &Connection,
+    username: &str,
+    size: Option<usize>,
+    puzzle_ids: Option<&[u32]>,
+) -> anyhow::Result<Vec<SizeProgress>> {
+    let mut stmt = db_conn.prepare(
+        "SELECT puzzles.size, COUNT(*), COUNT(user_attempts.puzzle_id),
+            COALESCE(SUM(user_attempts.solved), 0)
+        FROM puzzles
+        LEFT JOIN (
+            SELECT puzzle_id, MAX(solved) AS solved FROM puzzle_attempts
+            WHERE user_id = (SELECT id FROM users WHERE username = ?1) GROUP BY puzzle_id
+        ) AS user_attempts ON user_attempts.puzzle_id = puzzles.id
+        WHERE puzzles.published = 1 AND (?2 IS NULL OR puzzles.size = ?2)
+            AND (?3 IS NULL OR puzzles.id IN (SELECT value FROM json_each(?3)))
+        GROUP BY puzzles.size
+        ORDER BY puzzles.size",
+    )?;
+    let puzzle_ids = puzzle_ids.map(|ids| serde_json::to_string(ids).unwrap_or_default());
+    let rows = stmt.query_map(rusqlite::params![username, size, puzzle_ids], |row| {
+        let num_published: u32 = row.get(1)?;
+        let num_attempted: u32 = row.get(2)?;
+        Ok(SizeProgress {
+            size: row.get(0)?,
+            counts: PuzzleCounts {
+                num_published,
+                num_attempted,
+                num_solved: row.get(3)?,
+                num_remaining: num_published - num_attempted,
+            },
+        })
+    })?;
+    Ok(rows.collect::<Result<Vec<_>, _>>()?)
+}
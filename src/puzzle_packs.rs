@@ -0,0 +1,153 @@
+use axum::{
+    Json,
+    extract::{Query, State},
+    http::{StatusCode, header},
+    response::IntoResponse,
+};
+use serde::{Deserialize, Serialize};
+use serde_rusqlite::from_row;
+use utoipa::{IntoParams, ToSchema};
+
+use crate::{
+    AppState, PuzzleRow, db, now_seconds,
+    validation::{ApiError, ValidationError},
+};
+
+// Downloadable bundles of published puzzles, for offline trainers and mobile apps.
+// Only puzzles and their ratings are included, never anything about users
+
+// Bumped whenever a change to the format would break existing readers
+const FORMAT_VERSION: u32 = 1;
+const DEFAULT_COUNT: u32 = 100;
+const MAX_COUNT: u32 = 1000;
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct PackQuery {
+    // Every size if not set
+    size: Option<usize>,
+    count: Option<u32>,
+}
+
+#[derive(Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct PuzzlePack {
+    manifest: Manifest,
+    puzzles: Vec<PackPuzzle>,
+}
+
+#[derive(Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct Manifest {
+    format_version: u32,
+    generated_seconds: u64,
+    size: Option<usize>,
+    num_puzzles: usize,
+}
+
+// Like the API's puzzles, but with the rating, and the target time before any per-user scaling
+#[derive(Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct PackPuzzle {
+    id: u64,
+    size: usize,
+    komi: String,
+    #[serde(rename = "rootTPS")]
+    root_tps: String,
+    defender_start_move: String,
+    solution: Vec<String>,
+    target_time_seconds: u32,
+    rating: f64,
+    player_white: String,
+    player_black: String,
+    playtak_game_id: usize,
+}
+
+// Download up to `count` published puzzles, in order of id
+#[utoipa::path(
+    get,
+    path = "/puzzles/export",
+    tag = "puzzles",
+    params(PackQuery),
+    responses(
+        (status = 200, body = PuzzlePack),
+        (status = 400, body = ValidationError),
+    ),
+)]
+pub async fn export_puzzles(
+    State(state): State<AppState>,
+    Query(query): Query<PackQuery>,
+) -> Result<impl IntoResponse, ApiError> {
+    if let Some(size) = query.size
+        && !(3..=8).contains(&size)
+    {
+        return Err(ValidationError::new("size", "Size must be between 3 and 8").into());
+    }
+    let count = match query.count {
+        None => DEFAULT_COUNT,
+        Some(count) if (1..=MAX_COUNT).contains(&count) => count,
+        Some(_) => {
+            return Err(ValidationError::new(
+                "count",
+                format!("Count must be between 1 and {MAX_COUNT}"),
+            )
+            .into());
+        }
+    };
+    let rows = read_puzzles(query.size, count).map_err(|e| {
+        tracing::error!("Error reading puzzles for export: {:?}", e);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+    let ratings = state.store.published_puzzle_ratings().await.map_err(|e| {
+        tracing::error!("Error reading puzzle ratings from database: {:?}", e);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+    let puzzles: Vec<PackPuzzle> = rows
+        .into_iter()
+        .map(|row| PackPuzzle {
+            rating: ratings.get(&(row.id as u32)).copied().unwrap_or_default(),
+            id: row.id,
+            size: row.size,
+            komi: row.komi,
+            root_tps: row.root_tps,
+            defender_start_move: row.defender_start_move,
+            solution: row.solution.split_whitespace().map(String::from).collect(),
+            target_time_seconds: row.target_time_seconds,
+            player_white: row.player_white,
+            player_black: row.player_black,
+            playtak_game_id: row.playtak_game_id,
+        })
+        .collect();
+
+    let filename = match query.size {
+        Some(size) => format!("tak-puzzles-{size}x{size}.json"),
+        None => "tak-puzzles.json".to_string(),
+    };
+    Ok((
+        [(
+            header::CONTENT_DISPOSITION,
+            format!("attachment; filename=\"{filename}\""),
+        )],
+        Json(PuzzlePack {
+            manifest: Manifest {
+                format_version: FORMAT_VERSION,
+                generated_seconds: now_seconds(),
+                size: query.size,
+                num_puzzles: puzzles.len(),
+            },
+            puzzles,
+        }),
+    ))
+}
+
+fn read_puzzles(size: Option<usize>, count: u32) -> anyhow::Result<Vec<PuzzleRow>> {
+    let db_conn = db::open()?;
+    let mut stmt = db_conn.prepare(
+        "SELECT * FROM puzzles WHERE published = 1 AND (?1 IS NULL OR size = ?1)
+        ORDER BY id LIMIT ?2",
+    )?;
+    let rows = stmt
+        .query_and_then(rusqlite::params![size, count], from_row::<PuzzleRow>)?
+        .collect::<Result<_, _>>()?;
+    Ok(rows)
+}
@@ -0,0 +1,207 @@
+use axum::{
+    Json,
+    extract::{Path, Query},
+    http::StatusCode,
+};
+use rusqlite::Connection;
+use serde::{Deserialize, Serialize};
+use serde_rusqlite::from_row;
+
+use crate::{Puzzle, PuzzleRequest, PuzzleRow, bans, db, validation};
+use utoipa::ToSchema;
+
+pub fn init_db_tables(db_conn: &Connection) -> anyhow::Result<()> {
+    // Sets have to be inserted manually for now
+    db_conn.execute(
+        "CREATE TABLE IF NOT EXISTS puzzle_sets (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            name TEXT NOT NULL UNIQUE,
+            description TEXT NOT NULL DEFAULT ''
+        )",
+        [],
+    )?;
+
+    // `position` determines the order puzzles are served within a set
+    db_conn.execute(
+        "CREATE TABLE IF NOT EXISTS puzzle_set_entries (
+            set_id INTEGER NOT NULL,
+            puzzle_id INTEGER NOT NULL,
+            position INTEGER NOT NULL,
+            PRIMARY KEY (set_id, puzzle_id),
+            FOREIGN KEY (set_id) REFERENCES puzzle_sets(id),
+            FOREIGN KEY (puzzle_id) REFERENCES puzzles(id)
+        )",
+        [],
+    )?;
+
+    Ok(())
+}
+
+#[derive(Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct PuzzleSet {
+    pub id: u64,
+    pub name: String,
+    pub description: String,
+    pub num_puzzles: u32,
+}
+
+#[derive(Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct PuzzleSetProgress {
+    pub set_id: u64,
+    pub username: String,
+    pub num_puzzles: u32,
+    pub num_attempted: u32,
+    pub num_solved: u32,
+    pub completed: bool,
+}
+
+// List all puzzle sets
+#[utoipa::path(
+    get,
+    path = "/puzzle-sets",
+    tag = "puzzle sets",
+    responses((status = 200, body = Vec<PuzzleSet>)),
+)]
+pub async fn get_puzzle_sets() -> Result<Json<Vec<PuzzleSet>>, StatusCode> {
+    let db_conn = db::open().map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let sets = read_puzzle_sets(&db_conn).map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    Ok(Json(sets))
+}
+
+// Get the first puzzle in the set that the user hasn't attempted yet
+#[utoipa::path(
+    get,
+    path = "/puzzle-sets/{id}/next",
+    tag = "puzzle sets",
+    params(("id" = u64, Path), PuzzleRequest),
+    responses(
+        (status = 200, body = Puzzle),
+        (status = 403, description = "The user is banned"),
+        (status = 404),
+    ),
+)]
+pub async fn get_next_puzzle_in_set(
+    Path(id): Path<u64>,
+    Query(request): Query<PuzzleRequest>,
+) -> Result<Json<Puzzle>, StatusCode> {
+    let username = validation::canonical_username(&request.username);
+    if username.is_empty() {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+    let db_conn = db::open().map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    bans::check_not_banned(&db_conn, &username)?;
+    match read_next_unattempted_puzzle_in_set(&db_conn, id, &username) {
+        Ok(Some(puzzle)) => Ok(Json(puzzle.into())),
+        Ok(None) => Err(StatusCode::NOT_FOUND),
+        Err(e) => {
+            tracing::error!("Error reading puzzle set from database: {:?}", e);
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
+// Get how far the user has gotten through the set
+#[utoipa::path(
+    get,
+    path = "/puzzle-sets/{id}/progress",
+    tag = "puzzle sets",
+    params(("id" = u64, Path), PuzzleRequest),
+    responses((status = 200, body = PuzzleSetProgress), (status = 404)),
+)]
+pub async fn get_puzzle_set_progress(
+    Path(id): Path<u64>,
+    Query(request): Query<PuzzleRequest>,
+) -> Result<Json<PuzzleSetProgress>, StatusCode> {
+    let username = validation::canonical_username(&request.username);
+    if username.is_empty() {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+    let db_conn = db::open().map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    match read_puzzle_set_progress(&db_conn, id, &username) {
+        Ok(Some(progress)) => Ok(Json(progress)),
+        Ok(None) => Err(StatusCode::NOT_FOUND),
+        Err(e) => {
+            tracing::error!("Error reading puzzle set progress from database: {:?}", e);
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
+pub fn read_puzzle_sets(db_conn: &Connection) -> anyhow::Result<Vec<PuzzleSet>> {
+    let mut stmt = db_conn.prepare(
+        "SELECT puzzle_sets.id, puzzle_sets.name, puzzle_sets.description,
+            COUNT(puzzle_set_entries.puzzle_id) AS num_puzzles
+        FROM puzzle_sets
+        LEFT JOIN puzzle_set_entries ON puzzle_sets.id = puzzle_set_entries.set_id
+        GROUP BY puzzle_sets.id ORDER BY puzzle_sets.id",
+    )?;
+    let rows = stmt.query_map([], |row| {
+        Ok(PuzzleSet {
+            id: row.get(0)?,
+            name: row.get(1)?,
+            description: row.get(2)?,
+            num_puzzles: row.get(3)?,
+        })
+    })?;
+    Ok(rows.collect::<Result<Vec<_>, _>>()?)
+}
+
+pub fn read_next_unattempted_puzzle_in_set(
+    db_conn: &Connection,
+    set_id: u64,
+    username: &str,
+) -> anyhow::Result<Option<PuzzleRow>> {
+    let mut stmt = db_conn.prepare(
+        "SELECT puzzles.* FROM puzzle_set_entries
+        JOIN puzzles ON puzzles.id = puzzle_set_entries.puzzle_id
+        WHERE puzzle_set_entries.set_id = ?1 AND NOT EXISTS (
+            SELECT 1 FROM puzzle_attempts
+            WHERE puzzle_attempts.puzzle_id = puzzles.id
+                AND puzzle_attempts.user_id = (SELECT id FROM users WHERE username = ?2)
+        )
+        ORDER BY puzzle_set_entries.position LIMIT 1",
+    )?;
+    Ok(stmt
+        .query_and_then(rusqlite::params![set_id, username], from_row::<PuzzleRow>)?
+        .next()
+        .transpose()?)
+}
+
+pub fn read_puzzle_set_progress(
+    db_conn: &Connection,
+    set_id: u64,
+    username: &str,
+) -> anyhow::Result<Option<PuzzleSetProgress>> {
+    let set_exists = db_conn
+        .prepare("SELECT 1 FROM puzzle_sets WHERE id = ?1")?
+        .exists([set_id])?;
+    if !set_exists {
+        return Ok(None);
+    }
+
+    // A puzzle counts as solved if any of the user's attempts solved it
+    let (num_puzzles, num_attempted, num_solved) = db_conn.query_row(
+        "SELECT COUNT(*),
+            COUNT(attempts.puzzle_id),
+            COALESCE(SUM(attempts.solved), 0)
+        FROM puzzle_set_entries
+        LEFT JOIN (
+            SELECT puzzle_id, MAX(solved) AS solved FROM puzzle_attempts
+            WHERE user_id = (SELECT id FROM users WHERE username = ?2) GROUP BY puzzle_id
+        ) AS attempts ON attempts.puzzle_id = puzzle_set_entries.puzzle_id
+        WHERE puzzle_set_entries.set_id = ?1",
+        rusqlite::params![set_id, username],
+        |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)),
+    )?;
+
+    Ok(Some(PuzzleSetProgress {
+        set_id,
+        username: username.to_string(),
+        num_puzzles,
+        num_attempted,
+        num_solved,
+        completed: num_puzzles > 0 && num_attempted == num_puzzles,
+    }))
+}
@@ -0,0 +1,407 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::Instant,
+};
+
+use axum::{
+    extract::{
+        Path, Query, State,
+        ws::{Message, WebSocket, WebSocketUpgrade},
+    },
+    http::StatusCode,
+    response::Response,
+};
+use rusqlite::Connection;
+use serde::{Deserialize, Serialize};
+use serde_rusqlite::from_row;
+use tokio::sync::broadcast;
+
+use crate::{
+    AppState, Puzzle, PuzzleRequest, PuzzleRow, attempts, bans, db,
+    validation::{self, ApiError},
+};
+
+// Players are sent the puzzles without their solutions, and send back their lines, which are checked
+// and timed by the server, so that the results aren't up to the players
+
+// Number of puzzles in each race
+const RACE_LENGTH: usize = 5;
+
+pub type RaceRooms = Arc<Mutex<HashMap<String, Room>>>;
+
+pub fn init_db_tables(db_conn: &Connection) -> anyhow::Result<()> {
+    db_conn.execute(
+        "CREATE TABLE IF NOT EXISTS races (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            room TEXT NOT NULL,
+            winner TEXT,
+            finished_seconds INTEGER NOT NULL DEFAULT (strftime('%s', 'now'))
+        )",
+        [],
+    )?;
+
+    db_conn.execute(
+        "CREATE TABLE IF NOT EXISTS race_players (
+            race_id INTEGER NOT NULL,
+            username TEXT NOT NULL,
+            puzzles_solved INTEGER NOT NULL,
+            total_time_seconds INTEGER NOT NULL,
+            forfeited INTEGER NOT NULL,
+            PRIMARY KEY (race_id, username),
+            FOREIGN KEY (race_id) REFERENCES races(id)
+        )",
+        [],
+    )?;
+
+    Ok(())
+}
+
+pub struct Room {
+    players: Vec<RacePlayer>,
+    puzzles: Vec<PuzzleRow>,
+    finished: bool,
+    events: broadcast::Sender<RaceEvent>,
+}
+
+struct RacePlayer {
+    username: String,
+    connected: bool,
+    puzzles_completed: usize,
+    puzzles_solved: usize,
+    total_time_seconds: u32,
+    // When the race started, or when the player's last result arrived
+    puzzle_started: Instant,
+}
+
+// Messages sent from the server to both players
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "camelCase")]
+enum RaceEvent {
+    #[serde(rename_all = "camelCase")]
+    Joined { username: String },
+    #[serde(rename_all = "camelCase")]
+    Start { puzzles: Vec<Puzzle> },
+    #[serde(rename_all = "camelCase")]
+    Progress {
+        username: String,
+        puzzles_completed: usize,
+        puzzles_solved: usize,
+    },
+    #[serde(rename_all = "camelCase")]
+    Finished {
+        winner: Option<String>,
+        results: Vec<RaceResult>,
+    },
+}
+
+// Messages sent from a player to the server, once per puzzle, in order
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "camelCase")]
+enum PlayerMessage {
+    #[serde(rename_all = "camelCase")]
+    Result {
+        puzzle_index: usize,
+        // The attacker's moves, as in `PuzzleSolveRequest`
+        solution: Vec<String>,
+    },
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct RaceResult {
+    username: String,
+    puzzles_solved: usize,
+    total_time_seconds: u32,
+    forfeited: bool,
+}
+
+// Join a race room. The race starts as soon as a second player joins
+#[utoipa::path(
+    get,
+    path = "/races/{room}/ws",
+    tag = "races",
+    description = "WebSocket for racing other players in a room",
+    params(("room" = String, Path), PuzzleRequest),
+    responses(
+        (status = 101, description = "Switching to the WebSocket protocol"),
+        (status = 400, body = validation::ValidationError),
+        (status = 403, description = "The user is banned"),
+        (status = 409, description = "The room is full or the race has started"),
+    ),
+)]
+pub async fn race_socket(
+    ws: WebSocketUpgrade,
+    Path(room): Path<String>,
+    Query(request): Query<PuzzleRequest>,
+    State(state): State<AppState>,
+) -> Result<Response, ApiError> {
+    let username = validation::canonical_username(&request.username);
+    validation::validate_username(&username)?;
+    bans::check_not_banned(
+        &db::open().map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?,
+        &username,
+    )?;
+    if !room_is_joinable(&state.races, &room, &username) {
+        return Err(StatusCode::CONFLICT.into());
+    }
+    Ok(ws.on_upgrade(move |socket| handle_socket(socket, state.races, room, username)))
+}
+
+fn room_is_joinable(rooms: &RaceRooms, room_name: &str, username: &str) -> bool {
+    let rooms = rooms.lock().unwrap();
+    rooms.get(room_name).is_none_or(|room| {
+        room.players.len() < 2 && !room.players.iter().any(|p| p.username == username)
+    })
+}
+
+fn join_room(
+    rooms: &RaceRooms,
+    room_name: &str,
+    username: &str,
+) -> Option<broadcast::Receiver<RaceEvent>> {
+    let mut rooms = rooms.lock().unwrap();
+    let room = rooms.entry(room_name.to_string()).or_insert_with(|| Room {
+        players: vec![],
+        puzzles: vec![],
+        finished: false,
+        events: broadcast::channel(16).0,
+    });
+    if room.players.len() >= 2 || room.players.iter().any(|p| p.username == username) {
+        return None;
+    }
+    room.players.push(RacePlayer {
+        username: username.to_string(),
+        connected: true,
+        puzzles_completed: 0,
+        puzzles_solved: 0,
+        total_time_seconds: 0,
+        puzzle_started: Instant::now(),
+    });
+    Some(room.events.subscribe())
+}
+
+pub async fn handle_socket(
+    mut socket: WebSocket,
+    rooms: RaceRooms,
+    room_name: String,
+    username: String,
+) {
+    // Someone else may have joined while the connection was being upgraded
+    let Some(mut events) = join_room(&rooms, &room_name, &username) else {
+        let _ = socket.send(Message::Close(None)).await;
+        return;
+    };
+    if let Err(e) = start_if_full(&rooms, &room_name, &username) {
+        tracing::error!("Error starting race in room {}: {:?}", room_name, e);
+    }
+
+    loop {
+        tokio::select! {
+            message = socket.recv() => match message {
+                Some(Ok(Message::Text(text))) => {
+                    match serde_json::from_str::<PlayerMessage>(&text) {
+                        Ok(message) => handle_player_message(&rooms, &room_name, &username, message),
+                        Err(e) => tracing::warn!("Invalid race message from {}: {}", username, e),
+                    }
+                }
+                Some(Ok(Message::Close(_))) | Some(Err(_)) | None => break,
+                Some(Ok(_)) => {}
+            },
+            event = events.recv() => match event {
+                Ok(event) => {
+                    let text = serde_json::to_string(&event).unwrap();
+                    if socket.send(Message::Text(text.into())).await.is_err() {
+                        break;
+                    }
+                }
+                Err(broadcast::error::RecvError::Lagged(_)) => {}
+                Err(broadcast::error::RecvError::Closed) => break,
+            },
+        }
+    }
+
+    leave_room(&rooms, &room_name, &username);
+}
+
+fn start_if_full(rooms: &RaceRooms, room_name: &str, username: &str) -> anyhow::Result<()> {
+    let mut rooms = rooms.lock().unwrap();
+    let Some(room) = rooms.get_mut(room_name) else {
+        return Ok(());
+    };
+    let _ = room.events.send(RaceEvent::Joined {
+        username: username.to_string(),
+    });
+    if room.players.len() < 2 || !room.puzzles.is_empty() {
+        return Ok(());
+    }
+    let db_conn = db::open()?;
+    room.puzzles = read_race_puzzles(&db_conn)?;
+    let puzzles = room
+        .puzzles
+        .iter()
+        .map(|row| Puzzle {
+            solution: vec![],
+            ..Puzzle::from(row.clone())
+        })
+        .collect();
+    let now = Instant::now();
+    for player in &mut room.players {
+        player.puzzle_started = now;
+    }
+    let _ = room.events.send(RaceEvent::Start { puzzles });
+    Ok(())
+}
+
+fn handle_player_message(
+    rooms: &RaceRooms,
+    room_name: &str,
+    username: &str,
+    message: PlayerMessage,
+) {
+    let mut rooms = rooms.lock().unwrap();
+    let Some(room) = rooms.get_mut(room_name) else {
+        return;
+    };
+    if room.finished || room.puzzles.is_empty() {
+        return;
+    }
+    let num_puzzles = room.puzzles.len();
+    let PlayerMessage::Result {
+        puzzle_index,
+        solution,
+    } = message;
+    let Some(player) = room.players.iter_mut().find(|p| p.username == username) else {
+        return;
+    };
+    // Results must arrive in order, and each puzzle only counts once
+    if puzzle_index != player.puzzles_completed
+        || puzzle_index >= num_puzzles
+        || validation::validate_solution(&solution).is_err()
+    {
+        return;
+    }
+    let now = Instant::now();
+    let solve_time_seconds = now.duration_since(player.puzzle_started).as_secs() as u32;
+    player.puzzle_started = now;
+    let solved =
+        attempts::verify(&room.puzzles[puzzle_index], &solution, solve_time_seconds).correct;
+    player.puzzles_completed += 1;
+    if solved {
+        player.puzzles_solved += 1;
+    }
+    player.total_time_seconds += solve_time_seconds;
+    let _ = room.events.send(RaceEvent::Progress {
+        username: username.to_string(),
+        puzzles_completed: player.puzzles_completed,
+        puzzles_solved: player.puzzles_solved,
+    });
+
+    if room
+        .players
+        .iter()
+        .all(|p| p.puzzles_completed == num_puzzles)
+    {
+        finish_race(room, room_name);
+    }
+}
+
+fn leave_room(rooms: &RaceRooms, room_name: &str, username: &str) {
+    let mut rooms = rooms.lock().unwrap();
+    let Some(room) = rooms.get_mut(room_name) else {
+        return;
+    };
+    if room.puzzles.is_empty() {
+        // The race hasn't started, so the room can be joined by someone else
+        room.players.retain(|p| p.username != username);
+    } else {
+        if let Some(player) = room.players.iter_mut().find(|p| p.username == username) {
+            player.connected = false;
+        }
+        if !room.finished {
+            finish_race(room, room_name);
+        }
+    }
+    if room.players.iter().all(|p| !p.connected) {
+        rooms.remove(room_name);
+    }
+}
+
+// Declare a winner, and notify and persist the results. A player who disconnected
+// before finishing forfeits. Otherwise, most puzzles solved wins, with total time as tiebreak
+fn finish_race(room: &mut Room, room_name: &str) {
+    room.finished = true;
+    let num_puzzles = room.puzzles.len();
+    let results: Vec<RaceResult> = room
+        .players
+        .iter()
+        .map(|p| RaceResult {
+            username: p.username.clone(),
+            puzzles_solved: p.puzzles_solved,
+            total_time_seconds: p.total_time_seconds,
+            forfeited: !p.connected && p.puzzles_completed < num_puzzles,
+        })
+        .collect();
+
+    let mut ranked: Vec<&RaceResult> = results.iter().collect();
+    ranked.sort_by_key(|r| {
+        (
+            r.forfeited,
+            std::cmp::Reverse(r.puzzles_solved),
+            r.total_time_seconds,
+        )
+    });
+    let winner = match ranked.as_slice() {
+        [first, second]
+            if first.forfeited == second.forfeited
+                && first.puzzles_solved == second.puzzles_solved
+                && first.total_time_seconds == second.total_time_seconds =>
+        {
+            None
+        }
+        [first, ..] => Some(first.username.clone()),
+        [] => None,
+    };
+
+    if let Err(e) = persist_race(room_name, winner.as_deref(), &results) {
+        tracing::error!("Error saving race in room {}: {:?}", room_name, e);
+    }
+    let _ = room.events.send(RaceEvent::Finished { winner, results });
+}
+
+fn persist_race(
+    room_name: &str,
+    winner: Option<&str>,
+    results: &[RaceResult],
+) -> anyhow::Result<()> {
+    let mut db_conn = db::open()?;
+    let transaction = db_conn.transaction()?;
+    transaction.execute(
+        "INSERT INTO races (room, winner) VALUES (?1, ?2)",
+        rusqlite::params![room_name, winner],
+    )?;
+    let race_id = transaction.last_insert_rowid();
+    for result in results {
+        transaction.execute(
+            "INSERT INTO race_players (race_id, username, puzzles_solved, total_time_seconds, forfeited)
+            VALUES (?1, ?2, ?3, ?4, ?5)",
+            rusqlite::params![
+                race_id,
+                result.username,
+                result.puzzles_solved,
+                result.total_time_seconds,
+                result.forfeited
+            ],
+        )?;
+    }
+    transaction.commit()?;
+    Ok(())
+}
+
+fn read_race_puzzles(db_conn: &Connection) -> anyhow::Result<Vec<PuzzleRow>> {
+    // Same pool of puzzles as `read_unsolved_puzzles_from_db`
+    let mut stmt =
+        db_conn.prepare("SELECT * FROM puzzles WHERE published = 1 ORDER BY RANDOM() LIMIT ?1")?;
+    let rows = stmt.query_and_then([RACE_LENGTH], from_row::<PuzzleRow>)?;
+    Ok(rows.collect::<Result<Vec<_>, _>>()?)
+}
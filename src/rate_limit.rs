@@ -0,0 +1,143 @@
+use std::{
+    collections::HashMap,
+    net::{IpAddr, SocketAddr},
+    sync::Mutex,
+    time::Instant,
+};
+
+use crate::{AppState, config::RateLimitConfig, validation};
+use axum::{
+    extract::{ConnectInfo, Request, State},
+    http::{HeaderMap, StatusCode, header},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+
+// Prune idle buckets once there are this many, so the maps don't grow forever
+const PRUNE_THRESHOLD: usize = 10_000;
+
+// In-memory token buckets, one per client IP and one per username.
+// Each bucket holds up to a minute's worth of requests, and refills continuously
+pub struct RateLimiter {
+    config: RateLimitConfig,
+    ip_buckets: Mutex<HashMap<IpAddr, Bucket>>,
+    username_buckets: Mutex<HashMap<String, Bucket>>,
+}
+
+struct Bucket {
+    tokens: f64,
+    last_refill: Instant,
+}
+
+impl RateLimiter {
+    pub fn new(config: RateLimitConfig) -> Self {
+        Self {
+            config,
+            ip_buckets: Mutex::new(HashMap::new()),
+            username_buckets: Mutex::new(HashMap::new()),
+        }
+    }
+
+    // Take one token from the IP's bucket, and from the username's bucket if there is one.
+    // On failure, returns the number of seconds until the request would be allowed
+    pub fn check(&self, ip: Option<IpAddr>, username: Option<&str>) -> Result<(), u64> {
+        let now = Instant::now();
+        if let Some(ip) = ip {
+            take_token(
+                &mut self.ip_buckets.lock().unwrap(),
+                ip,
+                self.config.per_ip_per_minute,
+                now,
+            )?;
+        }
+        if let Some(username) = username {
+            take_token(
+                &mut self.username_buckets.lock().unwrap(),
+                username.to_string(),
+                self.config.per_username_per_minute,
+                now,
+            )?;
+        }
+        Ok(())
+    }
+}
+
+fn take_token<K: std::hash::Hash + Eq>(
+    buckets: &mut HashMap<K, Bucket>,
+    key: K,
+    per_minute: u32,
+    now: Instant,
+) -> Result<(), u64> {
+    let capacity = per_minute as f64;
+    let refill_per_second = capacity / 60.0;
+
+    if buckets.len() >= PRUNE_THRESHOLD {
+        buckets.retain(|_, bucket| {
+            let elapsed = now.duration_since(bucket.last_refill).as_secs_f64();
+            bucket.tokens + elapsed * refill_per_second < capacity
+        });
+    }
+
+    let bucket = buckets.entry(key).or_insert(Bucket {
+        tokens: capacity,
+        last_refill: now,
+    });
+    let elapsed = now.duration_since(bucket.last_refill).as_secs_f64();
+    bucket.tokens = (bucket.tokens + elapsed * refill_per_second).min(capacity);
+    bucket.last_refill = now;
+
+    if bucket.tokens >= 1.0 {
+        bucket.tokens -= 1.0;
+        Ok(())
+    } else if refill_per_second > 0.0 {
+        Err(((1.0 - bucket.tokens) / refill_per_second).ceil() as u64)
+    } else {
+        Err(60)
+    }
+}
+
+// Middleware that rejects requests over the configured limits with `429 Too Many Requests`
+pub async fn rate_limit(State(state): State<AppState>, request: Request, next: Next) -> Response {
+    let config = &state.config.rate_limit;
+    if !config.enabled {
+        return next.run(request).await;
+    }
+
+    let ip = client_ip(
+        request.headers(),
+        request.extensions().get::<ConnectInfo<SocketAddr>>(),
+        config.trust_forwarded_for,
+    );
+
+    let (request, username) = match validation::request_username(request).await {
+        Ok(found) => found,
+        Err(e) => return e.into_response(),
+    };
+
+    match state.rate_limiter.check(ip, username.as_deref()) {
+        Ok(()) => next.run(request).await,
+        Err(retry_after_seconds) => (
+            StatusCode::TOO_MANY_REQUESTS,
+            [(header::RETRY_AFTER, retry_after_seconds.to_string())],
+            "Rate limit exceeded",
+        )
+            .into_response(),
+    }
+}
+
+pub fn client_ip(
+    headers: &HeaderMap,
+    connect_info: Option<&ConnectInfo<SocketAddr>>,
+    trust_forwarded_for: bool,
+) -> Option<IpAddr> {
+    if trust_forwarded_for
+        && let Some(ip) = headers
+            .get("x-forwarded-for")
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.split(',').next())
+            .and_then(|ip| ip.trim().parse().ok())
+    {
+        return Some(ip);
+    }
+    connect_info.map(|ConnectInfo(addr)| addr.ip())
+}
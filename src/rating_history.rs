@@ -0,0 +1,342 @@
+use axum::{
+    Json,
+    extract::{Path, Query},
+    http::StatusCode,
+};
+use rusqlite::Connection;
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use crate::{
+    admin::AdminAuth,
+    db,
+    pagination::{Page, PageQuery},
+    ratings, storage,
+    validation::{self, ApiError},
+};
+
+// Every user's rating after each of their rated attempts, for rating graphs,
+// and every puzzle's rating after each rated attempt that counts towards it.
+// `recompute-ratings` rewrites both from scratch, which also fills them in for attempts made before they existed
+pub fn init_db_tables(db_conn: &Connection) -> anyhow::Result<()> {
+    db_conn.execute(
+        "CREATE TABLE IF NOT EXISTS user_rating_history (
+            attempt_id INTEGER PRIMARY KEY,
+            username TEXT NOT NULL,
+            rating REAL NOT NULL,
+            deviation REAL NOT NULL,
+            timestamp_seconds INTEGER NOT NULL
+        )",
+        [],
+    )?;
+
+    db_conn.execute(
+        "CREATE INDEX IF NOT EXISTS idx_user_rating_history_username
+        ON user_rating_history (username, timestamp_seconds)",
+        [],
+    )?;
+
+    // A puzzle's rating is computed as a single rating period over all its rated attempts,
+    // so each row is the result of the rating period that ended with that attempt
+    db_conn.execute(
+        "CREATE TABLE IF NOT EXISTS puzzle_rating_history (
+            attempt_id INTEGER PRIMARY KEY,
+            puzzle_id INTEGER NOT NULL,
+            rating REAL NOT NULL,
+            deviation REAL NOT NULL,
+            timestamp_seconds INTEGER NOT NULL
+        )",
+        [],
+    )?;
+
+    db_conn.execute(
+        "CREATE INDEX IF NOT EXISTS idx_puzzle_rating_history_puzzle_id
+        ON puzzle_rating_history (puzzle_id, attempt_id)",
+        [],
+    )?;
+
+    // Ratings that were outside the limits in `RatingLimitsConfig` after a rated attempt, and were clamped.
+    // `kind` is `user` or `puzzle`, for which of the attempt's two ratings it was
+    db_conn.execute(
+        "CREATE TABLE IF NOT EXISTS rating_clamps (
+            attempt_id INTEGER NOT NULL,
+            kind TEXT NOT NULL,
+            username TEXT NOT NULL,
+            puzzle_id INTEGER NOT NULL,
+            unclamped_rating REAL NOT NULL,
+            clamped_rating REAL NOT NULL,
+            timestamp_seconds INTEGER NOT NULL,
+            PRIMARY KEY (attempt_id, kind)
+        )",
+        [],
+    )?;
+
+    Ok(())
+}
+
+// Record the user's rating after a rated attempt, at the time of the attempt
+pub fn record_user_rating(
+    db_conn: &Connection,
+    username: &str,
+    puzzle_id: u32,
+    attempt_number: u32,
+    rating: &ratings::Limited,
+) -> anyhow::Result<()> {
+    db_conn
+        .prepare_cached(
+            "INSERT OR REPLACE INTO user_rating_history (attempt_id, username, rating, deviation, timestamp_seconds)
+            SELECT id, ?1, ?4, ?5, timestamp_seconds FROM puzzle_attempts
+            WHERE user_id = (SELECT id FROM users WHERE username = ?1) AND puzzle_id = ?2 AND attempt_number = ?3",
+        )?
+        .execute(rusqlite::params![
+            username,
+            puzzle_id,
+            attempt_number,
+            rating.rating.rating,
+            rating.rating.deviation
+        ])?;
+    record_clamp(db_conn, "user", username, puzzle_id, attempt_number, rating)
+}
+
+// Record the puzzle's rating after a rated attempt that counts towards it
+pub fn record_puzzle_rating(
+    db_conn: &Connection,
+    username: &str,
+    puzzle_id: u32,
+    attempt_number: u32,
+    rating: &ratings::Limited,
+) -> anyhow::Result<()> {
+    db_conn
+        .prepare_cached(
+            "INSERT OR REPLACE INTO puzzle_rating_history (attempt_id, puzzle_id, rating, deviation, timestamp_seconds)
+            SELECT id, puzzle_id, ?4, ?5, timestamp_seconds FROM puzzle_attempts
+            WHERE user_id = (SELECT id FROM users WHERE username = ?1) AND puzzle_id = ?2 AND attempt_number = ?3",
+        )?
+        .execute(rusqlite::params![
+            username,
+            puzzle_id,
+            attempt_number,
+            rating.rating.rating,
+            rating.rating.deviation
+        ])?;
+    record_clamp(
+        db_conn,
+        "puzzle",
+        username,
+        puzzle_id,
+        attempt_number,
+        rating,
+    )
+}
+
+fn record_clamp(
+    db_conn: &Connection,
+    kind: &str,
+    username: &str,
+    puzzle_id: u32,
+    attempt_number: u32,
+    rating: &ratings::Limited,
+) -> anyhow::Result<()> {
+    let Some(unclamped) = rating.unclamped else {
+        return Ok(());
+    };
+    db_conn
+        .prepare_cached(
+            "INSERT OR REPLACE INTO rating_clamps (attempt_id, kind, username, puzzle_id, unclamped_rating,
+                clamped_rating, timestamp_seconds)
+            SELECT id, ?4, ?1, puzzle_id, ?5, ?6, timestamp_seconds FROM puzzle_attempts
+            WHERE user_id = (SELECT id FROM users WHERE username = ?1) AND puzzle_id = ?2 AND attempt_number = ?3",
+        )?
+        .execute(rusqlite::params![
+            username,
+            puzzle_id,
+            attempt_number,
+            kind,
+            unclamped,
+            rating.rating.rating
+        ])?;
+    Ok(())
+}
+
+#[derive(Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct RatingHistoryEntry {
+    timestamp_seconds: u64,
+    rating: f64,
+    deviation: f64,
+    // The attempt that changed the rating, for `GET /attempts/{id}/replay`
+    attempt_id: i64,
+    puzzle_id: u64,
+}
+
+// Get the user's rating after each of their rated attempts, oldest first.
+// Users without rated attempts have an empty history, and the default rating
+#[utoipa::path(
+    get,
+    path = "/users/{username}/rating-history",
+    tag = "ratings",
+    params(("username" = String, Path)),
+    responses(
+        (status = 200, body = Vec<RatingHistoryEntry>),
+        (status = 400, body = validation::ValidationError),
+    ),
+)]
+pub async fn get_user_rating_history(
+    Path(username): Path<String>,
+) -> Result<Json<Vec<RatingHistoryEntry>>, ApiError> {
+    let username = validation::canonical_username(&username);
+    validation::validate_username(&username)?;
+    let db_conn = db::open().map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    match read_user_rating_history(&db_conn, &username) {
+        Ok(history) => Ok(Json(history)),
+        Err(e) => {
+            tracing::error!("Error reading rating history from database: {:?}", e);
+            Err(StatusCode::INTERNAL_SERVER_ERROR.into())
+        }
+    }
+}
+
+pub fn read_user_rating_history(
+    db_conn: &Connection,
+    username: &str,
+) -> anyhow::Result<Vec<RatingHistoryEntry>> {
+    let mut stmt = db_conn.prepare(
+        "SELECT user_rating_history.timestamp_seconds, user_rating_history.rating, user_rating_history.deviation,
+            user_rating_history.attempt_id, puzzle_attempts.puzzle_id
+        FROM user_rating_history JOIN puzzle_attempts ON puzzle_attempts.id = user_rating_history.attempt_id
+        WHERE user_rating_history.username = ?1
+        ORDER BY user_rating_history.timestamp_seconds, user_rating_history.attempt_id",
+    )?;
+    let rows = stmt.query_map([username], |row| {
+        Ok(RatingHistoryEntry {
+            timestamp_seconds: row.get(0)?,
+            rating: row.get(1)?,
+            deviation: row.get(2)?,
+            attempt_id: row.get(3)?,
+            puzzle_id: row.get(4)?,
+        })
+    })?;
+    Ok(rows.collect::<Result<Vec<_>, _>>()?)
+}
+
+#[derive(Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct PuzzleRatingHistoryEntry {
+    timestamp_seconds: u64,
+    rating: f64,
+    deviation: f64,
+    // How many rated attempts the rating was computed from
+    num_attempts: u32,
+}
+
+// Get the puzzle's rating after each rated attempt, oldest first, to see how its difficulty estimate converges.
+// Attempts by users who don't count towards puzzle ratings are left out
+#[utoipa::path(
+    get,
+    path = "/puzzles/{id}/rating-history",
+    tag = "ratings",
+    params(("id" = u32, Path)),
+    responses((status = 200, body = Vec<PuzzleRatingHistoryEntry>), (status = 404)),
+)]
+pub async fn get_puzzle_rating_history(
+    Path(id): Path<u32>,
+) -> Result<Json<Vec<PuzzleRatingHistoryEntry>>, StatusCode> {
+    let db_conn = db::open().map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    storage::read_puzzle_by_id(&db_conn, id)
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+        .filter(|puzzle| puzzle.published)
+        .ok_or(StatusCode::NOT_FOUND)?;
+    read_puzzle_rating_history(&db_conn, id)
+        .map(Json)
+        .map_err(|e| {
+            tracing::error!("Error reading rating history from database: {:?}", e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })
+}
+
+fn read_puzzle_rating_history(
+    db_conn: &Connection,
+    puzzle_id: u32,
+) -> anyhow::Result<Vec<PuzzleRatingHistoryEntry>> {
+    let mut stmt = db_conn.prepare(
+        "SELECT timestamp_seconds, rating, deviation, ROW_NUMBER() OVER (ORDER BY attempt_id)
+        FROM puzzle_rating_history
+        WHERE puzzle_id = ?1
+        ORDER BY attempt_id",
+    )?;
+    let rows = stmt.query_map([puzzle_id], |row| {
+        Ok(PuzzleRatingHistoryEntry {
+            timestamp_seconds: row.get(0)?,
+            rating: row.get(1)?,
+            deviation: row.get(2)?,
+            num_attempts: row.get(3)?,
+        })
+    })?;
+    Ok(rows.collect::<Result<Vec<_>, _>>()?)
+}
+
+#[derive(Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct RatingClamp {
+    attempt_id: i64,
+    // `user` or `puzzle`
+    kind: String,
+    username: String,
+    puzzle_id: u64,
+    unclamped_rating: f64,
+    clamped_rating: f64,
+    timestamp_seconds: u64,
+}
+
+// List the ratings that were clamped to a floor or ceiling, newest first
+#[utoipa::path(
+    get,
+    path = "/admin/rating-clamps",
+    tag = "ratings",
+    params(PageQuery),
+    security(("admin_token" = [])),
+    responses(
+        (status = 200, body = Page<RatingClamp>),
+        (status = 400, body = validation::ValidationError),
+        (status = 401),
+        (status = 403),
+    ),
+)]
+pub async fn get_rating_clamps(
+    _: AdminAuth,
+    Query(page): Query<PageQuery>,
+) -> Result<Json<Page<RatingClamp>>, ApiError> {
+    let db_conn = db::open().map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let page = page.fetch(
+        |after, limit| read_rating_clamps(&db_conn, after, limit),
+        |clamp| (clamp.attempt_id, clamp.kind.clone()),
+    )?;
+    Ok(Json(page))
+}
+
+fn read_rating_clamps(
+    db_conn: &Connection,
+    after: Option<(i64, String)>,
+    limit: u32,
+) -> anyhow::Result<Vec<RatingClamp>> {
+    let (after_id, after_kind) = after.unzip();
+    let mut stmt = db_conn.prepare(
+        "SELECT attempt_id, kind, username, puzzle_id, unclamped_rating, clamped_rating, timestamp_seconds
+        FROM rating_clamps
+        WHERE ?1 IS NULL OR (attempt_id, kind) < (?1, ?2)
+        ORDER BY attempt_id DESC, kind DESC
+        LIMIT ?3",
+    )?;
+    let rows = stmt.query_map(rusqlite::params![after_id, after_kind, limit], |row| {
+        Ok(RatingClamp {
+            attempt_id: row.get(0)?,
+            kind: row.get(1)?,
+            username: row.get(2)?,
+            puzzle_id: row.get(3)?,
+            unclamped_rating: row.get(4)?,
+            clamped_rating: row.get(5)?,
+            timestamp_seconds: row.get(6)?,
+        })
+    })?;
+    Ok(rows.collect::<Result<Vec<_>, _>>()?)
+}
@@ -1,39 +1,123 @@
-use rusqlite::Connection;
+use std::{
+    collections::HashMap,
+    sync::{OnceLock, RwLock},
+    time::{Duration, Instant},
+};
 
 use serde::{Deserialize, Serialize};
-use serde_rusqlite::from_row;
 use skillratings::{
     Outcomes,
-    glicko2::{Glicko2Config, Glicko2Rating, glicko2_rating_period},
+    glicko2::{Glicko2Config, Glicko2Rating, glicko2, glicko2_rating_period},
 };
+use utoipa::ToSchema;
+
+use crate::{config::RatingLimitsConfig, telemetry};
+
+static LIMITS: OnceLock<RatingLimitsConfig> = OnceLock::new();
+
+// Set the rating limits, like `db::configure`. Should be called once at startup, before any ratings are computed.
+// Until then, the default limits are used
+pub fn configure(limits: RatingLimitsConfig) {
+    if LIMITS.set(limits).is_err() {
+        tracing::warn!("Rating limits were already set, ignoring the new ones");
+    }
+}
+
+// A rating with the configured floor and ceiling applied, see `RatingLimitsConfig`
+#[derive(Debug, Clone, Copy)]
+pub struct Limited {
+    pub rating: Glicko2Rating,
+    // The rating before it was clamped, if it was outside the limits
+    pub unclamped: Option<f64>,
+}
+
+fn clamp(rating: Glicko2Rating, floor: Option<f64>, ceiling: Option<f64>) -> Limited {
+    let clamped = rating
+        .rating
+        .max(floor.unwrap_or(f64::NEG_INFINITY))
+        .min(ceiling.unwrap_or(f64::INFINITY));
+    Limited {
+        rating: Glicko2Rating {
+            rating: clamped,
+            ..rating
+        },
+        unclamped: (clamped != rating.rating).then_some(rating.rating),
+    }
+}
+
+// A rated attempt at a puzzle, with the rating of the user who made it
 #[derive(Deserialize, Serialize)]
-struct RatingRow {
-    solved: bool,
-    username: String,
-    rating: f64,
+pub struct RatingRow {
+    pub solved: bool,
+    // The attempt failed, but the retry right after it solved the puzzle, see `retries.rs`
+    pub solved_on_retry: bool,
+    pub username: String,
+    pub rating: f64,
+    pub deviation: f64,
+    pub volatility: f64,
+    // See `PROVISIONAL_ATTEMPTS`
+    pub provisional: bool,
+}
+
+// New users start with the default Glicko-2 rating, whose high deviation makes it move quickly.
+// Their rating is provisional until they've made this many rated attempts
+pub const PROVISIONAL_ATTEMPTS: u32 = 10;
+
+// Provisional users count towards puzzle ratings as if their deviation was at least this.
+// Glicko-2 gives less weight to opponents with high deviations, so their attempts move puzzle ratings less
+const PROVISIONAL_MIN_DEVIATION: f64 = 350.0;
+
+// How a rated attempt changed the user's rating
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct RatingChange {
+    pub old_rating: f64,
+    pub new_rating: f64,
+    // The puzzle's rating after the attempt
+    pub puzzle_rating: f64,
+}
+
+// Puzzle ratings are invalidated whenever the puzzle gets a new attempt,
+// so this only matters if the database is changed some other way
+const RATING_CACHE_TTL: Duration = Duration::from_secs(10 * 60);
+
+// Computing a puzzle's rating replays every attempt at it, so they are cached in memory
+#[derive(Default)]
+pub struct RatingCache {
+    ratings: RwLock<HashMap<i64, (Glicko2Rating, Instant)>>,
 }
 
-pub fn rating_for_puzzles(db_conn: &Connection, puzzle_id: i64) -> anyhow::Result<Glicko2Rating> {
-    let mut stmt = db_conn.prepare("WITH ranked_attempts AS (
-    SELECT *,
-           ROW_NUMBER() OVER (
-               PARTITION BY username, puzzle_id
-               ORDER BY timestamp_seconds ASC
-           ) AS rn
-    FROM puzzle_attempts
-        )
-    SELECT ranked_attempts.solved, users.username, users.rating
-    FROM ranked_attempts JOIN users ON ranked_attempts.username = users.username
-    WHERE puzzle_id = ?1 AND rn = 1 AND ranked_attempts.username != 'Morten' AND ranked_attempts.username != 'Mort2'
-")?;
-    let ratings: Vec<RatingRow> = stmt
-        .query_and_then([puzzle_id], from_row::<RatingRow>)?
-        .collect::<Result<Vec<_>, _>>()?;
-
-    let puzzle_default_rating = default_puzzle_rating(db_conn, puzzle_id)?;
+impl RatingCache {
+    // Get a puzzle's cached rating, or compute it with `compute` if it isn't cached
+    pub fn rating_for_puzzle(
+        &self,
+        puzzle_id: i64,
+        compute: impl FnOnce() -> anyhow::Result<Glicko2Rating>,
+    ) -> anyhow::Result<Glicko2Rating> {
+        if let Some((rating, computed_at)) = self.ratings.read().unwrap().get(&puzzle_id)
+            && computed_at.elapsed() < RATING_CACHE_TTL
+        {
+            return Ok(*rating);
+        }
+        let rating = compute()?;
+        self.ratings
+            .write()
+            .unwrap()
+            .insert(puzzle_id, (rating, Instant::now()));
+        Ok(rating)
+    }
 
+    // Should be called after the transaction with the puzzle's new attempt has been committed.
+    // Otherwise a concurrent request could cache the rating from before the attempt again
+    pub fn invalidate(&self, puzzle_id: i64) {
+        self.ratings.write().unwrap().remove(&puzzle_id);
+    }
+}
+
+// A puzzle's rating is computed as one rating period, where every rated attempt is a game against the user
+pub fn rate_puzzle(default_rating: f64, ratings: Vec<RatingRow>) -> Limited {
     let puzzle_player = Glicko2Rating {
-        rating: puzzle_default_rating as f64,
+        rating: default_rating,
         ..Default::default()
     };
 
@@ -42,31 +126,59 @@ pub fn rating_for_puzzles(db_conn: &Connection, puzzle_id: i64) -> anyhow::Resul
         .map(|r| {
             let player_rating = Glicko2Rating {
                 rating: r.rating,
-                ..Default::default()
+                deviation: if r.provisional {
+                    r.deviation.max(PROVISIONAL_MIN_DEVIATION)
+                } else {
+                    r.deviation
+                },
+                volatility: r.volatility,
+            };
+            let outcome = match outcome(r.solved, r.solved_on_retry) {
+                Outcomes::WIN => Outcomes::LOSS,
+                Outcomes::LOSS => Outcomes::WIN,
+                Outcomes::DRAW => Outcomes::DRAW,
             };
-            if r.solved {
-                (player_rating, Outcomes::LOSS)
-            } else {
-                (player_rating, Outcomes::WIN)
-            }
+            (player_rating, outcome)
         })
         .collect::<Vec<_>>();
 
-    let new_player = glicko2_rating_period(&puzzle_player, &results, &Glicko2Config::new());
-
-    Ok(new_player)
+    let limits = LIMITS.get_or_init(Default::default);
+    clamp(
+        glicko2_rating_period(&puzzle_player, &results, &Glicko2Config::new()),
+        limits.puzzle_floor,
+        limits.puzzle_ceiling,
+    )
 }
 
-pub fn default_puzzle_rating(db_conn: &Connection, puzzle_id: i64) -> anyhow::Result<f64> {
-    let solution = db_conn
-        .query_row(
-            "SELECT solution FROM puzzles WHERE id = ?1",
-            [puzzle_id],
-            |row| row.get::<_, String>(0),
-        )
-        .unwrap_or_default();
-
+// Longer solutions start out as harder puzzles
+pub fn default_rating_for_solution(solution: &str) -> f64 {
     let puzzle_default_rating = 1250 + (solution.split_whitespace().count() / 2) * 350;
 
-    Ok(puzzle_default_rating as f64)
+    puzzle_default_rating as f64
+}
+
+// The result of a rated attempt for the user, as a game against the puzzle.
+// A failed attempt that was solved on the retry is a draw
+pub fn outcome(solved: bool, solved_on_retry: bool) -> Outcomes {
+    if solved {
+        Outcomes::WIN
+    } else if solved_on_retry {
+        Outcomes::DRAW
+    } else {
+        Outcomes::LOSS
+    }
+}
+
+// The user's rating after a rated attempt, treating the attempt as a game against the puzzle.
+// `puzzle_rating` should be the puzzle's rating from before the attempt
+pub fn rate_user(
+    old_rating: &Glicko2Rating,
+    puzzle_rating: &Glicko2Rating,
+    outcome: Outcomes,
+) -> Limited {
+    let (new_rating, _) = telemetry::time_rating_computation("user", || {
+        glicko2(old_rating, puzzle_rating, &outcome, &Glicko2Config::new())
+    });
+    let limits = LIMITS.get_or_init(Default::default);
+    clamp(new_rating, limits.user_floor, limits.user_ceiling)
 }
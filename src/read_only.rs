@@ -0,0 +1,133 @@
+use std::sync::RwLock;
+
+use axum::{
+    Json,
+    extract::{Request, State},
+    http::{Method, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use crate::{AppState, admin::AdminAuth, audit, db, routes};
+
+// For migrations and restoring backups: while the server is read-only, puzzles and stats are still served,
+// but attempts aren't recorded. Requests that would change data get `503 Service Unavailable`,
+// live sessions and races can't be started, and abandoned attempts aren't expired.
+// Admin endpoints under `/admin` keep working, so that admins can turn it off again.
+// Set with `server.read_only` at startup, or with `POST /admin/read-only`
+
+const DEFAULT_MESSAGE: &str =
+    "The server is down for maintenance. Puzzles can still be solved, but attempts aren't recorded";
+
+// The maintenance message, if the server is read-only
+#[derive(Default)]
+pub struct ReadOnly(RwLock<Option<String>>);
+
+impl ReadOnly {
+    pub fn new(read_only: bool) -> Self {
+        Self(RwLock::new(read_only.then(|| DEFAULT_MESSAGE.to_string())))
+    }
+
+    pub fn message(&self) -> Option<String> {
+        self.0.read().unwrap().clone()
+    }
+
+    fn set(&self, message: Option<String>) {
+        *self.0.write().unwrap() = message;
+    }
+}
+
+// Middleware that rejects requests that change data while the server is read-only
+pub async fn reject_writes(
+    State(state): State<AppState>,
+    request: Request,
+    next: Next,
+) -> Response {
+    if let Some(message) = state.read_only.message()
+        && !matches!(
+            *request.method(),
+            Method::GET | Method::HEAD | Method::OPTIONS
+        )
+        && !is_admin_path(request.uri().path())
+    {
+        return unavailable(message);
+    }
+    next.run(request).await
+}
+
+pub fn unavailable(message: String) -> Response {
+    (
+        StatusCode::SERVICE_UNAVAILABLE,
+        Json(serde_json::json!({ "error": "Read-only", "message": message })),
+    )
+        .into_response()
+}
+
+fn is_admin_path(path: &str) -> bool {
+    routes::unversioned_path(path).starts_with("admin/")
+}
+
+#[derive(Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct ReadOnlyStatus {
+    read_only: bool,
+    // Shown to users whose requests are rejected. Defaults to a generic maintenance message
+    message: Option<String>,
+}
+
+#[utoipa::path(
+    get,
+    path = "/admin/read-only",
+    tag = "admin",
+    security(("admin_token" = [])),
+    responses(
+        (status = 200, body = ReadOnlyStatus),
+        (status = 401),
+        (status = 403),
+    ),
+)]
+pub async fn get_read_only(_: AdminAuth, State(state): State<AppState>) -> Json<ReadOnlyStatus> {
+    let message = state.read_only.message();
+    Json(ReadOnlyStatus {
+        read_only: message.is_some(),
+        message,
+    })
+}
+
+// Turn read-only mode on or off. Lasts until it's changed again or the server restarts
+#[utoipa::path(
+    post,
+    path = "/admin/read-only",
+    tag = "admin",
+    request_body = ReadOnlyStatus,
+    security(("admin_token" = [])),
+    responses(
+        (status = 200, body = ReadOnlyStatus),
+        (status = 401),
+        (status = 403),
+    ),
+)]
+pub async fn set_read_only(
+    admin: AdminAuth,
+    State(state): State<AppState>,
+    Json(payload): Json<ReadOnlyStatus>,
+) -> Result<Json<ReadOnlyStatus>, StatusCode> {
+    let message = payload.read_only.then(|| {
+        payload
+            .message
+            .unwrap_or_else(|| DEFAULT_MESSAGE.to_string())
+    });
+    audit::record_for(
+        &db::open().map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?,
+        &admin,
+        "set_read_only",
+        serde_json::json!({"readOnly": payload.read_only, "message": message}),
+    )?;
+    state.read_only.set(message.clone());
+    Ok(Json(ReadOnlyStatus {
+        read_only: payload.read_only,
+        message,
+    }))
+}
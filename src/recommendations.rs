@@ -0,0 +1,144 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use rand::Rng;
+use rusqlite::Connection;
+use serde::{Deserialize, Serialize};
+use serde_rusqlite::from_row;
+use utoipa::ToSchema;
+
+use crate::{
+    PuzzleRow,
+    storage::{PUZZLE_FILTER, PuzzleFilter},
+};
+
+// Picking the next rated puzzle from how a user has done on each theme, for `GET /puzzles?strategy=weaknesses`.
+// How candidates are weighted is up to a `SelectionStrategy`, so that other weightings can be tried without touching the rest
+
+// How `GET /puzzles` picks among the puzzles the user hasn't attempted
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub enum Strategy {
+    // Any of them, after the introductory puzzles
+    #[default]
+    Random,
+    // Prefer puzzles with themes the user solves less often, see `Weaknesses`. Skips the introductory puzzles
+    Weaknesses,
+}
+
+// The user's rated attempts at puzzles with a theme
+#[derive(Clone, Copy, Debug, Default)]
+pub struct ThemeStats {
+    pub num_attempts: u32,
+    pub num_solved: u32,
+}
+
+pub trait SelectionStrategy: Send + Sync {
+    // How likely a puzzle with these themes is to be picked, relative to the others. Must be positive
+    fn weight(&self, themes: &BTreeSet<String>, stats: &BTreeMap<String, ThemeStats>) -> f64;
+}
+
+// Weights each puzzle by how rarely the user solves its themes. Solve rates are smoothed towards 50%,
+// so that a theme attempted once doesn't dominate, and themes the user hasn't seen count as 50%.
+// A puzzle with only themes the user always fails is picked up to `1 + MAX_BOOST` times as often as one without themes
+pub struct Weaknesses;
+
+const MAX_BOOST: f64 = 3.0;
+// Attempts' worth of 50% added to every theme's solve rate
+const PRIOR_ATTEMPTS: f64 = 2.0;
+
+impl SelectionStrategy for Weaknesses {
+    fn weight(&self, themes: &BTreeSet<String>, stats: &BTreeMap<String, ThemeStats>) -> f64 {
+        if themes.is_empty() {
+            return 1.0;
+        }
+        let failure_rate: f64 = themes
+            .iter()
+            .map(|theme| {
+                let theme_stats = stats.get(theme).copied().unwrap_or_default();
+                let solve_rate = (theme_stats.num_solved as f64 + PRIOR_ATTEMPTS / 2.0)
+                    / (theme_stats.num_attempts as f64 + PRIOR_ATTEMPTS);
+                1.0 - solve_rate
+            })
+            .sum::<f64>()
+            / themes.len() as f64;
+        1.0 + failure_rate * MAX_BOOST
+    }
+}
+
+pub fn read_theme_stats(
+    db_conn: &Connection,
+    username: &str,
+) -> anyhow::Result<BTreeMap<String, ThemeStats>> {
+    let mut stmt = db_conn.prepare(
+        "SELECT puzzle_themes.theme, COUNT(*), SUM(rated_attempts.solved) FROM rated_attempts
+        JOIN puzzle_themes ON puzzle_themes.puzzle_id = rated_attempts.puzzle_id
+        WHERE rated_attempts.username = ?1
+        GROUP BY puzzle_themes.theme",
+    )?;
+    let stats = stmt
+        .query_map([username], |row| {
+            Ok((
+                row.get(0)?,
+                ThemeStats {
+                    num_attempts: row.get(1)?,
+                    num_solved: row.get(2)?,
+                },
+            ))
+        })?
+        .collect::<Result<_, _>>()?;
+    Ok(stats)
+}
+
+// A published puzzle the user has never attempted, picked at random in proportion to the strategy's weights
+pub fn select_puzzle(
+    db_conn: &Connection,
+    strategy: &dyn SelectionStrategy,
+    username: &str,
+    filter: &PuzzleFilter,
+) -> anyhow::Result<Option<PuzzleRow>> {
+    let stats = read_theme_stats(db_conn, username)?;
+    let mut stmt = db_conn.prepare(&format!(
+        "SELECT puzzles.id, puzzle_themes.theme FROM puzzles
+        LEFT JOIN puzzle_themes ON puzzle_themes.puzzle_id = puzzles.id
+        WHERE puzzles.published = 1
+            AND puzzles.id NOT IN (
+                SELECT puzzle_id FROM puzzle_attempts WHERE user_id = (SELECT id FROM users WHERE username = ?1)
+            )
+            AND {PUZZLE_FILTER}"
+    ))?;
+    let mut candidates: BTreeMap<u32, BTreeSet<String>> = BTreeMap::new();
+    let mut rows = stmt.query(rusqlite::params![
+        username,
+        filter.player,
+        filter.sizes_json()
+    ])?;
+    while let Some(row) = rows.next()? {
+        let themes = candidates.entry(row.get(0)?).or_default();
+        if let Some(theme) = row.get::<_, Option<String>>(1)? {
+            themes.insert(theme);
+        }
+    }
+
+    let weights: Vec<(u32, f64)> = candidates
+        .iter()
+        .map(|(&id, themes)| (id, strategy.weight(themes, &stats)))
+        .collect();
+    let total: f64 = weights.iter().map(|(_, weight)| weight).sum();
+    if weights.is_empty() || total <= 0.0 {
+        return Ok(None);
+    }
+    let mut target = rand::rng().random_range(0.0..total);
+    let mut picked = weights[weights.len() - 1].0;
+    for (id, weight) in weights {
+        if target < weight {
+            picked = id;
+            break;
+        }
+        target -= weight;
+    }
+    let mut stmt = db_conn.prepare("SELECT * FROM puzzles WHERE id = ?1")?;
+    Ok(stmt
+        .query_and_then([picked], from_row::<PuzzleRow>)?
+        .next()
+        .transpose()?)
+}
@@ -0,0 +1,256 @@
+use axum::{
+    Json,
+    extract::{Path, Query, State},
+    http::StatusCode,
+};
+use rusqlite::Connection;
+use serde::{Deserialize, Serialize};
+use utoipa::{IntoParams, ToSchema};
+
+use crate::{
+    AppState,
+    admin::AdminAuth,
+    audit, db,
+    notifications::{self, NotificationKind},
+    pagination::{Page, PageQuery},
+    storage,
+    validation::{self, ApiError, ValidationError},
+    webhooks::{self, WebhookEvent},
+};
+
+pub const MAX_REASON_LENGTH: usize = 1000;
+
+// Users report puzzles that look broken, like a wrong solution or a position that isn't a win.
+// Reports stay unresolved until an admin resolves them
+pub fn init_db_tables(db_conn: &Connection) -> anyhow::Result<()> {
+    db_conn.execute(
+        "CREATE TABLE IF NOT EXISTS puzzle_reports (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            puzzle_id INTEGER NOT NULL,
+            username TEXT NOT NULL,
+            reason TEXT NOT NULL,
+            created_seconds INTEGER NOT NULL DEFAULT (strftime('%s', 'now')),
+            resolved_seconds INTEGER,
+            FOREIGN KEY (puzzle_id) REFERENCES puzzles(id)
+        )",
+        [],
+    )?;
+
+    Ok(())
+}
+
+#[derive(Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct NewReport {
+    username: String,
+    reason: String,
+}
+
+#[derive(Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct Report {
+    id: i64,
+    puzzle_id: u32,
+    username: String,
+    reason: String,
+    created_seconds: u64,
+    // Not set while the report is unresolved
+    resolved_seconds: Option<u64>,
+}
+
+#[derive(Serialize, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+#[serde(rename_all = "camelCase")]
+pub struct ReportsQuery {
+    // Only list unresolved reports
+    #[serde(default)]
+    unresolved: bool,
+}
+
+fn validate_reason(reason: &str) -> Result<(), ValidationError> {
+    if reason.trim().is_empty() {
+        return Err(ValidationError::new("reason", "Reason can't be empty"));
+    }
+    if reason.chars().count() > MAX_REASON_LENGTH {
+        return Err(ValidationError::new(
+            "reason",
+            format!("Reason can't be longer than {MAX_REASON_LENGTH} characters"),
+        ));
+    }
+    Ok(())
+}
+
+// Report a puzzle as broken
+#[utoipa::path(
+    post,
+    path = "/puzzles/{id}/reports",
+    tag = "reports",
+    params(("id" = u32, Path)),
+    request_body = NewReport,
+    responses(
+        (status = 200, body = Report),
+        (status = 400, body = validation::ValidationError),
+        (status = 404),
+    ),
+)]
+pub async fn create_report(
+    State(state): State<AppState>,
+    Path(id): Path<u32>,
+    Json(payload): Json<NewReport>,
+) -> Result<Json<Report>, ApiError> {
+    validation::validate_username(&payload.username)?;
+    validate_reason(&payload.reason)?;
+    let username = validation::canonical_username(&payload.username);
+    let db_conn = db::open().map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    storage::read_puzzle_by_id(&db_conn, id)
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+        .filter(|puzzle| puzzle.published)
+        .ok_or(StatusCode::NOT_FOUND)?;
+    let report = db_conn
+        .query_row(
+            "INSERT INTO puzzle_reports (puzzle_id, username, reason) VALUES (?1, ?2, ?3)
+            RETURNING id, puzzle_id, username, reason, created_seconds, resolved_seconds",
+            rusqlite::params![id, username, payload.reason],
+            read_report,
+        )
+        .map_err(|e| {
+            tracing::error!("Error writing report to database: {:?}", e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+    webhooks::enqueue(&db_conn, WebhookEvent::ReportFiled, &report).map_err(|e| {
+        tracing::error!("Error queueing webhooks for report: {:?}", e);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+    state.discord.notify(format!(
+        "Puzzle {} was reported by {}: {}",
+        report.puzzle_id, report.username, report.reason
+    ));
+    Ok(Json(report))
+}
+
+// List reports, newest first
+#[utoipa::path(
+    get,
+    path = "/admin/reports",
+    tag = "reports",
+    params(ReportsQuery, PageQuery),
+    security(("admin_token" = [])),
+    responses(
+        (status = 200, body = Page<Report>),
+        (status = 400, body = validation::ValidationError),
+        (status = 401),
+        (status = 403),
+    ),
+)]
+pub async fn get_reports(
+    _: AdminAuth,
+    Query(query): Query<ReportsQuery>,
+    Query(page): Query<PageQuery>,
+) -> Result<Json<Page<Report>>, ApiError> {
+    let db_conn = db::open().map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let page = page.fetch(
+        |after, limit| read_reports(&db_conn, query.unresolved, after, limit),
+        |report| report.id,
+    )?;
+    Ok(Json(page))
+}
+
+// Mark a report as resolved, and let the user who filed it know.
+// Resolving it again keeps the time it was first resolved, and doesn't notify them again
+#[utoipa::path(
+    post,
+    path = "/admin/reports/{id}/resolve",
+    tag = "reports",
+    params(("id" = i64, Path)),
+    security(("admin_token" = [])),
+    responses(
+        (status = 200, body = Report),
+        (status = 401),
+        (status = 403),
+        (status = 404),
+    ),
+)]
+pub async fn resolve_report(
+    admin: AdminAuth,
+    Path(id): Path<i64>,
+) -> Result<Json<Report>, StatusCode> {
+    let mut db_conn = db::open().map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let transaction = db_conn
+        .transaction()
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let was_resolved: bool = transaction
+        .query_row(
+            "SELECT resolved_seconds IS NOT NULL FROM puzzle_reports WHERE id = ?1",
+            [id],
+            |row| row.get(0),
+        )
+        .map_err(|e| match e {
+            rusqlite::Error::QueryReturnedNoRows => StatusCode::NOT_FOUND,
+            e => {
+                tracing::error!("Error reading report: {:?}", e);
+                StatusCode::INTERNAL_SERVER_ERROR
+            }
+        })?;
+    let report = transaction
+        .query_row(
+            "UPDATE puzzle_reports SET resolved_seconds = COALESCE(resolved_seconds, strftime('%s', 'now'))
+            WHERE id = ?1
+            RETURNING id, puzzle_id, username, reason, created_seconds, resolved_seconds",
+            [id],
+            read_report,
+        )
+        .map_err(|e| {
+            tracing::error!("Error resolving report: {:?}", e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+    if !was_resolved {
+        notifications::notify(
+            &transaction,
+            &report.username,
+            NotificationKind::ReportResolved,
+            &report,
+        )
+        .map_err(|e| {
+            tracing::error!("Error notifying user: {:?}", e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+    }
+    audit::record_for(
+        &transaction,
+        &admin,
+        "resolve_report",
+        serde_json::json!({"id": id}),
+    )?;
+    transaction
+        .commit()
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    Ok(Json(report))
+}
+
+fn read_report(row: &rusqlite::Row) -> rusqlite::Result<Report> {
+    Ok(Report {
+        id: row.get(0)?,
+        puzzle_id: row.get(1)?,
+        username: row.get(2)?,
+        reason: row.get(3)?,
+        created_seconds: row.get(4)?,
+        resolved_seconds: row.get(5)?,
+    })
+}
+
+fn read_reports(
+    db_conn: &Connection,
+    unresolved: bool,
+    after: Option<i64>,
+    limit: u32,
+) -> anyhow::Result<Vec<Report>> {
+    let mut stmt = db_conn.prepare(
+        "SELECT id, puzzle_id, username, reason, created_seconds, resolved_seconds
+        FROM puzzle_reports
+        WHERE (?1 IS NULL OR id < ?1) AND (?2 = 0 OR resolved_seconds IS NULL)
+        ORDER BY id DESC
+        LIMIT ?3",
+    )?;
+    let rows = stmt.query_map(rusqlite::params![after, unresolved, limit], read_report)?;
+    Ok(rows.collect::<Result<Vec<_>, _>>()?)
+}
@@ -0,0 +1,87 @@
+use rusqlite::Connection;
+
+use crate::{config::RetentionConfig, db, now_seconds, users, validation};
+
+// Guests and abandoned attempts in progress pile up forever otherwise, since nothing else removes them.
+// See `config::RetentionConfig`. Off by default, since it deletes data
+
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct Purged {
+    pub num_guests: usize,
+    pub num_attempts: usize,
+    pub num_in_progress: usize,
+}
+
+// Remove everything that's older than the configured limits, in a single transaction.
+// Guests' rated attempts are kept so that puzzle ratings don't change, like when users are deleted
+pub fn purge(db_conn: &mut Connection, config: &RetentionConfig) -> anyhow::Result<Purged> {
+    let now = now_seconds();
+    let transaction = db_conn.transaction()?;
+
+    let in_progress_cutoff_ms = now.saturating_sub(config.in_progress_days * 86400) * 1000;
+    let num_in_progress = transaction.execute(
+        "DELETE FROM attempts_in_progress WHERE started_ms <= ?1",
+        [in_progress_cutoff_ms],
+    )?;
+
+    let guest_cutoff_seconds = now.saturating_sub(config.guest_inactive_days * 86400);
+    let guests = {
+        let mut stmt = transaction.prepare(
+            "SELECT username FROM users
+            WHERE deleted_seconds IS NULL AND substr(username, 1, length(?1)) = ?1
+                AND NOT EXISTS (SELECT 1 FROM puzzle_attempts
+                    WHERE user_id = users.id AND timestamp_seconds > ?2)
+                AND NOT EXISTS (SELECT 1 FROM attempts_in_progress
+                    WHERE attempts_in_progress.username = users.username)",
+        )?;
+        stmt.query_map(
+            rusqlite::params![
+                validation::canonical_username(&config.guest_prefix),
+                guest_cutoff_seconds
+            ],
+            |row| row.get::<_, String>(0),
+        )?
+        .collect::<Result<Vec<_>, _>>()?
+    };
+
+    let mut num_attempts = 0;
+    for username in &guests {
+        transaction.execute("DELETE FROM attempt_moves WHERE username = ?1", [username])?;
+        transaction.execute(
+            "DELETE FROM attempt_variants WHERE username = ?1
+                AND NOT EXISTS (SELECT 1 FROM rated_attempts
+                    WHERE rated_attempts.username = attempt_variants.username
+                        AND rated_attempts.puzzle_id = attempt_variants.puzzle_id
+                        AND rated_attempts.attempt_number = attempt_variants.attempt_number)",
+            [username],
+        )?;
+        num_attempts += transaction.execute(
+            "DELETE FROM puzzle_attempts
+            WHERE user_id = (SELECT id FROM users WHERE username = ?1)
+                AND (attempt_number > 1 OR practice = 1)",
+            [username],
+        )?;
+        users::write_delete(&transaction, username)?;
+    }
+
+    transaction.commit()?;
+    Ok(Purged {
+        num_guests: guests.len(),
+        num_attempts,
+        num_in_progress,
+    })
+}
+
+// The `purge-old-data` job, see `scheduler.rs`
+pub async fn run_purge(config: RetentionConfig) -> anyhow::Result<()> {
+    let purged = purge(&mut db::open()?, &config)?;
+    if purged != Purged::default() {
+        tracing::info!(
+            "Deleted {} inactive guests, {} of their attempts and {} old attempts in progress",
+            purged.num_guests,
+            purged.num_attempts,
+            purged.num_in_progress
+        );
+    }
+    Ok(())
+}
@@ -0,0 +1,151 @@
+use rusqlite::{Connection, OptionalExtension};
+use serde::{Deserialize, Serialize};
+use skillratings::glicko2::Glicko2Rating;
+use utoipa::ToSchema;
+
+use crate::{ratings::RatingChange, seasons::SeasonRating};
+
+// A user who fails their rated attempt at a puzzle gets one more try, by sending their next attempt at it with `retry` set.
+// If the retry solves the puzzle, the first attempt is rated as a draw against the puzzle instead of a loss.
+// The retry itself is stored like any later attempt, with `retry_of` pointing at the failed one, which gets `solved_on_retry`.
+// The failed attempt has already been rated as a loss by then, so the ratings from before it are kept here
+// to rate it again. That's only right while it's still the user's latest rated attempt,
+// so the retry has to come before any other rated attempt, and before any other attempt at the puzzle
+
+pub fn init_db_tables(db_conn: &Connection) -> anyhow::Result<()> {
+    // The season columns are null if seasons were off
+    db_conn.execute(
+        "CREATE TABLE IF NOT EXISTS retryable_attempts (
+            username TEXT PRIMARY KEY,
+            puzzle_id INTEGER NOT NULL,
+            attempt_number INTEGER NOT NULL,
+            rating REAL NOT NULL,
+            deviation REAL NOT NULL,
+            volatility REAL NOT NULL,
+            puzzle_rating REAL NOT NULL,
+            puzzle_deviation REAL NOT NULL,
+            puzzle_volatility REAL NOT NULL,
+            season_start TEXT,
+            season_rating REAL,
+            season_deviation REAL,
+            season_volatility REAL
+        )",
+        [],
+    )?;
+
+    Ok(())
+}
+
+// A failed rated attempt that can still be retried, with the ratings it was rated with
+pub struct Retryable {
+    pub attempt_number: u32,
+    // The user's rating from before the attempt
+    pub rating: Glicko2Rating,
+    // The puzzle's rating from before the attempt
+    pub puzzle_rating: Glicko2Rating,
+    pub season_rating: Option<SeasonRating>,
+}
+
+// How a retry went, for the solve response
+#[derive(Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct RetryResult {
+    // The number of the failed attempt that was retried
+    pub retry_of: u32,
+    // The two attempts together, as the first attempt is rated: 0.5 if the retry solved the puzzle, 0 if not
+    pub score: f64,
+    // How rating the first attempt as a draw changed the user's rating, from their rating after the failure.
+    // Only set if the retry solved the puzzle
+    pub rating_change: Option<RatingChange>,
+}
+
+// Make the user's failed rated attempt the one they can retry, replacing any earlier one
+pub fn remember(
+    db_conn: &Connection,
+    username: &str,
+    puzzle_id: u32,
+    retryable: &Retryable,
+) -> anyhow::Result<()> {
+    let season = retryable.season_rating.as_ref();
+    db_conn
+        .prepare_cached(
+            "INSERT OR REPLACE INTO retryable_attempts (username, puzzle_id, attempt_number, rating, deviation, volatility,
+                puzzle_rating, puzzle_deviation, puzzle_volatility, season_start, season_rating, season_deviation,
+                season_volatility)
+            VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13)",
+        )?
+        .execute(rusqlite::params![
+            username,
+            puzzle_id,
+            retryable.attempt_number,
+            retryable.rating.rating,
+            retryable.rating.deviation,
+            retryable.rating.volatility,
+            retryable.puzzle_rating.rating,
+            retryable.puzzle_rating.deviation,
+            retryable.puzzle_rating.volatility,
+            season.map(|season| &season.season_start),
+            season.map(|season| season.rating.rating),
+            season.map(|season| season.rating.deviation),
+            season.map(|season| season.rating.volatility),
+        ])?;
+    Ok(())
+}
+
+// Called for every attempt that isn't a retry. Afterwards the user's failed attempt can no longer be retried
+// if this attempt was rated, or was at the same puzzle
+pub fn attempted(
+    db_conn: &Connection,
+    username: &str,
+    puzzle_id: u32,
+    rated: bool,
+) -> anyhow::Result<()> {
+    db_conn
+        .prepare_cached(
+            "DELETE FROM retryable_attempts WHERE username = ?1 AND (?3 OR puzzle_id = ?2)",
+        )?
+        .execute(rusqlite::params![username, puzzle_id, rated])?;
+    Ok(())
+}
+
+// The user's failed attempt at the puzzle, if they can retry it. It can't be retried again afterwards
+pub fn take(
+    db_conn: &Connection,
+    username: &str,
+    puzzle_id: u32,
+) -> anyhow::Result<Option<Retryable>> {
+    Ok(db_conn
+        .prepare_cached(
+            "DELETE FROM retryable_attempts WHERE username = ?1 AND puzzle_id = ?2
+            RETURNING attempt_number, rating, deviation, volatility, puzzle_rating, puzzle_deviation, puzzle_volatility,
+                season_start, season_rating, season_deviation, season_volatility",
+        )?
+        .query_row(rusqlite::params![username, puzzle_id], |row| {
+            let season_rating = match row.get::<_, Option<String>>(7)? {
+                Some(season_start) => Some(SeasonRating {
+                    season_start,
+                    rating: Glicko2Rating {
+                        rating: row.get(8)?,
+                        deviation: row.get(9)?,
+                        volatility: row.get(10)?,
+                    },
+                }),
+                None => None,
+            };
+            Ok(Retryable {
+                attempt_number: row.get(0)?,
+                rating: Glicko2Rating {
+                    rating: row.get(1)?,
+                    deviation: row.get(2)?,
+                    volatility: row.get(3)?,
+                },
+                puzzle_rating: Glicko2Rating {
+                    rating: row.get(4)?,
+                    deviation: row.get(5)?,
+                    volatility: row.get(6)?,
+                },
+                season_rating,
+            })
+        })
+        .optional()?)
+}
@@ -0,0 +1,278 @@
+use axum::{
+    Router,
+    http::HeaderValue,
+    response::Response,
+    routing::{delete, get, post, put},
+};
+
+use crate::{
+    AppState, achievements, admin, attempts, audit, auth, backups, bans, campaign, collections,
+    daily, dashboard, events, experiments, explanations, exports, feed, friends, graphql, health,
+    in_progress, leaderboard, live, notifications, openapi, progress, puzzle_packs, puzzle_sets,
+    races, rating_history, read_only, reports, scheduler, search, seasons, sessions, settings,
+    stats, svg, teams, telemetry, themes, tournaments, users, webhooks,
+};
+
+// A request's path without the version prefix like `/v1` or the leading slash, for middleware that sees every version
+pub fn unversioned_path(path: &str) -> &str {
+    match path
+        .strip_prefix("/v")
+        .and_then(|rest| rest.split_once('/'))
+    {
+        Some((version, rest))
+            if !version.is_empty() && version.bytes().all(|b| b.is_ascii_digit()) =>
+        {
+            rest
+        }
+        _ => path.trim_start_matches('/'),
+    }
+}
+
+// Every version of the API is nested under its own prefix, like `/v1`.
+// A breaking change gets a new version with its own routes function, served side by side with the old one,
+// so that clients can move over at their own pace. Handlers that didn't change can be shared between versions
+pub fn router() -> Router<AppState> {
+    Router::new()
+        .nest("/v1", v1())
+        // The original unversioned paths, kept as aliases of `/v1` while clients move over
+        .merge(v1().layer(axum::middleware::map_response(mark_deprecated)))
+        .route("/healthz", get(health::get_health))
+        .route("/livez", get(health::get_liveness))
+        .route("/readyz", get(health::get_readiness))
+        .route("/metrics", get(telemetry::get_metrics))
+        .route("/openapi.json", get(openapi::get_openapi_spec))
+        .route("/docs", get(openapi::get_swagger_ui))
+}
+
+fn v1() -> Router<AppState> {
+    Router::new()
+        .route("/puzzles/ratings", get(crate::get_puzzle_ratings))
+        .route("/puzzles/{id}/rating", get(crate::get_puzzle_rating))
+        .route("/puzzles/{id}/move-times", get(attempts::get_move_times))
+        .route("/puzzles/{id}/solve-times", get(attempts::get_solve_times))
+        .route(
+            "/puzzles/{id}/rating-history",
+            get(rating_history::get_puzzle_rating_history),
+        )
+        .route("/puzzles", get(crate::get_puzzle))
+        .route("/puzzles/feed.atom", get(feed::get_feed))
+        .route("/puzzles/export", get(puzzle_packs::export_puzzles))
+        .route("/puzzles/search", get(search::search_puzzles))
+        .route("/puzzles/current", get(in_progress::get_current_attempt))
+        .route("/puzzles/current/moves", post(in_progress::add_move))
+        .route(
+            "/puzzles/{id}",
+            get(crate::get_puzzle_by_id).post(crate::solve_puzzle),
+        )
+        .route("/puzzles/{id}/reports", post(reports::create_report))
+        .route("/puzzles/{id}/image.svg", get(svg::get_puzzle_image))
+        .route(
+            "/puzzles/{id}/solution.svg",
+            get(svg::get_solution_animation),
+        )
+        .route("/puzzles/{id}/themes", get(themes::get_themes))
+        .route("/puzzles/{id}/similar", get(themes::get_similar_puzzles))
+        .route(
+            "/puzzles/{id}/explanation",
+            get(explanations::get_explanation),
+        )
+        .route("/puzzle-sets", get(puzzle_sets::get_puzzle_sets))
+        .route(
+            "/puzzle-sets/{id}/next",
+            get(puzzle_sets::get_next_puzzle_in_set),
+        )
+        .route(
+            "/puzzle-sets/{id}/progress",
+            get(puzzle_sets::get_puzzle_set_progress),
+        )
+        .route("/collections", post(collections::create_collection))
+        .route("/collections/{slug}", get(collections::get_collection))
+        .route(
+            "/collections/{slug}/next",
+            get(collections::get_next_puzzle_in_collection),
+        )
+        .route(
+            "/collections/{slug}/puzzles",
+            post(collections::add_puzzle_to_collection),
+        )
+        .route(
+            "/collections/{slug}/puzzles/{puzzle_id}",
+            delete(collections::remove_puzzle_from_collection),
+        )
+        .route("/campaign", get(campaign::get_campaign))
+        .route(
+            "/campaign/chapters/{id}/next",
+            get(campaign::get_next_puzzle_in_chapter),
+        )
+        .route(
+            "/users/{username}/achievements",
+            get(achievements::get_user_achievements),
+        )
+        .route(
+            "/users/{username}/progress",
+            get(progress::get_user_progress),
+        )
+        .route(
+            "/users/{username}/puzzle-progress",
+            get(progress::get_puzzle_progress),
+        )
+        .route(
+            "/users/{username}/attempts",
+            get(attempts::get_attempt_history),
+        )
+        .route(
+            "/users/{username}/attempts.ptn",
+            get(exports::export_attempts_ptn),
+        )
+        .route(
+            "/users/{username}/rating-history",
+            get(rating_history::get_user_rating_history),
+        )
+        .route("/attempts/{id}/replay", get(attempts::get_attempt_replay))
+        .route("/daily", get(daily::get_daily_puzzle))
+        .route("/embed/daily", get(daily::get_embedded_daily_puzzle))
+        .route(
+            "/embed/daily/{day}",
+            get(daily::get_embedded_puzzle_for_day),
+        )
+        .route("/graphql", get(graphql::graphiql).post(graphql::graphql))
+        .route("/leaderboard", get(leaderboard::get_leaderboard))
+        .route("/stats", get(stats::get_stats))
+        .route("/leaderboard/snapshots", get(leaderboard::get_snapshots))
+        .route("/seasons", get(seasons::get_seasons))
+        .route("/seasons/{start}/standings", get(seasons::get_standings))
+        .route("/users/{username}/ratings", get(seasons::get_user_ratings))
+        .route(
+            "/users/{username}/settings",
+            get(settings::get_settings).put(settings::set_settings),
+        )
+        .route(
+            "/users/{username}/notifications",
+            get(notifications::get_notifications),
+        )
+        .route(
+            "/users/{username}/notifications/read",
+            post(notifications::mark_read),
+        )
+        .route("/auth/magic-link", post(auth::send_magic_link))
+        .route("/auth/magic-link/verify", post(auth::verify_magic_link))
+        .route("/auth/refresh", post(sessions::refresh))
+        .route("/auth/logout", post(sessions::logout))
+        .route("/auth/session", get(sessions::get_current_user))
+        .route("/auth/sessions", get(sessions::get_sessions))
+        .route("/auth/sessions/{id}", delete(sessions::delete_session))
+        .route(
+            "/admin/users/{username}/sessions",
+            delete(sessions::revoke_user_sessions),
+        )
+        .route(
+            "/leaderboard/snapshots/{period}/{start}",
+            get(leaderboard::get_snapshot),
+        )
+        .route(
+            "/users/{username}/follow",
+            post(friends::follow_user).delete(friends::unfollow_user),
+        )
+        .route("/users/{username}/following", get(friends::get_following))
+        .route(
+            "/users/{username}/following/leaderboard",
+            get(friends::get_following_leaderboard),
+        )
+        .route(
+            "/users/{username}/following/daily",
+            get(friends::get_following_daily),
+        )
+        .route("/races/{room}/ws", get(races::race_socket))
+        .route("/ws", get(live::live_socket))
+        .route("/events", get(events::get_events))
+        .route("/admin/tournaments", post(tournaments::create_tournament))
+        .route(
+            "/admin/users/{username}/rating-exclusion",
+            post(admin::set_rating_exclusion),
+        )
+        .route(
+            "/admin/users/{username}/ban",
+            post(bans::ban_user).delete(bans::unban_user),
+        )
+        .route("/admin/bans", get(bans::get_bans))
+        .route("/admin/users/{username}/rename", post(users::rename_user))
+        .route("/admin/users/merge", post(users::merge_users))
+        .route("/users/{username}", delete(users::delete_user))
+        .route("/users/{username}/export", get(users::export_user))
+        .route(
+            "/admin/rating-clamps",
+            get(rating_history::get_rating_clamps),
+        )
+        .route("/admin/dashboard", get(dashboard::get_dashboard))
+        .route("/admin/export/attempts.csv", get(exports::export_attempts))
+        .route("/admin/audit-log", get(audit::get_audit_log))
+        .route("/admin/jobs", get(scheduler::get_jobs))
+        .route("/admin/jobs/{name}/run", post(scheduler::run_job))
+        .route("/admin/backups", post(backups::create_backup))
+        .route(
+            "/admin/read-only",
+            get(read_only::get_read_only).post(read_only::set_read_only),
+        )
+        .route(
+            "/admin/webhooks",
+            get(webhooks::get_webhooks).post(webhooks::create_webhook),
+        )
+        .route("/admin/webhooks/{id}", delete(webhooks::delete_webhook))
+        .route("/admin/puzzles/{id}/themes", put(themes::set_themes))
+        .route(
+            "/admin/puzzles/{id}/explanation",
+            put(explanations::set_explanation),
+        )
+        .route("/admin/reports", get(reports::get_reports))
+        .route("/admin/reports/{id}/resolve", post(reports::resolve_report))
+        .route("/admin/experiments", get(experiments::get_experiments))
+        .route(
+            "/admin/experiments/{name}",
+            get(experiments::get_experiment),
+        )
+        .route(
+            "/admin/experiments/{name}/start",
+            post(experiments::start_experiment),
+        )
+        .route(
+            "/admin/experiments/{name}/stop",
+            post(experiments::stop_experiment),
+        )
+        .route("/tournaments", get(tournaments::get_tournaments))
+        .route("/tournaments/{id}", get(tournaments::get_tournament))
+        .route(
+            "/tournaments/{id}/register",
+            post(tournaments::register_for_tournament),
+        )
+        .route(
+            "/tournaments/{id}/next",
+            get(tournaments::get_next_tournament_puzzle),
+        )
+        .route(
+            "/tournaments/{id}/attempts",
+            post(tournaments::submit_tournament_attempt),
+        )
+        .route(
+            "/tournaments/{id}/standings",
+            get(tournaments::get_tournament_standings),
+        )
+        .route(
+            "/teams",
+            get(teams::get_team_leaderboard).post(teams::create_team),
+        )
+        .route("/teams/weekly", get(teams::get_weekly_competition))
+        .route("/teams/{id}", get(teams::get_team))
+        .route("/teams/{id}/join", post(teams::join_team))
+        .route("/teams/{id}/leave", post(teams::leave_team))
+        .route(
+            "/users/{username}/collections",
+            get(collections::get_collections_for_user),
+        )
+}
+
+async fn mark_deprecated(mut response: Response) -> Response {
+    response
+        .headers_mut()
+        .insert("deprecation", HeaderValue::from_static("true"));
+    response
+}
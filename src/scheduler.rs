@@ -0,0 +1,476 @@
+use std::{future::Future, pin::Pin, str::FromStr, time::Duration};
+
+use axum::{
+    Json,
+    extract::{Path, State},
+    http::StatusCode,
+};
+use rusqlite::{Connection, OptionalExtension};
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use crate::{
+    AppState, admin::AdminAuth, audit, backups, config::Config, daily, db, in_progress,
+    leaderboard, maintenance, now_seconds, retention, webhooks,
+};
+
+// Periodic work, like expiring abandoned attempts. Each job runs on a cron schedule, which can be changed
+// in `scheduler.schedules`. A job is never run twice at once: a scheduled run is skipped if the previous one,
+// or one started with `POST /admin/jobs/{name}/run`, is still going.
+// To add a job, add it to `JOBS`
+
+type JobFuture = Pin<Box<dyn Future<Output = anyhow::Result<()>> + Send>>;
+
+pub struct Job {
+    pub name: &'static str,
+    pub default_schedule: &'static str,
+    // Jobs that are turned off in their own config section aren't scheduled, but can still be run by admins
+    pub enabled: fn(&Config) -> bool,
+    pub run: fn(AppState) -> JobFuture,
+}
+
+pub const JOBS: &[Job] = &[
+    Job {
+        name: "expire-abandoned-attempts",
+        default_schedule: "* * * * *",
+        enabled: |_| true,
+        run: |state| Box::pin(in_progress::run_expiry(state)),
+    },
+    Job {
+        name: "leaderboard-snapshots",
+        default_schedule: "0 * * * *",
+        enabled: |_| true,
+        run: |_| Box::pin(leaderboard::run_snapshots()),
+    },
+    Job {
+        name: "purge-old-data",
+        default_schedule: "30 * * * *",
+        enabled: |config| config.retention.enabled,
+        run: |state| Box::pin(retention::run_purge(state.config.retention.clone())),
+    },
+    Job {
+        name: "backups",
+        default_schedule: "0 3 * * *",
+        enabled: |config| config.backups.enabled,
+        run: |state| Box::pin(backups::run_backup(state.config.backups.clone())),
+    },
+    Job {
+        name: "database-maintenance",
+        default_schedule: "30 4 * * *",
+        enabled: |_| true,
+        run: |_| Box::pin(maintenance::run_maintenance()),
+    },
+    Job {
+        name: "wal-checkpoint",
+        default_schedule: "*/5 * * * *",
+        enabled: |_| true,
+        run: |_| Box::pin(maintenance::run_checkpoint()),
+    },
+    Job {
+        name: "daily-puzzle",
+        default_schedule: "0 0 * * *",
+        enabled: |_| true,
+        run: |_| Box::pin(daily::run_pick()),
+    },
+    Job {
+        name: "webhook-deliveries",
+        default_schedule: "* * * * *",
+        enabled: |_| true,
+        run: |_| Box::pin(webhooks::run_deliveries()),
+    },
+];
+
+pub fn init_db_tables(db_conn: &Connection) -> anyhow::Result<()> {
+    // `running_since_seconds` is set while a run is in progress
+    db_conn.execute(
+        "CREATE TABLE IF NOT EXISTS scheduled_jobs (
+            name TEXT PRIMARY KEY,
+            running_since_seconds INTEGER,
+            last_started_seconds INTEGER,
+            last_finished_seconds INTEGER,
+            last_error TEXT,
+            num_runs INTEGER NOT NULL DEFAULT 0
+        )",
+        [],
+    )?;
+
+    Ok(())
+}
+
+// A cron expression with the usual five fields, minute, hour, day of month, month and day of week,
+// each a `*`, a number, a range like `1-5`, or a comma-separated list of those, optionally with a step like `*/15`.
+// Days of the week are 0 to 6 from Sunday, or 7 for Sunday. Times are in UTC.
+// `@hourly`, `@daily`, `@weekly` and `@monthly` work as well
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct Schedule {
+    expression: String,
+    minutes: u64,
+    hours: u64,
+    days_of_month: u64,
+    months: u64,
+    days_of_week: u64,
+    // Like cron, a day matches if either the day of month or the day of week does, unless one of them is `*`
+    any_day_of_month: bool,
+    any_day_of_week: bool,
+}
+
+impl FromStr for Schedule {
+    type Err = String;
+
+    fn from_str(expression: &str) -> Result<Self, Self::Err> {
+        let expanded = match expression.trim() {
+            "@hourly" => "0 * * * *",
+            "@daily" => "0 0 * * *",
+            "@weekly" => "0 0 * * 0",
+            "@monthly" => "0 0 1 * *",
+            expression => expression,
+        };
+        let fields: Vec<&str> = expanded.split_whitespace().collect();
+        let [minutes, hours, days_of_month, months, days_of_week] = fields[..] else {
+            return Err(format!(
+                "Schedule {expression:?} must have 5 fields, not {}",
+                fields.len()
+            ));
+        };
+        let mut days_of_week_bits = parse_field(days_of_week, 0, 7)?;
+        // Sunday is both 0 and 7
+        if days_of_week_bits & (1 << 7) != 0 {
+            days_of_week_bits |= 1;
+        }
+        Ok(Schedule {
+            expression: expression.to_string(),
+            minutes: parse_field(minutes, 0, 59)?,
+            hours: parse_field(hours, 0, 23)?,
+            days_of_month: parse_field(days_of_month, 1, 31)?,
+            months: parse_field(months, 1, 12)?,
+            days_of_week: days_of_week_bits,
+            any_day_of_month: days_of_month == "*",
+            any_day_of_week: days_of_week == "*",
+        })
+    }
+}
+
+impl TryFrom<String> for Schedule {
+    type Error = String;
+
+    fn try_from(expression: String) -> Result<Self, Self::Error> {
+        expression.parse()
+    }
+}
+
+impl From<Schedule> for String {
+    fn from(schedule: Schedule) -> Self {
+        schedule.expression
+    }
+}
+
+// The values in one field, as bits
+fn parse_field(field: &str, min: u32, max: u32) -> Result<u64, String> {
+    let mut bits = 0;
+    for part in field.split(',') {
+        let (range, step) = match part.split_once('/') {
+            Some((range, step)) => (
+                range,
+                step.parse::<u32>()
+                    .ok()
+                    .filter(|&step| step > 0)
+                    .ok_or_else(|| format!("Invalid step in {field:?}"))?,
+            ),
+            None => (part, 1),
+        };
+        let parse = |value: &str| {
+            value
+                .parse::<u32>()
+                .ok()
+                .filter(|value| (min..=max).contains(value))
+                .ok_or_else(|| format!("{value:?} in {field:?} isn't between {min} and {max}"))
+        };
+        let (start, end) = match range {
+            "*" => (min, max),
+            range => match range.split_once('-') {
+                Some((start, end)) => (parse(start)?, parse(end)?),
+                None if step > 1 => (parse(range)?, max),
+                None => (parse(range)?, parse(range)?),
+            },
+        };
+        if start > end {
+            return Err(format!("Empty range {range:?} in {field:?}"));
+        }
+        for value in (start..=end).step_by(step as usize) {
+            bits |= 1 << value;
+        }
+    }
+    Ok(bits)
+}
+
+impl Schedule {
+    // The first time the schedule matches that's strictly after `seconds`, to the minute.
+    // `None` if it never matches, like on February 30th
+    pub fn next_after(&self, seconds: u64) -> Option<u64> {
+        let mut minute = seconds / 60 + 1;
+        // Dates fall on the same days of the week every 28 years, at least until 2100,
+        // so a schedule that matches at all matches by then
+        let last_minute = minute + 28 * 366 * 24 * 60;
+        while minute < last_minute {
+            let day = minute / (24 * 60);
+            if !self.matches_day(day) {
+                minute = (day + 1) * 24 * 60;
+                continue;
+            }
+            let minute_of_day = minute % (24 * 60);
+            if self.hours & (1 << (minute_of_day / 60)) == 0 {
+                minute = (minute / 60 + 1) * 60;
+                continue;
+            }
+            if self.minutes & (1 << (minute_of_day % 60)) == 0 {
+                minute += 1;
+                continue;
+            }
+            return Some(minute * 60);
+        }
+        None
+    }
+
+    fn matches_day(&self, days_since_epoch: u64) -> bool {
+        let (_, month, day) = civil_from_days(days_since_epoch);
+        if self.months & (1 << month) == 0 {
+            return false;
+        }
+        // The epoch was a Thursday
+        let day_of_week = (days_since_epoch + 4) % 7;
+        let day_of_month_matches = self.days_of_month & (1 << day) != 0;
+        let day_of_week_matches = self.days_of_week & (1 << day_of_week) != 0;
+        match (self.any_day_of_month, self.any_day_of_week) {
+            (true, _) => day_of_week_matches,
+            (false, true) => day_of_month_matches,
+            (false, false) => day_of_month_matches || day_of_week_matches,
+        }
+    }
+}
+
+// `(year, month, day)` of a day counted from 1970-01-01, from Howard Hinnant's `civil_from_days`
+fn civil_from_days(days_since_epoch: u64) -> (u64, u64, u64) {
+    let z = days_since_epoch + 719468;
+    let era = z / 146097;
+    let day_of_era = z - era * 146097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = year_of_era + era * 400 + u64::from(month <= 2);
+    (year, month, day)
+}
+
+fn find_job(name: &str) -> Option<&'static Job> {
+    JOBS.iter().find(|job| job.name == name)
+}
+
+fn schedule(config: &Config, job: &Job) -> Schedule {
+    config
+        .scheduler
+        .schedules
+        .get(job.name)
+        .cloned()
+        .unwrap_or_else(|| job.default_schedule.parse().unwrap())
+}
+
+// Start every enabled job on its schedule. Fails if the config has a schedule for a job that doesn't exist
+pub fn spawn(state: AppState) -> anyhow::Result<()> {
+    if let Some(name) = state
+        .config
+        .scheduler
+        .schedules
+        .keys()
+        .find(|name| find_job(name).is_none())
+    {
+        anyhow::bail!("There's a schedule for {name:?}, but no job with that name");
+    }
+    // Only one server runs against the database, so any runs still marked as in progress were cut off by a restart
+    db::open()?.execute("UPDATE scheduled_jobs SET running_since_seconds = NULL", [])?;
+
+    for job in JOBS {
+        if !(job.enabled)(&state.config) {
+            continue;
+        }
+        let schedule = schedule(&state.config, job);
+        let state = state.clone();
+        tokio::spawn(async move {
+            loop {
+                let now = now_seconds();
+                let Some(next) = schedule.next_after(now) else {
+                    tracing::warn!("The schedule for job {} never matches", job.name);
+                    return;
+                };
+                tokio::time::sleep(Duration::from_secs(next - now)).await;
+                match run(&state, job).await {
+                    Ok(true) => (),
+                    Ok(false) => tracing::warn!(
+                        "Skipped job {}, since its previous run hasn't finished",
+                        job.name
+                    ),
+                    Err(e) => tracing::error!("Error running job {}: {:?}", job.name, e),
+                }
+            }
+        });
+    }
+    Ok(())
+}
+
+// Run the job now, unless it's already running. Returns whether it ran.
+// The job's own errors are returned as well as recorded
+pub async fn run(state: &AppState, job: &Job) -> anyhow::Result<bool> {
+    Ok(run_as(job.name, (job.run)(state.clone())).await?.is_some())
+}
+
+// Run `work` as a run of the named job, for endpoints that do a job's work and return its result.
+// Returns `None` without running it if the job is already running
+pub async fn run_as<T>(
+    name: &str,
+    work: impl Future<Output = anyhow::Result<T>>,
+) -> anyhow::Result<Option<T>> {
+    let started = db::open()?.execute(
+        "INSERT INTO scheduled_jobs (name, running_since_seconds, last_started_seconds) VALUES (?1, ?2, ?2)
+        ON CONFLICT (name) DO UPDATE SET running_since_seconds = excluded.running_since_seconds,
+            last_started_seconds = excluded.last_started_seconds
+        WHERE running_since_seconds IS NULL",
+        rusqlite::params![name, now_seconds()],
+    )?;
+    if started == 0 {
+        return Ok(None);
+    }
+    let result = work.await;
+    db::open()?.execute(
+        "UPDATE scheduled_jobs SET running_since_seconds = NULL, last_finished_seconds = ?2, last_error = ?3,
+            num_runs = num_runs + 1
+        WHERE name = ?1",
+        rusqlite::params![
+            name,
+            now_seconds(),
+            result.as_ref().err().map(|e| format!("{e:?}"))
+        ],
+    )?;
+    result.map(Some)
+}
+
+#[derive(Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct JobStatus {
+    name: String,
+    // As a cron expression
+    schedule: String,
+    // Whether the job runs on its schedule
+    enabled: bool,
+    // Not set if no run is in progress
+    running_since_seconds: Option<u64>,
+    last_started_seconds: Option<u64>,
+    last_finished_seconds: Option<u64>,
+    // Not set if the last run succeeded
+    last_error: Option<String>,
+    num_runs: u32,
+    next_run_seconds: Option<u64>,
+}
+
+fn read_status(db_conn: &Connection, config: &Config, job: &Job) -> anyhow::Result<JobStatus> {
+    let schedule = schedule(config, job);
+    let enabled = (job.enabled)(config);
+    let row = db_conn
+        .query_row(
+            "SELECT running_since_seconds, last_started_seconds, last_finished_seconds, last_error, num_runs
+            FROM scheduled_jobs WHERE name = ?1",
+            [job.name],
+            |row| {
+                Ok((
+                    row.get(0)?,
+                    row.get(1)?,
+                    row.get(2)?,
+                    row.get(3)?,
+                    row.get(4)?,
+                ))
+            },
+        )
+        .optional()?;
+    let (running_since_seconds, last_started_seconds, last_finished_seconds, last_error, num_runs) =
+        row.unwrap_or_default();
+    Ok(JobStatus {
+        name: job.name.to_string(),
+        next_run_seconds: enabled
+            .then(|| schedule.next_after(now_seconds()))
+            .flatten(),
+        schedule: schedule.into(),
+        enabled,
+        running_since_seconds,
+        last_started_seconds,
+        last_finished_seconds,
+        last_error,
+        num_runs,
+    })
+}
+
+// List the background jobs, with when they last ran and will next run
+#[utoipa::path(
+    get,
+    path = "/admin/jobs",
+    tag = "admin",
+    security(("admin_token" = [])),
+    responses(
+        (status = 200, body = Vec<JobStatus>),
+        (status = 401),
+        (status = 403),
+    ),
+)]
+pub async fn get_jobs(
+    _: AdminAuth,
+    State(state): State<AppState>,
+) -> Result<Json<Vec<JobStatus>>, StatusCode> {
+    let db_conn = db::open().map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    JOBS.iter()
+        .map(|job| read_status(&db_conn, &state.config, job))
+        .collect::<anyhow::Result<Vec<_>>>()
+        .map(Json)
+        .map_err(|e| {
+            tracing::error!("Error reading jobs: {:?}", e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })
+}
+
+// Run a job now, and wait for it to finish. Works for jobs that aren't enabled too.
+// If the job fails, the error is in `lastError`
+#[utoipa::path(
+    post,
+    path = "/admin/jobs/{name}/run",
+    tag = "admin",
+    params(("name" = String, Path)),
+    security(("admin_token" = [])),
+    responses(
+        (status = 200, body = JobStatus),
+        (status = 401),
+        (status = 403),
+        (status = 404),
+        (status = 409, description = "The job is already running"),
+    ),
+)]
+pub async fn run_job(
+    admin: AdminAuth,
+    State(state): State<AppState>,
+    Path(name): Path<String>,
+) -> Result<Json<JobStatus>, StatusCode> {
+    let job = find_job(&name).ok_or(StatusCode::NOT_FOUND)?;
+    audit::record_for(
+        &db::open().map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?,
+        &admin,
+        "run_job",
+        serde_json::json!({"name": job.name}),
+    )?;
+    match run(&state, job).await {
+        Ok(true) => (),
+        Ok(false) => return Err(StatusCode::CONFLICT),
+        Err(e) => tracing::error!("Error running job {}: {:?}", job.name, e),
+    }
+    let db_conn = db::open().map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    read_status(&db_conn, &state.config, job)
+        .map(Json)
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)
+}
@@ -0,0 +1,118 @@
+use axum::{Json, extract::Query, http::StatusCode};
+use rusqlite::Connection;
+use serde::Deserialize;
+use serde_rusqlite::from_row;
+use utoipa::IntoParams;
+
+use crate::{
+    Puzzle, PuzzleRow, db,
+    validation::{ApiError, ValidationError},
+};
+
+// Full-text search over puzzle metadata, with an FTS5 index that triggers keep in sync with `puzzles`
+
+const DEFAULT_LIMIT: u32 = 20;
+const MAX_LIMIT: u32 = 100;
+// Longer queries are almost certainly not typed by a person
+const MAX_QUERY_LENGTH: usize = 200;
+
+pub fn init_db_tables(db_conn: &Connection) -> anyhow::Result<()> {
+    // Indexes the columns of `puzzles` without storing them again. Filled for existing puzzles by a migration
+    db_conn.execute_batch(
+        "CREATE VIRTUAL TABLE IF NOT EXISTS puzzle_search USING fts5(
+            player_white, player_black, playtak_game_id,
+            content = 'puzzles', content_rowid = 'id'
+        );
+        CREATE TRIGGER IF NOT EXISTS puzzle_search_insert AFTER INSERT ON puzzles BEGIN
+            INSERT INTO puzzle_search (rowid, player_white, player_black, playtak_game_id)
+            VALUES (new.id, new.player_white, new.player_black, new.playtak_game_id);
+        END;
+        CREATE TRIGGER IF NOT EXISTS puzzle_search_delete AFTER DELETE ON puzzles BEGIN
+            INSERT INTO puzzle_search (puzzle_search, rowid, player_white, player_black, playtak_game_id)
+            VALUES ('delete', old.id, old.player_white, old.player_black, old.playtak_game_id);
+        END;
+        CREATE TRIGGER IF NOT EXISTS puzzle_search_update
+        AFTER UPDATE OF player_white, player_black, playtak_game_id ON puzzles BEGIN
+            INSERT INTO puzzle_search (puzzle_search, rowid, player_white, player_black, playtak_game_id)
+            VALUES ('delete', old.id, old.player_white, old.player_black, old.playtak_game_id);
+            INSERT INTO puzzle_search (rowid, player_white, player_black, playtak_game_id)
+            VALUES (new.id, new.player_white, new.player_black, new.playtak_game_id);
+        END;",
+    )?;
+    Ok(())
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct SearchQuery {
+    // Words to search for, like player names or a playtak game id. Every word must match,
+    // and words match the start of longer ones, so `EVRN` finds `EVRNjayhawker`
+    q: String,
+    limit: Option<u32>,
+}
+
+// Search published puzzles, best matches first
+#[utoipa::path(
+    get,
+    path = "/puzzles/search",
+    tag = "puzzles",
+    params(SearchQuery),
+    responses(
+        (status = 200, body = Vec<Puzzle>),
+        (status = 400, body = ValidationError),
+    ),
+)]
+pub async fn search_puzzles(
+    Query(query): Query<SearchQuery>,
+) -> Result<Json<Vec<Puzzle>>, ApiError> {
+    let limit = match query.limit {
+        None => DEFAULT_LIMIT,
+        Some(limit) if (1..=MAX_LIMIT).contains(&limit) => limit,
+        Some(_) => {
+            return Err(ValidationError::new(
+                "limit",
+                format!("Limit must be between 1 and {MAX_LIMIT}"),
+            )
+            .into());
+        }
+    };
+    if query.q.len() > MAX_QUERY_LENGTH {
+        return Err(ValidationError::new(
+            "q",
+            format!("Query is longer than {MAX_QUERY_LENGTH} characters"),
+        )
+        .into());
+    }
+    let Some(fts_query) = to_fts_query(&query.q) else {
+        return Err(ValidationError::new("q", "Query is empty").into());
+    };
+    let db_conn = db::open().map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let puzzles = search(&db_conn, &fts_query, limit).map_err(|e| {
+        tracing::error!("Error searching puzzles: {:?}", e);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+    Ok(Json(puzzles.into_iter().map(Puzzle::from).collect()))
+}
+
+// Every word as a quoted prefix, so that characters FTS5 would read as query syntax are matched literally
+fn to_fts_query(query: &str) -> Option<String> {
+    let terms: Vec<String> = query
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(|word| format!("\"{word}\"*"))
+        .collect();
+    (!terms.is_empty()).then(|| terms.join(" "))
+}
+
+fn search(db_conn: &Connection, fts_query: &str, limit: u32) -> anyhow::Result<Vec<PuzzleRow>> {
+    let mut stmt = db_conn.prepare(
+        "SELECT puzzles.* FROM puzzle_search
+        JOIN puzzles ON puzzles.id = puzzle_search.rowid
+        WHERE puzzle_search MATCH ?1 AND puzzles.published = 1
+        ORDER BY puzzle_search.rank, puzzles.id LIMIT ?2",
+    )?;
+    let rows = stmt
+        .query_and_then(rusqlite::params![fts_query, limit], from_row::<PuzzleRow>)?
+        .collect::<Result<_, _>>()?;
+    Ok(rows)
+}
@@ -0,0 +1,398 @@
+use axum::{
+    Json,
+    extract::{Path, State},
+    http::StatusCode,
+};
+use rusqlite::{Connection, OptionalExtension};
+use serde::{Deserialize, Serialize};
+use skillratings::glicko2::Glicko2Rating;
+use utoipa::ToSchema;
+
+use crate::{
+    AppState,
+    config::SeasonsConfig,
+    db, ratings,
+    validation::{self, ApiError},
+};
+
+// Seasons give everyone a fresh start every few months, without touching their lifetime rating.
+// Each season has its own ratings, which start at the default and are updated by the same rated attempts
+// as the lifetime ratings, against the same puzzle ratings. Puzzle ratings only use lifetime ratings.
+// Once a season ends its ratings aren't updated anymore, so they're its final standings.
+// `recompute-ratings` only recomputes lifetime ratings
+
+const STANDINGS_SIZE: u32 = 100;
+
+pub fn init_db_tables(db_conn: &Connection) -> anyhow::Result<()> {
+    // Seasons are added when they're first needed. Dates are in UTC, like `2025-04-01`, and `end` is the day after
+    db_conn.execute(
+        "CREATE TABLE IF NOT EXISTS seasons (
+            start TEXT PRIMARY KEY,
+            end TEXT NOT NULL
+        )",
+        [],
+    )?;
+
+    db_conn.execute(
+        "CREATE TABLE IF NOT EXISTS season_ratings (
+            season_start TEXT NOT NULL,
+            username TEXT NOT NULL,
+            rating REAL NOT NULL,
+            deviation REAL NOT NULL,
+            volatility REAL NOT NULL,
+            num_attempts INTEGER NOT NULL,
+            num_solved INTEGER NOT NULL,
+            PRIMARY KEY (season_start, username)
+        )",
+        [],
+    )?;
+
+    Ok(())
+}
+
+// The season that's running now, as `(start, end)`, adding it if it's new.
+// A season keeps the boundaries it started with, even if `length_months` is changed during it
+fn current_season(
+    db_conn: &Connection,
+    config: &SeasonsConfig,
+) -> anyhow::Result<(String, String)> {
+    let running = db_conn
+        .query_row(
+            "SELECT start, end FROM seasons WHERE start <= date('now') AND date('now') < end",
+            [],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        )
+        .optional()?;
+    if let Some(season) = running {
+        return Ok(season);
+    }
+
+    let (year, month): (u32, u32) = db_conn.query_row(
+        "SELECT CAST(strftime('%Y', 'now') AS INTEGER), CAST(strftime('%m', 'now') AS INTEGER)",
+        [],
+        |row| Ok((row.get(0)?, row.get(1)?)),
+    )?;
+    let length_months = config.length_months.max(1);
+    let months = year * 12 + month - 1;
+    let start_months = months - (months % 12) % length_months;
+    let start = format!("{:04}-{:02}-01", start_months / 12, start_months % 12 + 1);
+    Ok(db_conn.query_row(
+        "INSERT INTO seasons (start, end)
+        SELECT MAX(?1, COALESCE((SELECT MAX(end) FROM seasons), '')), date(?1, ?2)
+        RETURNING start, end",
+        rusqlite::params![start, format!("+{length_months} months")],
+        |row| Ok((row.get(0)?, row.get(1)?)),
+    )?)
+}
+
+// The user's season rating from before a rated attempt, to rate the attempt again with `rerate_attempt`
+pub struct SeasonRating {
+    pub season_start: String,
+    pub rating: Glicko2Rating,
+}
+
+// Update the user's rating for the current season after a rated attempt.
+// Returns their season rating from before the attempt, if seasons are on
+pub fn record_attempt(
+    db_conn: &Connection,
+    config: &SeasonsConfig,
+    username: &str,
+    puzzle_rating: &Glicko2Rating,
+    solved: bool,
+) -> anyhow::Result<Option<SeasonRating>> {
+    if !config.enabled {
+        return Ok(None);
+    }
+    let (season_start, _) = current_season(db_conn, config)?;
+    let old_rating = db_conn
+        .query_row(
+            "SELECT rating, deviation, volatility FROM season_ratings
+            WHERE season_start = ?1 AND username = ?2",
+            [&season_start, username],
+            |row| {
+                Ok(Glicko2Rating {
+                    rating: row.get(0)?,
+                    deviation: row.get(1)?,
+                    volatility: row.get(2)?,
+                })
+            },
+        )
+        .optional()?
+        .unwrap_or_default();
+    let new_rating =
+        ratings::rate_user(&old_rating, puzzle_rating, ratings::outcome(solved, false)).rating;
+    db_conn.execute(
+        "INSERT INTO season_ratings (season_start, username, rating, deviation, volatility, num_attempts, num_solved)
+        VALUES (?1, ?2, ?3, ?4, ?5, 1, ?6)
+        ON CONFLICT (season_start, username) DO UPDATE
+            SET rating = excluded.rating, deviation = excluded.deviation, volatility = excluded.volatility,
+                num_attempts = num_attempts + 1, num_solved = num_solved + excluded.num_solved",
+        rusqlite::params![
+            season_start,
+            username,
+            new_rating.rating,
+            new_rating.deviation,
+            new_rating.volatility,
+            solved as u32
+        ],
+    )?;
+    Ok(Some(SeasonRating {
+        season_start,
+        rating: old_rating,
+    }))
+}
+
+// Replace the season rating from the user's last rated attempt, rating it with another outcome.
+// The attempt still counts as unsolved in the season's numbers. Does nothing once the season has ended
+pub fn rerate_attempt(
+    db_conn: &Connection,
+    config: &SeasonsConfig,
+    username: &str,
+    before: &SeasonRating,
+    puzzle_rating: &Glicko2Rating,
+    outcome: skillratings::Outcomes,
+) -> anyhow::Result<()> {
+    if !config.enabled || current_season(db_conn, config)?.0 != before.season_start {
+        return Ok(());
+    }
+    let new_rating = ratings::rate_user(&before.rating, puzzle_rating, outcome).rating;
+    db_conn.execute(
+        "UPDATE season_ratings SET rating = ?3, deviation = ?4, volatility = ?5
+        WHERE season_start = ?1 AND username = ?2",
+        rusqlite::params![
+            before.season_start,
+            username,
+            new_rating.rating,
+            new_rating.deviation,
+            new_rating.volatility
+        ],
+    )?;
+    Ok(())
+}
+
+#[derive(Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct Season {
+    start: String,
+    // The first day after the season
+    end: String,
+    current: bool,
+    num_players: u32,
+}
+
+#[derive(Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct StandingsEntry {
+    rank: u32,
+    username: String,
+    rating: f64,
+    num_attempts: u32,
+    num_solved: u32,
+}
+
+#[derive(Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct UserSeason {
+    start: String,
+    end: String,
+    rank: u32,
+    rating: f64,
+    deviation: f64,
+    num_attempts: u32,
+    num_solved: u32,
+}
+
+#[derive(Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct LifetimeRating {
+    rating: f64,
+    deviation: f64,
+    num_rated_attempts: u32,
+    // Until the user has made `ratings::PROVISIONAL_ATTEMPTS` rated attempts
+    provisional: bool,
+}
+
+#[derive(Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct UserRatings {
+    lifetime: LifetimeRating,
+    // Not set if the user hasn't made a rated attempt this season, or seasons are disabled
+    current_season: Option<UserSeason>,
+    // Every earlier season the user played in, newest first
+    past_seasons: Vec<UserSeason>,
+}
+
+// List every season, newest first
+#[utoipa::path(
+    get,
+    path = "/seasons",
+    tag = "seasons",
+    responses((status = 200, body = Vec<Season>)),
+)]
+pub async fn get_seasons(State(state): State<AppState>) -> Result<Json<Vec<Season>>, StatusCode> {
+    let db_conn = db::open().map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    read_seasons(&db_conn, &state.config.seasons)
+        .map(Json)
+        .map_err(|e| {
+            tracing::error!("Error reading seasons from database: {:?}", e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })
+}
+
+fn read_seasons(db_conn: &Connection, config: &SeasonsConfig) -> anyhow::Result<Vec<Season>> {
+    let current_start = if config.enabled {
+        Some(current_season(db_conn, config)?.0)
+    } else {
+        None
+    };
+    let mut stmt = db_conn.prepare(
+        "SELECT seasons.start, seasons.end,
+            (SELECT COUNT(*) FROM season_ratings WHERE season_ratings.season_start = seasons.start)
+        FROM seasons ORDER BY seasons.start DESC",
+    )?;
+    let rows = stmt.query_map([], |row| {
+        let start: String = row.get(0)?;
+        Ok(Season {
+            current: current_start.as_ref() == Some(&start),
+            start,
+            end: row.get(1)?,
+            num_players: row.get(2)?,
+        })
+    })?;
+    Ok(rows.collect::<Result<Vec<_>, _>>()?)
+}
+
+// Get the highest rated users of a season, by its start date or `current`
+#[utoipa::path(
+    get,
+    path = "/seasons/{start}/standings",
+    tag = "seasons",
+    params(("start" = String, Path, description = "The first day of the season, like `2025-04-01`, or `current`")),
+    responses((status = 200, body = Vec<StandingsEntry>), (status = 404)),
+)]
+pub async fn get_standings(
+    State(state): State<AppState>,
+    Path(start): Path<String>,
+) -> Result<Json<Vec<StandingsEntry>>, StatusCode> {
+    let db_conn = db::open().map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let start = if start == "current" && state.config.seasons.enabled {
+        current_season(&db_conn, &state.config.seasons)
+            .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+            .0
+    } else {
+        start
+    };
+    let exists = db_conn
+        .prepare("SELECT 1 FROM seasons WHERE start = ?1")
+        .and_then(|mut stmt| stmt.exists([&start]))
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    if !exists {
+        return Err(StatusCode::NOT_FOUND);
+    }
+    read_standings(&db_conn, &start).map(Json).map_err(|e| {
+        tracing::error!("Error reading season standings from database: {:?}", e);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })
+}
+
+fn read_standings(db_conn: &Connection, season_start: &str) -> anyhow::Result<Vec<StandingsEntry>> {
+    let mut stmt = db_conn.prepare(
+        "SELECT username, rating, num_attempts, num_solved FROM season_ratings
+        WHERE season_start = ?1 ORDER BY rating DESC LIMIT ?2",
+    )?;
+    let rows = stmt.query_map(rusqlite::params![season_start, STANDINGS_SIZE], |row| {
+        Ok(StandingsEntry {
+            rank: 0,
+            username: row.get(0)?,
+            rating: row.get(1)?,
+            num_attempts: row.get(2)?,
+            num_solved: row.get(3)?,
+        })
+    })?;
+    let mut entries = rows.collect::<Result<Vec<_>, _>>()?;
+    for (i, entry) in entries.iter_mut().enumerate() {
+        entry.rank = i as u32 + 1;
+    }
+    Ok(entries)
+}
+
+// Get the user's lifetime rating, and their rating and rank in every season they played in
+#[utoipa::path(
+    get,
+    path = "/users/{username}/ratings",
+    tag = "seasons",
+    params(("username" = String, Path)),
+    responses(
+        (status = 200, body = UserRatings),
+        (status = 400, body = validation::ValidationError),
+    ),
+)]
+pub async fn get_user_ratings(
+    State(state): State<AppState>,
+    Path(username): Path<String>,
+) -> Result<Json<UserRatings>, ApiError> {
+    let username = validation::canonical_username(&username);
+    validation::validate_username(&username)?;
+    let lifetime = state.store.user_rating(&username).await.map_err(|e| {
+        tracing::error!("Error reading user rating: {:?}", e);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+    let db_conn = db::open().map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let num_rated_attempts: u32 = db_conn
+        .query_row(
+            "SELECT COUNT(*) FROM rated_attempts WHERE username = ?1",
+            [&username],
+            |row| row.get(0),
+        )
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let current_start = if state.config.seasons.enabled {
+        Some(
+            current_season(&db_conn, &state.config.seasons)
+                .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+                .0,
+        )
+    } else {
+        None
+    };
+    let (current, past): (Vec<_>, Vec<_>) = read_user_seasons(&db_conn, &username)
+        .map_err(|e| {
+            tracing::error!("Error reading user seasons from database: {:?}", e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?
+        .into_iter()
+        .partition(|season| current_start.as_ref() == Some(&season.start));
+    Ok(Json(UserRatings {
+        lifetime: LifetimeRating {
+            rating: lifetime.rating,
+            deviation: lifetime.deviation,
+            num_rated_attempts,
+            provisional: num_rated_attempts < ratings::PROVISIONAL_ATTEMPTS,
+        },
+        current_season: current.into_iter().next(),
+        past_seasons: past,
+    }))
+}
+
+fn read_user_seasons(db_conn: &Connection, username: &str) -> anyhow::Result<Vec<UserSeason>> {
+    let mut stmt = db_conn.prepare(
+        "SELECT seasons.start, seasons.end, season_ratings.rating, season_ratings.deviation,
+            season_ratings.num_attempts, season_ratings.num_solved,
+            (SELECT COUNT(*) + 1 FROM season_ratings AS others
+                WHERE others.season_start = seasons.start AND others.rating > season_ratings.rating)
+        FROM season_ratings JOIN seasons ON seasons.start = season_ratings.season_start
+        WHERE season_ratings.username = ?1
+        ORDER BY seasons.start DESC",
+    )?;
+    let rows = stmt.query_map([username], |row| {
+        Ok(UserSeason {
+            start: row.get(0)?,
+            end: row.get(1)?,
+            rating: row.get(2)?,
+            deviation: row.get(3)?,
+            num_attempts: row.get(4)?,
+            num_solved: row.get(5)?,
+            rank: row.get(6)?,
+        })
+    })?;
+    Ok(rows.collect::<Result<Vec<_>, _>>()?)
+}
@@ -0,0 +1,219 @@
+use std::{net::SocketAddr, path::Path, time::Duration};
+
+use anyhow::Context;
+
+use axum::{
+    Router,
+    http::{HeaderName, HeaderValue, Method},
+};
+use axum_server::tls_rustls::RustlsConfig;
+use tokio::sync::watch;
+use tower_http::{
+    compression::{
+        CompressionLayer, Predicate,
+        predicate::{NotForContentType, SizeAbove},
+    },
+    cors::{AllowHeaders, AllowOrigin, Any, CorsLayer},
+};
+
+use crate::{
+    config::{CompressionConfig, CorsConfig, ServerConfig, TlsConfig},
+    shutdown, telemetry,
+};
+
+// Serve the app on every configured listener, until a shutdown is requested and every listener has drained
+pub async fn serve(
+    app: Router,
+    config: &ServerConfig,
+    shutdown: watch::Receiver<bool>,
+) -> anyhow::Result<()> {
+    let plaintext = async {
+        if config.tcp {
+            serve_plaintext(app.clone(), config.address, shutdown.clone()).await
+        } else {
+            Ok(())
+        }
+    };
+    let unix = async {
+        match &config.unix_socket {
+            #[cfg(unix)]
+            Some(path) => serve_unix(app.clone(), path, shutdown.clone()).await,
+            #[cfg(not(unix))]
+            Some(_) => anyhow::bail!("Unix sockets are not supported on this platform"),
+            None => Ok(()),
+        }
+    };
+    let tls = async {
+        match &config.tls {
+            Some(tls) => serve_tls(app.clone(), tls, shutdown.clone()).await,
+            None => Ok(()),
+        }
+    };
+    tokio::try_join!(plaintext, unix, tls)?;
+    Ok(())
+}
+
+// Response headers that browsers let the frontend read
+const EXPOSED_HEADERS: [&str; 3] = [telemetry::REQUEST_ID_HEADER, "retry-after", "deprecation"];
+
+pub fn cors_layer(config: &CorsConfig) -> anyhow::Result<CorsLayer> {
+    let origins = if config.allowed_origins.iter().any(|origin| origin == "*") {
+        AllowOrigin::from(Any)
+    } else {
+        let origins = config
+            .allowed_origins
+            .iter()
+            .map(|origin| HeaderValue::from_str(origin))
+            .collect::<Result<Vec<_>, _>>()
+            .context("Invalid origin in cors.allowed_origins")?;
+        AllowOrigin::list(origins)
+    };
+    let methods = config
+        .allowed_methods
+        .iter()
+        .map(|method| Method::from_bytes(method.as_bytes()))
+        .collect::<Result<Vec<_>, _>>()
+        .context("Invalid method in cors.allowed_methods")?;
+    let headers = if config.allowed_headers.iter().any(|header| header == "*") {
+        AllowHeaders::from(Any)
+    } else {
+        let headers = config
+            .allowed_headers
+            .iter()
+            .map(|header| HeaderName::from_bytes(header.as_bytes()))
+            .collect::<Result<Vec<_>, _>>()
+            .context("Invalid header in cors.allowed_headers")?;
+        AllowHeaders::list(headers)
+    };
+    Ok(CorsLayer::new()
+        .allow_origin(origins)
+        .allow_methods(methods)
+        .allow_headers(headers)
+        .expose_headers(EXPOSED_HEADERS.map(HeaderName::from_static)))
+}
+
+// Server-sent events are never compressed, since that would buffer them.
+// With compression disabled, the layer passes every response through unchanged
+pub fn compression_layer(config: &CompressionConfig) -> CompressionLayer<impl Predicate + use<>> {
+    CompressionLayer::new()
+        .gzip(config.enabled && config.gzip)
+        .br(config.enabled && config.br)
+        .compress_when(
+            SizeAbove::new(config.min_size_bytes)
+                .and(NotForContentType::GRPC)
+                .and(NotForContentType::IMAGES)
+                .and(NotForContentType::SSE),
+        )
+}
+
+async fn serve_plaintext(
+    app: Router,
+    address: SocketAddr,
+    shutdown: watch::Receiver<bool>,
+) -> anyhow::Result<()> {
+    let listener = tokio::net::TcpListener::bind(address).await?;
+    tracing::info!("Listening on http://{}", listener.local_addr()?);
+    let server = axum::serve(
+        listener,
+        app.into_make_service_with_connect_info::<SocketAddr>(),
+    )
+    .with_graceful_shutdown(shutdown::requested(shutdown.clone()));
+    tokio::select! {
+        result = server => result?,
+        _ = shutdown::drain_timeout(shutdown) => warn_connections_still_open(),
+    }
+    Ok(())
+}
+
+#[cfg(unix)]
+async fn serve_unix(
+    app: Router,
+    path: &Path,
+    shutdown: watch::Receiver<bool>,
+) -> anyhow::Result<()> {
+    // A socket file left behind by an earlier run would make binding fail
+    if path.exists() {
+        std::fs::remove_file(path)
+            .with_context(|| format!("Failed to remove old socket {}", path.display()))?;
+    }
+    let listener = tokio::net::UnixListener::bind(path)
+        .with_context(|| format!("Failed to bind to {}", path.display()))?;
+    tracing::info!("Listening on unix:{}", path.display());
+    let server = axum::serve(listener, app.into_make_service())
+        .with_graceful_shutdown(shutdown::requested(shutdown.clone()));
+    tokio::select! {
+        result = server => result?,
+        _ = shutdown::drain_timeout(shutdown) => warn_connections_still_open(),
+    }
+    let _ = std::fs::remove_file(path);
+    Ok(())
+}
+
+fn warn_connections_still_open() {
+    tracing::warn!(
+        "Connections still open after {:?}, closing them",
+        shutdown::DRAIN_TIMEOUT
+    );
+}
+
+async fn serve_tls(
+    app: Router,
+    config: &TlsConfig,
+    shutdown: watch::Receiver<bool>,
+) -> anyhow::Result<()> {
+    let rustls_config = RustlsConfig::from_pem_file(&config.cert_path, &config.key_path).await?;
+    if let Some(interval) = config.reload_interval_seconds {
+        tokio::spawn(reload_certificates(
+            rustls_config.clone(),
+            config.clone(),
+            Duration::from_secs(interval),
+        ));
+    }
+
+    let handle = axum_server::Handle::new();
+    tokio::spawn({
+        let handle = handle.clone();
+        async move {
+            shutdown::requested(shutdown).await;
+            handle.graceful_shutdown(Some(shutdown::DRAIN_TIMEOUT));
+        }
+    });
+
+    tracing::info!("Listening on https://{}", config.address);
+    axum_server::bind_rustls(config.address, rustls_config)
+        .handle(handle)
+        .serve(app.into_make_service_with_connect_info::<SocketAddr>())
+        .await?;
+    Ok(())
+}
+
+// Reload the certificate and key whenever either file is modified.
+// A failed reload keeps the old certificate, so that a half-written renewal doesn't take the server down
+async fn reload_certificates(rustls_config: RustlsConfig, config: TlsConfig, interval: Duration) {
+    let modified = |config: &TlsConfig| {
+        [&config.cert_path, &config.key_path].map(|path| {
+            std::fs::metadata(path)
+                .and_then(|metadata| metadata.modified())
+                .ok()
+        })
+    };
+    let mut last_modified = modified(&config);
+    let mut interval = tokio::time::interval(interval);
+    loop {
+        interval.tick().await;
+        let current = modified(&config);
+        if current == last_modified {
+            continue;
+        }
+        match rustls_config
+            .reload_from_pem_file(&config.cert_path, &config.key_path)
+            .await
+        {
+            Ok(()) => {
+                tracing::info!("Reloaded TLS certificate");
+                last_modified = current;
+            }
+            Err(e) => tracing::error!("Error reloading TLS certificate: {:?}", e),
+        }
+    }
+}
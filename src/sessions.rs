@@ -0,0 +1,566 @@
+use std::convert::Infallible;
+
+use axum::{
+    Json,
+    extract::{FromRequestParts, OptionalFromRequestParts, Path, Query, Request, State},
+    http::{StatusCode, header::AUTHORIZATION, request::Parts},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use rusqlite::{Connection, OptionalExtension};
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use crate::{
+    AppState,
+    admin::{AdminAuth, hash_token},
+    audit, auth,
+    config::AuthConfig,
+    db, now_seconds, routes,
+    validation::{self, ApiError},
+};
+
+// Sessions for users who logged in, see `auth.rs`. Each session has a short-lived access token, sent with every request,
+// and a refresh token that's exchanged for new tokens before the access token expires.
+// Access tokens are signed rather than stored, and refresh tokens are only stored hashed.
+// Every refresh replaces the refresh token, and using a replaced one again ends the session, since it must have been copied.
+//
+// Other endpoints still take a `username`. Once a username has been claimed by logging in, `authenticate` only lets
+// requests use it with an access token for it, so that nobody else can play or change settings as that user.
+// Usernames that haven't been claimed, like playtak users', work without logging in as before.
+// Browsers can't set headers on WebSockets, so WebSocket upgrades can send the access token as `accessToken`
+// in the query string instead. Usernames sent in WebSocket messages and gRPC calls don't pass through
+// `authenticate`, so those check them with `check_acting_as` themselves
+
+const ACCESS_TOKEN_PURPOSE: &str = "access";
+const REFRESH_TOKEN_LENGTH: usize = 40;
+
+pub fn init_db_tables(db_conn: &Connection) -> anyhow::Result<()> {
+    // Sessions are kept after they end, until the user next logs in
+    db_conn.execute(
+        "CREATE TABLE IF NOT EXISTS sessions (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            username TEXT NOT NULL,
+            refresh_token_hash TEXT NOT NULL UNIQUE,
+            previous_refresh_token_hash TEXT,
+            created_seconds INTEGER NOT NULL DEFAULT (strftime('%s', 'now')),
+            refreshed_seconds INTEGER NOT NULL DEFAULT (strftime('%s', 'now')),
+            expires_seconds INTEGER NOT NULL,
+            revoked_seconds INTEGER
+        )",
+        [],
+    )?;
+    db_conn.execute(
+        "CREATE INDEX IF NOT EXISTS sessions_by_user ON sessions (username)",
+        [],
+    )?;
+    db_conn.execute(
+        "CREATE INDEX IF NOT EXISTS sessions_by_previous_token ON sessions (previous_refresh_token_hash)",
+        [],
+    )?;
+
+    Ok(())
+}
+
+// What an access token vouches for
+#[derive(Serialize, Deserialize)]
+struct AccessToken {
+    session_id: i64,
+    username: String,
+    expires_seconds: u64,
+}
+
+#[derive(Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct Tokens {
+    username: String,
+    session_id: i64,
+    // Send as `Authorization: Bearer <accessToken>`
+    access_token: String,
+    access_token_expires_seconds: u64,
+    // Exchanged for new tokens at `/auth/refresh`. Only shown once, since the database only keeps its hash
+    refresh_token: String,
+    refresh_token_expires_seconds: u64,
+}
+
+fn tokens(
+    secret: &str,
+    config: &AuthConfig,
+    session_id: i64,
+    username: &str,
+    refresh_token: String,
+    refresh_token_expires_seconds: u64,
+) -> Tokens {
+    let access_token_expires_seconds = now_seconds() + config.access_token_ttl_seconds;
+    let access_token = auth::sign(
+        secret,
+        ACCESS_TOKEN_PURPOSE,
+        &AccessToken {
+            session_id,
+            username: username.to_string(),
+            expires_seconds: access_token_expires_seconds,
+        },
+    );
+    Tokens {
+        username: username.to_string(),
+        session_id,
+        access_token,
+        access_token_expires_seconds,
+        refresh_token,
+        refresh_token_expires_seconds,
+    }
+}
+
+pub fn start_session(
+    db_conn: &Connection,
+    secret: &str,
+    config: &AuthConfig,
+    username: &str,
+) -> anyhow::Result<Tokens> {
+    let now = now_seconds();
+    db_conn.execute(
+        "DELETE FROM sessions WHERE username = ?1 AND (expires_seconds <= ?2 OR revoked_seconds IS NOT NULL)",
+        rusqlite::params![username, now],
+    )?;
+    let refresh_token = auth::random_token(REFRESH_TOKEN_LENGTH);
+    let expires_seconds = now + config.refresh_token_ttl_seconds;
+    let session_id = db_conn.query_row(
+        "INSERT INTO sessions (username, refresh_token_hash, expires_seconds) VALUES (?1, ?2, ?3) RETURNING id",
+        rusqlite::params![username, hash_token(&refresh_token), expires_seconds],
+        |row| row.get(0),
+    )?;
+    Ok(tokens(
+        secret,
+        config,
+        session_id,
+        username,
+        refresh_token,
+        expires_seconds,
+    ))
+}
+
+#[derive(Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct Refresh {
+    refresh_token: String,
+}
+
+// Replace a session's tokens. The refresh token that was sent stops working
+#[utoipa::path(
+    post,
+    path = "/auth/refresh",
+    tag = "auth",
+    request_body = Refresh,
+    responses(
+        (status = 200, body = Tokens),
+        (status = 401, description = "The session has ended, or the refresh token has already been used"),
+        (status = 404, description = "Login by email is off"),
+    ),
+)]
+pub async fn refresh(
+    State(state): State<AppState>,
+    Json(payload): Json<Refresh>,
+) -> Result<Json<Tokens>, StatusCode> {
+    let secret = state
+        .config
+        .auth
+        .secret
+        .as_deref()
+        .ok_or(StatusCode::NOT_FOUND)?;
+    let mut db_conn = db::open().map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let transaction = db_conn
+        .transaction()
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let tokens = rotate(
+        &transaction,
+        secret,
+        &state.config.auth,
+        &payload.refresh_token,
+    )
+    .map_err(|e| {
+        tracing::error!("Error refreshing session: {:?}", e);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+    // Committed even if refreshing failed, since a reused token revokes its session
+    transaction
+        .commit()
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    tokens.map(Json).ok_or(StatusCode::UNAUTHORIZED)
+}
+
+fn rotate(
+    db_conn: &Connection,
+    secret: &str,
+    config: &AuthConfig,
+    refresh_token: &str,
+) -> anyhow::Result<Option<Tokens>> {
+    let now = now_seconds();
+    let token_hash = hash_token(refresh_token);
+    let reused = db_conn.execute(
+        "UPDATE sessions SET revoked_seconds = ?2
+        WHERE previous_refresh_token_hash = ?1 AND revoked_seconds IS NULL",
+        rusqlite::params![token_hash, now],
+    )?;
+    if reused > 0 {
+        tracing::warn!("A replaced refresh token was used, ending its session");
+        return Ok(None);
+    }
+
+    let new_refresh_token = auth::random_token(REFRESH_TOKEN_LENGTH);
+    let expires_seconds = now + config.refresh_token_ttl_seconds;
+    let session: Option<(i64, String)> = db_conn
+        .query_row(
+            "UPDATE sessions SET previous_refresh_token_hash = refresh_token_hash, refresh_token_hash = ?2,
+                refreshed_seconds = ?3, expires_seconds = ?4
+            WHERE refresh_token_hash = ?1 AND revoked_seconds IS NULL AND expires_seconds > ?3
+            RETURNING id, username",
+            rusqlite::params![
+                token_hash,
+                hash_token(&new_refresh_token),
+                now,
+                expires_seconds
+            ],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        )
+        .optional()?;
+    Ok(session.map(|(session_id, username)| {
+        tokens(
+            secret,
+            config,
+            session_id,
+            &username,
+            new_refresh_token,
+            expires_seconds,
+        )
+    }))
+}
+
+// The logged in user, put in the request's extensions by `authenticate` for requests with a valid access token.
+// Also an extractor for endpoints that need a logged in user
+#[derive(Clone, Debug)]
+pub struct AuthUser {
+    pub username: String,
+    pub session_id: i64,
+}
+
+impl<S: Send + Sync> FromRequestParts<S> for AuthUser {
+    type Rejection = StatusCode;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        parts
+            .extensions
+            .get::<AuthUser>()
+            .cloned()
+            .ok_or(StatusCode::UNAUTHORIZED)
+    }
+}
+
+impl<S: Send + Sync> OptionalFromRequestParts<S> for AuthUser {
+    type Rejection = Infallible;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        _state: &S,
+    ) -> Result<Option<Self>, Self::Rejection> {
+        Ok(parts.extensions.get::<AuthUser>().cloned())
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct AccessTokenQuery {
+    access_token: Option<String>,
+}
+
+// The user behind the request's access token, if it has one that's valid and its session hasn't ended.
+// Other bearer tokens, like admin tokens, are left to their own extractors
+fn resolve_user(
+    db_conn: &Connection,
+    secret: &str,
+    request: &Request,
+) -> anyhow::Result<Option<AuthUser>> {
+    let path = routes::unversioned_path(request.uri().path());
+    let token = match request
+        .headers()
+        .get(AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+    {
+        Some(token) => Some(token.to_string()),
+        None if path == "ws" || path.ends_with("/ws") => {
+            Query::<AccessTokenQuery>::try_from_uri(request.uri())
+                .ok()
+                .and_then(|Query(query)| query.access_token)
+        }
+        None => None,
+    };
+    match token {
+        Some(token) => user_for_token(db_conn, secret, &token),
+        None => Ok(None),
+    }
+}
+
+// The user an access token belongs to, if it's valid and its session hasn't ended.
+// The username is the session's rather than the token's, since sessions are updated when users are renamed
+pub fn user_for_token(
+    db_conn: &Connection,
+    secret: &str,
+    token: &str,
+) -> anyhow::Result<Option<AuthUser>> {
+    let Some(access_token) = auth::verify::<AccessToken>(secret, ACCESS_TOKEN_PURPOSE, token)
+    else {
+        return Ok(None);
+    };
+    let now = now_seconds();
+    if access_token.expires_seconds <= now {
+        return Ok(None);
+    }
+    let username: Option<String> = db_conn
+        .prepare_cached(
+            "SELECT username FROM sessions WHERE id = ?1 AND revoked_seconds IS NULL AND expires_seconds > ?2",
+        )?
+        .query_row(rusqlite::params![access_token.session_id, now], |row| {
+            row.get(0)
+        })
+        .optional()?;
+    Ok(username.map(|username| AuthUser {
+        username,
+        session_id: access_token.session_id,
+    }))
+}
+
+// Whether a request from the user, or from someone who isn't logged in, may act as the canonical username.
+// `UNAUTHORIZED` if it's claimed and nobody is logged in, `FORBIDDEN` if someone else is logged in
+pub fn check_acting_as(
+    db_conn: &Connection,
+    user: Option<&AuthUser>,
+    username: &str,
+) -> Result<(), StatusCode> {
+    match user {
+        Some(user) if user.username == username => Ok(()),
+        Some(_) => Err(StatusCode::FORBIDDEN),
+        None => match auth::is_claimed(db_conn, username) {
+            Ok(false) => Ok(()),
+            Ok(true) => Err(StatusCode::UNAUTHORIZED),
+            Err(e) => {
+                tracing::error!("Error reading user emails: {:?}", e);
+                Err(StatusCode::INTERNAL_SERVER_ERROR)
+            }
+        },
+    }
+}
+
+// `/users/{username}/...` endpoints that only the user should see or change
+fn private_path_username(path: &str) -> Option<String> {
+    let (username, rest) = path.strip_prefix("users/")?.split_once('/')?;
+    let private = ["settings", "notifications"]
+        .iter()
+        .any(|prefix| rest == *prefix || rest.starts_with(&format!("{prefix}/")));
+    private.then(|| validation::canonical_username(username))
+}
+
+// Middleware that resolves the logged in user, and rejects requests that use a claimed username without logging in as it.
+// The username a request acts as is its `username` in the query string or JSON body, or the username in a private path.
+// Admin endpoints act on other users' names, and `/auth` endpoints are how users log in, so they're left alone
+pub async fn authenticate(
+    State(state): State<AppState>,
+    mut request: Request,
+    next: Next,
+) -> Response {
+    let Some(secret) = state.config.auth.secret.as_deref() else {
+        return next.run(request).await;
+    };
+    let Ok(db_conn) = db::open() else {
+        return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+    };
+    let user = match resolve_user(&db_conn, secret, &request) {
+        Ok(user) => user,
+        Err(e) => {
+            tracing::error!("Error reading sessions from database: {:?}", e);
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+    };
+    if let Some(user) = &user {
+        request.extensions_mut().insert(user.clone());
+    }
+
+    let path = routes::unversioned_path(request.uri().path());
+    if path.starts_with("admin/") || path.starts_with("auth/") {
+        return next.run(request).await;
+    }
+    let path_username = private_path_username(path);
+    let (request, username) = match validation::request_username(request).await {
+        Ok(found) => found,
+        Err(e) => return e.into_response(),
+    };
+    let Some(username) = username.or(path_username) else {
+        return next.run(request).await;
+    };
+    if let Err(status) = check_acting_as(&db_conn, user.as_ref(), &username) {
+        return status.into_response();
+    }
+    next.run(request).await
+}
+
+#[derive(Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct CurrentUser {
+    username: String,
+    session_id: i64,
+}
+
+// Who the access token belongs to
+#[utoipa::path(
+    get,
+    path = "/auth/session",
+    tag = "auth",
+    security(("session_token" = [])),
+    responses((status = 200, body = CurrentUser), (status = 401)),
+)]
+pub async fn get_current_user(user: AuthUser) -> Json<CurrentUser> {
+    Json(CurrentUser {
+        username: user.username,
+        session_id: user.session_id,
+    })
+}
+
+#[derive(Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct Session {
+    id: i64,
+    created_seconds: u64,
+    refreshed_seconds: u64,
+    expires_seconds: u64,
+    // Whether this is the session of the request's access token
+    current: bool,
+}
+
+// The user's sessions that haven't ended, newest first
+#[utoipa::path(
+    get,
+    path = "/auth/sessions",
+    tag = "auth",
+    security(("session_token" = [])),
+    responses((status = 200, body = Vec<Session>), (status = 401)),
+)]
+pub async fn get_sessions(user: AuthUser) -> Result<Json<Vec<Session>>, StatusCode> {
+    let db_conn = db::open().map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let sessions = read_sessions(&db_conn, &user).map_err(|e| {
+        tracing::error!("Error reading sessions: {:?}", e);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+    Ok(Json(sessions))
+}
+
+fn read_sessions(db_conn: &Connection, user: &AuthUser) -> anyhow::Result<Vec<Session>> {
+    let mut stmt = db_conn.prepare(
+        "SELECT id, created_seconds, refreshed_seconds, expires_seconds FROM sessions
+        WHERE username = ?1 AND revoked_seconds IS NULL AND expires_seconds > ?2
+        ORDER BY id DESC",
+    )?;
+    let sessions = stmt
+        .query_map(rusqlite::params![user.username, now_seconds()], |row| {
+            let id = row.get(0)?;
+            Ok(Session {
+                id,
+                created_seconds: row.get(1)?,
+                refreshed_seconds: row.get(2)?,
+                expires_seconds: row.get(3)?,
+                current: id == user.session_id,
+            })
+        })?
+        .collect::<Result<_, _>>()?;
+    Ok(sessions)
+}
+
+fn revoke(db_conn: &Connection, username: &str, session_id: Option<i64>) -> anyhow::Result<usize> {
+    Ok(db_conn.execute(
+        "UPDATE sessions SET revoked_seconds = ?3
+        WHERE username = ?1 AND (?2 IS NULL OR id = ?2) AND revoked_seconds IS NULL",
+        rusqlite::params![username, session_id, now_seconds()],
+    )?)
+}
+
+// End the access token's session, on every device using it
+#[utoipa::path(
+    post,
+    path = "/auth/logout",
+    tag = "auth",
+    security(("session_token" = [])),
+    responses((status = 204), (status = 401)),
+)]
+pub async fn logout(user: AuthUser) -> Result<StatusCode, StatusCode> {
+    let db_conn = db::open().map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    revoke(&db_conn, &user.username, Some(user.session_id)).map_err(|e| {
+        tracing::error!("Error ending session: {:?}", e);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+// End another of the user's sessions, like one on a lost device
+#[utoipa::path(
+    delete,
+    path = "/auth/sessions/{id}",
+    tag = "auth",
+    params(("id" = i64, Path)),
+    security(("session_token" = [])),
+    responses((status = 204), (status = 401), (status = 404)),
+)]
+pub async fn delete_session(user: AuthUser, Path(id): Path<i64>) -> Result<StatusCode, StatusCode> {
+    let db_conn = db::open().map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let num_revoked = revoke(&db_conn, &user.username, Some(id)).map_err(|e| {
+        tracing::error!("Error ending session: {:?}", e);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+    if num_revoked == 0 {
+        return Err(StatusCode::NOT_FOUND);
+    }
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[derive(Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct RevokedSessions {
+    num_revoked: usize,
+}
+
+// Log a user out everywhere, for example after their email account was compromised
+#[utoipa::path(
+    delete,
+    path = "/admin/users/{username}/sessions",
+    tag = "admin",
+    params(("username" = String, Path)),
+    security(("admin_token" = [])),
+    responses(
+        (status = 200, body = RevokedSessions),
+        (status = 400, body = validation::ValidationError),
+        (status = 401),
+        (status = 403),
+    ),
+)]
+pub async fn revoke_user_sessions(
+    admin: AdminAuth,
+    Path(username): Path<String>,
+) -> Result<Json<RevokedSessions>, ApiError> {
+    validation::validate_username(&username)?;
+    let username = validation::canonical_username(&username);
+    let mut db_conn = db::open().map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let transaction = db_conn
+        .transaction()
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let num_revoked = revoke(&transaction, &username, None).map_err(|e| {
+        tracing::error!("Error ending sessions: {:?}", e);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+    audit::record_for(
+        &transaction,
+        &admin,
+        "revoke_sessions",
+        serde_json::json!({"username": username, "numRevoked": num_revoked}),
+    )?;
+    transaction
+        .commit()
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    Ok(Json(RevokedSessions { num_revoked }))
+}
@@ -0,0 +1,168 @@
+use std::collections::BTreeSet;
+
+use axum::{Json, extract::Path, http::StatusCode};
+use rusqlite::{Connection, OptionalExtension};
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use crate::{
+    db,
+    validation::{self, ApiError, ValidationError},
+};
+
+// Per-user preferences, set by the frontend. Users without a row in `user_settings` get `Settings::default()`
+
+pub fn init_db_tables(db_conn: &Connection) -> anyhow::Result<()> {
+    // `preferred_sizes` is separated by spaces, and empty for every size
+    db_conn.execute(
+        "CREATE TABLE IF NOT EXISTS user_settings (
+            username TEXT PRIMARY KEY,
+            preferred_sizes TEXT NOT NULL,
+            rated_by_default INTEGER NOT NULL,
+            hide_solve_times INTEGER NOT NULL,
+            normalize_colors INTEGER NOT NULL
+        )",
+        [],
+    )?;
+
+    Ok(())
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct Settings {
+    // Board sizes to serve puzzles on. Empty for every size
+    pub preferred_sizes: BTreeSet<usize>,
+    // Serve rated puzzles rather than practice, unless asked otherwise
+    pub rated_by_default: bool,
+    // Don't show other players' solve times after an attempt
+    pub hide_solve_times: bool,
+    // Show every puzzle with white to move, swapping colors in puzzles where black is to move
+    pub normalize_colors: bool,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            preferred_sizes: BTreeSet::new(),
+            rated_by_default: true,
+            hide_solve_times: false,
+            normalize_colors: false,
+        }
+    }
+}
+
+fn validate_settings(settings: &Settings) -> Result<(), ValidationError> {
+    if let Some(size) = settings
+        .preferred_sizes
+        .iter()
+        .find(|size| !(3..=8).contains(*size))
+    {
+        return Err(ValidationError::new(
+            "preferredSizes",
+            format!("Invalid size {size}. Sizes must be between 3 and 8"),
+        ));
+    }
+    Ok(())
+}
+
+pub fn read_settings(db_conn: &Connection, username: &str) -> anyhow::Result<Settings> {
+    let settings = db_conn
+        .query_row(
+            "SELECT preferred_sizes, rated_by_default, hide_solve_times, normalize_colors
+            FROM user_settings WHERE username = ?1",
+            [username],
+            |row| {
+                Ok((
+                    row.get::<_, String>(0)?,
+                    row.get(1)?,
+                    row.get(2)?,
+                    row.get(3)?,
+                ))
+            },
+        )
+        .optional()?;
+    let Some((preferred_sizes, rated_by_default, hide_solve_times, normalize_colors)) = settings
+    else {
+        return Ok(Settings::default());
+    };
+    Ok(Settings {
+        preferred_sizes: preferred_sizes
+            .split_whitespace()
+            .map(str::parse)
+            .collect::<Result<_, _>>()?,
+        rated_by_default,
+        hide_solve_times,
+        normalize_colors,
+    })
+}
+
+fn write_settings(db_conn: &Connection, username: &str, settings: &Settings) -> anyhow::Result<()> {
+    let preferred_sizes: Vec<String> = settings
+        .preferred_sizes
+        .iter()
+        .map(usize::to_string)
+        .collect();
+    db_conn.execute(
+        "INSERT INTO user_settings (username, preferred_sizes, rated_by_default, hide_solve_times, normalize_colors)
+        VALUES (?1, ?2, ?3, ?4, ?5)
+        ON CONFLICT (username) DO UPDATE SET preferred_sizes = ?2, rated_by_default = ?3,
+            hide_solve_times = ?4, normalize_colors = ?5",
+        rusqlite::params![
+            username,
+            preferred_sizes.join(" "),
+            settings.rated_by_default,
+            settings.hide_solve_times,
+            settings.normalize_colors
+        ],
+    )?;
+    Ok(())
+}
+
+#[utoipa::path(
+    get,
+    path = "/users/{username}/settings",
+    tag = "settings",
+    params(("username" = String, Path)),
+    responses(
+        (status = 200, body = Settings),
+        (status = 400, body = ValidationError),
+    ),
+)]
+pub async fn get_settings(Path(username): Path<String>) -> Result<Json<Settings>, ApiError> {
+    validation::validate_username(&username)?;
+    let username = validation::canonical_username(&username);
+    let db_conn = db::open().map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let settings = read_settings(&db_conn, &username).map_err(|e| {
+        tracing::error!("Error reading settings: {:?}", e);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+    Ok(Json(settings))
+}
+
+// Replace all of the user's settings
+#[utoipa::path(
+    put,
+    path = "/users/{username}/settings",
+    tag = "settings",
+    params(("username" = String, Path)),
+    request_body = Settings,
+    responses(
+        (status = 200, body = Settings),
+        (status = 400, body = ValidationError),
+    ),
+)]
+pub async fn set_settings(
+    Path(username): Path<String>,
+    Json(payload): Json<Settings>,
+) -> Result<Json<Settings>, ApiError> {
+    validation::validate_username(&username)?;
+    validate_settings(&payload)?;
+    let username = validation::canonical_username(&username);
+    let db_conn = db::open().map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    write_settings(&db_conn, &username, &payload).map_err(|e| {
+        tracing::error!("Error writing settings: {:?}", e);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+    Ok(Json(payload))
+}
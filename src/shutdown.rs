@@ -0,0 +1,42 @@
+use std::time::Duration;
+
+use tokio::sync::watch;
+
+// After a shutdown signal, open connections get this long to finish before the server exits anyway.
+// Mostly matters for websockets, which otherwise stay open until the client leaves
+pub const DRAIN_TIMEOUT: Duration = Duration::from_secs(10);
+
+// Resolves on Ctrl+C, or on SIGTERM on Unix
+pub async fn signal() {
+    let ctrl_c = async {
+        tokio::signal::ctrl_c()
+            .await
+            .expect("Failed to listen for Ctrl+C");
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())
+            .expect("Failed to listen for SIGTERM")
+            .recv()
+            .await;
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => {},
+        _ = terminate => {},
+    }
+}
+
+// Resolves once a shutdown has been requested through the channel
+pub async fn requested(mut shutdown: watch::Receiver<bool>) {
+    let _ = shutdown.wait_for(|shutting_down| *shutting_down).await;
+}
+
+// Resolves `DRAIN_TIMEOUT` after a shutdown has been requested
+pub async fn drain_timeout(shutdown: watch::Receiver<bool>) {
+    requested(shutdown).await;
+    tokio::time::sleep(DRAIN_TIMEOUT).await;
+}
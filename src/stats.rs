@@ -0,0 +1,64 @@
+use axum::{Json, http::StatusCode};
+use rusqlite::Connection;
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use crate::{db, now_seconds};
+
+// Site-wide numbers for the homepage. Counts include every user, also those excluded from ratings
+
+const DAY_SECONDS: u64 = 24 * 60 * 60;
+
+#[derive(Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct SiteStats {
+    // Published puzzles only
+    num_puzzles: u32,
+    // Rated and unrated
+    num_attempts: u32,
+    num_attempts_last_day: u32,
+    // Users with an attempt in the last 7 days
+    num_active_users_last_week: u32,
+    // The share of rated attempts that were solved. Not set before the first rated attempt
+    solve_rate: Option<f64>,
+}
+
+#[utoipa::path(
+    get,
+    path = "/stats",
+    tag = "puzzles",
+    responses((status = 200, body = SiteStats)),
+)]
+pub async fn get_stats() -> Result<Json<SiteStats>, StatusCode> {
+    let db_conn = db::open().map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    read_stats(&db_conn).map(Json).map_err(|e| {
+        tracing::error!("Error reading site stats: {:?}", e);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })
+}
+
+fn read_stats(db_conn: &Connection) -> anyhow::Result<SiteStats> {
+    let now = now_seconds();
+    let num_puzzles = db_conn.query_row(
+        "SELECT COUNT(*) FROM puzzles WHERE published = 1",
+        [],
+        |row| row.get(0),
+    )?;
+    let (num_attempts, num_attempts_last_day, num_active_users_last_week) = db_conn.query_row(
+        "SELECT COUNT(*), COUNT(*) FILTER (WHERE timestamp_seconds >= ?1),
+            COUNT(DISTINCT user_id) FILTER (WHERE timestamp_seconds >= ?2)
+        FROM puzzle_attempts",
+        [now - DAY_SECONDS, now - 7 * DAY_SECONDS],
+        |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)),
+    )?;
+    let solve_rate = db_conn.query_row("SELECT AVG(solved) FROM rated_attempts", [], |row| {
+        row.get(0)
+    })?;
+    Ok(SiteStats {
+        num_puzzles,
+        num_attempts,
+        num_attempts_last_day,
+        num_active_users_last_week,
+        solve_rate,
+    })
+}
@@ -0,0 +1,931 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+use anyhow::Context;
+use async_trait::async_trait;
+use rusqlite::{Connection, OptionalExtension, Transaction};
+use serde::{Deserialize, Serialize};
+use serde_rusqlite::from_row;
+use skillratings::{Outcomes, glicko2::Glicko2Rating};
+use utoipa::ToSchema;
+
+use crate::{
+    PuzzleRow, achievements, admin, attempts, audit, auth, bans, campaign, collections,
+    config::SeasonsConfig,
+    daily, db, default_target_time_seconds, events, experiments, explanations, friends,
+    idempotency, in_progress, leaderboard, migrations, notifications, puzzle_sets, races,
+    rating_history,
+    ratings::{self, RatingCache, RatingChange, RatingRow},
+    reports,
+    retries::{self, RetryResult, Retryable},
+    scheduler, search, seasons, sessions, settings, teams, telemetry, themes, tournaments,
+    webhooks,
+};
+
+// Which published puzzles `PuzzleStore` picks from. The default allows all of them
+#[derive(Clone, Copy, Default)]
+pub struct PuzzleFilter<'a> {
+    // Only puzzles from games this player played in, as either color
+    pub player: Option<&'a str>,
+    // Only puzzles on these board sizes. Empty for every size
+    pub sizes: &'a [usize],
+}
+
+impl PuzzleFilter<'_> {
+    // For a `?3` parameter, matched by `SIZE_FILTER`
+    pub fn sizes_json(&self) -> String {
+        serde_json::to_string(self.sizes).unwrap_or_default()
+    }
+}
+
+// The conditions of a `PuzzleFilter` on `puzzles`, with the player as `?2` and `sizes_json` as `?3`
+pub const PUZZLE_FILTER: &str = "(?2 IS NULL OR puzzles.player_white = ?2 COLLATE NOCASE OR puzzles.player_black = ?2 COLLATE NOCASE)
+    AND (json_array_length(?3) = 0 OR puzzles.size IN (SELECT value FROM json_each(?3)))";
+
+// Everything the puzzle handlers read and write: puzzles, attempts and ratings.
+// Handlers only see this trait, so they work with any backend. The other modules still query SQLite directly
+#[async_trait]
+pub trait PuzzleStore: Send + Sync {
+    // Any puzzle, published or not
+    async fn puzzle(&self, id: u32) -> anyhow::Result<Option<PuzzleRow>>;
+
+    async fn published_puzzle(&self, id: u32) -> anyhow::Result<Option<PuzzleRow>>;
+
+    // A random published puzzle that the user has never attempted
+    async fn unattempted_puzzle(
+        &self,
+        username: &str,
+        filter: &PuzzleFilter,
+    ) -> anyhow::Result<Option<PuzzleRow>>;
+
+    // A random published puzzle, preferring ones the user has attempted before
+    async fn practice_puzzle(
+        &self,
+        username: &str,
+        filter: &PuzzleFilter,
+    ) -> anyhow::Result<Option<PuzzleRow>>;
+
+    // A published puzzle the user has attempted, to review once they've attempted every puzzle.
+    // Puzzles they've never solved come first, then the ones they solved longest ago
+    async fn review_puzzle(
+        &self,
+        username: &str,
+        filter: &PuzzleFilter,
+    ) -> anyhow::Result<Option<PuzzleRow>>;
+
+    // The user's rated attempts
+    async fn attempts_for_user(&self, username: &str) -> anyhow::Result<Vec<PuzzleAttemptRow>>;
+
+    // Store an attempt and update the ratings, achievements and unlocks that depend on it, all or nothing.
+    // If the idempotency key was used before, nothing is stored and the earlier attempt is returned instead
+    async fn record_attempt(
+        &self,
+        attempt: attempts::NewAttempt,
+        idempotency_key: Option<String>,
+    ) -> anyhow::Result<AttemptOutcome>;
+
+    async fn puzzle_rating(&self, id: u32) -> anyhow::Result<Glicko2Rating>;
+
+    // The default rating for users who haven't made a rated attempt yet
+    async fn user_rating(&self, username: &str) -> anyhow::Result<Glicko2Rating>;
+
+    // The ratings of every published puzzle, by id
+    async fn published_puzzle_ratings(&self) -> anyhow::Result<BTreeMap<u32, f64>>;
+
+    // Forget any cached ratings of these puzzles, after their attempts were changed other than by `record_attempt`
+    fn invalidate_puzzle_ratings(&self, puzzle_ids: &[u32]);
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct PuzzleAttemptRow {
+    pub puzzle_id: u64,
+    pub username: String,
+    pub solved: bool,
+    pub solve_time_seconds: u32,
+    pub solution: String,
+    pub timestamp_seconds: u64,
+    pub attempt_number: u32,
+}
+
+// Also stored as JSON with idempotency keys, so that retries get the same rating change
+#[derive(Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct RecordedAttempt {
+    pub attempt_number: u32,
+    // Whether the attempt counts for the user's and the puzzle's ratings
+    pub rated: bool,
+    // Only set for rated attempts
+    pub rating_change: Option<RatingChange>,
+    // Only set for retries
+    #[serde(default)]
+    pub retry: Option<RetryResult>,
+}
+
+pub enum AttemptOutcome {
+    Recorded(RecordedAttempt),
+    // The attempt was sent as a retry, but the user has no failed attempt at the puzzle they can retry
+    NotRetryable,
+    // The idempotency key had already been used, for an attempt at `puzzle_id`
+    Repeated {
+        puzzle_id: u32,
+        attempt: RecordedAttempt,
+    },
+}
+
+// The SQLite backend. Reads open their own connection, and writes go through the single writer thread
+pub struct SqliteStore {
+    writer: db::Writer,
+    events: events::EventSender,
+    rating_cache: RatingCache,
+    seasons: SeasonsConfig,
+}
+
+impl SqliteStore {
+    pub fn new(events: events::EventSender, seasons: SeasonsConfig) -> anyhow::Result<Self> {
+        Ok(Self {
+            writer: db::Writer::start()?,
+            events,
+            rating_cache: RatingCache::default(),
+            seasons,
+        })
+    }
+}
+
+#[async_trait]
+impl PuzzleStore for SqliteStore {
+    async fn puzzle(&self, id: u32) -> anyhow::Result<Option<PuzzleRow>> {
+        read_puzzle_by_id(&db::open()?, id)
+    }
+
+    async fn published_puzzle(&self, id: u32) -> anyhow::Result<Option<PuzzleRow>> {
+        let db_conn = db::open()?;
+        let mut stmt = db_conn.prepare("SELECT * FROM puzzles WHERE id = ?1 AND published = 1")?;
+        Ok(stmt
+            .query_and_then([id], from_row::<PuzzleRow>)?
+            .next()
+            .transpose()?)
+    }
+
+    async fn unattempted_puzzle(
+        &self,
+        username: &str,
+        filter: &PuzzleFilter,
+    ) -> anyhow::Result<Option<PuzzleRow>> {
+        let db_conn = db::open()?;
+        telemetry::time_db_query("select_puzzle", || {
+            let mut stmt = db_conn.prepare(&format!(
+                "SELECT puzzles.* FROM puzzles
+                LEFT JOIN puzzle_attempts ON puzzles.id = puzzle_attempts.puzzle_id
+                    AND puzzle_attempts.user_id = (SELECT id FROM users WHERE username = ?1)
+                WHERE puzzles.published = 1 AND puzzle_attempts.puzzle_id IS NULL AND {PUZZLE_FILTER}
+                ORDER BY RANDOM() LIMIT 1"
+            ))?;
+            Ok(stmt
+                .query_and_then(
+                    rusqlite::params![username, filter.player, filter.sizes_json()],
+                    from_row::<PuzzleRow>,
+                )?
+                .next()
+                .transpose()?)
+        })
+    }
+
+    async fn practice_puzzle(
+        &self,
+        username: &str,
+        filter: &PuzzleFilter,
+    ) -> anyhow::Result<Option<PuzzleRow>> {
+        let db_conn = db::open()?;
+        telemetry::time_db_query("select_practice_puzzle", || {
+            let mut stmt = db_conn.prepare(&format!(
+                "SELECT puzzles.* FROM puzzles WHERE puzzles.published = 1 AND {PUZZLE_FILTER}
+                ORDER BY puzzles.id IN (
+                    SELECT puzzle_id FROM puzzle_attempts WHERE user_id = (SELECT id FROM users WHERE username = ?1)
+                ) DESC, RANDOM()
+                LIMIT 1"
+            ))?;
+            Ok(stmt
+                .query_and_then(
+                    rusqlite::params![username, filter.player, filter.sizes_json()],
+                    from_row::<PuzzleRow>,
+                )?
+                .next()
+                .transpose()?)
+        })
+    }
+
+    async fn review_puzzle(
+        &self,
+        username: &str,
+        filter: &PuzzleFilter,
+    ) -> anyhow::Result<Option<PuzzleRow>> {
+        let db_conn = db::open()?;
+        telemetry::time_db_query("select_review_puzzle", || {
+            let mut stmt = db_conn.prepare(&format!(
+                "SELECT puzzles.* FROM puzzles
+                JOIN puzzle_attempts ON puzzles.id = puzzle_attempts.puzzle_id
+                    AND puzzle_attempts.user_id = (SELECT id FROM users WHERE username = ?1)
+                WHERE puzzles.published = 1 AND {PUZZLE_FILTER}
+                GROUP BY puzzles.id
+                ORDER BY MAX(puzzle_attempts.solved) ASC,
+                    MAX(CASE WHEN puzzle_attempts.solved THEN puzzle_attempts.timestamp_seconds END) ASC,
+                    RANDOM()
+                LIMIT 1"
+            ))?;
+            Ok(stmt
+                .query_and_then(
+                    rusqlite::params![username, filter.player, filter.sizes_json()],
+                    from_row::<PuzzleRow>,
+                )?
+                .next()
+                .transpose()?)
+        })
+    }
+
+    async fn attempts_for_user(&self, username: &str) -> anyhow::Result<Vec<PuzzleAttemptRow>> {
+        let db_conn = db::open()?;
+        let mut stmt = db_conn.prepare("SELECT * FROM rated_attempts WHERE username = ?1")?;
+        let rows = stmt.query_and_then([username], from_row::<PuzzleAttemptRow>)?;
+        Ok(rows.collect::<Result<Vec<_>, _>>()?)
+    }
+
+    async fn record_attempt(
+        &self,
+        attempt: attempts::NewAttempt,
+        idempotency_key: Option<String>,
+    ) -> anyhow::Result<AttemptOutcome> {
+        let puzzle_id = attempt.puzzle_id;
+        let events = self.events.clone();
+        let seasons = self.seasons.clone();
+        let outcome = self
+            .writer
+            .write(move |db_conn| -> anyhow::Result<_> {
+                let transaction = db_conn.transaction()?;
+                if let Some(key) = &idempotency_key
+                    && let Some(keyed) =
+                        idempotency::read_keyed_attempt(&transaction, &attempt.username, key)?
+                {
+                    let recorded = keyed
+                        .response
+                        .and_then(|response| serde_json::from_str(&response).ok())
+                        .unwrap_or_else(|| RecordedAttempt {
+                            attempt_number: keyed.attempt_number,
+                            rated: attempts::is_rated(
+                                keyed.attempt_number,
+                                attempt.practice,
+                                false,
+                            ),
+                            rating_change: None,
+                            retry: None,
+                        });
+                    return Ok(AttemptOutcome::Repeated {
+                        puzzle_id: keyed.puzzle_id,
+                        attempt: recorded,
+                    });
+                }
+                let retried = if attempt.retry {
+                    match retries::take(&transaction, &attempt.username, attempt.puzzle_id)? {
+                        Some(retried) => Some(retried),
+                        None => return Ok(AttemptOutcome::NotRetryable),
+                    }
+                } else {
+                    None
+                };
+                let recorded = telemetry::time_db_query("record_attempt", || {
+                    write_attempt(&transaction, &attempt, retried, &seasons)
+                })?;
+                if let Some(key) = &idempotency_key {
+                    let keyed = idempotency::KeyedAttempt {
+                        puzzle_id: attempt.puzzle_id,
+                        attempt_number: recorded.attempt_number,
+                        response: Some(serde_json::to_string(&recorded)?),
+                    };
+                    idempotency::store_key(&transaction, &attempt.username, key, &keyed)?;
+                }
+                transaction.commit()?;
+                publish_attempt_events(db_conn, &events, &attempt, recorded.rating_change.as_ref());
+                Ok(AttemptOutcome::Recorded(recorded))
+            })
+            .await??;
+        if let AttemptOutcome::Recorded(_) = outcome {
+            self.rating_cache.invalidate(puzzle_id as i64);
+        }
+        Ok(outcome)
+    }
+
+    async fn puzzle_rating(&self, id: u32) -> anyhow::Result<Glicko2Rating> {
+        self.rating_cache
+            .rating_for_puzzle(id as i64, || rating_for_puzzle(&db::open()?, id as i64))
+    }
+
+    async fn user_rating(&self, username: &str) -> anyhow::Result<Glicko2Rating> {
+        Ok(read_user_rating(&db::open()?, username)?.unwrap_or_default())
+    }
+
+    async fn published_puzzle_ratings(&self) -> anyhow::Result<BTreeMap<u32, f64>> {
+        let db_conn = db::open()?;
+        telemetry::time_db_query("read_puzzle_ratings", || {
+            ratings_for_published_puzzles(&db_conn)
+        })
+    }
+
+    fn invalidate_puzzle_ratings(&self, puzzle_ids: &[u32]) {
+        for &puzzle_id in puzzle_ids {
+            self.rating_cache.invalidate(puzzle_id as i64);
+        }
+    }
+}
+
+// The store from `database.postgres_url`, see `postgres.rs`
+#[cfg(feature = "postgres")]
+pub async fn open_postgres(url: &str) -> anyhow::Result<std::sync::Arc<dyn PuzzleStore>> {
+    Ok(std::sync::Arc::new(
+        crate::postgres::PostgresStore::connect(url).await?,
+    ))
+}
+
+#[cfg(not(feature = "postgres"))]
+pub async fn open_postgres(_url: &str) -> anyhow::Result<std::sync::Arc<dyn PuzzleStore>> {
+    anyhow::bail!(
+        "`database.postgres_url` is set, but the server was built without the `postgres` feature"
+    )
+}
+
+pub fn init_db_tables() -> anyhow::Result<()> {
+    let mut db_conn = db::open().context("Failed to open database connection")?;
+
+    db_conn.execute(
+        "CREATE TABLE IF NOT EXISTS puzzles (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            root_tps TEXT NOT NULL,
+            defender_start_move TEXT NOT NULL,
+            size INTEGER NOT NULL,
+            komi TEXT NOT NULL,
+            player_white TEXT NOT NULL,
+            player_black TEXT NOT NULL,
+            solution TEXT NOT NULL,
+            initial_rating INTEGER,
+            rating INTEGER,
+            target_time_seconds INTEGER NOT NULL DEFAULT 60,
+            playtak_game_id INTEGER NOT NULL
+        )",
+        [],
+    )?;
+
+    db_conn.execute(
+        "CREATE TABLE IF NOT EXISTS puzzle_attempts (
+            puzzle_id INTEGER NOT NULL,
+            username TEXT NOT NULL,
+            solved INTEGER NOT NULL,
+            solve_time_seconds INTEGER NOT NULL,
+            solution TEXT NOT NULL,
+            timestamp_seconds INTEGER NOT NULL DEFAULT (strftime('%s', 'now')),
+            FOREIGN KEY (puzzle_id) REFERENCES puzzles(id)
+        )",
+        [],
+    )?;
+
+    // When each move of an attempt was played, for attempts where that's known. See `attempts.rs`
+    db_conn.execute(
+        "CREATE TABLE IF NOT EXISTS attempt_moves (
+            username TEXT NOT NULL,
+            puzzle_id INTEGER NOT NULL,
+            attempt_number INTEGER NOT NULL,
+            move_index INTEGER NOT NULL,
+            ptn_move TEXT NOT NULL,
+            elapsed_ms INTEGER NOT NULL,
+            PRIMARY KEY (username, puzzle_id, attempt_number, move_index)
+        )",
+        [],
+    )?;
+
+    // Users are added with the default rating on their first attempt. See `users.rs`,
+    // and `migrations.rs` for the columns that were added since
+    db_conn.execute(
+        "CREATE TABLE IF NOT EXISTS \"users\" (
+	    \"username\" TEXT NOT NULL,
+	    \"rating\" REAL NOT NULL,
+	    PRIMARY KEY(\"username\")
+    )",
+        [],
+    )?;
+
+    puzzle_sets::init_db_tables(&db_conn)?;
+    collections::init_db_tables(&db_conn)?;
+    campaign::init_db_tables(&db_conn)?;
+    achievements::init_db_tables(&db_conn)?;
+    daily::init_db_tables(&db_conn)?;
+    friends::init_db_tables(&db_conn)?;
+    idempotency::init_db_tables(&db_conn)?;
+    in_progress::init_db_tables(&db_conn)?;
+    leaderboard::init_db_tables(&db_conn)?;
+    rating_history::init_db_tables(&db_conn)?;
+    seasons::init_db_tables(&db_conn)?;
+    races::init_db_tables(&db_conn)?;
+    tournaments::init_db_tables(&db_conn)?;
+    teams::init_db_tables(&db_conn)?;
+    admin::init_db_tables(&db_conn)?;
+    audit::init_db_tables(&db_conn)?;
+    scheduler::init_db_tables(&db_conn)?;
+    experiments::init_db_tables(&db_conn)?;
+    reports::init_db_tables(&db_conn)?;
+    bans::init_db_tables(&db_conn)?;
+    webhooks::init_db_tables(&db_conn)?;
+    search::init_db_tables(&db_conn)?;
+    themes::init_db_tables(&db_conn)?;
+    explanations::init_db_tables(&db_conn)?;
+    settings::init_db_tables(&db_conn)?;
+    notifications::init_db_tables(&db_conn)?;
+    auth::init_db_tables(&db_conn)?;
+    sessions::init_db_tables(&db_conn)?;
+    retries::init_db_tables(&db_conn)?;
+
+    migrations::run(&mut db_conn)?;
+
+    Ok(())
+}
+
+pub fn read_puzzle_by_id(db_conn: &Connection, id: u32) -> anyhow::Result<Option<PuzzleRow>> {
+    let mut stmt = db_conn.prepare("SELECT * FROM puzzles WHERE id = ?1")?;
+    Ok(stmt
+        .query_and_then([id], from_row::<PuzzleRow>)?
+        .next()
+        .transpose()?)
+}
+
+// Store an attempt, and update everything that depends on the user's attempts.
+// `retried` is the failed attempt it retries, if it's a retry.
+// Takes a transaction so that the attempt and the rating updates are only ever written together
+fn write_attempt(
+    db_conn: &Transaction,
+    attempt: &attempts::NewAttempt,
+    retried: Option<Retryable>,
+    seasons: &SeasonsConfig,
+) -> anyhow::Result<RecordedAttempt> {
+    let username = &attempt.username;
+    let puzzle_rating_before = if attempt.practice {
+        None
+    } else {
+        Some(rating_for_puzzle(db_conn, attempt.puzzle_id as i64)?)
+    };
+
+    let retry_of = retried.as_ref().map(|retried| retried.attempt_number);
+    let inserted = attempts::insert_attempt(db_conn, attempt, retry_of)?;
+    let attempt_number = inserted.attempt_number;
+    let rated = attempts::is_rated(attempt_number, attempt.practice, inserted.shared_device);
+    if retried.is_none() {
+        retries::attempted(db_conn, username, attempt.puzzle_id, rated)?;
+    }
+    experiments::record_variants(db_conn, username, attempt.puzzle_id, attempt_number)?;
+    telemetry::record_attempt_submitted(rated, attempt.solved);
+
+    let rating_change = match puzzle_rating_before {
+        Some(puzzle_rating) if rated => {
+            let (old_rating, new_rating) = update_user_rating(
+                db_conn,
+                username,
+                &puzzle_rating,
+                ratings::outcome(attempt.solved, false),
+            )?;
+            rating_history::record_user_rating(
+                db_conn,
+                username,
+                attempt.puzzle_id,
+                attempt_number,
+                &new_rating,
+            )?;
+            let season_rating = seasons::record_attempt(
+                db_conn,
+                seasons,
+                username,
+                &puzzle_rating,
+                attempt.solved,
+            )?;
+            if !attempt.solved {
+                let retryable = Retryable {
+                    attempt_number,
+                    rating: old_rating,
+                    puzzle_rating,
+                    season_rating,
+                };
+                retries::remember(db_conn, username, attempt.puzzle_id, &retryable)?;
+            }
+            let puzzle_rating = limited_rating_for_puzzle(db_conn, attempt.puzzle_id as i64)?;
+            if counts_for_puzzle_ratings(db_conn, username)? {
+                rating_history::record_puzzle_rating(
+                    db_conn,
+                    username,
+                    attempt.puzzle_id,
+                    attempt_number,
+                    &puzzle_rating,
+                )?;
+            }
+            Some(RatingChange {
+                old_rating: old_rating.rating,
+                new_rating: new_rating.rating.rating,
+                puzzle_rating: puzzle_rating.rating.rating,
+            })
+        }
+        _ => None,
+    };
+    let retry = match retried {
+        Some(retried) => Some(rate_retried_attempt(
+            db_conn,
+            attempt,
+            attempt_number,
+            &retried,
+            seasons,
+        )?),
+        None => None,
+    };
+
+    campaign::update_unlocks(db_conn, username)?;
+    achievements::evaluate(db_conn, username)?;
+    Ok(RecordedAttempt {
+        attempt_number,
+        rated: rating_change.is_some(),
+        rating_change,
+        retry,
+    })
+}
+
+// After a retry, rate the failed attempt it retried again, as a draw if the retry solved the puzzle.
+// The user's rating is rated again from the one they had before the failed attempt, see `retries.rs`
+fn rate_retried_attempt(
+    db_conn: &Connection,
+    retry: &attempts::NewAttempt,
+    retry_number: u32,
+    retried: &Retryable,
+    seasons: &SeasonsConfig,
+) -> anyhow::Result<RetryResult> {
+    let username = &retry.username;
+    if !retry.solved {
+        return Ok(RetryResult {
+            retry_of: retried.attempt_number,
+            score: 0.0,
+            rating_change: None,
+        });
+    }
+    db_conn.execute(
+        "UPDATE puzzle_attempts SET solved_on_retry = 1
+        WHERE user_id = (SELECT id FROM users WHERE username = ?1) AND puzzle_id = ?2 AND attempt_number = ?3",
+        rusqlite::params![username, retry.puzzle_id, retried.attempt_number],
+    )?;
+    let old_rating = read_user_rating(db_conn, username)?.unwrap_or_default();
+    let new_rating = ratings::rate_user(&retried.rating, &retried.puzzle_rating, Outcomes::DRAW);
+    write_user_rating(db_conn, username, &new_rating.rating)?;
+    rating_history::record_user_rating(
+        db_conn,
+        username,
+        retry.puzzle_id,
+        retried.attempt_number,
+        &new_rating,
+    )?;
+    if let Some(season_rating) = &retried.season_rating {
+        seasons::rerate_attempt(
+            db_conn,
+            seasons,
+            username,
+            season_rating,
+            &retried.puzzle_rating,
+            Outcomes::DRAW,
+        )?;
+    }
+    // Other users may have attempted the puzzle since the failed attempt,
+    // so its new rating is recorded at the retry rather than at the failed attempt
+    let puzzle_rating = limited_rating_for_puzzle(db_conn, retry.puzzle_id as i64)?;
+    if counts_for_puzzle_ratings(db_conn, username)? {
+        rating_history::record_puzzle_rating(
+            db_conn,
+            username,
+            retry.puzzle_id,
+            retry_number,
+            &puzzle_rating,
+        )?;
+    }
+    Ok(RetryResult {
+        retry_of: retried.attempt_number,
+        score: 0.5,
+        rating_change: Some(RatingChange {
+            old_rating: old_rating.rating,
+            new_rating: new_rating.rating.rating,
+            puzzle_rating: puzzle_rating.rating.rating,
+        }),
+    })
+}
+
+// Tell live feeds about an attempt. Should only be called once the attempt has been committed,
+// and the attempt is already stored by then, so errors are only logged
+fn publish_attempt_events(
+    db_conn: &Connection,
+    events: &events::EventSender,
+    attempt: &attempts::NewAttempt,
+    rating_change: Option<&RatingChange>,
+) {
+    // Practice doesn't change any ratings, and shouldn't show up in other users' live feeds
+    if attempt.practice {
+        return;
+    }
+    if let Err(e) = events::publish_attempt_events(db_conn, events, attempt, rating_change) {
+        tracing::error!("Error publishing attempt events: {:?}", e);
+    }
+}
+
+// Compute a puzzle's rating from scratch. Prefer `PuzzleStore::puzzle_rating`, which is cached
+pub fn rating_for_puzzle(db_conn: &Connection, puzzle_id: i64) -> anyhow::Result<Glicko2Rating> {
+    Ok(limited_rating_for_puzzle(db_conn, puzzle_id)?.rating)
+}
+
+fn limited_rating_for_puzzle(
+    db_conn: &Connection,
+    puzzle_id: i64,
+) -> anyhow::Result<ratings::Limited> {
+    telemetry::time_rating_computation("puzzle", || {
+        let mut stmt = db_conn.prepare(
+            "SELECT rated_attempts.solved, rated_attempts.solved_on_retry, rated_attempts.username, users.rating, users.deviation, users.volatility,
+                (SELECT COUNT(*) FROM rated_attempts AS others
+                    WHERE others.user_id = rated_attempts.user_id) < ?2 AS provisional
+            FROM rated_attempts JOIN users ON users.id = rated_attempts.user_id
+            WHERE puzzle_id = ?1 AND users.excluded_from_ratings = 0",
+        )?;
+        let ratings: Vec<RatingRow> = stmt
+            .query_and_then(
+                rusqlite::params![puzzle_id, ratings::PROVISIONAL_ATTEMPTS],
+                from_row::<RatingRow>,
+            )?
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let solution = db_conn
+            .query_row(
+                "SELECT solution FROM puzzles WHERE id = ?1",
+                [puzzle_id],
+                |row| row.get::<_, String>(0),
+            )
+            .unwrap_or_default();
+
+        Ok(ratings::rate_puzzle(
+            ratings::default_rating_for_solution(&solution),
+            ratings,
+        ))
+    })
+}
+
+// Replay every rated attempt in order, recomputing every user's rating from the default.
+// Each attempt is rated against the puzzle's rating from the attempts before it, like when it was made.
+// Users without any rated attempts keep their rating. Returns the number of users whose rating changed.
+// The rating history is rewritten along with the ratings
+pub fn recompute_user_ratings(db_conn: &mut Connection) -> anyhow::Result<usize> {
+    let transaction = db_conn.transaction()?;
+    let num_changed = replay_rated_attempts(&transaction)?;
+    transaction.commit()?;
+    Ok(num_changed)
+}
+
+// `recompute_user_ratings`, as part of a larger transaction
+pub fn replay_rated_attempts(transaction: &Transaction) -> anyhow::Result<usize> {
+    transaction.execute("DELETE FROM user_rating_history", [])?;
+    transaction.execute("DELETE FROM puzzle_rating_history", [])?;
+    transaction.execute("DELETE FROM rating_clamps", [])?;
+    let mut stmt = transaction.prepare(
+        "SELECT rated_attempts.puzzle_id, rated_attempts.username, rated_attempts.solved, puzzles.solution,
+            rated_attempts.attempt_number, rated_attempts.solved_on_retry
+        FROM rated_attempts JOIN puzzles ON puzzles.id = rated_attempts.puzzle_id
+        ORDER BY rated_attempts.timestamp_seconds, rated_attempts.id",
+    )?;
+    let attempts = stmt
+        .query_map([], |row| {
+            Ok((
+                row.get::<_, u64>(0)?,
+                row.get::<_, String>(1)?,
+                row.get::<_, bool>(2)?,
+                row.get::<_, String>(3)?,
+                row.get::<_, u32>(4)?,
+                row.get::<_, bool>(5)?,
+            ))
+        })?
+        .collect::<Result<Vec<_>, _>>()?;
+    drop(stmt);
+
+    let excluded: HashSet<String> = transaction
+        .prepare("SELECT username FROM users WHERE excluded_from_ratings = 1")?
+        .query_map([], |row| row.get(0))?
+        .collect::<Result<_, _>>()?;
+
+    // A puzzle's rating from the attempts so far that count towards it, with the users' ratings
+    // and numbers of rated attempts so far
+    let rate_puzzle = |solution: &str,
+                       earlier: &[(String, bool, bool)],
+                       user_ratings: &HashMap<String, Glicko2Rating>,
+                       num_rated: &HashMap<String, u32>| {
+        ratings::rate_puzzle(
+            ratings::default_rating_for_solution(solution),
+            earlier
+                .iter()
+                .map(|(username, solved, solved_on_retry)| {
+                    let rating = user_ratings.get(username).copied().unwrap_or_default();
+                    RatingRow {
+                        solved: *solved,
+                        solved_on_retry: *solved_on_retry,
+                        username: username.clone(),
+                        rating: rating.rating,
+                        deviation: rating.deviation,
+                        volatility: rating.volatility,
+                        provisional: num_rated.get(username).copied().unwrap_or_default()
+                            < ratings::PROVISIONAL_ATTEMPTS,
+                    }
+                })
+                .collect(),
+        )
+    };
+
+    let mut user_ratings: HashMap<String, Glicko2Rating> = HashMap::new();
+    let mut num_rated: HashMap<String, u32> = HashMap::new();
+    let mut earlier_attempts: HashMap<u64, Vec<(String, bool, bool)>> = HashMap::new();
+    for (puzzle_id, username, solved, solution, attempt_number, solved_on_retry) in attempts {
+        let earlier = earlier_attempts.entry(puzzle_id).or_default();
+        let puzzle_rating = rate_puzzle(&solution, earlier, &user_ratings, &num_rated).rating;
+        *num_rated.entry(username.clone()).or_default() += 1;
+        let user_rating = user_ratings.entry(username.clone()).or_default();
+        let new_rating = ratings::rate_user(
+            user_rating,
+            &puzzle_rating,
+            ratings::outcome(solved, solved_on_retry),
+        );
+        *user_rating = new_rating.rating;
+        rating_history::record_user_rating(
+            transaction,
+            &username,
+            puzzle_id as u32,
+            attempt_number,
+            &new_rating,
+        )?;
+        if !excluded.contains(&username) {
+            earlier.push((username.clone(), solved, solved_on_retry));
+            rating_history::record_puzzle_rating(
+                transaction,
+                &username,
+                puzzle_id as u32,
+                attempt_number,
+                &rate_puzzle(&solution, earlier, &user_ratings, &num_rated),
+            )?;
+        }
+    }
+
+    let mut num_changed = 0;
+    for (username, rating) in &user_ratings {
+        let old_rating = read_user_rating(transaction, username)?;
+        if old_rating.as_ref() != Some(rating) {
+            num_changed += 1;
+        }
+        transaction.execute(
+            "INSERT INTO users (username, rating, deviation, volatility) VALUES (?1, ?2, ?3, ?4)
+            ON CONFLICT (username) DO UPDATE
+                SET rating = excluded.rating, deviation = excluded.deviation, volatility = excluded.volatility",
+            rusqlite::params![username, rating.rating, rating.deviation, rating.volatility],
+        )?;
+    }
+    Ok(num_changed)
+}
+
+// Set every puzzle's target time to `default_target_time_seconds`, replacing any that were set by hand.
+// Returns the number of puzzles whose target time changed
+pub fn backfill_target_times(db_conn: &mut Connection) -> anyhow::Result<usize> {
+    let transaction = db_conn.transaction()?;
+    let mut stmt =
+        transaction.prepare("SELECT id, root_tps, solution, target_time_seconds FROM puzzles")?;
+    let puzzles = stmt
+        .query_map([], |row| {
+            Ok((
+                row.get::<_, u64>(0)?,
+                row.get::<_, String>(1)?,
+                row.get::<_, String>(2)?,
+                row.get::<_, u32>(3)?,
+            ))
+        })?
+        .collect::<Result<Vec<_>, _>>()?;
+    drop(stmt);
+
+    let mut num_changed = 0;
+    for (id, root_tps, solution, old_target_time) in puzzles {
+        let target_time = default_target_time_seconds(&root_tps, &solution);
+        if target_time != old_target_time {
+            transaction.execute(
+                "UPDATE puzzles SET target_time_seconds = ?1 WHERE id = ?2",
+                rusqlite::params![target_time, id],
+            )?;
+            num_changed += 1;
+        }
+    }
+    transaction.commit()?;
+    Ok(num_changed)
+}
+
+// Users flagged with `excluded_from_ratings` still have their own rating, but their attempts don't change
+// puzzle ratings, like the queries above
+fn counts_for_puzzle_ratings(db_conn: &Connection, username: &str) -> anyhow::Result<bool> {
+    Ok(db_conn
+        .query_row(
+            "SELECT excluded_from_ratings FROM users WHERE username = ?1",
+            [username],
+            |row| row.get::<_, bool>(0),
+        )
+        .optional()?
+        .is_none_or(|excluded| !excluded))
+}
+
+// Remember the form of the username the user last typed, see `validation::canonical_username`
+pub fn set_display_name(
+    db_conn: &Connection,
+    username: &str,
+    display_name: &str,
+) -> anyhow::Result<()> {
+    db_conn
+        .prepare_cached("UPDATE users SET display_name = ?2 WHERE username = ?1")?
+        .execute([username, display_name])?;
+    Ok(())
+}
+
+// The ratings of every published puzzle, from a single query over all their rated attempts
+fn ratings_for_published_puzzles(db_conn: &Connection) -> anyhow::Result<BTreeMap<u32, f64>> {
+    let mut stmt = db_conn.prepare(
+        "WITH rated_counts AS (SELECT user_id, COUNT(*) AS num_rated FROM rated_attempts GROUP BY user_id)
+        SELECT puzzles.id, puzzles.solution, rated_attempts.solved, rated_attempts.username,
+            users.rating, users.deviation, users.volatility, rated_counts.num_rated < ?1, rated_attempts.solved_on_retry
+        FROM puzzles
+        LEFT JOIN rated_attempts ON rated_attempts.puzzle_id = puzzles.id
+            AND rated_attempts.user_id NOT IN (SELECT id FROM users WHERE excluded_from_ratings = 1)
+        LEFT JOIN users ON users.id = rated_attempts.user_id
+        LEFT JOIN rated_counts ON rated_counts.user_id = rated_attempts.user_id
+        WHERE puzzles.published = 1",
+    )?;
+    let mut puzzles: BTreeMap<u32, (String, Vec<RatingRow>)> = BTreeMap::new();
+    let mut rows = stmt.query([ratings::PROVISIONAL_ATTEMPTS])?;
+    while let Some(row) = rows.next()? {
+        let (_, ratings) = puzzles
+            .entry(row.get(0)?)
+            .or_insert_with(|| (row.get(1).unwrap_or_default(), vec![]));
+        // Puzzles without attempts still get one row, with nulls for the attempt
+        if let Some(username) = row.get::<_, Option<String>>(3)? {
+            ratings.push(RatingRow {
+                solved: row.get(2)?,
+                solved_on_retry: row.get(8)?,
+                username,
+                rating: row.get(4)?,
+                deviation: row.get(5)?,
+                volatility: row.get(6)?,
+                provisional: row.get(7)?,
+            });
+        }
+    }
+    Ok(puzzles
+        .into_iter()
+        .map(|(id, (solution, ratings))| {
+            let rating = telemetry::time_rating_computation("puzzle", || {
+                ratings::rate_puzzle(ratings::default_rating_for_solution(&solution), ratings)
+            });
+            (id, rating.rating.rating)
+        })
+        .collect())
+}
+
+fn read_user_rating(db_conn: &Connection, username: &str) -> anyhow::Result<Option<Glicko2Rating>> {
+    Ok(db_conn
+        .query_row(
+            "SELECT rating, deviation, volatility FROM users WHERE username = ?1",
+            [username],
+            |row| {
+                Ok(Glicko2Rating {
+                    rating: row.get(0)?,
+                    deviation: row.get(1)?,
+                    volatility: row.get(2)?,
+                })
+            },
+        )
+        .optional()?)
+}
+
+// Update the user's rating after a rated attempt, see `ratings::rate_user`
+fn update_user_rating(
+    db_conn: &Connection,
+    username: &str,
+    puzzle_rating: &Glicko2Rating,
+    outcome: Outcomes,
+) -> anyhow::Result<(Glicko2Rating, ratings::Limited)> {
+    let old_rating = read_user_rating(db_conn, username)?.unwrap_or_default();
+    let new_rating = ratings::rate_user(&old_rating, puzzle_rating, outcome);
+    write_user_rating(db_conn, username, &new_rating.rating)?;
+    Ok((old_rating, new_rating))
+}
+
+fn write_user_rating(
+    db_conn: &Connection,
+    username: &str,
+    rating: &Glicko2Rating,
+) -> anyhow::Result<()> {
+    db_conn.execute(
+        "INSERT INTO users (username, rating, deviation, volatility) VALUES (?1, ?2, ?3, ?4)
+        ON CONFLICT (username) DO UPDATE
+            SET rating = excluded.rating, deviation = excluded.deviation, volatility = excluded.volatility",
+        rusqlite::params![username, rating.rating, rating.deviation, rating.volatility],
+    )?;
+    Ok(())
+}
@@ -0,0 +1,288 @@
+use std::fmt::Write;
+
+use axum::{
+    extract::{Path, State},
+    http::{HeaderMap, HeaderValue, StatusCode, header},
+    response::{IntoResponse, Response},
+};
+
+use crate::{
+    AppState, PuzzleRow,
+    board::{Board, Color, Move, Role, Square},
+    etag, validation,
+};
+
+// Board diagrams, for link previews, the Atom feed and chat embeds
+
+const SQUARE_SIZE: usize = 64;
+// Room for the coordinates, left of and below the board
+const MARGIN: usize = 24;
+// Stacks taller than this only show their top pieces
+const MAX_STACK_PIECES: usize = 9;
+// How long each position of an animated solution is shown. The final position is shown twice as long
+const FRAME_SECONDS: f64 = 1.5;
+
+// The puzzle as the user first sees it, after the defender's move, which is highlighted
+#[utoipa::path(
+    get,
+    path = "/puzzles/{id}/image.svg",
+    tag = "puzzles",
+    params(("id" = u32, Path)),
+    responses(
+        (status = 200, description = "The board as an SVG image", content_type = "image/svg+xml", body = String),
+        (status = 304, description = "The image matches the `If-None-Match` ETag"),
+        (status = 404),
+    ),
+)]
+pub async fn get_puzzle_image(
+    Path(id): Path<u32>,
+    State(state): State<AppState>,
+    headers: HeaderMap,
+) -> Result<Response, StatusCode> {
+    let puzzle = state
+        .store
+        .published_puzzle(id)
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+        .ok_or(StatusCode::NOT_FOUND)?;
+    let svg = render_puzzle(&puzzle).map_err(|e| {
+        tracing::error!("Error rendering puzzle {}: {:?}", id, e);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+    Ok(svg_response(&headers, svg))
+}
+
+// The solution played out from the puzzle's start, one move at a time, as an animation that loops.
+// Browsers play it when the image is opened directly or shown with an `<img>` tag
+#[utoipa::path(
+    get,
+    path = "/puzzles/{id}/solution.svg",
+    tag = "puzzles",
+    params(("id" = u32, Path)),
+    responses(
+        (status = 200, description = "The solution as an animated SVG image", content_type = "image/svg+xml", body = String),
+        (status = 304, description = "The image matches the `If-None-Match` ETag"),
+        (status = 404),
+    ),
+)]
+pub async fn get_solution_animation(
+    Path(id): Path<u32>,
+    State(state): State<AppState>,
+    headers: HeaderMap,
+) -> Result<Response, StatusCode> {
+    let puzzle = state
+        .store
+        .published_puzzle(id)
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+        .ok_or(StatusCode::NOT_FOUND)?;
+    let svg = render_solution(&puzzle).map_err(|e| {
+        tracing::error!("Error rendering the solution of puzzle {}: {:?}", id, e);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+    Ok(svg_response(&headers, svg))
+}
+
+fn svg_response(headers: &HeaderMap, svg: String) -> Response {
+    let etag = etag::weak_etag(&svg);
+    etag::with_etag(
+        headers,
+        etag,
+        (
+            [(
+                header::CONTENT_TYPE,
+                HeaderValue::from_static("image/svg+xml"),
+            )],
+            svg,
+        )
+            .into_response(),
+    )
+}
+
+fn render_solution(puzzle: &PuzzleRow) -> anyhow::Result<String> {
+    let (mut board, highlighted) = puzzle_start(puzzle)?;
+    let mut frames = vec![(board.clone(), highlighted)];
+    for ptn_move in puzzle.solution.split_whitespace() {
+        if validation::normalize_move(ptn_move).is_empty() {
+            continue;
+        }
+        let mv = Move::parse(ptn_move)?;
+        board.play(&mv)?;
+        frames.push((board.clone(), mv.squares()));
+    }
+    Ok(render_animation(&frames))
+}
+
+// Every frame is drawn, and shown in turn by animating its opacity
+pub fn render_animation(frames: &[(Board, Vec<Square>)]) -> String {
+    let [(first, first_highlighted), rest @ ..] = frames else {
+        return String::new();
+    };
+    if rest.is_empty() {
+        return render(first, first_highlighted);
+    }
+    let num_units = frames.len() + 1;
+    let duration = num_units as f64 * FRAME_SECONDS;
+    let mut svg = open(first);
+    for (i, (board, highlighted)) in frames.iter().enumerate() {
+        let start = i as f64 / num_units as f64;
+        let end = (i + 1) as f64 / num_units as f64;
+        let (values, key_times) = match i {
+            0 => ("1;0".to_string(), format!("0;{end}")),
+            _ if i == frames.len() - 1 => ("0;1".to_string(), format!("0;{start}")),
+            _ => ("0;1;0".to_string(), format!("0;{start};{end}")),
+        };
+        let _ = write!(
+            svg,
+            r#"<g opacity="{}"><animate attributeName="opacity" values="{values}" keyTimes="{key_times}" dur="{duration}s" calcMode="discrete" repeatCount="indefinite"/>"#,
+            if i == 0 { 1 } else { 0 },
+        );
+        render_board(&mut svg, board, highlighted);
+        svg.push_str("</g>");
+    }
+    svg.push_str("</svg>");
+    svg
+}
+
+fn render_puzzle(puzzle: &PuzzleRow) -> anyhow::Result<String> {
+    let (board, highlighted) = puzzle_start(puzzle)?;
+    Ok(render(&board, &highlighted))
+}
+
+// The position after the defender's move, and the squares that move changed
+fn puzzle_start(puzzle: &PuzzleRow) -> anyhow::Result<(Board, Vec<Square>)> {
+    let mut board = Board::from_tps(&puzzle.root_tps)?;
+    let mut highlighted = Vec::new();
+    if !validation::normalize_move(&puzzle.defender_start_move).is_empty() {
+        let mv = Move::parse(&puzzle.defender_start_move)?;
+        board.play(&mv)?;
+        highlighted = mv.squares();
+    }
+    Ok((board, highlighted))
+}
+
+pub fn render(board: &Board, highlighted: &[Square]) -> String {
+    let mut svg = open(board);
+    render_board(&mut svg, board, highlighted);
+    svg.push_str("</svg>");
+    svg
+}
+
+// The image with everything but the pieces and highlights, which is the same in every frame
+fn open(board: &Board) -> String {
+    let board_size = board.size * SQUARE_SIZE;
+    let mut svg = String::new();
+    let _ = write!(
+        svg,
+        r#"<svg xmlns="http://www.w3.org/2000/svg" width="{width}" height="{width}" viewBox="0 0 {width} {width}" font-family="sans-serif">"#,
+        width = board_size + MARGIN + 8,
+    );
+    svg.push_str(r##"<rect width="100%" height="100%" fill="#f7f3ea"/>"##);
+    for i in 0..board.size {
+        let center = MARGIN + i * SQUARE_SIZE + SQUARE_SIZE / 2;
+        let _ = write!(
+            svg,
+            r##"<text x="{center}" y="{y}" font-size="14" text-anchor="middle" fill="#555">{file}</text>"##,
+            y = board_size + MARGIN - 4,
+            file = (b'a' + i as u8) as char,
+        );
+        let _ = write!(
+            svg,
+            r##"<text x="{x}" y="{y}" font-size="14" text-anchor="middle" fill="#555">{rank}</text>"##,
+            x = MARGIN / 2,
+            y = 8 + i * SQUARE_SIZE + SQUARE_SIZE / 2 + 5,
+            rank = board.size - i,
+        );
+    }
+    svg
+}
+
+fn render_board(svg: &mut String, board: &Board, highlighted: &[Square]) {
+    for rank in 0..board.size {
+        for file in 0..board.size {
+            let square = Square { file, rank };
+            let (x, y) = corner(board, square);
+            let fill = if (file + rank) % 2 == 0 {
+                "#c9b48a"
+            } else {
+                "#e2d3b1"
+            };
+            let _ = write!(
+                svg,
+                r#"<rect x="{x}" y="{y}" width="{SQUARE_SIZE}" height="{SQUARE_SIZE}" fill="{fill}"/>"#
+            );
+            if highlighted.contains(&square) {
+                let _ = write!(
+                    svg,
+                    r##"<rect x="{x}" y="{y}" width="{SQUARE_SIZE}" height="{SQUARE_SIZE}" fill="#f2d95c" fill-opacity="0.6"/>"##
+                );
+            }
+            render_stack(svg, board, square);
+        }
+    }
+}
+
+// The top left corner of the square. The board is drawn below an 8px border, right of the rank numbers
+fn corner(board: &Board, square: Square) -> (usize, usize) {
+    (
+        MARGIN + square.file * SQUARE_SIZE,
+        8 + (board.size - 1 - square.rank) * SQUARE_SIZE,
+    )
+}
+
+fn render_stack(svg: &mut String, board: &Board, square: Square) {
+    let stack = board.stack(square);
+    let Some((color, role)) = stack.top() else {
+        return;
+    };
+    let (x, y) = corner(board, square);
+    let (cx, cy) = (x + SQUARE_SIZE / 2, y + SQUARE_SIZE / 2 - 4);
+    let (fill, stroke) = colors(color);
+    let _ = match role {
+        Role::Flat => write!(
+            svg,
+            r#"<rect x="{}" y="{}" width="32" height="32" rx="4" fill="{fill}" stroke="{stroke}" stroke-width="2"/>"#,
+            cx - 16,
+            cy - 16
+        ),
+        Role::Wall => write!(
+            svg,
+            r#"<rect x="{}" y="{}" width="12" height="36" rx="2" fill="{fill}" stroke="{stroke}" stroke-width="2" transform="rotate(45 {cx} {cy})"/>"#,
+            cx - 6,
+            cy - 18
+        ),
+        Role::Cap => write!(
+            svg,
+            r#"<circle cx="{cx}" cy="{cy}" r="16" fill="{fill}" stroke="{stroke}" stroke-width="2"/>"#
+        ),
+    };
+
+    // The pieces under the top one, from the bottom, as a row of small squares
+    let under = &stack.colors[..stack.colors.len() - 1];
+    let shown = &under[under.len().saturating_sub(MAX_STACK_PIECES)..];
+    for (i, &color) in shown.iter().enumerate() {
+        let (fill, stroke) = colors(color);
+        let _ = write!(
+            svg,
+            r#"<rect x="{}" y="{}" width="6" height="6" fill="{fill}" stroke="{stroke}"/>"#,
+            x + 4 + i * 6,
+            y + SQUARE_SIZE - 10
+        );
+    }
+    if stack.colors.len() > 1 {
+        let _ = write!(
+            svg,
+            r##"<text x="{}" y="{}" font-size="11" text-anchor="end" fill="#333">{}</text>"##,
+            x + SQUARE_SIZE - 4,
+            y + 13,
+            stack.colors.len()
+        );
+    }
+}
+
+fn colors(color: Color) -> (&'static str, &'static str) {
+    match color {
+        Color::White => ("#f4f1ea", "#6b6256"),
+        Color::Black => ("#34302b", "#131110"),
+    }
+}
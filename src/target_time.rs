@@ -0,0 +1,19 @@
+use crate::config::TargetTimeConfig;
+
+// The target time for a user, scaled from the puzzle's own `target_time_seconds`.
+// Users rated below the puzzle get more time, and users rated above it get less.
+// Both the served puzzle and the verification of an attempt use this, so `targetTimeMet` matches the clock the user saw
+pub fn for_user(
+    config: &TargetTimeConfig,
+    puzzle_target_time_seconds: u32,
+    puzzle_rating: f64,
+    user_rating: f64,
+) -> u32 {
+    if !config.enabled {
+        return puzzle_target_time_seconds;
+    }
+    let factor = 2f64
+        .powf((puzzle_rating - user_rating) / config.doubling_rating_difference)
+        .clamp(config.min_factor, config.max_factor);
+    ((puzzle_target_time_seconds as f64 * factor).round() as u32).max(1)
+}
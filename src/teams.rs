@@ -0,0 +1,339 @@
+use axum::{
+    Json,
+    extract::{Path, Query},
+    http::StatusCode,
+};
+use rusqlite::{Connection, OptionalExtension};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    PuzzleRequest, db, now_seconds,
+    validation::{self, ApiError},
+};
+use utoipa::{IntoParams, ToSchema};
+
+pub fn init_db_tables(db_conn: &Connection) -> anyhow::Result<()> {
+    db_conn.execute(
+        "CREATE TABLE IF NOT EXISTS teams (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            name TEXT NOT NULL UNIQUE,
+            created_seconds INTEGER NOT NULL DEFAULT (strftime('%s', 'now'))
+        )",
+        [],
+    )?;
+
+    // Users can only be on one team at a time
+    db_conn.execute(
+        "CREATE TABLE IF NOT EXISTS team_members (
+            team_id INTEGER NOT NULL,
+            username TEXT NOT NULL PRIMARY KEY,
+            joined_seconds INTEGER NOT NULL DEFAULT (strftime('%s', 'now')),
+            FOREIGN KEY (team_id) REFERENCES teams(id)
+        )",
+        [],
+    )?;
+
+    Ok(())
+}
+
+#[derive(Serialize, Deserialize, ToSchema)]
+pub struct CreateTeamRequest {
+    username: String,
+    name: String,
+}
+
+#[derive(Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct Team {
+    id: u64,
+    name: String,
+    members: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct TeamLeaderboardEntry {
+    rank: u32,
+    id: u64,
+    name: String,
+    num_members: u32,
+    // Average over members that have a rating
+    average_rating: Option<f64>,
+    total_solved: u32,
+}
+
+#[derive(Serialize, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct WeekRequest {
+    week: Option<i64>,
+}
+
+#[derive(Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct WeeklyCompetition {
+    week: i64,
+    start_seconds: i64,
+    end_seconds: i64,
+    standings: Vec<WeeklyTeamResult>,
+}
+
+#[derive(Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct WeeklyTeamResult {
+    rank: u32,
+    id: u64,
+    name: String,
+    puzzles_solved: u32,
+    puzzles_attempted: u32,
+}
+
+// Weeks are numbered from the unix epoch, and start on Mondays (UTC)
+fn current_week() -> i64 {
+    (now_seconds() as i64 / 86400 + 3) / 7
+}
+
+fn week_start_seconds(week: i64) -> i64 {
+    (week * 7 - 3) * 86400
+}
+
+// Create a team. The creator becomes its first member
+#[utoipa::path(
+    post,
+    path = "/teams",
+    tag = "teams",
+    request_body = CreateTeamRequest,
+    responses(
+        (status = 200, body = Team),
+        (status = 400, body = validation::ValidationError),
+        (status = 409, description = "The team name is taken"),
+    ),
+)]
+pub async fn create_team(Json(payload): Json<CreateTeamRequest>) -> Result<Json<Team>, ApiError> {
+    validation::validate_username(&payload.username)?;
+    validation::validate_name(&payload.name)?;
+    let mut db_conn = db::open().map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let transaction = db_conn
+        .transaction()
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let number_of_rows = transaction
+        .execute(
+            "INSERT OR IGNORE INTO teams (name) VALUES (?1)",
+            [&payload.name],
+        )
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    if number_of_rows == 0 {
+        return Err(StatusCode::CONFLICT.into());
+    }
+    let id = transaction.last_insert_rowid() as u64;
+    let username = validation::canonical_username(&payload.username);
+    join(&transaction, id, &username).map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    transaction
+        .commit()
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+    Ok(read_team(&db_conn, id)
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+        .map(Json)
+        .ok_or(StatusCode::INTERNAL_SERVER_ERROR)?)
+}
+
+#[utoipa::path(
+    get,
+    path = "/teams/{id}",
+    tag = "teams",
+    params(("id" = u64, Path)),
+    responses((status = 200, body = Team), (status = 404)),
+)]
+pub async fn get_team(Path(id): Path<u64>) -> Result<Json<Team>, StatusCode> {
+    let db_conn = db::open().map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    read_team(&db_conn, id)
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+        .map(Json)
+        .ok_or(StatusCode::NOT_FOUND)
+}
+
+// Join a team, leaving the user's current team if they have one
+#[utoipa::path(
+    post,
+    path = "/teams/{id}/join",
+    tag = "teams",
+    params(("id" = u64, Path)),
+    request_body = PuzzleRequest,
+    responses(
+        (status = 200, body = Team),
+        (status = 400, body = validation::ValidationError),
+        (status = 404),
+    ),
+)]
+pub async fn join_team(
+    Path(id): Path<u64>,
+    Json(payload): Json<PuzzleRequest>,
+) -> Result<Json<Team>, ApiError> {
+    validation::validate_username(&payload.username)?;
+    let db_conn = db::open().map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    read_team(&db_conn, id)
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+        .ok_or(StatusCode::NOT_FOUND)?;
+    join(
+        &db_conn,
+        id,
+        &validation::canonical_username(&payload.username),
+    )
+    .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    Ok(read_team(&db_conn, id)
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+        .map(Json)
+        .ok_or(StatusCode::NOT_FOUND)?)
+}
+
+#[utoipa::path(
+    post,
+    path = "/teams/{id}/leave",
+    tag = "teams",
+    params(("id" = u64, Path)),
+    request_body = PuzzleRequest,
+    responses((status = 200)),
+)]
+pub async fn leave_team(
+    Path(id): Path<u64>,
+    Json(payload): Json<PuzzleRequest>,
+) -> Result<(), StatusCode> {
+    let db_conn = db::open().map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    db_conn
+        .execute(
+            "DELETE FROM team_members WHERE team_id = ?1 AND username = ?2",
+            rusqlite::params![id, validation::canonical_username(&payload.username)],
+        )
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    Ok(())
+}
+
+// Get all teams, ranked by total number of puzzles solved by their members
+#[utoipa::path(
+    get,
+    path = "/teams",
+    tag = "teams",
+    responses((status = 200, body = Vec<TeamLeaderboardEntry>)),
+)]
+pub async fn get_team_leaderboard() -> Result<Json<Vec<TeamLeaderboardEntry>>, StatusCode> {
+    let db_conn = db::open().map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let leaderboard =
+        read_team_leaderboard(&db_conn).map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    Ok(Json(leaderboard))
+}
+
+// Team-vs-team results for a week, defaulting to the current week.
+// Only members' first attempts made during the week count
+#[utoipa::path(
+    get,
+    path = "/teams/weekly",
+    tag = "teams",
+    params(WeekRequest),
+    responses((status = 200, body = WeeklyCompetition)),
+)]
+pub async fn get_weekly_competition(
+    week: Query<WeekRequest>,
+) -> Result<Json<WeeklyCompetition>, StatusCode> {
+    let week = week.week.unwrap_or_else(current_week);
+    let db_conn = db::open().map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let start_seconds = week_start_seconds(week);
+    let end_seconds = week_start_seconds(week + 1);
+    let standings = read_weekly_results(&db_conn, start_seconds, end_seconds)
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    Ok(Json(WeeklyCompetition {
+        week,
+        start_seconds,
+        end_seconds,
+        standings,
+    }))
+}
+
+fn join(db_conn: &Connection, team_id: u64, username: &str) -> anyhow::Result<()> {
+    db_conn.execute(
+        "INSERT OR REPLACE INTO team_members (team_id, username) VALUES (?1, ?2)",
+        rusqlite::params![team_id, username],
+    )?;
+    Ok(())
+}
+
+fn read_team(db_conn: &Connection, id: u64) -> anyhow::Result<Option<Team>> {
+    let Some(name) = db_conn
+        .query_row("SELECT name FROM teams WHERE id = ?1", [id], |row| {
+            row.get(0)
+        })
+        .optional()?
+    else {
+        return Ok(None);
+    };
+    let mut stmt = db_conn
+        .prepare("SELECT username FROM team_members WHERE team_id = ?1 ORDER BY joined_seconds")?;
+    let members = stmt
+        .query_map([id], |row| row.get(0))?
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(Some(Team { id, name, members }))
+}
+
+fn read_team_leaderboard(db_conn: &Connection) -> anyhow::Result<Vec<TeamLeaderboardEntry>> {
+    let mut stmt = db_conn.prepare(
+        "SELECT teams.id, teams.name, COUNT(team_members.username), AVG(users.rating),
+            COALESCE(SUM(
+                (SELECT COUNT(DISTINCT puzzle_id) FROM puzzle_attempts
+                    WHERE puzzle_attempts.user_id = users.id AND solved = 1)
+            ), 0) AS total_solved
+        FROM teams
+        LEFT JOIN team_members ON team_members.team_id = teams.id
+        LEFT JOIN users ON users.username = team_members.username
+        GROUP BY teams.id
+        ORDER BY total_solved DESC, teams.name",
+    )?;
+    let rows = stmt.query_map([], |row| {
+        Ok(TeamLeaderboardEntry {
+            rank: 0,
+            id: row.get(0)?,
+            name: row.get(1)?,
+            num_members: row.get(2)?,
+            average_rating: row.get(3)?,
+            total_solved: row.get(4)?,
+        })
+    })?;
+    let mut entries = rows.collect::<Result<Vec<_>, _>>()?;
+    for (i, entry) in entries.iter_mut().enumerate() {
+        entry.rank = i as u32 + 1;
+    }
+    Ok(entries)
+}
+
+fn read_weekly_results(
+    db_conn: &Connection,
+    start_seconds: i64,
+    end_seconds: i64,
+) -> anyhow::Result<Vec<WeeklyTeamResult>> {
+    let mut stmt = db_conn.prepare(
+        "WITH weekly_attempts AS (
+            SELECT * FROM rated_attempts
+            WHERE timestamp_seconds >= ?1 AND timestamp_seconds < ?2
+        )
+        SELECT teams.id, teams.name,
+            COALESCE(SUM(weekly_attempts.solved), 0) AS puzzles_solved,
+            COUNT(weekly_attempts.puzzle_id)
+        FROM teams
+        LEFT JOIN team_members ON team_members.team_id = teams.id
+        LEFT JOIN weekly_attempts ON weekly_attempts.username = team_members.username
+        GROUP BY teams.id
+        ORDER BY puzzles_solved DESC, teams.name",
+    )?;
+    let rows = stmt.query_map(rusqlite::params![start_seconds, end_seconds], |row| {
+        Ok(WeeklyTeamResult {
+            rank: 0,
+            id: row.get(0)?,
+            name: row.get(1)?,
+            puzzles_solved: row.get(2)?,
+            puzzles_attempted: row.get(3)?,
+        })
+    })?;
+    let mut results = rows.collect::<Result<Vec<_>, _>>()?;
+    for (i, result) in results.iter_mut().enumerate() {
+        result.rank = i as u32 + 1;
+    }
+    Ok(results)
+}
@@ -0,0 +1,216 @@
+use std::time::{Duration, Instant};
+
+use axum::{
+    body::Body,
+    extract::{MatchedPath, Request, State},
+    http::{HeaderValue, header},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use metrics_exporter_prometheus::{PrometheusBuilder, PrometheusHandle};
+use rand::Rng;
+use tracing::Span;
+
+use crate::AppState;
+
+pub const REQUEST_ID_HEADER: &str = "x-request-id";
+
+const MAX_REQUEST_ID_LENGTH: usize = 128;
+
+// Error responses are rewritten to include the request ID. Ours are a few hundred bytes at most
+const MAX_ERROR_BODY_BYTES: usize = 64 * 1024;
+
+// Histogram buckets, in seconds
+const DURATION_BUCKETS: &[f64] = &[
+    0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0,
+];
+
+// Histograms are only compacted during upkeep, so it has to run regularly to bound their memory use
+const UPKEEP_INTERVAL: Duration = Duration::from_secs(5);
+
+// Install the global metrics recorder. Metrics recorded before this are lost
+pub fn install_recorder() -> anyhow::Result<PrometheusHandle> {
+    Ok(PrometheusBuilder::new()
+        .set_buckets(DURATION_BUCKETS)?
+        .install_recorder()?)
+}
+
+pub fn spawn_upkeep(handle: PrometheusHandle) {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(UPKEEP_INTERVAL);
+        loop {
+            interval.tick().await;
+            handle.run_upkeep();
+        }
+    });
+}
+
+// All metrics in the Prometheus text format
+pub async fn get_metrics(State(state): State<AppState>) -> String {
+    state.metrics.render()
+}
+
+// Count requests and their latencies, labelled with the route rather than the full path,
+// so that every puzzle id doesn't get its own time series
+pub async fn track_requests(request: Request, next: Next) -> Response {
+    let path = request
+        .extensions()
+        .get::<MatchedPath>()
+        .map(|path| path.as_str().to_string())
+        .unwrap_or_else(|| "unmatched".to_string());
+    let method = request.method().to_string();
+    let start = Instant::now();
+    let response = next.run(request).await;
+    let labels = [
+        ("method", method),
+        ("path", path),
+        ("status", response.status().as_u16().to_string()),
+    ];
+    metrics::counter!("http_requests_total", &labels).increment(1);
+    metrics::histogram!("http_request_duration_seconds", &labels)
+        .record(start.elapsed().as_secs_f64());
+    response
+}
+
+// Time a database query, and run it in its own span so that its SQL statements are logged as part of it.
+// `query` names what the query does, like "select_puzzle"
+pub fn time_db_query<T>(query: &'static str, f: impl FnOnce() -> T) -> T {
+    let span = tracing::info_span!("db_query", query, duration_ms = tracing::field::Empty);
+    let start = Instant::now();
+    let result = span.in_scope(f);
+    let duration = start.elapsed();
+    span.record("duration_ms", duration.as_secs_f64() * 1000.0);
+    span.in_scope(|| tracing::debug!(target: "sql", "Finished database query"));
+    metrics::histogram!("db_query_duration_seconds", "query" => query)
+        .record(duration.as_secs_f64());
+    result
+}
+
+// Time computing a rating. `kind` is either "puzzle" or "user"
+pub fn time_rating_computation<T>(kind: &'static str, f: impl FnOnce() -> T) -> T {
+    let start = Instant::now();
+    let result = f();
+    metrics::histogram!("rating_computation_duration_seconds", "kind" => kind)
+        .record(start.elapsed().as_secs_f64());
+    result
+}
+
+// Time a step of `maintenance.rs`, like "analyze"
+pub fn time_maintenance_step<T>(step: &'static str, f: impl FnOnce() -> T) -> T {
+    let start = Instant::now();
+    let result = f();
+    metrics::histogram!("db_maintenance_duration_seconds", "step" => step)
+        .record(start.elapsed().as_secs_f64());
+    result
+}
+
+// Count a recorded attempt.
+// The solve rate is the share of these with `solved="true"`
+pub fn record_attempt_submitted(rated: bool, solved: bool) {
+    metrics::counter!(
+        "attempts_submitted_total",
+        "rated" => rated.to_string(),
+        "solved" => solved.to_string(),
+    )
+    .increment(1);
+}
+
+// Log everything as JSON, one object per line. Log lines include every span they are in,
+// so lines from within a request include its request ID.
+// The level is set with `RUST_LOG`, and is `info` by default
+pub fn init_logging() {
+    tracing_subscriber::fmt()
+        .json()
+        .with_current_span(true)
+        .with_span_list(true)
+        .with_env_filter(
+            tracing_subscriber::EnvFilter::try_from_default_env()
+                .unwrap_or_else(|_| tracing_subscriber::EnvFilter::new("info")),
+        )
+        .init();
+}
+
+// Readable logs on stderr for the commands other than `serve`, which may print their output to stdout
+pub fn init_cli_logging() {
+    tracing_subscriber::fmt()
+        .with_writer(std::io::stderr)
+        .with_env_filter(
+            tracing_subscriber::EnvFilter::try_from_default_env()
+                .unwrap_or_else(|_| tracing_subscriber::EnvFilter::new("info")),
+        )
+        .init();
+}
+
+// The span for a request, which every log line of the request is a part of
+pub fn request_span(request: &Request) -> Span {
+    let request_id = request
+        .headers()
+        .get(REQUEST_ID_HEADER)
+        .and_then(|id| id.to_str().ok())
+        .unwrap_or_default();
+    tracing::info_span!(
+        "request",
+        method = %request.method(),
+        uri = %request.uri(),
+        request_id,
+    )
+}
+
+// Give every request an ID, so that a client's error report can be matched with our logs.
+// Clients and proxies may send their own in `X-Request-Id`, otherwise one is generated.
+// The ID is sent back in the same header, and is added to the body of every error response
+pub async fn request_id(mut request: Request, next: Next) -> Response {
+    let request_id = request
+        .headers()
+        .get(REQUEST_ID_HEADER)
+        .filter(|id| !id.is_empty() && id.len() <= MAX_REQUEST_ID_LENGTH && id.to_str().is_ok())
+        .cloned()
+        .unwrap_or_else(new_request_id);
+    request
+        .headers_mut()
+        .insert(REQUEST_ID_HEADER, request_id.clone());
+
+    let mut response = next.run(request).await;
+    if response.status().is_client_error() || response.status().is_server_error() {
+        response = add_request_id_to_error(response, &request_id).await;
+    }
+    response.headers_mut().insert(REQUEST_ID_HEADER, request_id);
+    response
+}
+
+fn new_request_id() -> HeaderValue {
+    let id: u128 = rand::rng().random();
+    HeaderValue::from_str(&format!("{id:032x}")).unwrap()
+}
+
+// Empty error bodies become `{"error": <reason>, "requestId": <id>}`, and JSON objects get a `requestId` field.
+// Any other body is left alone
+async fn add_request_id_to_error(response: Response, request_id: &HeaderValue) -> Response {
+    let is_json = response
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .is_some_and(|content_type| content_type.as_bytes().starts_with(b"application/json"));
+    let has_body = response.headers().contains_key(header::CONTENT_TYPE);
+    if has_body && !is_json {
+        return response;
+    }
+    let (mut parts, body) = response.into_parts();
+    let Ok(bytes) = axum::body::to_bytes(body, MAX_ERROR_BODY_BYTES).await else {
+        return parts.status.into_response();
+    };
+    let mut json = if bytes.is_empty() {
+        serde_json::json!({ "error": parts.status.canonical_reason().unwrap_or_default() })
+    } else {
+        match serde_json::from_slice::<serde_json::Value>(&bytes) {
+            Ok(json) if json.is_object() => json,
+            _ => return Response::from_parts(parts, Body::from(bytes)),
+        }
+    };
+    json["requestId"] = request_id.to_str().unwrap_or_default().into();
+    parts.headers.remove(header::CONTENT_LENGTH);
+    parts.headers.insert(
+        header::CONTENT_TYPE,
+        HeaderValue::from_static("application/json"),
+    );
+    Response::from_parts(parts, Body::from(json.to_string()))
+}
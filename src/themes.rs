@@ -0,0 +1,295 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use axum::{
+    Json,
+    extract::{Path, Query, State},
+    http::StatusCode,
+};
+use rusqlite::Connection;
+use serde::{Deserialize, Serialize};
+use utoipa::{IntoParams, ToSchema};
+
+use crate::{
+    AppState,
+    admin::AdminAuth,
+    audit, db,
+    validation::{ApiError, ValidationError},
+};
+
+// Tactical themes of puzzles, like `cap-smash` or `road-threat`, set by admins.
+// Used to suggest similar puzzles after solving one
+
+const MAX_THEMES: usize = 10;
+const MAX_THEME_LENGTH: usize = 32;
+const DEFAULT_NUM_SIMILAR: u32 = 5;
+const MAX_NUM_SIMILAR: u32 = 20;
+
+// How much each shared theme and a matching board size count for, in rating points of difference
+const SHARED_THEME_WEIGHT: f64 = 200.0;
+const SAME_SIZE_WEIGHT: f64 = 100.0;
+
+pub fn init_db_tables(db_conn: &Connection) -> anyhow::Result<()> {
+    db_conn.execute(
+        "CREATE TABLE IF NOT EXISTS puzzle_themes (
+            puzzle_id INTEGER NOT NULL,
+            theme TEXT NOT NULL,
+            PRIMARY KEY (puzzle_id, theme),
+            FOREIGN KEY (puzzle_id) REFERENCES puzzles(id)
+        )",
+        [],
+    )?;
+
+    Ok(())
+}
+
+#[derive(Serialize, Deserialize, ToSchema)]
+pub struct Themes {
+    // Lowercase letters, digits and dashes
+    themes: BTreeSet<String>,
+}
+
+fn validate_themes(themes: &BTreeSet<String>) -> Result<(), ValidationError> {
+    if themes.len() > MAX_THEMES {
+        return Err(ValidationError::new(
+            "themes",
+            format!("A puzzle can have at most {MAX_THEMES} themes"),
+        ));
+    }
+    for theme in themes {
+        if theme.is_empty()
+            || theme.len() > MAX_THEME_LENGTH
+            || !theme
+                .bytes()
+                .all(|b| b.is_ascii_lowercase() || b.is_ascii_digit() || b == b'-')
+        {
+            return Err(ValidationError::new(
+                "themes",
+                format!(
+                    "Invalid theme '{theme}'. Themes are 1 to {MAX_THEME_LENGTH} lowercase letters, digits and dashes"
+                ),
+            ));
+        }
+    }
+    Ok(())
+}
+
+pub fn read_themes(db_conn: &Connection, puzzle_id: u32) -> anyhow::Result<BTreeSet<String>> {
+    let mut stmt = db_conn.prepare("SELECT theme FROM puzzle_themes WHERE puzzle_id = ?1")?;
+    let themes = stmt
+        .query_map([puzzle_id], |row| row.get(0))?
+        .collect::<Result<_, _>>()?;
+    Ok(themes)
+}
+
+#[utoipa::path(
+    get,
+    path = "/puzzles/{id}/themes",
+    tag = "puzzles",
+    params(("id" = u32, Path)),
+    responses((status = 200, body = Themes), (status = 404)),
+)]
+pub async fn get_themes(
+    Path(id): Path<u32>,
+    State(state): State<AppState>,
+) -> Result<Json<Themes>, StatusCode> {
+    state
+        .store
+        .published_puzzle(id)
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+        .ok_or(StatusCode::NOT_FOUND)?;
+    let db_conn = db::open().map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let themes = read_themes(&db_conn, id).map_err(|e| {
+        tracing::error!("Error reading themes: {:?}", e);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+    Ok(Json(Themes { themes }))
+}
+
+// Replace a puzzle's themes. Works for unpublished puzzles too, so they can be tagged before publishing
+#[utoipa::path(
+    put,
+    path = "/admin/puzzles/{id}/themes",
+    tag = "admin",
+    params(("id" = u32, Path)),
+    request_body = Themes,
+    security(("admin_token" = [])),
+    responses(
+        (status = 200, body = Themes),
+        (status = 400, body = ValidationError),
+        (status = 401),
+        (status = 403),
+        (status = 404),
+    ),
+)]
+pub async fn set_themes(
+    admin: AdminAuth,
+    Path(id): Path<u32>,
+    State(state): State<AppState>,
+    Json(payload): Json<Themes>,
+) -> Result<Json<Themes>, ApiError> {
+    validate_themes(&payload.themes)?;
+    state
+        .store
+        .puzzle(id)
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+        .ok_or(StatusCode::NOT_FOUND)?;
+    let mut db_conn = db::open().map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let transaction = db_conn
+        .transaction()
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    write_themes(&transaction, id, &payload.themes).map_err(|e| {
+        tracing::error!("Error writing themes: {:?}", e);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+    audit::record_for(
+        &transaction,
+        &admin,
+        "set_puzzle_themes",
+        serde_json::json!({"puzzleId": id, "themes": payload.themes}),
+    )?;
+    transaction
+        .commit()
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    Ok(Json(payload))
+}
+
+fn write_themes(
+    db_conn: &Connection,
+    puzzle_id: u32,
+    themes: &BTreeSet<String>,
+) -> anyhow::Result<()> {
+    db_conn.execute(
+        "DELETE FROM puzzle_themes WHERE puzzle_id = ?1",
+        [puzzle_id],
+    )?;
+    for theme in themes {
+        db_conn.execute(
+            "INSERT INTO puzzle_themes (puzzle_id, theme) VALUES (?1, ?2)",
+            rusqlite::params![puzzle_id, theme],
+        )?;
+    }
+    Ok(())
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct SimilarQuery {
+    limit: Option<u32>,
+}
+
+#[derive(Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct SimilarPuzzle {
+    id: u32,
+    size: usize,
+    rating: f64,
+    // The themes it has in common with the puzzle it's similar to
+    shared_themes: BTreeSet<String>,
+}
+
+// Other published puzzles like this one, most similar first. Puzzles are ranked by how many themes they share with it,
+// whether they're on the same board size, and how close their ratings are
+#[utoipa::path(
+    get,
+    path = "/puzzles/{id}/similar",
+    tag = "puzzles",
+    params(("id" = u32, Path), SimilarQuery),
+    responses(
+        (status = 200, body = Vec<SimilarPuzzle>),
+        (status = 400, body = ValidationError),
+        (status = 404),
+    ),
+)]
+pub async fn get_similar_puzzles(
+    Path(id): Path<u32>,
+    State(state): State<AppState>,
+    Query(query): Query<SimilarQuery>,
+) -> Result<Json<Vec<SimilarPuzzle>>, ApiError> {
+    let limit = match query.limit {
+        None => DEFAULT_NUM_SIMILAR,
+        Some(limit) if (1..=MAX_NUM_SIMILAR).contains(&limit) => limit,
+        Some(_) => {
+            return Err(ValidationError::new(
+                "limit",
+                format!("Limit must be between 1 and {MAX_NUM_SIMILAR}"),
+            )
+            .into());
+        }
+    };
+    let puzzle = state
+        .store
+        .published_puzzle(id)
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+        .ok_or(StatusCode::NOT_FOUND)?;
+    let ratings = state.store.published_puzzle_ratings().await.map_err(|e| {
+        tracing::error!("Error reading puzzle ratings from database: {:?}", e);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+    let db_conn = db::open().map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let candidates = read_candidates(&db_conn).map_err(|e| {
+        tracing::error!("Error reading puzzles for similarity: {:?}", e);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+
+    let themes = candidates
+        .get(&id)
+        .map(|(_, themes)| themes.clone())
+        .unwrap_or_default();
+    let rating = ratings.get(&id).copied().unwrap_or_default();
+    let mut similar: Vec<(f64, SimilarPuzzle)> = candidates
+        .into_iter()
+        .filter(|(candidate_id, _)| *candidate_id != id)
+        .map(|(candidate_id, (size, candidate_themes))| {
+            let shared_themes: BTreeSet<String> =
+                themes.intersection(&candidate_themes).cloned().collect();
+            let candidate_rating = ratings.get(&candidate_id).copied().unwrap_or_default();
+            let score = shared_themes.len() as f64 * SHARED_THEME_WEIGHT
+                + if size == puzzle.size {
+                    SAME_SIZE_WEIGHT
+                } else {
+                    0.0
+                }
+                - (candidate_rating - rating).abs();
+            (
+                score,
+                SimilarPuzzle {
+                    id: candidate_id,
+                    size,
+                    rating: candidate_rating,
+                    shared_themes,
+                },
+            )
+        })
+        .collect();
+    // Ties go to the lowest id, so that the order is stable
+    similar.sort_by(|(a_score, a), (b_score, b)| b_score.total_cmp(a_score).then(a.id.cmp(&b.id)));
+    similar.truncate(limit as usize);
+    Ok(Json(
+        similar.into_iter().map(|(_, puzzle)| puzzle).collect(),
+    ))
+}
+
+// Every published puzzle's size and themes, by id
+fn read_candidates(
+    db_conn: &Connection,
+) -> anyhow::Result<BTreeMap<u32, (usize, BTreeSet<String>)>> {
+    let mut stmt = db_conn.prepare(
+        "SELECT puzzles.id, puzzles.size, puzzle_themes.theme FROM puzzles
+        LEFT JOIN puzzle_themes ON puzzle_themes.puzzle_id = puzzles.id
+        WHERE puzzles.published = 1",
+    )?;
+    let mut candidates: BTreeMap<u32, (usize, BTreeSet<String>)> = BTreeMap::new();
+    let mut rows = stmt.query([])?;
+    while let Some(row) = rows.next()? {
+        let (_, themes) = candidates
+            .entry(row.get(0)?)
+            .or_insert((row.get(1)?, BTreeSet::new()));
+        if let Some(theme) = row.get::<_, Option<String>>(2)? {
+            themes.insert(theme);
+        }
+    }
+    Ok(candidates)
+}
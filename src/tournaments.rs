@@ -0,0 +1,617 @@
+use axum::{
+    Json,
+    extract::{Path, Query},
+    http::StatusCode,
+};
+use rand::{Rng, distr::Alphanumeric};
+use rusqlite::{Connection, OptionalExtension};
+use serde::{Deserialize, Serialize};
+use serde_rusqlite::from_row;
+
+use crate::{
+    Puzzle, PuzzleRequest, PuzzleRow,
+    admin::AdminAuth,
+    attempts, audit, bans, db, now_seconds, storage,
+    validation::{self, ApiError},
+};
+use utoipa::ToSchema;
+
+pub fn init_db_tables(db_conn: &Connection) -> anyhow::Result<()> {
+    db_conn.execute(
+        "CREATE TABLE IF NOT EXISTS tournaments (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            name TEXT NOT NULL,
+            start_seconds INTEGER NOT NULL,
+            end_seconds INTEGER NOT NULL,
+            frozen INTEGER NOT NULL DEFAULT 0
+        )",
+        [],
+    )?;
+
+    db_conn.execute(
+        "CREATE TABLE IF NOT EXISTS tournament_puzzles (
+            tournament_id INTEGER NOT NULL,
+            puzzle_id INTEGER NOT NULL,
+            position INTEGER NOT NULL,
+            PRIMARY KEY (tournament_id, puzzle_id),
+            FOREIGN KEY (tournament_id) REFERENCES tournaments(id),
+            FOREIGN KEY (puzzle_id) REFERENCES puzzles(id)
+        )",
+        [],
+    )?;
+
+    db_conn.execute(
+        "CREATE TABLE IF NOT EXISTS tournament_participants (
+            tournament_id INTEGER NOT NULL,
+            username TEXT NOT NULL,
+            registered_seconds INTEGER NOT NULL DEFAULT (strftime('%s', 'now')),
+            PRIMARY KEY (tournament_id, username),
+            FOREIGN KEY (tournament_id) REFERENCES tournaments(id)
+        )",
+        [],
+    )?;
+
+    // A token is issued when a participant is served a puzzle, and can only be redeemed once.
+    // Solve times are measured from when the token was issued
+    db_conn.execute(
+        "CREATE TABLE IF NOT EXISTS tournament_tokens (
+            token TEXT NOT NULL PRIMARY KEY,
+            tournament_id INTEGER NOT NULL,
+            username TEXT NOT NULL,
+            puzzle_id INTEGER NOT NULL,
+            issued_seconds INTEGER NOT NULL,
+            used INTEGER NOT NULL DEFAULT 0,
+            FOREIGN KEY (tournament_id) REFERENCES tournaments(id)
+        )",
+        [],
+    )?;
+
+    db_conn.execute(
+        "CREATE TABLE IF NOT EXISTS tournament_results (
+            tournament_id INTEGER NOT NULL,
+            username TEXT NOT NULL,
+            puzzle_id INTEGER NOT NULL,
+            solved INTEGER NOT NULL,
+            solve_time_seconds INTEGER NOT NULL,
+            solution TEXT NOT NULL,
+            PRIMARY KEY (tournament_id, username, puzzle_id),
+            FOREIGN KEY (tournament_id) REFERENCES tournaments(id)
+        )",
+        [],
+    )?;
+
+    // Final standings, written once the tournament is over
+    db_conn.execute(
+        "CREATE TABLE IF NOT EXISTS tournament_standings (
+            tournament_id INTEGER NOT NULL,
+            rank INTEGER NOT NULL,
+            username TEXT NOT NULL,
+            puzzles_solved INTEGER NOT NULL,
+            total_time_seconds INTEGER NOT NULL,
+            PRIMARY KEY (tournament_id, username),
+            FOREIGN KEY (tournament_id) REFERENCES tournaments(id)
+        )",
+        [],
+    )?;
+
+    Ok(())
+}
+
+#[derive(Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct CreateTournamentRequest {
+    name: String,
+    start_seconds: u64,
+    end_seconds: u64,
+    puzzle_ids: Vec<u64>,
+}
+
+#[derive(Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct Tournament {
+    id: u64,
+    name: String,
+    start_seconds: u64,
+    end_seconds: u64,
+    num_puzzles: u32,
+    num_participants: u32,
+    frozen: bool,
+}
+
+#[derive(Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct TournamentPuzzle {
+    token: String,
+    // Without its solution, which comes with the result, see `TournamentAttemptResult`
+    puzzle: Puzzle,
+}
+
+#[derive(Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct TournamentAttempt {
+    username: String,
+    token: String,
+    // Must agree with whether the solution is the puzzle's
+    solved: bool,
+    solution: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct TournamentAttemptResult {
+    solved: bool,
+    // The puzzle's solution, now that the participant's result for it is in
+    solution: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct TournamentStanding {
+    rank: u32,
+    username: String,
+    puzzles_solved: u32,
+    total_time_seconds: u32,
+}
+
+#[derive(Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct TournamentStandings {
+    tournament_id: u64,
+    frozen: bool,
+    standings: Vec<TournamentStanding>,
+}
+
+// Schedule a new tournament
+#[utoipa::path(
+    post,
+    path = "/admin/tournaments",
+    tag = "tournaments",
+    request_body = CreateTournamentRequest,
+    security(("admin_token" = [])),
+    responses(
+        (status = 200, body = Tournament),
+        (status = 400),
+        (status = 401),
+        (status = 403),
+    ),
+)]
+pub async fn create_tournament(
+    admin: AdminAuth,
+    Json(payload): Json<CreateTournamentRequest>,
+) -> Result<Json<Tournament>, StatusCode> {
+    if payload.name.is_empty()
+        || payload.end_seconds <= payload.start_seconds
+        || payload.puzzle_ids.is_empty()
+    {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+    let mut db_conn = db::open().map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let id = insert_tournament(&mut db_conn, &payload).map_err(|e| {
+        tracing::error!("Error creating tournament: {:?}", e);
+        StatusCode::BAD_REQUEST
+    })?;
+    audit::record_for(
+        &db_conn,
+        &admin,
+        "create_tournament",
+        serde_json::json!({"id": id, "request": payload}),
+    )?;
+    read_tournament(&db_conn, id)
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+        .map(Json)
+        .ok_or(StatusCode::INTERNAL_SERVER_ERROR)
+}
+
+// List all tournaments, most recent first
+#[utoipa::path(
+    get,
+    path = "/tournaments",
+    tag = "tournaments",
+    responses((status = 200, body = Vec<Tournament>)),
+)]
+pub async fn get_tournaments() -> Result<Json<Vec<Tournament>>, StatusCode> {
+    let db_conn = db::open().map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let tournaments = read_tournaments(&db_conn).map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    Ok(Json(tournaments))
+}
+
+#[utoipa::path(
+    get,
+    path = "/tournaments/{id}",
+    tag = "tournaments",
+    params(("id" = u64, Path)),
+    responses((status = 200, body = Tournament), (status = 404)),
+)]
+pub async fn get_tournament(Path(id): Path<u64>) -> Result<Json<Tournament>, StatusCode> {
+    let db_conn = db::open().map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    read_tournament(&db_conn, id)
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+        .map(Json)
+        .ok_or(StatusCode::NOT_FOUND)
+}
+
+// Register for a tournament. Registration is open until the tournament ends
+#[utoipa::path(
+    post,
+    path = "/tournaments/{id}/register",
+    tag = "tournaments",
+    params(("id" = u64, Path)),
+    request_body = PuzzleRequest,
+    responses(
+        (status = 200),
+        (status = 400, body = validation::ValidationError),
+        (status = 403, description = "The tournament has ended"),
+        (status = 404),
+    ),
+)]
+pub async fn register_for_tournament(
+    Path(id): Path<u64>,
+    Json(payload): Json<PuzzleRequest>,
+) -> Result<(), ApiError> {
+    validation::validate_username(&payload.username)?;
+    let db_conn = db::open().map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let tournament = read_tournament(&db_conn, id)
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+        .ok_or(StatusCode::NOT_FOUND)?;
+    if now_seconds() >= tournament.end_seconds {
+        return Err(StatusCode::FORBIDDEN.into());
+    }
+    db_conn
+        .execute(
+            "INSERT OR IGNORE INTO tournament_participants (tournament_id, username) VALUES (?1, ?2)",
+            rusqlite::params![id, validation::canonical_username(&payload.username)],
+        )
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    Ok(())
+}
+
+// Get the participant's next puzzle, along with a single-use token for submitting the result
+#[utoipa::path(
+    get,
+    path = "/tournaments/{id}/next",
+    tag = "tournaments",
+    params(("id" = u64, Path), PuzzleRequest),
+    responses(
+        (status = 200, body = TournamentPuzzle),
+        (status = 403),
+        (status = 404),
+    ),
+)]
+pub async fn get_next_tournament_puzzle(
+    Path(id): Path<u64>,
+    Query(request): Query<PuzzleRequest>,
+) -> Result<Json<TournamentPuzzle>, StatusCode> {
+    let username = validation::canonical_username(&request.username);
+    let db_conn = db::open().map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    check_can_play(&db_conn, id, &username)?;
+
+    let puzzle = read_next_tournament_puzzle(&db_conn, id, &username)
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+        .ok_or(StatusCode::NOT_FOUND)?;
+
+    // Re-fetching a puzzle issues a new token, but does not reset the clock
+    let token = generate_token();
+    db_conn
+        .execute(
+            "INSERT INTO tournament_tokens (token, tournament_id, username, puzzle_id, issued_seconds)
+            VALUES (?1, ?2, ?3, ?4, COALESCE(
+                (SELECT MIN(issued_seconds) FROM tournament_tokens
+                    WHERE tournament_id = ?2 AND username = ?3 AND puzzle_id = ?4),
+                ?5))",
+            rusqlite::params![token, id, username, puzzle.id, now_seconds()],
+        )
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+    Ok(Json(TournamentPuzzle {
+        token,
+        puzzle: Puzzle {
+            solution: vec![],
+            ..puzzle.into()
+        },
+    }))
+}
+
+// Submit the result for a tournament puzzle. Each token is only accepted once,
+// and each puzzle only gets one result per participant
+#[utoipa::path(
+    post,
+    path = "/tournaments/{id}/attempts",
+    tag = "tournaments",
+    params(("id" = u64, Path)),
+    request_body = TournamentAttempt,
+    responses(
+        (status = 200, body = TournamentAttemptResult),
+        (status = 400, body = validation::ValidationError),
+        (status = 403),
+        (status = 409, description = "The token was already used"),
+    ),
+)]
+pub async fn submit_tournament_attempt(
+    Path(id): Path<u64>,
+    Json(payload): Json<TournamentAttempt>,
+) -> Result<Json<TournamentAttemptResult>, ApiError> {
+    validation::validate_solution(&payload.solution)?;
+    let username = validation::canonical_username(&payload.username);
+    let mut db_conn = db::open().map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    check_can_play(&db_conn, id, &username)?;
+
+    let transaction = db_conn
+        .transaction()
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let (puzzle_id, issued_seconds): (u64, u64) = transaction
+        .query_row(
+            "SELECT puzzle_id, issued_seconds FROM tournament_tokens
+            WHERE token = ?1 AND tournament_id = ?2 AND username = ?3 AND used = 0",
+            rusqlite::params![payload.token, id, username],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        )
+        .optional()
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+        .ok_or(StatusCode::CONFLICT)?;
+    let puzzle = storage::read_puzzle_by_id(&transaction, puzzle_id as u32)
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+        .ok_or(StatusCode::NOT_FOUND)?;
+    validation::validate_solution_for_size(&payload.solution, puzzle.size)?;
+    let solve_time_seconds = now_seconds().saturating_sub(issued_seconds);
+    let verification = attempts::verify(&puzzle, &payload.solution, solve_time_seconds as u32);
+    let solved = verification.correct;
+    if payload.solved != solved {
+        let message = if solved {
+            "Solution is the puzzle's solution, but wasn't marked as solved"
+        } else {
+            "Solution doesn't match the puzzle's solution"
+        };
+        return Err(validation::ValidationError::new("solution", message).into());
+    }
+    transaction
+        .execute(
+            "UPDATE tournament_tokens SET used = 1 WHERE token = ?1",
+            [&payload.token],
+        )
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let number_of_rows = transaction
+        .execute(
+            "INSERT OR IGNORE INTO tournament_results (tournament_id, username, puzzle_id, solved, solve_time_seconds, solution)
+            VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+            rusqlite::params![
+                id,
+                username,
+                puzzle_id,
+                solved,
+                solve_time_seconds,
+                payload.solution.join(" ")
+            ],
+        )
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    if number_of_rows == 0 {
+        return Err(StatusCode::CONFLICT.into());
+    }
+    transaction
+        .commit()
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    Ok(Json(TournamentAttemptResult {
+        solved,
+        solution: verification.solution,
+    }))
+}
+
+// Get the standings. These are live while the tournament is running, and frozen once it has ended
+#[utoipa::path(
+    get,
+    path = "/tournaments/{id}/standings",
+    tag = "tournaments",
+    params(("id" = u64, Path)),
+    responses((status = 200, body = TournamentStandings), (status = 404)),
+)]
+pub async fn get_tournament_standings(
+    Path(id): Path<u64>,
+) -> Result<Json<TournamentStandings>, StatusCode> {
+    let mut db_conn = db::open().map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let tournament = read_tournament(&db_conn, id)
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+        .ok_or(StatusCode::NOT_FOUND)?;
+
+    if !tournament.frozen && now_seconds() >= tournament.end_seconds {
+        freeze_standings(&mut db_conn, id).map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    }
+    let frozen = tournament.frozen || now_seconds() >= tournament.end_seconds;
+    let standings = if frozen {
+        read_frozen_standings(&db_conn, id)
+    } else {
+        compute_standings(&db_conn, id)
+    }
+    .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+    Ok(Json(TournamentStandings {
+        tournament_id: id,
+        frozen,
+        standings,
+    }))
+}
+
+fn generate_token() -> String {
+    rand::rng()
+        .sample_iter(&Alphanumeric)
+        .take(32)
+        .map(char::from)
+        .collect()
+}
+
+fn check_can_play(db_conn: &Connection, id: u64, username: &str) -> Result<(), StatusCode> {
+    bans::check_not_banned(db_conn, username)?;
+    let tournament = read_tournament(db_conn, id)
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+        .ok_or(StatusCode::NOT_FOUND)?;
+    let now = now_seconds();
+    if now < tournament.start_seconds || now >= tournament.end_seconds {
+        return Err(StatusCode::FORBIDDEN);
+    }
+    let registered = db_conn
+        .prepare("SELECT 1 FROM tournament_participants WHERE tournament_id = ?1 AND username = ?2")
+        .and_then(|mut stmt| stmt.exists(rusqlite::params![id, username]))
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    if !registered {
+        return Err(StatusCode::FORBIDDEN);
+    }
+    Ok(())
+}
+
+fn insert_tournament(
+    db_conn: &mut Connection,
+    tournament: &CreateTournamentRequest,
+) -> anyhow::Result<u64> {
+    let transaction = db_conn.transaction()?;
+    transaction.execute(
+        "INSERT INTO tournaments (name, start_seconds, end_seconds) VALUES (?1, ?2, ?3)",
+        rusqlite::params![
+            tournament.name,
+            tournament.start_seconds,
+            tournament.end_seconds
+        ],
+    )?;
+    let id = transaction.last_insert_rowid() as u64;
+    for (position, puzzle_id) in tournament.puzzle_ids.iter().enumerate() {
+        transaction.execute(
+            "INSERT INTO tournament_puzzles (tournament_id, puzzle_id, position)
+            SELECT ?1, id, ?3 FROM puzzles WHERE id = ?2",
+            rusqlite::params![id, puzzle_id, position],
+        )?;
+        anyhow::ensure!(
+            transaction.changes() == 1,
+            "Puzzle {} does not exist",
+            puzzle_id
+        );
+    }
+    transaction.commit()?;
+    Ok(id)
+}
+
+const TOURNAMENT_COLUMNS: &str = "tournaments.id, tournaments.name, tournaments.start_seconds,
+    tournaments.end_seconds,
+    (SELECT COUNT(*) FROM tournament_puzzles WHERE tournament_id = tournaments.id),
+    (SELECT COUNT(*) FROM tournament_participants WHERE tournament_id = tournaments.id),
+    tournaments.frozen";
+
+fn tournament_from_row(row: &rusqlite::Row) -> rusqlite::Result<Tournament> {
+    Ok(Tournament {
+        id: row.get(0)?,
+        name: row.get(1)?,
+        start_seconds: row.get(2)?,
+        end_seconds: row.get(3)?,
+        num_puzzles: row.get(4)?,
+        num_participants: row.get(5)?,
+        frozen: row.get(6)?,
+    })
+}
+
+fn read_tournament(db_conn: &Connection, id: u64) -> anyhow::Result<Option<Tournament>> {
+    Ok(db_conn
+        .query_row(
+            &format!("SELECT {TOURNAMENT_COLUMNS} FROM tournaments WHERE id = ?1"),
+            [id],
+            tournament_from_row,
+        )
+        .optional()?)
+}
+
+fn read_tournaments(db_conn: &Connection) -> anyhow::Result<Vec<Tournament>> {
+    let mut stmt = db_conn.prepare(&format!(
+        "SELECT {TOURNAMENT_COLUMNS} FROM tournaments ORDER BY start_seconds DESC"
+    ))?;
+    let rows = stmt.query_map([], tournament_from_row)?;
+    Ok(rows.collect::<Result<Vec<_>, _>>()?)
+}
+
+fn read_next_tournament_puzzle(
+    db_conn: &Connection,
+    id: u64,
+    username: &str,
+) -> anyhow::Result<Option<PuzzleRow>> {
+    let mut stmt = db_conn.prepare(
+        "SELECT puzzles.* FROM tournament_puzzles
+        JOIN puzzles ON puzzles.id = tournament_puzzles.puzzle_id
+        WHERE tournament_puzzles.tournament_id = ?1 AND NOT EXISTS (
+            SELECT 1 FROM tournament_results
+            WHERE tournament_results.tournament_id = ?1
+                AND tournament_results.puzzle_id = puzzles.id
+                AND tournament_results.username = ?2
+        )
+        ORDER BY tournament_puzzles.position LIMIT 1",
+    )?;
+    Ok(stmt
+        .query_and_then(rusqlite::params![id, username], from_row::<PuzzleRow>)?
+        .next()
+        .transpose()?)
+}
+
+// Most puzzles solved wins, with total time spent on solved puzzles as tiebreak
+fn compute_standings(db_conn: &Connection, id: u64) -> anyhow::Result<Vec<TournamentStanding>> {
+    let mut stmt = db_conn.prepare(
+        "SELECT tournament_participants.username,
+            COALESCE(SUM(tournament_results.solved), 0) AS puzzles_solved,
+            COALESCE(SUM(CASE WHEN tournament_results.solved THEN tournament_results.solve_time_seconds ELSE 0 END), 0) AS total_time_seconds
+        FROM tournament_participants
+        LEFT JOIN tournament_results ON tournament_results.tournament_id = tournament_participants.tournament_id
+            AND tournament_results.username = tournament_participants.username
+        WHERE tournament_participants.tournament_id = ?1
+        GROUP BY tournament_participants.username
+        ORDER BY puzzles_solved DESC, total_time_seconds ASC, tournament_participants.username",
+    )?;
+    let rows = stmt.query_map([id], |row| {
+        Ok(TournamentStanding {
+            rank: 0,
+            username: row.get(0)?,
+            puzzles_solved: row.get(1)?,
+            total_time_seconds: row.get(2)?,
+        })
+    })?;
+    let mut standings = rows.collect::<Result<Vec<_>, _>>()?;
+    for (i, standing) in standings.iter_mut().enumerate() {
+        standing.rank = i as u32 + 1;
+    }
+    Ok(standings)
+}
+
+fn freeze_standings(db_conn: &mut Connection, id: u64) -> anyhow::Result<()> {
+    let standings = compute_standings(db_conn, id)?;
+    let transaction = db_conn.transaction()?;
+    // Another request may have frozen the standings in the meantime
+    if transaction.execute(
+        "UPDATE tournaments SET frozen = 1 WHERE id = ?1 AND frozen = 0",
+        [id],
+    )? == 0
+    {
+        return Ok(());
+    }
+    for standing in standings {
+        transaction.execute(
+            "INSERT INTO tournament_standings (tournament_id, rank, username, puzzles_solved, total_time_seconds)
+            VALUES (?1, ?2, ?3, ?4, ?5)",
+            rusqlite::params![
+                id,
+                standing.rank,
+                standing.username,
+                standing.puzzles_solved,
+                standing.total_time_seconds
+            ],
+        )?;
+    }
+    transaction.commit()?;
+    Ok(())
+}
+
+fn read_frozen_standings(db_conn: &Connection, id: u64) -> anyhow::Result<Vec<TournamentStanding>> {
+    let mut stmt = db_conn.prepare(
+        "SELECT rank, username, puzzles_solved, total_time_seconds FROM tournament_standings
+        WHERE tournament_id = ?1 ORDER BY rank",
+    )?;
+    let rows = stmt.query_map([id], |row| {
+        Ok(TournamentStanding {
+            rank: row.get(0)?,
+            username: row.get(1)?,
+            puzzles_solved: row.get(2)?,
+            total_time_seconds: row.get(3)?,
+        })
+    })?;
+    Ok(rows.collect::<Result<Vec<_>, _>>()?)
+}
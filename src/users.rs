@@ -0,0 +1,685 @@
+use std::collections::{BTreeMap, HashMap};
+
+use axum::{
+    Json,
+    extract::{Path, State},
+    http::{StatusCode, header},
+    response::IntoResponse,
+};
+use rusqlite::{Connection, OptionalExtension, Transaction, types::ValueRef};
+use serde::{Deserialize, Serialize};
+use skillratings::glicko2::Glicko2Rating;
+use utoipa::ToSchema;
+
+use crate::{
+    AppState,
+    admin::AdminAuth,
+    audit, db,
+    rating_history::{self, RatingHistoryEntry},
+    sessions::AuthUser,
+    storage,
+    validation::{self, ApiError, ValidationError},
+};
+
+// Users have a numeric id, which attempts refer to, so that renaming a user keeps their attempts.
+// Everything else about a user is stored by their username, in the columns below,
+// which have to be updated along with `users` when a user is renamed.
+// Add new tables that store usernames here
+pub const USERNAME_COLUMNS: &[(&str, &str)] = &[
+    ("attempt_moves", "username"),
+    ("attempt_variants", "username"),
+    ("attempts_in_progress", "username"),
+    ("banned_users", "username"),
+    ("campaign_unlocks", "username"),
+    ("collections", "owner"),
+    ("follows", "follower"),
+    ("follows", "followee"),
+    ("idempotency_keys", "username"),
+    ("leaderboard_snapshot_entries", "username"),
+    ("notifications", "username"),
+    ("puzzle_reports", "username"),
+    ("race_players", "username"),
+    ("races", "winner"),
+    ("rating_clamps", "username"),
+    ("retryable_attempts", "username"),
+    ("season_ratings", "username"),
+    ("sessions", "username"),
+    ("team_members", "username"),
+    ("tournament_participants", "username"),
+    ("tournament_results", "username"),
+    ("tournament_standings", "username"),
+    ("tournament_tokens", "username"),
+    ("user_achievements", "username"),
+    ("user_emails", "username"),
+    ("user_rating_history", "username"),
+    ("user_settings", "username"),
+];
+
+// When a user is deleted, their rows in these tables are deleted. Their rows in the other tables above
+// are kept under an anonymous username, so that rankings, puzzle statistics and reports stay the same
+const DELETED_WITH_USER: &[&str] = &[
+    "attempts_in_progress",
+    "banned_users",
+    "campaign_unlocks",
+    "follows",
+    "idempotency_keys",
+    "notifications",
+    "retryable_attempts",
+    "sessions",
+    "team_members",
+    "tournament_tokens",
+    "user_achievements",
+    "user_emails",
+    "user_settings",
+];
+
+// The user's id, adding them with the default rating if this is their first attempt
+pub fn get_or_create_id(db_conn: &Connection, username: &str) -> anyhow::Result<i64> {
+    let default_rating = Glicko2Rating::default();
+    db_conn
+        .prepare_cached(
+            "INSERT INTO users (username, display_name, rating, deviation, volatility) VALUES (?1, ?1, ?2, ?3, ?4)
+            ON CONFLICT (username) DO NOTHING",
+        )?
+        .execute(rusqlite::params![
+            username,
+            default_rating.rating,
+            default_rating.deviation,
+            default_rating.volatility
+        ])?;
+    Ok(db_conn
+        .prepare_cached("SELECT id FROM users WHERE username = ?1")?
+        .query_row([username], |row| row.get(0))?)
+}
+
+#[derive(Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct Rename {
+    // The new username, in the form it should be displayed
+    username: String,
+}
+
+#[derive(Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct User {
+    id: i64,
+    username: String,
+    display_name: String,
+}
+
+// Rename a user, keeping their attempts, ratings and everything else.
+// Renaming to a username that only differs in case just changes how it's displayed
+#[utoipa::path(
+    post,
+    path = "/admin/users/{username}/rename",
+    tag = "admin",
+    params(("username" = String, Path)),
+    request_body = Rename,
+    security(("admin_token" = [])),
+    responses(
+        (status = 200, body = User),
+        (status = 400, body = validation::ValidationError),
+        (status = 401),
+        (status = 403),
+        (status = 404),
+        (status = 409, description = "Another user already has the new username"),
+    ),
+)]
+pub async fn rename_user(
+    admin: AdminAuth,
+    Path(username): Path<String>,
+    Json(payload): Json<Rename>,
+) -> Result<Json<User>, ApiError> {
+    let username = validation::canonical_username(&username);
+    validation::validate_username(&payload.username)?;
+    let mut db_conn = db::open().map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let transaction = db_conn
+        .transaction()
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let user = match write_rename(&transaction, &username, &payload.username) {
+        Ok(RenameOutcome::Renamed(user)) => user,
+        Ok(RenameOutcome::NoSuchUser) => return Err(StatusCode::NOT_FOUND.into()),
+        Ok(RenameOutcome::UsernameTaken) => return Err(StatusCode::CONFLICT.into()),
+        Err(e) => {
+            tracing::error!("Error renaming user: {:?}", e);
+            return Err(StatusCode::INTERNAL_SERVER_ERROR.into());
+        }
+    };
+    audit::record_for(
+        &transaction,
+        &admin,
+        "rename_user",
+        serde_json::json!({"from": username, "to": user.username}),
+    )?;
+    transaction
+        .commit()
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    Ok(Json(user))
+}
+
+enum RenameOutcome {
+    Renamed(User),
+    NoSuchUser,
+    UsernameTaken,
+}
+
+fn write_rename(
+    transaction: &Transaction,
+    username: &str,
+    display_name: &str,
+) -> anyhow::Result<RenameOutcome> {
+    let exists = transaction
+        .prepare("SELECT 1 FROM users WHERE username = ?1")?
+        .exists([username])?;
+    if !exists {
+        return Ok(RenameOutcome::NoSuchUser);
+    }
+    let new_username = validation::canonical_username(display_name);
+    let Some(user) = transaction
+        .query_row(
+            "UPDATE OR IGNORE users SET username = ?2, display_name = ?3 WHERE username = ?1
+            RETURNING id, username, display_name",
+            [username, &new_username, display_name],
+            |row| {
+                Ok(User {
+                    id: row.get(0)?,
+                    username: row.get(1)?,
+                    display_name: row.get(2)?,
+                })
+            },
+        )
+        .optional()?
+    else {
+        return Ok(RenameOutcome::UsernameTaken);
+    };
+    if new_username != username {
+        // Rows for the new username that don't belong to any user, like follows of a name
+        // that was never used, are replaced by the renamed user's own
+        for (table, column) in USERNAME_COLUMNS {
+            transaction.execute(
+                &format!("UPDATE OR REPLACE {table} SET {column} = ?2 WHERE {column} = ?1"),
+                [username, &new_username],
+            )?;
+        }
+        transaction.execute("DELETE FROM follows WHERE follower = followee", [])?;
+    }
+    Ok(RenameOutcome::Renamed(user))
+}
+
+#[derive(Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct MergeRequest {
+    // The user whose attempts are moved, and who is removed afterwards
+    from: String,
+    // The user who is kept
+    into: String,
+}
+
+#[derive(Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct Merge {
+    user: User,
+    num_attempts_moved: u32,
+    // The puzzles either user had attempted, whose ratings were recomputed
+    affected_puzzle_ids: Vec<u32>,
+    // How many users' ratings changed when every rating was recomputed
+    num_ratings_changed: u32,
+}
+
+// Merge one user into another, for people who ended up with attempts under two usernames.
+// The users' attempts at each puzzle are numbered again by time, so only the earliest attempt is rated,
+// and every rating is recomputed like `recompute-ratings`. Where both users had a row for the same thing,
+// like a team membership or an achievement, the kept user's row is kept.
+// Other puzzles' ratings may be served from the cache for up to 10 minutes afterwards
+#[utoipa::path(
+    post,
+    path = "/admin/users/merge",
+    tag = "admin",
+    request_body = MergeRequest,
+    security(("admin_token" = [])),
+    responses(
+        (status = 200, body = Merge),
+        (status = 400, body = validation::ValidationError),
+        (status = 401),
+        (status = 403),
+        (status = 404, description = "One of the users doesn't exist"),
+    ),
+)]
+pub async fn merge_users(
+    admin: AdminAuth,
+    State(state): State<AppState>,
+    Json(payload): Json<MergeRequest>,
+) -> Result<Json<Merge>, ApiError> {
+    let from = validation::canonical_username(&payload.from);
+    let into = validation::canonical_username(&payload.into);
+    validation::validate_username(&from).map_err(|e| ValidationError::new("from", e.message))?;
+    validation::validate_username(&into).map_err(|e| ValidationError::new("into", e.message))?;
+    if from == into {
+        return Err(ValidationError::new("into", "Can't merge a user into themselves").into());
+    }
+    let mut db_conn = db::open().map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let transaction = db_conn
+        .transaction()
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let merge = write_merge(&transaction, &from, &into)
+        .map_err(|e| {
+            tracing::error!("Error merging users: {:?}", e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?
+        .ok_or(StatusCode::NOT_FOUND)?;
+    audit::record_for(
+        &transaction,
+        &admin,
+        "merge_users",
+        serde_json::json!({"from": from, "into": into}),
+    )?;
+    transaction
+        .commit()
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    state
+        .store
+        .invalidate_puzzle_ratings(&merge.affected_puzzle_ids);
+    Ok(Json(merge))
+}
+
+// Returns `None` if either user doesn't exist
+fn write_merge(transaction: &Transaction, from: &str, into: &str) -> anyhow::Result<Option<Merge>> {
+    let read_id = |username: &str| {
+        transaction
+            .query_row(
+                "SELECT id FROM users WHERE username = ?1",
+                [username],
+                |row| row.get::<_, i64>(0),
+            )
+            .optional()
+    };
+    let (Some(from_id), Some(into_id)) = (read_id(from)?, read_id(into)?) else {
+        return Ok(None);
+    };
+
+    // Both users' attempts, in the order they are numbered again
+    let attempts = transaction
+        .prepare(
+            "SELECT id, puzzle_id, user_id, attempt_number FROM puzzle_attempts
+            WHERE user_id IN (?1, ?2)
+            ORDER BY puzzle_id, timestamp_seconds, id",
+        )?
+        .query_map([from_id, into_id], |row| {
+            Ok((
+                row.get::<_, i64>(0)?,
+                row.get::<_, u32>(1)?,
+                row.get::<_, i64>(2)?,
+                row.get::<_, u32>(3)?,
+            ))
+        })?
+        .collect::<Result<Vec<_>, _>>()?;
+
+    // Attempt numbers are negated first, so that they don't collide with the ones that haven't been moved yet
+    let mut next_numbers: HashMap<u32, u32> = HashMap::new();
+    let mut num_attempts_moved = 0;
+    for &(id, puzzle_id, user_id, old_number) in &attempts {
+        let next_number = next_numbers.entry(puzzle_id).or_insert(1);
+        let new_number = *next_number;
+        *next_number += 1;
+        let old_username = if user_id == from_id {
+            num_attempts_moved += 1;
+            from
+        } else {
+            into
+        };
+        transaction.execute(
+            "UPDATE puzzle_attempts SET user_id = ?2, attempt_number = ?3 WHERE id = ?1",
+            rusqlite::params![id, into_id, -(new_number as i64)],
+        )?;
+        // Idempotency keys are only unique per user, so both users may have used the same one.
+        // The removed user's are left behind, and deleted with the rest of their rows below
+        for table in ["attempt_moves", "attempt_variants", "idempotency_keys"] {
+            transaction.execute(
+                &format!(
+                    "UPDATE OR IGNORE {table} SET username = ?4, attempt_number = ?5
+                    WHERE username = ?1 AND puzzle_id = ?2 AND attempt_number = ?3"
+                ),
+                rusqlite::params![
+                    old_username,
+                    puzzle_id,
+                    old_number,
+                    into,
+                    -(new_number as i64)
+                ],
+            )?;
+        }
+    }
+    transaction.execute(
+        "UPDATE puzzle_attempts SET attempt_number = -attempt_number WHERE user_id = ?1 AND attempt_number < 0",
+        [into_id],
+    )?;
+    for table in ["attempt_moves", "attempt_variants", "idempotency_keys"] {
+        transaction.execute(
+            &format!(
+                "UPDATE {table} SET attempt_number = -attempt_number WHERE username = ?1 AND attempt_number < 0"
+            ),
+            [into],
+        )?;
+    }
+
+    for (table, column) in USERNAME_COLUMNS {
+        transaction.execute(
+            &format!("UPDATE OR IGNORE {table} SET {column} = ?2 WHERE {column} = ?1"),
+            [from, into],
+        )?;
+        transaction.execute(&format!("DELETE FROM {table} WHERE {column} = ?1"), [from])?;
+    }
+    transaction.execute("DELETE FROM follows WHERE follower = followee", [])?;
+    transaction.execute(
+        "UPDATE users SET excluded_from_ratings = MAX(excluded_from_ratings, (
+            SELECT excluded_from_ratings FROM users WHERE id = ?1
+        ))
+        WHERE id = ?2",
+        [from_id, into_id],
+    )?;
+    transaction.execute("DELETE FROM users WHERE id = ?1", [from_id])?;
+
+    let num_ratings_changed = storage::replay_rated_attempts(transaction)? as u32;
+    let user = transaction.query_row(
+        "SELECT id, username, COALESCE(display_name, username) FROM users WHERE id = ?1",
+        [into_id],
+        |row| {
+            Ok(User {
+                id: row.get(0)?,
+                username: row.get(1)?,
+                display_name: row.get(2)?,
+            })
+        },
+    )?;
+    let mut affected_puzzle_ids: Vec<u32> = next_numbers.into_keys().collect();
+    affected_puzzle_ids.sort();
+    Ok(Some(Merge {
+        user,
+        num_attempts_moved,
+        affected_puzzle_ids,
+        num_ratings_changed,
+    }))
+}
+
+// Delete a user's personal data, for requests to be forgotten. Their attempts and ratings are kept
+// under an anonymous username that can't be signed up with, so that puzzle ratings and statistics
+// don't change, and they're left out of the leaderboard. Using the username again starts a new user.
+// Users can delete themselves once logged in, and admins can delete anyone
+#[utoipa::path(
+    delete,
+    path = "/users/{username}",
+    tag = "admin",
+    params(("username" = String, Path)),
+    security(("admin_token" = []), ("session_token" = [])),
+    responses(
+        (status = 204),
+        (status = 401),
+        (status = 403),
+        (status = 404),
+    ),
+)]
+pub async fn delete_user(
+    user: Option<AuthUser>,
+    admin: Result<AdminAuth, StatusCode>,
+    Path(username): Path<String>,
+) -> Result<StatusCode, StatusCode> {
+    let username = validation::canonical_username(&username);
+    let admin = check_self_or_admin(user, admin, &username)?;
+    let mut db_conn = db::open().map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let transaction = db_conn
+        .transaction()
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let deleted = write_delete(&transaction, &username).map_err(|e| {
+        tracing::error!("Error deleting user: {:?}", e);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+    if !deleted {
+        return Err(StatusCode::NOT_FOUND);
+    }
+    if let Some(admin) = &admin {
+        audit::record_for(
+            &transaction,
+            admin,
+            "delete_user",
+            serde_json::json!({"username": username}),
+        )?;
+    }
+    transaction
+        .commit()
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+// Let the user themselves through, or an admin, who is returned for the audit log.
+// Otherwise fails like `AdminAuth` does
+fn check_self_or_admin(
+    user: Option<AuthUser>,
+    admin: Result<AdminAuth, StatusCode>,
+    username: &str,
+) -> Result<Option<AdminAuth>, StatusCode> {
+    match (user, admin) {
+        (_, Ok(admin)) => Ok(Some(admin)),
+        (Some(user), _) if user.username == username => Ok(None),
+        (_, Err(status)) => Err(status),
+    }
+}
+
+// Returns whether the user existed
+pub fn write_delete(transaction: &Transaction, username: &str) -> anyhow::Result<bool> {
+    let Some(id) = transaction
+        .query_row(
+            "SELECT id FROM users WHERE username = ?1 AND deleted_seconds IS NULL",
+            [username],
+            |row| row.get::<_, i64>(0),
+        )
+        .optional()?
+    else {
+        return Ok(false);
+    };
+    // Spaces aren't allowed in usernames, see `validation::validate_username`
+    let anonymous = format!("deleted user {id}");
+    transaction.execute(
+        "UPDATE users SET username = ?2, display_name = NULL, deleted_seconds = strftime('%s', 'now')
+        WHERE id = ?1",
+        rusqlite::params![id, anonymous],
+    )?;
+    for (table, column) in USERNAME_COLUMNS {
+        if DELETED_WITH_USER.contains(table) {
+            transaction.execute(
+                &format!("DELETE FROM {table} WHERE {column} = ?1"),
+                [username],
+            )?;
+        } else {
+            transaction.execute(
+                &format!("UPDATE {table} SET {column} = ?2 WHERE {column} = ?1"),
+                [username, &anonymous],
+            )?;
+        }
+    }
+    Ok(true)
+}
+
+#[derive(Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct UserExport {
+    profile: Profile,
+    rating_history: Vec<RatingHistoryEntry>,
+    // Oldest first
+    attempts: Vec<ExportedAttempt>,
+    // Every other row stored for the user, by table, with the tables' own column names.
+    // Includes when each move of an attempt was played, in `attempt_moves`
+    #[schema(value_type = Object)]
+    other_data: BTreeMap<String, Vec<serde_json::Value>>,
+}
+
+#[derive(Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct Profile {
+    id: i64,
+    username: String,
+    display_name: String,
+    rating: f64,
+    deviation: f64,
+    volatility: f64,
+    excluded_from_ratings: bool,
+}
+
+#[derive(Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct ExportedAttempt {
+    id: i64,
+    puzzle_id: u32,
+    attempt_number: u32,
+    solved: bool,
+    practice: bool,
+    solve_time_seconds: u32,
+    timestamp_seconds: u64,
+    solution: Vec<String>,
+}
+
+// Get everything stored about a user, as a JSON file to download, for requests for their personal data.
+// Users can export themselves once logged in, and admins can export anyone
+#[utoipa::path(
+    get,
+    path = "/users/{username}/export",
+    tag = "admin",
+    params(("username" = String, Path)),
+    security(("admin_token" = []), ("session_token" = [])),
+    responses(
+        (status = 200, body = UserExport),
+        (status = 401),
+        (status = 403),
+        (status = 404),
+    ),
+)]
+pub async fn export_user(
+    user: Option<AuthUser>,
+    admin: Result<AdminAuth, StatusCode>,
+    Path(username): Path<String>,
+) -> Result<impl IntoResponse, StatusCode> {
+    let username = validation::canonical_username(&username);
+    let admin = check_self_or_admin(user, admin, &username)?;
+    let mut db_conn = db::open().map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    // Read everything in one transaction, so that the export is consistent
+    let transaction = db_conn
+        .transaction()
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let export = read_export(&transaction, &username)
+        .map_err(|e| {
+            tracing::error!("Error exporting user: {:?}", e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?
+        .ok_or(StatusCode::NOT_FOUND)?;
+    if let Some(admin) = &admin {
+        audit::record_for(
+            &transaction,
+            admin,
+            "export_user",
+            serde_json::json!({"username": username}),
+        )?;
+    }
+    transaction
+        .commit()
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let content_disposition = format!("attachment; filename=\"{username}.json\"");
+    Ok((
+        [(header::CONTENT_DISPOSITION, content_disposition)],
+        Json(export),
+    ))
+}
+
+fn read_export(transaction: &Transaction, username: &str) -> anyhow::Result<Option<UserExport>> {
+    let Some(profile) = transaction
+        .query_row(
+            "SELECT id, username, COALESCE(display_name, username), rating, deviation, volatility,
+                excluded_from_ratings
+            FROM users WHERE username = ?1 AND deleted_seconds IS NULL",
+            [username],
+            |row| {
+                Ok(Profile {
+                    id: row.get(0)?,
+                    username: row.get(1)?,
+                    display_name: row.get(2)?,
+                    rating: row.get(3)?,
+                    deviation: row.get(4)?,
+                    volatility: row.get(5)?,
+                    excluded_from_ratings: row.get(6)?,
+                })
+            },
+        )
+        .optional()?
+    else {
+        return Ok(None);
+    };
+
+    let attempts = transaction
+        .prepare(
+            "SELECT id, puzzle_id, attempt_number, solved, practice, solve_time_seconds, timestamp_seconds, solution
+            FROM puzzle_attempts WHERE user_id = ?1
+            ORDER BY timestamp_seconds, id",
+        )?
+        .query_map([profile.id], |row| {
+            Ok(ExportedAttempt {
+                id: row.get(0)?,
+                puzzle_id: row.get(1)?,
+                attempt_number: row.get(2)?,
+                solved: row.get(3)?,
+                practice: row.get(4)?,
+                solve_time_seconds: row.get(5)?,
+                timestamp_seconds: row.get(6)?,
+                solution: row
+                    .get::<_, String>(7)?
+                    .split_whitespace()
+                    .map(String::from)
+                    .collect(),
+            })
+        })?
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let mut other_data: BTreeMap<String, Vec<serde_json::Value>> = BTreeMap::new();
+    for (table, column) in USERNAME_COLUMNS {
+        if *table == "user_rating_history" {
+            continue;
+        }
+        let rows = read_rows(transaction, table, column, username)?;
+        if !rows.is_empty() {
+            other_data
+                .entry(table.to_string())
+                .or_default()
+                .extend(rows);
+        }
+    }
+
+    Ok(Some(UserExport {
+        rating_history: rating_history::read_user_rating_history(transaction, username)?,
+        profile,
+        attempts,
+        other_data,
+    }))
+}
+
+// Every row in the table with the username in the column, as JSON objects keyed by column name
+fn read_rows(
+    db_conn: &Connection,
+    table: &str,
+    column: &str,
+    username: &str,
+) -> anyhow::Result<Vec<serde_json::Value>> {
+    let mut stmt = db_conn.prepare(&format!("SELECT * FROM {table} WHERE {column} = ?1"))?;
+    let names: Vec<String> = stmt.column_names().into_iter().map(String::from).collect();
+    let rows = stmt.query_map([username], |row| {
+        let mut object = serde_json::Map::new();
+        for (i, name) in names.iter().enumerate() {
+            let value = match row.get_ref(i)? {
+                ValueRef::Null => serde_json::Value::Null,
+                ValueRef::Integer(n) => n.into(),
+                ValueRef::Real(x) => x.into(),
+                ValueRef::Text(text) => String::from_utf8_lossy(text).into(),
+                ValueRef::Blob(bytes) => bytes.to_vec().into(),
+            };
+            object.insert(name.clone(), value);
+        }
+        Ok(serde_json::Value::Object(object))
+    })?;
+    Ok(rows.collect::<Result<Vec<_>, _>>()?)
+}
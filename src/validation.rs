@@ -0,0 +1,263 @@
+use axum::{
+    Json,
+    body::Body,
+    extract::{Query, Request},
+    http::{HeaderMap, StatusCode, header},
+    response::{IntoResponse, Response},
+};
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use crate::board;
+
+// Largest request body accepted by any endpoint
+pub const MAX_BODY_BYTES: usize = 16 * 1024;
+
+pub const MAX_USERNAME_LENGTH: usize = 32;
+
+// Longer than any puzzle in the database, with plenty of room for wrong continuations
+pub const MAX_SOLUTION_MOVES: usize = 64;
+
+pub const MAX_SOLVE_TIME_SECONDS: u32 = 24 * 60 * 60;
+
+// A request that was well-formed, but contained an invalid value.
+// Sent to the client as a 400 with a JSON body, so the frontend can show which field was wrong
+#[derive(Debug, Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct ValidationError {
+    pub field: &'static str,
+    pub message: String,
+}
+
+impl ValidationError {
+    pub fn new(field: &'static str, message: impl Into<String>) -> Self {
+        Self {
+            field,
+            message: message.into(),
+        }
+    }
+}
+
+impl IntoResponse for ValidationError {
+    fn into_response(self) -> Response {
+        (StatusCode::BAD_REQUEST, Json(self)).into_response()
+    }
+}
+
+// Error type for handlers that validate their input.
+// Plain status codes convert into it, so `?` keeps working on the usual `map_err`s
+#[derive(Debug)]
+pub enum ApiError {
+    Status(StatusCode),
+    Invalid(ValidationError),
+}
+
+impl From<StatusCode> for ApiError {
+    fn from(status: StatusCode) -> Self {
+        ApiError::Status(status)
+    }
+}
+
+impl From<ValidationError> for ApiError {
+    fn from(error: ValidationError) -> Self {
+        ApiError::Invalid(error)
+    }
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        match self {
+            ApiError::Status(status) => status.into_response(),
+            ApiError::Invalid(error) => error.into_response(),
+        }
+    }
+}
+
+// Usernames are usually playtak usernames, so only ASCII letters, digits, underscores and dashes are allowed
+pub fn validate_username(username: &str) -> Result<(), ValidationError> {
+    if username.is_empty() {
+        return Err(ValidationError::new("username", "Username is empty"));
+    }
+    if username.len() > MAX_USERNAME_LENGTH {
+        return Err(ValidationError::new(
+            "username",
+            format!("Username is longer than {MAX_USERNAME_LENGTH} characters"),
+        ));
+    }
+    if !username
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+    {
+        return Err(ValidationError::new(
+            "username",
+            "Username may only contain letters, digits, '_' and '-'",
+        ));
+    }
+    Ok(())
+}
+
+// Usernames are case-insensitive, so "Alice" and "alice" are the same user.
+// Everything is stored and looked up by this lowercase form, and the form the user typed
+// is kept as their display name, see `storage::set_display_name`
+pub fn canonical_username(username: &str) -> String {
+    username.to_ascii_lowercase()
+}
+
+pub const MAX_NAME_LENGTH: usize = 64;
+
+// Display names of things users create, like teams and collections
+pub fn validate_name(name: &str) -> Result<(), ValidationError> {
+    if name.trim().is_empty() {
+        return Err(ValidationError::new("name", "Name is empty"));
+    }
+    if name.chars().count() > MAX_NAME_LENGTH {
+        return Err(ValidationError::new(
+            "name",
+            format!("Name is longer than {MAX_NAME_LENGTH} characters"),
+        ));
+    }
+    if name.chars().any(char::is_control) {
+        return Err(ValidationError::new(
+            "name",
+            "Name may not contain control characters",
+        ));
+    }
+    Ok(())
+}
+
+pub fn validate_solution(solution: &[String]) -> Result<(), ValidationError> {
+    if solution.len() > MAX_SOLUTION_MOVES {
+        return Err(ValidationError::new(
+            "solution",
+            format!("Solution has more than {MAX_SOLUTION_MOVES} moves"),
+        ));
+    }
+    for ptn_move in solution {
+        parse_move(ptn_move)?;
+    }
+    Ok(())
+}
+
+// Also check that every move fits on the puzzle's board, see `board::Move::check_fits`.
+// Whether the moves are legal in the position isn't checked here
+pub fn validate_solution_for_size(solution: &[String], size: usize) -> Result<(), ValidationError> {
+    validate_solution(solution)?;
+    for ptn_move in solution {
+        if let Some(mv) = parse_move(ptn_move)? {
+            mv.check_fits(size).map_err(|reason| {
+                ValidationError::new("solution", format!("Invalid move {ptn_move:?}: {reason}"))
+            })?;
+        }
+    }
+    Ok(())
+}
+
+pub fn validate_solve_time(solve_time_seconds: u32) -> Result<(), ValidationError> {
+    if solve_time_seconds > MAX_SOLVE_TIME_SECONDS {
+        return Err(ValidationError::new(
+            "solveTimeSeconds",
+            format!("Solve time is longer than {MAX_SOLVE_TIME_SECONDS} seconds"),
+        ));
+    }
+    Ok(())
+}
+
+// One time for every move, in the order they were played
+pub fn validate_move_times(
+    move_times_ms: &[u32],
+    solution: &[String],
+) -> Result<(), ValidationError> {
+    if move_times_ms.len() != solution.len() {
+        return Err(ValidationError::new(
+            "moveTimesMs",
+            format!(
+                "Got {} move times for {} moves",
+                move_times_ms.len(),
+                solution.len()
+            ),
+        ));
+    }
+    if move_times_ms.windows(2).any(|pair| pair[1] < pair[0]) {
+        return Err(ValidationError::new(
+            "moveTimesMs",
+            "Move times must not decrease",
+        ));
+    }
+    if move_times_ms
+        .last()
+        .is_some_and(|&last| last > MAX_SOLVE_TIME_SECONDS * 1000)
+    {
+        return Err(ValidationError::new(
+            "moveTimesMs",
+            format!("Move times are longer than {MAX_SOLVE_TIME_SECONDS} seconds"),
+        ));
+    }
+    Ok(())
+}
+
+// Strip annotations from a move, so `d4-'` and `d4-` compare equal.
+// The end-of-line marker `*` normalizes to an empty string
+pub fn normalize_move(ptn_move: &str) -> &str {
+    ptn_move.trim_end_matches(['\'', '"', '!', '?', '*'])
+}
+
+// Parse a single PTN move, like `Sc3`, `3e3+12` or `d4-'`.
+// A lone `*` is also accepted, since stored solutions use it to mark the end of the line, and isn't a move
+fn parse_move(ptn_move: &str) -> Result<Option<board::Move>, ValidationError> {
+    if ptn_move == "*" {
+        return Ok(None);
+    }
+    board::Move::parse(ptn_move)
+        .map(Some)
+        .map_err(|e| ValidationError::new("solution", e.to_string()))
+}
+
+#[derive(Deserialize)]
+struct UsernameField {
+    username: Option<String>,
+}
+
+// The canonical `username` of a request that hasn't reached its handler yet, from its query string and its JSON body.
+// Handlers read the username from either, so a request that carries two different usernames is rejected.
+// Reading the body means putting it back, so the request is handed back too
+pub async fn request_username(request: Request) -> Result<(Request, Option<String>), ApiError> {
+    let query_username = Query::<UsernameField>::try_from_uri(request.uri())
+        .ok()
+        .and_then(|Query(field)| field.username)
+        .map(|username| canonical_username(&username));
+
+    if !is_json_content_type(request.headers()) {
+        return Ok((request, query_username));
+    }
+    let (parts, body) = request.into_parts();
+    let bytes = axum::body::to_bytes(body, MAX_BODY_BYTES)
+        .await
+        .map_err(|_| StatusCode::PAYLOAD_TOO_LARGE)?;
+    let body_username = serde_json::from_slice::<UsernameField>(&bytes)
+        .ok()
+        .and_then(|field| field.username)
+        .map(|username| canonical_username(&username));
+    let username = match (query_username, body_username) {
+        (Some(query_username), Some(body_username)) if query_username != body_username => {
+            return Err(ValidationError::new(
+                "username",
+                "The username in the query string doesn't match the one in the body",
+            )
+            .into());
+        }
+        (query_username, body_username) => query_username.or(body_username),
+    };
+    Ok((Request::from_parts(parts, Body::from(bytes)), username))
+}
+
+// The same check as axum's `Json` extractor, so that every body a handler reads as JSON is read here too
+fn is_json_content_type(headers: &HeaderMap) -> bool {
+    headers
+        .get(header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.parse::<mime::Mime>().ok())
+        .is_some_and(|mime| {
+            mime.type_() == "application"
+                && (mime.subtype() == "json" || mime.suffix().is_some_and(|name| name == "json"))
+        })
+}
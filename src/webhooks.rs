@@ -0,0 +1,391 @@
+use std::time::Duration;
+
+use axum::{Json, extract::Path, http::StatusCode};
+use rand::{Rng, distr::Alphanumeric};
+use rusqlite::Connection;
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use crate::{
+    admin::AdminAuth,
+    audit, db, now_seconds,
+    validation::{ApiError, ValidationError},
+};
+
+// Admins register URLs that are POSTed to when something happens, see `WebhookEvent`.
+// Deliveries are queued in `webhook_deliveries` as events happen,
+// and sent by the `webhook-deliveries` job, which retries failed ones with exponential backoff.
+// The body is a JSON `Delivery`, signed with the webhook's secret: the `X-Webhook-Signature` header is
+// `sha256=` followed by the hex HMAC-SHA256 of the body. Receivers should check it, and use `id` to ignore
+// anything delivered twice
+
+// Deliveries that have failed this many times are given up on. The last retry is about 8.5 hours after the first try
+const MAX_ATTEMPTS: u32 = 10;
+
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+// Sent per run of the job, so that a big backlog doesn't hold it up for too long
+const BATCH_SIZE: u32 = 100;
+
+const SIGNATURE_HEADER: &str = "x-webhook-signature";
+
+pub fn init_db_tables(db_conn: &Connection) -> anyhow::Result<()> {
+    // `events` is a JSON array of `WebhookEvent`s
+    db_conn.execute(
+        "CREATE TABLE IF NOT EXISTS webhooks (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            url TEXT NOT NULL,
+            secret TEXT NOT NULL,
+            events TEXT NOT NULL,
+            created_seconds INTEGER NOT NULL DEFAULT (strftime('%s', 'now'))
+        )",
+        [],
+    )?;
+
+    // Delivered ones are kept, so that admins can see what was sent
+    db_conn.execute(
+        "CREATE TABLE IF NOT EXISTS webhook_deliveries (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            webhook_id INTEGER NOT NULL,
+            event TEXT NOT NULL,
+            data TEXT NOT NULL,
+            created_seconds INTEGER NOT NULL DEFAULT (strftime('%s', 'now')),
+            num_attempts INTEGER NOT NULL DEFAULT 0,
+            next_attempt_seconds INTEGER NOT NULL DEFAULT (strftime('%s', 'now')),
+            last_error TEXT,
+            delivered_seconds INTEGER,
+            failed_seconds INTEGER,
+            FOREIGN KEY (webhook_id) REFERENCES webhooks(id)
+        )",
+        [],
+    )?;
+
+    db_conn.execute(
+        "CREATE INDEX IF NOT EXISTS webhook_deliveries_pending
+        ON webhook_deliveries (next_attempt_seconds) WHERE delivered_seconds IS NULL AND failed_seconds IS NULL",
+        [],
+    )?;
+
+    Ok(())
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub enum WebhookEvent {
+    // A puzzle was added as published, with `import`. `data` is the puzzle
+    PuzzlePublished,
+    // A user reported a puzzle. `data` is the report
+    ReportFiled,
+    // A new daily puzzle was picked, just after midnight UTC. `data` is the puzzle
+    DailyPuzzle,
+}
+
+impl WebhookEvent {
+    fn name(self) -> String {
+        serde_json::to_value(self)
+            .ok()
+            .and_then(|value| value.as_str().map(String::from))
+            .unwrap_or_default()
+    }
+}
+
+// Queue a delivery of the event to every webhook that's registered for it
+pub fn enqueue(
+    db_conn: &Connection,
+    event: WebhookEvent,
+    data: &impl Serialize,
+) -> anyhow::Result<()> {
+    db_conn.execute(
+        "INSERT INTO webhook_deliveries (webhook_id, event, data)
+        SELECT id, ?1, ?2 FROM webhooks
+        WHERE EXISTS (SELECT 1 FROM json_each(webhooks.events) WHERE value = ?1)",
+        rusqlite::params![event.name(), serde_json::to_string(data)?],
+    )?;
+    Ok(())
+}
+
+#[derive(Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct NewWebhook {
+    url: String,
+    events: Vec<WebhookEvent>,
+}
+
+#[derive(Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct Webhook {
+    id: i64,
+    url: String,
+    events: Vec<WebhookEvent>,
+    // For checking signatures. Only included when the webhook is created
+    #[serde(skip_serializing_if = "Option::is_none")]
+    secret: Option<String>,
+    created_seconds: u64,
+}
+
+// The body of every webhook request
+#[derive(Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct Delivery {
+    // The same for every retry
+    id: i64,
+    event: WebhookEvent,
+    // When the event happened
+    timestamp_seconds: u64,
+    #[schema(value_type = Object)]
+    data: serde_json::Value,
+}
+
+fn validate_webhook(webhook: &NewWebhook) -> Result<(), ValidationError> {
+    let url = reqwest::Url::parse(&webhook.url)
+        .map_err(|e| ValidationError::new("url", format!("Invalid URL: {e}")))?;
+    if !matches!(url.scheme(), "http" | "https") {
+        return Err(ValidationError::new("url", "URL must be http or https"));
+    }
+    if webhook.events.is_empty() {
+        return Err(ValidationError::new("events", "No events given"));
+    }
+    Ok(())
+}
+
+// Register a URL to be sent the given events. The response includes the secret deliveries are signed with,
+// which isn't shown again
+#[utoipa::path(
+    post,
+    path = "/admin/webhooks",
+    tag = "admin",
+    request_body = NewWebhook,
+    security(("admin_token" = [])),
+    responses(
+        (status = 200, body = Webhook),
+        (status = 400, body = ValidationError),
+        (status = 401),
+        (status = 403),
+    ),
+)]
+pub async fn create_webhook(
+    admin: AdminAuth,
+    Json(payload): Json<NewWebhook>,
+) -> Result<Json<Webhook>, ApiError> {
+    validate_webhook(&payload)?;
+    let mut events = payload.events.clone();
+    events.sort_by_key(|event| event.name());
+    events.dedup();
+    let secret: String = rand::rng()
+        .sample_iter(&Alphanumeric)
+        .take(32)
+        .map(char::from)
+        .collect();
+    let db_conn = db::open().map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let (id, created_seconds) = db_conn
+        .query_row(
+            "INSERT INTO webhooks (url, secret, events) VALUES (?1, ?2, ?3)
+            RETURNING id, created_seconds",
+            rusqlite::params![
+                payload.url,
+                secret,
+                serde_json::to_string(&events).map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+            ],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        )
+        .map_err(|e| {
+            tracing::error!("Error creating webhook: {:?}", e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+    audit::record_for(
+        &db_conn,
+        &admin,
+        "create_webhook",
+        serde_json::json!({"id": id, "url": payload.url, "events": events}),
+    )?;
+    Ok(Json(Webhook {
+        id,
+        url: payload.url,
+        events,
+        secret: Some(secret),
+        created_seconds,
+    }))
+}
+
+#[utoipa::path(
+    get,
+    path = "/admin/webhooks",
+    tag = "admin",
+    security(("admin_token" = [])),
+    responses(
+        (status = 200, body = Vec<Webhook>),
+        (status = 401),
+        (status = 403),
+    ),
+)]
+pub async fn get_webhooks(_: AdminAuth) -> Result<Json<Vec<Webhook>>, StatusCode> {
+    let db_conn = db::open().map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    read_webhooks(&db_conn).map(Json).map_err(|e| {
+        tracing::error!("Error reading webhooks: {:?}", e);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })
+}
+
+fn read_webhooks(db_conn: &Connection) -> anyhow::Result<Vec<Webhook>> {
+    let mut stmt =
+        db_conn.prepare("SELECT id, url, events, created_seconds FROM webhooks ORDER BY id")?;
+    let rows = stmt.query_and_then([], |row| {
+        Ok(Webhook {
+            id: row.get(0)?,
+            url: row.get(1)?,
+            events: serde_json::from_str(&row.get::<_, String>(2)?)?,
+            secret: None,
+            created_seconds: row.get(3)?,
+        })
+    })?;
+    rows.collect()
+}
+
+// Stop sending events to the webhook. Deliveries that haven't been sent yet are dropped
+#[utoipa::path(
+    delete,
+    path = "/admin/webhooks/{id}",
+    tag = "admin",
+    params(("id" = i64, Path)),
+    security(("admin_token" = [])),
+    responses(
+        (status = 204),
+        (status = 401),
+        (status = 403),
+        (status = 404),
+    ),
+)]
+pub async fn delete_webhook(
+    admin: AdminAuth,
+    Path(id): Path<i64>,
+) -> Result<StatusCode, StatusCode> {
+    let mut db_conn = db::open().map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let transaction = db_conn
+        .transaction()
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    transaction
+        .execute("DELETE FROM webhook_deliveries WHERE webhook_id = ?1", [id])
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let num_deleted = transaction
+        .execute("DELETE FROM webhooks WHERE id = ?1", [id])
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    if num_deleted == 0 {
+        return Err(StatusCode::NOT_FOUND);
+    }
+    audit::record_for(
+        &transaction,
+        &admin,
+        "delete_webhook",
+        serde_json::json!({"id": id}),
+    )?;
+    transaction
+        .commit()
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+struct PendingDelivery {
+    url: String,
+    secret: String,
+    delivery: Delivery,
+    num_attempts: u32,
+}
+
+// The `webhook-deliveries` job, see `scheduler.rs`. Sends every delivery that's due
+pub async fn run_deliveries() -> anyhow::Result<()> {
+    let pending = read_pending(&db::open()?)?;
+    if pending.is_empty() {
+        return Ok(());
+    }
+    let client = reqwest::Client::builder().timeout(TIMEOUT).build()?;
+    let mut num_failed = 0;
+    for pending in &pending {
+        let result = send(&client, pending).await;
+        let db_conn = db::open()?;
+        match result {
+            Ok(()) => {
+                db_conn.execute(
+                    "UPDATE webhook_deliveries SET num_attempts = num_attempts + 1,
+                        delivered_seconds = strftime('%s', 'now'), last_error = NULL
+                    WHERE id = ?1",
+                    [pending.delivery.id],
+                )?;
+            }
+            Err(e) => {
+                num_failed += 1;
+                // 1 minute, then 2, 4 and so on
+                let num_attempts = pending.num_attempts + 1;
+                let retry_seconds = 60 << (num_attempts - 1).min(16);
+                db_conn.execute(
+                    "UPDATE webhook_deliveries SET num_attempts = ?2, last_error = ?3,
+                        next_attempt_seconds = ?4,
+                        failed_seconds = CASE WHEN ?2 >= ?5 THEN strftime('%s', 'now') END
+                    WHERE id = ?1",
+                    rusqlite::params![
+                        pending.delivery.id,
+                        num_attempts,
+                        e.to_string(),
+                        now_seconds() + retry_seconds,
+                        MAX_ATTEMPTS
+                    ],
+                )?;
+            }
+        }
+    }
+    tracing::info!(
+        "Sent {} webhook deliveries, {} failed",
+        pending.len(),
+        num_failed
+    );
+    Ok(())
+}
+
+fn read_pending(db_conn: &Connection) -> anyhow::Result<Vec<PendingDelivery>> {
+    let mut stmt = db_conn.prepare(
+        "SELECT webhooks.url, webhooks.secret, webhook_deliveries.id, webhook_deliveries.event,
+            webhook_deliveries.created_seconds, webhook_deliveries.data, webhook_deliveries.num_attempts
+        FROM webhook_deliveries JOIN webhooks ON webhooks.id = webhook_deliveries.webhook_id
+        WHERE delivered_seconds IS NULL AND failed_seconds IS NULL AND next_attempt_seconds <= ?1
+        ORDER BY webhook_deliveries.id
+        LIMIT ?2",
+    )?;
+    let rows = stmt.query_and_then(rusqlite::params![now_seconds(), BATCH_SIZE], |row| {
+        Ok(PendingDelivery {
+            url: row.get(0)?,
+            secret: row.get(1)?,
+            delivery: Delivery {
+                id: row.get(2)?,
+                event: serde_json::from_value(serde_json::Value::String(row.get(3)?))?,
+                timestamp_seconds: row.get(4)?,
+                data: serde_json::from_str(&row.get::<_, String>(5)?)?,
+            },
+            num_attempts: row.get(6)?,
+        })
+    })?;
+    rows.collect()
+}
+
+async fn send(client: &reqwest::Client, pending: &PendingDelivery) -> anyhow::Result<()> {
+    let body = serde_json::to_vec(&pending.delivery)?;
+    client
+        .post(&pending.url)
+        .header(reqwest::header::CONTENT_TYPE, "application/json")
+        .header(
+            SIGNATURE_HEADER,
+            format!("sha256={}", sign(&pending.secret, &body)),
+        )
+        .body(body)
+        .send()
+        .await?
+        .error_for_status()?;
+    Ok(())
+}
+
+fn sign(secret: &str, body: &[u8]) -> String {
+    let key = ring::hmac::Key::new(ring::hmac::HMAC_SHA256, secret.as_bytes());
+    ring::hmac::sign(&key, body)
+        .as_ref()
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect()
+}
@@ -0,0 +1,964 @@
+use axum::http::{StatusCode, header};
+use serde_json::json;
+use tak_tactics_backend::{
+    config::{self, RetentionConfig},
+    retention,
+    scheduler::Schedule,
+};
+
+use crate::common::{SOLUTION, TestApp, assert_fields};
+
+#[tokio::test]
+async fn reports_stay_unresolved_until_an_admin_resolves_them() {
+    let app = TestApp::new().await;
+    let response = app
+        .post(
+            "/v1/puzzles/1/reports",
+            json!({"username": "alice", "reason": " "}),
+        )
+        .await;
+    assert_eq!(response.status, StatusCode::BAD_REQUEST);
+    let response = app
+        .post(
+            "/v1/puzzles/6/reports",
+            json!({"username": "alice", "reason": "Not published"}),
+        )
+        .await;
+    assert_eq!(response.status, StatusCode::NOT_FOUND);
+
+    let report = app
+        .post(
+            "/v1/puzzles/1/reports",
+            json!({"username": "alice", "reason": "The solution isn't a win"}),
+        )
+        .await
+        .json();
+    assert_fields(
+        &report,
+        &[
+            "id",
+            "puzzleId",
+            "username",
+            "reason",
+            "createdSeconds",
+            "resolvedSeconds",
+        ],
+    );
+    assert_eq!(report["resolvedSeconds"], json!(null));
+    app.post(
+        "/v1/puzzles/2/reports",
+        json!({"username": "bob", "reason": "Two winning moves"}),
+    )
+    .await;
+
+    let response = app.get("/v1/admin/reports").await;
+    assert_eq!(response.status, StatusCode::UNAUTHORIZED);
+    let reports = app
+        .admin("GET", "/v1/admin/reports", json!({}))
+        .await
+        .json();
+    let puzzle_ids: Vec<_> = reports["items"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|report| report["puzzleId"].as_u64().unwrap())
+        .collect();
+    assert_eq!(puzzle_ids, vec![2, 1]);
+
+    let uri = format!("/v1/admin/reports/{}/resolve", report["id"]);
+    let resolved = app.admin("POST", &uri, json!({})).await.json();
+    assert!(resolved["resolvedSeconds"].is_u64());
+    let reports = app
+        .admin("GET", "/v1/admin/reports?unresolved=true", json!({}))
+        .await
+        .json();
+    assert_eq!(reports["items"].as_array().unwrap().len(), 1);
+    assert_eq!(reports["items"][0]["puzzleId"], 2);
+
+    let response = app
+        .admin("POST", "/v1/admin/reports/1000/resolve", json!({}))
+        .await;
+    assert_eq!(response.status, StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn reports_are_posted_to_discord_without_mentions() {
+    let (sender, mut received) = tokio::sync::mpsc::unbounded_channel();
+    let receiver = axum::Router::new().route(
+        "/discord",
+        axum::routing::post(
+            move |axum::Json(body): axum::Json<serde_json::Value>| async move {
+                sender.send(body).unwrap();
+                StatusCode::NO_CONTENT
+            },
+        ),
+    );
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("http://{}/discord", listener.local_addr().unwrap());
+    tokio::spawn(async move { axum::serve(listener, receiver).await.unwrap() });
+    let mut config = config::Config::default();
+    config.discord.webhook_url = Some(url);
+    let app = TestApp::with_config(config).await;
+
+    let response = app
+        .post(
+            "/v1/puzzles/3/reports",
+            json!({"username": "alice", "reason": "@everyone the solution is wrong"}),
+        )
+        .await;
+    assert_eq!(response.status, StatusCode::OK);
+    let message = tokio::time::timeout(std::time::Duration::from_secs(5), received.recv())
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(
+        message["content"],
+        "Puzzle 3 was reported by alice: @everyone the solution is wrong"
+    );
+    assert_eq!(message["allowed_mentions"], json!({"parse": []}));
+}
+
+#[tokio::test]
+async fn attempts_can_be_exported_as_csv() {
+    let app = TestApp::new().await;
+    let response = app.get("/v1/admin/export/attempts.csv").await;
+    assert_eq!(response.status, StatusCode::UNAUTHORIZED);
+    let response = app
+        .admin("GET", "/v1/admin/export/attempts.csv", json!({}))
+        .await;
+    assert_eq!(response.status, StatusCode::OK);
+    assert_eq!(
+        String::from_utf8(response.body).unwrap(),
+        "id,puzzle_id,username,attempt_number,solved,practice,solve_time_seconds,timestamp_seconds,solution\n"
+    );
+
+    app.solve(1, "alice", true).await;
+    app.solve(2, "bob", false).await;
+    app.db()
+        .execute(
+            "UPDATE puzzle_attempts SET timestamp_seconds = 1000 WHERE puzzle_id = 1",
+            [],
+        )
+        .unwrap();
+    let response = app
+        .admin("GET", "/v1/admin/export/attempts.csv", json!({}))
+        .await;
+    assert_eq!(
+        response.headers[header::CONTENT_TYPE],
+        "text/csv; charset=utf-8"
+    );
+    let csv = String::from_utf8(response.body).unwrap();
+    let lines: Vec<&str> = csv.lines().collect();
+    assert_eq!(lines.len(), 3);
+    assert_eq!(
+        lines[1],
+        format!("1,1,alice,1,true,false,30,1000,{}", SOLUTION.join(" "))
+    );
+    assert!(lines[2].starts_with("2,2,bob,1,false,false,30,"));
+
+    let response = app
+        .admin("GET", "/v1/admin/export/attempts.csv?since=1001", json!({}))
+        .await;
+    assert_eq!(String::from_utf8(response.body).unwrap().lines().count(), 2);
+    let response = app
+        .admin("GET", "/v1/admin/export/attempts.csv?until=1001", json!({}))
+        .await;
+    let csv = String::from_utf8(response.body).unwrap();
+    assert_eq!(csv.lines().nth(1).unwrap().split(',').nth(2), Some("alice"));
+    let response = app
+        .admin(
+            "GET",
+            "/v1/admin/export/attempts.csv?since=5&until=4",
+            json!({}),
+        )
+        .await;
+    assert_eq!(response.status, StatusCode::BAD_REQUEST);
+
+    // Large exports are sent in several chunks
+    app.db()
+        .execute(
+            "WITH RECURSIVE numbers (i) AS (SELECT 2 UNION ALL SELECT i + 1 FROM numbers WHERE i < 2500)
+            INSERT INTO puzzle_attempts (puzzle_id, user_id, solved, solve_time_seconds, solution, attempt_number)
+            SELECT 3, (SELECT id FROM users WHERE username = 'alice'), 0, 30, '', i FROM numbers",
+            [],
+        )
+        .unwrap();
+    let response = app
+        .admin("GET", "/v1/admin/export/attempts.csv", json!({}))
+        .await;
+    assert_eq!(
+        String::from_utf8(response.body).unwrap().lines().count(),
+        2502
+    );
+    assert_eq!(
+        app.count("SELECT COUNT(*) FROM audit_log WHERE action = 'export_attempts'"),
+        5
+    );
+}
+
+#[tokio::test]
+async fn dashboard_summarizes_recent_activity() {
+    let app = TestApp::new().await;
+    let response = app.get("/v1/admin/dashboard").await;
+    assert_eq!(response.status, StatusCode::UNAUTHORIZED);
+    let response = app
+        .admin("GET", "/v1/admin/dashboard?days=0", json!({}))
+        .await;
+    assert_eq!(response.status, StatusCode::BAD_REQUEST);
+
+    for i in 0..5 {
+        app.solve(1, &format!("user{i}"), false).await;
+        app.solve(2, &format!("user{i}"), i > 0).await;
+    }
+    app.post(
+        "/v1/puzzles/1/reports",
+        json!({"username": "user0", "reason": "Too hard"}),
+    )
+    .await;
+
+    let dashboard = app
+        .admin("GET", "/v1/admin/dashboard?days=7", json!({}))
+        .await
+        .json();
+    assert_fields(
+        &dashboard,
+        &[
+            "days",
+            "attemptsPerDay",
+            "newUsersPerDay",
+            "worstSolveRates",
+            "numUnresolvedReports",
+            "oldestUnresolvedReportSeconds",
+            "ratingDriftPerDay",
+        ],
+    );
+    let today = &dashboard["attemptsPerDay"][0];
+    assert_eq!(today["numAttempts"], 10);
+    assert_eq!(today["numRatedAttempts"], 10);
+    assert_eq!(today["numSolved"], 4);
+    assert_eq!(today["numUsers"], 5);
+    assert_eq!(dashboard["newUsersPerDay"][0]["numUsers"], 5);
+    assert_eq!(dashboard["numUnresolvedReports"], 1);
+
+    let worst: Vec<_> = dashboard["worstSolveRates"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|puzzle| {
+            (
+                puzzle["puzzleId"].as_u64().unwrap(),
+                puzzle["solveRate"].as_f64().unwrap(),
+            )
+        })
+        .collect();
+    assert_eq!(worst, vec![(1, 0.0), (2, 0.8)]);
+
+    let drift = &dashboard["ratingDriftPerDay"][0];
+    assert_eq!(drift["numRatedAttempts"], 10);
+    assert!(drift["averageUserRatingChange"].is_f64());
+    assert!(drift["averagePuzzleRatingChange"].is_f64());
+}
+
+#[tokio::test]
+async fn banned_users_are_not_served_puzzles_and_cannot_submit_attempts() {
+    let app = TestApp::new().await;
+    app.get("/v1/puzzles?username=spammer").await;
+    assert_eq!(app.count("SELECT COUNT(*) FROM attempts_in_progress"), 1);
+
+    let response = app
+        .post(
+            "/v1/admin/users/spammer/ban",
+            json!({"reason": "Fails every puzzle"}),
+        )
+        .await;
+    assert_eq!(response.status, StatusCode::UNAUTHORIZED);
+    let ban = app
+        .admin(
+            "POST",
+            "/v1/admin/users/spammer/ban",
+            json!({"reason": "Fails every puzzle"}),
+        )
+        .await
+        .json();
+    assert_fields(&ban, &["username", "reason", "bannedSeconds"]);
+    // The attempt in progress is dropped, rather than recorded as failed
+    assert_eq!(app.count("SELECT COUNT(*) FROM attempts_in_progress"), 0);
+
+    let response = app.get("/v1/puzzles?username=spammer").await;
+    assert_eq!(response.status, StatusCode::FORBIDDEN);
+    let response = app.solve(1, "spammer", false).await;
+    assert_eq!(response.status, StatusCode::FORBIDDEN);
+    let response = app.get("/v1/puzzles/current?username=spammer").await;
+    assert_eq!(response.status, StatusCode::FORBIDDEN);
+    assert_eq!(
+        app.count("SELECT COUNT(*) FROM users WHERE username = 'spammer'"),
+        0
+    );
+    let response = app.get("/v1/puzzles?username=alice").await;
+    assert_eq!(response.status, StatusCode::OK);
+
+    let bans = app.admin("GET", "/v1/admin/bans", json!({})).await.json();
+    assert_eq!(bans["items"][0]["username"], "spammer");
+
+    let response = app
+        .admin("DELETE", "/v1/admin/users/spammer/ban", json!({}))
+        .await;
+    assert_eq!(response.status, StatusCode::NO_CONTENT);
+    let response = app.solve(1, "spammer", false).await;
+    assert_eq!(response.status, StatusCode::OK);
+    let response = app
+        .admin("DELETE", "/v1/admin/users/spammer/ban", json!({}))
+        .await;
+    assert_eq!(response.status, StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn renamed_users_keep_their_attempts_and_follows() {
+    let app = TestApp::new().await;
+    app.solve(3, "alice", true).await;
+    app.solve(1, "bob", false).await;
+    app.post("/v1/users/bob/follow", json!({"username": "alice"}))
+        .await;
+
+    let response = app
+        .admin(
+            "POST",
+            "/v1/admin/users/alice/rename",
+            json!({"username": "Carol"}),
+        )
+        .await;
+    assert_eq!(response.status, StatusCode::OK);
+    let user = response.json();
+    assert_fields(&user, &["id", "username", "displayName"]);
+    assert_eq!(user["username"], "carol");
+    assert_eq!(user["displayName"], "Carol");
+
+    let history = app.get("/v1/users/carol/attempts").await.json();
+    assert_eq!(history["items"].as_array().unwrap().len(), 1);
+    let history = app.get("/v1/users/alice/attempts").await.json();
+    assert_eq!(history["items"].as_array().unwrap().len(), 0);
+    assert_eq!(
+        app.get("/v1/users/carol/following").await.json(),
+        json!(["bob"])
+    );
+    assert_eq!(
+        app.get("/v1/leaderboard").await.json()[0]["username"],
+        "Carol"
+    );
+    // The rated attempt is still the user's first, so attempting the puzzle again isn't rated
+    assert_eq!(app.solve(3, "carol", true).await.json()["rated"], false);
+
+    let response = app
+        .admin(
+            "POST",
+            "/v1/admin/users/carol/rename",
+            json!({"username": "Bob"}),
+        )
+        .await;
+    assert_eq!(response.status, StatusCode::CONFLICT);
+    let response = app
+        .admin(
+            "POST",
+            "/v1/admin/users/alice/rename",
+            json!({"username": "dave"}),
+        )
+        .await;
+    assert_eq!(response.status, StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn merged_users_keep_only_their_earliest_attempt_at_each_puzzle_rated() {
+    let app = TestApp::new().await;
+    app.solve(3, "alice", true).await;
+    app.solve(3, "alice_typo", false).await;
+    app.solve(1, "alice_typo", true).await;
+    app.db()
+        .execute(
+            "UPDATE puzzle_attempts SET timestamp_seconds = timestamp_seconds - 100
+            WHERE puzzle_id = 3 AND user_id = (SELECT id FROM users WHERE username = 'alice_typo')",
+            [],
+        )
+        .unwrap();
+
+    let response = app
+        .admin(
+            "POST",
+            "/v1/admin/users/merge",
+            json!({"from": "alice_typo", "into": "alice"}),
+        )
+        .await;
+    assert_eq!(response.status, StatusCode::OK);
+    let merge = response.json();
+    assert_fields(
+        &merge,
+        &[
+            "user",
+            "numAttemptsMoved",
+            "affectedPuzzleIds",
+            "numRatingsChanged",
+        ],
+    );
+    assert_eq!(merge["user"]["username"], "alice");
+    assert_eq!(merge["numAttemptsMoved"], 2);
+    assert_eq!(merge["affectedPuzzleIds"], json!([1, 3]));
+
+    // The typo's failed attempt at puzzle 3 came first, so it's the rated one now
+    let history = app.get("/v1/users/alice/attempts").await.json();
+    assert_eq!(history["items"].as_array().unwrap().len(), 3);
+    assert_eq!(
+        app.count("SELECT COUNT(*) FROM rated_attempts WHERE username = 'alice' AND puzzle_id = 3 AND solved = 0"),
+        1
+    );
+    assert_eq!(
+        app.count("SELECT COUNT(*) FROM user_rating_history WHERE username = 'alice'"),
+        2
+    );
+    assert_eq!(app.count("SELECT COUNT(*) FROM users"), 1);
+
+    let response = app
+        .admin(
+            "POST",
+            "/v1/admin/users/merge",
+            json!({"from": "alice_typo", "into": "alice"}),
+        )
+        .await;
+    assert_eq!(response.status, StatusCode::NOT_FOUND);
+    let response = app
+        .admin(
+            "POST",
+            "/v1/admin/users/merge",
+            json!({"from": "alice", "into": "Alice"}),
+        )
+        .await;
+    assert_eq!(response.status, StatusCode::BAD_REQUEST);
+    assert_eq!(response.json()["field"], "into");
+}
+
+#[tokio::test]
+async fn deleted_users_are_anonymized_without_changing_puzzle_ratings() {
+    let app = TestApp::new().await;
+    app.solve(3, "alice", true).await;
+    app.solve(3, "bob", false).await;
+    app.post("/v1/users/bob/follow", json!({"username": "alice"}))
+        .await;
+    let rating = app.get("/v1/puzzles/3/rating").await.json();
+
+    let response = app.delete("/v1/users/alice").await;
+    assert_eq!(response.status, StatusCode::UNAUTHORIZED);
+    let response = app.admin("DELETE", "/v1/users/Alice", json!(null)).await;
+    assert_eq!(response.status, StatusCode::NO_CONTENT);
+
+    assert_eq!(app.get("/v1/puzzles/3/rating").await.json(), rating);
+    let leaderboard = app.get("/v1/leaderboard").await.json();
+    assert_eq!(leaderboard.as_array().unwrap().len(), 1);
+    assert_eq!(leaderboard[0]["username"], "bob");
+    let history = app.get("/v1/users/alice/attempts").await.json();
+    assert_eq!(history["items"].as_array().unwrap().len(), 0);
+    assert_eq!(app.count("SELECT COUNT(*) FROM follows"), 0);
+    assert_eq!(
+        app.count("SELECT COUNT(*) FROM rated_attempts WHERE username LIKE 'deleted user %'"),
+        1
+    );
+
+    // The username can be used again, by a new user
+    assert_eq!(app.solve(3, "alice", true).await.json()["rated"], true);
+    let response = app.admin("DELETE", "/v1/users/carol", json!(null)).await;
+    assert_eq!(response.status, StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn exports_include_everything_stored_about_the_user() {
+    let app = TestApp::new().await;
+    app.solve(3, "alice", true).await;
+    app.solve(1, "alice", false).await;
+    app.post("/v1/users/bob/follow", json!({"username": "alice"}))
+        .await;
+
+    let response = app
+        .admin("GET", "/v1/users/Alice/export", json!(null))
+        .await;
+    assert_eq!(response.status, StatusCode::OK);
+    assert_eq!(
+        response.headers[header::CONTENT_DISPOSITION],
+        "attachment; filename=\"alice.json\""
+    );
+    let export = response.json();
+    assert_fields(
+        &export,
+        &["profile", "ratingHistory", "attempts", "otherData"],
+    );
+    assert_eq!(export["profile"]["username"], "alice");
+    assert_eq!(export["ratingHistory"].as_array().unwrap().len(), 2);
+    let attempts = export["attempts"].as_array().unwrap();
+    assert_eq!(attempts.len(), 2);
+    assert_eq!(attempts[0]["puzzleId"], 3);
+    assert_eq!(attempts[0]["solution"], json!(SOLUTION));
+    assert_eq!(export["otherData"]["follows"][0]["followee"], "bob");
+
+    let response = app
+        .admin("GET", "/v1/users/carol/export", json!(null))
+        .await;
+    assert_eq!(response.status, StatusCode::NOT_FOUND);
+    let response = app.get("/v1/users/alice/export").await;
+    assert_eq!(response.status, StatusCode::UNAUTHORIZED);
+}
+
+#[tokio::test]
+async fn logged_in_users_can_export_and_delete_themselves() {
+    let mut config = config::Config::default();
+    config.auth.secret = Some("test-secret".to_string());
+    let app = TestApp::with_config(config).await;
+    let dave = app.log_in("dave@example.com", "dave").await;
+    let dave_token = dave["accessToken"].as_str().unwrap();
+    let mallory = app.log_in("mallory@example.com", "mallory").await;
+    let mallory_token = mallory["accessToken"].as_str().unwrap();
+    let solve = json!({
+        "id": 3,
+        "username": "dave",
+        "solved": true,
+        "solution": SOLUTION,
+        "solveTimeSeconds": 30,
+    });
+    let response = app
+        .as_user(dave_token, "POST", "/v1/puzzles/3", solve)
+        .await;
+    assert_eq!(response.status, StatusCode::OK);
+
+    let response = app
+        .as_user(mallory_token, "GET", "/v1/users/dave/export", json!(null))
+        .await;
+    assert_eq!(response.status, StatusCode::FORBIDDEN);
+    let response = app
+        .as_user(dave_token, "GET", "/v1/users/Dave/export", json!(null))
+        .await;
+    assert_eq!(response.status, StatusCode::OK);
+    assert_eq!(response.json()["profile"]["username"], "dave");
+
+    let response = app
+        .as_user(mallory_token, "DELETE", "/v1/users/dave", json!(null))
+        .await;
+    assert_eq!(response.status, StatusCode::FORBIDDEN);
+    let response = app
+        .as_user(dave_token, "DELETE", "/v1/users/dave", json!(null))
+        .await;
+    assert_eq!(response.status, StatusCode::NO_CONTENT);
+    assert_eq!(
+        app.count("SELECT COUNT(*) FROM rated_attempts WHERE username = 'dave'"),
+        0
+    );
+    // Deleting ends the user's sessions, and only admins' actions are audited
+    let response = app
+        .as_user(dave_token, "GET", "/v1/auth/session", json!(null))
+        .await;
+    assert_eq!(response.status, StatusCode::UNAUTHORIZED);
+    assert_eq!(app.count("SELECT COUNT(*) FROM audit_log"), 0);
+}
+
+#[tokio::test]
+async fn purging_deletes_inactive_guests_and_old_attempts_in_progress() {
+    let app = TestApp::new().await;
+    app.post(
+        "/v1/puzzles/3",
+        json!({
+            "id": 3,
+            "username": "Guest1",
+            "solved": true,
+            "solution": SOLUTION,
+            "solveTimeSeconds": 30,
+            "moveTimesMs": [1000, 2000],
+        }),
+    )
+    .await;
+    assert_eq!(app.count("SELECT COUNT(*) FROM attempt_moves"), 2);
+    app.solve(3, "Guest1", false).await;
+    app.solve(3, "Guest2", true).await;
+    app.solve(3, "alice", true).await;
+    app.get("/v1/puzzles?username=bob").await;
+    app.db()
+        .execute(
+            "UPDATE puzzle_attempts SET timestamp_seconds = 0
+            WHERE user_id IN (SELECT id FROM users WHERE username IN ('guest1', 'alice'))",
+            [],
+        )
+        .unwrap();
+    app.db()
+        .execute("UPDATE attempts_in_progress SET started_ms = 0", [])
+        .unwrap();
+    let rating = app.get("/v1/puzzles/3/rating").await.json();
+
+    let purged = retention::purge(&mut app.db(), &RetentionConfig::default()).unwrap();
+    assert_eq!(
+        purged,
+        retention::Purged {
+            num_guests: 1,
+            num_attempts: 1,
+            num_in_progress: 1,
+        }
+    );
+    assert_eq!(app.get("/v1/puzzles/3/rating").await.json(), rating);
+    assert_eq!(
+        app.count("SELECT COUNT(*) FROM users WHERE deleted_seconds IS NULL"),
+        2
+    );
+    assert_eq!(app.count("SELECT COUNT(*) FROM puzzle_attempts"), 3);
+    assert_eq!(app.count("SELECT COUNT(*) FROM attempt_moves"), 0);
+    assert_eq!(app.count("SELECT COUNT(*) FROM attempts_in_progress"), 0);
+
+    let purged = retention::purge(&mut app.db(), &RetentionConfig::default()).unwrap();
+    assert_eq!(purged, retention::Purged::default());
+}
+
+#[tokio::test]
+async fn admin_actions_are_recorded_in_the_audit_log() {
+    let app = TestApp::new().await;
+    app.solve(1, "alice", true).await;
+    app.admin(
+        "POST",
+        "/v1/admin/users/spammer/ban",
+        json!({"reason": "Spam"}),
+    )
+    .await;
+    app.admin(
+        "POST",
+        "/v1/admin/users/alice/rename",
+        json!({"username": "Alicia"}),
+    )
+    .await;
+    // Actions that fail aren't recorded
+    let response = app
+        .admin("DELETE", "/v1/admin/users/bob/ban", json!(null))
+        .await;
+    assert_eq!(response.status, StatusCode::NOT_FOUND);
+    app.get("/v1/admin/bans").await;
+
+    let response = app.get("/v1/admin/audit-log").await;
+    assert_eq!(response.status, StatusCode::UNAUTHORIZED);
+    let page = app
+        .admin("GET", "/v1/admin/audit-log?limit=1", json!(null))
+        .await
+        .json();
+    let entry = &page["items"][0];
+    assert_fields(
+        entry,
+        &["id", "actor", "action", "payload", "timestampSeconds"],
+    );
+    assert_eq!(entry["actor"], "ADMIN_TOKEN");
+    assert_eq!(entry["action"], "rename_user");
+    assert_eq!(entry["payload"], json!({"from": "alice", "to": "alicia"}));
+
+    let cursor = page["nextCursor"].as_str().unwrap();
+    let page = app
+        .admin(
+            "GET",
+            &format!("/v1/admin/audit-log?cursor={cursor}"),
+            json!(null),
+        )
+        .await
+        .json();
+    let items = page["items"].as_array().unwrap();
+    assert_eq!(items.len(), 1);
+    assert_eq!(items[0]["action"], "ban_user");
+    assert_eq!(
+        items[0]["payload"],
+        json!({"username": "spammer", "reason": "Spam"})
+    );
+    assert_eq!(page["nextCursor"], json!(null));
+}
+
+#[tokio::test]
+async fn jobs_can_be_run_by_admins_but_not_twice_at_once() {
+    let app = TestApp::new().await;
+    let jobs = app.admin("GET", "/v1/admin/jobs", json!(null)).await.json();
+    let jobs = jobs.as_array().unwrap();
+    assert_eq!(jobs.len(), 8);
+    let purge = jobs
+        .iter()
+        .find(|job| job["name"] == "purge-old-data")
+        .unwrap();
+    assert_eq!(purge["enabled"], false);
+    assert_eq!(purge["nextRunSeconds"], json!(null));
+
+    let response = app
+        .admin(
+            "POST",
+            "/v1/admin/jobs/leaderboard-snapshots/run",
+            json!(null),
+        )
+        .await;
+    assert_eq!(response.status, StatusCode::OK);
+    let job = response.json();
+    assert_fields(
+        &job,
+        &[
+            "name",
+            "schedule",
+            "enabled",
+            "runningSinceSeconds",
+            "lastStartedSeconds",
+            "lastFinishedSeconds",
+            "lastError",
+            "numRuns",
+            "nextRunSeconds",
+        ],
+    );
+    assert_eq!(job["schedule"], "0 * * * *");
+    assert_eq!(job["numRuns"], 1);
+    assert_eq!(job["runningSinceSeconds"], json!(null));
+    assert_eq!(job["lastError"], json!(null));
+
+    app.db()
+        .execute(
+            "UPDATE scheduled_jobs SET running_since_seconds = 1 WHERE name = 'leaderboard-snapshots'",
+            [],
+        )
+        .unwrap();
+    let response = app
+        .admin(
+            "POST",
+            "/v1/admin/jobs/leaderboard-snapshots/run",
+            json!(null),
+        )
+        .await;
+    assert_eq!(response.status, StatusCode::CONFLICT);
+    let response = app
+        .admin("POST", "/v1/admin/jobs/backup/run", json!(null))
+        .await;
+    assert_eq!(response.status, StatusCode::NOT_FOUND);
+}
+
+#[test]
+fn schedules_are_cron_expressions_in_utc() {
+    // Friday 2026-10-16 17:50
+    let friday_evening = 1792173000;
+    let working_hours: Schedule = "*/15 9-17 * * 1-5".parse().unwrap();
+    assert_eq!(
+        working_hours.next_after(friday_evening),
+        Some(friday_evening + 60 * 60 * 63 + 10 * 60),
+        "Monday 2026-10-19 09:00"
+    );
+    let hourly: Schedule = "@hourly".parse().unwrap();
+    assert_eq!(
+        hourly.next_after(friday_evening),
+        Some(friday_evening + 10 * 60)
+    );
+    // Either the day of month or the day of week has to match, like in cron
+    let firsts_and_mondays: Schedule = "0 0 1 * 1".parse().unwrap();
+    assert_eq!(
+        firsts_and_mondays.next_after(friday_evening),
+        Some(friday_evening + 60 * 60 * 54 + 10 * 60)
+    );
+    let leap_days: Schedule = "0 0 29 2 *".parse().unwrap();
+    assert_eq!(leap_days.next_after(friday_evening), Some(1835395200));
+    let never: Schedule = "0 0 30 2 *".parse().unwrap();
+    assert_eq!(never.next_after(friday_evening), None);
+
+    assert!("* * * *".parse::<Schedule>().is_err());
+    assert!("60 * * * *".parse::<Schedule>().is_err());
+    assert!("*/0 * * * *".parse::<Schedule>().is_err());
+    assert!("5-1 * * * *".parse::<Schedule>().is_err());
+}
+
+#[tokio::test]
+async fn backups_are_copies_of_the_database_and_only_the_newest_are_kept() {
+    let directory = std::env::temp_dir().join("tak-tactics-test-backups");
+    let _ = std::fs::remove_dir_all(&directory);
+    let app = TestApp::with_config(config::Config {
+        backups: config::BackupsConfig {
+            directory: directory.clone(),
+            keep: 2,
+            ..Default::default()
+        },
+        ..Default::default()
+    })
+    .await;
+    app.solve(1, "alice", true).await;
+
+    let response = app.post("/v1/admin/backups", json!(null)).await;
+    assert_eq!(response.status, StatusCode::UNAUTHORIZED);
+    let mut backups = Vec::new();
+    for _ in 0..3 {
+        let response = app.admin("POST", "/v1/admin/backups", json!(null)).await;
+        assert_eq!(response.status, StatusCode::OK);
+        backups.push(response.json());
+    }
+    assert_fields(&backups[0], &["fileName", "sizeBytes", "numDeleted"]);
+    assert_eq!(backups[1]["numDeleted"], 0);
+    assert_eq!(backups[2]["numDeleted"], 1);
+
+    let mut files: Vec<_> = std::fs::read_dir(&directory)
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+        .collect();
+    files.sort();
+    assert_eq!(
+        json!(files),
+        json!([backups[1]["fileName"], backups[2]["fileName"]])
+    );
+    let backup =
+        rusqlite::Connection::open(directory.join(backups[2]["fileName"].as_str().unwrap()))
+            .unwrap();
+    let num_attempts: u32 = backup
+        .query_row("SELECT COUNT(*) FROM puzzle_attempts", [], |row| row.get(0))
+        .unwrap();
+    assert_eq!(num_attempts, 1);
+    std::fs::remove_dir_all(&directory).unwrap();
+}
+
+#[tokio::test]
+async fn read_only_mode_keeps_serving_puzzles_but_rejects_attempts() {
+    let app = TestApp::new().await;
+    let response = app
+        .admin(
+            "POST",
+            "/v1/admin/read-only",
+            json!({"readOnly": true, "message": "Back in 10 minutes"}),
+        )
+        .await;
+    assert_eq!(
+        response.json(),
+        json!({"readOnly": true, "message": "Back in 10 minutes"})
+    );
+
+    let response = app.solve(1, "alice", true).await;
+    assert_eq!(response.status, StatusCode::SERVICE_UNAVAILABLE);
+    assert_eq!(response.json()["message"], "Back in 10 minutes");
+    let response = app
+        .post("/puzzles/1", json!({"username": "alice", "solved": false}))
+        .await;
+    assert_eq!(response.status, StatusCode::SERVICE_UNAVAILABLE);
+    let response = app
+        .post("/v1/users/bob/follow", json!({"username": "alice"}))
+        .await;
+    assert_eq!(response.status, StatusCode::SERVICE_UNAVAILABLE);
+    assert_eq!(app.count("SELECT COUNT(*) FROM puzzle_attempts"), 0);
+    assert_eq!(app.get("/v1/puzzles/1").await.status, StatusCode::OK);
+    assert_eq!(app.get("/v1/puzzles/1/rating").await.status, StatusCode::OK);
+
+    // Admin endpoints keep working, with or without the version prefix
+    let status = app
+        .admin("GET", "/admin/read-only", json!(null))
+        .await
+        .json();
+    assert_eq!(status["readOnly"], true);
+    app.admin("POST", "/v1/admin/read-only", json!({"readOnly": false}))
+        .await;
+    let response = app.solve(1, "alice", true).await;
+    assert_eq!(response.status, StatusCode::OK);
+}
+
+#[tokio::test]
+async fn webhooks_are_signed_and_failed_deliveries_are_retried() {
+    let app = TestApp::new().await;
+    let (sender, mut received) = tokio::sync::mpsc::unbounded_channel();
+    let receiver = axum::Router::new().route(
+        "/hook",
+        axum::routing::post(move |headers: header::HeaderMap, body: String| async move {
+            sender.send((headers, body)).unwrap();
+            StatusCode::NO_CONTENT
+        }),
+    );
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("http://{}/hook", listener.local_addr().unwrap());
+    tokio::spawn(async move { axum::serve(listener, receiver).await.unwrap() });
+    // Nothing listens on a port that was just released
+    let dead_url = {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        format!("http://{}/hook", listener.local_addr().unwrap())
+    };
+
+    let response = app
+        .admin(
+            "POST",
+            "/v1/admin/webhooks",
+            json!({"url": "ftp://example.com", "events": ["reportFiled"]}),
+        )
+        .await;
+    assert_eq!(response.status, StatusCode::BAD_REQUEST);
+    let response = app
+        .admin(
+            "POST",
+            "/v1/admin/webhooks",
+            json!({"url": url, "events": ["reportFiled", "dailyPuzzle"]}),
+        )
+        .await;
+    assert_eq!(response.status, StatusCode::OK);
+    let webhook = response.json();
+    let secret = webhook["secret"].as_str().unwrap().to_string();
+    let response = app
+        .admin(
+            "POST",
+            "/v1/admin/webhooks",
+            json!({"url": dead_url, "events": ["reportFiled"]}),
+        )
+        .await;
+    let dead_id = response.json()["id"].as_i64().unwrap();
+    let webhooks = app
+        .admin("GET", "/v1/admin/webhooks", json!(null))
+        .await
+        .json();
+    assert_eq!(webhooks.as_array().unwrap().len(), 2);
+    assert_eq!(webhooks[0]["secret"], json!(null));
+
+    app.post(
+        "/v1/puzzles/2/reports",
+        json!({"username": "alice", "reason": "Two winning moves"}),
+    )
+    .await;
+    let response = app
+        .admin("POST", "/v1/admin/jobs/webhook-deliveries/run", json!(null))
+        .await;
+    assert_eq!(response.status, StatusCode::OK);
+
+    let (headers, body) = received.try_recv().unwrap();
+    let signature = headers["x-webhook-signature"].to_str().unwrap();
+    let signature = signature.strip_prefix("sha256=").unwrap();
+    let signature: Vec<u8> = (0..signature.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&signature[i..i + 2], 16).unwrap())
+        .collect();
+    let key = ring::hmac::Key::new(ring::hmac::HMAC_SHA256, secret.as_bytes());
+    ring::hmac::verify(&key, body.as_bytes(), &signature).unwrap();
+    let delivery: serde_json::Value = serde_json::from_str(&body).unwrap();
+    assert_fields(&delivery, &["id", "event", "timestampSeconds", "data"]);
+    assert_eq!(delivery["event"], "reportFiled");
+    assert_eq!(delivery["data"]["puzzleId"], 2);
+    assert!(received.try_recv().is_err());
+
+    let (num_attempts, last_error): (u32, Option<String>) = app
+        .db()
+        .query_row(
+            "SELECT num_attempts, last_error FROM webhook_deliveries WHERE webhook_id = ?1",
+            [dead_id],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        )
+        .unwrap();
+    assert_eq!(num_attempts, 1);
+    assert!(last_error.is_some());
+    // Not due again for a minute
+    app.admin("POST", "/v1/admin/jobs/webhook-deliveries/run", json!(null))
+        .await;
+    assert_eq!(
+        app.count(&format!(
+            "SELECT num_attempts FROM webhook_deliveries WHERE webhook_id = {dead_id}"
+        )),
+        1
+    );
+
+    let response = app
+        .admin(
+            "DELETE",
+            &format!("/v1/admin/webhooks/{dead_id}"),
+            json!(null),
+        )
+        .await;
+    assert_eq!(response.status, StatusCode::NO_CONTENT);
+    assert_eq!(app.count("SELECT COUNT(*) FROM webhook_deliveries"), 1);
+    let response = app
+        .admin(
+            "DELETE",
+            &format!("/v1/admin/webhooks/{dead_id}"),
+            json!(null),
+        )
+        .await;
+    assert_eq!(response.status, StatusCode::NOT_FOUND);
+}
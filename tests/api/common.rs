@@ -0,0 +1,391 @@
+use std::{
+    net::SocketAddr,
+    path::Path,
+    sync::{Arc, Mutex, OnceLock},
+};
+
+use axum::{
+    Router,
+    body::Body,
+    http::{HeaderMap, Request, StatusCode, header},
+    response::Response,
+};
+use futures_util::StreamExt;
+use rusqlite::Connection;
+use serde_json::Value;
+use tak_tactics_backend::{AppState, config, db, fixtures, grpc, mail, storage, telemetry};
+use tokio::sync::MutexGuard;
+use tokio_tungstenite::{MaybeTlsStream, WebSocketStream, tungstenite::Message};
+use tower::ServiceExt;
+
+pub const ADMIN_TOKEN: &str = "test-admin-token";
+
+// Every test shares one in-memory database, which lives as long as a connection to it is open.
+// Tests take turns through `TEST_LOCK`, and each one starts from an empty database with the puzzles in `FIXTURE`
+const DB_PATH: &str = "file:tak-tactics-tests?mode=memory&cache=shared";
+
+// Puzzles 1 to 5 are published, and 6 isn't
+const FIXTURE: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/api.json");
+
+static TEST_LOCK: tokio::sync::Mutex<()> = tokio::sync::Mutex::const_new(());
+static KEEP_ALIVE: OnceLock<Mutex<Connection>> = OnceLock::new();
+static METRICS: OnceLock<metrics_exporter_prometheus::PrometheusHandle> = OnceLock::new();
+
+// The line that solves every seeded puzzle
+pub const SOLUTION: [&str; 2] = ["d4-", "3e3+12"];
+
+pub type TestSocket = WebSocketStream<MaybeTlsStream<tokio::net::TcpStream>>;
+
+pub struct TestApp {
+    router: Router,
+    state: AppState,
+    // Emails the app sent, instead of sending them
+    pub outbox: Arc<mail::Outbox>,
+    _guard: MutexGuard<'static, ()>,
+}
+
+pub struct TestResponse {
+    pub status: StatusCode,
+    pub headers: HeaderMap,
+    pub body: Vec<u8>,
+}
+
+impl TestResponse {
+    pub fn json(&self) -> Value {
+        serde_json::from_slice(&self.body).unwrap_or_else(|e| {
+            panic!(
+                "Response is not JSON ({e}): {}",
+                String::from_utf8_lossy(&self.body)
+            )
+        })
+    }
+
+    pub fn text(&self) -> String {
+        String::from_utf8_lossy(&self.body).into_owned()
+    }
+}
+
+impl TestApp {
+    pub async fn new() -> Self {
+        Self::with_config(config::Config::default()).await
+    }
+
+    pub async fn with_config(config: config::Config) -> Self {
+        let guard = TEST_LOCK.lock().await;
+        let metrics = METRICS.get_or_init(|| {
+            // SAFETY: Runs once, before any test has started a server that could read the environment
+            unsafe { std::env::set_var("ADMIN_TOKEN", ADMIN_TOKEN) };
+            db::configure(config::DatabaseConfig {
+                path: DB_PATH.to_string(),
+                ..Default::default()
+            });
+            KEEP_ALIVE.get_or_init(|| Mutex::new(db::open().unwrap()));
+            storage::init_db_tables().unwrap();
+            telemetry::install_recorder().unwrap()
+        });
+        reset_database();
+
+        let mut state = AppState::new(config, metrics.clone()).unwrap();
+        let outbox = Arc::new(mail::Outbox::default());
+        state.mail = outbox.clone();
+        Self {
+            router: tak_tactics_backend::app(state.clone()).unwrap(),
+            state,
+            outbox,
+            _guard: guard,
+        }
+    }
+
+    // Like `new`, but with puzzles, attempts and ratings stored in the Postgres database at `TEST_POSTGRES_URL`.
+    // The database is emptied first, and given the puzzles in `FIXTURE`
+    #[cfg(feature = "postgres")]
+    pub async fn with_postgres() -> Self {
+        let mut app = Self::new().await;
+        app.state.store = storage::open_postgres(&postgres_url()).await.unwrap();
+        app.router = tak_tactics_backend::app(app.state.clone()).unwrap();
+        let client = app.postgres().await;
+        client
+            .batch_execute(
+                "TRUNCATE puzzles, users, puzzle_attempts, idempotency_keys, retryable_attempts RESTART IDENTITY",
+            )
+            .await
+            .unwrap();
+        let db_conn = app.db();
+        let mut stmt = db_conn
+            .prepare(
+                "SELECT id, root_tps, defender_start_move, size, komi, player_white, player_black, solution,
+                    target_time_seconds, playtak_game_id, published FROM puzzles",
+            )
+            .unwrap();
+        let mut rows = stmt.query([]).unwrap();
+        while let Some(row) = rows.next().unwrap() {
+            client
+                .execute(
+                    "INSERT INTO puzzles (id, root_tps, defender_start_move, size, komi, player_white, player_black,
+                        solution, target_time_seconds, playtak_game_id, published)
+                    VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11)",
+                    &[
+                        &row.get::<_, i64>(0).unwrap(),
+                        &row.get::<_, String>(1).unwrap(),
+                        &row.get::<_, String>(2).unwrap(),
+                        &row.get::<_, i64>(3).unwrap(),
+                        &row.get::<_, String>(4).unwrap(),
+                        &row.get::<_, String>(5).unwrap(),
+                        &row.get::<_, String>(6).unwrap(),
+                        &row.get::<_, String>(7).unwrap(),
+                        &row.get::<_, i64>(8).unwrap(),
+                        &row.get::<_, i64>(9).unwrap(),
+                        &row.get::<_, bool>(10).unwrap(),
+                    ],
+                )
+                .await
+                .unwrap();
+        }
+        app
+    }
+
+    // A separate connection to the database from `with_postgres`
+    #[cfg(feature = "postgres")]
+    pub async fn postgres(&self) -> tokio_postgres::Client {
+        let (client, connection) = tokio_postgres::connect(&postgres_url(), tokio_postgres::NoTls)
+            .await
+            .unwrap();
+        tokio::spawn(connection);
+        client
+    }
+
+    // A client for the gRPC API, served on a free port until the test ends
+    pub async fn grpc(&self) -> grpc::PuzzlesClient<tonic::transport::Channel> {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        let state = self.state.clone();
+        tokio::spawn(async move {
+            let (_shutdown_sender, shutdown) = tokio::sync::watch::channel(false);
+            grpc::serve(state, listener, shutdown).await
+        });
+        grpc::PuzzlesClient::connect(format!("http://{address}"))
+            .await
+            .unwrap()
+    }
+
+    // A WebSocket to the app, served on a free port until the test ends
+    pub async fn websocket(&self, uri: &str) -> TestSocket {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        let router = self.router.clone();
+        tokio::spawn(async move {
+            axum::serve(
+                listener,
+                router.into_make_service_with_connect_info::<SocketAddr>(),
+            )
+            .await
+        });
+        tokio_tungstenite::connect_async(format!("ws://{address}{uri}"))
+            .await
+            .unwrap()
+            .0
+    }
+
+    pub fn db(&self) -> Connection {
+        db::open().unwrap()
+    }
+
+    // Send a request without reading the body, for streaming responses
+    pub async fn open(&self, request: Request<Body>) -> Response {
+        self.router.clone().oneshot(request).await.unwrap()
+    }
+
+    pub async fn request(&self, request: Request<Body>) -> TestResponse {
+        let response = self.open(request).await;
+        let status = response.status();
+        let headers = response.headers().clone();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap()
+            .to_vec();
+        TestResponse {
+            status,
+            headers,
+            body,
+        }
+    }
+
+    pub async fn get(&self, uri: &str) -> TestResponse {
+        self.request(Request::get(uri).body(Body::empty()).unwrap())
+            .await
+    }
+
+    pub async fn post(&self, uri: &str, body: Value) -> TestResponse {
+        self.request(json_request("POST", uri, body)).await
+    }
+
+    // Send a request with the admin token
+    pub async fn admin(&self, method: &str, uri: &str, body: Value) -> TestResponse {
+        let mut request = json_request(method, uri, body);
+        request.headers_mut().insert(
+            header::AUTHORIZATION,
+            format!("Bearer {ADMIN_TOKEN}").parse().unwrap(),
+        );
+        self.request(request).await
+    }
+
+    // Send a request with a user's access token, from `log_in`
+    pub async fn as_user(
+        &self,
+        access_token: &str,
+        method: &str,
+        uri: &str,
+        body: Value,
+    ) -> TestResponse {
+        let mut request = json_request(method, uri, body);
+        request.headers_mut().insert(
+            header::AUTHORIZATION,
+            format!("Bearer {access_token}").parse().unwrap(),
+        );
+        self.request(request).await
+    }
+
+    // Log in through the link emailed to this address, and return the new session's tokens.
+    // Needs `auth.secret` in the config
+    pub async fn log_in(&self, email: &str, username: &str) -> Value {
+        let link = self
+            .post(
+                "/v1/auth/magic-link",
+                serde_json::json!({"email": email, "username": username}),
+            )
+            .await;
+        assert_eq!(link.status, StatusCode::ACCEPTED);
+        let sent = self.outbox.sent();
+        let token = sent
+            .last()
+            .unwrap()
+            .text
+            .split_whitespace()
+            .find_map(|word| word.split_once("token="))
+            .unwrap()
+            .1
+            .to_string();
+        let login = self
+            .post(
+                "/v1/auth/magic-link/verify",
+                serde_json::json!({"token": token}),
+            )
+            .await;
+        assert_eq!(login.status, StatusCode::OK);
+        login.json()
+    }
+
+    pub async fn delete(&self, uri: &str) -> TestResponse {
+        self.request(Request::delete(uri).body(Body::empty()).unwrap())
+            .await
+    }
+
+    // Submit an attempt at one of the seeded puzzles
+    pub async fn solve(&self, puzzle_id: u32, username: &str, solved: bool) -> TestResponse {
+        let solution: &[&str] = if solved { &SOLUTION } else { &[] };
+        self.post(
+            &format!("/v1/puzzles/{puzzle_id}"),
+            serde_json::json!({
+                "id": puzzle_id,
+                "username": username,
+                "solved": solved,
+                "solution": solution,
+                "solveTimeSeconds": 30,
+            }),
+        )
+        .await
+    }
+
+    // Add a puzzle set with these puzzles, in order
+    pub fn add_puzzle_set(&self, name: &str, puzzle_ids: &[u32]) -> u64 {
+        let db_conn = self.db();
+        db_conn
+            .execute(
+                "INSERT INTO puzzle_sets (name, description) VALUES (?1, 'A test set')",
+                [name],
+            )
+            .unwrap();
+        let set_id = db_conn.last_insert_rowid() as u64;
+        for (position, puzzle_id) in puzzle_ids.iter().enumerate() {
+            db_conn
+                .execute(
+                    "INSERT INTO puzzle_set_entries (set_id, puzzle_id, position) VALUES (?1, ?2, ?3)",
+                    rusqlite::params![set_id, puzzle_id, position],
+                )
+                .unwrap();
+        }
+        set_id
+    }
+
+    pub fn count(&self, sql: &str) -> u32 {
+        self.db().query_row(sql, [], |row| row.get(0)).unwrap()
+    }
+}
+
+#[cfg(feature = "postgres")]
+fn postgres_url() -> String {
+    std::env::var("TEST_POSTGRES_URL")
+        .expect("The `postgres` feature's tests need a database to empty in TEST_POSTGRES_URL")
+}
+
+pub fn json_request(method: &str, uri: &str, body: Value) -> Request<Body> {
+    Request::builder()
+        .method(method)
+        .uri(uri)
+        .header(header::CONTENT_TYPE, "application/json")
+        .body(Body::from(body.to_string()))
+        .unwrap()
+}
+
+// Skip the socket's JSON messages until one with this `type`
+pub async fn next_of_type(socket: &mut TestSocket, message_type: &str) -> Value {
+    loop {
+        let Some(Ok(Message::Text(text))) = socket.next().await else {
+            panic!("The socket closed before a {message_type:?} message");
+        };
+        let message: Value = serde_json::from_str(&text).unwrap();
+        if message["type"] == message_type {
+            return message;
+        }
+    }
+}
+
+// Check that a JSON object has exactly these fields
+#[track_caller]
+pub fn assert_fields(value: &Value, fields: &[&str]) {
+    let object = value
+        .as_object()
+        .unwrap_or_else(|| panic!("Expected an object, got {value}"));
+    let mut actual: Vec<&str> = object.keys().map(String::as_str).collect();
+    let mut expected = fields.to_vec();
+    actual.sort();
+    expected.sort();
+    assert_eq!(actual, expected, "Unexpected fields in {value}");
+}
+
+fn reset_database() {
+    let db_conn = KEEP_ALIVE.get().unwrap().lock().unwrap();
+    db_conn.pragma_update(None, "foreign_keys", false).unwrap();
+    let tables: Vec<String> = db_conn
+        // Full-text indexes are kept in sync by triggers, so they're left alone
+        .prepare(
+            "SELECT name FROM sqlite_master WHERE type = 'table' AND sql NOT LIKE 'CREATE VIRTUAL TABLE%'
+            AND name NOT IN (SELECT name FROM pragma_table_list WHERE type = 'shadow')",
+        )
+        .unwrap()
+        .query_map([], |row| row.get(0))
+        .unwrap()
+        .collect::<Result<_, _>>()
+        .unwrap();
+    for table in tables {
+        db_conn
+            .execute(&format!("DELETE FROM \"{table}\""), [])
+            .unwrap();
+    }
+    db_conn.pragma_update(None, "foreign_keys", true).unwrap();
+    drop(db_conn);
+    // Puts back the rows that are inserted at startup, like the default achievements
+    storage::init_db_tables().unwrap();
+    let fixture = fixtures::load(Path::new(FIXTURE)).unwrap();
+    assert!(fixtures::seed(&mut db::open().unwrap(), &fixture).unwrap());
+}
@@ -0,0 +1,94 @@
+use axum::http::StatusCode;
+use serde_json::json;
+
+use crate::common::{TestApp, assert_fields};
+
+#[tokio::test]
+async fn experiments_are_admin_only() {
+    let app = TestApp::new().await;
+    let response = app
+        .post("/v1/admin/experiments/target-time/start", json!({}))
+        .await;
+    assert_eq!(response.status, StatusCode::UNAUTHORIZED);
+    let response = app
+        .admin("POST", "/v1/admin/experiments/unknown/start", json!({}))
+        .await;
+    assert_eq!(response.status, StatusCode::NOT_FOUND);
+    assert_eq!(app.count("SELECT COUNT(*) FROM experiments"), 0);
+}
+
+#[tokio::test]
+async fn attempts_record_the_variant_that_was_used() {
+    let app = TestApp::new().await;
+    let experiment = app
+        .admin("POST", "/v1/admin/experiments/target-time/start", json!({}))
+        .await
+        .json();
+    assert_fields(
+        &experiment,
+        &[
+            "name",
+            "running",
+            "startedSeconds",
+            "stoppedSeconds",
+            "variants",
+        ],
+    );
+    assert_eq!(experiment["running"], true);
+
+    let usernames: Vec<String> = (0..10).map(|i| format!("user{i}")).collect();
+    for username in &usernames {
+        let result = app.solve(1, username, false).await.json();
+        let variant: String = app
+            .db()
+            .query_row(
+                "SELECT variant FROM attempt_variants WHERE username = ?1 AND experiment = 'target-time'",
+                [username],
+                |row| row.get(0),
+            )
+            .unwrap();
+        // New users are rated below the puzzle, and stay below it since they all fail,
+        // so scaling gives them more than its 120 seconds
+        match variant.as_str() {
+            "unscaled" => assert_eq!(result["targetTimeSeconds"], 120),
+            "scaled" => assert!(result["targetTimeSeconds"].as_u64().unwrap() > 120),
+            _ => panic!("Unknown variant {variant}"),
+        }
+    }
+
+    let results = app
+        .admin("GET", "/v1/admin/experiments/target-time", json!({}))
+        .await
+        .json();
+    let variants = results["variants"].as_array().unwrap();
+    assert_eq!(variants.len(), 2);
+    assert_fields(
+        &variants[0],
+        &[
+            "variant",
+            "numUsers",
+            "numAttempts",
+            "numSolved",
+            "solveRate",
+            "averageSolveTimeSeconds",
+        ],
+    );
+    let num_attempts: u64 = variants
+        .iter()
+        .map(|variant| variant["numAttempts"].as_u64().unwrap())
+        .sum();
+    assert_eq!(num_attempts, 10);
+
+    let stopped = app
+        .admin("POST", "/v1/admin/experiments/target-time/stop", json!({}))
+        .await
+        .json();
+    assert_eq!(stopped["running"], false);
+    app.solve(2, "user0", true).await;
+    assert_eq!(app.count("SELECT COUNT(*) FROM attempt_variants"), 10);
+    let listed = app
+        .admin("GET", "/v1/admin/experiments", json!({}))
+        .await
+        .json();
+    assert_eq!(listed[0]["name"], "target-time");
+}
@@ -0,0 +1,155 @@
+use std::path::Path;
+
+use serde_json::json;
+use tak_tactics_backend::{fixtures, importers, storage};
+
+use crate::common::TestApp;
+
+#[tokio::test]
+async fn fixtures_are_only_loaded_into_empty_databases() {
+    let app = TestApp::new().await;
+    let fixture: fixtures::Fixture =
+        serde_json::from_value(json!({"users": [{"username": "alice"}]})).unwrap();
+    assert!(!fixtures::seed(&mut app.db(), &fixture).unwrap());
+    assert_eq!(app.count("SELECT COUNT(*) FROM users"), 0);
+}
+
+#[tokio::test]
+async fn the_sample_fixture_can_be_loaded() {
+    let app = TestApp::new().await;
+    app.db().execute("DELETE FROM puzzles", []).unwrap();
+    let path = Path::new(concat!(env!("CARGO_MANIFEST_DIR"), "/fixtures/sample.json"));
+    let fixture = fixtures::load(path).unwrap();
+    assert!(fixtures::seed(&mut app.db(), &fixture).unwrap());
+
+    assert_eq!(app.count("SELECT COUNT(*) FROM puzzles"), 3);
+    assert_eq!(
+        app.count("SELECT COUNT(*) FROM puzzles WHERE published = 1"),
+        2
+    );
+    let id = app.count("SELECT id FROM puzzles WHERE playtak_game_id = 491458");
+    let puzzle = app.get(&format!("/v1/puzzles/{id}")).await.json();
+    assert_eq!(puzzle["solution"], json!(["d4-", "3e3+12", "*"]));
+
+    // Users without ratings get the default
+    let leaderboard = app.get("/v1/leaderboard").await.json();
+    assert_eq!(leaderboard[0]["username"], "alice");
+    assert_eq!(leaderboard[0]["rating"], 1620.0);
+    assert_eq!(leaderboard[1]["username"], "bob");
+    assert_eq!(leaderboard[1]["rating"], 1500.0);
+}
+
+#[tokio::test]
+async fn fixtures_with_invalid_usernames_are_not_loaded() {
+    let app = TestApp::new().await;
+    app.db().execute("DELETE FROM puzzles", []).unwrap();
+    let fixture: fixtures::Fixture = serde_json::from_value(json!({
+        "users": [{"username": "alice"}, {"username": "not valid"}],
+    }))
+    .unwrap();
+    assert!(fixtures::seed(&mut app.db(), &fixture).is_err());
+    assert_eq!(app.count("SELECT COUNT(*) FROM users"), 0);
+}
+
+#[tokio::test]
+async fn exports_can_be_imported_into_an_empty_database() {
+    let app = TestApp::new().await;
+    app.solve(1, "alice", true).await;
+    let exported = fixtures::export(&app.db()).unwrap();
+    assert_eq!(exported.puzzles.len(), 6);
+    assert_eq!(exported.users.len(), 1);
+    assert!(fixtures::import(&mut app.db(), &exported).is_err());
+
+    let db_conn = app.db();
+    for table in ["puzzle_attempts", "puzzles", "users"] {
+        db_conn
+            .execute(&format!("DELETE FROM {table}"), [])
+            .unwrap();
+    }
+    fixtures::import(&mut app.db(), &exported).unwrap();
+    let reexported = fixtures::export(&app.db()).unwrap();
+    assert_eq!(
+        serde_json::to_value(&reexported).unwrap(),
+        serde_json::to_value(&exported).unwrap()
+    );
+}
+
+#[tokio::test]
+async fn recomputing_ratings_replays_rated_attempts() {
+    let app = TestApp::new().await;
+    app.solve(1, "alice", true).await;
+    app.solve(2, "alice", false).await;
+    app.solve(1, "bob", false).await;
+    let leaderboard = app.get("/v1/leaderboard").await.json();
+    let history = app.get("/v1/users/alice/rating-history").await.json();
+    let puzzle_history = app.get("/v1/puzzles/1/rating-history").await.json();
+
+    app.db()
+        .execute("UPDATE users SET rating = 1000, deviation = 50", [])
+        .unwrap();
+    let num_changed = storage::recompute_user_ratings(&mut app.db()).unwrap();
+    assert_eq!(num_changed, 2);
+    assert_eq!(app.get("/v1/leaderboard").await.json(), leaderboard);
+    assert_eq!(
+        app.get("/v1/users/alice/rating-history").await.json(),
+        history
+    );
+    assert_eq!(
+        app.get("/v1/puzzles/1/rating-history").await.json(),
+        puzzle_history
+    );
+}
+
+#[tokio::test]
+async fn puzzles_from_other_formats_are_checked_and_duplicates_skipped() {
+    let app = TestApp::new().await;
+    let seeded_tps: String = app
+        .db()
+        .query_row("SELECT root_tps FROM puzzles WHERE id = 1", [], |row| {
+            row.get(0)
+        })
+        .unwrap();
+    let file = json!({"puzzles": [
+        {"tps": seeded_tps, "setupMove": "5e3<", "solution": "d4- 3e3+12"},
+        {"tps": "x5/x5/x5/x5/x5 1 1", "lastMove": "a1", "solution": ["e5"], "white": "alice", "gameId": 7, "komi": 2},
+        {"tps": "x5/x5/x5/x5/x5 1 1", "setupMove": "a1'", "solution": ["e5"]},
+    ]});
+    let puzzles =
+        importers::parse(&file.to_string(), importers::Format::PuzzletakJson, false).unwrap();
+    let summary = importers::import(&mut app.db(), puzzles).unwrap();
+    assert_eq!(summary.num_imported, 1);
+    assert_eq!(summary.num_duplicates, 2);
+    let (size, komi, solution, published): (u32, String, String, bool) = app
+        .db()
+        .query_row(
+            "SELECT size, komi, solution, published FROM puzzles WHERE playtak_game_id = 7",
+            [],
+            |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?)),
+        )
+        .unwrap();
+    assert_eq!((size, komi.as_str(), solution.as_str()), (5, "2", "e5 *"));
+    assert!(!published);
+
+    let csv = "TPS,Setup move,Solution,White,Black,Game ID\n\
+        x4/x4/x4/x4 1 1,d4,a1 b1,bob,carol,8\n";
+    let puzzles = importers::parse(csv, importers::Format::Csv, true).unwrap();
+    assert_eq!(puzzles[0].size, 4);
+    assert_eq!(puzzles[0].player_black, "carol");
+    assert_eq!(
+        importers::import(&mut app.db(), puzzles)
+            .unwrap()
+            .num_imported,
+        1
+    );
+    assert_eq!(
+        app.count("SELECT COUNT(*) FROM puzzles WHERE published = 1"),
+        6
+    );
+
+    // The defender can't place on a stack
+    let csv = "tps,setup_move,solution\nx4/x4/x4/x4 1 1,d4,a1\n\"1,x3/x4/x4/x4 2 2\",a4,b2\n";
+    let Err(error) = importers::parse(csv, importers::Format::Csv, false) else {
+        panic!("Imported an illegal setup move");
+    };
+    assert!(format!("{error:#}").contains("Puzzle 2"), "{error:#}");
+}
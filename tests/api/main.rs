@@ -0,0 +1,13 @@
+// Tests for the whole API, with requests sent straight to the router.
+// A single test binary, so that every test can share the in-memory database, see `common.rs`
+mod admin;
+mod common;
+mod experiments;
+mod fixtures;
+#[cfg(feature = "postgres")]
+mod postgres;
+mod progress;
+mod puzzles;
+mod server;
+mod social;
+mod tournaments;
@@ -0,0 +1,154 @@
+use axum::http::StatusCode;
+use serde_json::json;
+
+use crate::common::{SOLUTION, TestApp, json_request};
+
+// Only built with the `postgres` feature, and run against the database in `TEST_POSTGRES_URL`
+
+#[tokio::test]
+async fn attempts_are_stored_and_rated_in_postgres() {
+    let app = TestApp::with_postgres().await;
+    let puzzle = app.get("/v1/puzzles?username=alice").await.json();
+    assert_eq!(puzzle["id"], 3);
+
+    let rating_before = app.get("/v1/puzzles/3/rating").await.json();
+    let result = app.solve(3, "alice", true).await.json();
+    assert_eq!(result["attemptNumber"], 1);
+    assert_eq!(result["rated"], true);
+    assert_eq!(result["ratingChange"]["oldRating"], 1500.0);
+    let new_rating = result["ratingChange"]["newRating"].as_f64().unwrap();
+    assert!(new_rating > 1500.0);
+    let rating_after = app.get("/v1/puzzles/3/rating").await.json();
+    assert!(rating_after.as_f64().unwrap() < rating_before.as_f64().unwrap());
+    assert_eq!(result["ratingChange"]["puzzleRating"], rating_after);
+    let ratings = app.get("/v1/puzzles/ratings").await.json();
+    let ids: Vec<&String> = ratings.as_object().unwrap().keys().collect();
+    assert_eq!(ids, ["1", "2", "3", "4", "5"]);
+    assert_eq!(ratings["3"], rating_after);
+
+    let again = app.solve(3, "alice", true).await.json();
+    assert_eq!(again["attemptNumber"], 2);
+    assert_eq!(again["rated"], false);
+
+    let client = app.postgres().await;
+    let row = client
+        .query_one(
+            "SELECT rating, (SELECT COUNT(*) FROM puzzle_attempts), (SELECT COUNT(*) FROM rated_attempts)
+            FROM users WHERE username = 'alice'",
+            &[],
+        )
+        .await
+        .unwrap();
+    assert_eq!(row.get::<_, f64>(0), new_rating);
+    assert_eq!(row.get::<_, i64>(1), 2);
+    assert_eq!(row.get::<_, i64>(2), 1);
+    assert_eq!(app.count("SELECT COUNT(*) FROM puzzle_attempts"), 0);
+
+    // The next puzzle is one alice hasn't attempted yet
+    let puzzle = app.get("/v1/puzzles?username=alice").await.json();
+    assert_ne!(puzzle["id"], 3);
+}
+
+#[tokio::test]
+async fn unpublished_puzzles_are_not_served_from_postgres() {
+    let app = TestApp::with_postgres().await;
+    assert_eq!(app.get("/v1/puzzles/6").await.status, StatusCode::NOT_FOUND);
+    assert_eq!(
+        app.solve(6, "alice", true).await.status,
+        StatusCode::NOT_FOUND
+    );
+    for id in [1, 2, 3, 4, 5] {
+        app.solve(id, "alice", false).await;
+    }
+    // Only the published puzzles are left to review
+    let puzzle = app.get("/v1/puzzles?username=alice").await.json();
+    assert_eq!(puzzle["review"], true);
+    assert_ne!(puzzle["id"], 6);
+}
+
+#[tokio::test]
+async fn failed_attempts_can_be_retried_once_in_postgres() {
+    let app = TestApp::with_postgres().await;
+    let retry = || {
+        app.post(
+            "/v1/puzzles/1",
+            json!({
+                "id": 1,
+                "username": "alice",
+                "solved": true,
+                "solution": SOLUTION,
+                "solveTimeSeconds": 30,
+                "retry": true,
+            }),
+        )
+    };
+    assert_eq!(retry().await.status, StatusCode::BAD_REQUEST);
+
+    app.solve(1, "bob", true).await;
+    let failed = app.solve(1, "alice", false).await.json();
+    let retried = retry().await.json();
+    assert_eq!(retried["attemptNumber"], 2);
+    assert_eq!(retried["retry"]["retryOf"], 1);
+    assert_eq!(retried["retry"]["score"], 0.5);
+    let change = &retried["retry"]["ratingChange"];
+    assert_eq!(change["oldRating"], failed["ratingChange"]["newRating"]);
+    let rating = change["newRating"].as_f64().unwrap();
+    assert!(rating > failed["ratingChange"]["newRating"].as_f64().unwrap());
+    assert!(rating < failed["ratingChange"]["oldRating"].as_f64().unwrap());
+    assert_eq!(retry().await.status, StatusCode::BAD_REQUEST);
+
+    let solved_on_retry: bool = app
+        .postgres()
+        .await
+        .query_one(
+            "SELECT solved_on_retry FROM rated_attempts WHERE username = 'alice'",
+            &[],
+        )
+        .await
+        .unwrap()
+        .get(0);
+    assert!(solved_on_retry);
+}
+
+#[tokio::test]
+async fn idempotency_keys_are_kept_in_postgres() {
+    let app = TestApp::with_postgres().await;
+    let keyed_request = |uri: &str, id: u32| {
+        let mut request = json_request(
+            "POST",
+            uri,
+            json!({
+                "id": id,
+                "username": "alice",
+                "solved": true,
+                "solution": SOLUTION,
+                "solveTimeSeconds": 30,
+            }),
+        );
+        request
+            .headers_mut()
+            .insert("idempotency-key", "retry-1".parse().unwrap());
+        request
+    };
+
+    let first = app.request(keyed_request("/v1/puzzles/3", 3)).await;
+    let repeated = app.request(keyed_request("/v1/puzzles/3", 3)).await;
+    assert_eq!(repeated.status, StatusCode::OK);
+    assert_eq!(
+        first.json()["ratingChange"],
+        repeated.json()["ratingChange"]
+    );
+    assert_eq!(
+        app.request(keyed_request("/v1/puzzles/4", 4)).await.status,
+        StatusCode::CONFLICT
+    );
+
+    let num_attempts: i64 = app
+        .postgres()
+        .await
+        .query_one("SELECT COUNT(*) FROM puzzle_attempts", &[])
+        .await
+        .unwrap()
+        .get(0);
+    assert_eq!(num_attempts, 1);
+}
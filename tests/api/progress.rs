@@ -0,0 +1,402 @@
+use axum::http::{StatusCode, header};
+use serde_json::json;
+
+use crate::common::{TestApp, assert_fields};
+
+#[tokio::test]
+async fn puzzle_sets_are_served_in_order_with_progress() {
+    let app = TestApp::new().await;
+    let set_id = app.add_puzzle_set("Basics", &[4, 2]);
+
+    let sets = app.get("/v1/puzzle-sets").await.json();
+    assert_eq!(
+        sets,
+        json!([{"id": set_id, "name": "Basics", "description": "A test set", "numPuzzles": 2}])
+    );
+
+    let next = app
+        .get(&format!("/v1/puzzle-sets/{set_id}/next?username=alice"))
+        .await;
+    assert_eq!(next.json()["id"], 4);
+    app.solve(4, "alice", true).await;
+    let next = app
+        .get(&format!("/v1/puzzle-sets/{set_id}/next?username=alice"))
+        .await;
+    assert_eq!(next.json()["id"], 2);
+    app.solve(2, "alice", false).await;
+    let next = app
+        .get(&format!("/v1/puzzle-sets/{set_id}/next?username=alice"))
+        .await;
+    assert_eq!(next.status, StatusCode::NOT_FOUND);
+
+    let progress = app
+        .get(&format!("/v1/puzzle-sets/{set_id}/progress?username=alice"))
+        .await
+        .json();
+    assert_eq!(
+        progress,
+        json!({
+            "setId": set_id,
+            "username": "alice",
+            "numPuzzles": 2,
+            "numAttempted": 2,
+            "numSolved": 1,
+            "completed": true,
+        })
+    );
+}
+
+#[tokio::test]
+async fn unknown_puzzle_sets_are_not_found() {
+    let app = TestApp::new().await;
+    let response = app.get("/v1/puzzle-sets/99/progress?username=alice").await;
+    assert_eq!(response.status, StatusCode::NOT_FOUND);
+    let response = app.get("/v1/puzzle-sets/99/progress?username=").await;
+    assert_eq!(response.status, StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn campaign_chapters_unlock_once_the_previous_one_is_passed() {
+    let app = TestApp::new().await;
+    let first_set = app.add_puzzle_set("Chapter 1", &[1, 2]);
+    let second_set = app.add_puzzle_set("Chapter 2", &[4]);
+    app.db()
+        .execute(
+            "INSERT INTO campaign_chapters (set_id, position, required_solved) VALUES (?1, 1, 1), (?2, 2, NULL)",
+            [first_set, second_set],
+        )
+        .unwrap();
+
+    let campaign = app.get("/v1/campaign?username=alice").await.json();
+    let chapters = campaign.as_array().unwrap();
+    assert_eq!(chapters.len(), 2);
+    assert_fields(
+        &chapters[0],
+        &[
+            "id",
+            "setId",
+            "name",
+            "description",
+            "position",
+            "requiredSolved",
+            "numPuzzles",
+            "numAttempted",
+            "numSolved",
+            "unlocked",
+            "passed",
+        ],
+    );
+    assert_eq!(chapters[0]["unlocked"], true);
+    assert_eq!(chapters[1]["unlocked"], false);
+    let second_chapter = chapters[1]["id"].as_u64().unwrap();
+    let locked = app
+        .get(&format!(
+            "/v1/campaign/chapters/{second_chapter}/next?username=alice"
+        ))
+        .await;
+    assert_eq!(locked.status, StatusCode::FORBIDDEN);
+
+    app.solve(1, "alice", true).await;
+    assert_eq!(
+        app.count("SELECT COUNT(*) FROM campaign_unlocks WHERE username = 'alice'"),
+        2
+    );
+    let campaign = app.get("/v1/campaign?username=alice").await.json();
+    assert_eq!(campaign[0]["passed"], true);
+    assert_eq!(campaign[1]["unlocked"], true);
+    let next = app
+        .get(&format!(
+            "/v1/campaign/chapters/{second_chapter}/next?username=alice"
+        ))
+        .await;
+    assert_eq!(next.json()["id"], 4);
+}
+
+#[tokio::test]
+async fn achievements_are_awarded_after_attempts() {
+    let app = TestApp::new().await;
+    let earned = |achievements: serde_json::Value| -> Vec<String> {
+        achievements
+            .as_array()
+            .unwrap()
+            .iter()
+            .filter(|achievement| !achievement["earnedSeconds"].is_null())
+            .map(|achievement| achievement["id"].as_str().unwrap().to_string())
+            .collect()
+    };
+    let achievements = app.get("/v1/users/alice/achievements").await.json();
+    assert_eq!(achievements.as_array().unwrap().len(), 4);
+    assert_fields(
+        &achievements[0],
+        &["id", "name", "description", "earnedSeconds"],
+    );
+    assert!(earned(achievements).is_empty());
+
+    app.solve(3, "alice", true).await;
+    let achievements = app.get("/v1/users/alice/achievements").await.json();
+    assert_eq!(earned(achievements), ["first-solve"]);
+    assert_eq!(app.count("SELECT COUNT(*) FROM user_achievements"), 1);
+}
+
+#[tokio::test]
+async fn user_progress_covers_every_puzzle_set() {
+    let app = TestApp::new().await;
+    let set_id = app.add_puzzle_set("Basics", &[1, 2]);
+    app.solve(1, "alice", true).await;
+
+    let progress = app.get("/v1/users/alice/progress").await.json();
+    let progress = progress.as_array().unwrap();
+    assert_eq!(progress.len(), 1);
+    assert_fields(
+        &progress[0],
+        &[
+            "setId",
+            "name",
+            "chapterId",
+            "unlocked",
+            "numPuzzles",
+            "numAttempted",
+            "numSolved",
+            "completed",
+            "lastActivitySeconds",
+            "nextPuzzleId",
+        ],
+    );
+    assert_eq!(progress[0]["setId"], set_id);
+    assert_eq!(progress[0]["chapterId"], json!(null));
+    assert_eq!(progress[0]["numSolved"], 1);
+    assert_eq!(progress[0]["nextPuzzleId"], 2);
+}
+
+#[tokio::test]
+async fn puzzle_progress_counts_published_puzzles() {
+    let app = TestApp::new().await;
+    app.solve(1, "alice", true).await;
+    app.solve(2, "alice", false).await;
+
+    let progress = app.get("/v1/users/alice/puzzle-progress").await.json();
+    assert_eq!(
+        progress,
+        json!({
+            "username": "alice",
+            "numPublished": 5,
+            "numAttempted": 2,
+            "numSolved": 1,
+            "numRemaining": 3,
+            "bySize": [{
+                "size": 6,
+                "numPublished": 5,
+                "numAttempted": 2,
+                "numSolved": 1,
+                "numRemaining": 3,
+            }],
+        })
+    );
+    let progress = app
+        .get("/v1/users/alice/puzzle-progress?size=5")
+        .await
+        .json();
+    assert_eq!(progress["numPublished"], 0);
+
+    // Solving puzzle 1 made it easier than the unattempted puzzles, and failing puzzle 2 harder
+    let counts = |query: &str| {
+        let app = &app;
+        let uri = format!("/v1/users/alice/puzzle-progress?{query}");
+        async move {
+            let progress = app.get(&uri).await.json();
+            [
+                progress["numPublished"].clone(),
+                progress["numSolved"].clone(),
+                progress["numRemaining"].clone(),
+            ]
+        }
+    };
+    assert_eq!(counts("maxRating=1500").await, [1, 1, 0]);
+    assert_eq!(counts("minRating=1700&size=6").await, [1, 0, 0]);
+    assert_eq!(counts("minRating=1600&maxRating=1600").await, [3, 0, 3]);
+    let response = app
+        .get("/v1/users/alice/puzzle-progress?minRating=1600&maxRating=1400")
+        .await;
+    assert_eq!(response.status, StatusCode::BAD_REQUEST);
+    assert_eq!(response.json()["field"], "minRating");
+}
+
+#[tokio::test]
+async fn attempt_history_is_paginated_newest_first() {
+    let app = TestApp::new().await;
+    for puzzle_id in 1..=3 {
+        app.solve(puzzle_id, "alice", true).await;
+    }
+
+    let page = app.get("/v1/users/alice/attempts?limit=2").await.json();
+    assert_fields(&page, &["items", "nextCursor"]);
+    let items = page["items"].as_array().unwrap();
+    assert_eq!(items.len(), 2);
+    assert_fields(
+        &items[0],
+        &[
+            "id",
+            "puzzleId",
+            "attemptNumber",
+            "solved",
+            "solveTimeSeconds",
+            "practice",
+            "timestampSeconds",
+            "retryOf",
+            "solvedOnRetry",
+            "verdict",
+            "illegalMoveIndex",
+        ],
+    );
+    assert_eq!(items[0]["puzzleId"], 3);
+    assert_eq!(items[1]["puzzleId"], 2);
+
+    let cursor = page["nextCursor"].as_str().unwrap();
+    let page = app
+        .get(&format!("/v1/users/alice/attempts?limit=2&cursor={cursor}"))
+        .await
+        .json();
+    assert_eq!(page["items"].as_array().unwrap().len(), 1);
+    assert_eq!(page["items"][0]["puzzleId"], 1);
+    assert_eq!(page["nextCursor"], json!(null));
+
+    let response = app.get("/v1/users/alice/attempts?cursor=nonsense").await;
+    assert_eq!(response.status, StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn attempts_can_be_replayed() {
+    let app = TestApp::new().await;
+    app.post(
+        "/v1/puzzles/1",
+        json!({
+            "id": 1,
+            "username": "alice",
+            "solved": false,
+            "solution": ["d4-", "c3"],
+            "solveTimeSeconds": 12,
+            "moveTimesMs": [5000, 11500],
+        }),
+    )
+    .await;
+    app.solve(2, "alice", true).await;
+
+    let history = app.get("/v1/users/alice/attempts").await.json();
+    let failed_id = history["items"][1]["id"].as_i64().unwrap();
+    let replay = app
+        .get(&format!("/v1/attempts/{failed_id}/replay"))
+        .await
+        .json();
+    assert_fields(
+        &replay,
+        &[
+            "id",
+            "puzzleId",
+            "username",
+            "attemptNumber",
+            "solved",
+            "practice",
+            "solveTimeSeconds",
+            "timestampSeconds",
+            "size",
+            "komi",
+            "rootTPS",
+            "defenderStartMove",
+            "moves",
+            "solution",
+        ],
+    );
+    assert_eq!(replay["puzzleId"], 1);
+    assert_eq!(
+        replay["moves"],
+        json!([
+            {"ptnMove": "d4-", "correct": true, "elapsedMs": 5000},
+            {"ptnMove": "c3", "correct": false, "elapsedMs": 11500},
+        ])
+    );
+
+    // Attempts without move times still replay
+    let solved_id = history["items"][0]["id"].as_i64().unwrap();
+    let replay = app
+        .get(&format!("/v1/attempts/{solved_id}/replay"))
+        .await
+        .json();
+    assert_eq!(replay["moves"][1]["elapsedMs"], json!(null));
+    assert_eq!(replay["moves"][1]["correct"], true);
+
+    let response = app.get("/v1/attempts/999999/replay").await;
+    assert_eq!(response.status, StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn attempts_can_be_downloaded_as_ptn() {
+    let app = TestApp::new().await;
+    let response = app.get("/v1/users/alice/attempts.ptn").await;
+    assert_eq!(response.status, StatusCode::OK);
+    assert_eq!(response.text(), "");
+
+    app.solve(1, "alice", true).await;
+    app.post(
+        "/v1/puzzles/2",
+        json!({
+            "id": 2,
+            "username": "alice",
+            "solved": false,
+            "solution": ["d4-", "c3", "a1+"],
+            "solveTimeSeconds": 12,
+        }),
+    )
+    .await;
+    app.solve(3, "bob", true).await;
+
+    let response = app.get("/v1/users/Alice/attempts.ptn").await;
+    assert_eq!(response.status, StatusCode::OK);
+    assert_eq!(
+        response.headers[header::CONTENT_DISPOSITION],
+        "attachment; filename=\"alice-attempts.ptn\""
+    );
+    let ptn = response.text();
+    let games: Vec<&str> = ptn.split("\n\n[").collect();
+    assert_eq!(games.len(), 2);
+    assert!(games[0].starts_with("[Site \"Tak tactics\"]\n[Event \"Puzzle 1\"]\n[Round \"1\"]\n"));
+    assert!(games[0].contains("[Size \"6\"]\n[Komi \"2\"]\n[TPS \"2,x,x,2,1,1/"));
+    assert!(games[0].ends_with("\n\n47. -- 5e3<\n48. d4- 3e3+12\n{Solved in 30 seconds}"));
+    assert!(games[1].starts_with("Site \"Tak tactics\"]\n[Event \"Puzzle 2\"]"));
+    assert!(games[1].ends_with("\n\n47. -- 5e3<\n48. d4- c3\n49. a1+\n{Not solved}\n"));
+
+    let response = app.get("/v1/users/not%20valid/attempts.ptn").await;
+    assert_eq!(response.status, StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn rating_history_has_a_point_per_rated_attempt() {
+    let app = TestApp::new().await;
+    assert_eq!(
+        app.get("/v1/users/alice/rating-history").await.json(),
+        json!([])
+    );
+
+    let first = app.solve(1, "alice", true).await.json();
+    app.solve(1, "alice", true).await;
+    let second = app.solve(2, "alice", false).await.json();
+
+    let history = app.get("/v1/users/alice/rating-history").await.json();
+    assert_fields(
+        &history[0],
+        &[
+            "timestampSeconds",
+            "rating",
+            "deviation",
+            "attemptId",
+            "puzzleId",
+        ],
+    );
+    assert_eq!(history.as_array().unwrap().len(), 2);
+    assert_eq!(history[0]["puzzleId"], 1);
+    assert_eq!(history[0]["rating"], first["ratingChange"]["newRating"]);
+    assert_eq!(history[1]["puzzleId"], 2);
+    assert_eq!(history[1]["rating"], second["ratingChange"]["newRating"]);
+
+    let response = app.get("/v1/users/%20/rating-history").await;
+    assert_eq!(response.status, StatusCode::BAD_REQUEST);
+}
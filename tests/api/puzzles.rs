@@ -0,0 +1,1586 @@
+use axum::{
+    body::Body,
+    http::{Request, StatusCode, header},
+};
+use futures_util::{SinkExt, StreamExt};
+use serde_json::json;
+use tak_tactics_backend::{default_target_time_seconds, grpc, storage};
+use tokio_tungstenite::tungstenite::Message;
+
+use crate::common::{SOLUTION, TestApp, assert_fields, json_request};
+
+const PUZZLE_FIELDS: [&str; 11] = [
+    "id",
+    "size",
+    "komi",
+    "rootTPS",
+    "defenderStartMove",
+    "solution",
+    "targetTimeSeconds",
+    "playerWhite",
+    "playerBlack",
+    "playtakGameId",
+    "review",
+];
+
+#[tokio::test]
+async fn new_users_get_puzzle_3_then_15_then_any_unattempted_puzzle() {
+    let app = TestApp::new().await;
+    let response = app.get("/v1/puzzles?username=alice").await;
+    assert_eq!(response.status, StatusCode::OK);
+    let puzzle = response.json();
+    assert_fields(&puzzle, &PUZZLE_FIELDS);
+    assert_eq!(puzzle["id"], 3);
+    assert_eq!(puzzle["solution"], json!(["d4-", "3e3+12", "*"]));
+
+    // There is no puzzle 15, so any published puzzle the user hasn't attempted is next
+    app.solve(3, "alice", true).await;
+    for _ in 0..4 {
+        let puzzle = app.get("/v1/puzzles?username=alice").await.json();
+        let id = puzzle["id"].as_u64().unwrap() as u32;
+        assert!((1..=5).contains(&id) && id != 3, "Got puzzle {id}");
+        assert_eq!(app.solve(id, "alice", false).await.status, StatusCode::OK);
+    }
+    let response = app.get("/v1/puzzles?username=alice").await;
+    assert_eq!(response.status, StatusCode::OK);
+    assert_eq!(response.json()["review"], true);
+}
+
+#[tokio::test]
+async fn users_who_attempted_every_puzzle_review_failed_then_oldest_solved_ones() {
+    let app = TestApp::new().await;
+    for (id, solved) in [(3, true), (1, true), (2, false), (4, true), (5, true)] {
+        app.solve(id, "alice", solved).await;
+    }
+    app.db()
+        .execute(
+            "UPDATE puzzle_attempts SET timestamp_seconds = 1000 + puzzle_id",
+            [],
+        )
+        .unwrap();
+    app.db()
+        .execute(
+            "UPDATE puzzle_attempts SET timestamp_seconds = 900 WHERE puzzle_id = 5",
+            [],
+        )
+        .unwrap();
+
+    let puzzle = app.get("/v1/puzzles?username=alice").await.json();
+    assert_eq!(puzzle["id"], 2);
+    assert_eq!(puzzle["review"], true);
+    let result = app.solve(2, "alice", true).await.json();
+    assert_eq!(result["rated"], false);
+
+    // Puzzle 2 has been solved just now, so the one solved longest ago is next
+    let puzzle = app.get("/v1/puzzles?username=alice").await.json();
+    assert_eq!(puzzle["id"], 5);
+    assert_eq!(puzzle["review"], true);
+    let current = app.get("/v1/puzzles/current?username=alice").await.json();
+    assert_eq!(current["rated"], false);
+
+    // Practicing is unaffected, and so are users with puzzles left
+    let practice = app
+        .get("/v1/puzzles?username=alice&rated=false")
+        .await
+        .json();
+    assert_eq!(practice["review"], false);
+    assert_eq!(
+        app.get("/v1/puzzles?username=bob").await.json()["review"],
+        false
+    );
+}
+
+#[tokio::test]
+async fn practice_serves_attempted_puzzles() {
+    let app = TestApp::new().await;
+    app.solve(2, "alice", false).await;
+    let puzzle = app
+        .get("/v1/puzzles?username=alice&rated=false")
+        .await
+        .json();
+    assert_eq!(puzzle["id"], 2);
+}
+
+#[tokio::test]
+async fn puzzles_can_be_picked_from_one_players_games() {
+    let app = TestApp::new().await;
+    app.db()
+        .execute(
+            "UPDATE puzzles SET player_black = 'x57696c6c' WHERE id IN (2, 6)",
+            [],
+        )
+        .unwrap();
+    app.db()
+        .execute(
+            "UPDATE puzzles SET player_white = 'x57696c6c' WHERE id = 4",
+            [],
+        )
+        .unwrap();
+
+    // Puzzle 3 isn't served first, and puzzle 6 isn't published
+    let mut ids = vec![];
+    for _ in 0..2 {
+        let puzzle = app
+            .get("/v1/puzzles?username=alice&player=X57696C6C")
+            .await
+            .json();
+        let id = puzzle["id"].as_u64().unwrap() as u32;
+        app.solve(id, "alice", false).await;
+        ids.push(id);
+    }
+    ids.sort();
+    assert_eq!(ids, [2, 4]);
+    let review = app
+        .get("/v1/puzzles?username=alice&player=x57696c6c")
+        .await
+        .json();
+    assert_eq!(review["review"], true);
+    assert!([2, 4].contains(&review["id"].as_u64().unwrap()));
+
+    let puzzle = app
+        .get("/v1/puzzles?username=alice&player=x57696c6c&rated=false")
+        .await
+        .json();
+    assert!([2, 4].contains(&puzzle["id"].as_u64().unwrap()));
+    let response = app.get("/v1/puzzles?username=alice&player=a%20b").await;
+    assert_eq!(response.status, StatusCode::BAD_REQUEST);
+    assert_eq!(response.json()["field"], "player");
+}
+
+#[tokio::test]
+async fn puzzles_are_picked_by_the_users_settings() {
+    let app = TestApp::new().await;
+    app.db()
+        .execute("UPDATE puzzles SET size = 5 WHERE id IN (4, 5)", [])
+        .unwrap();
+    let set_settings = |rated_by_default: bool| {
+        app.request(json_request(
+            "PUT",
+            "/v1/users/alice/settings",
+            json!({
+                "preferredSizes": [5],
+                "ratedByDefault": rated_by_default,
+                "hideSolveTimes": false,
+                "normalizeColors": false,
+            }),
+        ))
+    };
+    assert_eq!(set_settings(true).await.status, StatusCode::OK);
+
+    // Puzzle 3 isn't on a preferred size, so it isn't served first
+    let mut ids = vec![];
+    for _ in 0..2 {
+        let puzzle = app.get("/v1/puzzles?username=alice").await.json();
+        assert_eq!(puzzle["size"], 5);
+        let id = puzzle["id"].as_u64().unwrap() as u32;
+        app.solve(id, "alice", true).await;
+        ids.push(id);
+    }
+    ids.sort();
+    assert_eq!(ids, [4, 5]);
+    let review = app.get("/v1/puzzles?username=alice").await.json();
+    assert_eq!(review["review"], true);
+    assert_eq!(review["size"], 5);
+
+    // The query overrides the settings
+    let puzzle = app.get("/v1/puzzles?username=alice&size=6").await.json();
+    assert_eq!(puzzle["id"], 3);
+    let response = app.get("/v1/puzzles?username=alice&size=9").await;
+    assert_eq!(response.status, StatusCode::BAD_REQUEST);
+    assert_eq!(response.json()["field"], "size");
+
+    assert_eq!(set_settings(false).await.status, StatusCode::OK);
+    let puzzle = app.get("/v1/puzzles?username=alice").await.json();
+    assert!([4, 5].contains(&puzzle["id"].as_u64().unwrap()));
+    assert_eq!(puzzle["review"], false);
+    let puzzle = app
+        .get("/v1/puzzles?username=alice&rated=true")
+        .await
+        .json();
+    assert_eq!(puzzle["review"], true);
+}
+
+#[tokio::test]
+async fn invalid_usernames_are_rejected() {
+    let app = TestApp::new().await;
+    let response = app.get("/v1/puzzles?username=not%20valid").await;
+    assert_eq!(response.status, StatusCode::BAD_REQUEST);
+    let error = response.json();
+    assert_fields(&error, &["field", "message", "requestId"]);
+    assert_eq!(error["field"], "username");
+}
+
+#[tokio::test]
+async fn only_published_puzzles_can_be_fetched_by_id() {
+    let app = TestApp::new().await;
+    let response = app.get("/v1/puzzles/1").await;
+    assert_eq!(response.status, StatusCode::OK);
+    assert_fields(&response.json(), &PUZZLE_FIELDS);
+    assert_eq!(response.json()["id"], 1);
+
+    assert_eq!(app.get("/v1/puzzles/6").await.status, StatusCode::NOT_FOUND);
+    assert_eq!(
+        app.get("/v1/puzzles/99").await.status,
+        StatusCode::NOT_FOUND
+    );
+}
+
+#[tokio::test]
+async fn puzzles_are_not_sent_again_if_the_etag_matches() {
+    let app = TestApp::new().await;
+    let response = app.get("/v1/puzzles/1").await;
+    let etag = response.headers[header::ETAG].clone();
+
+    let request = Request::get("/v1/puzzles/1")
+        .header(header::IF_NONE_MATCH, etag)
+        .body(Body::empty())
+        .unwrap();
+    let response = app.request(request).await;
+    assert_eq!(response.status, StatusCode::NOT_MODIFIED);
+    assert!(response.body.is_empty());
+}
+
+#[tokio::test]
+async fn the_feed_lists_published_puzzles_newest_first() {
+    let app = TestApp::new().await;
+    app.db()
+        .execute(
+            "UPDATE puzzles SET published_seconds = published_seconds + 60 WHERE id = 4",
+            [],
+        )
+        .unwrap();
+    let response = app.get("/v1/puzzles/feed.atom").await;
+    assert_eq!(response.status, StatusCode::OK);
+    assert!(
+        response.headers[header::CONTENT_TYPE]
+            .to_str()
+            .unwrap()
+            .starts_with("application/atom+xml")
+    );
+    let feed = response.text();
+    let ids: Vec<&str> = feed
+        .split("<id>urn:tak-tactics:puzzle:")
+        .skip(1)
+        .map(|entry| entry.split('<').next().unwrap())
+        .collect();
+    assert_eq!(ids, ["4", "5", "3", "2", "1"]);
+    assert!(feed.contains("<link rel=\"alternate\" href=\"4\"/>"));
+    assert!(feed.starts_with("<?xml"));
+}
+
+#[tokio::test]
+async fn puzzle_images_show_the_board_after_the_defenders_move() {
+    let app = TestApp::new().await;
+    let response = app.get("/v1/puzzles/1/image.svg").await;
+    assert_eq!(response.status, StatusCode::OK);
+    assert_eq!(response.headers[header::CONTENT_TYPE], "image/svg+xml");
+    let svg = response.text();
+    assert!(svg.starts_with("<svg"));
+    // `5e3<` is highlighted on e3 and d3, where it left a stack of 6
+    assert_eq!(svg.matches("#f2d95c").count(), 2);
+    assert!(svg.contains(">6</text>"));
+
+    let response = app.get("/v1/puzzles/6/image.svg").await;
+    assert_eq!(response.status, StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn solutions_are_animated_one_move_at_a_time() {
+    let app = TestApp::new().await;
+    // The seeded solution doesn't have to be legal, but animating it plays every move
+    app.db()
+        .execute("UPDATE puzzles SET solution = 'd4- a6- *' WHERE id = 1", [])
+        .unwrap();
+    let response = app.get("/v1/puzzles/1/solution.svg").await;
+    assert_eq!(response.status, StatusCode::OK);
+    assert_eq!(response.headers[header::CONTENT_TYPE], "image/svg+xml");
+    let svg = response.text();
+    // The start and the two moves
+    assert_eq!(svg.matches("<animate ").count(), 3);
+    assert!(svg.ends_with("</svg>"));
+}
+#[tokio::test]
+async fn solving_a_puzzle_records_a_rated_attempt() {
+    let app = TestApp::new().await;
+    let response = app.solve(3, "alice", true).await;
+    assert_eq!(response.status, StatusCode::OK);
+    let result = response.json();
+    assert_fields(
+        &result,
+        &[
+            "attemptNumber",
+            "rated",
+            "ratingChange",
+            "retry",
+            "correct",
+            "solution",
+            "targetTimeSeconds",
+            "targetTimeMet",
+            "verdict",
+            "illegalMoveIndex",
+        ],
+    );
+    assert_eq!(result["attemptNumber"], 1);
+    assert_eq!(result["rated"], true);
+    assert_eq!(result["correct"], true);
+    assert_fields(
+        &result["ratingChange"],
+        &["oldRating", "newRating", "puzzleRating"],
+    );
+    assert_eq!(result["ratingChange"]["oldRating"], 1500.0);
+    assert!(result["ratingChange"]["newRating"].as_f64().unwrap() > 1500.0);
+
+    assert_eq!(
+        app.count(
+            "SELECT COUNT(*) FROM puzzle_attempts JOIN users ON users.id = user_id
+            WHERE username = 'alice' AND solved = 1"
+        ),
+        1
+    );
+    let rating: f64 = app
+        .db()
+        .query_row(
+            "SELECT rating FROM users WHERE username = 'alice'",
+            [],
+            |row| row.get(0),
+        )
+        .unwrap();
+    assert_eq!(
+        rating,
+        result["ratingChange"]["newRating"].as_f64().unwrap()
+    );
+}
+
+#[tokio::test]
+async fn only_the_first_attempt_is_rated() {
+    let app = TestApp::new().await;
+    app.solve(3, "alice", false).await;
+    let result = app.solve(3, "alice", true).await.json();
+    assert_eq!(result["attemptNumber"], 2);
+    assert_eq!(result["rated"], false);
+    assert_eq!(result["ratingChange"], json!(null));
+    assert_eq!(
+        app.count("SELECT COUNT(*) FROM rated_attempts WHERE username = 'alice'"),
+        1
+    );
+}
+
+#[tokio::test]
+async fn usernames_are_case_insensitive_and_keep_the_typed_form_for_display() {
+    let app = TestApp::new().await;
+    app.solve(3, "Alice", true).await;
+    let result = app.solve(3, "alice", false).await.json();
+    assert_eq!(result["attemptNumber"], 2);
+    assert_eq!(result["rated"], false);
+
+    let history = app.get("/v1/users/ALICE/attempts").await.json();
+    assert_eq!(history["items"].as_array().unwrap().len(), 2);
+    assert_eq!(app.count("SELECT COUNT(*) FROM users"), 1);
+
+    app.solve(2, "ALICE", true).await;
+    let leaderboard = app.get("/v1/leaderboard").await.json();
+    assert_eq!(leaderboard[0]["username"], "ALICE");
+    assert_eq!(leaderboard[0]["numSolved"], 2);
+}
+
+#[tokio::test]
+async fn practice_attempts_are_never_rated() {
+    let app = TestApp::new().await;
+    let response = app
+        .post(
+            "/v1/puzzles/3",
+            json!({
+                "id": 3,
+                "username": "alice",
+                "solved": true,
+                "solution": SOLUTION,
+                "solveTimeSeconds": 30,
+                "rated": false,
+            }),
+        )
+        .await;
+    assert_eq!(response.json()["rated"], false);
+    assert_eq!(
+        app.count("SELECT COUNT(*) FROM puzzle_attempts WHERE practice = 1"),
+        1
+    );
+    // The user is added for the attempt, but keeps the default rating
+    assert_eq!(
+        app.count("SELECT COUNT(*) FROM users WHERE rating = 1500 AND deviation = 350"),
+        1
+    );
+    assert_eq!(app.count("SELECT COUNT(*) FROM user_rating_history"), 0);
+}
+
+#[tokio::test]
+async fn solves_that_do_not_match_the_solution_are_rejected_without_side_effects() {
+    let app = TestApp::new().await;
+    let response = app
+        .post(
+            "/v1/puzzles/3",
+            json!({
+                "id": 3,
+                "username": "alice",
+                "solved": true,
+                "solution": ["a1"],
+                "solveTimeSeconds": 30,
+            }),
+        )
+        .await;
+    assert_eq!(response.status, StatusCode::BAD_REQUEST);
+    assert_eq!(response.json()["field"], "solution");
+    // Nor can the solution be passed off as a failure
+    let response = app
+        .post(
+            "/v1/puzzles/3",
+            json!({
+                "id": 3,
+                "username": "alice",
+                "solved": false,
+                "solution": SOLUTION,
+                "solveTimeSeconds": 30,
+            }),
+        )
+        .await;
+    assert_eq!(response.status, StatusCode::BAD_REQUEST);
+    assert_eq!(response.json()["field"], "solution");
+    assert_eq!(app.count("SELECT COUNT(*) FROM puzzle_attempts"), 0);
+    assert_eq!(app.count("SELECT COUNT(*) FROM users"), 0);
+}
+
+#[tokio::test]
+async fn unpublished_puzzles_cant_be_solved() {
+    let app = TestApp::new().await;
+    let response = app.solve(6, "alice", true).await;
+    assert_eq!(response.status, StatusCode::NOT_FOUND);
+    assert!(!response.text().contains("3e3+12"));
+    assert_eq!(app.count("SELECT COUNT(*) FROM puzzle_attempts"), 0);
+}
+
+#[tokio::test]
+async fn malformed_moves_and_moves_off_the_board_are_rejected() {
+    let app = TestApp::new().await;
+    let submit = |ptn_move: &str| {
+        app.post(
+            "/v1/puzzles/1",
+            json!({
+                "id": 1,
+                "username": "alice",
+                "solved": false,
+                "solution": ["a1", ptn_move],
+                "solveTimeSeconds": 30,
+            }),
+        )
+    };
+    for (ptn_move, reason) in [
+        ("e3+12", "the drops add up to 3 pieces, but 1 are picked up"),
+        ("c3x", "'x' isn't a direction"),
+        ("g1", "g1 is off the 6x6 board"),
+        ("7a1>", "it carries 7 pieces"),
+        ("2a1<11", "it spreads off the 6x6 board"),
+    ] {
+        let response = submit(ptn_move).await;
+        assert_eq!(response.status, StatusCode::BAD_REQUEST, "{ptn_move}");
+        let error = response.json();
+        assert_eq!(error["field"], "solution");
+        let message = error["message"].as_str().unwrap();
+        assert!(message.contains(ptn_move), "{message}");
+        assert!(message.contains(reason), "{message}");
+    }
+    assert_eq!(app.count("SELECT COUNT(*) FROM puzzle_attempts"), 0);
+
+    // Annotations, the end marker and moves that fit are fine
+    let response = submit("2f6<11'").await;
+    assert_eq!(response.status, StatusCode::OK);
+}
+
+#[tokio::test]
+async fn submitted_lines_are_played_out_to_tell_wrong_from_illegal() {
+    let app = TestApp::new().await;
+    let submit = |username: &str, solution: &[&str]| {
+        app.post(
+            "/v1/puzzles/1",
+            json!({
+                "id": 1,
+                "username": username,
+                "solved": false,
+                "solution": solution,
+                "solveTimeSeconds": 30,
+            }),
+        )
+    };
+
+    // After the defender's `5e3<`, white is to move
+    let wrong = submit("alice", &["b1"]).await.json();
+    assert_eq!(wrong["verdict"], "wrong");
+    assert!(wrong["illegalMoveIndex"].is_null());
+    // a1 is taken, and d3 is black's
+    let occupied = submit("bob", &["a1"]).await.json();
+    assert_eq!(occupied["verdict"], "illegal");
+    assert_eq!(occupied["illegalMoveIndex"], 0);
+    let not_theirs = submit("carol", &["b1", "e2", "d3+"]).await.json();
+    assert_eq!(not_theirs["verdict"], "illegal");
+    assert_eq!(not_theirs["illegalMoveIndex"], 2);
+    let correct = app.solve(1, "dave", true).await.json();
+    assert_eq!(correct["verdict"], "correct");
+
+    assert_eq!(
+        app.count(
+            "SELECT COUNT(*) FROM puzzle_attempts
+            WHERE verdict = 'illegal' AND illegal_move_index IS NOT NULL"
+        ),
+        2
+    );
+    let history = app.get("/v1/users/carol/attempts").await.json();
+    assert_eq!(history["items"][0]["verdict"], "illegal");
+    assert_eq!(history["items"][0]["illegalMoveIndex"], 2);
+}
+
+#[tokio::test]
+async fn solving_an_unknown_puzzle_is_not_found() {
+    let app = TestApp::new().await;
+    assert_eq!(
+        app.solve(99, "alice", true).await.status,
+        StatusCode::NOT_FOUND
+    );
+}
+
+#[tokio::test]
+async fn retries_with_an_idempotency_key_are_only_recorded_once() {
+    let app = TestApp::new().await;
+    let attempt = json!({
+        "id": 3,
+        "username": "alice",
+        "solved": true,
+        "solution": SOLUTION,
+        "solveTimeSeconds": 30,
+    });
+    let keyed_request = |uri: &str, body| {
+        let mut request = json_request("POST", uri, body);
+        request
+            .headers_mut()
+            .insert("idempotency-key", "retry-1".parse().unwrap());
+        request
+    };
+
+    let first = app
+        .request(keyed_request("/v1/puzzles/3", attempt.clone()))
+        .await;
+    let retry = app
+        .request(keyed_request("/v1/puzzles/3", attempt.clone()))
+        .await;
+    assert_eq!(retry.status, StatusCode::OK);
+    assert_eq!(
+        first.json()["ratingChange"]["newRating"],
+        retry.json()["ratingChange"]["newRating"]
+    );
+    assert_eq!(retry.json()["attemptNumber"], 1);
+    assert_eq!(app.count("SELECT COUNT(*) FROM puzzle_attempts"), 1);
+
+    let other_puzzle = app.request(keyed_request("/v1/puzzles/4", attempt)).await;
+    assert_eq!(other_puzzle.status, StatusCode::CONFLICT);
+    assert_eq!(app.count("SELECT COUNT(*) FROM puzzle_attempts"), 1);
+}
+
+#[tokio::test]
+async fn puzzle_ratings_change_after_rated_attempts() {
+    let app = TestApp::new().await;
+    let before = app.get("/v1/puzzles/3/rating").await.json();
+    app.solve(3, "alice", true).await;
+    let after = app.get("/v1/puzzles/3/rating").await.json();
+    assert!(after.as_f64().unwrap() < before.as_f64().unwrap());
+
+    let ratings = app.get("/v1/puzzles/ratings").await.json();
+    let ids: Vec<&String> = ratings.as_object().unwrap().keys().collect();
+    assert_eq!(ids, ["1", "2", "3", "4", "5"]);
+    assert_eq!(ratings["3"], after);
+}
+
+#[tokio::test]
+async fn new_users_have_provisional_ratings_that_still_count_for_puzzles() {
+    let app = TestApp::new().await;
+    app.solve(3, "alice", true).await;
+    let lifetime = &app.get("/v1/users/alice/ratings").await.json()["lifetime"];
+    assert_fields(
+        lifetime,
+        &["rating", "deviation", "numRatedAttempts", "provisional"],
+    );
+    assert_eq!(lifetime["numRatedAttempts"], 1);
+    assert_eq!(lifetime["provisional"], true);
+
+    // Users who were added without a deviation count as if they had the default one
+    let before = app.get("/v1/puzzles/4/rating").await.json();
+    app.db()
+        .execute_batch(
+            "INSERT INTO users (username, rating) VALUES ('imported', 1500);
+            INSERT INTO puzzle_attempts (puzzle_id, user_id, solved, solve_time_seconds, solution, attempt_number)
+            SELECT 4, id, 1, 30, 'd4- 3e3+12', 1 FROM users WHERE username = 'imported'",
+        )
+        .unwrap();
+    let after = app.get("/v1/puzzles/ratings").await.json()["4"].clone();
+    assert!(after.as_f64() < before.as_f64());
+}
+
+#[tokio::test]
+async fn ratings_are_clamped_to_the_floor_and_the_clamping_is_recorded() {
+    let app = TestApp::new().await;
+    app.db()
+        .execute(
+            "INSERT INTO users (username, rating, deviation, volatility) VALUES ('alice', 700, 50, 0.06)",
+            [],
+        )
+        .unwrap();
+    let result = app.solve(3, "alice", false).await.json();
+    assert_eq!(result["ratingChange"]["newRating"], 800.0);
+
+    let clamps = app
+        .admin("GET", "/v1/admin/rating-clamps", json!(null))
+        .await
+        .json();
+    let clamp = &clamps["items"][0];
+    assert_fields(
+        clamp,
+        &[
+            "attemptId",
+            "kind",
+            "username",
+            "puzzleId",
+            "unclampedRating",
+            "clampedRating",
+            "timestampSeconds",
+        ],
+    );
+    assert_eq!(clamps["items"].as_array().unwrap().len(), 1);
+    assert_eq!(clamp["kind"], "user");
+    assert_eq!(clamp["username"], "alice");
+    assert_eq!(clamp["clampedRating"], 800.0);
+    assert!(clamp["unclampedRating"].as_f64().unwrap() < 700.0);
+
+    let response = app.get("/v1/admin/rating-clamps").await;
+    assert_eq!(response.status, StatusCode::UNAUTHORIZED);
+}
+
+#[tokio::test]
+async fn users_excluded_from_ratings_do_not_change_puzzle_ratings() {
+    let app = TestApp::new().await;
+    let response = app
+        .admin(
+            "POST",
+            "/v1/admin/users/carol/rating-exclusion",
+            json!({"excludedFromRatings": true}),
+        )
+        .await;
+    assert_eq!(response.status, StatusCode::OK);
+    let before = app.get("/v1/puzzles/3/rating").await.json();
+    let result = app.solve(3, "carol", true).await.json();
+    assert_eq!(result["rated"], true);
+    assert!(result["ratingChange"]["newRating"].as_f64().unwrap() > 1500.0);
+    assert_eq!(app.get("/v1/puzzles/3/rating").await.json(), before);
+    assert_eq!(app.get("/v1/puzzles/ratings").await.json()["3"], before);
+
+    // Changing a user's exclusion changes the cached ratings of the puzzles they attempted right away
+    let response = app
+        .admin(
+            "POST",
+            "/v1/admin/users/carol/rating-exclusion",
+            json!({"excludedFromRatings": false}),
+        )
+        .await;
+    assert_eq!(response.status, StatusCode::OK);
+    let included = app.get("/v1/puzzles/3/rating").await.json();
+    assert!(included.as_f64() < before.as_f64());
+    app.admin(
+        "POST",
+        "/v1/admin/users/carol/rating-exclusion",
+        json!({"excludedFromRatings": true}),
+    )
+    .await;
+    assert_eq!(app.get("/v1/puzzles/3/rating").await.json(), before);
+
+    let response = app
+        .post(
+            "/v1/admin/users/carol/rating-exclusion",
+            json!({"excludedFromRatings": false}),
+        )
+        .await;
+    assert_eq!(response.status, StatusCode::UNAUTHORIZED);
+}
+
+#[tokio::test]
+async fn unversioned_paths_are_deprecated_aliases() {
+    let app = TestApp::new().await;
+    let response = app.get("/puzzles/1").await;
+    assert_eq!(response.status, StatusCode::OK);
+    assert_eq!(response.headers["deprecation"], "true");
+    assert!(
+        !app.get("/v1/puzzles/1")
+            .await
+            .headers
+            .contains_key("deprecation")
+    );
+}
+
+#[tokio::test]
+async fn target_times_are_read_from_the_database() {
+    let app = TestApp::new().await;
+    app.db()
+        .execute(
+            "UPDATE puzzles SET target_time_seconds = 1000 WHERE id = 1",
+            [],
+        )
+        .unwrap();
+    // Rated the same as the puzzle, so the target time isn't scaled
+    app.db()
+        .execute(
+            "INSERT INTO users (username, rating) VALUES ('alice', 1600)",
+            [],
+        )
+        .unwrap();
+    let puzzle = app.get("/v1/puzzles/1").await.json();
+    let target_time = puzzle["targetTimeSeconds"].as_u64().unwrap();
+    assert!((1000..1200).contains(&target_time));
+    let result = app.solve(1, "alice", true).await.json();
+    assert_eq!(result["targetTimeSeconds"], 1000);
+
+    assert_eq!(storage::backfill_target_times(&mut app.db()).unwrap(), 6);
+    assert_eq!(storage::backfill_target_times(&mut app.db()).unwrap(), 0);
+    let (root_tps, solution): (String, String) = app
+        .db()
+        .query_row(
+            "SELECT root_tps, solution FROM puzzles WHERE id = 1",
+            [],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        )
+        .unwrap();
+    assert_eq!(
+        app.count("SELECT target_time_seconds FROM puzzles WHERE id = 1"),
+        default_target_time_seconds(&root_tps, &solution)
+    );
+}
+
+#[tokio::test]
+async fn target_times_are_scaled_to_the_users_rating() {
+    let app = TestApp::new().await;
+    app.db()
+        .execute(
+            "INSERT INTO users (username, rating) VALUES ('strong', 3000), ('even', 1600), ('weak', 1200)",
+            [],
+        )
+        .unwrap();
+    // Every test puzzle has a target time of 120 seconds, and starts out rated 1600
+    let users = [("strong", 1, 60), ("even", 2, 120), ("weak", 4, 240)];
+    for (username, _, low) in users {
+        let puzzle = app
+            .get(&format!("/v1/puzzles?username={username}"))
+            .await
+            .json();
+        let target_time = puzzle["targetTimeSeconds"].as_u64().unwrap();
+        assert!(
+            (low..low * 6 / 5).contains(&target_time),
+            "{username} got {target_time}s"
+        );
+    }
+    // On different puzzles, since solving one changes its rating
+    for (username, puzzle_id, low) in users {
+        let result = app.solve(puzzle_id, username, true).await.json();
+        assert_eq!(result["targetTimeSeconds"], low);
+    }
+}
+
+#[tokio::test]
+async fn move_times_are_stored_and_summarized() {
+    let app = TestApp::new().await;
+    let solve = |username: &str, move_times_ms: serde_json::Value| {
+        app.post(
+            "/v1/puzzles/1",
+            json!({
+                "id": 1,
+                "username": username,
+                "solved": true,
+                "solution": SOLUTION,
+                "solveTimeSeconds": 30,
+                "moveTimesMs": move_times_ms,
+            }),
+        )
+    };
+    let response = solve("alice", json!([1000])).await;
+    assert_eq!(response.status, StatusCode::BAD_REQUEST);
+    assert_eq!(response.json()["field"], "moveTimesMs");
+    let response = solve("alice", json!([3000, 2000])).await;
+    assert_eq!(response.status, StatusCode::BAD_REQUEST);
+
+    assert_eq!(
+        solve("alice", json!([4000, 4000])).await.status,
+        StatusCode::OK
+    );
+    assert_eq!(
+        solve("bob", json!([2000, 9000])).await.status,
+        StatusCode::OK
+    );
+    app.solve(1, "carol", true).await;
+    assert_eq!(app.count("SELECT COUNT(*) FROM attempt_moves"), 4);
+
+    let stats = app.get("/v1/puzzles/1/move-times").await.json();
+    assert_fields(
+        &stats[0],
+        &[
+            "moveIndex",
+            "ptnMove",
+            "numAttempts",
+            "averageThinkMs",
+            "maxThinkMs",
+        ],
+    );
+    assert_eq!(
+        stats,
+        json!([
+            {"moveIndex": 0, "ptnMove": "d4-", "numAttempts": 2, "averageThinkMs": 3000.0, "maxThinkMs": 4000},
+            {"moveIndex": 1, "ptnMove": "3e3+12", "numAttempts": 2, "averageThinkMs": 3500.0, "maxThinkMs": 7000},
+        ])
+    );
+    let response = app.get("/v1/puzzles/6/move-times").await;
+    assert_eq!(response.status, StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn served_puzzles_can_be_resumed_until_they_are_submitted() {
+    let app = TestApp::new().await;
+    let response = app.get("/v1/puzzles/current?username=alice").await;
+    assert_eq!(response.status, StatusCode::NOT_FOUND);
+
+    let puzzle = app.get("/v1/puzzles?username=alice").await.json();
+    let current = app.get("/v1/puzzles/current?username=alice").await.json();
+    assert_fields(
+        &current,
+        &["puzzle", "rated", "startedSeconds", "elapsedMs", "moves"],
+    );
+    assert_eq!(current["puzzle"]["id"], puzzle["id"]);
+    assert_eq!(
+        current["puzzle"]["targetTimeSeconds"],
+        puzzle["targetTimeSeconds"]
+    );
+    assert_eq!(current["rated"], true);
+    assert_eq!(current["moves"], json!([]));
+
+    let response = app
+        .post(
+            "/v1/puzzles/current/moves",
+            json!({"username": "alice", "ptnMove": "not a move"}),
+        )
+        .await;
+    assert_eq!(response.status, StatusCode::BAD_REQUEST);
+    let current = app
+        .post(
+            "/v1/puzzles/current/moves",
+            json!({"username": "alice", "ptnMove": "d4-"}),
+        )
+        .await
+        .json();
+    assert_eq!(current["moves"][0]["ptnMove"], "d4-");
+    let current = app.get("/v1/puzzles/current?username=alice").await.json();
+    assert_eq!(current["moves"].as_array().unwrap().len(), 1);
+
+    app.solve(puzzle["id"].as_u64().unwrap() as u32, "alice", true)
+        .await;
+    let response = app.get("/v1/puzzles/current?username=alice").await;
+    assert_eq!(response.status, StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn abandoned_attempts_are_recorded_as_failed() {
+    let app = TestApp::new().await;
+    let puzzle = app.get("/v1/puzzles?username=alice").await.json();
+    app.post(
+        "/v1/puzzles/current/moves",
+        json!({"username": "alice", "ptnMove": "d4-"}),
+    )
+    .await;
+    app.db()
+        .execute("UPDATE attempts_in_progress SET started_ms = 0", [])
+        .unwrap();
+
+    let response = app.get("/v1/puzzles/current?username=alice").await;
+    assert_eq!(response.status, StatusCode::NOT_FOUND);
+    let (puzzle_id, solved, solution): (u64, bool, String) = app
+        .db()
+        .query_row(
+            "SELECT puzzle_id, solved, solution FROM rated_attempts WHERE username = 'alice'",
+            [],
+            |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)),
+        )
+        .unwrap();
+    assert_eq!(puzzle_id, puzzle["id"].as_u64().unwrap());
+    assert!(!solved);
+    assert_eq!(solution, "d4-");
+    assert_eq!(app.count("SELECT COUNT(*) FROM attempts_in_progress"), 0);
+}
+
+#[tokio::test]
+async fn puzzle_rating_history_has_a_point_per_rated_attempt() {
+    let app = TestApp::new().await;
+    assert_eq!(
+        app.get("/v1/puzzles/1/rating-history").await.json(),
+        json!([])
+    );
+    app.solve(1, "alice", true).await;
+    app.solve(1, "alice", false).await;
+    app.admin(
+        "POST",
+        "/v1/admin/users/Morten/rating-exclusion",
+        json!({"excludedFromRatings": true}),
+    )
+    .await;
+    app.solve(1, "Morten", true).await;
+    let last = app.solve(1, "bob", false).await.json();
+
+    let history = app.get("/v1/puzzles/1/rating-history").await.json();
+    assert_fields(
+        &history[0],
+        &["timestampSeconds", "rating", "deviation", "numAttempts"],
+    );
+    assert_eq!(history.as_array().unwrap().len(), 2);
+    assert_eq!(history[1]["numAttempts"], 2);
+    assert_eq!(history[1]["rating"], last["ratingChange"]["puzzleRating"]);
+    assert!(history[1]["deviation"].as_f64() < history[0]["deviation"].as_f64());
+
+    let response = app.get("/v1/puzzles/6/rating-history").await;
+    assert_eq!(response.status, StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn graphql_queries_fetch_nested_data_in_one_request() {
+    let app = TestApp::new().await;
+    app.solve(3, "alice", true).await;
+    app.solve(1, "Alice", false).await;
+
+    let query = "{
+        user(username: \"ALICE\") {
+            username numSolved
+            attempts { solved puzzle { id size numAttempts } }
+        }
+        puzzles(first: 2, after: 1) { id rootTPS }
+        stats { numPuzzles numAttempts }
+    }";
+    let response = app.post("/v1/graphql", json!({ "query": query })).await;
+    assert_eq!(response.status, StatusCode::OK);
+    let body = response.json();
+    assert_eq!(body["errors"], json!(null));
+    let data = &body["data"];
+    // The display name is the latest spelling
+    assert_eq!(data["user"]["username"], "Alice");
+    assert_eq!(data["user"]["numSolved"], 1);
+    assert_eq!(
+        data["user"]["attempts"],
+        json!([
+            {"solved": false, "puzzle": {"id": 1, "size": 6, "numAttempts": 1}},
+            {"solved": true, "puzzle": {"id": 3, "size": 6, "numAttempts": 1}},
+        ])
+    );
+    assert_eq!(data["puzzles"][0]["id"], 2);
+    assert_eq!(data["puzzles"].as_array().unwrap().len(), 2);
+    assert_eq!(data["stats"], json!({"numPuzzles": 5, "numAttempts": 2}));
+
+    // Too deep to run
+    let query = "{ user(username: \"alice\") { attempts { user { attempts { user { attempts { user { attempts { id } } } } } } } } }";
+    let body = app
+        .post("/v1/graphql", json!({ "query": query }))
+        .await
+        .json();
+    assert_eq!(body["data"], json!(null));
+    assert!(
+        body["errors"][0]["message"]
+            .as_str()
+            .unwrap()
+            .contains("nested too deep")
+    );
+}
+
+#[tokio::test]
+async fn puzzle_packs_bundle_published_puzzles_with_a_manifest() {
+    let app = TestApp::new().await;
+    app.solve(2, "alice", true).await;
+
+    let response = app.get("/v1/puzzles/export?size=6&count=3").await;
+    assert_eq!(response.status, StatusCode::OK);
+    assert_eq!(
+        response.headers[header::CONTENT_DISPOSITION],
+        "attachment; filename=\"tak-puzzles-6x6.json\""
+    );
+    let pack = response.json();
+    assert_fields(&pack, &["manifest", "puzzles"]);
+    assert_fields(
+        &pack["manifest"],
+        &["formatVersion", "generatedSeconds", "size", "numPuzzles"],
+    );
+    assert_eq!(pack["manifest"]["formatVersion"], 1);
+    assert_eq!(pack["manifest"]["numPuzzles"], 3);
+    let puzzles = pack["puzzles"].as_array().unwrap();
+    let ids: Vec<_> = puzzles.iter().map(|puzzle| puzzle["id"].clone()).collect();
+    assert_eq!(ids, [1, 2, 3]);
+    assert!(puzzles[1]["rating"].as_f64() < puzzles[0]["rating"].as_f64());
+    assert_eq!(puzzles[0]["solution"], json!(["d4-", "3e3+12", "*"]));
+
+    // Puzzle 6 isn't published
+    let pack = app.get("/v1/puzzles/export").await.json();
+    assert_eq!(pack["manifest"]["numPuzzles"], 5);
+    assert_eq!(pack["manifest"]["size"], json!(null));
+    assert_eq!(
+        app.get("/v1/puzzles/export?size=9").await.status,
+        StatusCode::BAD_REQUEST
+    );
+    assert_eq!(
+        app.get("/v1/puzzles/export?count=0").await.status,
+        StatusCode::BAD_REQUEST
+    );
+}
+
+#[tokio::test]
+async fn puzzles_can_be_searched_by_player_and_game() {
+    let app = TestApp::new().await;
+    let db_conn = app.db();
+    db_conn
+        .execute(
+            "UPDATE puzzles SET player_white = 'EVRNjayhawker', player_black = 'alion02', playtak_game_id = 123456
+            WHERE id = 2",
+            [],
+        )
+        .unwrap();
+    db_conn
+        .execute(
+            "UPDATE puzzles SET player_black = 'EVRNjayhawker' WHERE id IN (4, 6)",
+            [],
+        )
+        .unwrap();
+
+    let ids = |body: serde_json::Value| -> Vec<u64> {
+        body.as_array()
+            .unwrap()
+            .iter()
+            .map(|puzzle| puzzle["id"].as_u64().unwrap())
+            .collect()
+    };
+    // Puzzle 6 isn't published
+    let response = app.get("/v1/puzzles/search?q=evrnjay").await;
+    assert_eq!(response.status, StatusCode::OK);
+    assert_eq!(ids(response.json()), [2, 4]);
+    assert_eq!(
+        ids(app
+            .get("/v1/puzzles/search?q=EVRNjayhawker%20alion02")
+            .await
+            .json()),
+        [2]
+    );
+    assert_eq!(
+        ids(app.get("/v1/puzzles/search?q=123456").await.json()),
+        [2]
+    );
+    // Query syntax is matched literally
+    assert_eq!(
+        ids(app.get("/v1/puzzles/search?q=%22OR%20(").await.json()),
+        [0u64; 0]
+    );
+    assert_eq!(
+        app.get("/v1/puzzles/search?q=%20-").await.status,
+        StatusCode::BAD_REQUEST
+    );
+}
+
+#[tokio::test]
+async fn similar_puzzles_share_themes_and_have_close_ratings() {
+    let app = TestApp::new().await;
+    for (id, themes) in [
+        (1, json!(["cap-smash", "road-threat"])),
+        (2, json!(["cap-smash"])),
+        (4, json!(["cap-smash", "road-threat"])),
+        (6, json!(["cap-smash", "road-threat"])),
+    ] {
+        let response = app
+            .admin(
+                "PUT",
+                &format!("/v1/admin/puzzles/{id}/themes"),
+                json!({ "themes": themes }),
+            )
+            .await;
+        assert_eq!(response.status, StatusCode::OK);
+    }
+    let response = app
+        .admin(
+            "PUT",
+            "/v1/admin/puzzles/1/themes",
+            json!({"themes": ["Cap Smash"]}),
+        )
+        .await;
+    assert_eq!(response.status, StatusCode::BAD_REQUEST);
+    assert_eq!(
+        app.get("/v1/puzzles/4/themes").await.json(),
+        json!({"themes": ["cap-smash", "road-threat"]})
+    );
+    assert_eq!(
+        app.count("SELECT COUNT(*) FROM audit_log WHERE action = 'set_puzzle_themes'"),
+        4
+    );
+
+    // Solving puzzle 5 lowers its rating, so it's ranked below puzzle 3 although neither has themes.
+    // Puzzle 6 isn't published
+    app.solve(5, "alice", true).await;
+    let similar = app.get("/v1/puzzles/1/similar").await.json();
+    let ids: Vec<_> = similar
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|puzzle| puzzle["id"].as_u64().unwrap())
+        .collect();
+    assert_eq!(ids, [4, 2, 3, 5]);
+    assert_fields(&similar[0], &["id", "size", "rating", "sharedThemes"]);
+    assert_eq!(
+        similar[0]["sharedThemes"],
+        json!(["cap-smash", "road-threat"])
+    );
+    assert_eq!(
+        app.get("/v1/puzzles/1/similar?limit=1")
+            .await
+            .json()
+            .as_array()
+            .unwrap()
+            .len(),
+        1
+    );
+    assert_eq!(
+        app.get("/v1/puzzles/6/similar").await.status,
+        StatusCode::NOT_FOUND
+    );
+}
+
+#[tokio::test]
+async fn explanations_are_shown_once_the_puzzle_is_finished() {
+    let app = TestApp::new().await;
+    let explanation = json!({
+        "title": "The cap smash",
+        "explanation": "Flatten the wall to open the road.",
+        "moveComments": [
+            { "moveIndex": 1, "comment": "The only move" },
+            { "moveIndex": 0, "comment": "Threatens two roads" },
+        ],
+    });
+    let response = app
+        .admin("PUT", "/v1/admin/puzzles/1/explanation", explanation)
+        .await;
+    assert_eq!(response.status, StatusCode::OK);
+    assert_eq!(response.json()["moveComments"][0]["moveIndex"], 0);
+    let invalid = app
+        .admin(
+            "PUT",
+            "/v1/admin/puzzles/1/explanation",
+            json!({ "moveComments": [{ "moveIndex": 10, "comment": "Too far" }] }),
+        )
+        .await;
+    assert_eq!(invalid.status, StatusCode::BAD_REQUEST);
+
+    let uri = "/v1/puzzles/1/explanation?username=alice";
+    assert_eq!(app.get(uri).await.status, StatusCode::FORBIDDEN);
+    app.solve(1, "alice", false).await;
+    let response = app.get(uri).await;
+    assert_eq!(response.status, StatusCode::OK);
+    let shown = response.json();
+    assert_fields(&shown, &["title", "explanation", "moveComments"]);
+    assert_eq!(shown["title"], "The cap smash");
+    assert_eq!(shown["moveComments"][1]["comment"], "The only move");
+
+    // Puzzles without one, and ones whose explanation was removed
+    app.solve(2, "alice", true).await;
+    let uri = "/v1/puzzles/2/explanation?username=alice";
+    assert_eq!(app.get(uri).await.status, StatusCode::NOT_FOUND);
+    app.admin("PUT", "/v1/admin/puzzles/1/explanation", json!({}))
+        .await;
+    let uri = "/v1/puzzles/1/explanation?username=alice";
+    assert_eq!(app.get(uri).await.status, StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn solve_times_are_bucketed_and_compared_with_the_user() {
+    let app = TestApp::new().await;
+    for (username, solve_time_seconds) in [("alice", 8), ("bob", 25), ("carol", 25), ("dave", 700)]
+    {
+        let response = app
+            .post(
+                "/v1/puzzles/1",
+                json!({
+                    "id": 1,
+                    "username": username,
+                    "solved": true,
+                    "solution": SOLUTION,
+                    "solveTimeSeconds": solve_time_seconds,
+                }),
+            )
+            .await;
+        assert_eq!(response.status, StatusCode::OK);
+    }
+    // Failed attempts, and later attempts, aren't counted
+    app.solve(1, "erin", false).await;
+    app.solve(1, "alice", true).await;
+
+    let solve_times = app
+        .get("/v1/puzzles/1/solve-times?username=Bob")
+        .await
+        .json();
+    assert_fields(&solve_times, &["numSolves", "buckets", "user"]);
+    assert_eq!(solve_times["numSolves"], 4);
+    let buckets = solve_times["buckets"].as_array().unwrap();
+    assert_eq!(
+        buckets[0],
+        json!({"minSeconds": 0, "maxSeconds": 5, "numSolves": 0})
+    );
+    assert_eq!(buckets[1]["numSolves"], 1);
+    assert_eq!(
+        buckets[3],
+        json!({"minSeconds": 20, "maxSeconds": 30, "numSolves": 2})
+    );
+    assert_eq!(
+        buckets.last().unwrap(),
+        &json!({"minSeconds": 600, "maxSeconds": null, "numSolves": 1})
+    );
+    assert_eq!(
+        solve_times["user"],
+        json!({"solveTimeSeconds": 25, "fasterThanPercent": 25.0})
+    );
+    let solve_times = app
+        .get("/v1/puzzles/1/solve-times?username=alice")
+        .await
+        .json();
+    assert_eq!(solve_times["user"]["fasterThanPercent"], 75.0);
+    let solve_times = app
+        .get("/v1/puzzles/1/solve-times?username=erin")
+        .await
+        .json();
+    assert_eq!(solve_times["user"], json!(null));
+    assert_eq!(
+        app.get("/v1/puzzles/1/solve-times?username=a%20b")
+            .await
+            .status,
+        StatusCode::BAD_REQUEST
+    );
+    assert_eq!(
+        app.get("/v1/puzzles/6/solve-times").await.status,
+        StatusCode::NOT_FOUND
+    );
+}
+
+#[tokio::test]
+async fn site_stats_count_puzzles_attempts_and_active_users() {
+    let app = TestApp::new().await;
+    assert_eq!(
+        app.get("/v1/stats").await.json(),
+        json!({
+            "numPuzzles": 5,
+            "numAttempts": 0,
+            "numAttemptsLastDay": 0,
+            "numActiveUsersLastWeek": 0,
+            "solveRate": null,
+        })
+    );
+
+    app.solve(1, "alice", true).await;
+    app.solve(2, "alice", false).await;
+    app.solve(2, "alice", true).await;
+    app.solve(1, "bob", false).await;
+    app.solve(3, "carol", true).await;
+    // Two days ago, and two weeks ago
+    app.db()
+        .execute(
+            "UPDATE puzzle_attempts SET timestamp_seconds = timestamp_seconds - 2 * 86400
+            WHERE user_id = (SELECT id FROM users WHERE username = 'bob')",
+            [],
+        )
+        .unwrap();
+    app.db()
+        .execute(
+            "UPDATE puzzle_attempts SET timestamp_seconds = timestamp_seconds - 14 * 86400
+            WHERE user_id = (SELECT id FROM users WHERE username = 'carol')",
+            [],
+        )
+        .unwrap();
+    assert_eq!(
+        app.get("/v1/stats").await.json(),
+        json!({
+            "numPuzzles": 5,
+            "numAttempts": 5,
+            "numAttemptsLastDay": 3,
+            "numActiveUsersLastWeek": 2,
+            "solveRate": 0.5,
+        })
+    );
+}
+
+#[tokio::test]
+async fn recommendations_favour_themes_the_user_fails() {
+    let app = TestApp::new().await;
+    for id in [1, 2, 3] {
+        let response = app
+            .admin(
+                "PUT",
+                &format!("/v1/admin/puzzles/{id}/themes"),
+                json!({"themes": ["cap-smash"]}),
+            )
+            .await;
+        assert_eq!(response.status, StatusCode::OK);
+    }
+    // Puzzles 2 and 3 are three times as likely as 4 and 5. Puzzle 1 is attempted, and 6 isn't published.
+    // Spread over several users to stay under the rate limit
+    let mut num_themed = 0;
+    for username in ["alice", "carol", "dave", "erin"] {
+        app.solve(1, username, false).await;
+        for _ in 0..40 {
+            let puzzle = app
+                .get(&format!(
+                    "/v1/puzzles?username={username}&strategy=weaknesses"
+                ))
+                .await
+                .json();
+            let id = puzzle["id"].as_u64().unwrap();
+            assert!([2, 3, 4, 5].contains(&id));
+            if id <= 3 {
+                num_themed += 1;
+            }
+        }
+    }
+    assert!(num_themed > 95, "{num_themed} of 160 puzzles had the theme");
+
+    // New users don't get the introductory puzzles first
+    let mut ids = std::collections::BTreeSet::new();
+    for _ in 0..20 {
+        let puzzle = app
+            .get("/v1/puzzles?username=bob&strategy=weaknesses")
+            .await
+            .json();
+        ids.insert(puzzle["id"].as_u64().unwrap());
+    }
+    assert!(ids.len() > 1);
+    let response = app.get("/v1/puzzles?username=alice&strategy=hardest").await;
+    assert_eq!(response.status, StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn the_grpc_api_serves_puzzles_and_records_attempts() {
+    let app = TestApp::new().await;
+    let mut client = app.grpc().await;
+
+    let puzzle = client
+        .get_puzzle(grpc::GetPuzzleRequest { id: 2 })
+        .await
+        .unwrap()
+        .into_inner();
+    assert_eq!(puzzle.id, 2);
+    assert_eq!(puzzle.size, 6);
+    assert_eq!(puzzle.solution, ["d4-", "3e3+12", "*"]);
+    let status = client
+        .get_puzzle(grpc::GetPuzzleRequest { id: 6 })
+        .await
+        .unwrap_err();
+    assert_eq!(status.code(), tonic::Code::NotFound);
+
+    // Puzzle 3 is always served first
+    let puzzle = client
+        .next_puzzle(grpc::NextPuzzleRequest {
+            username: "alice".to_string(),
+            ..Default::default()
+        })
+        .await
+        .unwrap()
+        .into_inner();
+    assert_eq!(puzzle.id, 3);
+
+    let mut ratings = client
+        .watch_puzzle_ratings(grpc::WatchPuzzleRatingsRequest {})
+        .await
+        .unwrap()
+        .into_inner();
+    let result = client
+        .submit_attempt(grpc::SubmitAttemptRequest {
+            puzzle_id: 3,
+            username: "alice".to_string(),
+            solved: true,
+            solution: SOLUTION.map(String::from).to_vec(),
+            solve_time_seconds: 30,
+            ..Default::default()
+        })
+        .await
+        .unwrap()
+        .into_inner();
+    assert_eq!(result.attempt_number, 1);
+    assert!(result.rated && result.correct);
+    let change = result.rating_change.unwrap();
+    assert!(change.new_rating > change.old_rating);
+
+    let update = ratings.message().await.unwrap().unwrap();
+    assert_eq!(update.puzzle_id, 3);
+    assert_eq!(update.rating, change.puzzle_rating);
+
+    let rating = client
+        .get_user_rating(grpc::GetUserRatingRequest {
+            username: "Alice".to_string(),
+        })
+        .await
+        .unwrap()
+        .into_inner();
+    assert_eq!(rating.rating, change.new_rating);
+    assert_eq!(app.count("SELECT COUNT(*) FROM puzzle_attempts"), 1);
+
+    let status = client
+        .submit_attempt(grpc::SubmitAttemptRequest {
+            puzzle_id: 1,
+            username: "not a username".to_string(),
+            ..Default::default()
+        })
+        .await
+        .unwrap_err();
+    assert_eq!(status.code(), tonic::Code::InvalidArgument);
+}
+
+#[tokio::test]
+async fn only_one_anonymous_rated_attempt_per_puzzle_per_device() {
+    let mut config = tak_tactics_backend::config::Config::default();
+    config.rate_limit.trust_forwarded_for = true;
+    config.auth.secret = Some("test-secret".to_string());
+    let app = TestApp::with_config(config).await;
+    let frank = app.log_in("frank@example.com", "frank").await;
+
+    let attempt = |username: &str, device_id: &str, ip: &str, access_token: Option<&str>| {
+        let mut request = json_request(
+            "POST",
+            "/v1/puzzles/1",
+            json!({
+                "id": 1,
+                "username": username,
+                "solved": false,
+                "solution": [],
+                "solveTimeSeconds": 30,
+            }),
+        );
+        let headers = request.headers_mut();
+        headers.insert("x-device-id", device_id.parse().unwrap());
+        headers.insert("x-forwarded-for", ip.parse().unwrap());
+        if let Some(access_token) = access_token {
+            headers.insert(
+                header::AUTHORIZATION,
+                format!("Bearer {access_token}").parse().unwrap(),
+            );
+        }
+        request
+    };
+
+    let first = app
+        .request(attempt("guest1", "phone", "10.0.0.1", None))
+        .await;
+    assert_eq!(first.json()["rated"], true);
+    let puzzle_rating = first.json()["ratingChange"]["puzzleRating"].clone();
+
+    // A new name on the same device, or from the same IP, isn't rated
+    let same_device = app
+        .request(attempt("guest2", "phone", "10.0.0.2", None))
+        .await;
+    assert_eq!(same_device.json()["rated"], false);
+    let same_ip = app
+        .request(attempt("guest3", "laptop", "10.0.0.1", None))
+        .await;
+    assert_eq!(same_ip.json()["rated"], false);
+    assert_eq!(app.get("/v1/puzzles/1/rating").await.json(), puzzle_rating);
+    assert_eq!(
+        app.count("SELECT COUNT(*) FROM rated_attempts WHERE puzzle_id = 1"),
+        1
+    );
+
+    let elsewhere = app
+        .request(attempt("guest4", "tablet", "10.0.0.3", None))
+        .await;
+    assert_eq!(elsewhere.json()["rated"], true);
+    // The same device is fine for other puzzles
+    let other_puzzle = app.solve(2, "guest2", false).await;
+    assert_eq!(other_puzzle.json()["rated"], true);
+
+    // Logged in users are rated wherever they are
+    let logged_in = app
+        .request(attempt(
+            "frank",
+            "phone",
+            "10.0.0.1",
+            frank["accessToken"].as_str(),
+        ))
+        .await;
+    assert_eq!(logged_in.json()["rated"], true);
+}
+
+#[tokio::test]
+async fn a_failed_attempt_solved_on_the_retry_is_rated_as_a_draw() {
+    let app = TestApp::new().await;
+    let retry = |username: &str, solved: bool| {
+        let solution: &[&str] = if solved { &SOLUTION } else { &[] };
+        app.post(
+            "/v1/puzzles/1",
+            json!({
+                "id": 1,
+                "username": username,
+                "solved": solved,
+                "solution": solution,
+                "solveTimeSeconds": 30,
+                "retry": true,
+            }),
+        )
+    };
+
+    // Only a failed rated attempt can be retried
+    assert_eq!(retry("alice", true).await.status, StatusCode::BAD_REQUEST);
+    app.solve(1, "bob", true).await;
+    assert_eq!(retry("bob", true).await.status, StatusCode::BAD_REQUEST);
+
+    let failed = app.solve(1, "alice", false).await.json();
+    let rating_after_failure = failed["ratingChange"]["newRating"].clone();
+    let retried = retry("alice", true).await;
+    assert_eq!(retried.status, StatusCode::OK);
+    let retried = retried.json();
+    assert_eq!(retried["attemptNumber"], 2);
+    assert_eq!(retried["rated"], false);
+    assert_eq!(retried["retry"]["retryOf"], 1);
+    assert_eq!(retried["retry"]["score"], 0.5);
+    let change = &retried["retry"]["ratingChange"];
+    assert_eq!(change["oldRating"], rating_after_failure);
+    let rating = change["newRating"].as_f64().unwrap();
+    assert!(rating > rating_after_failure.as_f64().unwrap());
+    assert!(rating < failed["ratingChange"]["oldRating"].as_f64().unwrap());
+
+    // Both attempts are linked in the history
+    let history = app.get("/v1/users/alice/attempts").await.json();
+    assert_eq!(history["items"][0]["retryOf"], 1);
+    assert_eq!(history["items"][1]["solvedOnRetry"], true);
+
+    // Only once
+    assert_eq!(retry("alice", true).await.status, StatusCode::BAD_REQUEST);
+
+    // Replaying the attempts rates the draw the same way
+    let leaderboard = app.get("/v1/leaderboard").await.json();
+    storage::recompute_user_ratings(&mut app.db()).unwrap();
+    assert_eq!(app.get("/v1/leaderboard").await.json(), leaderboard);
+
+    // A failed retry leaves the loss, and a rated attempt elsewhere in between rules out a retry
+    app.solve(1, "carol", false).await;
+    let failed_retry = retry("carol", false).await.json();
+    assert_eq!(failed_retry["retry"]["score"], 0.0);
+    assert!(failed_retry["retry"]["ratingChange"].is_null());
+    app.solve(1, "dave", false).await;
+    app.solve(2, "dave", true).await;
+    assert_eq!(retry("dave", true).await.status, StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn live_sessions_only_start_on_published_puzzles() {
+    let app = TestApp::new().await;
+    for (puzzle_id, reply_type) in [(6, "error"), (1, "puzzle")] {
+        let mut socket = app.websocket("/v1/ws").await;
+        let start = json!({"type": "start", "username": "alice", "puzzleId": puzzle_id});
+        socket.send(Message::text(start.to_string())).await.unwrap();
+        let Some(Ok(Message::Text(reply))) = socket.next().await else {
+            panic!("No reply for puzzle {puzzle_id}");
+        };
+        let reply: serde_json::Value = serde_json::from_str(&reply).unwrap();
+        assert_eq!(reply["type"], reply_type, "{reply}");
+    }
+}
@@ -0,0 +1,147 @@
+use std::time::Duration;
+
+use axum::{
+    body::Body,
+    http::{Method, Request, StatusCode, header},
+};
+use serde_json::json;
+use tokio_stream::StreamExt;
+
+use crate::common::{TestApp, assert_fields};
+
+#[tokio::test]
+async fn health_checks_report_on_the_database() {
+    let app = TestApp::new().await;
+    let health = app.get("/healthz").await;
+    assert_eq!(health.status, StatusCode::OK);
+    assert_fields(
+        &health.json(),
+        &["status", "version", "uptimeSeconds", "database"],
+    );
+    assert_eq!(health.json()["database"], "ok");
+
+    assert_eq!(app.get("/livez").await.json()["status"], "ok");
+
+    // Background jobs are only started by `main`
+    let readiness = app.get("/readyz").await;
+    assert_eq!(readiness.status, StatusCode::SERVICE_UNAVAILABLE);
+    let readiness = readiness.json();
+    assert_eq!(readiness["migrations"], "ok");
+    assert_eq!(readiness["database"], "ok");
+    assert_eq!(readiness["backgroundJobs"], "not started");
+}
+
+#[tokio::test]
+async fn metrics_are_exported_for_prometheus() {
+    let app = TestApp::new().await;
+    app.solve(3, "alice", true).await;
+    let metrics = app.get("/metrics").await;
+    assert_eq!(metrics.status, StatusCode::OK);
+    assert!(
+        metrics.text().contains("http_requests_total"),
+        "{}",
+        metrics.text()
+    );
+}
+
+#[tokio::test]
+async fn database_maintenance_is_timed_in_the_metrics() {
+    let app = TestApp::new().await;
+    for job in ["database-maintenance", "wal-checkpoint"] {
+        let response = app
+            .admin("POST", &format!("/v1/admin/jobs/{job}/run"), json!(null))
+            .await;
+        assert_eq!(response.json()["lastError"], json!(null), "{job}");
+    }
+    let metrics = app.get("/metrics").await.text();
+    for step in ["analyze", "optimize", "wal_checkpoint"] {
+        assert!(
+            metrics.contains(&format!(
+                "db_maintenance_duration_seconds_count{{step=\"{step}\"}}"
+            )),
+            "{metrics}"
+        );
+    }
+}
+
+#[tokio::test]
+async fn the_api_is_documented() {
+    let app = TestApp::new().await;
+    let spec = app.get("/openapi.json").await.json();
+    assert_eq!(spec["servers"][0]["url"], "/v1");
+    assert!(spec["paths"]["/puzzles/{id}"]["post"].is_object());
+    let docs = app.get("/docs").await;
+    assert_eq!(docs.status, StatusCode::OK);
+    assert!(docs.text().contains("/openapi.json"));
+}
+
+#[tokio::test]
+async fn every_response_has_a_request_id() {
+    let app = TestApp::new().await;
+    let response = app.get("/livez").await;
+    assert_eq!(response.headers["x-request-id"].len(), 32);
+
+    let request = Request::get("/v1/puzzles/99")
+        .header("x-request-id", "my-request")
+        .body(Body::empty())
+        .unwrap();
+    let response = app.request(request).await;
+    assert_eq!(response.headers["x-request-id"], "my-request");
+    assert_eq!(response.json()["requestId"], "my-request");
+}
+
+#[tokio::test]
+async fn browsers_are_allowed_to_call_the_api() {
+    let app = TestApp::new().await;
+    let request = Request::builder()
+        .method(Method::OPTIONS)
+        .uri("/v1/puzzles/3")
+        .header(header::ORIGIN, "https://example.com")
+        .header(header::ACCESS_CONTROL_REQUEST_METHOD, "POST")
+        .header(header::ACCESS_CONTROL_REQUEST_HEADERS, "idempotency-key")
+        .body(Body::empty())
+        .unwrap();
+    let response = app.request(request).await;
+    assert_eq!(response.status, StatusCode::OK);
+    assert_eq!(response.headers[header::ACCESS_CONTROL_ALLOW_ORIGIN], "*");
+    let allowed_methods = response.headers[header::ACCESS_CONTROL_ALLOW_METHODS]
+        .to_str()
+        .unwrap();
+    assert!(allowed_methods.contains("POST"), "{allowed_methods}");
+}
+
+#[tokio::test]
+async fn websockets_need_an_upgrade() {
+    let app = TestApp::new().await;
+    assert!(app.get("/v1/ws").await.status.is_client_error());
+    assert!(app.get("/v1/races/room/ws").await.status.is_client_error());
+}
+
+#[tokio::test]
+async fn attempts_are_sent_as_server_sent_events() {
+    let app = TestApp::new().await;
+    let response = app
+        .open(
+            Request::get("/v1/events?username=alice")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(
+        response.headers()[header::CONTENT_TYPE],
+        "text/event-stream"
+    );
+
+    app.solve(3, "alice", true).await;
+    let mut events = response.into_body().into_data_stream();
+    let mut received = String::new();
+    while !(received.contains("event: userRating") && received.contains("event: puzzleRating")) {
+        let chunk = tokio::time::timeout(Duration::from_secs(5), events.next())
+            .await
+            .expect("Timed out waiting for events")
+            .unwrap()
+            .unwrap();
+        received.push_str(std::str::from_utf8(&chunk).unwrap());
+    }
+}
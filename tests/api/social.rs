@@ -0,0 +1,1047 @@
+use axum::http::{StatusCode, header};
+use futures_util::{SinkExt, StreamExt};
+use serde_json::json;
+use tak_tactics_backend::{grpc, importers};
+use tokio_tungstenite::tungstenite::Message;
+
+use crate::common::{TestApp, assert_fields, json_request, next_of_type};
+
+#[tokio::test]
+async fn collections_can_only_be_changed_by_their_owner() {
+    let app = TestApp::new().await;
+    let collection = app
+        .post(
+            "/v1/collections",
+            json!({"username": "alice", "name": "Favourites"}),
+        )
+        .await
+        .json();
+    assert_fields(&collection, &["slug", "owner", "name", "puzzleIds"]);
+    let slug = collection["slug"].as_str().unwrap();
+
+    let added = app
+        .post(
+            &format!("/v1/collections/{slug}/puzzles"),
+            json!({"username": "alice", "puzzleId": 2}),
+        )
+        .await;
+    assert_eq!(added.json()["puzzleIds"], json!([2]));
+    let added = app
+        .post(
+            &format!("/v1/collections/{slug}/puzzles"),
+            json!({"username": "alice", "puzzleId": 4}),
+        )
+        .await;
+    assert_eq!(added.json()["puzzleIds"], json!([2, 4]));
+    let not_owner = app
+        .post(
+            &format!("/v1/collections/{slug}/puzzles"),
+            json!({"username": "bob", "puzzleId": 1}),
+        )
+        .await;
+    assert_eq!(not_owner.status, StatusCode::FORBIDDEN);
+    // Puzzle 6 isn't published
+    let unpublished = app
+        .post(
+            &format!("/v1/collections/{slug}/puzzles"),
+            json!({"username": "alice", "puzzleId": 6}),
+        )
+        .await;
+    assert_eq!(unpublished.status, StatusCode::NOT_FOUND);
+
+    let next = app
+        .get(&format!("/v1/collections/{slug}/next?username=bob"))
+        .await;
+    assert_eq!(next.json()["id"], 2);
+    // Nor are puzzles that were added before they were unpublished served
+    app.db()
+        .execute(
+            "INSERT INTO collection_entries (collection_id, puzzle_id, position)
+            SELECT id, 6, -1 FROM collections",
+            [],
+        )
+        .unwrap();
+    let next = app
+        .get(&format!("/v1/collections/{slug}/next?username=bob"))
+        .await;
+    assert_eq!(next.json()["id"], 2);
+    app.delete(&format!("/v1/collections/{slug}/puzzles/6?username=alice"))
+        .await;
+
+    let removed = app
+        .delete(&format!("/v1/collections/{slug}/puzzles/2?username=alice"))
+        .await;
+    assert_eq!(removed.json()["puzzleIds"], json!([4]));
+    assert_eq!(
+        app.get(&format!("/v1/collections/{slug}")).await.json()["puzzleIds"],
+        json!([4])
+    );
+
+    let owned = app.get("/v1/users/alice/collections").await.json();
+    assert_eq!(owned.as_array().unwrap().len(), 1);
+    assert_eq!(owned[0]["slug"], slug);
+    assert_eq!(
+        app.get("/v1/collections/missing").await.status,
+        StatusCode::NOT_FOUND
+    );
+}
+
+#[tokio::test]
+async fn collections_need_a_valid_name() {
+    let app = TestApp::new().await;
+    let response = app
+        .post("/v1/collections", json!({"username": "alice", "name": ""}))
+        .await;
+    assert_eq!(response.status, StatusCode::BAD_REQUEST);
+    assert_eq!(response.json()["field"], "name");
+    assert_eq!(app.count("SELECT COUNT(*) FROM collections"), 0);
+}
+
+#[tokio::test]
+async fn the_leaderboard_is_ordered_by_rating() {
+    let app = TestApp::new().await;
+    app.solve(3, "alice", true).await;
+    app.solve(3, "bob", false).await;
+
+    let leaderboard = app.get("/v1/leaderboard").await.json();
+    let leaderboard = leaderboard.as_array().unwrap();
+    assert_eq!(leaderboard.len(), 2);
+    assert_fields(
+        &leaderboard[0],
+        &["rank", "username", "rating", "numSolved"],
+    );
+    assert_eq!(leaderboard[0]["username"], "alice");
+    assert_eq!(leaderboard[0]["rank"], 1);
+    assert_eq!(leaderboard[0]["numSolved"], 1);
+    assert_eq!(leaderboard[1]["username"], "bob");
+    assert_eq!(leaderboard[1]["numSolved"], 0);
+}
+
+#[tokio::test]
+async fn past_leaderboards_are_kept_in_snapshots() {
+    let app = TestApp::new().await;
+    app.solve(3, "alice", true).await;
+    app.solve(3, "bob", false).await;
+    let leaderboard = app.get("/v1/leaderboard").await.json();
+
+    let weeks = app
+        .get("/v1/leaderboard/snapshots?period=week")
+        .await
+        .json();
+    assert_eq!(weeks.as_array().unwrap().len(), 1);
+    assert_fields(
+        &weeks[0],
+        &["period", "periodStart", "periodEnd", "takenSeconds"],
+    );
+    assert_eq!(weeks[0]["period"], "week");
+    let months = app
+        .get("/v1/leaderboard/snapshots?period=month")
+        .await
+        .json();
+    assert_eq!(months.as_array().unwrap().len(), 1);
+
+    // Later attempts don't change the snapshot
+    app.solve(4, "bob", true).await;
+    app.solve(5, "bob", true).await;
+    let start = weeks[0]["periodStart"].as_str().unwrap();
+    let snapshot = app
+        .get(&format!("/v1/leaderboard/snapshots/week/{start}"))
+        .await
+        .json();
+    assert_eq!(snapshot["periodEnd"], weeks[0]["periodEnd"]);
+    assert_eq!(snapshot["entries"], leaderboard);
+    assert_eq!(
+        app.get("/v1/leaderboard/snapshots?period=week")
+            .await
+            .json(),
+        weeks
+    );
+
+    let response = app.get("/v1/leaderboard/snapshots/week/2000-01-03").await;
+    assert_eq!(response.status, StatusCode::NOT_FOUND);
+    let response = app.get("/v1/leaderboard/snapshots?period=year").await;
+    assert_eq!(response.status, StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn users_can_follow_and_unfollow_each_other() {
+    let app = TestApp::new().await;
+    let follow = app
+        .post("/v1/users/bob/follow", json!({"username": "alice"}))
+        .await;
+    assert_eq!(follow.status, StatusCode::OK);
+    app.post("/v1/users/carol/follow", json!({"username": "alice"}))
+        .await;
+    let themselves = app
+        .post("/v1/users/alice/follow", json!({"username": "alice"}))
+        .await;
+    assert_eq!(themselves.status, StatusCode::BAD_REQUEST);
+    assert_eq!(
+        app.get("/v1/users/alice/following").await.json(),
+        json!(["bob", "carol"])
+    );
+
+    app.solve(1, "bob", true).await;
+    app.solve(1, "dave", true).await;
+    let leaderboard = app
+        .get("/v1/users/alice/following/leaderboard")
+        .await
+        .json();
+    assert_eq!(leaderboard.as_array().unwrap().len(), 1);
+    assert_eq!(leaderboard[0]["username"], "bob");
+
+    app.delete("/v1/users/carol/follow?username=alice").await;
+    assert_eq!(
+        app.get("/v1/users/alice/following").await.json(),
+        json!(["bob"])
+    );
+    assert_eq!(app.count("SELECT COUNT(*) FROM follows"), 1);
+}
+
+#[tokio::test]
+async fn the_daily_puzzle_can_be_embedded_on_other_sites() {
+    let app = TestApp::new().await;
+    let response = app.get("/v1/embed/daily").await;
+    assert_eq!(response.status, StatusCode::OK);
+    assert_eq!(response.headers[header::ACCESS_CONTROL_ALLOW_ORIGIN], "*");
+    let cache_control = response.headers[header::CACHE_CONTROL].to_str().unwrap();
+    let max_age: u64 = cache_control
+        .strip_prefix("public, max-age=")
+        .unwrap()
+        .parse()
+        .unwrap();
+    assert!(max_age <= 86400);
+    let embedded = response.json();
+    assert_fields(
+        &embedded,
+        &[
+            "day",
+            "id",
+            "size",
+            "komi",
+            "rootTPS",
+            "defenderStartMove",
+            "imageUrl",
+        ],
+    );
+    let day = embedded["day"].as_i64().unwrap();
+    let id = embedded["id"].as_u64().unwrap();
+    assert_eq!(app.get("/v1/daily").await.json()["id"], id);
+    assert_eq!(embedded["imageUrl"], format!("/v1/puzzles/{id}/image.svg"));
+
+    let response = app.get(&format!("/v1/embed/daily/{day}")).await;
+    assert_eq!(
+        response.headers[header::CACHE_CONTROL],
+        "public, max-age=31536000, immutable"
+    );
+    assert_eq!(response.json(), embedded);
+    // Past days without a puzzle aren't given one, and future days can't be seen early
+    for day in [day - 1, day + 1] {
+        let response = app.get(&format!("/v1/embed/daily/{day}")).await;
+        assert_eq!(response.status, StatusCode::NOT_FOUND);
+    }
+    assert_eq!(app.count("SELECT COUNT(*) FROM daily_puzzles"), 1);
+}
+
+#[tokio::test]
+async fn the_daily_puzzle_is_the_same_all_day() {
+    let app = TestApp::new().await;
+    let daily = app.get("/v1/daily").await.json();
+    let puzzle_id = daily["id"].as_u64().unwrap() as u32;
+    assert!((1..=5).contains(&puzzle_id));
+    assert_eq!(app.get("/v1/daily").await.json()["id"], puzzle_id);
+    assert_eq!(app.count("SELECT COUNT(*) FROM daily_puzzles"), 1);
+
+    app.post("/v1/users/bob/follow", json!({"username": "alice"}))
+        .await;
+    app.solve(puzzle_id, "bob", true).await;
+    let comparison = app.get("/v1/users/alice/following/daily").await.json();
+    assert_fields(&comparison, &["day", "puzzleId", "results"]);
+    assert_eq!(comparison["puzzleId"], puzzle_id);
+    assert_eq!(
+        comparison["results"],
+        json!([
+            {"username": "alice", "attempted": false, "solved": false, "solveTimeSeconds": null},
+            {"username": "bob", "attempted": true, "solved": true, "solveTimeSeconds": 30},
+        ])
+    );
+}
+
+#[tokio::test]
+async fn teams_are_ranked_by_their_members_solves() {
+    let app = TestApp::new().await;
+    let team = app
+        .post(
+            "/v1/teams",
+            json!({"username": "alice", "name": "Flatstones"}),
+        )
+        .await
+        .json();
+    assert_fields(&team, &["id", "name", "members"]);
+    assert_eq!(team["members"], json!(["alice"]));
+    let team_id = team["id"].as_u64().unwrap();
+    let taken = app
+        .post(
+            "/v1/teams",
+            json!({"username": "bob", "name": "Flatstones"}),
+        )
+        .await;
+    assert_eq!(taken.status, StatusCode::CONFLICT);
+    let other_team = app
+        .post(
+            "/v1/teams",
+            json!({"username": "carol", "name": "Capstones"}),
+        )
+        .await
+        .json();
+
+    let joined = app
+        .post(
+            &format!("/v1/teams/{team_id}/join"),
+            json!({"username": "bob"}),
+        )
+        .await;
+    assert_eq!(joined.json()["members"], json!(["alice", "bob"]));
+    assert_eq!(
+        app.post("/v1/teams/99/join", json!({"username": "bob"}))
+            .await
+            .status,
+        StatusCode::NOT_FOUND
+    );
+
+    app.solve(1, "alice", true).await;
+    app.solve(2, "bob", true).await;
+    app.solve(1, "carol", false).await;
+
+    let leaderboard = app.get("/v1/teams").await.json();
+    assert_fields(
+        &leaderboard[0],
+        &[
+            "rank",
+            "id",
+            "name",
+            "numMembers",
+            "averageRating",
+            "totalSolved",
+        ],
+    );
+    assert_eq!(leaderboard[0]["id"], team_id);
+    assert_eq!(leaderboard[0]["numMembers"], 2);
+    assert_eq!(leaderboard[0]["totalSolved"], 2);
+    assert_eq!(leaderboard[1]["id"], other_team["id"]);
+
+    let weekly = app.get("/v1/teams/weekly").await.json();
+    assert_fields(
+        &weekly,
+        &["week", "startSeconds", "endSeconds", "standings"],
+    );
+    assert_eq!(weekly["standings"][0]["puzzlesSolved"], 2);
+    assert_eq!(weekly["standings"][1]["puzzlesAttempted"], 1);
+
+    app.post(
+        &format!("/v1/teams/{team_id}/leave"),
+        json!({"username": "bob"}),
+    )
+    .await;
+    assert_eq!(
+        app.get(&format!("/v1/teams/{team_id}")).await.json()["members"],
+        json!(["alice"])
+    );
+}
+
+#[tokio::test]
+async fn seasons_have_their_own_ratings_and_keep_past_standings() {
+    let app = TestApp::new().await;
+    let solved = app.solve(3, "alice", true).await.json();
+    app.solve(3, "bob", false).await;
+    app.db()
+        .execute_batch(
+            "INSERT INTO seasons (start, end) VALUES ('2000-01-01', '2000-04-01');
+            INSERT INTO season_ratings (season_start, username, rating, deviation, volatility, num_attempts, num_solved)
+            VALUES ('2000-01-01', 'alice', 1400, 200, 0.06, 3, 1);",
+        )
+        .unwrap();
+
+    let ratings = app.get("/v1/users/alice/ratings").await.json();
+    assert_fields(&ratings, &["lifetime", "currentSeason", "pastSeasons"]);
+    assert_eq!(
+        ratings["lifetime"]["rating"],
+        solved["ratingChange"]["newRating"]
+    );
+    let current = &ratings["currentSeason"];
+    assert_fields(
+        current,
+        &[
+            "start",
+            "end",
+            "rank",
+            "rating",
+            "deviation",
+            "numAttempts",
+            "numSolved",
+        ],
+    );
+    assert_eq!(current["rating"], solved["ratingChange"]["newRating"]);
+    assert_eq!(current["rank"], 1);
+    assert_eq!(current["numAttempts"], 1);
+    assert_eq!(ratings["pastSeasons"][0]["start"], "2000-01-01");
+    assert_eq!(ratings["pastSeasons"][0]["rating"], 1400.0);
+
+    let seasons = app.get("/v1/seasons").await.json();
+    assert_eq!(seasons.as_array().unwrap().len(), 2);
+    assert_fields(&seasons[0], &["start", "end", "current", "numPlayers"]);
+    assert_eq!(seasons[0]["current"], true);
+    assert_eq!(seasons[0]["numPlayers"], 2);
+    assert_eq!(seasons[1]["current"], false);
+
+    let standings = app.get("/v1/seasons/current/standings").await.json();
+    assert_eq!(standings[0]["username"], "alice");
+    assert_eq!(standings[1]["username"], "bob");
+    let standings = app.get("/v1/seasons/2000-01-01/standings").await.json();
+    assert_eq!(standings.as_array().unwrap().len(), 1);
+    let response = app.get("/v1/seasons/1999-01-01/standings").await;
+    assert_eq!(response.status, StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn users_can_store_their_settings() {
+    let app = TestApp::new().await;
+    let defaults = json!({
+        "preferredSizes": [],
+        "ratedByDefault": true,
+        "hideSolveTimes": false,
+        "normalizeColors": false,
+    });
+    assert_eq!(app.get("/v1/users/alice/settings").await.json(), defaults);
+
+    let settings = json!({
+        "preferredSizes": [6, 5, 6],
+        "ratedByDefault": false,
+        "hideSolveTimes": true,
+        "normalizeColors": true,
+    });
+    let response = app
+        .request(json_request("PUT", "/v1/users/Alice/settings", settings))
+        .await;
+    assert_eq!(response.status, StatusCode::OK);
+    let stored = json!({
+        "preferredSizes": [5, 6],
+        "ratedByDefault": false,
+        "hideSolveTimes": true,
+        "normalizeColors": true,
+    });
+    assert_eq!(app.get("/v1/users/alice/settings").await.json(), stored);
+    assert_eq!(app.get("/v1/users/bob/settings").await.json(), defaults);
+
+    let response = app
+        .request(json_request(
+            "PUT",
+            "/v1/users/alice/settings",
+            json!({
+                "preferredSizes": [9],
+                "ratedByDefault": true,
+                "hideSolveTimes": false,
+                "normalizeColors": false,
+            }),
+        ))
+        .await;
+    assert_eq!(response.status, StatusCode::BAD_REQUEST);
+    assert_eq!(response.json()["field"], "preferredSizes");
+    // Every setting must be sent
+    let response = app
+        .request(json_request(
+            "PUT",
+            "/v1/users/alice/settings",
+            json!({"preferredSizes": []}),
+        ))
+        .await;
+    assert_eq!(response.status, StatusCode::UNPROCESSABLE_ENTITY);
+    assert_eq!(app.get("/v1/users/alice/settings").await.json(), stored);
+
+    // Settings are deleted with the user
+    app.solve(1, "alice", true).await;
+    let response = app.admin("DELETE", "/v1/users/alice", json!(null)).await;
+    assert_eq!(response.status, StatusCode::NO_CONTENT);
+    assert_eq!(app.get("/v1/users/alice/settings").await.json(), defaults);
+}
+
+#[tokio::test]
+async fn users_are_notified_of_resolved_reports_and_new_puzzles() {
+    let app = TestApp::new().await;
+    let response = app
+        .request(json_request(
+            "PUT",
+            "/v1/users/bob/settings",
+            json!({
+                "preferredSizes": [5, 6],
+                "ratedByDefault": true,
+                "hideSolveTimes": false,
+                "normalizeColors": false,
+            }),
+        ))
+        .await;
+    assert_eq!(response.status, StatusCode::OK);
+    let report = app
+        .post(
+            "/v1/puzzles/1/reports",
+            json!({"username": "alice", "reason": "Wrong solution"}),
+        )
+        .await
+        .json();
+    let report_id = report["id"].as_i64().unwrap();
+    // Resolving a report again doesn't send another notification
+    for _ in 0..2 {
+        let response = app
+            .admin(
+                "POST",
+                &format!("/v1/admin/reports/{report_id}/resolve"),
+                json!({}),
+            )
+            .await;
+        assert_eq!(response.status, StatusCode::OK);
+    }
+
+    // Only users who prefer the puzzle's size are told about it
+    let file = json!([
+        {"tps": "x5/x5/x5/x5/x5 1 1", "setupMove": "a1", "solution": ["e5"]},
+        {"tps": "x4/x4/x4/x4 1 1", "setupMove": "a1", "solution": ["d4"]},
+    ]);
+    let puzzles =
+        importers::parse(&file.to_string(), importers::Format::PuzzletakJson, true).unwrap();
+    importers::import(&mut app.db(), puzzles).unwrap();
+
+    let notifications = app.get("/v1/users/Alice/notifications").await.json();
+    let items = notifications["items"].as_array().unwrap();
+    assert_eq!(items.len(), 1);
+    assert_fields(
+        &items[0],
+        &["id", "kind", "data", "createdSeconds", "readSeconds"],
+    );
+    assert_eq!(items[0]["kind"], "reportResolved");
+    assert_eq!(items[0]["data"]["id"], report_id);
+    assert_eq!(items[0]["readSeconds"], json!(null));
+    let notifications = app.get("/v1/users/bob/notifications").await.json();
+    let items = notifications["items"].as_array().unwrap();
+    assert_eq!(items.len(), 1);
+    assert_eq!(items[0]["kind"], "newPuzzle");
+    assert_eq!(items[0]["data"]["size"], 5);
+
+    // Other users' notifications can't be marked as read
+    let alice_id = app.get("/v1/users/alice/notifications").await.json()["items"][0]["id"].clone();
+    let response = app
+        .post(
+            "/v1/users/bob/notifications/read",
+            json!({"ids": [alice_id]}),
+        )
+        .await;
+    assert_eq!(response.json(), json!({"numMarked": 0}));
+    let response = app
+        .post("/v1/users/alice/notifications/read", json!({}))
+        .await;
+    assert_eq!(response.json(), json!({"numMarked": 1}));
+    let notifications = app.get("/v1/users/alice/notifications").await.json();
+    assert!(notifications["items"][0]["readSeconds"].is_u64());
+    let response = app
+        .post(
+            "/v1/users/alice/notifications/read",
+            json!({"ids": [alice_id]}),
+        )
+        .await;
+    assert_eq!(response.json(), json!({"numMarked": 0}));
+}
+
+#[tokio::test]
+async fn users_can_log_in_with_a_link_sent_by_email() {
+    let mut config = tak_tactics_backend::config::Config::default();
+    config.auth.secret = Some("test-secret".to_string());
+    config.auth.magic_link_url = "https://example.com/login?token={token}".to_string();
+    let app = TestApp::with_config(config).await;
+
+    let link = app
+        .post("/v1/auth/magic-link", json!({"email": "Alice@example.com"}))
+        .await;
+    assert_eq!(
+        link.status,
+        StatusCode::BAD_REQUEST,
+        "New emails need a username"
+    );
+    let link = app
+        .post(
+            "/v1/auth/magic-link",
+            json!({"email": "Alice@example.com", "username": "Alice"}),
+        )
+        .await;
+    assert_eq!(link.status, StatusCode::ACCEPTED);
+    let sent = app.outbox.sent();
+    assert_eq!(sent.len(), 1);
+    assert_eq!(sent[0].to, "alice@example.com");
+    let token = sent[0]
+        .text
+        .split_whitespace()
+        .find_map(|word| word.strip_prefix("https://example.com/login?token="))
+        .unwrap()
+        .to_string();
+
+    let forged = format!("{}0.{}", &token[..2], &token[2..]);
+    for bad_token in ["", "nonsense", forged.as_str()] {
+        let login = app
+            .post("/v1/auth/magic-link/verify", json!({"token": bad_token}))
+            .await;
+        assert_eq!(login.status, StatusCode::UNAUTHORIZED);
+    }
+    let login = app
+        .post("/v1/auth/magic-link/verify", json!({"token": token}))
+        .await;
+    assert_eq!(login.status, StatusCode::OK);
+    let login = login.json();
+    assert_eq!(login["username"], "alice");
+    let reused = app
+        .post("/v1/auth/magic-link/verify", json!({"token": token}))
+        .await;
+    assert_eq!(
+        reused.status,
+        StatusCode::UNAUTHORIZED,
+        "Links only work once"
+    );
+
+    let access_token = login["accessToken"].as_str().unwrap();
+    let session = app
+        .as_user(access_token, "GET", "/v1/auth/session", json!(null))
+        .await;
+    assert_eq!(session.status, StatusCode::OK);
+    assert_eq!(session.json()["username"], "alice");
+    assert_eq!(
+        app.get("/v1/auth/session").await.status,
+        StatusCode::UNAUTHORIZED
+    );
+
+    // The email is linked to its username from now on
+    app.post(
+        "/v1/auth/magic-link",
+        json!({"email": "alice@example.com", "username": "mallory"}),
+    )
+    .await;
+    assert!(app.outbox.sent()[1].text.contains("log in as alice"));
+    let taken = app
+        .post(
+            "/v1/auth/magic-link",
+            json!({"email": "mallory@example.com", "username": "alice"}),
+        )
+        .await;
+    assert_eq!(taken.status, StatusCode::CONFLICT);
+}
+
+#[tokio::test]
+async fn usernames_that_have_been_played_as_cant_be_claimed_by_email() {
+    let mut config = tak_tactics_backend::config::Config::default();
+    config.auth.secret = Some("test-secret".to_string());
+    let app = TestApp::with_config(config).await;
+    assert_eq!(app.solve(1, "bob", true).await.status, StatusCode::OK);
+
+    let link = app
+        .post(
+            "/v1/auth/magic-link",
+            json!({"email": "mallory@example.com", "username": "Bob"}),
+        )
+        .await;
+    assert_eq!(link.status, StatusCode::CONFLICT);
+    assert!(app.outbox.sent().is_empty());
+    assert_eq!(app.solve(2, "bob", true).await.status, StatusCode::OK);
+
+    // Nor once a link has been sent, if someone plays as the username before it's used
+    let link = app
+        .post(
+            "/v1/auth/magic-link",
+            json!({"email": "mallory@example.com", "username": "carol"}),
+        )
+        .await;
+    assert_eq!(link.status, StatusCode::ACCEPTED);
+    assert_eq!(app.solve(1, "carol", true).await.status, StatusCode::OK);
+    let token = app.outbox.sent()[0]
+        .text
+        .split_whitespace()
+        .find_map(|word| word.split_once("token="))
+        .unwrap()
+        .1
+        .to_string();
+    let login = app
+        .post("/v1/auth/magic-link/verify", json!({"token": token}))
+        .await;
+    assert_eq!(login.status, StatusCode::CONFLICT);
+    assert_eq!(app.count("SELECT COUNT(*) FROM user_emails"), 0);
+}
+
+#[tokio::test]
+async fn login_by_email_is_off_without_a_secret() {
+    let app = TestApp::new().await;
+    let link = app
+        .post(
+            "/v1/auth/magic-link",
+            json!({"email": "alice@example.com", "username": "alice"}),
+        )
+        .await;
+    assert_eq!(link.status, StatusCode::NOT_FOUND);
+    assert!(app.outbox.sent().is_empty());
+}
+
+#[tokio::test]
+async fn sessions_are_refreshed_and_revoked() {
+    let mut config = tak_tactics_backend::config::Config::default();
+    config.auth.secret = Some("test-secret".to_string());
+    let app = TestApp::with_config(config).await;
+    let tokens = app.log_in("alice@example.com", "alice").await;
+
+    let refreshed = app
+        .post(
+            "/v1/auth/refresh",
+            json!({"refreshToken": tokens["refreshToken"]}),
+        )
+        .await;
+    assert_eq!(refreshed.status, StatusCode::OK);
+    let refreshed = refreshed.json();
+    assert_eq!(refreshed["sessionId"], tokens["sessionId"]);
+    assert_ne!(refreshed["refreshToken"], tokens["refreshToken"]);
+    let access_token = refreshed["accessToken"].as_str().unwrap();
+    let session = app
+        .as_user(access_token, "GET", "/v1/auth/session", json!(null))
+        .await;
+    assert_eq!(session.json()["username"], "alice");
+
+    // Using a replaced refresh token again ends the session, for whoever has the new one too
+    let reused = app
+        .post(
+            "/v1/auth/refresh",
+            json!({"refreshToken": tokens["refreshToken"]}),
+        )
+        .await;
+    assert_eq!(reused.status, StatusCode::UNAUTHORIZED);
+    let session = app
+        .as_user(access_token, "GET", "/v1/auth/session", json!(null))
+        .await;
+    assert_eq!(session.status, StatusCode::UNAUTHORIZED);
+    let refresh = app
+        .post(
+            "/v1/auth/refresh",
+            json!({"refreshToken": refreshed["refreshToken"]}),
+        )
+        .await;
+    assert_eq!(refresh.status, StatusCode::UNAUTHORIZED);
+
+    let phone = app.log_in("alice@example.com", "alice").await;
+    let laptop = app.log_in("alice@example.com", "alice").await;
+    let laptop_token = laptop["accessToken"].as_str().unwrap();
+    let sessions = app
+        .as_user(laptop_token, "GET", "/v1/auth/sessions", json!(null))
+        .await
+        .json();
+    assert_eq!(sessions.as_array().unwrap().len(), 2);
+    assert_eq!(sessions[0]["id"], laptop["sessionId"]);
+    assert_eq!(sessions[0]["current"], true);
+    assert_eq!(sessions[1]["current"], false);
+    let revoked = app
+        .as_user(
+            laptop_token,
+            "DELETE",
+            &format!("/v1/auth/sessions/{}", phone["sessionId"]),
+            json!(null),
+        )
+        .await;
+    assert_eq!(revoked.status, StatusCode::NO_CONTENT);
+    let phone_token = phone["accessToken"].as_str().unwrap();
+    let session = app
+        .as_user(phone_token, "GET", "/v1/auth/session", json!(null))
+        .await;
+    assert_eq!(session.status, StatusCode::UNAUTHORIZED);
+
+    let logout = app
+        .as_user(laptop_token, "POST", "/v1/auth/logout", json!(null))
+        .await;
+    assert_eq!(logout.status, StatusCode::NO_CONTENT);
+    let session = app
+        .as_user(laptop_token, "GET", "/v1/auth/session", json!(null))
+        .await;
+    assert_eq!(session.status, StatusCode::UNAUTHORIZED);
+
+    let tablet = app.log_in("alice@example.com", "alice").await;
+    let revoked = app
+        .admin("DELETE", "/v1/admin/users/alice/sessions", json!(null))
+        .await;
+    assert_eq!(revoked.json()["numRevoked"], 1);
+    let refresh = app
+        .post(
+            "/v1/auth/refresh",
+            json!({"refreshToken": tablet["refreshToken"]}),
+        )
+        .await;
+    assert_eq!(refresh.status, StatusCode::UNAUTHORIZED);
+}
+
+#[tokio::test]
+async fn claimed_usernames_can_only_be_used_by_their_owner() {
+    let mut config = tak_tactics_backend::config::Config::default();
+    config.auth.secret = Some("test-secret".to_string());
+    let app = TestApp::with_config(config).await;
+
+    // Nobody has claimed `bob`, so anyone may use it
+    assert_eq!(app.solve(1, "bob", true).await.status, StatusCode::OK);
+    assert_eq!(
+        app.get("/v1/users/bob/settings").await.status,
+        StatusCode::OK
+    );
+
+    let alice = app.log_in("alice@example.com", "alice").await;
+    let alice_token = alice["accessToken"].as_str().unwrap();
+    let mallory = app.log_in("mallory@example.com", "mallory").await;
+    let mallory_token = mallory["accessToken"].as_str().unwrap();
+
+    assert_eq!(
+        app.solve(1, "alice", true).await.status,
+        StatusCode::UNAUTHORIZED
+    );
+    assert_eq!(
+        app.get("/v1/puzzles?username=Alice").await.status,
+        StatusCode::UNAUTHORIZED
+    );
+    assert_eq!(
+        app.get("/v1/users/alice/notifications").await.status,
+        StatusCode::UNAUTHORIZED
+    );
+    let solve = json!({
+        "id": 1,
+        "username": "alice",
+        "solved": true,
+        "solution": crate::common::SOLUTION,
+        "solveTimeSeconds": 30,
+    });
+    let as_mallory = app
+        .as_user(mallory_token, "POST", "/v1/puzzles/1", solve.clone())
+        .await;
+    assert_eq!(as_mallory.status, StatusCode::FORBIDDEN);
+    let as_mallory = app
+        .as_user(
+            mallory_token,
+            "GET",
+            "/v1/users/alice/settings",
+            json!(null),
+        )
+        .await;
+    assert_eq!(as_mallory.status, StatusCode::FORBIDDEN);
+
+    let as_alice = app
+        .as_user(alice_token, "POST", "/v1/puzzles/1", solve)
+        .await;
+    assert_eq!(as_alice.status, StatusCode::OK);
+    let as_alice = app
+        .as_user(alice_token, "GET", "/v1/users/alice/settings", json!(null))
+        .await;
+    assert_eq!(as_alice.status, StatusCode::OK);
+    // Public pages about a user don't need their token
+    assert_eq!(
+        app.get("/v1/users/alice/achievements").await.status,
+        StatusCode::OK
+    );
+}
+
+#[tokio::test]
+async fn access_tokens_follow_their_user_when_renamed() {
+    let mut config = tak_tactics_backend::config::Config::default();
+    config.auth.secret = Some("test-secret".to_string());
+    let app = TestApp::with_config(config).await;
+    let alice = app.log_in("alice@example.com", "alice").await;
+    let token = alice["accessToken"].as_str().unwrap();
+    let solve = json!({
+        "id": 1,
+        "username": "alice",
+        "solved": true,
+        "solution": crate::common::SOLUTION,
+        "solveTimeSeconds": 30,
+    });
+    let response = app.as_user(token, "POST", "/v1/puzzles/1", solve).await;
+    assert_eq!(response.status, StatusCode::OK);
+    let response = app
+        .admin(
+            "POST",
+            "/v1/admin/users/alice/rename",
+            json!({"username": "Alicia"}),
+        )
+        .await;
+    assert_eq!(response.status, StatusCode::OK);
+
+    let session = app
+        .as_user(token, "GET", "/v1/auth/session", json!(null))
+        .await;
+    assert_eq!(session.json()["username"], "alicia");
+    let response = app
+        .as_user(token, "GET", "/v1/users/alicia/settings", json!(null))
+        .await;
+    assert_eq!(response.status, StatusCode::OK);
+    let response = app
+        .as_user(token, "GET", "/v1/users/alice/settings", json!(null))
+        .await;
+    assert_eq!(response.status, StatusCode::FORBIDDEN);
+}
+
+#[tokio::test]
+async fn claimed_usernames_cant_be_smuggled_past_the_check() {
+    let mut config = tak_tactics_backend::config::Config::default();
+    config.auth.secret = Some("test-secret".to_string());
+    let app = TestApp::with_config(config).await;
+    app.log_in("alice@example.com", "alice").await;
+    let solve = |username: &str| {
+        json!({
+            "id": 1,
+            "username": username,
+            "solved": true,
+            "solution": crate::common::SOLUTION,
+            "solveTimeSeconds": 30,
+        })
+    };
+
+    // An unclaimed username in the query string doesn't cover up the one in the body
+    let response = app.post("/v1/puzzles/1?username=bob", solve("alice")).await;
+    assert_eq!(response.status, StatusCode::BAD_REQUEST);
+    assert_eq!(response.json()["field"], "username");
+    let response = app.post("/v1/puzzles/1?username=Bob", solve("bob")).await;
+    assert_eq!(response.status, StatusCode::OK);
+
+    // Bodies are read whenever the handler would read them as JSON
+    for content_type in [
+        "Application/JSON",
+        "application/x+json",
+        "application/json; charset=utf-8",
+    ] {
+        let mut request = json_request("POST", "/v1/puzzles/1", solve("alice"));
+        request
+            .headers_mut()
+            .insert(header::CONTENT_TYPE, content_type.parse().unwrap());
+        let response = app.request(request).await;
+        assert_eq!(response.status, StatusCode::UNAUTHORIZED, "{content_type}");
+    }
+    assert_eq!(app.count("SELECT COUNT(*) FROM puzzle_attempts"), 1);
+}
+
+#[tokio::test]
+async fn claimed_usernames_need_a_token_over_websockets_and_grpc() {
+    let mut config = tak_tactics_backend::config::Config::default();
+    config.auth.secret = Some("test-secret".to_string());
+    let app = TestApp::with_config(config).await;
+    let alice = app.log_in("alice@example.com", "alice").await;
+    let alice_token = alice["accessToken"].as_str().unwrap();
+    let mallory = app.log_in("mallory@example.com", "mallory").await;
+    let mallory_token = mallory["accessToken"].as_str().unwrap();
+
+    let start_as_alice = |uri: String| {
+        let app = &app;
+        async move {
+            let mut socket = app.websocket(&uri).await;
+            let start = json!({"type": "start", "username": "alice", "puzzleId": 1});
+            socket.send(Message::text(start.to_string())).await.unwrap();
+            let Some(Ok(Message::Text(reply))) = socket.next().await else {
+                panic!("No reply on {uri}");
+            };
+            serde_json::from_str::<serde_json::Value>(&reply).unwrap()
+        }
+    };
+    let reply = start_as_alice("/v1/ws".to_string()).await;
+    assert_eq!(reply["type"], "error");
+    assert_eq!(reply["message"], "Log in to play as this user");
+    let reply = start_as_alice(format!("/v1/ws?accessToken={mallory_token}")).await;
+    assert_eq!(reply["message"], "Logged in as another user");
+    let reply = start_as_alice(format!("/v1/ws?accessToken={alice_token}")).await;
+    assert_eq!(reply["type"], "puzzle");
+
+    // Race rooms take the username in the query string, so the token goes there too
+    let response = app.get("/v1/races/room/ws?username=alice").await;
+    assert_eq!(response.status, StatusCode::UNAUTHORIZED);
+    let response = app
+        .get(&format!(
+            "/v1/races/room/ws?username=alice&accessToken={alice_token}"
+        ))
+        .await;
+    assert!(response.status.is_client_error());
+    assert_ne!(response.status, StatusCode::UNAUTHORIZED);
+
+    let mut client = app.grpc().await;
+    let next_puzzle = |token: Option<&str>| {
+        let mut request = tonic::Request::new(grpc::NextPuzzleRequest {
+            username: "alice".to_string(),
+            ..Default::default()
+        });
+        if let Some(token) = token {
+            request
+                .metadata_mut()
+                .insert("authorization", format!("Bearer {token}").parse().unwrap());
+        }
+        request
+    };
+    let status = client.next_puzzle(next_puzzle(None)).await.unwrap_err();
+    assert_eq!(status.code(), tonic::Code::Unauthenticated);
+    let status = client
+        .next_puzzle(next_puzzle(Some(mallory_token)))
+        .await
+        .unwrap_err();
+    assert_eq!(status.code(), tonic::Code::PermissionDenied);
+    assert!(
+        client
+            .next_puzzle(next_puzzle(Some(alice_token)))
+            .await
+            .is_ok()
+    );
+
+    let status = client
+        .submit_attempt(grpc::SubmitAttemptRequest {
+            puzzle_id: 1,
+            username: "alice".to_string(),
+            solved: true,
+            solution: crate::common::SOLUTION.map(String::from).to_vec(),
+            solve_time_seconds: 30,
+            ..Default::default()
+        })
+        .await
+        .unwrap_err();
+    assert_eq!(status.code(), tonic::Code::Unauthenticated);
+    // The only attempt is alice's live one, which failed when its socket closed
+    assert_eq!(
+        app.count("SELECT COUNT(*) FROM puzzle_attempts WHERE solved = 1"),
+        0
+    );
+}
+
+#[tokio::test]
+async fn races_are_scored_by_the_server() {
+    let app = TestApp::new().await;
+    let mut alice = app.websocket("/v1/races/room/ws?username=alice").await;
+    let mut bob = app.websocket("/v1/races/room/ws?username=bob").await;
+
+    let start = next_of_type(&mut alice, "start").await;
+    let puzzles = start["puzzles"].as_array().unwrap();
+    assert_eq!(puzzles.len(), 5);
+    assert!(puzzles.iter().all(|puzzle| puzzle["solution"] == json!([])));
+    next_of_type(&mut bob, "start").await;
+
+    for puzzle_index in 0..5 {
+        let result = json!({"type": "result", "puzzleIndex": puzzle_index, "solution": crate::common::SOLUTION});
+        alice.send(Message::text(result.to_string())).await.unwrap();
+        // Claims of having solved a puzzle are ignored
+        let result = json!({
+            "type": "result",
+            "puzzleIndex": puzzle_index,
+            "solved": true,
+            "solveTimeSeconds": 0,
+            "solution": ["a1"],
+        });
+        bob.send(Message::text(result.to_string())).await.unwrap();
+    }
+    let finished = next_of_type(&mut alice, "finished").await;
+    assert_eq!(finished["winner"], "alice");
+    let solved: Vec<_> = finished["results"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|result| (result["username"].clone(), result["puzzlesSolved"].clone()))
+        .collect();
+    assert_eq!(
+        solved,
+        [(json!("alice"), json!(5)), (json!("bob"), json!(0))]
+    );
+}
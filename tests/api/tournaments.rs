@@ -0,0 +1,221 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use axum::http::{StatusCode, header};
+use serde_json::{Value, json};
+use tak_tactics_backend::admin;
+
+use crate::common::{ADMIN_TOKEN, SOLUTION, TestApp, assert_fields, json_request};
+
+fn now_seconds() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs()
+}
+
+async fn create_tournament(app: &TestApp, token: Option<&str>, body: Value) -> (StatusCode, Value) {
+    let mut request = json_request("POST", "/v1/admin/tournaments", body);
+    if let Some(token) = token {
+        request.headers_mut().insert(
+            header::AUTHORIZATION,
+            format!("Bearer {token}").parse().unwrap(),
+        );
+    }
+    let response = app.request(request).await;
+    let body = if response.status.is_success() {
+        response.json()
+    } else {
+        Value::Null
+    };
+    (response.status, body)
+}
+
+fn running_tournament() -> Value {
+    json!({
+        "name": "Weekend cup",
+        "startSeconds": now_seconds() - 60,
+        "endSeconds": now_seconds() + 3600,
+        "puzzleIds": [2, 1],
+    })
+}
+
+#[tokio::test]
+async fn only_admins_can_create_tournaments() {
+    let app = TestApp::new().await;
+    let (status, _) = create_tournament(&app, None, running_tournament()).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+    let (status, _) = create_tournament(&app, Some("wrong"), running_tournament()).await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+    assert_eq!(app.count("SELECT COUNT(*) FROM tournaments"), 0);
+
+    let (status, tournament) =
+        create_tournament(&app, Some(ADMIN_TOKEN), running_tournament()).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_fields(
+        &tournament,
+        &[
+            "id",
+            "name",
+            "startSeconds",
+            "endSeconds",
+            "numPuzzles",
+            "numParticipants",
+            "frozen",
+        ],
+    );
+    assert_eq!(tournament["numPuzzles"], 2);
+    assert_eq!(tournament["frozen"], false);
+
+    let id = &tournament["id"];
+    assert_eq!(
+        app.get(&format!("/v1/tournaments/{id}")).await.json(),
+        tournament
+    );
+    assert_eq!(app.get("/v1/tournaments").await.json(), json!([tournament]));
+    assert_eq!(
+        app.get("/v1/tournaments/99").await.status,
+        StatusCode::NOT_FOUND
+    );
+}
+
+#[tokio::test]
+async fn tournaments_need_a_name_a_time_span_and_puzzles() {
+    let app = TestApp::new().await;
+    let mut tournament = running_tournament();
+    tournament["puzzleIds"] = json!([]);
+    let (status, _) = create_tournament(&app, Some(ADMIN_TOKEN), tournament).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    let mut tournament = running_tournament();
+    tournament["endSeconds"] = tournament["startSeconds"].clone();
+    let (status, _) = create_tournament(&app, Some(ADMIN_TOKEN), tournament).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn participants_play_through_the_puzzles_in_order() {
+    let app = TestApp::new().await;
+    let (_, tournament) = create_tournament(&app, Some(ADMIN_TOKEN), running_tournament()).await;
+    let id = &tournament["id"];
+
+    let unregistered = app
+        .get(&format!("/v1/tournaments/{id}/next?username=alice"))
+        .await;
+    assert_eq!(unregistered.status, StatusCode::FORBIDDEN);
+    let registered = app
+        .post(
+            &format!("/v1/tournaments/{id}/register"),
+            json!({"username": "alice"}),
+        )
+        .await;
+    assert_eq!(registered.status, StatusCode::OK);
+
+    let next = app
+        .get(&format!("/v1/tournaments/{id}/next?username=alice"))
+        .await
+        .json();
+    assert_fields(&next, &["token", "puzzle"]);
+    assert_eq!(next["puzzle"]["id"], 2);
+    assert_eq!(next["puzzle"]["solution"], json!([]));
+    // A wrong line can't be claimed as solved, and doesn't use up the token
+    let forged = app
+        .post(
+            &format!("/v1/tournaments/{id}/attempts"),
+            json!({
+                "username": "alice",
+                "token": next["token"],
+                "solved": true,
+                "solution": ["d4-", "c3"],
+            }),
+        )
+        .await;
+    assert_eq!(forged.status, StatusCode::BAD_REQUEST);
+    assert_eq!(forged.json()["field"], "solution");
+    let attempt = json!({
+        "username": "alice",
+        "token": next["token"],
+        "solved": true,
+        "solution": SOLUTION,
+    });
+    let submitted = app
+        .post(&format!("/v1/tournaments/{id}/attempts"), attempt.clone())
+        .await;
+    assert_eq!(submitted.status, StatusCode::OK);
+    assert_eq!(
+        submitted.json(),
+        json!({"solved": true, "solution": ["d4-", "3e3+12", "*"]})
+    );
+    let reused = app
+        .post(&format!("/v1/tournaments/{id}/attempts"), attempt)
+        .await;
+    assert_eq!(reused.status, StatusCode::CONFLICT);
+    assert_eq!(app.count("SELECT COUNT(*) FROM tournament_results"), 1);
+
+    let next = app
+        .get(&format!("/v1/tournaments/{id}/next?username=alice"))
+        .await
+        .json();
+    assert_eq!(next["puzzle"]["id"], 1);
+
+    let standings = app
+        .get(&format!("/v1/tournaments/{id}/standings"))
+        .await
+        .json();
+    assert_fields(&standings, &["tournamentId", "frozen", "standings"]);
+    assert_eq!(standings["frozen"], false);
+    assert_fields(
+        &standings["standings"][0],
+        &["rank", "username", "puzzlesSolved", "totalTimeSeconds"],
+    );
+    assert_eq!(standings["standings"][0]["username"], "alice");
+    assert_eq!(standings["standings"][0]["puzzlesSolved"], 1);
+    // Tournament attempts don't affect ratings
+    assert_eq!(app.count("SELECT COUNT(*) FROM puzzle_attempts"), 0);
+}
+
+#[tokio::test]
+async fn standings_are_frozen_once_the_tournament_has_ended() {
+    let app = TestApp::new().await;
+    let (_, tournament) = create_tournament(
+        &app,
+        Some(ADMIN_TOKEN),
+        json!({
+            "name": "Last week's cup",
+            "startSeconds": now_seconds() - 7200,
+            "endSeconds": now_seconds() - 3600,
+            "puzzleIds": [1],
+        }),
+    )
+    .await;
+    let id = &tournament["id"];
+
+    let late = app
+        .post(
+            &format!("/v1/tournaments/{id}/register"),
+            json!({"username": "alice"}),
+        )
+        .await;
+    assert_eq!(late.status, StatusCode::FORBIDDEN);
+    let standings = app
+        .get(&format!("/v1/tournaments/{id}/standings"))
+        .await
+        .json();
+    assert_eq!(standings["frozen"], true);
+    assert_eq!(standings["standings"], json!([]));
+}
+
+#[tokio::test]
+async fn tokens_from_create_token_are_admin_tokens() {
+    let app = TestApp::new().await;
+    let token = admin::create_token(&app.db(), "test").unwrap();
+    let (status, _) = create_tournament(&app, Some(&token), running_tournament()).await;
+    assert_eq!(status, StatusCode::OK);
+    let hashed = app.count(&format!(
+        "SELECT COUNT(*) FROM admin_tokens WHERE token_hash = '{token}'"
+    ));
+    assert_eq!(hashed, 0, "Tokens must not be stored in plain text");
+    let audit_log = app
+        .admin("GET", "/v1/admin/audit-log", json!(null))
+        .await
+        .json();
+    assert_eq!(audit_log["items"][0]["actor"], "test");
+}